@@ -0,0 +1,318 @@
+//! Drives synthetic device updates through `handle_message` to measure the
+//! throughput and latency of the devices/scenes/expr invalidation path.
+//!
+//! Run with `cargo bench`. Reports throughput as well as latency percentiles
+//! for handling a single update, including all messages caused by it.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use homectl_server::{
+    core::{
+        appliances::Appliances, button_presses::ButtonPresses, commands::Commands,
+        config::parse_integration_config, conflicts::Conflicts, devices::Devices,
+        event_bus::EventBus, expr::Expr, groups::Groups, ha_discovery::HaDiscovery,
+        integrations::Integrations, message::handle_message, modes::Modes,
+        motion_lighting::MotionLighting, open_alerts::OpenAlerts, persons::Persons,
+        presence::Presence, quiet_hours::QuietHours, rate_alerts::RateAlerts, rules::Rules,
+        safety::Safety, scene_transitions::SceneTransitions, scenes::Scenes, state::AppState,
+        sun::Sun, utility_meters::UtilityMeters,
+    },
+    types::{
+        action::Action,
+        color::Capabilities,
+        device::{ControllableDevice, Device, DeviceData, DeviceId, DeviceRef, ManageKind},
+        event::{mk_event_channel, Message, RxEventChannel},
+        group::{GroupConfig, GroupId},
+        integration::IntegrationId,
+        scene::{
+            SceneConfig, SceneDescriptor, SceneDeviceConfig, SceneDeviceState,
+            SceneDevicesSearchConfig, SceneGroupsConfig, SceneId,
+        },
+    },
+};
+use ordered_float::OrderedFloat;
+
+const DEVICE_COUNTS: [usize; 3] = [10, 100, 1000];
+
+fn integration_id() -> IntegrationId {
+    IntegrationId::from("bench".to_string())
+}
+
+fn mk_device(i: usize, brightness: f32) -> Device {
+    Device::new(
+        integration_id(),
+        DeviceId::new(&i.to_string()),
+        format!("Light {i}"),
+        DeviceData::Controllable(ControllableDevice::new(
+            None,
+            true,
+            Some(brightness),
+            None,
+            None,
+            Capabilities::default(),
+            // Unmanaged devices accept incoming state as-is, which exercises
+            // the full invalidation path instead of just sending corrections
+            ManageKind::Unmanaged,
+        )),
+    )
+}
+
+/// Handles all queued messages, including any messages sent as a result of
+/// handling them
+async fn drain(state: &mut AppState, event_rx: &mut RxEventChannel) {
+    while let Ok((_, msg)) = event_rx.try_recv() {
+        handle_message(state, &msg).await.ok();
+    }
+}
+
+fn mk_scene_device_state() -> SceneDeviceConfig {
+    SceneDeviceConfig::DeviceState(SceneDeviceState {
+        power: Some(true),
+        color: None,
+        brightness: Some(OrderedFloat(1.0)),
+        transition_ms: None,
+        managed: None,
+    })
+}
+
+/// Scene `light_{i}` targeting only device `i`
+fn mk_device_scene(i: usize) -> (SceneId, SceneConfig) {
+    (
+        SceneId::new(format!("light_{i}")),
+        SceneConfig {
+            name: format!("Light {i}"),
+            devices: Some(SceneDevicesSearchConfig(BTreeMap::from([(
+                integration_id(),
+                BTreeMap::from([(format!("Light {i}"), mk_scene_device_state())]),
+            )]))),
+            groups: None,
+            hidden: None,
+            exclude: None,
+            palette: None,
+            keyframes: None,
+            window: None,
+            expr: None,
+        },
+    )
+}
+
+/// Sets up app state with `n` devices, all belonging to group `all`, and a
+/// scene `bright` targeting that group. With `scene_per_device`, each device
+/// also gets a scene of its own.
+async fn mk_state(n: usize, scene_per_device: bool) -> (AppState, RxEventChannel) {
+    let (event_tx, mut event_rx) = mk_event_channel();
+
+    let groups_config = BTreeMap::from([(
+        GroupId("all".to_string()),
+        GroupConfig {
+            name: "All".to_string(),
+            devices: Some(
+                (0..n)
+                    .map(|i| DeviceRef::new_with_name(integration_id(), format!("Light {i}")))
+                    .collect(),
+            ),
+            groups: None,
+            hidden: None,
+            exclude: None,
+        },
+    )]);
+
+    let mut scenes_config = BTreeMap::from([(
+        SceneId::new("bright".to_string()),
+        SceneConfig {
+            name: "Bright".to_string(),
+            devices: None,
+            groups: Some(SceneGroupsConfig(BTreeMap::from([(
+                GroupId("all".to_string()),
+                mk_scene_device_state(),
+            )]))),
+            hidden: None,
+            exclude: None,
+            palette: None,
+            keyframes: None,
+            window: None,
+            expr: None,
+        },
+    )]);
+
+    if scene_per_device {
+        scenes_config.extend((0..n).map(mk_device_scene));
+    }
+
+    let mut integrations = Integrations::new(event_tx.clone(), Default::default());
+    let (integration_config, config) =
+        parse_integration_config(&serde_json::json!({ "plugin": "dummy", "devices": {} })).unwrap();
+    integrations
+        .load_integration(&integration_id(), &integration_config, &config)
+        .await
+        .unwrap();
+    integrations.run_register_pass().await.unwrap();
+    integrations.run_start_pass().await.unwrap();
+
+    let mut state = AppState {
+        integrations,
+        groups: Groups::new(groups_config),
+        scenes: Scenes::new(scenes_config),
+        scene_transitions: SceneTransitions::new(event_tx.clone()),
+        button_presses: ButtonPresses::new(event_tx.clone()),
+        devices: Devices::new(event_tx.clone(), Default::default(), Default::default()),
+        rules: Rules::new(Default::default(), event_tx.clone()),
+        persons: Persons::new(Default::default(), event_tx.clone()),
+        presence: Presence::new(Default::default(), event_tx.clone()),
+        notifications: Default::default(),
+        quiet_hours: QuietHours::new(None, event_tx.clone()),
+        modes: Modes::new(Default::default(), event_tx.clone()),
+        safety: Safety::new(Default::default(), event_tx.clone()),
+        appliances: Appliances::new(Default::default(), event_tx.clone()),
+        rate_alerts: RateAlerts::new(Default::default(), event_tx.clone()),
+        utility_meters: UtilityMeters::new(
+            Default::default(),
+            event_tx.clone(),
+            Default::default(),
+        ),
+        heating: Default::default(),
+        sun: Sun::new(None, event_tx.clone()),
+        covers: Default::default(),
+        motion_lighting: MotionLighting::new(Default::default(), event_tx.clone()),
+        open_alerts: OpenAlerts::new(Default::default(), event_tx.clone()),
+        commands: Commands::new(Default::default(), event_tx.clone()),
+        conflicts: Conflicts::new(Default::default()),
+        polling: Default::default(),
+        event_bus: EventBus::new(None, event_tx.clone()),
+        ha_discovery: HaDiscovery::new(None, event_tx.clone()),
+        event_tx: event_tx.clone(),
+        expr: Expr::default(),
+        ws: Default::default(),
+        standby: false,
+    };
+
+    for i in 0..n {
+        event_tx.send(Message::RecvDeviceState {
+            device: mk_device(i, 0.5),
+        });
+    }
+    drain(&mut state, &mut event_rx).await;
+
+    (state, event_rx)
+}
+
+/// Latency statistics of a benchmark run
+struct Stats {
+    total: Duration,
+    p50: Duration,
+    p99: Duration,
+    max: Duration,
+}
+
+impl Stats {
+    fn from_samples(mut samples: Vec<Duration>) -> Stats {
+        samples.sort();
+
+        let percentile = |p: usize| samples[(samples.len() * p / 100).min(samples.len() - 1)];
+
+        Stats {
+            total: samples.iter().sum(),
+            p50: percentile(50),
+            p99: percentile(99),
+            max: *samples.last().unwrap(),
+        }
+    }
+
+    fn print(&self, name: &str, n: usize, iterations: usize, elements_per_iter: usize) {
+        let throughput = (iterations * elements_per_iter) as f64 / self.total.as_secs_f64();
+
+        println!(
+            "{name:<20} devices={n:<6} {throughput:>12.0} devices/s   p50={:>10.2?} p99={:>10.2?} max={:>10.2?}",
+            self.p50, self.p99, self.max
+        );
+    }
+}
+
+/// Sends one device update at a time and waits until all resulting messages
+/// have been handled. Only scenes referencing the updated device should be
+/// recomputed, so adding a scene per device should not slow updates down.
+async fn bench_recv_device_state(n: usize, iterations: usize, scene_per_device: bool) -> Stats {
+    let (mut state, mut event_rx) = mk_state(n, scene_per_device).await;
+    let mut samples = Vec::with_capacity(iterations);
+
+    for i in 0..iterations {
+        // Alternate brightness so that every update is a state change
+        let brightness = if (i / n) % 2 == 0 { 0.2 } else { 0.8 };
+        let device = mk_device(i % n, brightness);
+
+        let start = Instant::now();
+        state.event_tx.send(Message::RecvDeviceState { device });
+        drain(&mut state, &mut event_rx).await;
+        samples.push(start.elapsed());
+    }
+
+    Stats::from_samples(samples)
+}
+
+/// Activates a scene affecting all devices and waits until all resulting
+/// messages have been handled
+async fn bench_activate_scene(n: usize, iterations: usize) -> Stats {
+    let (mut state, mut event_rx) = mk_state(n, false).await;
+    let mut samples = Vec::with_capacity(iterations);
+
+    for _ in 0..iterations {
+        let start = Instant::now();
+        state
+            .event_tx
+            .send(Message::Action(Action::ActivateScene(SceneDescriptor {
+                scene_id: SceneId::new("bright".to_string()),
+                device_keys: None,
+                group_keys: None,
+                transition_ms: None,
+                brightness: None,
+                override_window: None,
+            })));
+        drain(&mut state, &mut event_rx).await;
+        samples.push(start.elapsed());
+    }
+
+    Stats::from_samples(samples)
+}
+
+#[tokio::main]
+async fn main() {
+    // `cargo bench` passes --bench to the harness. Otherwise (e.g. with
+    // `cargo test --benches`) run a single quick round to keep the harness
+    // itself tested.
+    let quick = !std::env::args().any(|arg| arg == "--bench");
+    let device_counts = if quick {
+        &DEVICE_COUNTS[..1]
+    } else {
+        &DEVICE_COUNTS[..]
+    };
+
+    for &n in device_counts {
+        let iterations = if quick { n } else { 10 * n };
+        bench_recv_device_state(n, iterations, false).await.print(
+            "recv_device_state",
+            n,
+            iterations,
+            1,
+        );
+    }
+
+    for &n in device_counts {
+        let iterations = if quick { n } else { 10 * n };
+        bench_recv_device_state(n, iterations, true).await.print(
+            "recv_scene_per_dev",
+            n,
+            iterations,
+            1,
+        );
+    }
+
+    for &n in device_counts {
+        let iterations = if quick { 1 } else { 20 };
+        bench_activate_scene(n, iterations)
+            .await
+            .print("activate_scene", n, iterations, n);
+    }
+}
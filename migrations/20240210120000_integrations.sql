@@ -0,0 +1,8 @@
+create table integrations (
+  id serial primary key not null,
+
+  integration_id text not null,
+  config jsonb not null,
+
+  unique(integration_id)
+);
@@ -0,0 +1,9 @@
+create table device_metadata (
+  id serial primary key not null,
+
+  integration_id text not null,
+  device_id text not null,
+  metadata jsonb not null,
+
+  unique(integration_id, device_id)
+);
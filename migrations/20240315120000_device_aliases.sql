@@ -0,0 +1,12 @@
+create table device_aliases (
+  id serial primary key not null,
+
+  integration_id text not null,
+  device_id text not null,
+  name text,
+
+  target_integration_id text not null,
+  target_device_id text not null,
+
+  unique(integration_id, device_id)
+);
@@ -0,0 +1,4 @@
+create table utility_meters (
+  id text primary key not null,
+  state jsonb not null
+);
@@ -0,0 +1,4 @@
+create table user_preferences (
+  user_id text primary key not null,
+  preferences jsonb not null
+);
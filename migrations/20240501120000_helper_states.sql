@@ -0,0 +1,4 @@
+create table helper_states (
+  id text primary key not null,
+  state jsonb not null
+);
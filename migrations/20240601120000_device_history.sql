@@ -0,0 +1,8 @@
+create table device_history (
+  integration_id text not null,
+  device_id text not null,
+  recorded_at timestamptz not null default now(),
+  state jsonb not null
+);
+
+create index device_history_device_idx on device_history (integration_id, device_id, recorded_at);
@@ -0,0 +1,4 @@
+create table scene_stats (
+  scene_id text primary key not null,
+  stats jsonb not null
+);
@@ -0,0 +1 @@
+alter table devices add column last_seen_at timestamptz not null default now();
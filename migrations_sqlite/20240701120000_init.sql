@@ -0,0 +1,79 @@
+-- SQLite counterpart of the PostgreSQL migrations in ../migrations, JSON
+-- columns are stored as text and timestamps as milliseconds since the epoch
+
+create table devices (
+  id integer primary key autoincrement not null,
+
+  name text not null,
+  integration_id text not null,
+  device_id text not null,
+
+  state text not null,
+
+  unique(integration_id, device_id)
+);
+
+create table scenes (
+  id integer primary key autoincrement not null,
+
+  scene_id text not null,
+  config text not null,
+
+  unique(scene_id)
+);
+
+create table integrations (
+  id integer primary key autoincrement not null,
+
+  integration_id text not null,
+  config text not null,
+
+  unique(integration_id)
+);
+
+create table device_metadata (
+  id integer primary key autoincrement not null,
+
+  integration_id text not null,
+  device_id text not null,
+  metadata text not null,
+
+  unique(integration_id, device_id)
+);
+
+create table device_aliases (
+  id integer primary key autoincrement not null,
+
+  integration_id text not null,
+  device_id text not null,
+  name text,
+
+  target_integration_id text not null,
+  target_device_id text not null,
+
+  unique(integration_id, device_id)
+);
+
+create table utility_meters (
+  id text primary key not null,
+  state text not null
+);
+
+create table user_preferences (
+  user_id text primary key not null,
+  preferences text not null
+);
+
+create table helper_states (
+  id text primary key not null,
+  state text not null
+);
+
+create table device_history (
+  integration_id text not null,
+  device_id text not null,
+  recorded_at integer not null,
+  state text not null
+);
+
+create index device_history_device_idx on device_history (integration_id, device_id, recorded_at);
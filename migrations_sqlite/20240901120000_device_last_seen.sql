@@ -0,0 +1,3 @@
+-- Existing devices count as seen when the column is added
+alter table devices add column last_seen_at integer not null default 0;
+update devices set last_seen_at = cast(strftime('%s', 'now') as integer) * 1000;
@@ -0,0 +1,383 @@
+//! Derives `homectl_server::core::schema::JsonSchema`, describing the shape
+//! that the serde `Deserialize` impl of a type accepts. Only the serde
+//! attributes used by homectl config types are supported.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{
+    parenthesized, parse_macro_input, token, Attribute, Data, DataEnum, DataStruct, DeriveInput,
+    Error, Expr, Fields, LitStr, Result, Token, Variant,
+};
+
+#[proc_macro_derive(JsonSchema)]
+pub fn derive_json_schema(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct SerdeAttrs {
+    rename: Option<String>,
+    rename_all: Option<String>,
+    tag: Option<String>,
+    content: Option<String>,
+    untagged: bool,
+    default: bool,
+    flatten: bool,
+    skip: bool,
+}
+
+fn parse_serde_attrs(attrs: &[Attribute]) -> Result<SerdeAttrs> {
+    let mut serde_attrs = SerdeAttrs::default();
+
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            let ident = meta
+                .path
+                .get_ident()
+                .map(ToString::to_string)
+                .unwrap_or_default();
+
+            match ident.as_str() {
+                "rename" => serde_attrs.rename = Some(meta.value()?.parse::<LitStr>()?.value()),
+                "rename_all" => {
+                    serde_attrs.rename_all = Some(meta.value()?.parse::<LitStr>()?.value())
+                }
+                "tag" => serde_attrs.tag = Some(meta.value()?.parse::<LitStr>()?.value()),
+                "content" => serde_attrs.content = Some(meta.value()?.parse::<LitStr>()?.value()),
+                "untagged" => serde_attrs.untagged = true,
+                "flatten" => serde_attrs.flatten = true,
+                "skip" | "skip_deserializing" => serde_attrs.skip = true,
+                "default" => {
+                    serde_attrs.default = true;
+                    if meta.input.peek(Token![=]) {
+                        meta.value()?.parse::<LitStr>()?;
+                    }
+                }
+                "from" | "try_from" | "transparent" | "deny_unknown_fields" => {
+                    return Err(meta.error(format!("serde({ident}) is not supported")));
+                }
+                // Attributes not affecting the accepted shape
+                _ => {
+                    if meta.input.peek(Token![=]) {
+                        meta.value()?.parse::<Expr>()?;
+                    } else if meta.input.peek(token::Paren) {
+                        let content;
+                        parenthesized!(content in meta.input);
+                        content.parse::<TokenStream>()?;
+                    }
+                }
+            }
+
+            Ok(())
+        })?;
+    }
+
+    Ok(serde_attrs)
+}
+
+/// Joins doc comment lines into paragraphs
+fn doc_comment(attrs: &[Attribute]) -> TokenStream {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .filter_map(|attr| match &attr.meta {
+            syn::Meta::NameValue(meta) => match &meta.value {
+                Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(lit),
+                    ..
+                }) => Some(lit.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+
+    let doc = lines
+        .split(String::is_empty)
+        .map(|paragraph| paragraph.join(" "))
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    if doc.is_empty() {
+        quote!(None)
+    } else {
+        quote!(Some(#doc))
+    }
+}
+
+fn split_words(name: &str) -> Vec<String> {
+    let mut words: Vec<String> = vec![];
+
+    for (i, c) in name.chars().enumerate() {
+        if c == '_' {
+            words.push(String::new());
+        } else if c.is_uppercase() && i > 0 {
+            words.push(c.to_lowercase().to_string());
+        } else if let Some(word) = words.last_mut() {
+            word.extend(c.to_lowercase());
+        } else {
+            words.push(c.to_lowercase().to_string());
+        }
+    }
+
+    words.into_iter().filter(|word| !word.is_empty()).collect()
+}
+
+fn apply_rename_all(name: &str, rename_all: Option<&str>) -> Result<String> {
+    let words = split_words(name);
+    let capitalize = |word: &String| {
+        let mut chars = word.chars();
+        chars
+            .next()
+            .map(|c| c.to_uppercase().chain(chars).collect::<String>())
+            .unwrap_or_default()
+    };
+
+    let renamed = match rename_all {
+        None => name.to_string(),
+        Some("lowercase") => name.to_lowercase(),
+        Some("UPPERCASE") => name.to_uppercase(),
+        Some("snake_case") => words.join("_"),
+        Some("SCREAMING_SNAKE_CASE") => words.join("_").to_uppercase(),
+        Some("kebab-case") => words.join("-"),
+        Some("PascalCase") => words.iter().map(capitalize).collect(),
+        Some("camelCase") => {
+            let pascal: String = words.iter().map(capitalize).collect();
+            let mut chars = pascal.chars();
+            chars
+                .next()
+                .map(|c| c.to_lowercase().chain(chars).collect())
+                .unwrap_or_default()
+        }
+        Some(other) => {
+            return Err(Error::new(
+                proc_macro2::Span::call_site(),
+                format!("serde(rename_all = \"{other}\") is not supported"),
+            ))
+        }
+    };
+
+    Ok(renamed)
+}
+
+/// Statements adding named fields to an `ObjectSchema` called `schema`
+fn expand_named_fields(fields: &Fields, container: &SerdeAttrs) -> Result<TokenStream> {
+    let mut statements = vec![];
+
+    for field in fields {
+        let attrs = parse_serde_attrs(&field.attrs)?;
+        if attrs.skip {
+            continue;
+        }
+
+        let ty = &field.ty;
+
+        if attrs.flatten {
+            statements.push(quote!(schema.flatten::<#ty>(gen);));
+            continue;
+        }
+
+        let ident = field.ident.as_ref().expect("Expected named field");
+        let name = match attrs.rename {
+            Some(name) => name,
+            None => {
+                let ident = ident.to_string();
+                let ident = ident.trim_start_matches("r#");
+                apply_rename_all(ident, container.rename_all.as_deref())?
+            }
+        };
+        let description = doc_comment(&field.attrs);
+        let default = attrs.default || container.default;
+
+        statements.push(quote!(schema.property::<#ty>(gen, #name, #description, #default);));
+    }
+
+    Ok(quote!(#(#statements)*))
+}
+
+fn expand_struct(input: &DeriveInput, data: &DataStruct) -> Result<TokenStream> {
+    let attrs = parse_serde_attrs(&input.attrs)?;
+    let description = doc_comment(&input.attrs);
+
+    let body = match &data.fields {
+        Fields::Named(_) => {
+            let fields = expand_named_fields(&data.fields, &attrs)?;
+
+            quote! {
+                let mut schema = crate::core::schema::ObjectSchema::new(#description);
+                #fields
+                schema.into_value()
+            }
+        }
+        // Newtypes are transparent
+        Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+            let ty = &fields.unnamed[0].ty;
+            quote!(gen.subschema_for::<#ty>())
+        }
+        Fields::Unnamed(fields) => {
+            let tys = fields.unnamed.iter().map(|field| &field.ty);
+            quote!(crate::core::schema::tuple_schema(
+                vec![#(gen.subschema_for::<#tys>()),*]
+            ))
+        }
+        Fields::Unit => quote!(serde_json::json!({ "type": "null" })),
+    };
+
+    let schema_name = match &data.fields {
+        Fields::Named(_) => {
+            let name = input.ident.to_string();
+            quote!(Some(#name.to_string()))
+        }
+        _ => quote!(None),
+    };
+
+    Ok(quote! {
+        fn schema_name() -> Option<String> {
+            #schema_name
+        }
+
+        fn json_schema(gen: &mut crate::core::schema::SchemaGenerator) -> serde_json::Value {
+            #body
+        }
+    })
+}
+
+/// Schema of the content of a variant, i.e. without any tag
+fn expand_variant_content(variant: &Variant, container: &SerdeAttrs) -> Result<TokenStream> {
+    let description = doc_comment(&variant.attrs);
+
+    let content = match &variant.fields {
+        Fields::Named(_) => {
+            // rename_all of the container applies to variant names only
+            let fields = expand_named_fields(&variant.fields, &SerdeAttrs::default())?;
+            let tag = match (&container.tag, &container.content, container.untagged) {
+                (Some(tag), None, false) => {
+                    let name = variant_name(variant, container)?;
+                    quote!(schema.tag(#tag, #name);)
+                }
+                _ => quote!(),
+            };
+
+            quote! {{
+                let mut schema = crate::core::schema::ObjectSchema::new(#description);
+                #tag
+                #fields
+                schema.into_value()
+            }}
+        }
+        Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+            let ty = &fields.unnamed[0].ty;
+            quote!(crate::core::schema::with_description(gen.subschema_for::<#ty>(), #description))
+        }
+        Fields::Unnamed(fields) => {
+            let tys = fields.unnamed.iter().map(|field| &field.ty);
+            quote!(crate::core::schema::tuple_schema(
+                vec![#(gen.subschema_for::<#tys>()),*]
+            ))
+        }
+        Fields::Unit => quote!(serde_json::json!({ "type": "null" })),
+    };
+
+    Ok(content)
+}
+
+fn variant_name(variant: &Variant, container: &SerdeAttrs) -> Result<String> {
+    let attrs = parse_serde_attrs(&variant.attrs)?;
+
+    match attrs.rename {
+        Some(name) => Ok(name),
+        None => apply_rename_all(&variant.ident.to_string(), container.rename_all.as_deref()),
+    }
+}
+
+fn expand_enum(input: &DeriveInput, data: &DataEnum) -> Result<TokenStream> {
+    let attrs = parse_serde_attrs(&input.attrs)?;
+    let description = doc_comment(&input.attrs);
+    let mut variants = vec![];
+
+    for variant in &data.variants {
+        if parse_serde_attrs(&variant.attrs)?.skip {
+            continue;
+        }
+
+        let name = variant_name(variant, &attrs)?;
+        let variant_description = doc_comment(&variant.attrs);
+        let content = expand_variant_content(variant, &attrs)?;
+        let is_unit = matches!(variant.fields, Fields::Unit);
+        let is_struct = matches!(variant.fields, Fields::Named(_));
+
+        let schema = match (&attrs.tag, &attrs.content, attrs.untagged) {
+            (_, _, true) => content,
+            (None, _, false) if is_unit => {
+                quote!(crate::core::schema::const_schema(#name, #variant_description))
+            }
+            (None, _, false) => {
+                quote!(crate::core::schema::external_tag(#name, #content))
+            }
+            (Some(_), None, false) if is_struct => content,
+            (Some(tag), content_key, false) => {
+                let content = match content_key {
+                    _ if is_unit => quote!(),
+                    None => quote!(schema.all_of(#content);),
+                    Some(content_key) => {
+                        quote!(schema.property_schema(#content_key, #content, false);)
+                    }
+                };
+
+                quote! {{
+                    let mut schema = crate::core::schema::ObjectSchema::new(#variant_description);
+                    schema.tag(#tag, #name);
+                    #content
+                    schema.into_value()
+                }}
+            }
+        };
+
+        variants.push(schema);
+    }
+
+    let name = input.ident.to_string();
+
+    Ok(quote! {
+        fn schema_name() -> Option<String> {
+            Some(#name.to_string())
+        }
+
+        fn json_schema(gen: &mut crate::core::schema::SchemaGenerator) -> serde_json::Value {
+            crate::core::schema::any_of(vec![#(#variants),*], #description)
+        }
+    })
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "JsonSchema cannot be derived for generic types",
+        ));
+    }
+
+    let body = match &input.data {
+        Data::Struct(data) => expand_struct(input, data)?,
+        Data::Enum(data) => expand_enum(input, data)?,
+        Data::Union(_) => {
+            return Err(Error::new_spanned(
+                input,
+                "JsonSchema cannot be derived for unions",
+            ))
+        }
+    };
+
+    let ident = &input.ident;
+
+    Ok(quote! {
+        impl crate::core::schema::JsonSchema for #ident {
+            #body
+        }
+    })
+}
@@ -0,0 +1,252 @@
+use std::sync::Arc;
+
+use crate::types::api::{ApiConfig, ApiScope};
+use crate::utils::constant_time_eq;
+use serde::Serialize;
+use warp::{
+    http::{header, Method, StatusCode},
+    path::FullPath,
+    reject::Reject,
+    Filter, Rejection, Reply,
+};
+
+/// Paths below `/api/v1` that require the admin scope regardless of method
+const ADMIN_PATHS: &[&str] = &[
+    "debug",
+    "devices/migrate",
+    "devices/unseen",
+    "integrations",
+    "log",
+    "sessions",
+    "snapshot",
+    "users",
+];
+
+#[derive(Debug)]
+enum AuthRejection {
+    /// No token or an unknown token was sent
+    Unauthorized,
+
+    /// The token's scope doesn't allow the request
+    Forbidden,
+}
+
+impl Reject for AuthRejection {}
+
+#[derive(Serialize)]
+struct AuthError {
+    error: String,
+}
+
+/// Scope needed for a request to given path below `/api/v1`
+fn required_scope(method: &Method, path: &str) -> ApiScope {
+    let is_admin_path = ADMIN_PATHS.iter().any(|admin_path| {
+        path.strip_prefix(admin_path)
+            .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+    });
+
+    if is_admin_path {
+        ApiScope::Admin
+    } else if method == Method::GET || method == Method::HEAD {
+        ApiScope::Read
+    } else {
+        ApiScope::Control
+    }
+}
+
+/// Token in given `Authorization` header value
+pub fn bearer_token(authorization: Option<&str>) -> Option<&str> {
+    Some(authorization?.strip_prefix("Bearer ")?.trim())
+}
+
+pub fn requires_auth(config: &ApiConfig) -> bool {
+    config
+        .tokens
+        .as_ref()
+        .map_or(false, |tokens| !tokens.is_empty())
+}
+
+/// Scope granted by given token, None if the token is unknown
+pub fn token_scope(config: &ApiConfig, token: &str) -> Option<ApiScope> {
+    let tokens = config.tokens.as_deref().unwrap_or_default();
+
+    tokens
+        .iter()
+        .find(|api_token| constant_time_eq(&api_token.token, token))
+        .map(|api_token| api_token.scope)
+}
+
+/// Scope of a client presenting given token, all scopes if the API doesn't
+/// require authentication
+pub fn client_scope(config: &ApiConfig, token: Option<&str>) -> Option<ApiScope> {
+    if !requires_auth(config) {
+        return Some(ApiScope::Admin);
+    }
+
+    token_scope(config, token?)
+}
+
+fn authorize_request(
+    config: &ApiConfig,
+    method: &Method,
+    path: &str,
+    authorization: Option<&str>,
+) -> Result<(), AuthRejection> {
+    if !requires_auth(config) {
+        return Ok(());
+    }
+
+    let scope = bearer_token(authorization)
+        .and_then(|token| token_scope(config, token))
+        .ok_or(AuthRejection::Unauthorized)?;
+    let path = path.trim_start_matches("/api/v1/");
+
+    if scope >= required_scope(method, path) {
+        Ok(())
+    } else {
+        Err(AuthRejection::Forbidden)
+    }
+}
+
+/// Rejects requests without a bearer token of sufficient scope, if tokens are
+/// configured
+pub fn authorize(config: ApiConfig) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let config = Arc::new(config);
+
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::header::optional::<String>("authorization"))
+        .and_then(
+            move |method: Method, path: FullPath, authorization: Option<String>| {
+                let config = config.clone();
+
+                async move {
+                    authorize_request(&config, &method, path.as_str(), authorization.as_deref())
+                        .map_err(warp::reject::custom)
+                }
+            },
+        )
+        .untuple_one()
+}
+
+/// Replies to requests rejected by [authorize], leaves other rejections to
+/// the remaining filters
+pub async fn handle_auth_rejection(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    let Some(auth_rejection) = rejection.find::<AuthRejection>() else {
+        return Err(rejection);
+    };
+
+    let (status, error) = match auth_rejection {
+        AuthRejection::Unauthorized => (StatusCode::UNAUTHORIZED, "Missing or invalid token"),
+        AuthRejection::Forbidden => (StatusCode::FORBIDDEN, "Token scope is insufficient"),
+    };
+
+    let reply = warp::reply::with_status(
+        warp::reply::json(&AuthError {
+            error: error.to_string(),
+        }),
+        status,
+    );
+
+    Ok(warp::reply::with_header(
+        reply,
+        header::WWW_AUTHENTICATE,
+        "Bearer",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::api::ApiToken;
+
+    fn mk_config() -> ApiConfig {
+        let mk_token = |token: &str, scope| ApiToken {
+            token: token.to_string(),
+            scope,
+            name: None,
+        };
+
+        ApiConfig {
+            tokens: Some(vec![
+                mk_token("reader", ApiScope::Read),
+                mk_token("controller", ApiScope::Control),
+                mk_token("admin", ApiScope::Admin),
+            ]),
+        }
+    }
+
+    #[test]
+    fn test_required_scope() {
+        assert_eq!(required_scope(&Method::GET, "devices"), ApiScope::Read);
+        assert_eq!(
+            required_scope(&Method::PATCH, "devices/hue/1"),
+            ApiScope::Control
+        );
+        assert_eq!(
+            required_scope(&Method::POST, "devices/migrate"),
+            ApiScope::Admin
+        );
+        assert_eq!(
+            required_scope(&Method::DELETE, "devices/unseen"),
+            ApiScope::Admin
+        );
+        assert_eq!(
+            required_scope(&Method::GET, "integrations"),
+            ApiScope::Admin
+        );
+        assert_eq!(required_scope(&Method::PUT, "log/mqtt"), ApiScope::Admin);
+        assert_eq!(
+            required_scope(&Method::GET, "users/alice/preferences"),
+            ApiScope::Admin
+        );
+    }
+
+    #[test]
+    fn test_authorize_request() {
+        let config = mk_config();
+        let authorize = |method: Method, path: &str, token: Option<&str>| {
+            let authorization = token.map(|token| format!("Bearer {token}"));
+            authorize_request(&config, &method, path, authorization.as_deref())
+        };
+
+        assert!(authorize(Method::GET, "/api/v1/devices", Some("reader")).is_ok());
+        assert!(matches!(
+            authorize(Method::POST, "/api/v1/actions/trigger", Some("reader")),
+            Err(AuthRejection::Forbidden)
+        ));
+        assert!(authorize(Method::POST, "/api/v1/actions/trigger", Some("controller")).is_ok());
+        assert!(matches!(
+            authorize(Method::POST, "/api/v1/snapshot", Some("controller")),
+            Err(AuthRejection::Forbidden)
+        ));
+        assert!(authorize(Method::POST, "/api/v1/snapshot", Some("admin")).is_ok());
+        assert!(matches!(
+            authorize(Method::GET, "/api/v1/devices", Some("guess")),
+            Err(AuthRejection::Unauthorized)
+        ));
+        assert!(matches!(
+            authorize(Method::GET, "/api/v1/devices", None),
+            Err(AuthRejection::Unauthorized)
+        ));
+
+        let open = ApiConfig::default();
+        assert!(authorize_request(&open, &Method::POST, "/api/v1/snapshot", None).is_ok());
+    }
+
+    #[test]
+    fn test_client_scope() {
+        let config = mk_config();
+
+        assert_eq!(
+            client_scope(&config, Some("controller")),
+            Some(ApiScope::Control)
+        );
+        assert_eq!(client_scope(&config, Some("guess")), None);
+        assert_eq!(client_scope(&config, None), None);
+        assert_eq!(
+            client_scope(&ApiConfig::default(), None),
+            Some(ApiScope::Admin)
+        );
+    }
+}
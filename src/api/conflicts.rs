@@ -0,0 +1,26 @@
+use std::{convert::Infallible, sync::Arc};
+
+use crate::core::state::AppState;
+use tokio::sync::RwLock;
+use warp::Filter;
+
+use super::with_state;
+
+/// GET /conflicts
+pub fn conflicts(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("conflicts")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_state(app_state))
+        .and_then(get_conflicts_impl)
+}
+
+async fn get_conflicts_impl(
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+
+    Ok(warp::reply::json(app_state.conflicts.get_conflicts()))
+}
@@ -0,0 +1,28 @@
+use std::{convert::Infallible, sync::Arc};
+
+use crate::core::state::AppState;
+use tokio::sync::RwLock;
+use warp::Filter;
+
+use super::with_state;
+
+/// GET /debug/commands
+///
+/// Returns the latest command sent to each device along with pending state,
+/// retries and coalesced commands, to find out why a device hasn't reacted
+pub fn debug(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("debug" / "commands")
+        .and(warp::get())
+        .and(with_state(app_state))
+        .and_then(get_command_queue_impl)
+}
+
+async fn get_command_queue_impl(
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+
+    Ok(warp::reply::json(&app_state.commands.get_queue()))
+}
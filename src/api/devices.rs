@@ -1,26 +1,49 @@
 use std::{convert::Infallible, sync::Arc};
 
+use crate::db::actions::{db_get_device_history, db_get_unseen_devices};
 use crate::types::{
+    action::Action,
     color::ColorMode,
-    device::{Device, DeviceId},
+    device::{ControllableStateUpdate, Device, DeviceId, DeviceKey},
+    device_config::DeviceMetadata,
+    device_expiry::PurgeUnseenDevicesDescriptor,
+    dim::UpdateDeviceStateDescriptor,
+    event::Message,
+    history::downsample,
+    integration::IntegrationId,
 };
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
-use warp::Filter;
+use warp::{http::StatusCode, Filter};
 
-use crate::core::state::AppState;
+use crate::core::{groups::group_id_from_device_key, state::AppState};
 
 use super::with_state;
 
 #[derive(serde::Serialize)]
 pub struct DevicesResponse {
     devices: Vec<Device>,
+
+    /// Aggregate state of groups, as pseudo-devices
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    group_devices: Vec<Device>,
 }
 
 pub fn devices(
     app_state: &Arc<RwLock<AppState>>,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
-    warp::path("devices").and(get_devices(app_state).or(put_device(app_state)))
+    warp::path("devices").and(
+        get_command_statuses(app_state)
+            .or(get_unseen_devices())
+            .or(delete_unseen_devices(app_state))
+            .or(get_devices(app_state))
+            .or(put_device(app_state))
+            .or(put_device_metadata(app_state))
+            .or(get_device_history())
+            .or(patch_device(app_state))
+            .or(post_migrate_device(app_state)),
+    )
 }
 
 #[derive(Serialize, Deserialize)]
@@ -37,8 +60,21 @@ fn get_devices(
         .map(|q: GetQuery, app_state: Arc<RwLock<AppState>>| {
             let app_state = app_state.blocking_read();
             let devices = app_state.devices.get_state();
+            let device_configs = app_state.devices.get_device_configs();
 
             let devices_converted = devices
+                .0
+                .values()
+                .map(|device| {
+                    device_configs
+                        .apply_display_name(device)
+                        .color_to_mode(q.color_mode.clone().unwrap_or(ColorMode::Hs), true)
+                })
+                .collect::<Vec<Device>>();
+
+            let group_devices = app_state
+                .groups
+                .get_group_devices(devices)
                 .0
                 .values()
                 .map(|device| {
@@ -48,12 +84,105 @@ fn get_devices(
 
             let response = DevicesResponse {
                 devices: devices_converted,
+                group_devices,
             };
 
             warp::reply::json(&response)
         })
 }
 
+/// GET /devices/commands
+///
+/// Returns the status of the latest state command sent to each device
+fn get_command_statuses(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("commands")
+        .and(warp::get())
+        .and(with_state(app_state))
+        .map(|app_state: Arc<RwLock<AppState>>| {
+            let app_state = app_state.blocking_read();
+            warp::reply::json(app_state.commands.get_statuses())
+        })
+}
+
+#[derive(Deserialize)]
+struct UnseenQuery {
+    /// Devices that haven't reported their state for this many days
+    days: u32,
+}
+
+/// GET /devices/unseen?days={days}
+///
+/// Returns devices that haven't reported their state for given number of
+/// days, least recently seen first
+fn get_unseen_devices(
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("unseen")
+        .and(warp::get())
+        .and(warp::query::<UnseenQuery>())
+        .and_then(get_unseen_devices_impl)
+}
+
+async fn get_unseen_devices_impl(q: UnseenQuery) -> Result<impl warp::Reply, Infallible> {
+    let since = Utc::now() - Duration::days(q.days.into());
+
+    match db_get_unseen_devices(since).await {
+        Ok(devices) => Ok(warp::reply::with_status(
+            warp::reply::json(&devices),
+            StatusCode::OK,
+        )),
+        Err(e) => Ok(warp::reply::with_status(
+            warp::reply::json(&HistoryError {
+                error: e.to_string(),
+            }),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )),
+    }
+}
+
+/// DELETE /devices/unseen?days={days}
+///
+/// Purges devices that haven't reported their state for given number of days
+fn delete_unseen_devices(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("unseen")
+        .and(warp::delete())
+        .and(warp::query::<UnseenQuery>())
+        .and(with_state(app_state))
+        .and_then(delete_unseen_devices_impl)
+}
+
+async fn delete_unseen_devices_impl(
+    q: UnseenQuery,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    // 0 days would purge every device that isn't reporting its state right now
+    if q.days == 0 {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&HistoryError {
+                error: "days must be at least 1".to_string(),
+            }),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let app_state = app_state.read().await;
+    app_state
+        .event_tx
+        .send(Message::Action(Action::PurgeUnseenDevices(
+            PurgeUnseenDevicesDescriptor {
+                not_seen_for_days: q.days,
+            },
+        )));
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&()),
+        StatusCode::OK,
+    ))
+}
+
 fn put_device(
     app_state: &Arc<RwLock<AppState>>,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
@@ -71,21 +200,197 @@ async fn put_device_impl(
 ) -> Result<impl warp::Reply, Infallible> {
     // Make sure device_id matches with provided device
     if device_id != device.id {
-        return Ok(warp::reply::json(&DevicesResponse { devices: vec![] }));
+        return Ok(warp::reply::json(&DevicesResponse {
+            devices: vec![],
+            group_devices: vec![],
+        }));
     }
 
     let mut app_state = app_state.write().await;
     let scenes = app_state.scenes.clone();
+    let groups = app_state.groups.clone();
 
-    app_state
-        .devices
-        .set_device_state(&device, &scenes, true, false, false)
-        .await;
+    // Setting state of a group pseudo-device fans out to its members
+    let group_id = group_id_from_device_key(&device.get_device_key());
+    match (group_id, device.get_controllable_state()) {
+        (Some(group_id), Some(group_state)) => {
+            app_state
+                .devices
+                .set_group_state(&group_id, group_state, &groups, &scenes)
+                .await;
+        }
+        _ => {
+            app_state
+                .devices
+                .set_device_state(&device, &scenes, true, false, false)
+                .await;
+        }
+    }
 
     let devices = app_state.devices.get_state();
     let response = DevicesResponse {
         devices: devices.0.values().cloned().collect(),
+        group_devices: vec![],
     };
 
     Ok(warp::reply::json(&response))
 }
+
+#[derive(Deserialize)]
+struct MigrateDevice {
+    from: DeviceKey,
+    to: DeviceKey,
+}
+
+/// POST /devices/migrate
+fn post_migrate_device(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("migrate")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_state(app_state))
+        .and_then(post_migrate_device_impl)
+}
+
+async fn post_migrate_device_impl(
+    migrate: MigrateDevice,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+    app_state.event_tx.send(Message::MigrateDevice {
+        from: migrate.from,
+        to: migrate.to,
+    });
+
+    Ok(warp::reply::json(&()))
+}
+
+/// PATCH /devices/{integration_id}/{device_id}
+///
+/// Updates only the given fields of the device state. Group pseudo-devices
+/// update all members of the group.
+fn patch_device(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(IntegrationId / DeviceId)
+        .and(warp::patch())
+        .and(warp::body::json())
+        .and(with_state(app_state))
+        .and_then(patch_device_impl)
+}
+
+async fn patch_device_impl(
+    integration_id: IntegrationId,
+    device_id: DeviceId,
+    update: ControllableStateUpdate,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let device_key = DeviceKey::new(integration_id, device_id);
+
+    let descriptor = match group_id_from_device_key(&device_key) {
+        Some(group_id) => UpdateDeviceStateDescriptor {
+            device_keys: None,
+            group_keys: Some(vec![group_id]),
+            state: update,
+        },
+        None => UpdateDeviceStateDescriptor {
+            device_keys: Some(vec![device_key]),
+            group_keys: None,
+            state: update,
+        },
+    };
+
+    let app_state = app_state.read().await;
+    app_state
+        .event_tx
+        .send(Message::Action(Action::UpdateDeviceState(descriptor)));
+
+    Ok(warp::reply::json(&()))
+}
+
+/// PUT /devices/{integration_id}/{device_id}/metadata
+fn put_device_metadata(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(IntegrationId / DeviceId / "metadata")
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(with_state(app_state))
+        .and_then(put_device_metadata_impl)
+}
+
+async fn put_device_metadata_impl(
+    integration_id: IntegrationId,
+    device_id: DeviceId,
+    metadata: DeviceMetadata,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+    app_state.event_tx.send(Message::SetDeviceMetadata {
+        device_key: DeviceKey::new(integration_id, device_id),
+        metadata,
+    });
+
+    Ok(warp::reply::json(&()))
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    /// Defaults to 24 hours before `to`
+    from: Option<DateTime<Utc>>,
+
+    /// Defaults to now
+    to: Option<DateTime<Utc>>,
+
+    /// Return at most one entry per this many seconds
+    resolution: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct HistoryError {
+    error: String,
+}
+
+/// GET /devices/{integration_id}/{device_id}/history
+///
+/// Returns recorded states of a device, oldest first
+fn get_device_history(
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(IntegrationId / DeviceId / "history")
+        .and(warp::get())
+        .and(warp::query::<HistoryQuery>())
+        .and_then(get_device_history_impl)
+}
+
+async fn get_device_history_impl(
+    integration_id: IntegrationId,
+    device_id: DeviceId,
+    q: HistoryQuery,
+) -> Result<impl warp::Reply, Infallible> {
+    let to = q.to.unwrap_or_else(Utc::now);
+    let from = q.from.unwrap_or(to - Duration::hours(24));
+    let key = DeviceKey::new(integration_id, device_id);
+
+    let entries = match db_get_device_history(&key, from, to).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&HistoryError {
+                    error: e.to_string(),
+                }),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    };
+
+    let entries = match q.resolution {
+        Some(resolution) => downsample(entries, from, Duration::seconds(resolution.into())),
+        None => entries,
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&entries),
+        StatusCode::OK,
+    ))
+}
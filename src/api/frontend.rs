@@ -0,0 +1,51 @@
+use crate::types::frontend::FrontendConfig;
+use warp::{filters::BoxedFilter, path::FullPath, reply::Reply, Filter};
+
+/// Serves a static single page application, if configured. Paths that don't
+/// match a file get `index.html`, so the application can handle its own
+/// routes.
+pub fn frontend(config: Option<FrontendConfig>) -> BoxedFilter<(Box<dyn Reply>,)> {
+    let Some(config) = config else {
+        return warp::any()
+            .and_then(|| async { Err::<Box<dyn Reply>, _>(warp::reject::not_found()) })
+            .boxed();
+    };
+
+    let max_age = config.cache_max_age_secs.unwrap_or(3600);
+
+    let files =
+        warp::get()
+            .and(warp::fs::dir(config.dir.clone()))
+            .map(move |file: warp::fs::File| {
+                // index.html refers to the current versions of other files
+                let cache_control = if file.path().ends_with("index.html") {
+                    "no-cache".to_string()
+                } else {
+                    format!("public, max-age={max_age}")
+                };
+
+                Box::new(warp::reply::with_header(
+                    file,
+                    "cache-control",
+                    cache_control,
+                )) as Box<dyn Reply>
+            });
+
+    let fallback = warp::get()
+        .and(warp::path::full())
+        .and_then(|path: FullPath| async move {
+            // Unknown API paths should still 404
+            if path.as_str().starts_with("/api/") || path.as_str() == "/ws" {
+                Err(warp::reject::not_found())
+            } else {
+                Ok(())
+            }
+        })
+        .untuple_one()
+        .and(warp::fs::file(config.dir.join("index.html")))
+        .map(|file: warp::fs::File| {
+            Box::new(warp::reply::with_header(file, "cache-control", "no-cache")) as Box<dyn Reply>
+        });
+
+    files.or(fallback).unify().boxed()
+}
@@ -0,0 +1,231 @@
+use std::{convert::Infallible, sync::Arc};
+
+use crate::core::{message::handle_message, state::AppState};
+use crate::types::{event::Message, integration::IntegrationId};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use warp::{http::StatusCode, Filter, Reply};
+
+use super::with_state;
+
+#[derive(Serialize)]
+pub struct IntegrationStatus {
+    id: IntegrationId,
+    plugin: String,
+    running: bool,
+}
+
+#[derive(Serialize)]
+pub struct IntegrationsResponse {
+    integrations: Vec<IntegrationStatus>,
+}
+
+#[derive(Serialize)]
+struct IntegrationError {
+    error: String,
+}
+
+pub fn integrations(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("integrations").and(
+        get_integrations(app_state)
+            .or(post_integration(app_state))
+            .or(delete_integration(app_state))
+            .or(get_captured_traffic(app_state))
+            .or(get_snapshot(app_state))
+            .or(post_integration_command(app_state, "start"))
+            .or(post_integration_command(app_state, "stop"))
+            .or(post_integration_command(app_state, "restart")),
+    )
+}
+
+fn get_integrations(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path::end()
+        .and(warp::get())
+        .and(with_state(app_state))
+        .and_then(get_integrations_impl)
+}
+
+async fn get_integrations_impl(
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+
+    let integrations = app_state
+        .integrations
+        .get_integration_statuses()
+        .into_iter()
+        .map(|(id, plugin, running)| IntegrationStatus {
+            id,
+            plugin,
+            running,
+        })
+        .collect();
+
+    Ok(warp::reply::json(&IntegrationsResponse { integrations }))
+}
+
+#[derive(Deserialize)]
+struct NewIntegration {
+    id: IntegrationId,
+    config: serde_json::Value,
+}
+
+fn post_integration(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path::end()
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_state(app_state))
+        .and_then(post_integration_impl)
+}
+
+async fn post_integration_impl(
+    new_integration: NewIntegration,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+    app_state.event_tx.send(Message::AddIntegration {
+        integration_id: new_integration.id,
+        config: new_integration.config,
+    });
+
+    Ok(warp::reply::json(&()))
+}
+
+fn delete_integration(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(IntegrationId)
+        .and(warp::delete())
+        .and(with_state(app_state))
+        .and_then(delete_integration_impl)
+}
+
+async fn delete_integration_impl(
+    integration_id: IntegrationId,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+    app_state
+        .event_tx
+        .send(Message::RemoveIntegration { integration_id });
+
+    Ok(warp::reply::json(&()))
+}
+
+/// GET /integrations/{integration_id}/traffic
+///
+/// Returns raw traffic recently exchanged with each device, if the integration
+/// is configured to capture it
+fn get_captured_traffic(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(IntegrationId / "traffic")
+        .and(warp::get())
+        .and(with_state(app_state))
+        .and_then(get_captured_traffic_impl)
+}
+
+async fn get_captured_traffic_impl(
+    integration_id: IntegrationId,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+    let traffic = app_state
+        .integrations
+        .get_captured_traffic(&integration_id)
+        .await;
+
+    let status = if traffic.is_some() {
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&traffic),
+        status,
+    ))
+}
+
+/// GET /integrations/{integration_id}/snapshot
+///
+/// Returns the latest camera snapshot taken by the integration, e.g. of a
+/// doorbell when it last rang
+fn get_snapshot(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(IntegrationId / "snapshot")
+        .and(warp::get())
+        .and(with_state(app_state))
+        .and_then(get_snapshot_impl)
+}
+
+async fn get_snapshot_impl(
+    integration_id: IntegrationId,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<warp::reply::Response, Infallible> {
+    let app_state = app_state.read().await;
+    let snapshot = app_state.integrations.get_snapshot(&integration_id).await;
+
+    let response = match snapshot {
+        Some(snapshot) => warp::reply::with_header(
+            warp::reply::Response::new(snapshot.into()),
+            "content-type",
+            "image/jpeg",
+        )
+        .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    };
+
+    Ok(response)
+}
+
+/// POST /integrations/{integration_id}/{command}
+fn post_integration_command(
+    app_state: &Arc<RwLock<AppState>>,
+    command: &'static str,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path::param::<IntegrationId>()
+        .and(warp::path(command))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(with_state(app_state))
+        .and_then(move |integration_id, app_state| {
+            post_integration_command_impl(integration_id, command, app_state)
+        })
+}
+
+async fn post_integration_command_impl(
+    integration_id: IntegrationId,
+    command: &'static str,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let msg = match command {
+        "start" => Message::StartIntegration { integration_id },
+        "stop" => Message::StopIntegration { integration_id },
+        _ => Message::RestartIntegration { integration_id },
+    };
+
+    // Handled right away instead of through the event channel, so that
+    // failures can be reported back
+    let mut app_state = app_state.write().await;
+
+    match handle_message(&mut app_state, &msg).await {
+        Ok(()) => Ok(warp::reply::with_status(
+            warp::reply::json(&()),
+            StatusCode::OK,
+        )),
+        Err(e) => Ok(warp::reply::with_status(
+            warp::reply::json(&IntegrationError {
+                error: e.to_string(),
+            }),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )),
+    }
+}
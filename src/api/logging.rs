@@ -0,0 +1,104 @@
+use std::convert::Infallible;
+
+use crate::core::logging::{
+    get_log_filter, set_integration_log_level, set_log_filter, set_module_log_level,
+};
+use serde::{Deserialize, Serialize};
+use warp::{http::StatusCode, Filter};
+
+#[derive(Serialize, Deserialize)]
+pub struct LogFilter {
+    filter: String,
+}
+
+#[derive(Deserialize)]
+struct LogLevel {
+    level: String,
+}
+
+#[derive(Serialize)]
+struct LogError {
+    error: String,
+}
+
+pub fn logging() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("log").and(
+        get_filter()
+            .or(put_filter())
+            .or(put_integration_level())
+            .or(put_module_level()),
+    )
+}
+
+/// GET /log
+fn get_filter() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path::end().and(warp::get()).and_then(get_filter_impl)
+}
+
+async fn get_filter_impl() -> Result<impl warp::Reply, Infallible> {
+    Ok(to_reply(get_log_filter()))
+}
+
+/// PUT /log, replaces the whole filter
+fn put_filter() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path::end()
+        .and(warp::put())
+        .and(warp::body::json())
+        .and_then(put_filter_impl)
+}
+
+async fn put_filter_impl(body: LogFilter) -> Result<impl warp::Reply, Infallible> {
+    let result = set_log_filter(&body.filter).and_then(|_| get_log_filter());
+    Ok(to_reply(result))
+}
+
+/// PUT /log/integration/{integration_id}, e.g. /log/integration/mqtt with body
+/// `{ "level": "trace" }`
+fn put_integration_level(
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("integration" / String)
+        .and(warp::put())
+        .and(warp::body::json())
+        .and_then(put_integration_level_impl)
+}
+
+async fn put_integration_level_impl(
+    integration_id: String,
+    body: LogLevel,
+) -> Result<impl warp::Reply, Infallible> {
+    let result =
+        set_integration_log_level(&integration_id, &body.level).and_then(|_| get_log_filter());
+    Ok(to_reply(result))
+}
+
+/// PUT /log/{module}, e.g. /log/integrations::mqtt with body
+/// `{ "level": "trace" }`
+fn put_module_level() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone
+{
+    warp::path!(String)
+        .and(warp::put())
+        .and(warp::body::json())
+        .and_then(put_module_level_impl)
+}
+
+async fn put_module_level_impl(
+    module: String,
+    body: LogLevel,
+) -> Result<impl warp::Reply, Infallible> {
+    let result = set_module_log_level(&module, &body.level).and_then(|_| get_log_filter());
+    Ok(to_reply(result))
+}
+
+fn to_reply(filter: color_eyre::Result<String>) -> warp::reply::WithStatus<warp::reply::Json> {
+    match filter {
+        Ok(filter) => {
+            warp::reply::with_status(warp::reply::json(&LogFilter { filter }), StatusCode::OK)
+        }
+        Err(e) => warp::reply::with_status(
+            warp::reply::json(&LogError {
+                error: e.to_string(),
+            }),
+            StatusCode::BAD_REQUEST,
+        ),
+    }
+}
@@ -0,0 +1,108 @@
+use std::fmt::Write;
+use std::{collections::HashMap, convert::Infallible, sync::Arc};
+
+use crate::core::state::AppState;
+use crate::types::rule::{RoutineId, RoutineStats};
+use tokio::sync::RwLock;
+use warp::Filter;
+
+use super::with_state;
+
+/// GET /metrics
+///
+/// Returns routine execution statistics in the Prometheus text format
+pub fn metrics(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("metrics")
+        .and(warp::get())
+        .and(with_state(app_state))
+        .and_then(metrics_impl)
+}
+
+async fn metrics_impl(app_state: Arc<RwLock<AppState>>) -> Result<impl warp::Reply, Infallible> {
+    let stats = app_state.read().await.rules.get_stats();
+
+    Ok(warp::reply::with_header(
+        format_routine_metrics(&stats),
+        "content-type",
+        "text/plain; version=0.0.4",
+    ))
+}
+
+/// Escapes a Prometheus label value
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Name, type, help text and value of a metric
+type RoutineMetric = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&RoutineStats) -> f64,
+);
+
+fn format_routine_metrics(stats: &HashMap<RoutineId, RoutineStats>) -> String {
+    let mut stats: Vec<_> = stats.iter().collect();
+    stats.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+
+    let metrics: [RoutineMetric; 5] = [
+        (
+            "homectl_routine_triggers_total",
+            "counter",
+            "Times the routine was triggered",
+            |stats| stats.trigger_count as f64,
+        ),
+        (
+            "homectl_routine_last_triggered_timestamp_seconds",
+            "gauge",
+            "When the routine was last triggered",
+            |stats| {
+                stats
+                    .last_triggered_at
+                    .map_or(0.0, |at| at.timestamp_millis() as f64 / 1000.0)
+            },
+        ),
+        (
+            "homectl_routine_evaluations_total",
+            "counter",
+            "Times the rules of the routine were evaluated",
+            |stats| stats.evaluation_count as f64,
+        ),
+        (
+            "homectl_routine_evaluation_seconds_total",
+            "counter",
+            "Time spent evaluating the rules of the routine",
+            |stats| stats.evaluation_total_us as f64 / 1e6,
+        ),
+        (
+            "homectl_routine_evaluation_max_seconds",
+            "gauge",
+            "Longest evaluation of the rules of the routine",
+            |stats| stats.evaluation_max_us as f64 / 1e6,
+        ),
+    ];
+
+    let mut out = String::new();
+
+    for (name, kind, help, value) in metrics {
+        writeln!(out, "# HELP {name} {help}").ok();
+        writeln!(out, "# TYPE {name} {kind}").ok();
+
+        for (routine_id, stats) in &stats {
+            writeln!(
+                out,
+                "{name}{{routine_id=\"{}\"}} {}",
+                escape_label(&routine_id.0),
+                value(stats)
+            )
+            .ok();
+        }
+    }
+
+    out
+}
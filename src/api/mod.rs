@@ -1,13 +1,42 @@
 use std::sync::Arc;
 
-use crate::AppState;
+use crate::core::state::AppState;
+use crate::types::{api::ApiConfig, frontend::FrontendConfig};
 
 mod actions;
+mod auth;
+mod conflicts;
+mod debug;
 mod devices;
+mod frontend;
+mod integrations;
+mod logging;
+mod metrics;
+mod modes;
+mod routines;
+mod scenes;
+mod schema;
+mod sessions;
+mod snapshot;
+mod users;
 mod ws;
 
 use actions::*;
+use auth::*;
+use conflicts::*;
+use debug::*;
 use devices::*;
+use frontend::*;
+use integrations::*;
+use logging::*;
+use metrics::*;
+use modes::*;
+use routines::*;
+use scenes::*;
+use schema::*;
+use sessions::*;
+use snapshot::*;
+use users::*;
 
 use color_eyre::Result;
 use tokio::sync::RwLock;
@@ -23,15 +52,41 @@ pub fn with_state(
 }
 
 // Example of warp usage: https://github.com/seanmonstar/warp/blob/master/examples/todos.rs
-pub fn init_api(app_state: &Arc<RwLock<AppState>>) -> Result<()> {
+pub fn init_api(
+    app_state: &Arc<RwLock<AppState>>,
+    api_config: ApiConfig,
+    frontend_config: Option<FrontendConfig>,
+) -> Result<()> {
+    let routes = devices(app_state)
+        .or(actions(app_state))
+        .or(conflicts(app_state))
+        .or(debug(app_state))
+        .or(integrations(app_state))
+        .or(modes(app_state))
+        .or(routines(app_state))
+        .or(scenes(app_state))
+        .or(snapshot(app_state))
+        .or(sessions(app_state))
+        .or(users())
+        .or(logging())
+        .or(metrics(app_state))
+        .or(schema());
+
+    // Auth rejections are replied to here, so that they aren't answered by
+    // the frontend
     let api = warp::path("api")
         .and(warp::path("v1"))
-        .and(devices(app_state).or(actions(app_state)));
+        .and(authorize(api_config.clone()))
+        .and(routes)
+        .recover(handle_auth_rejection);
 
-    let ws = ws(app_state);
+    let ws = ws(app_state, api_config);
+    let frontend = frontend(frontend_config);
 
     tokio::spawn(async move {
-        warp::serve(ws.or(api)).run(([0, 0, 0, 0], 45289)).await;
+        warp::serve(ws.or(api).or(frontend))
+            .run(([0, 0, 0, 0], 45289))
+            .await;
     });
 
     Ok(())
@@ -0,0 +1,84 @@
+use std::{convert::Infallible, sync::Arc};
+
+use crate::core::state::AppState;
+use crate::types::{
+    action::Action,
+    event::Message,
+    mode::{ModeId, SetModeDescriptor},
+};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use tokio::sync::RwLock;
+use warp::{http::StatusCode, Filter};
+
+use super::with_state;
+
+#[derive(Serialize)]
+struct ModeError {
+    error: String,
+}
+
+#[derive(Serialize)]
+pub struct ModesResponse {
+    mode: ModeId,
+    modes: BTreeSet<ModeId>,
+}
+
+pub fn modes(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("modes").and(get_modes(app_state).or(put_mode(app_state)))
+}
+
+fn get_modes(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path::end()
+        .and(warp::get())
+        .and(with_state(app_state))
+        .and_then(get_modes_impl)
+}
+
+async fn get_modes_impl(app_state: Arc<RwLock<AppState>>) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+
+    Ok(warp::reply::json(&ModesResponse {
+        mode: app_state.modes.get_mode().clone(),
+        modes: app_state.modes.get_modes(),
+    }))
+}
+
+fn put_mode(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path::end()
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(with_state(app_state))
+        .and_then(put_mode_impl)
+}
+
+async fn put_mode_impl(
+    descriptor: SetModeDescriptor,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+
+    if !app_state.modes.get_modes().contains(&descriptor.mode) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&ModeError {
+                error: format!("Unknown mode {}", descriptor.mode),
+            }),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    app_state
+        .event_tx
+        .send(Message::Action(Action::SetMode(descriptor)));
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&()),
+        StatusCode::OK,
+    ))
+}
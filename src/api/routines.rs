@@ -0,0 +1,68 @@
+use std::{convert::Infallible, sync::Arc};
+
+use crate::core::state::AppState;
+use crate::types::simulation::SimulationDescriptor;
+use tokio::sync::RwLock;
+use warp::Filter;
+
+use super::with_state;
+
+pub fn routines(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("routines").and(simulate(app_state).or(stats(app_state)))
+}
+
+/// GET /routines/stats
+///
+/// Returns trigger counts, last trigger time and rule evaluation durations of
+/// each routine
+fn stats(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("stats")
+        .and(warp::get())
+        .and(with_state(app_state))
+        .and_then(stats_impl)
+}
+
+async fn stats_impl(app_state: Arc<RwLock<AppState>>) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+
+    Ok(warp::reply::json(&app_state.rules.get_stats()))
+}
+
+/// POST /routines/simulate
+///
+/// Reports which routines the given sensor events would trigger, without
+/// affecting devices
+fn simulate(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("simulate")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_state(app_state))
+        .and_then(simulate_impl)
+}
+
+async fn simulate_impl(
+    descriptor: SimulationDescriptor,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+
+    let steps = app_state
+        .rules
+        .simulate(
+            &descriptor,
+            &app_state.devices,
+            &app_state.scenes,
+            &app_state.groups,
+            &app_state.expr,
+            &app_state.quiet_hours,
+        )
+        .await;
+
+    Ok(warp::reply::json(&steps))
+}
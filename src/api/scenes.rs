@@ -0,0 +1,32 @@
+use std::{convert::Infallible, sync::Arc};
+
+use crate::core::state::AppState;
+use tokio::sync::RwLock;
+use warp::Filter;
+
+use super::with_state;
+
+pub fn scenes(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("scenes").and(stats(app_state))
+}
+
+/// GET /scenes/stats
+///
+/// Returns activation counts and last activation time of each scene,
+/// including scenes that were never activated
+fn stats(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("stats")
+        .and(warp::get())
+        .and(with_state(app_state))
+        .and_then(stats_impl)
+}
+
+async fn stats_impl(app_state: Arc<RwLock<AppState>>) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+
+    Ok(warp::reply::json(&app_state.scenes.get_stats()))
+}
@@ -0,0 +1,17 @@
+use std::convert::Infallible;
+
+use crate::core::schema::config_schema;
+use warp::Filter;
+
+/// GET /config/schema
+///
+/// Returns the JSON Schema of the config file
+pub fn schema() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("config" / "schema")
+        .and(warp::get())
+        .and_then(get_schema_impl)
+}
+
+async fn get_schema_impl() -> Result<impl warp::Reply, Infallible> {
+    Ok(warp::reply::json(&config_schema()))
+}
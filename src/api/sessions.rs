@@ -0,0 +1,62 @@
+use std::{convert::Infallible, sync::Arc};
+
+use crate::core::state::AppState;
+use tokio::sync::RwLock;
+use warp::{http::StatusCode, Filter};
+
+use super::with_state;
+
+pub fn sessions(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("sessions").and(get_sessions(app_state).or(kick_session(app_state)))
+}
+
+/// GET /sessions
+///
+/// Lists connected WebSocket clients
+fn get_sessions(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path::end()
+        .and(warp::get())
+        .and(with_state(app_state))
+        .and_then(get_sessions_impl)
+}
+
+async fn get_sessions_impl(
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let ws = app_state.read().await.ws.clone();
+    let mut sessions = ws.get_sessions().await;
+    sessions.sort_by_key(|session| session.id);
+
+    Ok(warp::reply::json(&sessions))
+}
+
+/// DELETE /sessions/{id}
+///
+/// Disconnects a WebSocket client
+fn kick_session(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(usize)
+        .and(warp::delete())
+        .and(with_state(app_state))
+        .and_then(kick_session_impl)
+}
+
+async fn kick_session_impl(
+    session_id: usize,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let ws = app_state.read().await.ws.clone();
+
+    let status = if ws.kick(session_id).await {
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    };
+
+    Ok(warp::reply::with_status(warp::reply::json(&()), status))
+}
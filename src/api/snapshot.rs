@@ -0,0 +1,59 @@
+use std::{convert::Infallible, sync::Arc};
+
+use crate::core::state::AppState;
+use crate::types::{event::Message, snapshot::Snapshot};
+use tokio::sync::RwLock;
+use warp::Filter;
+
+use super::with_state;
+
+pub fn snapshot(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("snapshot").and(get_snapshot(app_state).or(post_snapshot(app_state)))
+}
+
+/// GET /snapshot
+///
+/// Exports the complete resolved state
+fn get_snapshot(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path::end()
+        .and(warp::get())
+        .and(with_state(app_state))
+        .and_then(get_snapshot_impl)
+}
+
+async fn get_snapshot_impl(
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+
+    Ok(warp::reply::json(&app_state.get_snapshot()))
+}
+
+/// POST /snapshot
+///
+/// Restores state from a previously exported snapshot
+fn post_snapshot(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path::end()
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_state(app_state))
+        .and_then(post_snapshot_impl)
+}
+
+async fn post_snapshot_impl(
+    snapshot: Snapshot,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+    app_state
+        .event_tx
+        .send(Message::ImportSnapshot { snapshot });
+
+    Ok(warp::reply::json(&()))
+}
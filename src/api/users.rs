@@ -0,0 +1,92 @@
+use std::convert::Infallible;
+
+use crate::db::actions::{
+    db_delete_user_preferences, db_get_user_preferences, db_store_user_preferences,
+};
+use crate::types::preferences::{UserId, UserPreferences};
+use serde::Serialize;
+use warp::{http::StatusCode, Filter};
+
+#[derive(Serialize)]
+struct PreferencesError {
+    error: String,
+}
+
+fn mk_error_reply(error: eyre::Report) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(
+        warp::reply::json(&PreferencesError {
+            error: error.to_string(),
+        }),
+        StatusCode::INTERNAL_SERVER_ERROR,
+    )
+}
+
+/// Preferences are stored in the database only, and don't need app state
+pub fn users() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("users").and(
+        get_preferences()
+            .or(put_preferences())
+            .or(delete_preferences()),
+    )
+}
+
+/// GET /users/{user_id}/preferences
+///
+/// Returns default preferences for users that haven't stored any
+fn get_preferences() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone
+{
+    warp::path!(UserId / "preferences")
+        .and(warp::get())
+        .and_then(get_preferences_impl)
+}
+
+async fn get_preferences_impl(user_id: UserId) -> Result<impl warp::Reply, Infallible> {
+    let reply = match db_get_user_preferences(&user_id).await {
+        Ok(preferences) => warp::reply::with_status(
+            warp::reply::json(&preferences.unwrap_or_default()),
+            StatusCode::OK,
+        ),
+        Err(e) => mk_error_reply(e),
+    };
+
+    Ok(reply)
+}
+
+/// PUT /users/{user_id}/preferences
+fn put_preferences() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone
+{
+    warp::path!(UserId / "preferences")
+        .and(warp::put())
+        .and(warp::body::json())
+        .and_then(put_preferences_impl)
+}
+
+async fn put_preferences_impl(
+    user_id: UserId,
+    preferences: UserPreferences,
+) -> Result<impl warp::Reply, Infallible> {
+    let reply = match db_store_user_preferences(&user_id, &preferences).await {
+        Ok(()) => warp::reply::with_status(warp::reply::json(&preferences), StatusCode::OK),
+        Err(e) => mk_error_reply(e),
+    };
+
+    Ok(reply)
+}
+
+/// DELETE /users/{user_id}/preferences
+fn delete_preferences(
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(UserId / "preferences")
+        .and(warp::delete())
+        .and_then(delete_preferences_impl)
+}
+
+async fn delete_preferences_impl(user_id: UserId) -> Result<impl warp::Reply, Infallible> {
+    let reply = match db_delete_user_preferences(&user_id).await {
+        Ok(true) => warp::reply::with_status(warp::reply::json(&()), StatusCode::OK),
+        Ok(false) => warp::reply::with_status(warp::reply::json(&()), StatusCode::NOT_FOUND),
+        Err(e) => mk_error_reply(e),
+    };
+
+    Ok(reply)
+}
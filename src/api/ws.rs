@@ -1,11 +1,26 @@
-use super::with_state;
-use crate::types::websockets::WebSocketRequest;
-use crate::AppState;
+use super::{
+    auth::{bearer_token, client_scope, requires_auth, token_scope},
+    with_state,
+};
+use crate::core::state::AppState;
+use crate::types::{
+    action::Action,
+    api::{ApiConfig, ApiScope},
+    event::Message,
+    integration::CustomActionDescriptor,
+    websockets::{
+        CommandResult, WebSocketAction, WebSocketAuth, WebSocketRequest, WebSocketResponse,
+        WebSocketSession,
+    },
+};
 use futures::SinkExt;
 use futures_util::{StreamExt, TryFutureExt};
-use std::sync::{
-    atomic::{AtomicUsize, Ordering},
-    Arc,
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
 };
 use tokio::sync::{mpsc, RwLock};
 use tokio_stream::wrappers::UnboundedReceiverStream;
@@ -14,27 +29,158 @@ use warp::{ws::WebSocket, Filter};
 /// Our global unique user id counter.
 static NEXT_USER_ID: AtomicUsize = AtomicUsize::new(1);
 
+const INSUFFICIENT_SCOPE: &str = "Token scope is insufficient";
+
 pub fn ws(
     app_state: &Arc<RwLock<AppState>>,
+    api_config: ApiConfig,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let api_config = Arc::new(api_config);
+
     warp::path("ws")
         // The `ws()` filter will prepare the Websocket handshake.
         .and(warp::ws())
+        .and(warp::addr::remote())
+        .and(warp::header::optional::<String>("user-agent"))
+        .and(warp::header::optional::<String>("authorization"))
         .and(with_state(app_state))
-        .map(|ws: warp::ws::Ws, app_state: Arc<RwLock<AppState>>| {
-            // This will call our function if the handshake succeeds.
-            ws.on_upgrade(move |socket| user_connected(socket, app_state))
-        })
+        .map(
+            move |ws: warp::ws::Ws,
+                  remote_addr: Option<SocketAddr>,
+                  user_agent: Option<String>,
+                  authorization: Option<String>,
+                  app_state: Arc<RwLock<AppState>>| {
+                let api_config = api_config.clone();
+
+                // API tokens may be sent with the handshake, clients that
+                // can't set headers send them when authenticating instead
+                let scope = bearer_token(authorization.as_deref())
+                    .and_then(|token| client_scope(&api_config, Some(token)));
+
+                let session = WebSocketSession {
+                    // Use a counter to assign a new unique ID for this user.
+                    id: NEXT_USER_ID.fetch_add(1, Ordering::Relaxed),
+                    client: None,
+                    remote_addr: remote_addr.map(|addr| addr.to_string()),
+                    user_agent,
+                    connected_at: chrono::Utc::now(),
+                };
+
+                // This will call our function if the handshake succeeds.
+                ws.on_upgrade(move |socket| {
+                    user_connected(socket, session, scope, api_config, app_state)
+                })
+            },
+        )
+}
+
+/// Waits for the client to authenticate with a WebSocket or API token, returns
+/// None if it didn't do so in time or used an invalid token. Once the API
+/// requires tokens, WebSocket tokens only allow receiving state.
+async fn authenticate(
+    user_ws_rx: &mut futures::stream::SplitStream<WebSocket>,
+    api_config: &ApiConfig,
+    app_state: &Arc<RwLock<AppState>>,
+) -> Option<(WebSocketAuth, ApiScope)> {
+    let ws = app_state.read().await.ws.clone();
+    let msg = tokio::time::timeout(ws.auth_timeout(), user_ws_rx.next())
+        .await
+        .ok()??
+        .ok()?;
+
+    let Ok(WebSocketRequest::Auth(auth)) = serde_json::from_str(msg.to_str().ok()?) else {
+        return None;
+    };
+
+    let api_scope = auth
+        .token
+        .as_deref()
+        .and_then(|token| token_scope(api_config, token));
+
+    let scope = match api_scope {
+        Some(scope) => scope,
+        None if ws.requires_auth() && ws.is_valid_token(auth.token.as_deref()) => {
+            if requires_auth(api_config) {
+                ApiScope::Read
+            } else {
+                ApiScope::Admin
+            }
+        }
+        None => return None,
+    };
+
+    Some((auth, scope))
+}
+
+/// Checks that the action refers to things that exist, so that clients get an
+/// error instead of the action silently doing nothing
+fn validate_action(app_state: &AppState, action: &Action) -> Result<(), String> {
+    if app_state.standby {
+        return Err("Actions are run by the primary instance".to_string());
+    }
+
+    match action {
+        Action::ActivateScene(sd) if app_state.scenes.find_scene(&sd.scene_id).is_none() => {
+            Err(format!("Scene {} not found", sd.scene_id))
+        }
+        Action::SetDeviceState(device)
+            if app_state
+                .devices
+                .get_device(&device.get_device_key())
+                .is_none() =>
+        {
+            Err(format!("Device {} not found", device.get_device_key()))
+        }
+        Action::Custom(CustomActionDescriptor { integration_id, .. })
+            if !app_state.integrations.is_running(integration_id) =>
+        {
+            Err(format!("Integration {} is not running", integration_id))
+        }
+        _ => Ok(()),
+    }
 }
 
 // https://github.com/seanmonstar/warp/blob/master/examples/websockets_chat.rs
-async fn user_connected(ws: WebSocket, app_state: Arc<RwLock<AppState>>) {
-    // Use a counter to assign a new unique ID for this user.
-    let my_id = NEXT_USER_ID.fetch_add(1, Ordering::Relaxed);
+async fn user_connected(
+    ws: WebSocket,
+    mut session: WebSocketSession,
+    handshake_scope: Option<ApiScope>,
+    api_config: Arc<ApiConfig>,
+    app_state: Arc<RwLock<AppState>>,
+) {
+    let my_id = session.id;
 
     // Split the socket into a sender and receive of messages.
     let (mut user_ws_tx, mut user_ws_rx) = ws.split();
 
+    let mut scope = handshake_scope;
+
+    let requires_ws_auth = app_state.read().await.ws.requires_auth();
+    if requires_ws_auth || (scope.is_none() && requires_auth(&api_config)) {
+        if let Some((auth, auth_scope)) =
+            authenticate(&mut user_ws_rx, &api_config, &app_state).await
+        {
+            session.client = auth.client;
+            scope = scope.max(Some(auth_scope));
+        } else {
+            scope = None;
+        }
+    } else if scope.is_none() {
+        scope = Some(ApiScope::Admin);
+    }
+
+    let Some(scope) = scope else {
+        warn!(
+            "WebSocket client from {} failed to authenticate",
+            session.remote_addr.as_deref().unwrap_or("unknown address")
+        );
+
+        // 4001: Unauthorized
+        let msg = warp::ws::Message::close_with(4001u16, "Unauthorized");
+        user_ws_tx.send(msg).await.ok();
+        return;
+    };
+
     // Use an unbounded channel to handle buffering and flushing of messages
     // to the websocket...
     let (tx, rx) = mpsc::unbounded_channel();
@@ -51,13 +197,17 @@ async fn user_connected(ws: WebSocket, app_state: Arc<RwLock<AppState>>) {
         }
     });
 
-    let app_state = app_state.read().await;
+    let (ws, event_tx) = {
+        let app_state = app_state.read().await;
+
+        // Save the sender in our list of connected users.
+        app_state.ws.user_connected(session, tx).await;
 
-    // Save the sender in our list of connected users.
-    app_state.ws.user_connected(my_id, tx).await;
+        // Send snapshot of current state
+        app_state.send_state_ws(Some(my_id)).await;
 
-    // Send snapshot of current state
-    app_state.send_state_ws(Some(my_id)).await;
+        (app_state.ws.clone(), app_state.event_tx.clone())
+    };
 
     // Let AppState handle incoming user messages
     while let Some(result) = user_ws_rx.next().await {
@@ -75,15 +225,52 @@ async fn user_connected(ws: WebSocket, app_state: Arc<RwLock<AppState>>) {
             let msg = serde_json::from_str::<WebSocketRequest>(json);
 
             match msg {
-                Ok(WebSocketRequest::Message(msg)) => {
-                    app_state.event_tx.send(msg);
+                // Raw messages can add and remove integrations or migrate
+                // devices
+                Ok(WebSocketRequest::Message(msg)) if scope >= ApiScope::Admin => {
+                    event_tx.send(msg);
+                }
+                Ok(WebSocketRequest::Message(_)) => {
+                    let response = WebSocketResponse::CommandResult(CommandResult {
+                        id: None,
+                        error: Some(INSUFFICIENT_SCOPE.to_string()),
+                    });
+                    ws.send(Some(my_id), &response).await;
+                }
+                Ok(WebSocketRequest::Action(WebSocketAction { id, action })) => {
+                    let result = if scope >= ApiScope::Control {
+                        validate_action(&*app_state.read().await, &action)
+                    } else {
+                        Err(INSUFFICIENT_SCOPE.to_string())
+                    };
+
+                    if result.is_ok() {
+                        event_tx.send(Message::Action(action));
+                    }
+
+                    let response = WebSocketResponse::CommandResult(CommandResult {
+                        id: Some(id),
+                        error: result.err(),
+                    });
+                    ws.send(Some(my_id), &response).await;
+                }
+                Ok(WebSocketRequest::Auth(_)) => {
+                    debug!("Ignoring repeated auth from websocket client {}", my_id);
+                }
+                Err(e) => {
+                    warn!("Error while deserializing websocket message: {}", e);
+
+                    let response = WebSocketResponse::CommandResult(CommandResult {
+                        id: None,
+                        error: Some(e.to_string()),
+                    });
+                    ws.send(Some(my_id), &response).await;
                 }
-                Err(e) => warn!("Error while deserializing websocket message: {}", e),
             }
         }
     }
 
     // user_ws_rx stream will keep processing as long as the user stays
     // connected. Once they disconnect, then...
-    app_state.ws.user_disconnected(my_id).await;
+    ws.user_disconnected(my_id).await;
 }
@@ -0,0 +1,7 @@
+use homectl_server::core::admin_cli::run_admin_cli;
+
+#[tokio::main]
+async fn main() -> color_eyre::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    run_admin_cli(&args).await
+}
@@ -0,0 +1,177 @@
+//! The `homectl` command line tool, administering a running server through
+//! its API.
+
+use std::path::Path;
+
+use color_eyre::Result;
+use eyre::{eyre, Context};
+use hyper::Method;
+use serde::Deserialize;
+
+use crate::types::{
+    action::Action,
+    device::{Device, DeviceData, SensorDevice},
+    scene::{SceneDescriptor, SceneId},
+};
+
+use super::{
+    config::read_config_file,
+    http::{get, request},
+    schema::config_schema,
+};
+
+const DEFAULT_URL: &str = "http://localhost:45289";
+
+const USAGE: &str = "Usage: homectl [--url <url>] <command>
+
+Commands:
+    devices list                List all devices and their current state
+    scene activate <scene_id>   Activate a scene
+    config check [path]         Check that a config file is valid, defaults to Settings.toml
+    config schema               Print the JSON Schema of the config file
+
+The server url defaults to the HOMECTL_URL environment variable, or
+http://localhost:45289";
+
+#[derive(Deserialize)]
+struct DevicesResponse {
+    devices: Vec<Device>,
+}
+
+fn format_device(device: &Device) -> String {
+    let state = match &device.data {
+        DeviceData::Controllable(controllable) => controllable.state.to_string(),
+        DeviceData::Sensor(SensorDevice::Boolean { value }) => value.to_string(),
+        DeviceData::Sensor(SensorDevice::Number { value, .. }) => value.to_string(),
+        DeviceData::Sensor(SensorDevice::Text { value }) => value.clone(),
+        DeviceData::Sensor(SensorDevice::Color(state)) => state.to_string(),
+    };
+
+    format!(
+        "{:<40} {:<30} {}",
+        device.get_device_key().to_string(),
+        device.name,
+        state
+    )
+}
+
+async fn devices_list(url: &str) -> Result<()> {
+    let (status, body) = get(&format!("{url}/api/v1/devices"), &[])
+        .await
+        .wrap_err_with(|| eyre!("Failed to reach homectl at {url}"))?;
+
+    if !status.is_success() {
+        return Err(eyre!("Server responded with {}", status));
+    }
+
+    let response: DevicesResponse = serde_json::from_slice(&body)?;
+    let mut devices = response.devices;
+    devices.sort_by_key(|device| device.get_device_key());
+
+    for device in devices {
+        println!("{}", format_device(&device));
+    }
+
+    Ok(())
+}
+
+async fn scene_activate(url: &str, scene_id: &str) -> Result<()> {
+    let action = Action::ActivateScene(SceneDescriptor {
+        scene_id: SceneId::new(scene_id.to_string()),
+        device_keys: None,
+        group_keys: None,
+        transition_ms: None,
+        brightness: None,
+        override_window: Some(true),
+    });
+
+    let headers = [("content-type".to_string(), "application/json".to_string())];
+    let (status, _) = request(
+        Method::POST,
+        &format!("{url}/api/v1/actions/trigger"),
+        &headers,
+        serde_json::to_vec(&action)?,
+    )
+    .await
+    .wrap_err_with(|| eyre!("Failed to reach homectl at {url}"))?;
+
+    if !status.is_success() {
+        return Err(eyre!("Server responded with {}", status));
+    }
+
+    println!("Activated scene {scene_id}");
+
+    Ok(())
+}
+
+fn config_check(path: &str) -> Result<()> {
+    let source = config::File::from(Path::new(path)).format(config::FileFormat::Toml);
+    let (config, _) = read_config_file(source).wrap_err_with(|| eyre!("{path} is not valid"))?;
+    let routines = config
+        .get_routines()
+        .wrap_err_with(|| eyre!("{path} is not valid"))?;
+
+    println!(
+        "{path} is valid: {} integrations, {} scenes, {} groups, {} routines",
+        config.integrations.unwrap_or_default().len(),
+        config.scenes.unwrap_or_default().len(),
+        config.groups.unwrap_or_default().len(),
+        routines.len(),
+    );
+
+    Ok(())
+}
+
+/// Runs the `homectl` command given by `args`
+pub async fn run_admin_cli(args: &[String]) -> Result<()> {
+    let mut url = std::env::var("HOMECTL_URL").unwrap_or_else(|_| DEFAULT_URL.to_string());
+    let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    if args.first() == Some(&"--url") {
+        url = args
+            .get(1)
+            .ok_or_else(|| eyre!("Expected an url after --url"))?
+            .to_string();
+        args.drain(..2);
+    }
+
+    let url = url.trim_end_matches('/');
+
+    match args.as_slice() {
+        ["devices", "list"] => devices_list(url).await,
+        ["scene", "activate", scene_id] => scene_activate(url, scene_id).await,
+        ["config", "check"] => config_check("Settings.toml"),
+        ["config", "check", path] => config_check(path),
+        ["config", "schema"] => {
+            println!("{}", serde_json::to_string_pretty(&config_schema())?);
+            Ok(())
+        }
+        _ => Err(eyre!(USAGE)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{device::DeviceId, integration::IntegrationId};
+    use ordered_float::OrderedFloat;
+
+    #[test]
+    fn test_format_device() {
+        let device = Device::new(
+            IntegrationId::from("zigbee".to_string()),
+            DeviceId::new("hall_temp"),
+            "Hall temperature".to_string(),
+            DeviceData::Sensor(SensorDevice::Number {
+                value: OrderedFloat(21.5),
+                unit: None,
+                raw: None,
+            }),
+        );
+
+        assert_eq!(
+            format_device(&device),
+            format!("{:<40} {:<30} 21.5", "zigbee/hall_temp", "Hall temperature")
+        );
+    }
+}
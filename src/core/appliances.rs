@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::types::{
+    appliance::{ApplianceConfig, ApplianceId, AppliancesConfig, APPLIANCES_INTEGRATION_ID},
+    device::{Device, DeviceData, DeviceId, SensorDevice},
+    event::{Message, TxEventChannel},
+    integration::IntegrationId,
+};
+
+use super::devices::Devices;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct ApplianceState {
+    running: bool,
+
+    /// When power crossed the threshold that would toggle `running`
+    crossed_at: Option<Instant>,
+}
+
+/// Advances the state of an appliance given its current power draw. Returns
+/// the new state, and how long to wait before re-evaluating if the appliance
+/// is about to toggle.
+fn step(
+    config: &ApplianceConfig,
+    state: ApplianceState,
+    power: f64,
+    now: Instant,
+) -> (ApplianceState, Option<Duration>) {
+    let (crossed, min_duration) = if state.running {
+        (power < config.stop_below, config.stop_secs)
+    } else {
+        (power > config.start_above, config.start_secs)
+    };
+
+    if !crossed {
+        return (
+            ApplianceState {
+                running: state.running,
+                crossed_at: None,
+            },
+            None,
+        );
+    }
+
+    let crossed_at = state.crossed_at.unwrap_or(now);
+    let min_duration = Duration::from_secs(min_duration);
+    let elapsed = now.duration_since(crossed_at);
+
+    if elapsed >= min_duration {
+        (
+            ApplianceState {
+                running: !state.running,
+                crossed_at: None,
+            },
+            None,
+        )
+    } else {
+        (
+            ApplianceState {
+                running: state.running,
+                crossed_at: Some(crossed_at),
+            },
+            Some(min_duration - elapsed),
+        )
+    }
+}
+
+fn get_power(device: &Device) -> Option<f64> {
+    match device.get_sensor_state()? {
+        SensorDevice::Number { value, .. } => Some(value.0),
+        _ => None,
+    }
+}
+
+/// Derives on/off "appliance running" sensors from power sensors, e.g. to
+/// detect when a washing machine has finished
+#[derive(Clone)]
+pub struct Appliances {
+    config: AppliancesConfig,
+    event_tx: TxEventChannel,
+    states: HashMap<ApplianceId, ApplianceState>,
+}
+
+impl Appliances {
+    pub fn new(config: AppliancesConfig, event_tx: TxEventChannel) -> Self {
+        Appliances {
+            config,
+            event_tx,
+            states: Default::default(),
+        }
+    }
+
+    /// Re-evaluates appliances whose power sensor has changed
+    pub fn handle_internal_state_update(&mut self, new: &Device, devices: &Devices) {
+        let device_key = new.get_device_key();
+
+        let appliance_ids: Vec<ApplianceId> = self
+            .config
+            .iter()
+            .filter(|(_, appliance)| {
+                devices
+                    .get_device_by_ref(&appliance.power_sensor)
+                    .map(|device| device.get_device_key())
+                    == Some(device_key.clone())
+            })
+            .map(|(appliance_id, _)| appliance_id.clone())
+            .collect();
+
+        for appliance_id in appliance_ids {
+            self.evaluate(&appliance_id, devices);
+        }
+    }
+
+    /// Re-evaluates all appliances, used when a minimum duration has elapsed
+    pub fn refresh(&mut self, devices: &Devices) {
+        let appliance_ids: Vec<ApplianceId> = self.config.keys().cloned().collect();
+
+        for appliance_id in appliance_ids {
+            self.evaluate(&appliance_id, devices);
+        }
+    }
+
+    fn evaluate(&mut self, appliance_id: &ApplianceId, devices: &Devices) {
+        let appliance = &self.config[appliance_id];
+
+        let Some(power) = devices
+            .get_device_by_ref(&appliance.power_sensor)
+            .and_then(get_power)
+        else {
+            return;
+        };
+
+        let state = self.states.get(appliance_id).copied().unwrap_or_default();
+        let (state, refresh_in) = step(appliance, state, power, Instant::now());
+        self.states.insert(appliance_id.clone(), state);
+
+        if let Some(refresh_in) = refresh_in {
+            let event_tx = self.event_tx.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(refresh_in).await;
+                event_tx.send(Message::RefreshAppliances);
+            });
+        }
+
+        let device = Device::new(
+            IntegrationId::from_str(APPLIANCES_INTEGRATION_ID).unwrap(),
+            DeviceId::new(&appliance_id.to_string()),
+            appliance.name.clone(),
+            DeviceData::Sensor(SensorDevice::Boolean {
+                value: state.running,
+            }),
+        );
+
+        if devices.get_device(&device.get_device_key()) != Some(&device) {
+            self.event_tx.send(Message::RecvDeviceState { device });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::{device::DeviceRef, integration::IntegrationId};
+
+    use super::*;
+
+    #[test]
+    fn test_step() {
+        let config = ApplianceConfig {
+            name: "Washing machine".to_string(),
+            power_sensor: DeviceRef::new_with_name(
+                IntegrationId::from("tuya".to_string()),
+                "Washing machine plug".to_string(),
+            ),
+            start_above: 10.0,
+            stop_below: 3.0,
+            start_secs: 0,
+            stop_secs: 60,
+        };
+
+        let now = Instant::now();
+        let secs = |secs| now + Duration::from_secs(secs);
+
+        let (state, refresh_in) = step(&config, ApplianceState::default(), 500.0, now);
+        assert!(state.running);
+        assert_eq!(refresh_in, None);
+
+        // Idling between cycles
+        let (state, refresh_in) = step(&config, state, 1.0, secs(10));
+        assert!(state.running);
+        assert_eq!(refresh_in, Some(Duration::from_secs(60)));
+
+        let (state, _) = step(&config, state, 200.0, secs(30));
+        assert!(state.running);
+        assert_eq!(state.crossed_at, None);
+
+        // Finished
+        let (state, _) = step(&config, state, 1.0, secs(100));
+        let (state, refresh_in) = step(&config, state, 1.0, secs(160));
+        assert!(!state.running);
+        assert_eq!(refresh_in, None);
+    }
+}
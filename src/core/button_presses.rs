@@ -0,0 +1,344 @@
+//! Tells single, double and long presses of buttons apart, so that the same
+//! button can cycle scenes on a single press and turn them off on another
+//! kind of press
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::types::{
+    action::Action,
+    device::{Device, DeviceKey, SensorDevice},
+    event::{Message, TxEventChannel},
+    power::PowerDescriptor,
+    scene::{CycleScenesDescriptor, PressType},
+};
+
+use super::devices::Devices;
+
+/// How long to wait for a second press or a release before a press is
+/// considered a single press
+const PRESS_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Debug, PartialEq)]
+struct PendingPress {
+    descriptor: CycleScenesDescriptor,
+
+    /// Devices of the cycled scenes
+    area: Vec<DeviceKey>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct PressState {
+    /// Press that hasn't been told apart from a double or long press yet
+    pending: Option<PendingPress>,
+
+    /// Incremented to cancel pending timers
+    generation: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum PressEvent {
+    Press {
+        press: PendingPress,
+        reported: Option<PressType>,
+    },
+    Timeout {
+        generation: u64,
+        held: bool,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Effect {
+    Cycle(CycleScenesDescriptor),
+    TurnOff(Vec<DeviceKey>),
+    StartTimer { generation: u64 },
+}
+
+fn resolve(press: PendingPress, press_type: PressType) -> Effect {
+    if press.descriptor.turn_off_on.contains(&press_type) {
+        Effect::TurnOff(press.area)
+    } else {
+        Effect::Cycle(press.descriptor)
+    }
+}
+
+fn step(mut state: PressState, event: PressEvent) -> (PressState, Vec<Effect>) {
+    let effects = match event {
+        PressEvent::Press {
+            press,
+            reported: Some(press_type),
+        } => {
+            state.generation += 1;
+            state.pending = None;
+            vec![resolve(press, press_type)]
+        }
+        PressEvent::Press {
+            press,
+            reported: None,
+        } => {
+            state.generation += 1;
+
+            match state.pending.take() {
+                Some(pending) if pending.descriptor.turn_off_on.contains(&PressType::Double) => {
+                    vec![resolve(pending, PressType::Double)]
+                }
+                pending => {
+                    // Without double press handling, a quick second press
+                    // cycles once more
+                    let mut effects: Vec<Effect> = pending
+                        .map(|pending| Effect::Cycle(pending.descriptor))
+                        .into_iter()
+                        .collect();
+
+                    state.pending = Some(press);
+                    effects.push(Effect::StartTimer {
+                        generation: state.generation,
+                    });
+
+                    effects
+                }
+            }
+        }
+        PressEvent::Timeout { generation, held } if generation == state.generation => {
+            let press_type = if held {
+                PressType::Long
+            } else {
+                PressType::Single
+            };
+
+            state
+                .pending
+                .take()
+                .map(|pending| resolve(pending, press_type))
+                .into_iter()
+                .collect()
+        }
+        PressEvent::Timeout { .. } => vec![],
+    };
+
+    (state, effects)
+}
+
+/// Press type reported by integrations that tell presses apart themselves,
+/// e.g. as the action of a Zigbee remote
+fn parse_press_type(value: &str) -> Option<PressType> {
+    match value.to_lowercase().as_str() {
+        "single" | "press" | "click" => Some(PressType::Single),
+        "double" | "double_press" | "double_click" => Some(PressType::Double),
+        "long" | "hold" | "long_press" => Some(PressType::Long),
+        _ => None,
+    }
+}
+
+fn is_held(button: &Device) -> bool {
+    matches!(
+        button.get_sensor_state(),
+        Some(SensorDevice::Boolean { value: true })
+    )
+}
+
+#[derive(Clone)]
+pub struct ButtonPresses {
+    event_tx: TxEventChannel,
+    states: HashMap<DeviceKey, PressState>,
+}
+
+impl ButtonPresses {
+    pub fn new(event_tx: TxEventChannel) -> Self {
+        ButtonPresses {
+            event_tx,
+            states: Default::default(),
+        }
+    }
+
+    /// The button of a [Action::CycleScenes] with `turn_off_on` was pressed.
+    /// `area` holds the devices of the cycled scenes.
+    pub fn handle_press(
+        &mut self,
+        descriptor: &CycleScenesDescriptor,
+        area: Vec<DeviceKey>,
+        devices: &Devices,
+    ) {
+        let press = PendingPress {
+            descriptor: descriptor.clone(),
+            area,
+        };
+
+        let Some(button) = descriptor
+            .button
+            .as_ref()
+            .and_then(|button| devices.get_device_by_ref(button))
+        else {
+            warn!("Can't tell presses apart without a known button, cycling scenes");
+            self.apply_effect(None, Effect::Cycle(press.descriptor));
+            return;
+        };
+
+        let reported = match button.get_sensor_state() {
+            Some(SensorDevice::Text { value }) => parse_press_type(value),
+            _ => None,
+        };
+
+        self.apply(
+            button.get_device_key(),
+            PressEvent::Press { press, reported },
+        );
+    }
+
+    /// No second press has arrived in time
+    pub fn handle_timeout(&mut self, button: &DeviceKey, generation: u64, devices: &Devices) {
+        let held = devices.get_device(button).map_or(false, is_held);
+
+        self.apply(button.clone(), PressEvent::Timeout { generation, held });
+    }
+
+    fn apply(&mut self, button: DeviceKey, event: PressEvent) {
+        let state = self.states.remove(&button).unwrap_or_default();
+        let (state, effects) = step(state, event);
+        self.states.insert(button.clone(), state);
+
+        for effect in effects {
+            self.apply_effect(Some(&button), effect);
+        }
+    }
+
+    fn apply_effect(&self, button: Option<&DeviceKey>, effect: Effect) {
+        match effect {
+            Effect::Cycle(descriptor) => {
+                // Cycles right away once press handling is left out
+                let descriptor = CycleScenesDescriptor {
+                    button: None,
+                    turn_off_on: vec![],
+                    ..descriptor
+                };
+
+                self.event_tx
+                    .send(Message::Action(Action::CycleScenes(descriptor)));
+            }
+            Effect::TurnOff(area) => {
+                self.event_tx
+                    .send(Message::Action(Action::TurnOff(PowerDescriptor {
+                        device_keys: Some(area),
+                        group_keys: None,
+                        areas: None,
+                    })));
+            }
+            Effect::StartTimer { generation } => {
+                let Some(button) = button.cloned() else {
+                    return;
+                };
+                let event_tx = self.event_tx.clone();
+
+                tokio::spawn(async move {
+                    tokio::time::sleep(PRESS_TIMEOUT).await;
+                    event_tx.send(Message::ButtonPressTimeout { button, generation });
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::scene::{SceneDescriptor, SceneId};
+
+    fn mk_press(turn_off_on: Vec<PressType>) -> PendingPress {
+        PendingPress {
+            descriptor: CycleScenesDescriptor {
+                scenes: vec![SceneDescriptor {
+                    scene_id: SceneId::new("bright".to_string()),
+                    device_keys: None,
+                    group_keys: None,
+                    transition_ms: None,
+                    brightness: None,
+                    override_window: None,
+                }],
+                nowrap: None,
+                button: None,
+                turn_off_on,
+            },
+            area: vec![],
+        }
+    }
+
+    fn start_press(state: PressState, press: &PendingPress) -> (PressState, u64) {
+        let (state, effects) = step(
+            state,
+            PressEvent::Press {
+                press: press.clone(),
+                reported: None,
+            },
+        );
+        let [Effect::StartTimer { generation }] = effects[..] else {
+            panic!("Expected timer to start, got {:?}", effects);
+        };
+
+        (state, generation)
+    }
+
+    #[test]
+    fn test_step_single_and_long() {
+        let press = mk_press(vec![PressType::Long]);
+
+        let (state, generation) = start_press(Default::default(), &press);
+        let (state, effects) = step(
+            state,
+            PressEvent::Timeout {
+                generation,
+                held: false,
+            },
+        );
+        assert_eq!(effects, vec![Effect::Cycle(press.descriptor.clone())]);
+
+        let (state, generation) = start_press(state, &press);
+        let (_, effects) = step(
+            state,
+            PressEvent::Timeout {
+                generation,
+                held: true,
+            },
+        );
+        assert_eq!(effects, vec![Effect::TurnOff(vec![])]);
+    }
+
+    #[test]
+    fn test_step_double() {
+        let press = mk_press(vec![PressType::Double]);
+
+        let (state, generation) = start_press(Default::default(), &press);
+        let (state, effects) = step(
+            state,
+            PressEvent::Press {
+                press: press.clone(),
+                reported: None,
+            },
+        );
+        assert_eq!(effects, vec![Effect::TurnOff(vec![])]);
+
+        // Timer of the first press is stale
+        let (_, effects) = step(
+            state,
+            PressEvent::Timeout {
+                generation,
+                held: false,
+            },
+        );
+        assert_eq!(effects, vec![]);
+    }
+
+    #[test]
+    fn test_step_reported() {
+        let press = mk_press(vec![PressType::Long]);
+
+        let (_, effects) = step(
+            Default::default(),
+            PressEvent::Press {
+                press: press.clone(),
+                reported: parse_press_type("hold"),
+            },
+        );
+        assert_eq!(effects, vec![Effect::TurnOff(vec![])]);
+    }
+}
@@ -0,0 +1,284 @@
+//! Holiday calendar, telling workdays apart from weekends and public holidays
+
+use std::collections::HashSet;
+
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use color_eyre::Result;
+use eyre::eyre;
+
+use crate::types::calendar::{CalendarConfig, CalendarDay, HolidayCountry};
+
+/// How the date of a holiday is determined in a given year
+enum HolidayRule {
+    /// Same month and day every year
+    Fixed(u32, u32),
+    /// Days relative to Easter Sunday
+    Easter(i64),
+    /// Nth weekday of the month, counting from the end if negative
+    NthWeekday(u32, Weekday, i8),
+    /// First weekday on or after given month and day
+    WeekdayFrom(u32, u32, Weekday),
+}
+
+use HolidayRule::*;
+
+fn country_holidays(country: HolidayCountry) -> &'static [HolidayRule] {
+    match country {
+        HolidayCountry::De => &[
+            Fixed(1, 1),
+            Easter(-2),
+            Easter(1),
+            Fixed(5, 1),
+            Easter(39),
+            Easter(50),
+            Fixed(10, 3),
+            Fixed(12, 25),
+            Fixed(12, 26),
+        ],
+        HolidayCountry::Fi => &[
+            Fixed(1, 1),
+            Fixed(1, 6),
+            Easter(-2),
+            Easter(1),
+            Fixed(5, 1),
+            Easter(39),
+            // Midsummer eve and day
+            WeekdayFrom(6, 19, Weekday::Fri),
+            WeekdayFrom(6, 20, Weekday::Sat),
+            // All Saints' Day
+            WeekdayFrom(10, 31, Weekday::Sat),
+            Fixed(12, 6),
+            Fixed(12, 24),
+            Fixed(12, 25),
+            Fixed(12, 26),
+        ],
+        HolidayCountry::Gb => &[
+            Fixed(1, 1),
+            Easter(-2),
+            Easter(1),
+            NthWeekday(5, Weekday::Mon, 1),
+            NthWeekday(5, Weekday::Mon, -1),
+            NthWeekday(8, Weekday::Mon, -1),
+            Fixed(12, 25),
+            Fixed(12, 26),
+        ],
+        HolidayCountry::Se => &[
+            Fixed(1, 1),
+            Fixed(1, 6),
+            Easter(-2),
+            Easter(1),
+            Fixed(5, 1),
+            Easter(39),
+            Fixed(6, 6),
+            // Midsummer eve and day
+            WeekdayFrom(6, 19, Weekday::Fri),
+            WeekdayFrom(6, 20, Weekday::Sat),
+            // All Saints' Day
+            WeekdayFrom(10, 31, Weekday::Sat),
+            Fixed(12, 24),
+            Fixed(12, 25),
+            Fixed(12, 26),
+            Fixed(12, 31),
+        ],
+        HolidayCountry::Us => &[
+            Fixed(1, 1),
+            NthWeekday(1, Weekday::Mon, 3),
+            NthWeekday(2, Weekday::Mon, 3),
+            NthWeekday(5, Weekday::Mon, -1),
+            Fixed(6, 19),
+            Fixed(7, 4),
+            NthWeekday(9, Weekday::Mon, 1),
+            NthWeekday(10, Weekday::Mon, 2),
+            Fixed(11, 11),
+            NthWeekday(11, Weekday::Thu, 4),
+            Fixed(12, 25),
+        ],
+    }
+}
+
+/// Date of Easter Sunday in the Gregorian calendar
+fn easter(year: i32) -> Option<NaiveDate> {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+
+    NaiveDate::from_ymd_opt(year, month as u32, day as u32)
+}
+
+impl HolidayRule {
+    fn date(&self, year: i32) -> Option<NaiveDate> {
+        match *self {
+            Fixed(month, day) => NaiveDate::from_ymd_opt(year, month, day),
+            Easter(offset) => Some(easter(year)? + Duration::days(offset)),
+            NthWeekday(month, weekday, n) if n > 0 => {
+                NaiveDate::from_weekday_of_month_opt(year, month, weekday, n as u8)
+            }
+            NthWeekday(month, weekday, n) => {
+                let (next_year, next_month) = if month == 12 {
+                    (year + 1, 1)
+                } else {
+                    (year, month + 1)
+                };
+                let last_day = NaiveDate::from_ymd_opt(next_year, next_month, 1)?.pred_opt()?;
+                let days_back = (7 + last_day.weekday().num_days_from_monday()
+                    - weekday.num_days_from_monday())
+                    % 7;
+
+                Some(last_day - Duration::days(days_back as i64 + 7 * (-n as i64 - 1)))
+            }
+            WeekdayFrom(month, day, weekday) => {
+                let from = NaiveDate::from_ymd_opt(year, month, day)?;
+                let days_ahead = (7 + weekday.num_days_from_monday()
+                    - from.weekday().num_days_from_monday())
+                    % 7;
+
+                Some(from + Duration::days(days_ahead as i64))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Calendar {
+    country: Option<HolidayCountry>,
+    dates: HashSet<NaiveDate>,
+    recurring: HashSet<(u32, u32)>,
+    weekend: Vec<Weekday>,
+}
+
+impl Default for Calendar {
+    fn default() -> Self {
+        Calendar::new(CalendarConfig::default()).expect("Expected default calendar to be valid")
+    }
+}
+
+impl Calendar {
+    pub fn new(config: CalendarConfig) -> Result<Self> {
+        let mut dates = HashSet::new();
+        let mut recurring = HashSet::new();
+
+        for holiday in &config.holidays {
+            if let Ok(date) = NaiveDate::parse_from_str(holiday, "%Y-%m-%d") {
+                dates.insert(date);
+            } else if let Some(date) = holiday
+                .split_once('-')
+                .and_then(|(month, day)| Some((month.parse().ok()?, day.parse().ok()?)))
+                // Checked against a leap year so that 02-29 is allowed
+                .filter(|(month, day)| NaiveDate::from_ymd_opt(2000, *month, *day).is_some())
+            {
+                recurring.insert(date);
+            } else {
+                return Err(eyre!(
+                    "Invalid holiday {}, expected YYYY-MM-DD or MM-DD",
+                    holiday
+                ));
+            }
+        }
+
+        Ok(Calendar {
+            country: config.country,
+            dates,
+            recurring,
+            weekend: config.weekend,
+        })
+    }
+
+    pub fn is_holiday(&self, date: NaiveDate) -> bool {
+        if self.dates.contains(&date) || self.recurring.contains(&(date.month(), date.day())) {
+            return true;
+        }
+
+        self.country.map_or(false, |country| {
+            country_holidays(country)
+                .iter()
+                .any(|rule| rule.date(date.year()) == Some(date))
+        })
+    }
+
+    pub fn is_workday(&self, date: NaiveDate) -> bool {
+        !self.weekend.contains(&date.weekday()) && !self.is_holiday(date)
+    }
+
+    pub fn is_day(&self, day: CalendarDay, date: NaiveDate) -> bool {
+        match day {
+            CalendarDay::Workday => self.is_workday(date),
+            CalendarDay::Holiday => self.is_holiday(date),
+            CalendarDay::DayOff => !self.is_workday(date),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_easter() {
+        assert_eq!(easter(2024), Some(date(2024, 3, 31)));
+        assert_eq!(easter(2025), Some(date(2025, 4, 20)));
+        assert_eq!(easter(2026), Some(date(2026, 4, 5)));
+    }
+
+    #[test]
+    fn test_country_holidays() {
+        let calendar = Calendar::new(CalendarConfig {
+            country: Some(HolidayCountry::Fi),
+            ..Default::default()
+        })
+        .unwrap();
+
+        // Midsummer eve
+        assert!(calendar.is_holiday(date(2026, 6, 19)));
+        // Good Friday
+        assert!(calendar.is_holiday(date(2026, 4, 3)));
+        assert!(!calendar.is_workday(date(2026, 4, 3)));
+        assert!(calendar.is_workday(date(2026, 4, 7)));
+
+        let calendar = Calendar::new(CalendarConfig {
+            country: Some(HolidayCountry::Us),
+            ..Default::default()
+        })
+        .unwrap();
+
+        // Thanksgiving and Memorial Day
+        assert!(calendar.is_holiday(date(2026, 11, 26)));
+        assert!(calendar.is_holiday(date(2026, 5, 25)));
+        assert!(!calendar.is_holiday(date(2026, 5, 18)));
+    }
+
+    #[test]
+    fn test_custom_holidays() {
+        let calendar = Calendar::new(CalendarConfig {
+            holidays: vec!["2026-10-16".to_string(), "08-15".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert!(calendar.is_holiday(date(2026, 10, 16)));
+        assert!(!calendar.is_holiday(date(2027, 10, 16)));
+        assert!(calendar.is_holiday(date(2027, 8, 15)));
+        assert!(calendar.is_day(CalendarDay::DayOff, date(2026, 10, 17)));
+        assert!(calendar.is_day(CalendarDay::Workday, date(2026, 10, 15)));
+
+        assert!(Calendar::new(CalendarConfig {
+            holidays: vec!["13-01".to_string()],
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,142 @@
+use std::time::{Duration, Instant};
+
+/// Number of consecutive failures after which the circuit breaker opens.
+const FAILURE_THRESHOLD: u32 = 5;
+
+/// Time to wait before probing a failing integration for the first time.
+const INITIAL_COOLDOWN: Duration = Duration::from_secs(10);
+
+/// Upper bound for the time between recovery probes.
+const MAX_COOLDOWN: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CircuitState {
+    /// Requests are passed through to the integration.
+    Closed,
+
+    /// Requests are rejected until the given instant.
+    Open { until: Instant },
+
+    /// The cooldown has elapsed and a single probe request has been let
+    /// through to check whether the integration has recovered.
+    HalfOpen,
+}
+
+/// Keeps track of consecutive failures of an integration, and stops requests
+/// from reaching the integration while it keeps failing.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    state: CircuitState,
+    consecutive_failures: u32,
+    cooldown: Duration,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker {
+            state: CircuitState::Closed,
+            consecutive_failures: 0,
+            cooldown: INITIAL_COOLDOWN,
+        }
+    }
+}
+
+impl CircuitBreaker {
+    /// Returns true if a request should be passed through to the integration.
+    ///
+    /// Once the cooldown of an open circuit has elapsed, exactly one request
+    /// is let through as a probe.
+    pub fn allow_request(&mut self, now: Instant) -> bool {
+        match self.state {
+            CircuitState::Closed => true,
+            CircuitState::Open { until } if now >= until => {
+                self.state = CircuitState::HalfOpen;
+                true
+            }
+            CircuitState::Open { .. } | CircuitState::HalfOpen => false,
+        }
+    }
+
+    /// Records a successful request. Returns true if this closed a previously
+    /// open circuit.
+    pub fn record_success(&mut self) -> bool {
+        let was_open = self.state != CircuitState::Closed;
+
+        self.state = CircuitState::Closed;
+        self.consecutive_failures = 0;
+        self.cooldown = INITIAL_COOLDOWN;
+
+        was_open
+    }
+
+    /// Records a failed request. Returns the cooldown until the next probe if
+    /// this (re)opened the circuit.
+    pub fn record_failure(&mut self, now: Instant) -> Option<Duration> {
+        self.consecutive_failures += 1;
+
+        match self.state {
+            CircuitState::Closed if self.consecutive_failures >= FAILURE_THRESHOLD => {
+                self.state = CircuitState::Open {
+                    until: now + self.cooldown,
+                };
+                Some(self.cooldown)
+            }
+            CircuitState::HalfOpen => {
+                // Probe failed, back off exponentially
+                self.cooldown = (self.cooldown * 2).min(MAX_COOLDOWN);
+                self.state = CircuitState::Open {
+                    until: now + self.cooldown,
+                };
+                Some(self.cooldown)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opens_after_threshold() {
+        let mut cb = CircuitBreaker::default();
+        let now = Instant::now();
+
+        for _ in 0..FAILURE_THRESHOLD - 1 {
+            assert!(cb.allow_request(now));
+            assert_eq!(cb.record_failure(now), None);
+        }
+
+        assert!(cb.allow_request(now));
+        assert_eq!(cb.record_failure(now), Some(INITIAL_COOLDOWN));
+        assert!(!cb.allow_request(now));
+    }
+
+    #[test]
+    fn test_probe_after_cooldown() {
+        let mut cb = CircuitBreaker::default();
+        let now = Instant::now();
+
+        for _ in 0..FAILURE_THRESHOLD {
+            cb.record_failure(now);
+        }
+
+        let later = now + INITIAL_COOLDOWN;
+        assert!(cb.allow_request(later));
+        assert_eq!(cb.state, CircuitState::HalfOpen);
+
+        // Only one probe is let through at a time
+        assert!(!cb.allow_request(later));
+
+        // Failed probe doubles the cooldown
+        assert_eq!(cb.record_failure(later), Some(INITIAL_COOLDOWN * 2));
+        assert!(!cb.allow_request(later + INITIAL_COOLDOWN));
+
+        // Successful probe closes the circuit
+        assert!(cb.allow_request(later + INITIAL_COOLDOWN * 2));
+        assert!(cb.record_success());
+        assert!(cb.allow_request(later + INITIAL_COOLDOWN * 2));
+        assert!(!cb.record_success());
+    }
+}
@@ -0,0 +1,111 @@
+//! Wall clock time at home, in the time zone configured with `[location]`
+//! rather than whatever the host happens to be set to
+
+use std::path::{Component, Path, PathBuf};
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use color_eyre::Result;
+use eyre::eyre;
+
+fn zoneinfo_dir() -> PathBuf {
+    std::env::var_os("TZDIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/usr/share/zoneinfo"))
+}
+
+/// Makes all local times follow given IANA time zone, including daylight
+/// saving time transitions. Must be called before any tasks are spawned.
+pub fn init_timezone(timezone: &str) -> Result<()> {
+    let path = Path::new(timezone);
+    let is_name = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+
+    if !is_name || !zoneinfo_dir().join(path).is_file() {
+        return Err(eyre!(
+            "Unknown time zone {}, expected a name such as Europe/Helsinki",
+            timezone
+        ));
+    }
+
+    // chrono resolves the TZ variable against the system time zone database
+    std::env::set_var("TZ", timezone);
+    info!("Using time zone {}", timezone);
+
+    Ok(())
+}
+
+/// Current wall clock time at home
+pub fn local_now() -> NaiveDateTime {
+    Local::now().naive_local()
+}
+
+/// Current date at home
+pub fn local_today() -> NaiveDate {
+    Local::now().date_naive()
+}
+
+/// Finds the next occurrence after `now` whose wall clock time is later than
+/// that of the `last` run, so that schedules within the hour repeated when
+/// clocks are turned back don't run twice
+pub fn next_cron_occurrence<Tz: TimeZone>(
+    cron: &croner::Cron,
+    now: &DateTime<Tz>,
+    last: Option<NaiveDateTime>,
+) -> Option<DateTime<Tz>> {
+    let mut from = now.clone();
+
+    loop {
+        let next = cron.find_next_occurrence(&from, false).ok()?;
+
+        match last {
+            Some(last) if next.naive_local() <= last => from = next,
+            _ => return Some(next),
+        }
+    }
+}
+
+/// Sleeps until given wall clock time. Sleeps in short steps, so that the wall
+/// clock is followed after the host has been suspended or its clock adjusted.
+pub async fn sleep_until_utc(at: DateTime<Utc>) {
+    loop {
+        let remaining = (at - Utc::now()).to_std().unwrap_or_default();
+        if remaining.is_zero() {
+            return;
+        }
+
+        tokio::time::sleep(remaining.min(std::time::Duration::from_secs(60))).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, TimeZone};
+
+    #[test]
+    fn test_next_occurrence_after_clocks_turned_back() {
+        let cron = croner::Cron::new("30 2 * * *").parse().unwrap();
+
+        // 02:40 local time after clocks were turned back from 03:00 to 02:00,
+        // having already run at 02:30 before that
+        let now = FixedOffset::east_opt(2 * 3600)
+            .unwrap()
+            .with_ymd_and_hms(2024, 10, 27, 2, 10, 0)
+            .unwrap();
+        let last = now.date_naive().and_hms_opt(2, 30, 0);
+
+        let next = next_cron_occurrence(&cron, &now, last).unwrap();
+        assert_eq!(
+            next.naive_local(),
+            now.date_naive()
+                .succ_opt()
+                .unwrap()
+                .and_hms_opt(2, 30, 0)
+                .unwrap()
+        );
+
+        let next = next_cron_occurrence(&cron, &now, None).unwrap();
+        assert_eq!(next.naive_local(), last.unwrap());
+    }
+}
@@ -0,0 +1,494 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::types::{
+    command::{
+        CommandQueueEntry, CommandStatus, CommandsConfig, SceneProgress, COMMANDS_INTEGRATION_ID,
+        UNRESPONSIVE_DEVICE_ID,
+    },
+    device::{
+        ControllableState, Device, DeviceData, DeviceId, DeviceKey, ManageKind, SensorDevice,
+    },
+    event::{CorrelationId, Message, TxEventChannel},
+    integration::IntegrationId,
+    scene::SceneId,
+};
+
+use super::{
+    devices::{cmp_device_states, Devices},
+    telemetry::record_duration,
+};
+
+#[derive(Clone)]
+struct PendingCommand {
+    state: ControllableState,
+    generation: u64,
+    sent_at: Instant,
+}
+
+#[derive(Clone, Default)]
+struct CommandCounters {
+    retries: u64,
+    coalesced: u64,
+
+    /// State of the latest command, to tell retries of a failed command
+    /// apart from new commands
+    last_state: Option<ControllableState>,
+}
+
+/// Commands sent on behalf of a scene activation
+#[derive(Clone)]
+struct SceneActivation {
+    scene_id: SceneId,
+    activated_at: Instant,
+    statuses: BTreeMap<DeviceKey, CommandStatus>,
+}
+
+/// Tracks whether devices have applied the state they were last sent
+#[derive(Clone)]
+pub struct Commands {
+    config: CommandsConfig,
+    event_tx: TxEventChannel,
+    statuses: BTreeMap<DeviceKey, CommandStatus>,
+    pending: HashMap<DeviceKey, PendingCommand>,
+    counters: HashMap<DeviceKey, CommandCounters>,
+
+    /// Incremented whenever a command is sent to cancel earlier timeouts
+    generations: HashMap<DeviceKey, u64>,
+
+    /// Scene activations that haven't fully applied yet, by the correlation
+    /// id of the commands they caused
+    activations: HashMap<CorrelationId, SceneActivation>,
+
+    /// Activation each device was last commanded by
+    device_activations: HashMap<DeviceKey, CorrelationId>,
+}
+
+impl Commands {
+    pub fn new(config: CommandsConfig, event_tx: TxEventChannel) -> Self {
+        Commands {
+            config,
+            event_tx,
+            statuses: Default::default(),
+            pending: Default::default(),
+            counters: Default::default(),
+            generations: Default::default(),
+            activations: Default::default(),
+            device_activations: Default::default(),
+        }
+    }
+
+    /// Starts tracking progress of a scene activation. Commands sent while
+    /// handling the current message chain count towards it.
+    pub fn scene_activated(&mut self, scene_id: &SceneId) {
+        // Forget activations that didn't command any devices
+        let timeout = self.timeout();
+        self.activations.retain(|_, activation| {
+            !activation.statuses.is_empty() || activation.activated_at.elapsed() < timeout
+        });
+
+        self.activations.insert(
+            CorrelationId::current_or_next(),
+            SceneActivation {
+                scene_id: scene_id.clone(),
+                activated_at: Instant::now(),
+                statuses: Default::default(),
+            },
+        );
+    }
+
+    /// Updates the status of a device in the activation it was last commanded
+    /// by, if any, and broadcasts progress of that activation
+    fn update_activation(&mut self, device_key: &DeviceKey, status: Option<CommandStatus>) {
+        let Some(correlation_id) = self.device_activations.get(device_key).copied() else {
+            return;
+        };
+
+        let Some(activation) = self.activations.get_mut(&correlation_id) else {
+            self.device_activations.remove(device_key);
+            return;
+        };
+
+        match status {
+            Some(status) => activation.statuses.insert(device_key.clone(), status),
+            None => activation.statuses.remove(device_key),
+        };
+
+        if status != Some(CommandStatus::Pending) {
+            self.device_activations.remove(device_key);
+        }
+
+        let statuses_of = |wanted: CommandStatus| {
+            activation
+                .statuses
+                .iter()
+                .filter(move |(_, status)| **status == wanted)
+                .map(|(device_key, _)| device_key.clone())
+        };
+
+        let progress = SceneProgress {
+            scene_id: activation.scene_id.clone(),
+            activation_id: correlation_id.to_string(),
+            total: activation.statuses.len(),
+            confirmed: statuses_of(CommandStatus::Confirmed).count(),
+            failed: statuses_of(CommandStatus::Failed).collect(),
+            pending: statuses_of(CommandStatus::Pending).collect(),
+        };
+
+        if progress.pending.is_empty() {
+            self.activations.remove(&correlation_id);
+        }
+
+        self.event_tx
+            .send(Message::WsBroadcastSceneProgress { progress });
+    }
+
+    pub fn get_statuses(&self) -> &BTreeMap<DeviceKey, CommandStatus> {
+        &self.statuses
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(self.config.timeout_secs.unwrap_or(10))
+    }
+
+    /// Latest command of every device that has been sent one, along with
+    /// pending state and retry counts
+    pub fn get_queue(&self) -> Vec<CommandQueueEntry> {
+        self.statuses
+            .iter()
+            .map(|(device_key, status)| {
+                let pending = self.pending.get(device_key);
+                let counters = self.counters.get(device_key).cloned().unwrap_or_default();
+                let sent_ago = pending.map(|pending| pending.sent_at.elapsed());
+
+                CommandQueueEntry {
+                    device_key: device_key.clone(),
+                    status: *status,
+                    pending_state: pending.map(|pending| pending.state.clone()),
+                    sent_ms_ago: sent_ago.map(|sent_ago| sent_ago.as_millis() as u64),
+                    timeout_in_ms: sent_ago
+                        .map(|sent_ago| self.timeout().saturating_sub(sent_ago).as_millis() as u64),
+                    retries: counters.retries,
+                    coalesced: counters.coalesced,
+                }
+            })
+            .collect()
+    }
+
+    /// Marks the command as pending until the device reports given state.
+    /// Commands to unmanaged devices are fire-and-forget and not tracked.
+    pub fn sent(&mut self, device: &Device) {
+        let DeviceData::Controllable(controllable) = &device.data else {
+            return;
+        };
+
+        if controllable.managed == ManageKind::Unmanaged {
+            return;
+        }
+
+        let device_key = device.get_device_key();
+
+        let unconfirmed = self.statuses.get(&device_key) != Some(&CommandStatus::Confirmed);
+        let counters = self.counters.entry(device_key.clone()).or_default();
+        match &counters.last_state {
+            Some(last_state) if unconfirmed && cmp_device_states(controllable, last_state) => {
+                counters.retries += 1
+            }
+            Some(_) if self.pending.contains_key(&device_key) => counters.coalesced += 1,
+            _ => {}
+        }
+        counters.last_state = Some(controllable.state.clone());
+
+        // A newer command supersedes the one of an earlier activation
+        let correlation_id = CorrelationId::current_or_next();
+        if self.device_activations.get(&device_key) != Some(&correlation_id) {
+            self.update_activation(&device_key, None);
+        }
+        if self.activations.contains_key(&correlation_id) {
+            self.device_activations
+                .insert(device_key.clone(), correlation_id);
+            self.update_activation(&device_key, Some(CommandStatus::Pending));
+        }
+
+        let generation = self.generations.entry(device_key.clone()).or_default();
+        *generation += 1;
+
+        self.statuses
+            .insert(device_key.clone(), CommandStatus::Pending);
+        self.pending.insert(
+            device_key.clone(),
+            PendingCommand {
+                state: controllable.state.clone(),
+                generation: *generation,
+                sent_at: Instant::now(),
+            },
+        );
+
+        let event_tx = self.event_tx.clone();
+        let generation = *generation;
+        let timeout = self.timeout();
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            event_tx.send(Message::CommandTimeout {
+                device_key,
+                generation,
+            });
+        });
+    }
+
+    /// Confirms the pending command of given device if it reports the
+    /// commanded state. Returns true if the status changed.
+    pub fn received(&mut self, device: &Device, devices: &Devices) -> bool {
+        let DeviceData::Controllable(controllable) = &device.data else {
+            return false;
+        };
+
+        let device_key = device.get_device_key();
+        let Some(pending) = self.pending.get(&device_key) else {
+            return false;
+        };
+
+        if !cmp_device_states(controllable, &pending.state) {
+            return false;
+        }
+
+        record_duration(
+            "homectl.integration.round_trip",
+            vec![("integration_id", device_key.integration_id.to_string())],
+            pending.sent_at.elapsed(),
+        );
+
+        self.pending.remove(&device_key);
+        self.statuses
+            .insert(device_key.clone(), CommandStatus::Confirmed);
+        self.update_activation(&device_key, Some(CommandStatus::Confirmed));
+        self.refresh(devices);
+
+        true
+    }
+
+    /// The device did not report the commanded state in time. Returns true if
+    /// the status changed.
+    pub fn handle_timeout(
+        &mut self,
+        device_key: &DeviceKey,
+        generation: u64,
+        devices: &Devices,
+    ) -> bool {
+        let timed_out = self
+            .pending
+            .get(device_key)
+            .map_or(false, |pending| pending.generation == generation);
+
+        if !timed_out {
+            return false;
+        }
+
+        warn!("{} did not apply commanded state in time", device_key);
+        self.pending.remove(device_key);
+        self.statuses
+            .insert(device_key.clone(), CommandStatus::Failed);
+        self.update_activation(device_key, Some(CommandStatus::Failed));
+        self.refresh(devices);
+
+        true
+    }
+
+    /// Reports new state of the virtual unresponsive devices sensor if it has
+    /// changed
+    fn refresh(&self, devices: &Devices) {
+        let unresponsive = self
+            .statuses
+            .values()
+            .any(|status| *status == CommandStatus::Failed);
+
+        let device = Device::new(
+            IntegrationId::from_str(COMMANDS_INTEGRATION_ID).unwrap(),
+            DeviceId::new(UNRESPONSIVE_DEVICE_ID),
+            "Unresponsive devices".to_string(),
+            DeviceData::Sensor(SensorDevice::Boolean {
+                value: unresponsive,
+            }),
+        );
+
+        if devices.get_device(&device.get_device_key()) != Some(&device) {
+            self.event_tx.send(Message::RecvDeviceState { device });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        color::Capabilities,
+        device::ControllableDevice,
+        event::{mk_event_channel, CORRELATION_ID},
+    };
+
+    fn mk_light(power: bool) -> Device {
+        Device::new(
+            IntegrationId::from("hue".to_string()),
+            DeviceId::new("1"),
+            "Living room".to_string(),
+            DeviceData::Controllable(ControllableDevice::new(
+                None,
+                power,
+                None,
+                None,
+                None,
+                Capabilities::default(),
+                ManageKind::Full,
+            )),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_command_queue_counters() {
+        let (event_tx, _event_rx) = mk_event_channel();
+        let mut commands = Commands::new(Default::default(), event_tx);
+
+        commands.sent(&mk_light(true));
+        commands.sent(&mk_light(true));
+        commands.sent(&mk_light(false));
+
+        let queue = commands.get_queue();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].status, CommandStatus::Pending);
+        assert_eq!(queue[0].retries, 1);
+        assert_eq!(queue[0].coalesced, 1);
+        assert_eq!(
+            queue[0].pending_state.as_ref().map(|state| state.power),
+            Some(false)
+        );
+        assert!(queue[0].timeout_in_ms.unwrap() <= 10000);
+    }
+
+    #[tokio::test]
+    async fn test_scene_progress() {
+        let (event_tx, mut event_rx) = mk_event_channel();
+        let mut commands = Commands::new(Default::default(), event_tx.clone());
+        let devices = Devices::new(event_tx, Default::default(), Default::default());
+        let scene_id = SceneId::new("evening".to_string());
+
+        let mut light = mk_light(true);
+        let mut next_progress = || loop {
+            if let (_, Message::WsBroadcastSceneProgress { progress }) =
+                event_rx.try_recv().unwrap()
+            {
+                return progress;
+            }
+        };
+
+        CORRELATION_ID
+            .scope(CorrelationId::next(), async {
+                commands.scene_activated(&scene_id);
+                commands.sent(&light);
+            })
+            .await;
+
+        let progress = next_progress();
+        assert_eq!(progress.total, 1);
+        assert_eq!(progress.pending, vec![light.get_device_key()]);
+
+        commands.received(&light, &devices);
+        let progress = next_progress();
+        assert_eq!(progress.confirmed, 1);
+        assert!(progress.pending.is_empty());
+        assert!(commands.activations.is_empty());
+
+        // Commands outside of scene activations aren't tracked
+        light.id = DeviceId::new("2");
+        commands.sent(&light);
+        assert!(commands.device_activations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_matching_report_confirms_command() {
+        let (event_tx, _event_rx) = mk_event_channel();
+        let mut commands = Commands::new(Default::default(), event_tx.clone());
+        let devices = Devices::new(event_tx, Default::default(), Default::default());
+        let light = mk_light(true);
+
+        commands.sent(&light);
+        assert!(commands.received(&light, &devices));
+
+        assert_eq!(
+            commands.get_statuses().get(&light.get_device_key()),
+            Some(&CommandStatus::Confirmed)
+        );
+        assert!(commands.pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mismatching_report_leaves_command_pending() {
+        let (event_tx, _event_rx) = mk_event_channel();
+        let mut commands = Commands::new(Default::default(), event_tx.clone());
+        let devices = Devices::new(event_tx, Default::default(), Default::default());
+
+        commands.sent(&mk_light(true));
+        assert!(!commands.received(&mk_light(false), &devices));
+
+        assert_eq!(
+            commands
+                .get_statuses()
+                .get(&mk_light(true).get_device_key()),
+            Some(&CommandStatus::Pending)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timeout_fails_command() {
+        let (event_tx, mut event_rx) = mk_event_channel();
+        let mut commands = Commands::new(Default::default(), event_tx.clone());
+        let devices = Devices::new(event_tx, Default::default(), Default::default());
+        let device_key = mk_light(true).get_device_key();
+
+        commands.sent(&mk_light(true));
+        let generation = commands.pending[&device_key].generation;
+        assert!(commands.handle_timeout(&device_key, generation, &devices));
+
+        assert_eq!(
+            commands.get_statuses().get(&device_key),
+            Some(&CommandStatus::Failed)
+        );
+
+        // The virtual unresponsive devices sensor turns on
+        let (_, msg) = event_rx.try_recv().unwrap();
+        let Message::RecvDeviceState { device } = msg else {
+            panic!("Expected unresponsive devices sensor state, got {:?}", msg);
+        };
+        assert_eq!(device.id, DeviceId::new(UNRESPONSIVE_DEVICE_ID));
+        assert_eq!(
+            device.data,
+            DeviceData::Sensor(SensorDevice::Boolean { value: true })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_newer_command_supersedes_pending_one() {
+        let (event_tx, _event_rx) = mk_event_channel();
+        let mut commands = Commands::new(Default::default(), event_tx.clone());
+        let devices = Devices::new(event_tx, Default::default(), Default::default());
+        let device_key = mk_light(true).get_device_key();
+
+        commands.sent(&mk_light(true));
+        let old_generation = commands.pending[&device_key].generation;
+        commands.sent(&mk_light(false));
+
+        // Neither the timeout nor the state of the older command apply anymore
+        assert!(!commands.handle_timeout(&device_key, old_generation, &devices));
+        assert!(!commands.received(&mk_light(true), &devices));
+        assert_eq!(
+            commands.get_statuses().get(&device_key),
+            Some(&CommandStatus::Pending)
+        );
+
+        assert!(commands.received(&mk_light(false), &devices));
+        assert_eq!(
+            commands.get_statuses().get(&device_key),
+            Some(&CommandStatus::Confirmed)
+        );
+    }
+}
@@ -1,27 +1,97 @@
+use crate::core::routine_templates::instantiate_routines;
+use crate::core::schema::JsonSchema;
+use crate::db::actions::db_get_integrations;
 use crate::types::{
+    api::ApiConfig,
+    appliance::AppliancesConfig,
+    calendar::CalendarConfig,
+    command::CommandsConfig,
+    conflict::ConflictsConfig,
+    cover::CoversConfig,
+    device_config::DevicesConfig,
+    device_expiry::DeviceExpiryConfig,
+    event_bus::EventBusConfig,
+    frontend::FrontendConfig,
     group::GroupsConfig,
-    integration::{IntegrationId, IntegrationsConfig},
-    rule::RoutinesConfig,
+    ha_discovery::HaDiscoveryConfig,
+    heating::HeatingConfig,
+    integration::{IntegrationConfig, IntegrationId, IntegrationsConfig},
+    mode::ModesConfig,
+    motion_lighting::MotionLightingsConfig,
+    notification::NotificationsConfig,
+    open_alert::OpenAlertsConfig,
+    overrides::OverridesConfig,
+    person::PersonsConfig,
+    polling::PollingConfig,
+    presence::PresenceConfig,
+    quiet_hours::QuietHoursConfig,
+    rate_alert::RateAlertsConfig,
+    reconcile::ReconcileConfig,
+    rule::{RoutineConfigs, RoutineTemplatesConfig, RoutinesConfig},
+    safety::SafetyConfig,
     scene::ScenesConfig,
+    sentry::SentryConfig,
+    standby::StandbyConfig,
+    sun::LocationConfig,
+    utility_meter::UtilityMetersConfig,
+    websockets::WebSocketsConfig,
 };
 use color_eyre::Result;
-use eyre::Context;
+use eyre::{eyre, Context};
 use serde::Deserialize;
 use std::collections::HashMap;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct Config {
     pub integrations: Option<IntegrationsConfig>,
     pub scenes: Option<ScenesConfig>,
     pub groups: Option<GroupsConfig>,
-    pub routines: Option<RoutinesConfig>,
+    pub routines: Option<RoutineConfigs>,
+    pub routine_templates: Option<RoutineTemplatesConfig>,
+    pub overrides: Option<OverridesConfig>,
+    pub devices: Option<DevicesConfig>,
+    pub device_expiry: Option<DeviceExpiryConfig>,
+    pub standby: Option<StandbyConfig>,
+    pub persons: Option<PersonsConfig>,
+    pub presence: Option<PresenceConfig>,
+    pub notifications: Option<NotificationsConfig>,
+    pub quiet_hours: Option<QuietHoursConfig>,
+    pub modes: Option<ModesConfig>,
+    pub safety: Option<SafetyConfig>,
+    pub appliances: Option<AppliancesConfig>,
+    pub utility_meters: Option<UtilityMetersConfig>,
+    pub heating: Option<HeatingConfig>,
+    pub location: Option<LocationConfig>,
+    pub covers: Option<CoversConfig>,
+    pub motion_lighting: Option<MotionLightingsConfig>,
+    pub open_alerts: Option<OpenAlertsConfig>,
+    pub commands: Option<CommandsConfig>,
+    pub reconcile: Option<ReconcileConfig>,
+    pub polling: Option<PollingConfig>,
+    pub conflicts: Option<ConflictsConfig>,
+    pub websockets: Option<WebSocketsConfig>,
+    pub api: Option<ApiConfig>,
+    pub frontend: Option<FrontendConfig>,
+    pub sentry: Option<SentryConfig>,
+    pub calendar: Option<CalendarConfig>,
+    pub rate_alerts: Option<RateAlertsConfig>,
+    pub event_bus: Option<EventBusConfig>,
+    pub ha_discovery: Option<HaDiscoveryConfig>,
 }
 
-type OpaqueIntegrationsConfigs = HashMap<IntegrationId, config::Value>;
+impl Config {
+    /// Routines with instances of routine templates expanded
+    pub fn get_routines(&self) -> Result<RoutinesConfig> {
+        instantiate_routines(
+            self.routines.clone().unwrap_or_default(),
+            &self.routine_templates.clone().unwrap_or_default(),
+        )
+    }
+}
 
-pub fn read_config() -> Result<(Config, OpaqueIntegrationsConfigs)> {
-    let builder = config::Config::builder();
+pub type OpaqueIntegrationsConfigs = HashMap<IntegrationId, config::Value>;
 
+pub fn read_config() -> Result<(Config, OpaqueIntegrationsConfigs)> {
     let root = std::env::current_dir().unwrap();
     let sample_path = root.join("Settings.toml.example");
 
@@ -33,9 +103,15 @@ pub fn read_config() -> Result<(Config, OpaqueIntegrationsConfigs)> {
         std::fs::copy(sample_path, path).unwrap();
     }
 
-    let builder = builder.add_source(config::File::with_name("Settings"));
+    read_config_file(config::File::with_name("Settings"))
+}
 
-    let settings = builder.build()?;
+/// Reads and deserializes given config file
+pub fn read_config_file<S>(source: S) -> Result<(Config, OpaqueIntegrationsConfigs)>
+where
+    S: config::Source + Send + Sync + 'static,
+{
+    let settings = config::Config::builder().add_source(source).build()?;
 
     let config: Config = serde_path_to_error::deserialize(settings.clone()).wrap_err(
         "Failed to deserialize config, compare your config file to Settings.toml.example!",
@@ -47,3 +123,49 @@ pub fn read_config() -> Result<(Config, OpaqueIntegrationsConfigs)> {
 
     Ok((config, integrations_config))
 }
+
+/// Parses the config of an integration that was added at runtime
+pub fn parse_integration_config(
+    json: &serde_json::Value,
+) -> Result<(IntegrationConfig, config::Value)> {
+    let opaque_integration_config: config::Value = serde_json::from_value(json.clone())?;
+    let integration_config: IntegrationConfig = opaque_integration_config
+        .clone()
+        .try_deserialize()
+        .wrap_err("Failed to deserialize integration config")?;
+
+    Ok((integration_config, opaque_integration_config))
+}
+
+/// Re-reads the config section of given integration, either from the config
+/// file or from the DB for integrations that were added at runtime
+pub async fn read_integration_config(
+    integration_id: &IntegrationId,
+) -> Result<(IntegrationConfig, config::Value)> {
+    let (mut config, mut opaque_integrations_configs) = read_config()?;
+
+    let integration_config = config
+        .integrations
+        .as_mut()
+        .and_then(|integrations| integrations.remove(integration_id));
+    let opaque_integration_config = opaque_integrations_configs.remove(integration_id);
+
+    if let (Some(integration_config), Some(opaque_integration_config)) =
+        (integration_config, opaque_integration_config)
+    {
+        return Ok((integration_config, opaque_integration_config));
+    }
+
+    let db_integrations = db_get_integrations().await?;
+    let (_, json) = db_integrations
+        .iter()
+        .find(|(id, _)| id == integration_id)
+        .ok_or_else(|| {
+            eyre!(
+                "Expected to find config for integration with id {}",
+                integration_id
+            )
+        })?;
+
+    parse_integration_config(json)
+}
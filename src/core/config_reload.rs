@@ -0,0 +1,135 @@
+//! Applies changes to the config file without restarting. Groups, scenes,
+//! routines and integrations are reloaded, other sections still require a
+//! restart.
+
+use std::{collections::HashSet, path::Path, time::Duration};
+
+use color_eyre::Result;
+use notify::{RecursiveMode, Watcher};
+use tokio::sync::mpsc::unbounded_channel;
+
+use crate::db::actions::db_get_integrations;
+use crate::types::event::{Message, TxEventChannel};
+
+use super::{config::read_config, rules::validate_routines, state::AppState};
+
+/// Editors tend to write a file in several steps, wait for them to settle
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+fn is_config_file(path: &Path) -> bool {
+    path.file_stem().map_or(false, |stem| stem == "Settings")
+}
+
+/// Watches the config file, sending [Message::ConfigReloaded] whenever it has
+/// changed
+pub async fn watch_config(event_tx: TxEventChannel) {
+    if let Err(e) = run_config_watcher(event_tx).await {
+        error!("Error while watching config file: {:?}", e);
+    }
+}
+
+async fn run_config_watcher(event_tx: TxEventChannel) -> Result<()> {
+    let (changed_tx, mut changed_rx) = unbounded_channel();
+
+    // The directory is watched instead of the file, as editors often replace
+    // the file rather than writing to it
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else { return };
+
+        if !event.kind.is_access() && event.paths.iter().any(|path| is_config_file(path)) {
+            changed_tx.send(()).ok();
+        }
+    })?;
+    watcher.watch(&std::env::current_dir()?, RecursiveMode::NonRecursive)?;
+
+    while changed_rx.recv().await.is_some() {
+        while let Ok(Some(())) = tokio::time::timeout(DEBOUNCE, changed_rx.recv()).await {}
+
+        info!("Config file has changed, reloading");
+        event_tx.send(Message::ConfigReloaded);
+    }
+
+    Ok(())
+}
+
+/// Re-reads the config file and applies changes to groups, scenes, routines
+/// and integrations. Nothing is changed if the config fails to parse, or if
+/// its routine triggers or integration dependencies are invalid.
+pub async fn reload_config(state: &mut AppState) -> Result<()> {
+    let (config, opaque_integrations_configs) = read_config()?;
+    let routines = config.get_routines()?;
+    validate_routines(&routines, config.location.as_ref())?;
+
+    let integrations_config = config.integrations.unwrap_or_default();
+    let runtime_ids: HashSet<_> = db_get_integrations()
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect();
+
+    // Integrations of a standby instance aren't running yet
+    if !state.standby {
+        state
+            .integrations
+            .reload_start_order(&integrations_config, &runtime_ids)?;
+    }
+
+    state
+        .groups
+        .reload_config(config.groups.unwrap_or_default(), &state.devices);
+
+    state
+        .scenes
+        .reload_config(
+            config.scenes.unwrap_or_default(),
+            &state.devices,
+            &state.groups,
+            state.expr.get_context(),
+        )
+        .await;
+
+    state
+        .expr
+        .invalidate(state.devices.get_state(), &state.groups, &state.scenes);
+
+    state.rules.reload_config(
+        routines,
+        config.location.as_ref(),
+        &state.devices,
+        &state.groups,
+        &state.expr,
+    )?;
+
+    if state.standby {
+        info!("Not reloading integrations in standby mode, restart to apply changes");
+    } else {
+        state
+            .integrations
+            .reload_config(
+                &integrations_config,
+                &opaque_integrations_configs,
+                &runtime_ids,
+            )
+            .await?;
+    }
+
+    state.send_state_ws(None).await;
+
+    info!("Config reloaded");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_config_file() {
+        assert!(is_config_file(Path::new("/srv/homectl/Settings.toml")));
+        assert!(is_config_file(Path::new("Settings.yaml")));
+        assert!(!is_config_file(Path::new("Settings.toml.example")));
+        assert!(!is_config_file(Path::new(".Settings.toml.swp")));
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::types::{
+    conflict::{Conflict, ConflictsConfig, WriteSource},
+    device::{ControllableDevice, ControllableState, Device, DeviceData, DeviceKey},
+    event::CorrelationId,
+};
+
+use super::devices::cmp_device_states;
+
+/// How long to remember which automation started a message chain
+const SOURCE_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
+struct RecentWrite {
+    source: WriteSource,
+    state: ControllableState,
+    at: Instant,
+}
+
+/// Detects automations that fight over the same device, which would otherwise
+/// only show up as flickering lights
+#[derive(Clone)]
+pub struct Conflicts {
+    config: ConflictsConfig,
+    sources: HashMap<CorrelationId, (WriteSource, Instant)>,
+    writes: HashMap<DeviceKey, RecentWrite>,
+    conflicts: VecDeque<Conflict>,
+}
+
+impl Conflicts {
+    pub fn new(config: ConflictsConfig) -> Self {
+        Conflicts {
+            config,
+            sources: Default::default(),
+            writes: Default::default(),
+            conflicts: Default::default(),
+        }
+    }
+
+    /// Most recent conflicts, oldest first
+    pub fn get_conflicts(&self) -> &VecDeque<Conflict> {
+        &self.conflicts
+    }
+
+    /// Attributes device writes caused by the message currently being handled
+    /// to `source`. The first attribution wins, so that a scene activated by a
+    /// routine is attributed to the routine.
+    pub fn set_source(&mut self, source: WriteSource) {
+        let now = Instant::now();
+        self.sources
+            .retain(|_, (_, at)| now.duration_since(*at) < SOURCE_TTL);
+        self.sources
+            .entry(CorrelationId::current_or_next())
+            .or_insert((source, now));
+    }
+
+    /// Records a state write to `device`, logging a warning if it conflicts
+    /// with a recent write from another automation. Writes that were not
+    /// caused by an automation are ignored.
+    pub fn written(&mut self, device: &Device) {
+        let DeviceData::Controllable(controllable) = &device.data else {
+            return;
+        };
+
+        let Some((source, _)) = self.sources.get(&CorrelationId::current_or_next()) else {
+            return;
+        };
+
+        let device_key = device.get_device_key();
+        let source = source.clone();
+
+        if let Some(conflict) = self.check(&device_key, source, controllable, Instant::now()) {
+            warn!(
+                "Conflicting writes to {}: {} was overridden by {}",
+                device.name, conflict.first, conflict.second
+            );
+
+            let max_conflicts = self.config.max_conflicts.unwrap_or(50);
+            self.conflicts.push_back(conflict);
+            while self.conflicts.len() > max_conflicts {
+                self.conflicts.pop_front();
+            }
+        }
+    }
+
+    fn check(
+        &mut self,
+        device_key: &DeviceKey,
+        source: WriteSource,
+        device: &ControllableDevice,
+        now: Instant,
+    ) -> Option<Conflict> {
+        let window = Duration::from_secs(self.config.window_secs.unwrap_or(5));
+
+        let previous = self.writes.insert(
+            device_key.clone(),
+            RecentWrite {
+                source: source.clone(),
+                state: device.state.clone(),
+                at: now,
+            },
+        )?;
+
+        let conflicting = previous.source != source
+            && now.duration_since(previous.at) < window
+            && !cmp_device_states(device, &previous.state);
+
+        conflicting.then(|| Conflict {
+            device_key: device_key.clone(),
+            first: previous.source,
+            second: source,
+            detected_at: chrono::Utc::now(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::types::{
+        device::{DeviceId, ManageKind},
+        integration::IntegrationId,
+        rule::RoutineId,
+        scene::SceneId,
+    };
+
+    use super::*;
+
+    fn mk_light(power: bool) -> ControllableDevice {
+        ControllableDevice::new(
+            None,
+            power,
+            Some(1.0),
+            None,
+            None,
+            Default::default(),
+            ManageKind::Full,
+        )
+    }
+
+    #[test]
+    fn test_conflicting_writes_within_window() {
+        let mut conflicts = Conflicts::new(ConflictsConfig {
+            window_secs: Some(5),
+            max_conflicts: None,
+        });
+        let device_key = DeviceKey::new(
+            IntegrationId::from_str("test_integration").unwrap(),
+            DeviceId::new("light"),
+        );
+        let routine = WriteSource::Routine(RoutineId("motion".to_string()));
+        let scene = WriteSource::Scene(SceneId::new("movie".to_string()));
+        let now = Instant::now();
+
+        assert!(conflicts
+            .check(&device_key, scene.clone(), &mk_light(false), now)
+            .is_none());
+
+        // Same source rewriting its own state is not a conflict
+        assert!(conflicts
+            .check(&device_key, scene.clone(), &mk_light(true), now)
+            .is_none());
+
+        // Another source agreeing on the state is not a conflict
+        assert!(conflicts
+            .check(&device_key, routine.clone(), &mk_light(true), now)
+            .is_none());
+
+        let conflict = conflicts
+            .check(&device_key, scene.clone(), &mk_light(false), now)
+            .unwrap();
+        assert_eq!(conflict.first, routine);
+        assert_eq!(conflict.second, scene);
+
+        // Writes further apart than the window are not conflicting
+        assert!(conflicts
+            .check(
+                &device_key,
+                routine,
+                &mk_light(true),
+                now + Duration::from_secs(10)
+            )
+            .is_none());
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use crate::types::{
+    cover::{CoverConfig, CoverId, CoversConfig},
+    integration::IntegrationActionPayload,
+    sun::SunPosition,
+};
+
+use super::integrations::Integrations;
+
+/// Returns the position a cover should be in, shading its window while the
+/// sun shines on it
+fn target_position(cover: &CoverConfig, sun: &SunPosition) -> u8 {
+    // Angle between the sun and the direction the window is facing
+    let offset = (sun.azimuth - cover.orientation + 180.0).rem_euclid(360.0) - 180.0;
+
+    if sun.elevation > cover.min_elevation && offset.abs() < cover.fov {
+        cover.shade_position
+    } else {
+        cover.open_position
+    }
+}
+
+fn render_payload(value: &serde_json::Value, position: u8) -> serde_json::Value {
+    match value {
+        serde_json::Value::String(s) => {
+            serde_json::Value::String(s.replace("{position}", &position.to_string()))
+        }
+        serde_json::Value::Array(values) => values
+            .iter()
+            .map(|value| render_payload(value, position))
+            .collect(),
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.clone(), render_payload(value, position)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+/// Positions covers based on where the sun is
+#[derive(Clone, Default)]
+pub struct Covers {
+    config: CoversConfig,
+    last_sent: HashMap<CoverId, u8>,
+}
+
+impl Covers {
+    pub fn new(config: CoversConfig) -> Self {
+        Covers {
+            config,
+            last_sent: Default::default(),
+        }
+    }
+
+    /// Moves covers whose target position has changed since last time
+    pub async fn refresh(&mut self, sun: &SunPosition, integrations: &Integrations) {
+        for (cover_id, cover) in &self.config {
+            let position = target_position(cover, sun);
+
+            if self.last_sent.get(cover_id) == Some(&position) {
+                continue;
+            }
+
+            info!("Moving cover {} to {}%", cover_id, position);
+
+            let payload = match render_payload(&cover.payload, position) {
+                serde_json::Value::String(payload) => payload,
+                payload => payload.to_string(),
+            };
+
+            let result = integrations
+                .run_integration_action(
+                    &cover.integration_id,
+                    &IntegrationActionPayload::from(payload),
+                )
+                .await;
+
+            match result {
+                Ok(()) => {
+                    self.last_sent.insert(cover_id.clone(), position);
+                }
+                Err(e) => error!("Failed to move cover {}: {:?}", cover_id, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::integration::IntegrationId;
+
+    use super::*;
+
+    #[test]
+    fn test_target_position() {
+        let cover = CoverConfig {
+            integration_id: IntegrationId::from("mqtt".to_string()),
+            payload: serde_json::Value::Null,
+            // Facing north-northwest, the sun shines in from west to north-east
+            orientation: 340.0,
+            fov: 90.0,
+            min_elevation: 5.0,
+            shade_position: 20,
+            open_position: 100,
+        };
+
+        let sun = |azimuth, elevation| SunPosition { azimuth, elevation };
+
+        assert_eq!(target_position(&cover, &sun(20.0, 30.0)), 20);
+        assert_eq!(target_position(&cover, &sun(260.0, 30.0)), 20);
+        assert_eq!(target_position(&cover, &sun(180.0, 50.0)), 100);
+        assert_eq!(target_position(&cover, &sun(300.0, 2.0)), 100);
+    }
+}
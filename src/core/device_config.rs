@@ -0,0 +1,434 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, RwLock},
+};
+
+use crate::types::{
+    color::Capabilities,
+    device::{ControllableState, Device, DeviceData, DeviceKey, SensorDevice},
+    device_config::{DeviceConfig, DeviceMetadata, DevicesConfig, Unit, UnitConfig},
+    integration::IntegrationId,
+};
+use ordered_float::OrderedFloat;
+
+/// Converts brightness as seen by homectl to brightness sent to the device
+fn apply_gamma(brightness: f32, gamma: f32) -> f32 {
+    brightness.clamp(0.0, 1.0).powf(gamma)
+}
+
+/// Converts brightness reported by the device to brightness as seen by
+/// homectl
+fn apply_inverse_gamma(brightness: f32, gamma: f32) -> f32 {
+    brightness.clamp(0.0, 1.0).powf(1.0 / gamma)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Quantity {
+    Temperature,
+    Power,
+    Energy,
+    Illuminance,
+}
+
+/// Quantity measured by the unit, and the scale and offset converting values
+/// to the base unit of that quantity
+fn unit_definition(unit: Unit) -> (Quantity, f64, f64) {
+    match unit {
+        Unit::Celsius => (Quantity::Temperature, 1.0, 0.0),
+        Unit::Fahrenheit => (Quantity::Temperature, 5.0 / 9.0, -32.0 * 5.0 / 9.0),
+        Unit::Kelvin => (Quantity::Temperature, 1.0, -273.15),
+        Unit::W => (Quantity::Power, 1.0, 0.0),
+        Unit::Kw => (Quantity::Power, 1000.0, 0.0),
+        Unit::Wh => (Quantity::Energy, 1.0, 0.0),
+        Unit::Kwh => (Quantity::Energy, 1000.0, 0.0),
+        Unit::Lux => (Quantity::Illuminance, 1.0, 0.0),
+    }
+}
+
+fn unit_symbol(unit: Unit) -> &'static str {
+    match unit {
+        Unit::Celsius => "°C",
+        Unit::Fahrenheit => "°F",
+        Unit::Kelvin => "K",
+        Unit::W => "W",
+        Unit::Kw => "kW",
+        Unit::Wh => "Wh",
+        Unit::Kwh => "kWh",
+        Unit::Lux => "lx",
+    }
+}
+
+/// Converts a value between units of the same quantity
+fn convert_unit(value: f64, from: Unit, to: Unit) -> Option<f64> {
+    let (from_quantity, from_scale, from_offset) = unit_definition(from);
+    let (to_quantity, to_scale, to_offset) = unit_definition(to);
+
+    if from_quantity != to_quantity {
+        return None;
+    }
+
+    Some((value * from_scale + from_offset - to_offset) / to_scale)
+}
+
+/// Applies unit conversion, scale and offset to a reading
+fn apply_unit(value: f64, config: &UnitConfig) -> Option<f64> {
+    let value = match (config.from, config.to) {
+        (Some(from), Some(to)) => convert_unit(value, from, to)?,
+        _ => value,
+    };
+
+    Some(value * config.scale.unwrap_or(1.0) + config.offset.unwrap_or(0.0))
+}
+
+#[derive(Default)]
+struct DeviceConfigsInner {
+    devices: DevicesConfig,
+    integration_defaults: HashMap<IntegrationId, DeviceConfig>,
+    metadata: BTreeMap<DeviceKey, DeviceMetadata>,
+}
+
+/// Keeps track of per-device configs, and transforms device state between
+/// homectl and integrations accordingly.
+///
+/// Cloning returns a handle to the same configs, so that integration defaults
+/// registered while loading integrations are visible everywhere.
+#[derive(Clone, Default)]
+pub struct DeviceConfigs {
+    inner: Arc<RwLock<DeviceConfigsInner>>,
+}
+
+impl DeviceConfigs {
+    pub fn new(devices: DevicesConfig) -> Self {
+        DeviceConfigs {
+            inner: Arc::new(RwLock::new(DeviceConfigsInner {
+                devices,
+                integration_defaults: Default::default(),
+                metadata: Default::default(),
+            })),
+        }
+    }
+
+    pub fn set_integration_defaults(&self, integration_id: &IntegrationId, defaults: DeviceConfig) {
+        let mut inner = self.inner.write().unwrap();
+        inner
+            .integration_defaults
+            .insert(integration_id.clone(), defaults);
+    }
+
+    pub fn get_device_config(&self, device: &Device) -> DeviceConfig {
+        let inner = self.inner.read().unwrap();
+
+        let device_config = inner
+            .devices
+            .get(&device.integration_id)
+            .and_then(|devices| devices.get(&device.name))
+            .cloned()
+            .unwrap_or_default();
+
+        let mut device_config = match inner.integration_defaults.get(&device.integration_id) {
+            Some(defaults) => device_config.or(defaults),
+            None => device_config,
+        };
+
+        // Metadata set through the API takes precedence over the config file
+        if let Some(metadata) = inner.metadata.get(&device.get_device_key()) {
+            device_config.area = metadata.area.clone().or(device_config.area);
+            device_config.tags = metadata.tags.clone().or(device_config.tags);
+        }
+
+        device_config
+    }
+
+    pub fn set_device_metadata(&self, device_key: &DeviceKey, metadata: DeviceMetadata) {
+        let mut inner = self.inner.write().unwrap();
+        inner.metadata.insert(device_key.clone(), metadata);
+    }
+
+    pub fn remove_device_metadata(&self, device_key: &DeviceKey) {
+        let mut inner = self.inner.write().unwrap();
+        inner.metadata.remove(device_key);
+    }
+
+    pub fn is_disabled(&self, device_key: &DeviceKey) -> bool {
+        let inner = self.inner.read().unwrap();
+        inner
+            .metadata
+            .get(device_key)
+            .and_then(|metadata| metadata.disabled)
+            .unwrap_or(false)
+    }
+
+    pub fn get_device_metadata(&self) -> BTreeMap<DeviceKey, DeviceMetadata> {
+        self.inner.read().unwrap().metadata.clone()
+    }
+
+    /// Replaces the integration reported name of the device with its display
+    /// name, if one is set. Only meant for presenting devices, as configs
+    /// refer to devices by their integration reported name.
+    pub fn apply_display_name(&self, device: &Device) -> Device {
+        let inner = self.inner.read().unwrap();
+        let name = inner
+            .metadata
+            .get(&device.get_device_key())
+            .and_then(|metadata| metadata.name.clone());
+
+        match name {
+            Some(name) => Device {
+                name,
+                ..device.clone()
+            },
+            None => device.clone(),
+        }
+    }
+
+    /// Clamps brightness of the expected device state within the configured
+    /// limits
+    pub fn clamp_expected_state(&self, device: &Device, state: &mut ControllableState) {
+        let config = self.get_device_config(device);
+
+        if let Some(brightness) = &mut state.brightness {
+            brightness.0 = clamp_brightness(brightness.0, &config);
+        }
+    }
+
+    /// Sets the configured default transition time if the device state does
+    /// not specify one
+    pub fn apply_default_transition(&self, device: &Device) -> Device {
+        let Some(state) = device.get_controllable_state() else {
+            return device.clone();
+        };
+
+        if state.transition_ms.is_some() {
+            return device.clone();
+        }
+
+        let config = self.get_device_config(device);
+        let Some(default_transition_ms) = config.default_transition_ms else {
+            return device.clone();
+        };
+
+        let mut state = state.clone();
+        state.transition_ms = Some(default_transition_ms);
+
+        device.set_controllable_state(state)
+    }
+
+    /// Transforms device state computed by homectl before it's sent to the
+    /// integration
+    pub fn apply_outgoing(&self, device: &Device) -> Device {
+        let config = self.get_device_config(device);
+        map_brightness(device, |b| match config.gamma {
+            Some(gamma) => apply_gamma(b, gamma),
+            None => b,
+        })
+    }
+
+    /// Transforms device state reported by the integration before homectl
+    /// processes it, inverse of [DeviceConfigs::apply_outgoing]
+    pub fn apply_incoming(&self, device: &Device) -> Device {
+        let config = self.get_device_config(device);
+        let device = map_brightness(device, |b| match config.gamma {
+            Some(gamma) => apply_inverse_gamma(b, gamma),
+            None => b,
+        });
+
+        let device = match &config.capabilities {
+            Some(capabilities) => apply_default_capabilities(device, capabilities),
+            None => device,
+        };
+
+        match config.unit {
+            Some(unit) => convert_sensor_unit(&device, &unit),
+            None => device,
+        }
+    }
+}
+
+fn clamp_brightness(brightness: f32, config: &DeviceConfig) -> f32 {
+    let brightness = match config.max_brightness {
+        Some(max) => brightness.min(max),
+        None => brightness,
+    };
+
+    match config.min_brightness {
+        Some(min) => brightness.max(min),
+        None => brightness,
+    }
+}
+
+fn convert_sensor_unit(device: &Device, config: &UnitConfig) -> Device {
+    let Some(SensorDevice::Number { value, .. }) = device.get_sensor_state() else {
+        return device.clone();
+    };
+
+    let Some(converted) = apply_unit(value.0, config) else {
+        warn!(
+            "Cannot convert readings of {} from {:?} to {:?}",
+            device.get_device_key(),
+            config.from,
+            config.to
+        );
+        return device.clone();
+    };
+
+    let mut device = device.clone();
+    device.data = DeviceData::Sensor(SensorDevice::Number {
+        value: OrderedFloat(converted),
+        unit: config
+            .to
+            .or(config.from)
+            .map(|unit| unit_symbol(unit).to_string()),
+        raw: Some(*value),
+    });
+
+    device
+}
+
+/// Fills in capabilities of a controllable device that doesn't report any
+fn apply_default_capabilities(mut device: Device, capabilities: &Capabilities) -> Device {
+    if let DeviceData::Controllable(controllable) = &mut device.data {
+        if controllable.capabilities.is_empty() {
+            controllable.capabilities = capabilities.clone();
+        }
+    }
+
+    device
+}
+
+fn map_brightness(device: &Device, f: impl Fn(f32) -> f32) -> Device {
+    let Some(state) = device.get_controllable_state() else {
+        return device.clone();
+    };
+
+    let mut state = state.clone();
+    state.brightness = state.brightness.map(|b| OrderedFloat(f(b.0)));
+
+    device.set_controllable_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        color::ColorMode,
+        device::{ControllableDevice, DeviceId, ManageKind},
+    };
+
+    #[test]
+    fn test_gamma_roundtrip() {
+        for gamma in [0.5, 1.0, 2.2] {
+            for brightness in [0.0, 0.1, 0.5, 1.0] {
+                let roundtrip = apply_inverse_gamma(apply_gamma(brightness, gamma), gamma);
+                assert!((roundtrip - brightness).abs() < 1e-5);
+            }
+        }
+
+        assert!((apply_gamma(0.5, 2.0) - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_invalid_gamma() {
+        let parse = |json| serde_json::from_value::<DeviceConfig>(json);
+
+        assert_eq!(
+            parse(serde_json::json!({ "gamma": 2.2 })).unwrap().gamma,
+            Some(2.2)
+        );
+        assert_eq!(parse(serde_json::json!({})).unwrap().gamma, None);
+        assert!(parse(serde_json::json!({ "gamma": 0.0 })).is_err());
+        assert!(parse(serde_json::json!({ "gamma": -1.0 })).is_err());
+    }
+
+    #[test]
+    fn test_device_config_overrides_integration_defaults() {
+        let device_config = DeviceConfig {
+            gamma: Some(2.0),
+            ..Default::default()
+        };
+        let defaults = DeviceConfig {
+            gamma: Some(1.5),
+            min_brightness: Some(0.1),
+            ..Default::default()
+        };
+
+        let merged = device_config.or(&defaults);
+        assert_eq!(merged.gamma, Some(2.0));
+        assert_eq!(merged.min_brightness, Some(0.1));
+        assert_eq!(DeviceConfig::default().or(&defaults).gamma, Some(1.5));
+    }
+
+    #[test]
+    fn test_apply_unit() {
+        let fahrenheit_to_celsius = UnitConfig {
+            from: Some(Unit::Fahrenheit),
+            to: Some(Unit::Celsius),
+            ..Default::default()
+        };
+        let celsius = apply_unit(212.0, &fahrenheit_to_celsius).unwrap();
+        assert!((celsius - 100.0).abs() < 1e-9);
+
+        let w_to_kw = UnitConfig {
+            from: Some(Unit::W),
+            to: Some(Unit::Kw),
+            ..Default::default()
+        };
+        assert_eq!(apply_unit(1500.0, &w_to_kw), Some(1.5));
+
+        let lux_scale = UnitConfig {
+            scale: Some(2.5),
+            offset: Some(-10.0),
+            ..Default::default()
+        };
+        assert_eq!(apply_unit(100.0, &lux_scale), Some(240.0));
+
+        let mismatch = UnitConfig {
+            from: Some(Unit::W),
+            to: Some(Unit::Celsius),
+            ..Default::default()
+        };
+        assert_eq!(apply_unit(100.0, &mismatch), None);
+    }
+
+    #[test]
+    fn test_clamp_brightness() {
+        let config = DeviceConfig {
+            min_brightness: Some(0.1),
+            max_brightness: Some(0.8),
+            ..Default::default()
+        };
+
+        assert_eq!(clamp_brightness(0.05, &config), 0.1);
+        assert_eq!(clamp_brightness(0.5, &config), 0.5);
+        assert_eq!(clamp_brightness(1.0, &config), 0.8);
+        assert_eq!(clamp_brightness(0.05, &DeviceConfig::default()), 0.05);
+    }
+
+    #[test]
+    fn test_apply_default_capabilities() {
+        let mk_device = |capabilities: Capabilities| {
+            Device::new(
+                IntegrationId::from("mqtt".to_string()),
+                DeviceId::new("light"),
+                "Light".to_string(),
+                DeviceData::Controllable(ControllableDevice::new(
+                    None,
+                    true,
+                    Some(1.0),
+                    None,
+                    None,
+                    capabilities,
+                    ManageKind::Full,
+                )),
+            )
+        };
+        let cct_only = Capabilities::singleton(ColorMode::Ct(2200..4000));
+
+        let device = apply_default_capabilities(mk_device(Capabilities::default()), &cct_only);
+        assert_eq!(device.get_supported_color_modes(), Some(&cct_only));
+
+        // Reported capabilities win over configured ones
+        let reported = Capabilities::singleton(ColorMode::Xy);
+        let device = apply_default_capabilities(mk_device(reported.clone()), &cct_only);
+        assert_eq!(device.get_supported_color_modes(), Some(&reported));
+    }
+}
@@ -0,0 +1,80 @@
+//! Queues incoming device states per device. Messages are otherwise handled
+//! by a task per message, which may run in any order once they acquire the
+//! state lock, whereas the states of a device must be handled in the order
+//! they were received. A burst of states of a device is handled under a single
+//! acquisition of the state lock. Handling still takes the write lock of the
+//! whole app state, so states of different devices aren't handled
+//! concurrently.
+
+use std::{collections::HashMap, sync::Arc};
+
+use color_eyre::Result;
+use eyre::eyre;
+
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    RwLock,
+};
+
+use crate::types::{
+    device::{Device, DeviceKey},
+    event::{CorrelationId, Message},
+};
+
+use super::{message::process_message, state::AppState};
+
+type DeviceUpdate = (CorrelationId, Device);
+
+pub struct DeviceWorkers {
+    state: Arc<RwLock<AppState>>,
+    workers: HashMap<DeviceKey, UnboundedSender<DeviceUpdate>>,
+}
+
+impl DeviceWorkers {
+    pub fn new(state: Arc<RwLock<AppState>>) -> Self {
+        DeviceWorkers {
+            state,
+            workers: Default::default(),
+        }
+    }
+
+    /// Queues an incoming device state for the task of that device, spawning
+    /// the task when the device is seen for the first time
+    pub fn dispatch(&mut self, correlation_id: CorrelationId, device: Device) -> Result<()> {
+        let device_key = device.get_device_key();
+        let tx = self.workers.entry(device_key.clone()).or_insert_with(|| {
+            let (tx, rx) = unbounded_channel();
+            tokio::spawn(run_device_worker(Arc::clone(&self.state), rx));
+            tx
+        });
+
+        if tx.send((correlation_id, device)).is_err() {
+            // Spawn a new task for the next update
+            self.workers.remove(&device_key);
+            return Err(eyre!("Task of device {} has stopped", device_key));
+        }
+
+        Ok(())
+    }
+
+    /// Stops the task of a device that was removed, once it has handled its
+    /// queued updates
+    pub fn remove(&mut self, device_key: &DeviceKey) {
+        self.workers.remove(device_key);
+    }
+}
+
+async fn run_device_worker(state: Arc<RwLock<AppState>>, mut rx: UnboundedReceiver<DeviceUpdate>) {
+    while let Some(update) = rx.recv().await {
+        let mut updates = vec![update];
+        while let Ok(update) = rx.try_recv() {
+            updates.push(update);
+        }
+
+        let mut state = state.write().await;
+        for (correlation_id, device) in updates {
+            let msg = Message::RecvDeviceState { device };
+            process_message(&mut state, correlation_id, &msg).await;
+        }
+    }
+}
@@ -1,29 +1,106 @@
-use crate::db::actions::{db_find_device, db_update_device};
-use crate::types::color::{Capabilities, DeviceColor};
+use crate::db::{
+    actions::{db_find_device, db_store_device_history, db_touch_device, db_update_device},
+    spawn_db_write,
+};
+use crate::types::color::{resample_segments, Capabilities, DeviceColor};
 use crate::types::integration::IntegrationId;
 
+use super::device_config::DeviceConfigs;
 use super::expr::EvalContext;
 use super::groups::Groups;
-use super::scenes::{get_next_cycled_scene, Scenes};
+use super::scenes::Scenes;
+use super::sensor_filters::SensorFilters;
 use crate::types::device::{
-    ControllableDevice, ControllableState, DeviceRef, ManageKind, SensorDevice,
+    ControllableDevice, ControllableState, ControllableStateUpdate, DeviceAlias, DeviceRef,
+    ManageKind, SensorDevice,
 };
+use crate::types::device_expiry::PurgeUnseenDevicesDescriptor;
+use crate::types::dim::UpdateDeviceStateDescriptor;
 use crate::types::group::GroupId;
+use crate::types::overrides::OverridesConfig;
+use crate::types::power::{PowerDescriptor, EXEMPT_TAG};
 use crate::types::{
+    action::Action,
     device::{Device, DeviceData, DeviceKey, DevicesState},
     event::{Message, TxEventChannel},
     scene::{SceneDescriptor, SceneId},
 };
+use chrono::Utc;
 use color_eyre::Result;
 use eyre::eyre;
 use ordered_float::OrderedFloat;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+use tracing::instrument;
+
+/// How often the time a device was last seen is written to the DB, as
+/// devices may report their state many times a minute
+const TOUCH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long after its transition a device may still report stale or
+/// intermediate states, which aren't treated as manual changes
+const SETTLE_TIME: Duration = Duration::from_secs(5);
+
+/// Purges devices that haven't been seen for `expire_after_days` once a day.
+/// The first check runs a day after startup, so that devices get a chance to
+/// report their state after downtime.
+pub async fn expire_unseen_devices(event_tx: TxEventChannel, expire_after_days: u32) {
+    let period = Duration::from_secs(24 * 60 * 60);
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+
+    loop {
+        interval.tick().await;
+        event_tx.send(Message::Action(Action::PurgeUnseenDevices(
+            PurgeUnseenDevicesDescriptor {
+                not_seen_for_days: expire_after_days,
+            },
+        )));
+    }
+}
+
+/// Periodically compares reported and expected state of devices, see
+/// [Devices::reconcile]
+pub async fn reconcile_devices(event_tx: TxEventChannel, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+        event_tx.send(Message::ReconcileDevices);
+    }
+}
 
 #[derive(Clone)]
 pub struct Devices {
     event_tx: TxEventChannel,
     state: DevicesState,
     keys_by_name: BTreeMap<(IntegrationId, String), DeviceKey>,
+    unavailable_devices: BTreeSet<DeviceKey>,
+    overrides_config: OverridesConfig,
+    device_configs: DeviceConfigs,
+
+    /// Aliased device keys, resolved when looking up devices by reference
+    aliases: BTreeMap<DeviceKey, DeviceKey>,
+
+    /// Devices in manual override mode, along with when the override ends
+    overrides: BTreeMap<DeviceKey, Instant>,
+
+    /// Latest state reported by integrations for controllable devices, which
+    /// may differ from the expected state kept in `state`
+    reported: BTreeMap<DeviceKey, Device>,
+
+    /// Recent readings of sensors with a filter configured
+    sensor_filters: SensorFilters,
+
+    /// Whether integrations have completed their start pass, after which
+    /// newly discovered devices get their startup state right away
+    started: bool,
+
+    /// When the last seen time of each device was last written to the DB
+    last_touched: BTreeMap<DeviceKey, Instant>,
+
+    /// Until when devices that were recently sent a new state are expected to
+    /// settle
+    settling: BTreeMap<DeviceKey, Instant>,
 }
 
 /// Compares light colors in the color mode as preferred by the device, allowing
@@ -79,7 +156,7 @@ fn cmp_light_color(
 /// Compares the state of a ControllableDevice to some given ControllableState.
 ///
 /// If the states match, the function evaluates to true.
-fn cmp_device_states(device: &ControllableDevice, expected: &ControllableState) -> bool {
+pub fn cmp_device_states(device: &ControllableDevice, expected: &ControllableState) -> bool {
     if device.state.power != expected.power {
         return false;
     }
@@ -89,6 +166,25 @@ fn cmp_device_states(device: &ControllableDevice, expected: &ControllableState)
         return true;
     }
 
+    // Compare segment colors if the device reports them
+    if let (Some(segments), Some(count)) = (&device.state.segments, device.capabilities.segments) {
+        let expected = resample_segments(expected.segments.as_deref().unwrap_or_default(), count);
+        let matches = segments.len() == expected.len()
+            && segments.iter().zip(&expected).all(|(incoming, expected)| {
+                cmp_light_color(
+                    &device.capabilities,
+                    &Some(incoming.clone()),
+                    &None,
+                    &Some(expected.clone()),
+                    &None,
+                )
+            });
+
+        if !expected.is_empty() && !matches {
+            return false;
+        }
+    }
+
     // Compare colors if supported
     if device.state.color.is_some() {
         return cmp_light_color(
@@ -103,6 +199,33 @@ fn cmp_device_states(device: &ControllableDevice, expected: &ControllableState)
     true
 }
 
+/// Creates a copy of a device with reported state replaced by expected state,
+/// converted into a color format supported by the device
+fn mk_correction(
+    reported: &Device,
+    reported_state: &ControllableDevice,
+    expected_state: &ControllableState,
+) -> Device {
+    info!(
+        "Device state mismatch detected ({}/{}):\nwas:      {}\nexpected: {}\n",
+        reported.integration_id,
+        reported.name,
+        reported_state.state,
+        expected_state.color_to_device_preferred_mode(&reported_state.capabilities)
+    );
+
+    let mut controllable = reported_state.clone();
+    controllable.state =
+        expected_state.color_to_device_preferred_mode(&reported_state.capabilities);
+
+    // Disable transitions
+    controllable.state.transition_ms = None;
+
+    let mut device = reported.clone();
+    device.data = DeviceData::Controllable(controllable);
+    device
+}
+
 /// Compares the state of two sensor devices.
 ///
 /// If the states match, the function evaluates to true.
@@ -111,32 +234,345 @@ fn cmp_sensor_states(sensor: &SensorDevice, previous: &SensorDevice) -> bool {
 }
 
 impl Devices {
-    pub fn new(event_tx: TxEventChannel) -> Self {
+    pub fn new(
+        event_tx: TxEventChannel,
+        overrides_config: OverridesConfig,
+        device_configs: DeviceConfigs,
+    ) -> Self {
         Devices {
             event_tx,
             state: Default::default(),
             keys_by_name: Default::default(),
+            unavailable_devices: Default::default(),
+            overrides_config,
+            device_configs,
+            aliases: Default::default(),
+            overrides: Default::default(),
+            reported: Default::default(),
+            sensor_filters: Default::default(),
+            started: false,
+            last_touched: Default::default(),
+            settling: Default::default(),
+        }
+    }
+
+    /// Returns a copy that sends its messages to `event_tx`, for dry runs that
+    /// must not affect actual devices
+    pub fn detached(&self, event_tx: TxEventChannel) -> Devices {
+        Devices {
+            event_tx,
+            ..self.clone()
+        }
+    }
+
+    /// Integrations have completed their start pass, sets configured startup
+    /// states of devices discovered so far
+    pub fn handle_integrations_started(&mut self) {
+        self.started = true;
+
+        for device in self.state.0.values() {
+            self.send_startup_state(device);
         }
     }
 
+    fn send_startup_state(&self, device: &Device) {
+        let Some(state) = self.device_configs.get_device_config(device).startup_state else {
+            return;
+        };
+
+        debug!("Setting startup state of {}", device.get_device_key());
+
+        self.event_tx
+            .send(Message::Action(Action::UpdateDeviceState(
+                UpdateDeviceStateDescriptor {
+                    device_keys: Some(vec![device.get_device_key()]),
+                    group_keys: None,
+                    state,
+                },
+            )));
+    }
+
+    pub fn get_device_configs(&self) -> &DeviceConfigs {
+        &self.device_configs
+    }
+
     pub fn get_state(&self) -> &DevicesState {
         &self.state
     }
 
+    pub fn get_unavailable_devices(&self) -> &BTreeSet<DeviceKey> {
+        &self.unavailable_devices
+    }
+
+    /// Marks all known devices of given integration as available or unavailable
+    pub fn set_integration_availability(
+        &mut self,
+        integration_id: &IntegrationId,
+        available: bool,
+    ) {
+        let device_keys = self
+            .state
+            .0
+            .keys()
+            .filter(|device_key| &device_key.integration_id == integration_id)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for device_key in device_keys {
+            if available {
+                self.unavailable_devices.remove(&device_key);
+            } else {
+                self.unavailable_devices.insert(device_key);
+            }
+        }
+    }
+
+    /// Marks a single device as available or unavailable. Returns true if
+    /// availability changed.
+    pub fn set_device_availability(&mut self, device_key: &DeviceKey, available: bool) -> bool {
+        if available {
+            self.unavailable_devices.remove(device_key)
+        } else {
+            self.unavailable_devices.insert(device_key.clone())
+        }
+    }
+
+    /// Returns keys of given devices and devices belonging to given groups
+    pub fn resolve_device_keys(
+        &self,
+        device_keys: &Option<Vec<DeviceKey>>,
+        group_keys: &Option<Vec<GroupId>>,
+        groups: &Groups,
+    ) -> BTreeSet<DeviceKey> {
+        let mut keys: BTreeSet<DeviceKey> = device_keys
+            .iter()
+            .flatten()
+            .filter(|device_key| self.state.0.contains_key(device_key))
+            .cloned()
+            .collect();
+
+        for group_id in group_keys.iter().flatten() {
+            keys.extend(
+                groups
+                    .find_group_devices(&self.state, group_id)
+                    .into_iter()
+                    .map(|device| device.get_device_key()),
+            );
+        }
+
+        keys
+    }
+
+    /// Resolves devices located in given areas
+    fn resolve_area_device_keys(&self, areas: &Option<Vec<String>>) -> BTreeSet<DeviceKey> {
+        let Some(areas) = areas else {
+            return BTreeSet::new();
+        };
+
+        self.state
+            .0
+            .values()
+            .filter_map(|device| {
+                let area = self.device_configs.get_device_config(device).area?;
+                areas.contains(&area).then(|| device.get_device_key())
+            })
+            .collect()
+    }
+
+    /// Resolves given devices, groups and areas, or all devices if no targets
+    /// are given
+    fn resolve_targets(
+        &self,
+        device_keys: &Option<Vec<DeviceKey>>,
+        group_keys: &Option<Vec<GroupId>>,
+        areas: &Option<Vec<String>>,
+        groups: &Groups,
+    ) -> BTreeSet<DeviceKey> {
+        if device_keys.is_none() && group_keys.is_none() && areas.is_none() {
+            return self.state.0.keys().cloned().collect();
+        }
+
+        let mut keys = self.resolve_device_keys(device_keys, group_keys, groups);
+        keys.extend(self.resolve_area_device_keys(areas));
+
+        keys
+    }
+
+    pub fn is_overridden(&self, device_key: &DeviceKey) -> bool {
+        self.overrides
+            .get(device_key)
+            .map_or(false, |until| *until > Instant::now())
+    }
+
+    pub fn get_overridden_devices(&self) -> BTreeSet<DeviceKey> {
+        self.overrides
+            .keys()
+            .filter(|device_key| self.is_overridden(device_key))
+            .cloned()
+            .collect()
+    }
+
+    /// Puts given devices into manual override mode, where their state is not
+    /// corrected to match the active scene until the override expires.
+    pub fn set_override(&mut self, device_keys: BTreeSet<DeviceKey>, duration: Option<Duration>) {
+        let duration = duration.unwrap_or_else(|| {
+            Duration::from_secs(self.overrides_config.timeout_secs.unwrap_or(3600))
+        });
+        let until = Instant::now() + duration;
+
+        for device_key in device_keys {
+            info!("Overriding {} for {:?}", device_key, duration);
+            self.overrides.insert(device_key.clone(), until);
+
+            let event_tx = self.event_tx.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(duration).await;
+                event_tx.send(Message::OverrideExpired { device_key });
+            });
+        }
+
+        self.event_tx.send(Message::WsBroadcastState);
+    }
+
+    /// Ends manual override mode for given devices and restores their
+    /// expected state.
+    pub async fn clear_override(&mut self, device_keys: BTreeSet<DeviceKey>, scenes: &Scenes) {
+        for device_key in device_keys {
+            if self.overrides.remove(&device_key).is_none() {
+                continue;
+            }
+
+            info!("Override of {} ended", device_key);
+
+            if let Some(device) = self.get_device(&device_key).cloned() {
+                self.set_device_state(&device, scenes, false, false, false)
+                    .await;
+            }
+        }
+
+        self.event_tx.send(Message::WsBroadcastState);
+    }
+
+    /// Re-compares the latest reported state of managed devices with their
+    /// expected state, and re-sends expected state to devices that don't
+    /// match. Catches devices that dropped a command without reporting back.
+    pub fn reconcile(&self, scenes: &Scenes) {
+        for (device_key, reported) in &self.reported {
+            let Some(device) = self.get_device(device_key) else {
+                continue;
+            };
+
+            if !device.is_managed()
+                || self.is_overridden(device_key)
+                || self.unavailable_devices.contains(device_key)
+                || self.device_configs.is_disabled(device_key)
+            {
+                continue;
+            }
+
+            let DeviceData::Controllable(reported_state) = &reported.data else {
+                continue;
+            };
+
+            let Some(expected_state) = self.get_expected_state(device, scenes, false, None) else {
+                continue;
+            };
+
+            if !cmp_device_states(reported_state, &expected_state) {
+                let device = mk_correction(reported, reported_state, &expected_state);
+                self.event_tx.send(Message::SendDeviceState { device });
+            }
+        }
+    }
+
+    /// Clears the override of given device, unless it was extended after the
+    /// timer was started.
+    pub async fn handle_override_expired(&mut self, device_key: &DeviceKey, scenes: &Scenes) {
+        let expired = self
+            .overrides
+            .get(device_key)
+            .map_or(false, |until| *until <= Instant::now());
+
+        if expired {
+            self.clear_override(BTreeSet::from([device_key.clone()]), scenes)
+                .await;
+        }
+    }
+
     /// Checks whether device values were changed or not due to refresh
+    #[instrument(skip_all, fields(device = %incoming.get_device_key()))]
+    /// Applies the sensor filter configured for the device, returning None if
+    /// the reading should be ignored
+    fn filter_sensor_reading(&mut self, incoming: &Device) -> Option<Device> {
+        let Some(SensorDevice::Number { value, unit, raw }) = incoming.get_sensor_state() else {
+            return Some(incoming.clone());
+        };
+        let Some(filter) = self.device_configs.get_device_config(incoming).filter else {
+            return Some(incoming.clone());
+        };
+
+        let device_key = incoming.get_device_key();
+        let stored =
+            self.get_device(&device_key)
+                .and_then(|device| match device.get_sensor_state()? {
+                    SensorDevice::Number { value, .. } => Some(value.0),
+                    _ => None,
+                });
+        let value = self
+            .sensor_filters
+            .apply(&device_key, &filter, value.0, stored)?;
+
+        let mut device = incoming.clone();
+        device.data = DeviceData::Sensor(SensorDevice::Number {
+            value: OrderedFloat(value),
+            unit: unit.clone(),
+            raw: *raw,
+        });
+
+        Some(device)
+    }
+
     pub async fn handle_recv_device_state(
         &mut self,
         incoming: &Device,
         scenes: &Scenes,
     ) -> Result<()> {
         trace!("handle_recv_device_state {:?}", incoming);
+
+        // Disabled devices still count as seen, so that they don't get
+        // purged along with their metadata
+        self.touch_device(&incoming.get_device_key());
+
+        if self.device_configs.is_disabled(&incoming.get_device_key()) {
+            return Ok(());
+        }
+
+        let Some(incoming) = &self.filter_sensor_reading(incoming) else {
+            return Ok(());
+        };
+
+        if incoming.get_controllable_state().is_some() {
+            self.reported
+                .insert(incoming.get_device_key(), incoming.clone());
+        }
+
         let current = self.get_device(&incoming.get_device_key());
 
+        // Devices whose scene overrides their management keep the overridden
+        // kind, as integrations keep reporting their own
+        let scene_managed = current
+            .filter(|d| scenes.find_scene_device_manage_kind(d).is_some())
+            .and_then(|d| d.get_managed());
+        let incoming = &match scene_managed {
+            Some(managed) => incoming.set_managed(managed.clone()),
+            None => incoming.clone(),
+        };
+
         // recompute expected_state here as it may have changed since we last
         // computed it
         let expected_state = current
             .as_ref()
-            .and_then(|d| self.get_expected_state(d, scenes, false));
+            .and_then(|d| self.get_expected_state(d, scenes, false, None));
 
         // Take action if the device state differs from expected state
         match (&incoming.data, current, expected_state) {
@@ -169,6 +605,10 @@ impl Devices {
                             .await;
                     }
                 }
+
+                if self.started {
+                    self.send_startup_state(incoming);
+                }
             }
 
             (DeviceData::Sensor(incoming_sensor), Some(current), _) => {
@@ -187,7 +627,7 @@ impl Devices {
             }
 
             (DeviceData::Controllable(ref incoming_state), _, Some(expected_state)) => {
-                if !incoming.is_managed() {
+                if !incoming.is_managed() || self.is_overridden(&incoming.get_device_key()) {
                     self.set_device_state(incoming, scenes, false, false, true)
                         .await;
                     return Ok(());
@@ -214,36 +654,34 @@ impl Devices {
                     return Ok(());
                 }
 
-                let expected_converted =
-                    expected_state.color_to_device_preferred_mode(&incoming_state.capabilities);
+                if self.overrides_config.detect_manual_changes == Some(true) {
+                    if self.is_settling(&incoming.get_device_key()) {
+                        // Device is still transitioning to the state sent to
+                        // it, or hasn't received it yet
+                        debug!(
+                            "Ignoring state of {} while it settles",
+                            incoming.get_device_key()
+                        );
+                        return Ok(());
+                    }
+
+                    // Assume the state was changed manually, e.g. with a
+                    // physical switch, and stop fighting it for a while
+                    info!(
+                        "Manual change detected ({}/{}), entering override mode",
+                        incoming.integration_id, incoming.name,
+                    );
+                    self.set_override(BTreeSet::from([incoming.get_device_key()]), None);
+                    self.set_device_state(incoming, scenes, false, false, true)
+                        .await;
+                    return Ok(());
+                }
 
                 // Device state does not match expected state, maybe the device
                 // missed a state update or forgot its state? We will try fixing
                 // this by emitting a SetIntegrationDeviceState message back to
                 // integration
-                info!(
-                    "Device state mismatch detected ({}/{}):\nwas:      {}\nexpected: {}\n",
-                    incoming.integration_id,
-                    incoming.name,
-                    incoming_state.state,
-                    expected_converted
-                );
-
-                // Replace device state with expected state, converted into a
-                // supported color format
-                let mut controllable = incoming_state.clone();
-                controllable.state = expected_state.clone();
-                controllable.state.color = controllable
-                    .state
-                    .color
-                    .and_then(|c| c.to_device_preferred_mode(&incoming_state.capabilities));
-
-                // Disable transitions
-                controllable.state.transition_ms = None;
-
-                let mut device = incoming.clone();
-                device.data = DeviceData::Controllable(controllable);
-
+                let device = mk_correction(incoming, incoming_state, &expected_state);
                 self.event_tx.send(Message::SendDeviceState { device });
             }
 
@@ -265,17 +703,17 @@ impl Devices {
         device: &Device,
         scenes: &Scenes,
         use_passed_state: bool,
+        transition_ms: Option<u64>,
     ) -> Option<ControllableState> {
         match device.data {
             DeviceData::Sensor(_) => None,
 
             DeviceData::Controllable(_) => {
                 let scene_device_state = {
-                    let ignore_transition = use_passed_state;
-                    let device_state = scenes.find_scene_device_state(device);
-                    device_state.map(|state| {
-                        let mut state = state.clone();
-
+                    // Transition overrides are always respected
+                    let ignore_transition = use_passed_state && transition_ms.is_none();
+                    let device_state = scenes.find_scene_device_state(device, transition_ms);
+                    device_state.map(|mut state| {
                         // Ignore transition specified by scene if we're setting state
                         if ignore_transition {
                             state.transition_ms = None;
@@ -312,6 +750,9 @@ impl Devices {
                         expected_state.brightness =
                             Some(expected_state.brightness.unwrap_or(OrderedFloat(1.0)));
                     }
+
+                    self.device_configs
+                        .clamp_expected_state(device, expected_state);
                 }
 
                 expected_state
@@ -321,6 +762,7 @@ impl Devices {
 
     /// Sets internal state for given device and dispatches device state to
     /// integration
+    #[instrument(skip_all, fields(device = %device.get_device_key()))]
     pub async fn set_device_state(
         &mut self,
         device: &Device,
@@ -329,11 +771,36 @@ impl Devices {
         skip_db: bool,
         skip_send: bool,
     ) -> Device {
+        self.set_device_state_with_transition(device, scenes, set_scene, skip_db, skip_send, None)
+            .await
+    }
+
+    /// Like [Devices::set_device_state], but overrides the transition time of
+    /// the device's scene state
+    async fn set_device_state_with_transition(
+        &mut self,
+        device: &Device,
+        scenes: &Scenes,
+        set_scene: bool,
+        skip_db: bool,
+        skip_send: bool,
+        transition_ms: Option<u64>,
+    ) -> Device {
+        if self.device_configs.is_disabled(&device.get_device_key()) {
+            debug!("Ignoring state update of disabled device {}", device.name);
+            return device.clone();
+        }
+
         let old_states = { self.state.clone() };
         let old = old_states.0.get(&device.get_device_key()).cloned();
 
-        // Insert new device into keys_by_name map
-        if old.is_none() {
+        // Insert new or renamed device into keys_by_name map
+        if old.as_ref().map(|old| &old.name) != Some(&device.name) {
+            if let Some(old) = &old {
+                self.keys_by_name
+                    .remove(&(old.integration_id.clone(), old.name.clone()));
+            }
+
             self.keys_by_name.insert(
                 (device.integration_id.clone(), device.name.clone()),
                 device.get_device_key(),
@@ -348,21 +815,20 @@ impl Devices {
             device = device.set_scene(old_device_scene);
         }
 
-        if set_scene || device.is_managed() {
+        // Overridden devices keep their passed state regardless of scene
+        let overridden = self.is_overridden(&device.get_device_key());
+
+        if (set_scene || device.is_managed()) && !overridden {
             // Allow active scene to override device state
-            let expected_state = self.get_expected_state(&device, scenes, true);
+            let expected_state = self.get_expected_state(&device, scenes, true, transition_ms);
             let capabilities = device.get_supported_color_modes();
 
             // Replace device state with expected state
             if let (Some(expected_state), Some(capabilities)) = (expected_state, capabilities) {
-                let mut expected_state = expected_state.clone();
-
                 // Converted expected state into a supported color format
-                expected_state.color = expected_state
-                    .color
-                    .and_then(|c| c.to_device_preferred_mode(capabilities));
+                let expected_state = expected_state.color_to_device_preferred_mode(capabilities);
 
-                device = device.set_controllable_state(expected_state.clone());
+                device = device.set_controllable_state(expected_state);
             }
         }
 
@@ -380,73 +846,210 @@ impl Devices {
         }
 
         if !skip_send && !device.is_sensor() {
-            self.event_tx.send(Message::SendDeviceState {
-                device: device.clone(),
-            });
+            // Scenes and actions that don't specify a transition get the
+            // configured default one
+            let device = self.device_configs.apply_default_transition(&device);
+            let transition = device
+                .get_controllable_state()
+                .and_then(|state| state.transition_ms)
+                .map_or(Duration::ZERO, Duration::from_millis);
+            self.settling.insert(
+                device.get_device_key(),
+                Instant::now() + transition + SETTLE_TIME,
+            );
+            self.event_tx.send(Message::SendDeviceState { device });
         }
 
         if !skip_db && state_changed {
             let device = device.clone();
-            tokio::spawn(async move {
+            spawn_db_write(async move {
                 db_update_device(&device).await.ok();
+                db_store_device_history(&device).await.ok();
             });
         }
 
         device
     }
 
+    /// Whether the device was sent a new state recently enough that it may not
+    /// have reached it yet
+    fn is_settling(&self, device_key: &DeviceKey) -> bool {
+        self.settling
+            .get(device_key)
+            .map_or(false, |until| *until > Instant::now())
+    }
+
+    /// Records that the device has reported its state, at most once per
+    /// [TOUCH_INTERVAL]
+    fn touch_device(&mut self, device_key: &DeviceKey) {
+        let now = Instant::now();
+        let recently_touched = self.last_touched.get(device_key).map_or(false, |touched| {
+            now.duration_since(*touched) < TOUCH_INTERVAL
+        });
+
+        if recently_touched {
+            return;
+        }
+
+        self.last_touched.insert(device_key.clone(), now);
+
+        let device_key = device_key.clone();
+        spawn_db_write(async move {
+            db_touch_device(&device_key, Utc::now()).await.ok();
+        });
+    }
+
+    /// Makes references to the aliased device resolve to the new device
+    pub fn add_alias(&mut self, alias: &DeviceAlias) {
+        self.aliases.insert(alias.from.clone(), alias.to.clone());
+
+        if let Some(name) = &alias.name {
+            let name_key = (alias.from.integration_id.clone(), name.clone());
+
+            // Don't shadow a live device that still goes by the same name
+            let shadowed = self.keys_by_name.get(&name_key).map_or(false, |key| {
+                *key != alias.from && self.state.0.contains_key(key)
+            });
+
+            if !shadowed {
+                self.keys_by_name.insert(name_key, alias.to.clone());
+            }
+        }
+    }
+
+    /// Replaces the aliased device with the new device, which takes over its
+    /// scene. Returns the replaced device if it was known.
+    pub async fn migrate_device(&mut self, alias: &DeviceAlias, scenes: &Scenes) -> Option<Device> {
+        let old = self.remove_device(&alias.from);
+        self.add_alias(alias);
+
+        let old = old?;
+
+        if let Some(new) = self.get_device(&alias.to) {
+            let new = new.set_scene(old.get_scene());
+            self.set_device_state(&new, scenes, true, false, false)
+                .await;
+        }
+
+        Some(old)
+    }
+
+    /// Forgets about a device, e.g. when it gets disabled. The device is
+    /// discovered again the next time its integration reports it.
+    pub fn remove_device(&mut self, device_key: &DeviceKey) -> Option<Device> {
+        let device = self.state.0.remove(device_key)?;
+
+        self.keys_by_name
+            .remove(&(device.integration_id.clone(), device.name.clone()));
+        self.unavailable_devices.remove(device_key);
+        self.overrides.remove(device_key);
+        self.reported.remove(device_key);
+        self.last_touched.remove(device_key);
+        self.settling.remove(device_key);
+
+        self.event_tx.send(Message::DeviceRemoved {
+            device_key: device_key.clone(),
+        });
+
+        Some(device)
+    }
+
     pub fn get_device(&self, device_key: &DeviceKey) -> Option<&Device> {
         self.state.0.get(device_key)
     }
 
+    #[instrument(skip_all, fields(scene_id = %scene_descriptor.scene_id))]
     pub async fn activate_scene(
         &mut self,
-        scene_id: &SceneId,
-        device_keys: &Option<Vec<DeviceKey>>,
-        group_keys: &Option<Vec<GroupId>>,
+        scene_descriptor: &SceneDescriptor,
         groups: &Groups,
         scenes: &Scenes,
         eval_context: &EvalContext,
     ) -> Option<bool> {
+        let scene_id = &scene_descriptor.scene_id;
         info!("Activating scene {:?}", scene_id);
 
-        let scene_devices_config = scenes.find_scene_devices_config(
-            self,
-            groups,
-            &SceneDescriptor {
-                scene_id: scene_id.clone(),
-                device_keys: device_keys.clone(),
-                group_keys: group_keys.clone(),
-            },
-            eval_context,
-        )?;
+        let scene_devices_config =
+            scenes.find_scene_devices_config(self, groups, scene_descriptor, eval_context)?;
 
         for device_key in scene_devices_config.keys() {
+            // Explicitly activating a scene ends any manual override
+            self.overrides.remove(device_key);
+
             let device = self.get_device(device_key);
 
             if let Some(device) = device {
-                let device = device.set_scene(Some(scene_id.clone()));
-                self.set_device_state(&device, scenes, true, false, false)
-                    .await;
+                let mut device = device.set_scene(Some(scene_id.clone()));
+
+                // Management overrides start over each time the scene is
+                // activated
+                if let Some(managed) = scenes.find_scene_device_manage_kind(&device) {
+                    device = device.set_managed(managed);
+                }
+
+                self.set_device_state_with_transition(
+                    &device,
+                    scenes,
+                    true,
+                    false,
+                    false,
+                    scene_descriptor.transition_ms,
+                )
+                .await;
             }
         }
 
         Some(true)
     }
 
+    /// Dims given devices, groups and areas by step. Negative steps brighten.
+    /// If no targets are given, all devices are dimmed.
     pub async fn dim(
         &mut self,
-        _device_keys: &Option<Vec<DeviceKey>>,
-        _group_keys: &Option<Vec<GroupId>>,
-        step: &Option<f32>,
+        device_keys: &Option<Vec<DeviceKey>>,
+        group_keys: &Option<Vec<GroupId>>,
+        areas: &Option<Vec<String>>,
+        step: f32,
+        groups: &Groups,
+        scenes: &Scenes,
+    ) -> Option<bool> {
+        debug!("Dimming devices. Step: {}", step);
+
+        let device_keys = self.resolve_targets(device_keys, group_keys, areas, groups);
+        self.adjust_device_keys(device_keys, scenes, |device| device.dim_device(step))
+            .await
+    }
+
+    /// Applies a relative adjustment to given devices and groups, or to all
+    /// devices if none are given.
+    pub async fn adjust_devices(
+        &mut self,
+        device_keys: &Option<Vec<DeviceKey>>,
+        group_keys: &Option<Vec<GroupId>>,
+        groups: &Groups,
+        scenes: &Scenes,
+        adjust: impl Fn(&Device) -> Device,
+    ) -> Option<bool> {
+        let device_keys = self.resolve_targets(device_keys, group_keys, &None, groups);
+        self.adjust_device_keys(device_keys, scenes, adjust).await
+    }
+
+    async fn adjust_device_keys(
+        &mut self,
+        device_keys: BTreeSet<DeviceKey>,
         scenes: &Scenes,
+        adjust: impl Fn(&Device) -> Device,
     ) -> Option<bool> {
-        debug!("Dimming devices. Step: {}", step.unwrap_or(0.1));
+        for device_key in device_keys {
+            let Some(device) = self.get_device(&device_key) else {
+                continue;
+            };
 
-        let devices = self.get_state().clone();
-        for device in devices.0 {
-            let mut d = device.1.clone();
-            d = d.dim_device(step.unwrap_or(0.1));
+            if device.is_sensor() {
+                continue;
+            }
+
+            let mut d = adjust(device);
             d = d.set_scene(Some(SceneId::new("dimmed".to_string())));
             self.set_device_state(&d, scenes, false, false, false).await;
         }
@@ -454,41 +1057,136 @@ impl Devices {
         Some(true)
     }
 
-    pub async fn cycle_scenes(
+    /// Applies state of a group pseudo-device to all controllable members of
+    /// the group. Brightness and color are only applied if set. Members are
+    /// removed from their scenes.
+    pub async fn set_group_state(
         &mut self,
-        scene_descriptors: &[SceneDescriptor],
-        nowrap: bool,
+        group_id: &GroupId,
+        state: &ControllableState,
         groups: &Groups,
         scenes: &Scenes,
-        eval_context: &EvalContext,
-    ) -> Option<()> {
-        let next_scene = {
-            get_next_cycled_scene(
-                scene_descriptors,
-                nowrap,
-                self,
-                groups,
-                scenes,
-                eval_context,
-            )
-        }?;
+    ) {
+        let device_keys = self.resolve_device_keys(&None, &Some(vec![group_id.clone()]), groups);
 
-        self.activate_scene(
-            &next_scene.scene_id,
-            &next_scene.device_keys,
-            &next_scene.group_keys,
-            groups,
-            scenes,
-            eval_context,
-        )
-        .await;
+        for device_key in device_keys {
+            let Some(device) = self.get_device(&device_key) else {
+                continue;
+            };
+
+            let Some(member_state) = device.get_controllable_state() else {
+                continue;
+            };
+
+            let mut member_state = member_state.clone();
+            member_state.power = state.power;
+            member_state.transition_ms = state.transition_ms;
+
+            if state.brightness.is_some() {
+                member_state.brightness = state.brightness;
+            }
+
+            if state.color.is_some() {
+                member_state.color = state.color.clone();
+            }
+
+            let device = device.set_controllable_state(member_state).set_scene(None);
+            self.set_device_state(&device, scenes, true, false, false)
+                .await;
+        }
+    }
+
+    /// Merges given partial state with the expected state of given devices
+    /// and groups. Updated devices are removed from their scenes.
+    pub async fn update_device_state(
+        &mut self,
+        device_keys: &Option<Vec<DeviceKey>>,
+        group_keys: &Option<Vec<GroupId>>,
+        update: &ControllableStateUpdate,
+        groups: &Groups,
+        scenes: &Scenes,
+    ) {
+        let device_keys = self.resolve_device_keys(device_keys, group_keys, groups);
+
+        for device_key in device_keys {
+            let Some(device) = self.get_device(&device_key) else {
+                continue;
+            };
+
+            let Some(expected_state) = self.get_expected_state(device, scenes, false, None) else {
+                continue;
+            };
+
+            let device = device
+                .set_controllable_state(expected_state.merge(update))
+                .set_scene(None);
+            self.set_device_state(&device, scenes, true, false, false)
+                .await;
+        }
+    }
+
+    /// Powers given devices, groups and areas on or off, or all devices if no
+    /// targets are given. Devices tagged as exempt are skipped unless
+    /// explicitly listed. Targeted devices are removed from their scenes.
+    pub async fn set_power(
+        &mut self,
+        descriptor: &PowerDescriptor,
+        power: bool,
+        groups: &Groups,
+        scenes: &Scenes,
+    ) -> Option<bool> {
+        debug!("Setting power of devices to {}", power);
+
+        let target_all = descriptor.device_keys.is_none()
+            && descriptor.group_keys.is_none()
+            && descriptor.areas.is_none();
+
+        let mut device_keys = if target_all {
+            self.state.0.keys().cloned().collect()
+        } else {
+            self.resolve_device_keys(&None, &descriptor.group_keys, groups)
+        };
+
+        device_keys.extend(self.resolve_area_device_keys(&descriptor.areas));
+
+        device_keys.retain(|device_key| {
+            self.get_device(device_key).map_or(false, |device| {
+                !self
+                    .device_configs
+                    .get_device_config(device)
+                    .has_tag(EXEMPT_TAG)
+            })
+        });
+
+        // Explicitly listed devices are targeted even if exempt
+        device_keys.extend(self.resolve_device_keys(&descriptor.device_keys, &None, groups));
+
+        for device_key in device_keys {
+            let Some(device) = self.get_device(&device_key) else {
+                continue;
+            };
+
+            let Some(state) = device.get_controllable_state() else {
+                continue;
+            };
+
+            let mut state = state.clone();
+            state.power = power;
+
+            let device = device.set_controllable_state(state).set_scene(None);
+            self.set_device_state(&device, scenes, true, false, false)
+                .await;
+        }
 
-        Some(())
+        Some(true)
     }
 
     pub fn get_device_by_ref<'a>(&'a self, device_ref: &DeviceRef) -> Option<&'a Device> {
         let device_key = match device_ref {
-            DeviceRef::Id(id_ref) => Some(id_ref.clone().into_device_key()),
+            DeviceRef::Id(id_ref) => {
+                let device_key = id_ref.clone().into_device_key();
+                Some(self.aliases.get(&device_key).cloned().unwrap_or(device_key))
+            }
             DeviceRef::Name(name_ref) => self
                 .keys_by_name
                 .get(&(name_ref.integration_id.clone(), name_ref.name.clone()))
@@ -498,3 +1196,200 @@ impl Devices {
         self.state.0.get(&device_key)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        device::DeviceId,
+        device_config::DeviceConfig,
+        event::{mk_event_channel, RxEventChannel},
+    };
+
+    fn mk_light(name: &str) -> Device {
+        Device::new(
+            IntegrationId::from("hue".to_string()),
+            DeviceId::new(name),
+            name.to_string(),
+            DeviceData::Controllable(ControllableDevice::new(
+                None,
+                true,
+                Some(1.0),
+                None,
+                None,
+                Capabilities::default(),
+                ManageKind::Full,
+            )),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_dim_areas() {
+        let (event_tx, _event_rx) = mk_event_channel();
+        let kitchen_config = DeviceConfig {
+            area: Some("kitchen".to_string()),
+            ..Default::default()
+        };
+        let device_configs = DeviceConfigs::new(BTreeMap::from([(
+            IntegrationId::from("hue".to_string()),
+            BTreeMap::from([("kitchen".to_string(), kitchen_config)]),
+        )]));
+        let mut devices = Devices::new(event_tx, Default::default(), device_configs);
+
+        let kitchen = mk_light("kitchen");
+        let hallway = mk_light("hallway");
+        for device in [&kitchen, &hallway] {
+            devices
+                .state
+                .0
+                .insert(device.get_device_key(), device.clone());
+        }
+
+        devices
+            .dim(
+                &None,
+                &None,
+                &Some(vec!["kitchen".to_string()]),
+                0.5,
+                &Default::default(),
+                &Default::default(),
+            )
+            .await;
+
+        let brightness = |device: &Device| {
+            devices
+                .get_device(&device.get_device_key())
+                .and_then(|device| device.get_controllable_state())
+                .and_then(|state| state.brightness)
+        };
+        assert_eq!(brightness(&kitchen), Some(OrderedFloat(0.5)));
+        assert_eq!(brightness(&hallway), Some(OrderedFloat(1.0)));
+    }
+
+    /// Devices with a powered on light that is in a scene turning it off
+    async fn mk_scene_devices(
+        overrides_config: OverridesConfig,
+    ) -> (Devices, Scenes, RxEventChannel, Device) {
+        let (event_tx, event_rx) = mk_event_channel();
+        let mut devices = Devices::new(event_tx, overrides_config, Default::default());
+        let mut scenes = Scenes::default();
+
+        let light = mk_light("kitchen");
+        devices
+            .set_device_state(&light, &scenes, false, true, true)
+            .await;
+
+        let scenes_config = serde_json::from_value(serde_json::json!({
+            "evening": {
+                "name": "Evening",
+                "devices": { "hue": { "kitchen": { "power": false } } }
+            }
+        }))
+        .unwrap();
+        scenes
+            .reload_config(
+                scenes_config,
+                &devices,
+                &Groups::new(Default::default()),
+                &Default::default(),
+            )
+            .await;
+
+        let light = light.set_scene(Some(SceneId::new("evening".to_string())));
+        devices
+            .state
+            .0
+            .insert(light.get_device_key(), light.clone());
+
+        (devices, scenes, event_rx, mk_light("kitchen"))
+    }
+
+    /// States sent to devices since the last call
+    fn sent_states(event_rx: &mut RxEventChannel) -> Vec<Device> {
+        std::iter::from_fn(|| event_rx.try_recv().ok())
+            .filter_map(|(_, msg)| match msg {
+                Message::SendDeviceState { device } => Some(device),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_override_suppresses_scene_state() {
+        let (mut devices, scenes, mut event_rx, reported) =
+            mk_scene_devices(Default::default()).await;
+        let device_key = reported.get_device_key();
+        sent_states(&mut event_rx);
+
+        // Without an override, the scene state is re-asserted
+        devices
+            .handle_recv_device_state(&reported, &scenes)
+            .await
+            .unwrap();
+        let sent = sent_states(&mut event_rx);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            sent[0].get_controllable_state().map(|s| s.power),
+            Some(false)
+        );
+
+        devices.set_override(
+            BTreeSet::from([device_key.clone()]),
+            Some(Duration::from_millis(50)),
+        );
+        devices
+            .handle_recv_device_state(&reported, &scenes)
+            .await
+            .unwrap();
+        assert!(sent_states(&mut event_rx).is_empty());
+        assert_eq!(
+            devices
+                .get_device(&device_key)
+                .and_then(|device| device.get_controllable_state())
+                .map(|state| state.power),
+            Some(true)
+        );
+
+        // Once the override expires, the scene state is restored
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        devices.handle_override_expired(&device_key, &scenes).await;
+        assert!(!devices.is_overridden(&device_key));
+        let sent = sent_states(&mut event_rx);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            sent[0].get_controllable_state().map(|s| s.power),
+            Some(false)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_detect_manual_changes() {
+        let overrides_config = OverridesConfig {
+            detect_manual_changes: Some(true),
+            ..Default::default()
+        };
+        let (mut devices, scenes, mut event_rx, reported) =
+            mk_scene_devices(overrides_config).await;
+        let device_key = reported.get_device_key();
+
+        // States reported while the device settles aren't manual changes
+        let current = devices.get_device(&device_key).cloned().unwrap();
+        devices
+            .set_device_state(&current, &scenes, false, true, false)
+            .await;
+        sent_states(&mut event_rx);
+        devices
+            .handle_recv_device_state(&reported, &scenes)
+            .await
+            .unwrap();
+        assert!(!devices.is_overridden(&device_key));
+
+        devices.settling.clear();
+        devices
+            .handle_recv_device_state(&reported, &scenes)
+            .await
+            .unwrap();
+        assert!(devices.is_overridden(&device_key));
+        assert!(sent_states(&mut event_rx).is_empty());
+    }
+}
@@ -0,0 +1,256 @@
+//! Bridge to a NATS server, publishing internal events and accepting actions
+//! from a command subject. Speaks the plain text NATS client protocol over
+//! TCP, TLS connections are not supported.
+
+use std::time::Duration;
+
+use color_eyre::Result;
+use eyre::eyre;
+use serde_json::{json, Map, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc,
+};
+
+use crate::types::{
+    action::Action,
+    event::{Message, TxEventChannel},
+    event_bus::EventBusConfig,
+};
+
+const DEFAULT_PREFIX: &str = "homectl";
+const DEFAULT_PORT: u16 = 4222;
+
+/// Events published while the connection can't keep up are dropped
+const CHANNEL_CAPACITY: usize = 1024;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub struct EventBus {
+    prefix: String,
+    tx: Option<mpsc::Sender<(String, String)>>,
+}
+
+impl EventBus {
+    pub fn new(config: Option<EventBusConfig>, event_tx: TxEventChannel) -> Self {
+        let Some(config) = config else {
+            return EventBus {
+                prefix: DEFAULT_PREFIX.to_string(),
+                tx: None,
+            };
+        };
+
+        let prefix = config
+            .subject_prefix
+            .clone()
+            .unwrap_or_else(|| DEFAULT_PREFIX.to_string());
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        tokio::spawn(run(config, prefix.clone(), rx, event_tx));
+
+        EventBus {
+            prefix,
+            tx: Some(tx),
+        }
+    }
+
+    /// Publishes device state changes, triggered routines and actions
+    pub fn publish_message(&self, msg: &Message) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+
+        let (subject, payload) = match msg {
+            Message::InternalStateUpdate { new, .. } => (
+                format!(
+                    "{}.devices.{}.{}",
+                    self.prefix,
+                    subject_token(&new.integration_id.to_string()),
+                    subject_token(&new.id.to_string())
+                ),
+                serde_json::to_string(new),
+            ),
+            Message::RoutineTriggered { routine_id } => (
+                format!("{}.routines.{}", self.prefix, subject_token(&routine_id.0)),
+                serde_json::to_string(&json!({ "routine_id": routine_id.0 })),
+            ),
+            Message::Action(action) => (
+                format!("{}.actions", self.prefix),
+                serde_json::to_string(action),
+            ),
+            _ => return,
+        };
+
+        match payload {
+            Ok(payload) => {
+                if tx.try_send((subject, payload)).is_err() {
+                    debug!("Event bus is not keeping up, dropping event");
+                }
+            }
+            Err(e) => warn!("Could not serialize event for {}: {}", subject, e),
+        }
+    }
+}
+
+/// Replaces characters with special meaning in NATS subjects
+fn subject_token(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '.' | '*' | '>' => '_',
+            c if c.is_whitespace() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Parses `nats://host[:port]` into an address to connect to
+fn server_address(url: &str) -> Result<String> {
+    let (scheme, host) = url.split_once("://").unwrap_or(("nats", url));
+
+    if scheme != "nats" && scheme != "tcp" {
+        return Err(eyre!(
+            "Unsupported event bus url {}, only nats:// without TLS is supported",
+            url
+        ));
+    }
+
+    let host = host.trim_end_matches('/');
+    if host
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+    {
+        Ok(host.to_string())
+    } else {
+        Ok(format!("{}:{}", host, DEFAULT_PORT))
+    }
+}
+
+fn connect_command(config: &EventBusConfig) -> String {
+    let mut options = Map::new();
+    options.insert("verbose".to_string(), false.into());
+    options.insert("pedantic".to_string(), false.into());
+    options.insert("name".to_string(), "homectl".into());
+    options.insert("lang".to_string(), "rust".into());
+    options.insert("version".to_string(), env!("CARGO_PKG_VERSION").into());
+
+    let auth = [
+        ("auth_token", &config.token),
+        ("user", &config.user),
+        ("pass", &config.password),
+    ];
+    for (key, value) in auth {
+        if let Some(value) = value {
+            options.insert(key.to_string(), value.clone().into());
+        }
+    }
+
+    format!("CONNECT {}\r\n", Value::Object(options))
+}
+
+/// Returns payload size of a `MSG <subject> <sid> [reply-to] <#bytes>` line
+fn parse_msg_len(line: &str) -> Option<usize> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+
+    match parts.as_slice() {
+        ["MSG", _, _, len] | ["MSG", _, _, _, len] => len.parse().ok(),
+        _ => None,
+    }
+}
+
+async fn run(
+    config: EventBusConfig,
+    prefix: String,
+    mut rx: mpsc::Receiver<(String, String)>,
+    event_tx: TxEventChannel,
+) {
+    loop {
+        match session(&config, &prefix, &mut rx, &event_tx).await {
+            Ok(()) => return,
+            Err(e) => warn!("Lost connection to event bus at {}: {:?}", config.url, e),
+        }
+
+        // Events aren't buffered while disconnected
+        while rx.try_recv().is_ok() {}
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Forwards events to the server until the connection fails. Returns Ok once
+/// there's nothing left to publish.
+async fn session(
+    config: &EventBusConfig,
+    prefix: &str,
+    rx: &mut mpsc::Receiver<(String, String)>,
+    event_tx: &TxEventChannel,
+) -> Result<()> {
+    let stream = TcpStream::connect(server_address(&config.url)?).await?;
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+
+    let info = lines.next_line().await?.unwrap_or_default();
+    if !info.starts_with("INFO") {
+        return Err(eyre!("Expected INFO from server, got {}", info));
+    }
+
+    write.write_all(connect_command(config).as_bytes()).await?;
+    write
+        .write_all(format!("SUB {}.commands 1\r\n", prefix).as_bytes())
+        .await?;
+
+    info!("Connected to event bus at {}", config.url);
+
+    loop {
+        tokio::select! {
+            event = rx.recv() => {
+                let Some((subject, payload)) = event else {
+                    return Ok(());
+                };
+
+                let msg = format!("PUB {} {}\r\n{}\r\n", subject, payload.len(), payload);
+                write.write_all(msg.as_bytes()).await?;
+            }
+            line = lines.next_line() => {
+                let line = line?.ok_or_else(|| eyre!("Connection closed by server"))?;
+
+                if line == "PING" {
+                    write.write_all(b"PONG\r\n").await?;
+                } else if let Some(len) = parse_msg_len(&line) {
+                    // Payload is followed by \r\n
+                    let mut payload = vec![0; len + 2];
+                    lines.get_mut().read_exact(&mut payload).await?;
+                    payload.truncate(len);
+
+                    match serde_json::from_slice::<Action>(&payload) {
+                        Ok(action) => event_tx.send(Message::Action(action)),
+                        Err(e) => warn!("Ignoring invalid action from event bus: {}", e),
+                    }
+                } else if let Some(e) = line.strip_prefix("-ERR") {
+                    return Err(eyre!("Server error:{}", e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol() {
+        assert_eq!(parse_msg_len("MSG homectl.commands 1 42"), Some(42));
+        assert_eq!(parse_msg_len("MSG homectl.commands 1 _INBOX.x 7"), Some(7));
+        assert_eq!(parse_msg_len("+OK"), None);
+
+        assert_eq!(server_address("nats://nats:4223").unwrap(), "nats:4223");
+        assert_eq!(
+            server_address("nats://localhost").unwrap(),
+            "localhost:4222"
+        );
+        assert!(server_address("amqp://rabbitmq").is_err());
+
+        assert_eq!(subject_token("Living room.lamp*"), "Living_room_lamp_");
+    }
+}
@@ -3,6 +3,7 @@ use std::{
     sync::{Arc, RwLock},
 };
 
+use chrono::NaiveDate;
 use evalexpr::*;
 use eyre::Result;
 use jsonptr::Assign;
@@ -15,16 +16,23 @@ use crate::types::{
     group::{FlattenedGroupsConfig, GroupId},
     integration::{CustomActionDescriptor, IntegrationActionPayload, IntegrationId},
     rule::{ForceTriggerRoutineDescriptor, RoutineId},
-    scene::{FlattenedScenesConfig, SceneDescriptor, SceneDeviceConfig, SceneId},
+    scene::{
+        FlattenedSceneConfig, FlattenedScenesConfig, SceneDescriptor, SceneDeviceConfig, SceneId,
+    },
 };
 
 use super::{
-    groups::{flattened_groups_to_eval_context_values, Groups},
+    calendar::Calendar,
+    clock::local_today,
+    groups::{flattened_groups_to_eval_context_values, group_to_eval_context_values, Groups},
     scenes::Scenes,
 };
 
 pub type EvalContext = HashMapContext;
 
+/// Names of the variables set for each scene
+pub type SceneVariables = HashMap<SceneId, HashSet<String>>;
+
 fn value_kv_pairs_deep(
     value: &serde_json::Value,
     prefix: String,
@@ -86,60 +94,100 @@ fn name_to_evalexpr(device_name: &str) -> String {
     device_name.to_lowercase().replace(' ', "_")
 }
 
+/// Calendar lookup taking an optional "YYYY-MM-DD" date, defaulting to today
+fn calendar_function(calendar: &Calendar, f: fn(&Calendar, NaiveDate) -> bool) -> Function {
+    let calendar = calendar.clone();
+
+    Function::new(move |argument| {
+        let date = match argument {
+            Value::Empty => local_today(),
+            argument => {
+                let date = argument.as_string()?;
+                NaiveDate::parse_from_str(&date, "%Y-%m-%d").map_err(|e| {
+                    EvalexprError::CustomMessage(format!("Invalid date {date}: {e}"))
+                })?
+            }
+        };
+
+        Ok(Value::Boolean(f(&calendar, date)))
+    })
+}
+
+fn device_to_eval_context_values(device: &Device) -> Vec<(String, serde_json::Value)> {
+    let prefix = format!(
+        "devices.{}.{}",
+        device.integration_id,
+        name_to_evalexpr(&device.name)
+    );
+
+    value_kv_pairs_deep(&device.get_value(), prefix)
+}
+
+/// Expression context values of the device states of a scene, keyed by the
+/// current names of the devices
+fn scene_to_eval_context_values(
+    scene_id: &SceneId,
+    scene: &FlattenedSceneConfig,
+    devices: &DevicesState,
+) -> Result<Vec<(String, serde_json::Value)>> {
+    let prefix = format!("scenes.{}", name_to_evalexpr(&scene_id.to_string()));
+    let mut values = vec![];
+
+    for (device_key, state) in &scene.devices.0 {
+        let device = devices.0.get(device_key);
+
+        let Some(device) = device else {
+            continue;
+        };
+
+        let integration_id = &device.integration_id;
+        let name = name_to_evalexpr(&device.name.to_lowercase());
+        let prefix = format!("{prefix}.{integration_id}.{name}");
+
+        let value = serde_json::to_value(state)?;
+        values.extend(value_kv_pairs_deep(&value, prefix));
+    }
+
+    Ok(values)
+}
+
+fn set_context_values(
+    context: &mut HashMapContext,
+    values: Vec<(String, serde_json::Value)>,
+) -> Result<()> {
+    for (key, value) in values {
+        let value = serde_value_to_evalexpr(&value)?;
+        context.set_value(key, value)?;
+    }
+
+    Ok(())
+}
+
 pub fn state_to_eval_context(
     devices: &DevicesState,
     flattened_scenes: &FlattenedScenesConfig,
     flattened_groups: &FlattenedGroupsConfig,
-) -> Result<HashMapContext> {
+    calendar: &Calendar,
+) -> Result<(HashMapContext, SceneVariables)> {
     let mut context = HashMapContext::new();
     context.set_type_safety_checks_disabled(true)?;
 
     for device in devices.0.values() {
-        let root_value = device.get_value();
-        let prefix = format!(
-            "devices.{}.{}",
-            device.integration_id,
-            name_to_evalexpr(&device.name)
-        );
-        let values = value_kv_pairs_deep(&root_value, prefix);
-
-        for (key, value) in values {
-            let value = serde_value_to_evalexpr(&value).unwrap();
-            context.set_value(key, value)?;
-        }
+        set_context_values(&mut context, device_to_eval_context_values(device))?;
     }
 
-    for (scene_id, scene) in &flattened_scenes.0 {
-        let prefix = format!("scenes.{}", name_to_evalexpr(&scene_id.to_string()));
+    let mut scene_variables = SceneVariables::new();
 
-        for (device_key, state) in &scene.devices.0 {
-            let device = devices.0.get(device_key);
-
-            let Some(device) = device else {
-                continue;
-            };
-
-            let integration_id = &device.integration_id;
-            let name = name_to_evalexpr(&device.name.to_lowercase());
-            let prefix = format!("{prefix}.{integration_id}.{name}");
-
-            let value = serde_json::to_value(state)?;
-            let values = value_kv_pairs_deep(&value, prefix.clone());
-
-            for (key, value) in values {
-                let value = serde_value_to_evalexpr(&value)?;
-                context.set_value(key, value)?;
-            }
-        }
+    for (scene_id, scene) in &flattened_scenes.0 {
+        let values = scene_to_eval_context_values(scene_id, scene, devices)?;
+        let keys = values.iter().map(|(key, _)| key.clone()).collect();
+        scene_variables.insert(scene_id.clone(), keys);
+        set_context_values(&mut context, values)?;
     }
 
     let group_eval_context_values =
         flattened_groups_to_eval_context_values(flattened_groups, devices);
-
-    for (key, value) in group_eval_context_values {
-        let value = serde_value_to_evalexpr(&value)?;
-        context.set_value(key, value)?;
-    }
+    set_context_values(&mut context, group_eval_context_values)?;
 
     context.set_function("dbg".into(), {
         let context = context.clone();
@@ -154,7 +202,16 @@ pub fn state_to_eval_context(
         })
     })?;
 
-    Ok(context)
+    context.set_function(
+        "is_holiday".into(),
+        calendar_function(calendar, Calendar::is_holiday),
+    )?;
+    context.set_function(
+        "is_workday".into(),
+        calendar_function(calendar, Calendar::is_workday),
+    )?;
+
+    Ok((context, scene_variables))
 }
 
 fn tuple_value_to_vec_string(value: &Value) -> EvalexprResult<Vec<String>> {
@@ -327,6 +384,9 @@ pub fn eval_action_expr(
                     scene_id,
                     device_keys: None,
                     group_keys,
+                    transition_ms: None,
+                    brightness: None,
+                    override_window: None,
                 })
             }
             EvalExprAction::Custom(integration_id, payload) => {
@@ -363,6 +423,9 @@ pub fn eval_action_expr(
                 scene_id,
                 device_keys: Some(vec![device.get_device_key()]),
                 group_keys: None,
+                transition_ms: None,
+                brightness: None,
+                override_window: None,
             })));
         }
     }
@@ -394,12 +457,22 @@ pub fn debug_print_context(context: &HashMapContext) {
 #[derive(Clone)]
 pub struct Expr {
     context: HashMapContext,
+    calendar: Calendar,
+    scene_variables: SceneVariables,
+}
+
+impl Default for Expr {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
 }
 
 impl Expr {
-    pub fn new() -> Self {
+    pub fn new(calendar: Calendar) -> Self {
         Expr {
             context: HashMapContext::new(),
+            calendar,
+            scene_variables: Default::default(),
         }
     }
 
@@ -407,12 +480,16 @@ impl Expr {
         &self.context
     }
 
+    pub fn get_calendar(&self) -> &Calendar {
+        &self.calendar
+    }
+
     pub fn recompute(
         &self,
         devices_state: &DevicesState,
         groups: &Groups,
         scenes: &Scenes,
-    ) -> HashMapContext {
+    ) -> (HashMapContext, SceneVariables) {
         // TODO: decide whether we want to support scene expressions that reference
         // other scenes with expressions
 
@@ -420,13 +497,102 @@ impl Expr {
         let flattened_scenes = scenes.get_flattened_scenes();
         let flattened_groups = groups.get_flattened_groups();
 
-        state_to_eval_context(devices_state, flattened_scenes, flattened_groups)
-            .expect("Failed to create eval context")
+        state_to_eval_context(
+            devices_state,
+            flattened_scenes,
+            flattened_groups,
+            &self.calendar,
+        )
+        .expect("Failed to create eval context")
     }
 
     pub fn invalidate(&mut self, devices_state: &DevicesState, groups: &Groups, scenes: &Scenes) {
-        let context = self.recompute(devices_state, groups, scenes);
+        let (context, scene_variables) = self.recompute(devices_state, groups, scenes);
         self.context = context;
+        self.scene_variables = scene_variables;
+    }
+
+    /// Updates the context after a device has changed, only recomputing
+    /// values that depend on it: those of the device itself, of groups
+    /// containing it and of the given invalidated scenes. Everything is
+    /// recomputed for new or renamed devices, as values are keyed by device
+    /// name. Note that `dbg()` keeps printing the context as of the last full
+    /// recompute.
+    pub fn invalidate_device(
+        &mut self,
+        old: Option<&Device>,
+        new: &Device,
+        devices_state: &DevicesState,
+        groups: &Groups,
+        scenes: &Scenes,
+        invalidated_scenes: &HashSet<SceneId>,
+    ) {
+        if old.map_or(true, |old| old.name != new.name) {
+            self.invalidate(devices_state, groups, scenes);
+            return;
+        }
+
+        let result =
+            self.update_device(old, new, devices_state, groups, scenes, invalidated_scenes);
+
+        if let Err(e) = result {
+            error!("Failed to update eval context of {}: {}", new.name, e);
+            self.invalidate(devices_state, groups, scenes);
+        }
+    }
+
+    fn update_device(
+        &mut self,
+        old: Option<&Device>,
+        new: &Device,
+        devices_state: &DevicesState,
+        groups: &Groups,
+        scenes: &Scenes,
+        invalidated_scenes: &HashSet<SceneId>,
+    ) -> Result<()> {
+        let values = device_to_eval_context_values(new);
+
+        // Values the device no longer has read as empty, like null values
+        let keys: HashSet<&String> = values.iter().map(|(key, _)| key).collect();
+        let stale_keys: Vec<String> = old
+            .map(device_to_eval_context_values)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(key, _)| key)
+            .filter(|key| !keys.contains(key))
+            .collect();
+
+        for key in stale_keys {
+            self.context.set_value(key, Value::Empty)?;
+        }
+        set_context_values(&mut self.context, values)?;
+
+        let device_key = new.get_device_key();
+        for (group_id, group) in &groups.get_flattened_groups().0 {
+            if group.device_ids.contains(&device_key) {
+                let values = group_to_eval_context_values(group_id, group, devices_state);
+                set_context_values(&mut self.context, values)?;
+            }
+        }
+
+        for scene_id in invalidated_scenes {
+            let values = match scenes.get_flattened_scenes().0.get(scene_id) {
+                Some(scene) => scene_to_eval_context_values(scene_id, scene, devices_state)?,
+                None => vec![],
+            };
+
+            // Clear states of devices no longer in the scene
+            let keys: HashSet<String> = values.iter().map(|(key, _)| key.clone()).collect();
+            let prev_keys = self.scene_variables.remove(scene_id).unwrap_or_default();
+            for key in prev_keys.difference(&keys) {
+                self.context.set_value(key.clone(), Value::Empty)?;
+            }
+
+            self.scene_variables.insert(scene_id.clone(), keys);
+            set_context_values(&mut self.context, values)?;
+        }
+
+        Ok(())
     }
 }
 
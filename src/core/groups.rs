@@ -1,9 +1,20 @@
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::str::FromStr;
+
+use ordered_float::OrderedFloat;
 
 use crate::{
     types::{
-        device::{Device, DeviceRef, DevicesState},
-        group::{FlattenedGroupConfig, FlattenedGroupsConfig, GroupConfig, GroupId, GroupsConfig},
+        color::{Capabilities, DeviceColor},
+        device::{
+            ControllableDevice, ControllableState, Device, DeviceData, DeviceId, DeviceKey,
+            DeviceRef, DevicesState, ManageKind,
+        },
+        group::{
+            FlattenedGroupConfig, FlattenedGroupsConfig, GroupConfig, GroupId, GroupsConfig,
+            GROUPS_INTEGRATION_ID,
+        },
+        integration::IntegrationId,
     },
     utils::keys_match,
 };
@@ -48,6 +59,33 @@ fn eval_group_config_device_refs(
         .collect()
 }
 
+/// Collects the device refs excluded from given group, including exclusions
+/// declared by any linked groups
+fn eval_group_config_excluded_refs(
+    group: &GroupConfig,
+    groups: &GroupsConfig,
+) -> BTreeSet<DeviceRef> {
+    group
+        .exclude
+        .clone()
+        .unwrap_or_default()
+        .into_iter()
+        .chain(
+            group
+                .groups
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .flat_map(|group_link| {
+                    let group = groups.get(&group_link.group_id);
+                    group
+                        .map(|group| eval_group_config_excluded_refs(group, groups))
+                        .unwrap_or_default()
+                }),
+        )
+        .collect()
+}
+
 type DeviceRefsByGroups = BTreeMap<GroupId, BTreeSet<DeviceRef>>;
 fn mk_device_refs_by_groups(config: &GroupsConfig) -> DeviceRefsByGroups {
     config
@@ -73,6 +111,14 @@ fn mk_flattened_groups(
                 .get(group_id)
                 .expect("Expected to find group with id from device_refs_by_groups");
 
+            // Exclusions are resolved against current devices, so that a
+            // device referenced by name can be excluded by id and vice versa
+            let excluded_keys: BTreeSet<DeviceKey> = eval_group_config_excluded_refs(group, config)
+                .iter()
+                .filter_map(|device_ref| devices.get_device_by_ref(device_ref))
+                .map(|device| device.get_device_key())
+                .collect();
+
             (
                 group_id.clone(),
                 FlattenedGroupConfig {
@@ -81,6 +127,7 @@ fn mk_flattened_groups(
                         .iter()
                         .filter_map(|device_ref| devices.get_device_by_ref(device_ref))
                         .map(|device| device.get_device_key())
+                        .filter(|device_key| !excluded_keys.contains(device_key))
                         .collect(),
                     hidden: group.hidden,
                 },
@@ -98,52 +145,156 @@ pub fn flattened_groups_to_eval_context_values(
     flattened_config
         .0
         .iter()
-        .flat_map(|(group_id, group)| {
-            let group_devices: Vec<&Device> = group
-                .device_ids
-                .iter()
-                .filter_map(|device_key| devices.0.get(device_key))
-                .collect();
+        .flat_map(|(group_id, group)| group_to_eval_context_values(group_id, group, devices))
+        .collect()
+}
 
-            let all_devices_powered_on = group_devices
-                .iter()
-                .all(|device| device.is_powered_on() == Some(true));
-
-            let first_group_device = group_devices.first();
-
-            // group_scene_id is set only if all devices have the same scene activated
-            let group_scene_id = {
-                let first_device_scene_id = first_group_device.and_then(|d| d.get_scene());
-                if group_devices
-                    .iter()
-                    .all(|device| device.get_scene() == first_device_scene_id)
-                {
-                    first_device_scene_id
-                } else {
-                    None
-                }
-            };
-
-            let prefix = format!("groups.{}", group_id);
-
-            vec![
-                (
-                    format!("{}.name", prefix),
-                    serde_json::Value::String(group.name.clone()),
-                ),
-                (
-                    format!("{}.power", prefix),
-                    serde_json::Value::Bool(all_devices_powered_on),
-                ),
-                (
-                    format!("{}.scene_id", prefix),
-                    group_scene_id
-                        .map(|id| serde_json::Value::String(id.to_string()))
-                        .unwrap_or_else(|| serde_json::Value::Null),
-                ),
-            ]
+/// Expression context values of a single group, which depend on the state of
+/// its devices
+pub fn group_to_eval_context_values(
+    group_id: &GroupId,
+    group: &FlattenedGroupConfig,
+    devices: &DevicesState,
+) -> Vec<(String, serde_json::Value)> {
+    let group_devices: Vec<&Device> = group
+        .device_ids
+        .iter()
+        .filter_map(|device_key| devices.0.get(device_key))
+        .collect();
+
+    let all_devices_powered_on = group_devices
+        .iter()
+        .all(|device| device.is_powered_on() == Some(true));
+
+    let first_group_device = group_devices.first();
+
+    // group_scene_id is set only if all devices have the same scene activated
+    let group_scene_id = {
+        let first_device_scene_id = first_group_device.and_then(|d| d.get_scene());
+        if group_devices
+            .iter()
+            .all(|device| device.get_scene() == first_device_scene_id)
+        {
+            first_device_scene_id
+        } else {
+            None
+        }
+    };
+
+    let prefix = format!("groups.{}", group_id);
+
+    vec![
+        (
+            format!("{}.name", prefix),
+            serde_json::Value::String(group.name.clone()),
+        ),
+        (
+            format!("{}.power", prefix),
+            serde_json::Value::Bool(all_devices_powered_on),
+        ),
+        (
+            format!("{}.scene_id", prefix),
+            group_scene_id
+                .map(|id| serde_json::Value::String(id.to_string()))
+                .unwrap_or_else(|| serde_json::Value::Null),
+        ),
+    ]
+}
+
+/// Computes the aggregate state of a group as a pseudo-device, so that UIs
+/// can render one tile per group.
+///
+/// The group is powered on if any member is, brightness is the mean of
+/// powered on members, and color is the most common color among powered on
+/// members.
+pub fn mk_group_device(
+    group_id: &GroupId,
+    group: &FlattenedGroupConfig,
+    devices: &DevicesState,
+) -> Device {
+    let members: Vec<&ControllableDevice> = group
+        .device_ids
+        .iter()
+        .filter_map(|device_key| devices.0.get(device_key))
+        .filter_map(|device| match &device.data {
+            DeviceData::Controllable(controllable) => Some(controllable),
+            DeviceData::Sensor(_) => None,
         })
-        .collect()
+        .collect();
+
+    let powered_on: Vec<&ControllableDevice> = members
+        .iter()
+        .filter(|member| member.state.power)
+        .copied()
+        .collect();
+
+    let brightnesses: Vec<f32> = powered_on
+        .iter()
+        .filter_map(|member| member.state.brightness.map(|b| b.0))
+        .collect();
+    let brightness = (!brightnesses.is_empty())
+        .then(|| brightnesses.iter().sum::<f32>() / brightnesses.len() as f32);
+
+    let mut color_counts: HashMap<&DeviceColor, usize> = HashMap::new();
+    for color in powered_on
+        .iter()
+        .filter_map(|member| member.state.color.as_ref())
+    {
+        *color_counts.entry(color).or_default() += 1;
+    }
+    let color = color_counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(color, _)| color.clone());
+
+    // Group scene is set only if all members have the same scene activated
+    let first_scene = members.first().and_then(|member| member.scene.clone());
+    let scene = members
+        .iter()
+        .all(|member| member.scene == first_scene)
+        .then_some(first_scene)
+        .flatten();
+
+    let capabilities = members.iter().fold(Capabilities::default(), |acc, member| {
+        let caps = &member.capabilities;
+        Capabilities {
+            xy: acc.xy || caps.xy,
+            hs: acc.hs || caps.hs,
+            rgb: acc.rgb || caps.rgb,
+            rgbw: acc.rgbw || caps.rgbw,
+            rgbww: acc.rgbww || caps.rgbww,
+            ct: match (acc.ct, &caps.ct) {
+                (Some(a), Some(b)) => Some(a.start.min(b.start)..a.end.max(b.end)),
+                (a, b) => a.or_else(|| b.clone()),
+            },
+            segments: acc.segments.max(caps.segments),
+        }
+    });
+
+    Device::new(
+        IntegrationId::from_str(GROUPS_INTEGRATION_ID).unwrap(),
+        DeviceId::new(&group_id.to_string()),
+        group.name.clone(),
+        DeviceData::Controllable(ControllableDevice {
+            scene,
+            state: ControllableState {
+                power: !powered_on.is_empty(),
+                brightness: brightness.map(OrderedFloat),
+                color,
+                segments: None,
+                transition_ms: None,
+            },
+            capabilities,
+            managed: ManageKind::Unmanaged,
+        }),
+    )
+}
+
+/// Returns the group represented by given device key, if it belongs to a group
+/// pseudo-device
+pub fn group_id_from_device_key(device_key: &DeviceKey) -> Option<GroupId> {
+    (device_key.integration_id.to_string() == GROUPS_INTEGRATION_ID)
+        .then(|| GroupId(device_key.device_id.to_string()))
 }
 
 impl Groups {
@@ -157,6 +308,14 @@ impl Groups {
         }
     }
 
+    /// Replaces the groups config after the config file has changed
+    pub fn reload_config(&mut self, config: GroupsConfig, devices: &Devices) {
+        self.device_refs_by_groups = mk_device_refs_by_groups(&config);
+        self.config = config;
+        self.flattened_groups =
+            mk_flattened_groups(&self.config, &self.device_refs_by_groups, devices);
+    }
+
     /// Returns a flattened version of the groups config, with any contained
     /// groups expanded.
     pub fn get_flattened_groups(&self) -> &FlattenedGroupsConfig {
@@ -180,6 +339,20 @@ impl Groups {
             .collect()
     }
 
+    /// Returns aggregate pseudo-devices for all groups, see [mk_group_device]
+    pub fn get_group_devices(&self, devices: &DevicesState) -> DevicesState {
+        DevicesState(
+            self.flattened_groups
+                .0
+                .iter()
+                .map(|(group_id, group)| {
+                    let device = mk_group_device(group_id, group, devices);
+                    (device.get_device_key(), device)
+                })
+                .collect(),
+        )
+    }
+
     pub fn invalidate(
         &mut self,
         old_state: &DevicesState,
@@ -222,6 +395,7 @@ mod eval_group_config_device_links_tests {
             devices: Some(vec![device1.clone(), device2.clone()]),
             groups: None,
             hidden: None,
+            exclude: None,
         };
 
         let result = eval_group_config_device_refs(&group_config, &GroupsConfig::new());
@@ -250,6 +424,7 @@ mod eval_group_config_device_links_tests {
                 group_id: GroupId::from_str("test_group_2").unwrap(),
             }]),
             hidden: None,
+            exclude: None,
         };
 
         let mut groups_config = GroupsConfig::new();
@@ -260,6 +435,7 @@ mod eval_group_config_device_links_tests {
                 devices: Some(vec![device1.clone(), device2.clone()]),
                 groups: None,
                 hidden: None,
+                exclude: None,
             },
         );
 
@@ -289,6 +465,7 @@ mod eval_group_config_device_links_tests {
                 group_id: GroupId::from_str("test_group_2").unwrap(),
             }]),
             hidden: None,
+            exclude: None,
         };
 
         let mut groups_config = GroupsConfig::new();
@@ -299,6 +476,7 @@ mod eval_group_config_device_links_tests {
                 devices: Some(vec![device2.clone()]),
                 groups: None,
                 hidden: None,
+                exclude: None,
             },
         );
 
@@ -308,4 +486,103 @@ mod eval_group_config_device_links_tests {
         assert!(result.contains(&device1));
         assert!(result.contains(&device2));
     }
+
+    #[test]
+    fn test_eval_group_config_excluded_refs_with_linked_groups() {
+        let device1 = DeviceRef::new_with_id(
+            IntegrationId::from_str("test_integration").unwrap(),
+            DeviceId::from_str("test_device1").unwrap(),
+        );
+
+        let device2 = DeviceRef::new_with_id(
+            IntegrationId::from_str("test_integration").unwrap(),
+            DeviceId::from_str("test_device2").unwrap(),
+        );
+
+        let group_config = GroupConfig {
+            name: "Test Group 1".to_string(),
+            devices: None,
+            groups: Some(vec![GroupLink {
+                group_id: GroupId::from_str("test_group_2").unwrap(),
+            }]),
+            hidden: None,
+            exclude: Some(vec![device1.clone()]),
+        };
+
+        let mut groups_config = GroupsConfig::new();
+        groups_config.insert(
+            GroupId::from_str("test_group_2").unwrap(),
+            GroupConfig {
+                name: "Test Group 2".to_string(),
+                devices: None,
+                groups: None,
+                hidden: None,
+                exclude: Some(vec![device2.clone()]),
+            },
+        );
+
+        let result = eval_group_config_excluded_refs(&group_config, &groups_config);
+
+        assert_eq!(result.len(), 2);
+        assert!(result.contains(&device1));
+        assert!(result.contains(&device2));
+    }
+}
+
+#[cfg(test)]
+mod mk_group_device_tests {
+    use crate::types::device::ControllableDevice;
+
+    use super::*;
+
+    fn mk_light(id: &str, power: bool, brightness: f32, color: DeviceColor) -> Device {
+        Device::new(
+            IntegrationId::from_str("test_integration").unwrap(),
+            DeviceId::new(id),
+            id.to_string(),
+            DeviceData::Controllable(ControllableDevice::new(
+                None,
+                power,
+                Some(brightness),
+                Some(color),
+                None,
+                Capabilities::default(),
+                ManageKind::Full,
+            )),
+        )
+    }
+
+    #[test]
+    fn test_aggregates_powered_on_members() {
+        let devices = [
+            mk_light("a", true, 0.2, DeviceColor::new_from_ct(2700)),
+            mk_light("b", true, 0.6, DeviceColor::new_from_ct(2700)),
+            mk_light("c", true, 1.0, DeviceColor::new_from_ct(4000)),
+            mk_light("d", false, 1.0, DeviceColor::new_from_ct(4000)),
+        ];
+
+        let group = FlattenedGroupConfig {
+            name: "Living room".to_string(),
+            device_ids: devices.iter().map(|d| d.get_device_key()).collect(),
+            hidden: None,
+        };
+        let devices = DevicesState(
+            devices
+                .into_iter()
+                .map(|d| (d.get_device_key(), d))
+                .collect(),
+        );
+
+        let group_device =
+            mk_group_device(&GroupId::from_str("living_room").unwrap(), &group, &devices);
+        let state = group_device.get_controllable_state().unwrap();
+
+        assert_eq!(
+            group_id_from_device_key(&group_device.get_device_key()),
+            Some(GroupId::from_str("living_room").unwrap())
+        );
+        assert!(state.power);
+        assert!((state.brightness.unwrap().0 - 0.6).abs() < 1e-6);
+        assert_eq!(state.color, Some(DeviceColor::new_from_ct(2700)));
+    }
 }
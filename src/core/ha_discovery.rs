@@ -0,0 +1,544 @@
+//! Publishes devices to an MQTT broker following Home Assistant's MQTT
+//! discovery convention, so that they show up in Home Assistant without any
+//! configuration there. Lights can be controlled from Home Assistant.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use color_eyre::Result;
+use eyre::eyre;
+use ordered_float::OrderedFloat;
+use rand::{distributions::Alphanumeric, Rng};
+use rumqttc::{AsyncClient, EventLoop, LastWill, MqttOptions, QoS};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use crate::types::{
+    action::Action,
+    color::DeviceColor,
+    device::{
+        ControllableDevice, ControllableStateUpdate, Device, DeviceData, DeviceKey, SensorDevice,
+    },
+    dim::UpdateDeviceStateDescriptor,
+    event::{Message, TxEventChannel},
+    ha_discovery::HaDiscoveryConfig,
+};
+
+const DEFAULT_PORT: u16 = 1883;
+const DEFAULT_DISCOVERY_PREFIX: &str = "homeassistant";
+const DEFAULT_TOPIC_PREFIX: &str = "homectl";
+
+/// Device updates published while the connection can't keep up are dropped
+const CHANNEL_CAPACITY: usize = 1024;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub struct HaDiscovery {
+    tx: Option<mpsc::Sender<Device>>,
+}
+
+impl HaDiscovery {
+    pub fn new(config: Option<HaDiscoveryConfig>, event_tx: TxEventChannel) -> Self {
+        let Some(config) = config else {
+            return HaDiscovery { tx: None };
+        };
+
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        tokio::spawn(run(config, rx, event_tx));
+
+        HaDiscovery { tx: Some(tx) }
+    }
+
+    /// Publishes discovery info and state of changed devices
+    pub fn publish_message(&self, msg: &Message) {
+        let (Some(tx), Message::InternalStateUpdate { new, .. }) = (&self.tx, msg) else {
+            return;
+        };
+
+        if tx.try_send(new.clone()).is_err() {
+            debug!("Home Assistant discovery is not keeping up, dropping update");
+        }
+    }
+}
+
+struct Topics {
+    discovery_prefix: String,
+    topic_prefix: String,
+}
+
+impl Topics {
+    fn new(config: &HaDiscoveryConfig) -> Self {
+        Topics {
+            discovery_prefix: config
+                .discovery_prefix
+                .clone()
+                .unwrap_or_else(|| DEFAULT_DISCOVERY_PREFIX.to_string()),
+            topic_prefix: config
+                .topic_prefix
+                .clone()
+                .unwrap_or_else(|| DEFAULT_TOPIC_PREFIX.to_string()),
+        }
+    }
+
+    fn availability(&self) -> String {
+        format!("{}/availability", self.topic_prefix)
+    }
+
+    fn state(&self, object_id: &str) -> String {
+        format!("{}/{}/state", self.topic_prefix, object_id)
+    }
+
+    fn command(&self, object_id: &str) -> String {
+        format!("{}/{}/set", self.topic_prefix, object_id)
+    }
+
+    fn discovery(&self, component: &str, object_id: &str) -> String {
+        format!(
+            "{}/{}/{}/config",
+            self.discovery_prefix, component, object_id
+        )
+    }
+
+    /// Returns the object id of a command topic
+    fn parse_command<'a>(&self, topic: &'a str) -> Option<&'a str> {
+        topic
+            .strip_prefix(&self.topic_prefix)?
+            .strip_prefix('/')?
+            .strip_suffix("/set")
+    }
+}
+
+/// Topic level identifying the device, restricted to characters that Home
+/// Assistant accepts in object ids
+fn object_id(key: &DeviceKey) -> String {
+    format!("{}_{}", key.integration_id, key.device_id)
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() || c == '-' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn kelvin_to_mireds(kelvin: u64) -> u64 {
+    1_000_000 / kelvin.max(1)
+}
+
+/// Color modes in Home Assistant terms, colors are always exchanged as xy
+fn supported_color_modes(device: &ControllableDevice) -> Vec<&'static str> {
+    let capabilities = &device.capabilities;
+    let mut modes = vec![];
+
+    if capabilities.xy
+        || capabilities.hs
+        || capabilities.rgb
+        || capabilities.rgbw
+        || capabilities.rgbww
+    {
+        modes.push("xy");
+    }
+    if capabilities.ct.is_some() {
+        modes.push("color_temp");
+    }
+
+    if modes.is_empty() {
+        if device.state.brightness.is_some() {
+            modes.push("brightness");
+        } else {
+            modes.push("onoff");
+        }
+    }
+
+    modes
+}
+
+/// Discovery topic and config of given device, None for devices that can't
+/// be represented in Home Assistant
+fn discovery_config(device: &Device, topics: &Topics) -> Option<(String, Value)> {
+    let object_id = object_id(&device.get_device_key());
+    let unique_id = format!("homectl_{}", object_id);
+
+    let mut config = json!({
+        "name": null,
+        "unique_id": unique_id,
+        "object_id": object_id,
+        "state_topic": topics.state(&object_id),
+        "availability_topic": topics.availability(),
+        "device": {
+            "identifiers": [unique_id],
+            "name": device.name,
+            "manufacturer": "homectl",
+            "model": device.integration_id.to_string(),
+        },
+    });
+
+    let component = match &device.data {
+        DeviceData::Controllable(controllable) => {
+            config["schema"] = json!("json");
+            config["command_topic"] = json!(topics.command(&object_id));
+            config["supported_color_modes"] = json!(supported_color_modes(controllable));
+
+            if let Some(ct) = &controllable.capabilities.ct {
+                config["min_mireds"] = json!(kelvin_to_mireds(ct.end as u64));
+                config["max_mireds"] = json!(kelvin_to_mireds(ct.start as u64));
+            }
+
+            "light"
+        }
+        DeviceData::Sensor(SensorDevice::Boolean { .. }) => "binary_sensor",
+        DeviceData::Sensor(SensorDevice::Number { unit, .. }) => {
+            if let Some(unit) = unit {
+                config["unit_of_measurement"] = json!(unit);
+            }
+            config["state_class"] = json!("measurement");
+
+            "sensor"
+        }
+        DeviceData::Sensor(SensorDevice::Text { .. }) => "sensor",
+        DeviceData::Sensor(SensorDevice::Color(_)) => return None,
+    };
+
+    Some((topics.discovery(component, &object_id), config))
+}
+
+/// State payload of given device, in the format its discovery config
+/// announces
+fn state_payload(device: &Device) -> String {
+    match &device.data {
+        DeviceData::Controllable(controllable) => {
+            let state = &controllable.state;
+            let mut payload = json!({ "state": if state.power { "ON" } else { "OFF" } });
+
+            if let Some(brightness) = state.brightness {
+                payload["brightness"] = json!((brightness.0 * 255.0).round() as u8);
+            }
+
+            let modes = supported_color_modes(controllable);
+
+            match &state.color {
+                Some(DeviceColor::Ct(ct)) if modes.contains(&"color_temp") => {
+                    payload["color_mode"] = json!("color_temp");
+                    payload["color_temp"] = json!(kelvin_to_mireds(ct.ct));
+                }
+                Some(color) if modes.contains(&"xy") => {
+                    let yxy: palette::Yxy = color.into();
+                    payload["color_mode"] = json!("xy");
+                    payload["color"] = json!({ "x": yxy.x, "y": yxy.y });
+                }
+                _ => {}
+            }
+
+            payload.to_string()
+        }
+        DeviceData::Sensor(SensorDevice::Boolean { value }) => {
+            String::from(if *value { "ON" } else { "OFF" })
+        }
+        DeviceData::Sensor(SensorDevice::Number { value, .. }) => value.to_string(),
+        DeviceData::Sensor(SensorDevice::Text { value }) => value.clone(),
+        DeviceData::Sensor(SensorDevice::Color(_)) => String::new(),
+    }
+}
+
+#[derive(Deserialize)]
+struct CommandColor {
+    x: f32,
+    y: f32,
+}
+
+/// Command sent by Home Assistant to a light using the JSON schema
+#[derive(Deserialize)]
+struct LightCommand {
+    state: Option<String>,
+    brightness: Option<f32>,
+    color: Option<CommandColor>,
+    color_temp: Option<u64>,
+
+    /// Seconds
+    transition: Option<f32>,
+}
+
+fn parse_command(payload: &[u8]) -> Result<ControllableStateUpdate> {
+    let command: LightCommand = serde_json::from_slice(payload)?;
+
+    let power = match command.state.as_deref() {
+        Some("ON") => Some(true),
+        Some("OFF") => Some(false),
+        Some(state) => return Err(eyre!("Unknown state {}", state)),
+        None => None,
+    };
+
+    let color = match (command.color, command.color_temp) {
+        (Some(CommandColor { x, y }), _) => Some(DeviceColor::new_from_xy(x, y)),
+        (None, Some(mireds)) => Some(DeviceColor::new_from_ct(
+            kelvin_to_mireds(mireds).min(u16::MAX as u64) as u16,
+        )),
+        (None, None) => None,
+    };
+
+    Ok(ControllableStateUpdate {
+        power,
+        brightness: command
+            .brightness
+            .map(|brightness| OrderedFloat(brightness / 255.0)),
+        color,
+        segments: None,
+        transition_ms: command
+            .transition
+            .map(|transition| (transition * 1000.0) as u64),
+    })
+}
+
+type KnownDevices = Arc<Mutex<HashMap<String, Device>>>;
+
+async fn run(config: HaDiscoveryConfig, mut rx: mpsc::Receiver<Device>, event_tx: TxEventChannel) {
+    let topics = Arc::new(Topics::new(&config));
+
+    let random_string: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(8)
+        .map(char::from)
+        .collect();
+
+    let mut options = MqttOptions::new(
+        format!("homectl-ha-discovery-{}", random_string),
+        config.host.clone(),
+        config.port.unwrap_or(DEFAULT_PORT),
+    );
+    options.set_keep_alive(Duration::from_secs(5));
+    options.set_last_will(LastWill::new(
+        topics.availability(),
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let (Some(user), Some(password)) = (&config.user, &config.password) {
+        options.set_credentials(user, password);
+    }
+
+    let (client, eventloop) = AsyncClient::new(options, 10);
+    let devices: KnownDevices = Default::default();
+    let (connected_tx, mut connected_rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(poll_eventloop(
+        eventloop,
+        client.clone(),
+        topics.clone(),
+        devices.clone(),
+        connected_tx,
+        event_tx,
+    ));
+
+    loop {
+        let result = tokio::select! {
+            device = rx.recv() => {
+                let Some(device) = device else {
+                    return;
+                };
+
+                publish_device(&client, &topics, &devices, device).await
+            }
+            Some(()) = connected_rx.recv() => announce_all(&client, &topics, &devices).await,
+        };
+
+        if let Err(e) = result {
+            warn!("Error while publishing to Home Assistant: {:?}", e);
+        }
+    }
+}
+
+async fn publish(client: &AsyncClient, topic: String, payload: String) -> Result<()> {
+    client
+        .publish(topic, QoS::AtLeastOnce, true, payload)
+        .await?;
+
+    Ok(())
+}
+
+/// Publishes state of given device, preceded by its discovery config if that
+/// has changed
+async fn publish_device(
+    client: &AsyncClient,
+    topics: &Topics,
+    devices: &KnownDevices,
+    device: Device,
+) -> Result<()> {
+    let object_id = object_id(&device.get_device_key());
+    let config = discovery_config(&device, topics);
+
+    let prev = devices
+        .lock()
+        .unwrap()
+        .insert(object_id.clone(), device.clone());
+    let config_changed = prev.map_or(true, |prev| discovery_config(&prev, topics) != config);
+
+    let Some((discovery_topic, config)) = config else {
+        return Ok(());
+    };
+
+    if config_changed {
+        publish(client, discovery_topic, config.to_string()).await?;
+    }
+
+    publish(client, topics.state(&object_id), state_payload(&device)).await
+}
+
+/// Announces all known devices again, e.g. after reconnecting to the broker
+async fn announce_all(client: &AsyncClient, topics: &Topics, devices: &KnownDevices) -> Result<()> {
+    publish(client, topics.availability(), "online".to_string()).await?;
+
+    let devices: Vec<Device> = devices.lock().unwrap().values().cloned().collect();
+
+    for device in devices {
+        let Some((discovery_topic, config)) = discovery_config(&device, topics) else {
+            continue;
+        };
+
+        let object_id = object_id(&device.get_device_key());
+        publish(client, discovery_topic, config.to_string()).await?;
+        publish(client, topics.state(&object_id), state_payload(&device)).await?;
+    }
+
+    Ok(())
+}
+
+async fn poll_eventloop(
+    mut eventloop: EventLoop,
+    client: AsyncClient,
+    topics: Arc<Topics>,
+    devices: KnownDevices,
+    connected_tx: mpsc::UnboundedSender<()>,
+    event_tx: TxEventChannel,
+) {
+    loop {
+        match eventloop.poll().await {
+            Ok(rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_))) => {
+                info!("Connected to Home Assistant MQTT broker");
+
+                let filter = topics.command("+");
+                if let Err(e) = client.try_subscribe(filter, QoS::AtMostOnce) {
+                    warn!("Could not subscribe to Home Assistant commands: {:?}", e);
+                }
+
+                connected_tx.send(()).ok();
+            }
+            Ok(rumqttc::Event::Incoming(rumqttc::Packet::Publish(msg))) => {
+                let Some(object_id) = topics.parse_command(&msg.topic) else {
+                    continue;
+                };
+
+                let device_key = devices
+                    .lock()
+                    .unwrap()
+                    .get(object_id)
+                    .map(Device::get_device_key);
+                let Some(device_key) = device_key else {
+                    debug!(
+                        "Ignoring Home Assistant command to unknown device {}",
+                        object_id
+                    );
+                    continue;
+                };
+
+                match parse_command(&msg.payload) {
+                    Ok(state) => event_tx.send(Message::Action(Action::UpdateDeviceState(
+                        UpdateDeviceStateDescriptor {
+                            device_keys: Some(vec![device_key]),
+                            group_keys: None,
+                            state,
+                        },
+                    ))),
+                    Err(e) => warn!("Ignoring invalid command from Home Assistant: {}", e),
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!("Lost connection to Home Assistant MQTT broker: {:?}", e);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        color::Capabilities,
+        device::{DeviceId, ManageKind},
+        integration::IntegrationId,
+    };
+    use std::str::FromStr;
+
+    fn mk_topics() -> Topics {
+        Topics {
+            discovery_prefix: DEFAULT_DISCOVERY_PREFIX.to_string(),
+            topic_prefix: DEFAULT_TOPIC_PREFIX.to_string(),
+        }
+    }
+
+    fn mk_light() -> Device {
+        Device::new(
+            IntegrationId::from_str("hue").unwrap(),
+            DeviceId::new("lights/1"),
+            "Desk lamp".to_string(),
+            DeviceData::Controllable(ControllableDevice::new(
+                None,
+                true,
+                Some(0.5),
+                Some(DeviceColor::new_from_ct(2500)),
+                None,
+                Capabilities {
+                    ct: Some(2000..6500),
+                    ..Capabilities::singleton(crate::types::color::ColorMode::Xy)
+                },
+                ManageKind::Full,
+            )),
+        )
+    }
+
+    #[test]
+    fn test_discovery_config() {
+        let topics = mk_topics();
+        let (topic, config) = discovery_config(&mk_light(), &topics).unwrap();
+
+        assert_eq!(topic, "homeassistant/light/hue_lights_1/config");
+        assert_eq!(config["command_topic"], "homectl/hue_lights_1/set");
+        assert_eq!(config["supported_color_modes"], json!(["xy", "color_temp"]));
+        assert_eq!(config["min_mireds"], 153);
+        assert_eq!(config["max_mireds"], 500);
+        assert_eq!(
+            topics.parse_command("homectl/hue_lights_1/set"),
+            Some("hue_lights_1")
+        );
+    }
+
+    #[test]
+    fn test_state_payload() {
+        let payload: Value = serde_json::from_str(&state_payload(&mk_light())).unwrap();
+
+        assert_eq!(
+            payload,
+            json!({ "state": "ON", "brightness": 128, "color_mode": "color_temp", "color_temp": 400 })
+        );
+    }
+
+    #[test]
+    fn test_parse_command() {
+        let update = parse_command(
+            br#"{ "state": "ON", "brightness": 255, "color_temp": 250, "transition": 2 }"#,
+        )
+        .unwrap();
+
+        assert_eq!(update.power, Some(true));
+        assert_eq!(update.brightness, Some(OrderedFloat(1.0)));
+        assert_eq!(update.color, Some(DeviceColor::new_from_ct(4000)));
+        assert_eq!(update.transition_ms, Some(2000));
+
+        assert!(parse_command(br#"{ "state": "TOGGLE" }"#).is_err());
+    }
+}
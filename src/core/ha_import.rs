@@ -0,0 +1,452 @@
+//! Converts Home Assistant scene and group YAML into homectl config, easing
+//! migration from Home Assistant.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::BufRead;
+use std::str::FromStr;
+
+use color_eyre::Result;
+use eyre::Context;
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+use yaml_rust::{Yaml, YamlLoader};
+
+use crate::types::{
+    color::{Ct, DeviceColor, Hs, Rgb, Xy},
+    device::{DeviceNameRef, DeviceRef},
+    group::{GroupConfig, GroupId, GroupLink, GroupsConfig},
+    integration::IntegrationId,
+    scene::{
+        SceneConfig, SceneDeviceConfig, SceneDeviceState, SceneDevicesSearchConfig, SceneId,
+        ScenesConfig,
+    },
+};
+
+/// Entry of Home Assistant's `scenes.yaml`
+#[derive(Clone, Debug, Deserialize)]
+struct HaScene {
+    name: String,
+    entities: BTreeMap<String, HaEntityState>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+enum HaEntityState {
+    Power(bool),
+    State(String),
+    Attributes(HaEntityAttributes),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct HaEntityAttributes {
+    state: Option<String>,
+
+    /// 0 - 255
+    brightness: Option<f32>,
+
+    /// Mireds
+    color_temp: Option<f32>,
+    color_temp_kelvin: Option<f32>,
+
+    /// Hue in degrees, saturation in percent
+    hs_color: Option<(f32, f32)>,
+    xy_color: Option<(f32, f32)>,
+    rgb_color: Option<(u64, u64, u64)>,
+}
+
+/// Entry of Home Assistant's `groups.yaml`
+#[derive(Clone, Debug, Deserialize)]
+struct HaGroup {
+    name: Option<String>,
+    entities: Vec<String>,
+}
+
+/// Config produced by the importer, ready to be pasted into Settings.toml
+#[derive(Debug, Default, Serialize)]
+pub struct ImportedConfig {
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: GroupsConfig,
+
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub scenes: ScenesConfig,
+}
+
+fn yaml_to_json(yaml: &Yaml) -> serde_json::Value {
+    match yaml {
+        Yaml::Real(s) => s
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map_or(serde_json::Value::Null, serde_json::Value::Number),
+        Yaml::Integer(i) => serde_json::Value::from(*i),
+        Yaml::String(s) => serde_json::Value::from(s.clone()),
+        Yaml::Boolean(b) => serde_json::Value::from(*b),
+        Yaml::Array(array) => array.iter().map(yaml_to_json).collect(),
+        Yaml::Hash(hash) => hash
+            .iter()
+            .filter_map(|(key, value)| {
+                let key = match key {
+                    Yaml::String(s) | Yaml::Real(s) => s.clone(),
+                    Yaml::Integer(i) => i.to_string(),
+                    Yaml::Boolean(b) => b.to_string(),
+                    _ => return None,
+                };
+
+                Some((key, yaml_to_json(value)))
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+        Yaml::Alias(_) | Yaml::Null | Yaml::BadValue => serde_json::Value::Null,
+    }
+}
+
+fn parse_yaml<T: serde::de::DeserializeOwned>(yaml: &str) -> Result<T> {
+    let docs = YamlLoader::load_from_str(yaml)?;
+    let doc = docs.first().map(yaml_to_json).unwrap_or_default();
+
+    Ok(serde_json::from_value(doc)?)
+}
+
+/// Turns a Home Assistant name into an id usable as a homectl scene or group id
+fn slugify(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+fn parse_power(state: &str) -> Option<bool> {
+    match state {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn convert_entity_state(state: &HaEntityState) -> Option<SceneDeviceState> {
+    let attributes = match state {
+        HaEntityState::Power(power) => {
+            return Some(SceneDeviceState {
+                power: Some(*power),
+                color: None,
+                segments: None,
+                brightness: None,
+                transition_ms: None,
+                managed: None,
+            })
+        }
+        HaEntityState::State(state) => {
+            return parse_power(state).map(|power| SceneDeviceState {
+                power: Some(power),
+                color: None,
+                segments: None,
+                brightness: None,
+                transition_ms: None,
+                managed: None,
+            })
+        }
+        HaEntityState::Attributes(attributes) => attributes,
+    };
+
+    let power = match &attributes.state {
+        Some(state) => parse_power(state)?,
+        None => true,
+    };
+
+    let color = if let Some(kelvin) = attributes.color_temp_kelvin {
+        Some(DeviceColor::Ct(Ct { ct: kelvin as u64 }))
+    } else if let Some(mireds) = attributes.color_temp {
+        Some(DeviceColor::Ct(Ct {
+            ct: (1_000_000.0 / mireds).round() as u64,
+        }))
+    } else if let Some((h, s)) = attributes.hs_color {
+        Some(DeviceColor::Hs(Hs {
+            h: h.round() as u64,
+            s: OrderedFloat(s / 100.0),
+        }))
+    } else if let Some((x, y)) = attributes.xy_color {
+        Some(DeviceColor::Xy(Xy {
+            x: OrderedFloat(x),
+            y: OrderedFloat(y),
+        }))
+    } else {
+        attributes
+            .rgb_color
+            .map(|(r, g, b)| DeviceColor::Rgb(Rgb { r, g, b }))
+    };
+
+    let brightness = attributes
+        .brightness
+        .map(|brightness| OrderedFloat((brightness / 255.0 * 100.0).round() / 100.0));
+
+    Some(SceneDeviceState {
+        power: Some(power),
+        color,
+        segments: None,
+        brightness,
+        transition_ms: None,
+        managed: None,
+    })
+}
+
+/// Converts the contents of Home Assistant's `scenes.yaml`. `map_entity`
+/// resolves entity ids to homectl devices, entities it returns None for are
+/// left out.
+pub fn import_scenes(
+    yaml: &str,
+    map_entity: &mut impl FnMut(&str) -> Option<DeviceNameRef>,
+) -> Result<ScenesConfig> {
+    let ha_scenes: Vec<HaScene> = parse_yaml(yaml).wrap_err("Failed to parse scenes")?;
+    let mut scenes = ScenesConfig::new();
+
+    for ha_scene in ha_scenes {
+        let mut devices: BTreeMap<IntegrationId, BTreeMap<String, SceneDeviceConfig>> =
+            BTreeMap::new();
+
+        for (entity_id, state) in &ha_scene.entities {
+            let Some(state) = convert_entity_state(state) else {
+                warn!("Skipping {entity_id}, only on/off states can be imported");
+                continue;
+            };
+
+            let Some(device) = map_entity(entity_id) else {
+                continue;
+            };
+
+            devices
+                .entry(device.integration_id)
+                .or_default()
+                .insert(device.name, SceneDeviceConfig::DeviceState(state));
+        }
+
+        scenes.insert(
+            SceneId::new(slugify(&ha_scene.name)),
+            SceneConfig {
+                name: ha_scene.name,
+                devices: Some(SceneDevicesSearchConfig(devices)),
+                groups: None,
+                hidden: None,
+                exclude: None,
+                palette: None,
+                keyframes: None,
+                window: None,
+                expr: None,
+            },
+        );
+    }
+
+    Ok(scenes)
+}
+
+/// Converts the contents of Home Assistant's `groups.yaml`. Nested groups
+/// become group links, other entities are resolved with `map_entity`.
+pub fn import_groups(
+    yaml: &str,
+    map_entity: &mut impl FnMut(&str) -> Option<DeviceNameRef>,
+) -> Result<GroupsConfig> {
+    let ha_groups: BTreeMap<String, HaGroup> =
+        parse_yaml(yaml).wrap_err("Failed to parse groups")?;
+    let mut groups = GroupsConfig::new();
+
+    for (group_id, ha_group) in ha_groups {
+        let mut devices = vec![];
+        let mut links = vec![];
+
+        for entity_id in &ha_group.entities {
+            if let Some(linked_group_id) = entity_id.strip_prefix("group.") {
+                links.push(GroupLink {
+                    group_id: GroupId(linked_group_id.to_string()),
+                });
+            } else if let Some(device) = map_entity(entity_id) {
+                devices.push(DeviceRef::Name(device));
+            }
+        }
+
+        groups.insert(
+            GroupId(group_id.clone()),
+            GroupConfig {
+                name: ha_group.name.unwrap_or(group_id),
+                devices: (!devices.is_empty()).then_some(devices),
+                groups: (!links.is_empty()).then_some(links),
+                hidden: None,
+                exclude: None,
+            },
+        );
+    }
+
+    Ok(groups)
+}
+
+/// Asks the user which device an entity corresponds to, returns None if the
+/// entity should be skipped
+fn prompt_device(
+    entity_id: &str,
+    lines: &mut impl Iterator<Item = String>,
+) -> Option<DeviceNameRef> {
+    loop {
+        eprint!("Device for {entity_id} (integration_id/Device name, empty to skip): ");
+        let line = lines.next()?;
+        let line = line.trim();
+
+        if line.is_empty() {
+            return None;
+        }
+
+        match line.split_once('/') {
+            Some((integration_id, name)) if !name.is_empty() => {
+                return Some(DeviceNameRef {
+                    integration_id: IntegrationId::from_str(integration_id).unwrap(),
+                    name: name.to_string(),
+                })
+            }
+            _ => eprintln!("Expected integration_id/Device name, e.g. hue/Living room lamp"),
+        }
+    }
+}
+
+/// Runs the `import-ha` subcommand:
+///
+/// `homectl-server import-ha [--scenes scenes.yaml] [--groups groups.yaml]`
+///
+/// Entity ids are mapped to devices interactively, and the resulting config is
+/// printed to stdout.
+pub fn run_ha_import(args: &[String]) -> Result<()> {
+    let mut scenes_path = None;
+    let mut groups_path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let path = args
+            .next()
+            .ok_or_else(|| eyre!("Expected a path after {arg}"))?;
+
+        match arg.as_str() {
+            "--scenes" => scenes_path = Some(path),
+            "--groups" => groups_path = Some(path),
+            _ => return Err(eyre!("Unknown argument {arg}")),
+        }
+    }
+
+    if scenes_path.is_none() && groups_path.is_none() {
+        return Err(eyre!(
+            "Usage: homectl-server import-ha [--scenes scenes.yaml] [--groups groups.yaml]"
+        ));
+    }
+
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines().map_while(Result::ok);
+
+    // Each entity is only asked about once
+    let mut answers: HashMap<String, Option<DeviceNameRef>> = HashMap::new();
+    let mut map_entity = |entity_id: &str| {
+        answers
+            .entry(entity_id.to_string())
+            .or_insert_with(|| prompt_device(entity_id, &mut lines))
+            .clone()
+    };
+
+    let mut imported = ImportedConfig::default();
+
+    if let Some(path) = groups_path {
+        let yaml = std::fs::read_to_string(path).wrap_err_with(|| eyre!("Reading {path}"))?;
+        imported.groups = import_groups(&yaml, &mut map_entity)?;
+    }
+
+    if let Some(path) = scenes_path {
+        let yaml = std::fs::read_to_string(path).wrap_err_with(|| eyre!("Reading {path}"))?;
+        imported.scenes = import_scenes(&yaml, &mut map_entity)?;
+    }
+
+    println!("{}", toml::to_string(&imported)?);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map_lights(entity_id: &str) -> Option<DeviceNameRef> {
+        let name = entity_id.strip_prefix("light.")?;
+
+        Some(DeviceNameRef {
+            integration_id: IntegrationId::from_str("hue").unwrap(),
+            name: name.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_import_scenes() {
+        let yaml = r#"
+- id: "1612345678"
+  name: Movie night
+  entities:
+    light.sofa:
+      state: "on"
+      brightness: 51
+      color_temp: 370
+    light.ceiling: "off"
+    switch.fan: "on"
+"#;
+
+        let scenes = import_scenes(yaml, &mut map_lights).unwrap();
+        let scene = &scenes[&SceneId::new("movie_night".to_string())];
+        assert_eq!(scene.name, "Movie night");
+
+        let devices = &scene.devices.as_ref().unwrap().0[&IntegrationId::from_str("hue").unwrap()];
+        assert_eq!(devices.len(), 2);
+        assert_eq!(
+            devices["sofa"],
+            SceneDeviceConfig::DeviceState(SceneDeviceState {
+                power: Some(true),
+                color: Some(DeviceColor::Ct(Ct { ct: 2703 })),
+                segments: None,
+                brightness: Some(OrderedFloat(0.2)),
+                transition_ms: None,
+                managed: None,
+            })
+        );
+        assert_eq!(
+            devices["ceiling"],
+            SceneDeviceConfig::DeviceState(SceneDeviceState {
+                power: Some(false),
+                color: None,
+                segments: None,
+                brightness: None,
+                transition_ms: None,
+                managed: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_import_groups() {
+        let yaml = r#"
+living_room:
+  name: Living room
+  entities:
+    - light.sofa
+    - group.ceiling_lights
+    - sensor.temperature
+"#;
+
+        let groups = import_groups(yaml, &mut map_lights).unwrap();
+        let group = &groups[&GroupId("living_room".to_string())];
+
+        assert_eq!(group.name, "Living room");
+        assert_eq!(
+            group.devices,
+            Some(vec![DeviceRef::Name(DeviceNameRef {
+                integration_id: IntegrationId::from_str("hue").unwrap(),
+                name: "sofa".to_string(),
+            })])
+        );
+        assert_eq!(
+            group.groups,
+            Some(vec![GroupLink {
+                group_id: GroupId("ceiling_lights".to_string())
+            }])
+        );
+    }
+}
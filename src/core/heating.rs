@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{Datelike, NaiveTime, Timelike, Weekday};
+
+use crate::types::{
+    event::{Message, TxEventChannel},
+    heating::{HeatingConfig, HeatingScheduleEntry, HeatingZoneConfig, HeatingZoneId},
+    integration::IntegrationActionPayload,
+    mode::ModeId,
+};
+
+use super::clock::local_now;
+use super::integrations::Integrations;
+
+/// Mode in which zones use their away setpoint
+const AWAY_MODE: &str = "away";
+
+fn minute_of_week(weekday: Weekday, time: NaiveTime) -> u32 {
+    weekday.num_days_from_monday() * 24 * 60 + time.hour() * 60 + time.minute()
+}
+
+/// Returns the setpoint of the schedule entry that most recently took effect,
+/// wrapping around to the end of the previous week if needed
+fn scheduled_setpoint(
+    schedule: &[HeatingScheduleEntry],
+    weekday: Weekday,
+    time: NaiveTime,
+) -> Option<f64> {
+    let now = minute_of_week(weekday, time);
+
+    let entries: Vec<(u32, f64)> = schedule
+        .iter()
+        .flat_map(|entry| {
+            entry
+                .days
+                .iter()
+                .map(|day| (minute_of_week(*day, entry.at), entry.setpoint))
+        })
+        .collect();
+
+    let latest_before = entries
+        .iter()
+        .filter(|(minute, _)| *minute <= now)
+        .max_by_key(|(minute, _)| *minute);
+    let latest = entries.iter().max_by_key(|(minute, _)| *minute);
+
+    latest_before.or(latest).map(|(_, setpoint)| *setpoint)
+}
+
+fn render_payload(value: &serde_json::Value, setpoint: f64) -> serde_json::Value {
+    match value {
+        serde_json::Value::String(s) => {
+            serde_json::Value::String(s.replace("{setpoint}", &setpoint.to_string()))
+        }
+        serde_json::Value::Array(values) => values
+            .iter()
+            .map(|value| render_payload(value, setpoint))
+            .collect(),
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.clone(), render_payload(value, setpoint)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+fn zone_setpoint(
+    zone: &HeatingZoneConfig,
+    mode: &ModeId,
+    weekday: Weekday,
+    time: NaiveTime,
+) -> Option<f64> {
+    match zone.away_setpoint {
+        Some(away_setpoint) if mode.to_string() == AWAY_MODE => Some(away_setpoint),
+        _ => scheduled_setpoint(&zone.schedule, weekday, time),
+    }
+}
+
+/// Drives setpoints of heating zones according to weekly schedules
+#[derive(Clone, Default)]
+pub struct Heating {
+    config: HeatingConfig,
+    last_sent: HashMap<HeatingZoneId, f64>,
+}
+
+impl Heating {
+    pub fn new(config: HeatingConfig) -> Self {
+        Heating {
+            config,
+            last_sent: Default::default(),
+        }
+    }
+
+    /// Sends setpoints of zones whose setpoint has changed since last time
+    pub async fn refresh(&mut self, mode: &ModeId, integrations: &Integrations) {
+        let now = local_now();
+
+        for (zone_id, zone) in &self.config.zones {
+            let Some(setpoint) = zone_setpoint(zone, mode, now.weekday(), now.time()) else {
+                continue;
+            };
+
+            if self.last_sent.get(zone_id) == Some(&setpoint) {
+                continue;
+            }
+
+            info!("Setting heating zone {} to {}", zone_id, setpoint);
+
+            let payload = match render_payload(&zone.payload, setpoint) {
+                serde_json::Value::String(payload) => payload,
+                payload => payload.to_string(),
+            };
+
+            let result = integrations
+                .run_integration_action(
+                    &zone.integration_id,
+                    &IntegrationActionPayload::from(payload),
+                )
+                .await;
+
+            match result {
+                Ok(()) => {
+                    self.last_sent.insert(zone_id.clone(), setpoint);
+                }
+                Err(e) => error!("Failed to set heating zone {}: {:?}", zone_id, e),
+            }
+        }
+    }
+}
+
+/// Periodically applies heating schedules
+pub async fn refresh_heating(event_tx: TxEventChannel) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+        event_tx.send(Message::RefreshHeating);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    #[test]
+    fn test_scheduled_setpoint() {
+        let schedule = vec![
+            HeatingScheduleEntry {
+                days: vec![Weekday::Mon, Weekday::Tue],
+                at: time(6, 30),
+                setpoint: 21.0,
+            },
+            HeatingScheduleEntry {
+                days: vec![Weekday::Mon, Weekday::Tue],
+                at: time(22, 0),
+                setpoint: 17.0,
+            },
+        ];
+
+        assert_eq!(
+            scheduled_setpoint(&schedule, Weekday::Mon, time(12, 0)),
+            Some(21.0)
+        );
+        assert_eq!(
+            scheduled_setpoint(&schedule, Weekday::Tue, time(6, 0)),
+            Some(17.0)
+        );
+        // Wraps around to Tuesday evening of the previous week
+        assert_eq!(
+            scheduled_setpoint(&schedule, Weekday::Mon, time(6, 0)),
+            Some(17.0)
+        );
+        assert_eq!(
+            scheduled_setpoint(&schedule, Weekday::Sun, time(12, 0)),
+            Some(17.0)
+        );
+        assert_eq!(scheduled_setpoint(&[], Weekday::Sun, time(12, 0)), None);
+    }
+}
@@ -0,0 +1,180 @@
+//! Minimal HTTP client for talking to external services over http or https
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use color_eyre::Result;
+use eyre::eyre;
+use hyper::{client::conn, Body, Method, Request, Response, StatusCode, Uri};
+use once_cell::sync::OnceCell;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName},
+    TlsConnector,
+};
+
+static TLS_CONFIG: OnceCell<Arc<ClientConfig>> = OnceCell::new();
+
+fn tls_config() -> Arc<ClientConfig> {
+    TLS_CONFIG
+        .get_or_init(|| {
+            let mut roots = RootCertStore::empty();
+            roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    anchor.subject,
+                    anchor.spki,
+                    anchor.name_constraints,
+                )
+            }));
+
+            let config = ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+
+            Arc::new(config)
+        })
+        .clone()
+}
+
+async fn send<T>(io: T, request: Request<Body>) -> Result<Response<Body>>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = conn::handshake(io).await?;
+    tokio::spawn(connection);
+
+    Ok(sender.send_request(request).await?)
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(input: &[u8]) -> String {
+    let mut output = String::with_capacity((input.len() + 2) / 3 * 4);
+
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+
+    output
+}
+
+/// Authorization header for HTTP basic authentication
+pub fn basic_auth(user: &str, password: &str) -> (String, String) {
+    let credentials = base64_encode(format!("{}:{}", user, password).as_bytes());
+
+    (
+        "authorization".to_string(),
+        format!("Basic {}", credentials),
+    )
+}
+
+/// Sends a POST request with given headers and body, returning the response
+/// status
+pub async fn post(url: &str, headers: &[(String, String)], body: Vec<u8>) -> Result<StatusCode> {
+    let (status, _) = request(Method::POST, url, headers, body).await?;
+
+    Ok(status)
+}
+
+/// Sends a GET request, returning the response status and body
+pub async fn get(url: &str, headers: &[(String, String)]) -> Result<(StatusCode, Bytes)> {
+    request(Method::GET, url, headers, vec![]).await
+}
+
+/// Sends a GET request, returning the response status and body without
+/// waiting for the body to complete, e.g. for event streams
+pub async fn get_stream(url: &str, headers: &[(String, String)]) -> Result<(StatusCode, Body)> {
+    let response = send_request(Method::GET, url, headers, vec![]).await?;
+
+    Ok((response.status(), response.into_body()))
+}
+
+/// Sends a request, returning the response status and body
+pub async fn request(
+    method: Method,
+    url: &str,
+    headers: &[(String, String)],
+    body: Vec<u8>,
+) -> Result<(StatusCode, Bytes)> {
+    let response = send_request(method, url, headers, body).await?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+
+    Ok((status, body))
+}
+
+async fn send_request(
+    method: Method,
+    url: &str,
+    headers: &[(String, String)],
+    body: Vec<u8>,
+) -> Result<Response<Body>> {
+    let uri: Uri = url.parse()?;
+    let host = uri
+        .host()
+        .ok_or_else(|| eyre!("Missing host in {}", url))?
+        .to_string();
+    let https = match uri.scheme_str() {
+        Some("https") => true,
+        Some("http") => false,
+        _ => return Err(eyre!("Unsupported scheme in {}", url)),
+    };
+    let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+
+    let mut request = Request::builder()
+        .method(method)
+        .uri(path)
+        .header("host", &host);
+
+    for (key, value) in headers {
+        request = request.header(key, value);
+    }
+
+    let request = request.body(Body::from(body))?;
+    let tcp = TcpStream::connect((host.as_str(), port)).await?;
+
+    if https {
+        let server_name = ServerName::try_from(host.as_str())?;
+        let tls = TlsConnector::from(tls_config())
+            .connect(server_name, tcp)
+            .await?;
+
+        send(tls, request).await
+    } else {
+        send(tcp, request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_basic_auth() {
+        assert_eq!(
+            basic_auth("Aladdin", "open sesame").1,
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+        assert_eq!(base64_encode(b"ab"), "YWI=");
+        assert_eq!(base64_encode(b"abc"), "YWJj");
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use crate::types::{
+    device::SensorDevice,
+    rule::{IlluminanceFusion, IlluminanceRule},
+};
+
+use super::devices::Devices;
+
+fn fuse(fusion: IlluminanceFusion, values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+
+    let fused = match fusion {
+        IlluminanceFusion::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+        IlluminanceFusion::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        IlluminanceFusion::Mean => values.iter().sum::<f64>() / values.len() as f64,
+    };
+
+    Some(fused)
+}
+
+fn is_dark(rule: &IlluminanceRule, was_dark: bool, illuminance: f64) -> bool {
+    let threshold = if was_dark {
+        rule.below.0 + rule.hysteresis.0
+    } else {
+        rule.below.0
+    };
+
+    illuminance < threshold
+}
+
+/// Keeps track of whether it's dark according to each illuminance rule, which
+/// is needed for hysteresis
+#[derive(Clone, Default)]
+pub struct IlluminanceStates {
+    dark: HashMap<IlluminanceRule, bool>,
+}
+
+impl IlluminanceStates {
+    /// Re-evaluates given rules against current sensor readings
+    pub fn update<'a>(
+        &mut self,
+        rules: impl Iterator<Item = &'a IlluminanceRule>,
+        devices: &Devices,
+    ) {
+        for rule in rules {
+            let values: Vec<f64> = rule
+                .illuminance
+                .iter()
+                .filter_map(|sensor| devices.get_device_by_ref(sensor))
+                .filter(|device| {
+                    !devices
+                        .get_unavailable_devices()
+                        .contains(&device.get_device_key())
+                })
+                .filter_map(|device| match device.get_sensor_state()? {
+                    SensorDevice::Number { value, .. } => Some(value.0),
+                    _ => None,
+                })
+                .collect();
+
+            // Without readings, keep the previous state
+            let Some(illuminance) = fuse(rule.fusion, &values) else {
+                continue;
+            };
+
+            let was_dark = self.is_dark(rule);
+            self.dark
+                .insert(rule.clone(), is_dark(rule, was_dark, illuminance));
+        }
+    }
+
+    /// Rules without any readings yet count as dark, rather light up a room
+    /// than leave someone in the dark
+    pub fn is_dark(&self, rule: &IlluminanceRule) -> bool {
+        self.dark.get(rule).copied().unwrap_or(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ordered_float::OrderedFloat;
+
+    use super::*;
+
+    #[test]
+    fn test_fuse() {
+        let values = [10.0, 20.0, 60.0];
+        assert_eq!(fuse(IlluminanceFusion::Min, &values), Some(10.0));
+        assert_eq!(fuse(IlluminanceFusion::Max, &values), Some(60.0));
+        assert_eq!(fuse(IlluminanceFusion::Mean, &values), Some(30.0));
+        assert_eq!(fuse(IlluminanceFusion::Mean, &[]), None);
+    }
+
+    #[test]
+    fn test_is_dark_hysteresis() {
+        let rule = IlluminanceRule {
+            illuminance: vec![],
+            below: OrderedFloat(30.0),
+            hysteresis: OrderedFloat(20.0),
+            fusion: IlluminanceFusion::Mean,
+        };
+
+        assert!(!is_dark(&rule, false, 40.0));
+        assert!(is_dark(&rule, false, 20.0));
+        // Lights came on, but it's not bright enough to count as daylight
+        assert!(is_dark(&rule, true, 40.0));
+        assert!(!is_dark(&rule, true, 55.0));
+    }
+}
@@ -1,21 +1,58 @@
-use crate::integrations::cron::Cron;
+use crate::integrations::cron::{Cron, CronConfig};
 use crate::integrations::{
-    circadian::Circadian, dummy::Dummy, mqtt::Mqtt, random::Random, timer::Timer,
+    androidtv::{AndroidTv, AndroidTvConfig},
+    ble::{Ble, BleConfig},
+    circadian::{Circadian, CircadianConfig},
+    doorbird::{Doorbird, DoorbirdConfig},
+    dummy::{Dummy, DummyConfig},
+    homectl::{Homectl, HomectlConfig},
+    mqtt::{Mqtt, MqttConfig},
+    niko::{Niko, NikoConfig},
+    nut::{Nut, NutConfig},
+    ocpp::{Ocpp, OcppConfig},
+    ping::{Ping, PingConfig},
+    random::{Random, RandomConfig},
+    snmp::{Snmp, SnmpConfig},
+    timer::{Timer, TimerConfig},
+    velbus::{Velbus, VelbusConfig},
+    victron::{Victron, VictronConfig},
+    zigbee2mqtt::{Zigbee2mqtt, Zigbee2mqttConfig},
 };
 use crate::types::{
     device::{Device, DeviceKey},
-    event::TxEventChannel,
-    integration::{Integration, IntegrationActionPayload, IntegrationId},
+    event::{Message, TxEventChannel},
+    integration::{
+        CapturedTraffic, Integration, IntegrationActionPayload, IntegrationConfig, IntegrationId,
+        IntegrationsConfig,
+    },
 };
+use bytes::Bytes;
 use color_eyre::Result;
 use eyre::eyre;
-use std::{collections::HashMap, sync::Arc};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::sync::{Mutex, RwLock};
+use tracing::instrument;
+
+use super::{
+    circuit_breaker::CircuitBreaker, config::OpaqueIntegrationsConfigs,
+    device_config::DeviceConfigs, schema::SchemaGenerator,
+};
 
 #[derive(Clone)]
 pub struct LoadedIntegration {
     integration: Arc<Mutex<Box<dyn Integration>>>,
     module_name: String,
+    depends_on: Vec<IntegrationId>,
+    autostart: bool,
+    circuit_breaker: Arc<Mutex<CircuitBreaker>>,
+
+    /// Config the integration was loaded with, to detect changes on reload
+    config: config::Value,
 }
 
 pub type CustomIntegrationsMap = HashMap<IntegrationId, LoadedIntegration>;
@@ -25,27 +62,34 @@ pub type DeviceStates = HashMap<DeviceKey, Device>;
 pub struct Integrations {
     expected_device_states: Arc<RwLock<DeviceStates>>,
     custom_integrations: CustomIntegrationsMap,
+    failed_integrations: HashSet<IntegrationId>,
+    running_integrations: HashSet<IntegrationId>,
+    device_configs: DeviceConfigs,
     event_tx: TxEventChannel,
 }
 
 impl Integrations {
-    pub fn new(event_tx: TxEventChannel) -> Self {
+    pub fn new(event_tx: TxEventChannel, device_configs: DeviceConfigs) -> Self {
         let expected_device_states = Default::default();
         let integrations = Default::default();
 
         Integrations {
             expected_device_states,
             custom_integrations: integrations,
+            failed_integrations: Default::default(),
+            running_integrations: Default::default(),
+            device_configs,
             event_tx,
         }
     }
 
     pub async fn load_integration(
         &mut self,
-        module_name: &str,
         integration_id: &IntegrationId,
+        integration_config: &IntegrationConfig,
         config: &config::Value,
     ) -> Result<()> {
+        let module_name = &integration_config.plugin;
         info!("loading integration with module_name {}", module_name);
 
         let event_tx = self.event_tx.clone();
@@ -54,39 +98,451 @@ impl Integrations {
         let loaded_integration = LoadedIntegration {
             integration: Arc::new(Mutex::new(integration)),
             module_name: module_name.to_string(),
+            depends_on: integration_config.depends_on.clone(),
+            autostart: integration_config.autostart.unwrap_or(true),
+            circuit_breaker: Default::default(),
+            config: config.clone(),
         };
 
         self.custom_integrations
             .insert(integration_id.clone(), loaded_integration);
+        self.device_configs
+            .set_integration_defaults(integration_id, integration_config.device_defaults.clone());
 
         Ok(())
     }
 
-    pub async fn run_register_pass(&self) -> Result<()> {
-        for (integration_id, li) in self.custom_integrations.iter() {
+    /// Marks an integration that failed to load, so that integrations
+    /// depending on it are skipped instead of started
+    pub fn set_failed(&mut self, integration_id: &IntegrationId) {
+        self.failed_integrations.insert(integration_id.clone());
+    }
+
+    /// Returns integration ids in an order where each integration comes after
+    /// all of its dependencies. Integrations with unknown or circular
+    /// dependencies can't be ordered and are returned separately.
+    fn get_start_order(&self) -> (Vec<IntegrationId>, BTreeSet<IntegrationId>) {
+        let deps = self
+            .custom_integrations
+            .iter()
+            .map(|(integration_id, li)| (integration_id.clone(), li.depends_on.clone()))
+            .collect();
+
+        order_by_dependencies(&deps)
+    }
+
+    /// Returns the start order of loaded integrations, marking the ones whose
+    /// dependencies can't be resolved as failed so that the rest can still be
+    /// started
+    fn get_startable_order(&mut self) -> Vec<IntegrationId> {
+        let (order, unordered) = self.get_start_order();
+
+        for integration_id in unordered {
+            let li = &self.custom_integrations[&integration_id];
+
+            match li
+                .depends_on
+                .iter()
+                .find(|dep| !self.custom_integrations.contains_key(*dep))
+            {
+                Some(dep) if self.failed_integrations.contains(dep) => error!(
+                    "skipping {} integration {}: dependency {} failed",
+                    li.module_name, integration_id, dep
+                ),
+                Some(dep) => error!(
+                    "skipping {} integration {}: depends on unknown integration {}",
+                    li.module_name, integration_id, dep
+                ),
+                None => error!(
+                    "skipping {} integration {}: circular dependency between integrations",
+                    li.module_name, integration_id
+                ),
+            }
+
+            self.failed_integrations.insert(integration_id);
+        }
+
+        order
+    }
+
+    /// Returns the first dependency of given integration that has failed, if
+    /// any
+    fn find_failed_dependency(&self, li: &LoadedIntegration) -> Option<IntegrationId> {
+        li.depends_on
+            .iter()
+            .find(|dep| self.failed_integrations.contains(*dep))
+            .cloned()
+    }
+
+    /// Transforms device state reported by an integration according to
+    /// per-device configs
+    pub fn apply_incoming_device_config(&self, device: &Device) -> Device {
+        self.device_configs.apply_incoming(device)
+    }
+
+    pub fn is_loaded(&self, integration_id: &IntegrationId) -> bool {
+        self.custom_integrations.contains_key(integration_id)
+    }
+
+    pub fn is_running(&self, integration_id: &IntegrationId) -> bool {
+        self.running_integrations.contains(integration_id)
+    }
+
+    /// Returns (integration_id, module_name, running) for each loaded
+    /// integration
+    pub fn get_integration_statuses(&self) -> Vec<(IntegrationId, String, bool)> {
+        let mut statuses: Vec<_> = self
+            .custom_integrations
+            .iter()
+            .map(|(integration_id, li)| {
+                (
+                    integration_id.clone(),
+                    li.module_name.clone(),
+                    self.is_running(integration_id),
+                )
+            })
+            .collect();
+
+        statuses.sort();
+        statuses
+    }
+
+    pub async fn run_register_pass(&mut self) -> Result<()> {
+        for integration_id in self.get_startable_order() {
+            let li = &self.custom_integrations[&integration_id];
+
+            if !li.autostart {
+                continue;
+            }
+
+            if self.failed_integrations.contains(&integration_id) {
+                continue;
+            }
+
+            if let Some(dep) = self.find_failed_dependency(li) {
+                error!(
+                    "skipping registration of {} integration {}: dependency {} failed",
+                    li.module_name, integration_id, dep
+                );
+                self.failed_integrations.insert(integration_id);
+                continue;
+            }
+
+            let result = li.integration.lock().await.register().await;
+
+            match result {
+                Ok(()) => info!(
+                    "registered {} integration {}",
+                    li.module_name, integration_id
+                ),
+                Err(e) => {
+                    error!(
+                        "failed to register {} integration {}: {:?}",
+                        li.module_name, integration_id, e
+                    );
+                    self.failed_integrations.insert(integration_id);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn run_start_pass(&mut self) -> Result<()> {
+        for integration_id in self.get_startable_order() {
+            let li = &self.custom_integrations[&integration_id];
+
+            if !li.autostart {
+                info!(
+                    "not starting {} integration {} (autostart = false)",
+                    li.module_name, integration_id
+                );
+                continue;
+            }
+
+            if self.failed_integrations.contains(&integration_id) {
+                continue;
+            }
+
+            if let Some(dep) = self.find_failed_dependency(li) {
+                error!(
+                    "skipping start of {} integration {}: dependency {} failed",
+                    li.module_name, integration_id, dep
+                );
+                self.failed_integrations.insert(integration_id);
+                continue;
+            }
+
+            let result = li.integration.lock().await.start().await;
+
+            match result {
+                Ok(()) => {
+                    info!("started {} integration {}", li.module_name, integration_id);
+                    self.running_integrations.insert(integration_id);
+                }
+                Err(e) => {
+                    error!(
+                        "failed to start {} integration {}: {:?}",
+                        li.module_name, integration_id, e
+                    );
+                    self.failed_integrations.insert(integration_id);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn run_stop_pass(&self) -> Result<()> {
+        // Stop dependents before their dependencies
+        for integration_id in self.get_start_order().0.iter().rev() {
+            if !self.is_running(integration_id) {
+                continue;
+            }
+
+            let li = &self.custom_integrations[integration_id];
             let mut integration = li.integration.lock().await;
 
-            integration.register().await.unwrap();
-            info!(
-                "registered {} integration {}",
-                li.module_name, integration_id
+            // Keep stopping the remaining integrations even if one fails
+            match integration.stop().await {
+                Ok(()) => info!("stopped {} integration {}", li.module_name, integration_id),
+                Err(e) => error!(
+                    "Error while stopping {} integration {}: {:?}",
+                    li.module_name, integration_id, e
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stops a running integration. Devices of the integration are marked as
+    /// unavailable until it is started again.
+    pub async fn stop_integration(&mut self, integration_id: &IntegrationId) -> Result<()> {
+        let li = self
+            .custom_integrations
+            .get(integration_id)
+            .ok_or_else(|| eyre!("Expected to find integration by id {}", integration_id))?;
+
+        if !self.running_integrations.remove(integration_id) {
+            info!("integration {} is not running", integration_id);
+            return Ok(());
+        }
+
+        let dependents: Vec<&IntegrationId> = self
+            .custom_integrations
+            .iter()
+            .filter(|(id, other)| other.depends_on.contains(integration_id) && self.is_running(id))
+            .map(|(id, _)| id)
+            .collect();
+
+        if !dependents.is_empty() {
+            warn!(
+                "stopping integration {} which running integrations {:?} depend on",
+                integration_id, dependents
             );
         }
 
+        li.integration.lock().await.stop().await?;
+        info!("stopped {} integration {}", li.module_name, integration_id);
+
+        self.event_tx.send(Message::SetIntegrationAvailability {
+            integration_id: integration_id.clone(),
+            available: false,
+        });
+
+        Ok(())
+    }
+
+    /// Stops and unloads an integration
+    pub async fn remove_integration(&mut self, integration_id: &IntegrationId) -> Result<()> {
+        if let Some((dependent, _)) = self
+            .custom_integrations
+            .iter()
+            .find(|(_, li)| li.depends_on.contains(integration_id))
+        {
+            return Err(eyre!(
+                "Cannot remove integration {}: integration {} depends on it",
+                integration_id,
+                dependent
+            ));
+        }
+
+        self.stop_integration(integration_id).await?;
+        self.custom_integrations.remove(integration_id);
+        self.failed_integrations.remove(integration_id);
+
+        info!("removed integration {}", integration_id);
+
         Ok(())
     }
 
-    pub async fn run_start_pass(&self) -> Result<()> {
-        for (integration_id, li) in self.custom_integrations.iter() {
+    /// (Re)loads an integration with given config and starts it. The
+    /// integration must not be running.
+    pub async fn start_integration(
+        &mut self,
+        integration_id: &IntegrationId,
+        integration_config: &IntegrationConfig,
+        config: &config::Value,
+    ) -> Result<()> {
+        if self.is_running(integration_id) {
+            return Err(eyre!("Integration {} is already running", integration_id));
+        }
+
+        if let Some(dep) = integration_config
+            .depends_on
+            .iter()
+            .find(|dep| !self.is_running(dep))
+        {
+            return Err(eyre!(
+                "Cannot start integration {}: dependency {} is not running",
+                integration_id,
+                dep
+            ));
+        }
+
+        self.load_integration(integration_id, integration_config, config)
+            .await?;
+
+        let li = &self.custom_integrations[integration_id];
+        {
             let mut integration = li.integration.lock().await;
+            integration.register().await?;
+            integration.start().await?;
+        }
+        info!("started {} integration {}", li.module_name, integration_id);
+
+        self.failed_integrations.remove(integration_id);
+        self.running_integrations.insert(integration_id.clone());
+
+        self.event_tx.send(Message::SetIntegrationAvailability {
+            integration_id: integration_id.clone(),
+            available: true,
+        });
+
+        Ok(())
+    }
 
-            integration.start().await.unwrap();
-            info!("started {} integration {}", li.module_name, integration_id);
+    /// Integrations that are loaded, but neither in given config nor added
+    /// through the API
+    fn removed_on_reload(
+        &self,
+        integrations_config: &IntegrationsConfig,
+        runtime_ids: &HashSet<IntegrationId>,
+    ) -> Vec<IntegrationId> {
+        self.custom_integrations
+            .keys()
+            .filter(|id| !integrations_config.contains_key(*id) && !runtime_ids.contains(*id))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the start order of integrations after applying given config,
+    /// failing on unknown or circular dependencies. Nothing is changed, so
+    /// that a config can be validated before reloading it.
+    pub fn reload_start_order(
+        &self,
+        integrations_config: &IntegrationsConfig,
+        runtime_ids: &HashSet<IntegrationId>,
+    ) -> Result<Vec<IntegrationId>> {
+        let removed = self.removed_on_reload(integrations_config, runtime_ids);
+
+        let mut deps: BTreeMap<IntegrationId, Vec<IntegrationId>> = self
+            .custom_integrations
+            .iter()
+            .filter(|(integration_id, _)| !removed.contains(integration_id))
+            .map(|(integration_id, li)| (integration_id.clone(), li.depends_on.clone()))
+            .collect();
+        deps.extend(
+            integrations_config.iter().map(|(integration_id, config)| {
+                (integration_id.clone(), config.depends_on.clone())
+            }),
+        );
+
+        resolve_start_order(&deps)
+    }
+
+    /// Applies a re-read `[integrations]` config section: integrations that
+    /// were removed from it are unloaded, ones with a changed config are
+    /// restarted and new ones are started. Integrations in `runtime_ids` were
+    /// added through the API and are left alone.
+    pub async fn reload_config(
+        &mut self,
+        integrations_config: &IntegrationsConfig,
+        opaque_configs: &OpaqueIntegrationsConfigs,
+        runtime_ids: &HashSet<IntegrationId>,
+    ) -> Result<()> {
+        // Fail before stopping anything if the new dependencies are invalid
+        let start_order = self.reload_start_order(integrations_config, runtime_ids)?;
+
+        let removed = self.removed_on_reload(integrations_config, runtime_ids);
+
+        let changed: HashSet<IntegrationId> = opaque_configs
+            .iter()
+            .filter(|(id, config)| {
+                self.custom_integrations
+                    .get(*id)
+                    .map_or(true, |li| li.config != **config)
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for integration_id in &removed {
+            if let Err(e) = self.remove_integration(integration_id).await {
+                error!(
+                    "Error while removing integration {}: {:?}",
+                    integration_id, e
+                );
+            }
+        }
+
+        // Stop dependents before their dependencies
+        for integration_id in self.get_start_order().0.iter().rev() {
+            if !changed.contains(integration_id) {
+                continue;
+            }
+
+            if let Err(e) = self.stop_integration(integration_id).await {
+                error!(
+                    "Error while stopping integration {}: {:?}",
+                    integration_id, e
+                );
+            }
+        }
+
+        for integration_id in start_order {
+            if !changed.contains(&integration_id) {
+                continue;
+            }
+
+            let (Some(integration_config), Some(config)) = (
+                integrations_config.get(&integration_id),
+                opaque_configs.get(&integration_id),
+            ) else {
+                continue;
+            };
+
+            let result = if integration_config.autostart.unwrap_or(true) {
+                self.start_integration(&integration_id, integration_config, config)
+                    .await
+            } else {
+                self.load_integration(&integration_id, integration_config, config)
+                    .await
+            };
+
+            if let Err(e) = result {
+                error!(
+                    "failed to reload {} integration {}: {:?}",
+                    integration_config.plugin, integration_id, e
+                );
+                self.failed_integrations.insert(integration_id);
+            }
         }
 
         Ok(())
     }
 
+    #[instrument(skip_all, fields(device = %device.get_device_key()))]
     pub async fn set_integration_device_state(&self, device: &Device) -> Result<()> {
         {
             let mut expected_device_states = self.expected_device_states.write().await;
@@ -102,11 +558,124 @@ impl Integrations {
                     device.integration_id
                 )
             })?;
+
+        if !self.is_running(&device.integration_id) {
+            trace!(
+                "Integration {} is not running, not sending state of {}",
+                device.integration_id,
+                device.name
+            );
+            return Ok(());
+        }
+
+        {
+            let mut circuit_breaker = li.circuit_breaker.lock().await;
+            if !circuit_breaker.allow_request(Instant::now()) {
+                // The outage was reported once when the circuit opened, and
+                // the probe re-sends the latest expected state on recovery
+                debug!(
+                    "Circuit breaker open for integration {}, not sending state of {}",
+                    device.integration_id, device.name
+                );
+                return Ok(());
+            }
+        }
+
+        let result = {
+            let device = self.device_configs.apply_outgoing(device);
+            let mut integration = li.integration.lock().await;
+            integration.set_integration_device_state(&device).await
+        };
+
+        let mut circuit_breaker = li.circuit_breaker.lock().await;
+        match result {
+            Ok(()) => {
+                if circuit_breaker.record_success() {
+                    info!(
+                        "Integration {} has recovered, resuming device state updates",
+                        device.integration_id
+                    );
+                    self.event_tx.send(Message::SetIntegrationAvailability {
+                        integration_id: device.integration_id.clone(),
+                        available: true,
+                    });
+                }
+            }
+            Err(_) => {
+                if let Some(cooldown) = circuit_breaker.record_failure(Instant::now()) {
+                    warn!(
+                        "Integration {} keeps failing, pausing device state updates for {:?}",
+                        device.integration_id, cooldown
+                    );
+                    self.event_tx.send(Message::SetIntegrationAvailability {
+                        integration_id: device.integration_id.clone(),
+                        available: false,
+                    });
+                    self.schedule_probe(device.clone(), cooldown);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Re-sends the latest expected state of given device once the circuit
+    /// breaker cooldown has elapsed, in order to probe whether the integration
+    /// has recovered. Falls back to the state that failed to send if there's
+    /// no expected state, so that the circuit doesn't stay open until the
+    /// next command.
+    fn schedule_probe(&self, device: Device, cooldown: Duration) {
+        let expected_device_states = self.expected_device_states.clone();
+        let event_tx = self.event_tx.clone();
+
+        tokio::spawn(async move {
+            tokio::time::sleep(cooldown).await;
+
+            let device = {
+                let expected_device_states = expected_device_states.read().await;
+                expected_device_states
+                    .get(&device.get_device_key())
+                    .cloned()
+                    .unwrap_or(device)
+            };
+
+            event_tx.send(Message::SendDeviceState { device });
+        });
+    }
+
+    /// Asks the integration of given device to report its current state.
+    /// Returns false if the integration isn't running or can't poll devices.
+    pub async fn poll_device(&self, device: &Device) -> Result<bool> {
+        if !self.is_running(&device.integration_id) {
+            return Ok(false);
+        }
+
+        let Some(li) = self.custom_integrations.get(&device.integration_id) else {
+            return Ok(false);
+        };
+
+        let mut integration = li.integration.lock().await;
+        integration.poll_device(device).await
+    }
+
+    /// Returns raw traffic captured by the integration, or None if it isn't
+    /// capturing
+    pub async fn get_captured_traffic(
+        &self,
+        integration_id: &IntegrationId,
+    ) -> Option<CapturedTraffic> {
+        let li = self.custom_integrations.get(integration_id)?;
+        let mut integration = li.integration.lock().await;
+
+        integration.get_captured_traffic().await
+    }
+
+    /// Returns the latest camera snapshot taken by the integration
+    pub async fn get_snapshot(&self, integration_id: &IntegrationId) -> Option<Bytes> {
+        let li = self.custom_integrations.get(integration_id)?;
         let mut integration = li.integration.lock().await;
 
-        integration
-            .set_integration_device_state(&device.clone())
-            .await
+        integration.get_snapshot().await
     }
 
     pub async fn run_integration_action(
@@ -139,6 +708,158 @@ fn load_custom_integration(
         "timer" => Ok(Box::new(Timer::new(id, config, event_tx)?)),
         "dummy" => Ok(Box::new(Dummy::new(id, config, event_tx)?)),
         "mqtt" => Ok(Box::new(Mqtt::new(id, config, event_tx)?)),
+        "homectl" => Ok(Box::new(Homectl::new(id, config, event_tx)?)),
+        "niko" => Ok(Box::new(Niko::new(id, config, event_tx)?)),
+        "doorbird" => Ok(Box::new(Doorbird::new(id, config, event_tx)?)),
+        "velbus" => Ok(Box::new(Velbus::new(id, config, event_tx)?)),
+        "androidtv" => Ok(Box::new(AndroidTv::new(id, config, event_tx)?)),
+        "nut" => Ok(Box::new(Nut::new(id, config, event_tx)?)),
+        "snmp" => Ok(Box::new(Snmp::new(id, config, event_tx)?)),
+        "ble" => Ok(Box::new(Ble::new(id, config, event_tx)?)),
+        "ping" => Ok(Box::new(Ping::new(id, config, event_tx)?)),
+        "zigbee2mqtt" => Ok(Box::new(Zigbee2mqtt::new(id, config, event_tx)?)),
+        "ocpp" => Ok(Box::new(Ocpp::new(id, config, event_tx)?)),
+        "victron" => Ok(Box::new(Victron::new(id, config, event_tx)?)),
         _ => Err(eyre!("Unknown module name {}!", module_name)),
     }
 }
+
+/// Topologically sorts integrations by their dependencies. Integrations
+/// without dependencies between each other are ordered by id.
+fn resolve_start_order(
+    deps: &BTreeMap<IntegrationId, Vec<IntegrationId>>,
+) -> Result<Vec<IntegrationId>> {
+    for (integration_id, depends_on) in deps {
+        if let Some(dep) = depends_on.iter().find(|dep| !deps.contains_key(*dep)) {
+            return Err(eyre!(
+                "Integration {} depends on unknown integration {}",
+                integration_id,
+                dep
+            ));
+        }
+    }
+
+    let (order, remaining) = order_by_dependencies(deps);
+
+    if !remaining.is_empty() {
+        return Err(eyre!(
+            "Circular dependency between integrations: {:?}",
+            remaining
+        ));
+    }
+
+    Ok(order)
+}
+
+/// Orders integrations so that each one comes after all of its dependencies.
+/// Integrations that depend on unknown integrations, are part of a dependency
+/// cycle or depend on such integrations are returned separately.
+fn order_by_dependencies(
+    deps: &BTreeMap<IntegrationId, Vec<IntegrationId>>,
+) -> (Vec<IntegrationId>, BTreeSet<IntegrationId>) {
+    let mut order: Vec<IntegrationId> = vec![];
+    let mut remaining: BTreeSet<&IntegrationId> = deps.keys().collect();
+
+    loop {
+        let ready: Vec<&IntegrationId> = remaining
+            .iter()
+            .filter(|integration_id| {
+                deps[**integration_id]
+                    .iter()
+                    .all(|dep| deps.contains_key(dep) && !remaining.contains(dep))
+            })
+            .cloned()
+            .collect();
+
+        if ready.is_empty() {
+            break;
+        }
+
+        for integration_id in ready {
+            remaining.remove(integration_id);
+            order.push(integration_id.clone());
+        }
+    }
+
+    (order, remaining.into_iter().cloned().collect())
+}
+
+/// Schemas of the integration specific config of each plugin
+pub fn integration_config_schemas(gen: &mut SchemaGenerator) -> Vec<(&'static str, Value)> {
+    vec![
+        ("circadian", gen.subschema_for::<CircadianConfig>()),
+        ("cron", gen.subschema_for::<CronConfig>()),
+        ("random", gen.subschema_for::<RandomConfig>()),
+        ("timer", gen.subschema_for::<TimerConfig>()),
+        ("dummy", gen.subschema_for::<DummyConfig>()),
+        ("mqtt", gen.subschema_for::<MqttConfig>()),
+        ("homectl", gen.subschema_for::<HomectlConfig>()),
+        ("niko", gen.subschema_for::<NikoConfig>()),
+        ("doorbird", gen.subschema_for::<DoorbirdConfig>()),
+        ("velbus", gen.subschema_for::<VelbusConfig>()),
+        ("androidtv", gen.subschema_for::<AndroidTvConfig>()),
+        ("nut", gen.subschema_for::<NutConfig>()),
+        ("snmp", gen.subschema_for::<SnmpConfig>()),
+        ("ble", gen.subschema_for::<BleConfig>()),
+        ("ping", gen.subschema_for::<PingConfig>()),
+        ("zigbee2mqtt", gen.subschema_for::<Zigbee2mqttConfig>()),
+        ("ocpp", gen.subschema_for::<OcppConfig>()),
+        ("victron", gen.subschema_for::<VictronConfig>()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(s: &str) -> IntegrationId {
+        IntegrationId::from(s.to_string())
+    }
+
+    #[test]
+    fn test_resolve_start_order() {
+        let deps = BTreeMap::from([
+            (id("a"), vec![id("notify")]),
+            (id("notify"), vec![id("mqtt")]),
+            (id("mqtt"), vec![]),
+            (id("b"), vec![]),
+        ]);
+
+        let order = resolve_start_order(&deps).unwrap();
+
+        assert_eq!(order, vec![id("b"), id("mqtt"), id("notify"), id("a")]);
+    }
+
+    #[test]
+    fn test_resolve_start_order_cycle() {
+        let deps = BTreeMap::from([(id("a"), vec![id("b")]), (id("b"), vec![id("a")])]);
+
+        assert!(resolve_start_order(&deps).is_err());
+    }
+
+    #[test]
+    fn test_resolve_start_order_unknown_dependency() {
+        let deps = BTreeMap::from([(id("a"), vec![id("missing")])]);
+
+        assert!(resolve_start_order(&deps).is_err());
+    }
+
+    #[test]
+    fn test_order_by_dependencies_skips_unresolvable() {
+        let deps = BTreeMap::from([
+            (id("a"), vec![id("missing")]),
+            (id("b"), vec![id("a")]),
+            (id("c"), vec![id("d")]),
+            (id("d"), vec![id("c")]),
+            (id("e"), vec![]),
+        ]);
+
+        let (order, unordered) = order_by_dependencies(&deps);
+
+        assert_eq!(order, vec![id("e")]);
+        assert_eq!(
+            unordered,
+            BTreeSet::from([id("a"), id("b"), id("c"), id("d")])
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use color_eyre::Result;
+use eyre::eyre;
+use once_cell::sync::OnceCell;
+
+use super::telemetry::{check_otlp_protocol, init_telemetry};
+use tracing_subscriber::{
+    filter::Directive, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry,
+};
+
+static FILTER_HANDLE: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
+
+/// Sets up tracing with a log filter read from the `RUST_LOG` environment
+/// variable. The filter can later be changed at runtime with
+/// [set_log_filter], [set_module_log_level] and [set_integration_log_level].
+///
+/// Spans are additionally exported over OTLP if configured with the standard
+/// `OTEL_*` environment variables, see [init_telemetry].
+pub fn init_logging() {
+    let (filter, handle) = reload::Layer::new(EnvFilter::from_default_env());
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .with(init_telemetry())
+        .init();
+
+    check_otlp_protocol();
+
+    FILTER_HANDLE
+        .set(handle)
+        .expect("Expected logging to be initialized only once");
+}
+
+fn get_handle() -> Result<&'static reload::Handle<EnvFilter, Registry>> {
+    FILTER_HANDLE
+        .get()
+        .ok_or_else(|| eyre!("Logging has not been initialized"))
+}
+
+/// Returns the currently active log filter, in `RUST_LOG` syntax
+pub fn get_log_filter() -> Result<String> {
+    let filter = get_handle()?.with_current(|filter| filter.to_string())?;
+
+    Ok(filter)
+}
+
+/// Replaces the active log filter, e.g. `homectl_server=info`
+pub fn set_log_filter(directives: &str) -> Result<()> {
+    let filter = EnvFilter::try_new(directives)?;
+    get_handle()?.reload(filter)?;
+
+    info!("Log filter set to {}", directives);
+
+    Ok(())
+}
+
+/// Changes the log level of a single module, keeping other directives of the
+/// active filter. Module paths are relative to the homectl_server crate, e.g.
+/// `integrations::mqtt`.
+pub fn set_module_log_level(module: &str, level: &str) -> Result<()> {
+    set_target_log_level(&mk_target(module), level)
+}
+
+/// Changes the log level of a single integration instance, e.g. `mqtt`,
+/// keeping other directives of the active filter
+pub fn set_integration_log_level(integration_id: &str, level: &str) -> Result<()> {
+    set_target_log_level(&mk_integration_target(integration_id), level)
+}
+
+fn set_target_log_level(target: &str, level: &str) -> Result<()> {
+    let directive: Directive = format!("{target}={level}").parse()?;
+
+    let filter = get_log_filter()?;
+    let directives = replace_directive(&filter, target, &directive.to_string());
+
+    set_log_filter(&directives)
+}
+
+fn mk_target(module: &str) -> String {
+    let crate_name = env!("CARGO_CRATE_NAME");
+
+    if module == crate_name || module.starts_with(&format!("{crate_name}::")) {
+        module.to_string()
+    } else {
+        format!("{crate_name}::{module}")
+    }
+}
+
+/// Matches logs within the `integration` span of an integration instance
+fn mk_integration_target(integration_id: &str) -> String {
+    let crate_name = env!("CARGO_CRATE_NAME");
+
+    format!("{crate_name}[integration{{integration_id={integration_id}}}]")
+}
+
+/// Replaces any existing directive for given target in a comma separated
+/// filter string
+fn replace_directive(filter: &str, target: &str, directive: &str) -> String {
+    filter
+        .split(',')
+        .map(str::trim)
+        .filter(|existing| !existing.is_empty())
+        .filter(|existing| {
+            existing
+                .rsplit_once('=')
+                .map_or(*existing, |(existing_target, _)| existing_target)
+                != target
+        })
+        .chain(std::iter::once(directive))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mk_target() {
+        assert_eq!(
+            mk_target("integrations::mqtt"),
+            "homectl_server::integrations::mqtt"
+        );
+        assert_eq!(mk_target("homectl_server::core"), "homectl_server::core");
+    }
+
+    #[test]
+    fn test_mk_integration_target() {
+        let target = mk_integration_target("mqtt");
+        assert_eq!(target, "homectl_server[integration{integration_id=mqtt}]");
+
+        // Directives of other integrations are kept
+        assert_eq!(
+            replace_directive(
+                "homectl_server[integration{integration_id=hue}]=debug",
+                &target,
+                &format!("{target}=trace")
+            ),
+            "homectl_server[integration{integration_id=hue}]=debug,homectl_server[integration{integration_id=mqtt}]=trace"
+        );
+    }
+
+    #[test]
+    fn test_replace_directive() {
+        assert_eq!(
+            replace_directive(
+                "homectl_server=info,homectl_server::integrations::mqtt=debug",
+                "homectl_server::integrations::mqtt",
+                "homectl_server::integrations::mqtt=trace"
+            ),
+            "homectl_server=info,homectl_server::integrations::mqtt=trace"
+        );
+        assert_eq!(
+            replace_directive("", "homectl_server", "homectl_server=warn"),
+            "homectl_server=warn"
+        );
+    }
+}
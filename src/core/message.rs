@@ -1,24 +1,106 @@
+use chrono::Utc;
 use color_eyre::Result;
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+use tracing::Instrument;
 
 use crate::types::{
     action::Action,
-    dim::DimDescriptor,
+    conflict::WriteSource,
+    device::{Device, DeviceAlias, DeviceKey, DevicesState},
+    device_expiry::PurgeUnseenDevicesDescriptor,
+    dim::{
+        ColorTemperatureStepDescriptor, DimDescriptor, NudgeColorDescriptor,
+        UpdateDeviceStateDescriptor,
+    },
     event::*,
     integration::CustomActionDescriptor,
+    mode::SetModeDescriptor,
+    overrides::OverrideDescriptor,
+    person::PERSONS_INTEGRATION_ID,
+    quiet_hours::DoNotDisturbDescriptor,
     rule::ForceTriggerRoutineDescriptor,
     scene::{CycleScenesDescriptor, SceneDescriptor},
+    websockets::WebSocketResponse,
+};
+
+use crate::db::actions::{
+    db_delete_device, db_delete_integration, db_delete_scene, db_edit_scene, db_find_device,
+    db_get_integrations, db_get_unseen_devices, db_migrate_device, db_store_device_metadata,
+    db_store_integration, db_store_scene,
+};
+
+use super::{
+    clock::local_now,
+    config::{parse_integration_config, read_integration_config},
+    config_reload::reload_config,
+    expr::eval_action_expr,
+    groups::group_id_from_device_key,
+    sentry::{capture_message_error, MESSAGE_KIND},
+    state::AppState,
+    telemetry::record_duration,
 };
 
-use crate::db::actions::{db_delete_scene, db_edit_scene, db_store_scene};
+/// Handles a message within its correlation id, recording how long it took
+/// and reporting any error
+pub async fn process_message(state: &mut AppState, correlation_id: CorrelationId, msg: &Message) {
+    let span = info_span!("message", %correlation_id, kind = msg.kind());
 
-use super::{expr::eval_action_expr, state::AppState};
+    let handler = async {
+        let started_at = Instant::now();
+        let result = handle_message(state, msg).await;
+        record_duration(
+            "homectl.message.duration",
+            vec![("kind", msg.kind().to_string())],
+            started_at.elapsed(),
+        );
+
+        if let Err(err) = result {
+            error!(
+                "Error while handling message:\n    Msg:\n    {:#?}\n\n    Err:\n    {:#?}",
+                msg, err
+            );
+            capture_message_error(msg, correlation_id, &err);
+        }
+    };
+
+    // Messages sent while handling this message inherit its correlation id
+    CORRELATION_ID
+        .scope(correlation_id, MESSAGE_KIND.scope(msg.kind(), handler))
+        .instrument(span)
+        .await
+}
 
 pub async fn handle_message(state: &mut AppState, msg: &Message) -> Result<()> {
+    // The primary instance is in charge of running actions
+    if let (true, Message::Action(_)) = (state.standby, msg) {
+        debug!("Ignoring action in standby mode");
+        return Ok(());
+    }
+
+    state.event_bus.publish_message(msg);
+    state.ha_discovery.publish_message(msg);
+
     match msg {
         Message::RecvDeviceState { device } => {
+            let device = state.integrations.apply_incoming_device_config(device);
+            if state.commands.received(&device, &state.devices) {
+                state.event_tx.send(Message::WsBroadcastState);
+            }
+
+            let device_key = device.get_device_key();
+            if state.polling.seen(&device_key)
+                && state.devices.set_device_availability(&device_key, true)
+            {
+                info!("{} is reporting state again", device_key);
+                state.event_tx.send(Message::WsBroadcastState);
+            }
+
             state
                 .devices
-                .handle_recv_device_state(device, &state.scenes)
+                .handle_recv_device_state(&device, &state.scenes)
                 .await
         }
         Message::InternalStateUpdate {
@@ -34,7 +116,7 @@ pub async fn handle_message(state: &mut AppState, msg: &Message) -> Result<()> {
                 .groups
                 .invalidate(old_state, new_state, &state.devices);
 
-            let _invalidated_scenes = state.scenes.invalidate(
+            let invalidated_scenes = state.scenes.invalidate(
                 old_state,
                 new_state,
                 invalidated_device,
@@ -43,22 +125,61 @@ pub async fn handle_message(state: &mut AppState, msg: &Message) -> Result<()> {
                 state.expr.get_context(),
             );
 
-            // TODO: only invalidate changed devices/groups/scenes in expr context
             state
-                .expr
-                .invalidate(new_state, &state.groups, &state.scenes);
+                .persons
+                .handle_internal_state_update(old, new, &state.devices);
 
             state
-                .rules
-                .handle_internal_state_update(
-                    old_state,
-                    new_state,
-                    old,
-                    &state.devices,
-                    &state.groups,
-                    &state.expr,
-                )
-                .await;
+                .presence
+                .handle_internal_state_update(new, &state.devices);
+
+            state
+                .safety
+                .handle_internal_state_update(old, new, &state.devices);
+
+            state
+                .appliances
+                .handle_internal_state_update(new, &state.devices);
+
+            state
+                .rate_alerts
+                .handle_internal_state_update(new, &state.devices);
+
+            state
+                .utility_meters
+                .handle_internal_state_update(new, &state.devices);
+
+            state
+                .motion_lighting
+                .handle_internal_state_update(new, &state.devices, &state.groups);
+
+            state
+                .open_alerts
+                .handle_internal_state_update(old, new, &state.devices);
+
+            state.expr.invalidate_device(
+                old.as_ref(),
+                new,
+                new_state,
+                &state.groups,
+                &state.scenes,
+                &invalidated_scenes,
+            );
+
+            // Newly discovered devices don't trigger routines
+            if old.is_some() {
+                state
+                    .rules
+                    .handle_internal_state_update(
+                        old_state,
+                        new_state,
+                        &state.devices,
+                        &state.groups,
+                        &state.expr,
+                        &mut state.quiet_hours,
+                    )
+                    .await;
+            }
 
             state.event_tx.send(Message::WsBroadcastState);
 
@@ -77,16 +198,333 @@ pub async fn handle_message(state: &mut AppState, msg: &Message) -> Result<()> {
             Ok(())
         }
         Message::SendDeviceState { device } => {
+            state.commands.sent(device);
+            state.conflicts.written(device);
             state
                 .integrations
                 .set_integration_device_state(device)
                 .await
         }
+        Message::SetIntegrationAvailability {
+            integration_id,
+            available,
+        } => {
+            state
+                .devices
+                .set_integration_availability(integration_id, *available);
+            state.send_state_ws(None).await;
+
+            Ok(())
+        }
+        Message::SetDeviceAvailability {
+            device_key,
+            available,
+        } => {
+            // Integrations may learn about availability before state
+            if state.devices.get_device(device_key).is_some()
+                && state
+                    .devices
+                    .set_device_availability(device_key, *available)
+            {
+                info!(
+                    "{} is {}",
+                    device_key,
+                    if *available {
+                        "available"
+                    } else {
+                        "unavailable"
+                    }
+                );
+                state.event_tx.send(Message::WsBroadcastState);
+            }
+
+            Ok(())
+        }
+        Message::StartIntegration { integration_id } => {
+            let (integration_config, config) = read_integration_config(integration_id).await?;
+            state
+                .integrations
+                .start_integration(integration_id, &integration_config, &config)
+                .await
+        }
+        Message::StopIntegration { integration_id } => {
+            state.integrations.stop_integration(integration_id).await
+        }
+        Message::RestartIntegration { integration_id } => {
+            // Read config first so that a broken config doesn't leave the
+            // integration stopped
+            let (integration_config, config) = read_integration_config(integration_id).await?;
+            state.integrations.stop_integration(integration_id).await?;
+            state
+                .integrations
+                .start_integration(integration_id, &integration_config, &config)
+                .await
+        }
+        Message::AddIntegration {
+            integration_id,
+            config,
+        } => {
+            if state.integrations.is_loaded(integration_id) {
+                return Err(eyre!("Integration {} already exists", integration_id));
+            }
+
+            let (integration_config, opaque_config) = parse_integration_config(config)?;
+
+            // Only integrations that started are persisted, so that a broken
+            // config isn't loaded again on every startup
+            let result = state
+                .integrations
+                .start_integration(integration_id, &integration_config, &opaque_config)
+                .await;
+            let result = match result {
+                Ok(()) => db_store_integration(integration_id, config).await,
+                Err(e) => Err(e),
+            };
+
+            if result.is_err() && state.integrations.is_loaded(integration_id) {
+                state
+                    .integrations
+                    .remove_integration(integration_id)
+                    .await
+                    .ok();
+            }
+
+            result
+        }
+        Message::RemoveIntegration { integration_id } => {
+            let db_integrations = db_get_integrations().await?;
+            if !db_integrations.iter().any(|(id, _)| id == integration_id) {
+                return Err(eyre!(
+                    "Integration {} was not added at runtime, remove it from the config file instead",
+                    integration_id
+                ));
+            }
+
+            state
+                .integrations
+                .remove_integration(integration_id)
+                .await?;
+            db_delete_integration(integration_id).await
+        }
+        Message::OverrideExpired { device_key } => {
+            state
+                .devices
+                .handle_override_expired(device_key, &state.scenes)
+                .await;
+
+            Ok(())
+        }
+        Message::SetDeviceMetadata {
+            device_key,
+            metadata,
+        } => {
+            db_store_device_metadata(device_key, metadata).await?;
+            state
+                .devices
+                .get_device_configs()
+                .set_device_metadata(device_key, metadata.clone());
+
+            // Disabled devices are dropped from groups and scenes
+            if metadata.disabled == Some(true) {
+                let old_state = state.devices.get_state().clone();
+
+                if let Some(removed) = state.devices.remove_device(device_key) {
+                    invalidate_removed_device(state, &old_state, &removed);
+                }
+            }
+
+            state.send_state_ws(None).await;
+
+            Ok(())
+        }
+        Message::MigrateDevice { from, to } => {
+            let name = match state.devices.get_device(from) {
+                Some(device) => Some(device.name.clone()),
+                None => db_find_device(from).await.ok().map(|device| device.name),
+            };
+
+            let alias = DeviceAlias {
+                from: from.clone(),
+                name,
+                to: to.clone(),
+            };
+
+            db_migrate_device(&alias).await?;
+
+            let old_state = state.devices.get_state().clone();
+            let scenes = state.scenes.clone();
+
+            if let Some(removed) = state.devices.migrate_device(&alias, &scenes).await {
+                invalidate_removed_device(state, &old_state, &removed);
+            }
+
+            state.send_state_ws(None).await;
+
+            Ok(())
+        }
+        Message::RefreshQuietHours => {
+            if state.quiet_hours.refresh(&state.devices) {
+                state
+                    .notifications
+                    .flush_deferred(&state.integrations)
+                    .await;
+            }
+
+            Ok(())
+        }
+        Message::RefreshAppliances => {
+            state.appliances.refresh(&state.devices);
+
+            Ok(())
+        }
+        Message::RefreshRateAlerts => {
+            state.rate_alerts.refresh(&state.devices);
+
+            Ok(())
+        }
+        Message::RefreshUtilityMeters => {
+            state.utility_meters.refresh(&state.devices);
+
+            Ok(())
+        }
+        Message::RefreshHeating => {
+            state
+                .heating
+                .refresh(state.modes.get_mode(), &state.integrations)
+                .await;
+
+            Ok(())
+        }
+        Message::RefreshSun => {
+            state.sun.refresh(&state.devices);
+
+            if let Some(position) = state.sun.get_position() {
+                state.covers.refresh(&position, &state.integrations).await;
+            }
+
+            Ok(())
+        }
+        Message::MotionLightingTimeout { id, generation } => {
+            state.motion_lighting.handle_timeout(id, *generation);
+
+            Ok(())
+        }
+        Message::PresenceTimeout {
+            device_key,
+            generation,
+        } => {
+            if device_key.integration_id.to_string() == PERSONS_INTEGRATION_ID {
+                state
+                    .persons
+                    .handle_presence_timeout(device_key, *generation, &state.devices);
+            } else {
+                state
+                    .presence
+                    .handle_timeout(device_key, *generation, &state.devices);
+            }
+
+            Ok(())
+        }
+        Message::ButtonPressTimeout { button, generation } => {
+            state
+                .button_presses
+                .handle_timeout(button, *generation, &state.devices);
+
+            Ok(())
+        }
+        Message::SceneTransitionDone {
+            scene_descriptor,
+            generation,
+        } => {
+            if state
+                .scene_transitions
+                .finish(&scene_descriptor.scene_id, *generation)
+            {
+                state.commands.scene_activated(&scene_descriptor.scene_id);
+                apply_scene(state, scene_descriptor).await;
+            }
+
+            Ok(())
+        }
+        Message::CommandTimeout {
+            device_key,
+            generation,
+        } => {
+            if state
+                .commands
+                .handle_timeout(device_key, *generation, &state.devices)
+            {
+                state.event_tx.send(Message::WsBroadcastState);
+            }
+
+            Ok(())
+        }
+        Message::PollStaleDevices => {
+            for device in state.polling.get_stale_devices(&state.devices) {
+                let device_key = device.get_device_key();
+
+                match state.integrations.poll_device(&device).await {
+                    Ok(true) => {
+                        if state.polling.polled(&device_key)
+                            && state.devices.set_device_availability(&device_key, false)
+                        {
+                            warn!("{} is not answering polls, marking unavailable", device_key);
+                            state.event_tx.send(Message::WsBroadcastState);
+                        }
+                    }
+                    Ok(false) => {}
+                    Err(e) => warn!("Failed to poll {}: {:?}", device_key, e),
+                }
+            }
+
+            Ok(())
+        }
+        Message::ReconcileDevices => {
+            state.devices.reconcile(&state.scenes);
+
+            Ok(())
+        }
+        Message::OpenAlertTimeout { id, generation } => {
+            state
+                .open_alerts
+                .handle_timeout(id, *generation, &state.devices);
+
+            Ok(())
+        }
+        Message::ConfigReloaded => reload_config(state).await,
+        Message::PromoteStandby => {
+            if !state.standby {
+                return Ok(());
+            }
+
+            state.standby = false;
+            state.integrations.run_register_pass().await?;
+            state.integrations.run_start_pass().await?;
+            state.event_tx.send(Message::IntegrationsStarted);
+
+            Ok(())
+        }
+        // Handled by the main loop, which stops the task of the device
+        Message::DeviceRemoved { .. } => Ok(()),
+        Message::IntegrationsStarted => {
+            state.devices.handle_integrations_started();
+            state.rules.handle_integrations_started();
+
+            Ok(())
+        }
         Message::WsBroadcastState => {
             state.send_state_ws(None).await;
 
             Ok(())
         }
+        Message::WsBroadcastSceneProgress { progress } => {
+            state
+                .ws
+                .send(None, &WebSocketResponse::SceneProgress(progress.clone()))
+                .await;
+
+            Ok(())
+        }
         Message::DbStoreScene { scene_id, config } => {
             db_store_scene(scene_id, config).await.ok();
             state.scenes.refresh_db_scenes().await;
@@ -108,49 +546,208 @@ pub async fn handle_message(state: &mut AppState, msg: &Message) -> Result<()> {
 
             Ok(())
         }
-        Message::Action(Action::ActivateScene(SceneDescriptor {
-            scene_id,
+        Message::ImportSnapshot { snapshot } => {
+            info!("Importing snapshot created at {}", snapshot.created_at);
+
+            for (scene_id, config) in &snapshot.db_scenes {
+                state.event_tx.send(Message::DbStoreScene {
+                    scene_id: scene_id.clone(),
+                    config: config.clone(),
+                });
+            }
+
+            for (device_key, metadata) in &snapshot.device_metadata {
+                state.event_tx.send(Message::SetDeviceMetadata {
+                    device_key: device_key.clone(),
+                    metadata: metadata.clone(),
+                });
+            }
+
+            // Sensors report their own state
+            for device in snapshot.devices.0.values().filter(|d| !d.is_sensor()) {
+                state.event_tx.send(Message::SetExpectedState {
+                    device: device.clone(),
+                    set_scene: true,
+                    skip_send: false,
+                });
+            }
+
+            state
+                .event_tx
+                .send(Message::Action(Action::SetMode(SetModeDescriptor {
+                    mode: snapshot.mode.clone(),
+                })));
+            state.event_tx.send(Message::Action(Action::SetDoNotDisturb(
+                DoNotDisturbDescriptor {
+                    enabled: Some(snapshot.do_not_disturb),
+                },
+            )));
+
+            Ok(())
+        }
+        Message::RoutineTriggerFired { routine_id } => state.rules.handle_trigger(
+            routine_id,
+            &state.devices,
+            &state.groups,
+            &state.expr,
+            &mut state.quiet_hours,
+        ),
+        Message::RoutineTriggered { routine_id } => {
+            state
+                .conflicts
+                .set_source(WriteSource::Routine(routine_id.clone()));
+
+            Ok(())
+        }
+        Message::Action(Action::ActivateScene(scene_descriptor))
+            if !state
+                .scenes
+                .is_within_window(scene_descriptor, local_now().time()) =>
+        {
+            info!(
+                "Not activating scene {} outside of its activation window",
+                scene_descriptor.scene_id
+            );
+
+            Ok(())
+        }
+        Message::Action(Action::ActivateScene(scene_descriptor)) => {
+            state
+                .conflicts
+                .set_source(WriteSource::Scene(scene_descriptor.scene_id.clone()));
+            state.commands.scene_activated(&scene_descriptor.scene_id);
+            activate_scene(state, scene_descriptor).await;
+
+            Ok(())
+        }
+        Message::Action(Action::CycleScenes(descriptor)) if !descriptor.turn_off_on.is_empty() => {
+            let area = state.scenes.get_cycle_area(
+                &descriptor.scenes,
+                &state.devices,
+                &state.groups,
+                state.expr.get_context(),
+            );
+            state
+                .button_presses
+                .handle_press(descriptor, area, &state.devices);
+
+            Ok(())
+        }
+        Message::Action(Action::CycleScenes(CycleScenesDescriptor { scenes, nowrap, .. })) => {
+            let next_scene = state.scenes.cycle_scenes(
+                scenes,
+                nowrap.unwrap_or(false),
+                &state.devices,
+                &state.groups,
+                state.expr.get_context(),
+            );
+
+            if let Some(next_scene) = next_scene {
+                state.commands.scene_activated(&next_scene.scene_id);
+                activate_scene(state, &next_scene).await;
+            }
+
+            Ok(())
+        }
+        Message::Action(Action::Dim(DimDescriptor {
             device_keys,
             group_keys,
+            areas,
+            step,
         })) => {
-            let eval_context = state.expr.get_context();
             state
                 .devices
-                .activate_scene(
-                    scene_id,
+                .dim(
                     device_keys,
                     group_keys,
+                    areas,
+                    step.unwrap_or(0.1),
                     &state.groups,
                     &state.scenes,
-                    eval_context,
                 )
                 .await;
 
             Ok(())
         }
-        Message::Action(Action::CycleScenes(CycleScenesDescriptor { scenes, nowrap })) => {
-            let eval_context = state.expr.get_context();
+        Message::Action(Action::Warmer(ColorTemperatureStepDescriptor {
+            device_keys,
+            group_keys,
+            step,
+        })) => {
+            let step = -i64::from(step.unwrap_or(250));
             state
                 .devices
-                .cycle_scenes(
-                    scenes,
-                    nowrap.unwrap_or(false),
-                    &state.groups,
-                    &state.scenes,
-                    eval_context,
-                )
+                .adjust_devices(device_keys, group_keys, &state.groups, &state.scenes, |d| {
+                    d.step_color_temperature(step)
+                })
                 .await;
 
             Ok(())
         }
-        Message::Action(Action::Dim(DimDescriptor {
+        Message::Action(Action::Cooler(ColorTemperatureStepDescriptor {
             device_keys,
             group_keys,
             step,
         })) => {
+            let step = i64::from(step.unwrap_or(250));
             state
                 .devices
-                .dim(device_keys, group_keys, step, &state.scenes)
+                .adjust_devices(device_keys, group_keys, &state.groups, &state.scenes, |d| {
+                    d.step_color_temperature(step)
+                })
+                .await;
+
+            Ok(())
+        }
+        Message::Action(Action::NudgeColor(NudgeColorDescriptor {
+            device_keys,
+            group_keys,
+            hue_step,
+            saturation_step,
+        })) => {
+            let hue_step = hue_step.unwrap_or(0);
+            let saturation_step = saturation_step.unwrap_or(0.0);
+            state
+                .devices
+                .adjust_devices(device_keys, group_keys, &state.groups, &state.scenes, |d| {
+                    d.nudge_color(hue_step, saturation_step)
+                })
+                .await;
+
+            Ok(())
+        }
+        Message::Action(Action::TurnOff(descriptor)) => {
+            state
+                .devices
+                .set_power(descriptor, false, &state.groups, &state.scenes)
+                .await;
+
+            Ok(())
+        }
+        Message::Action(Action::TurnOn(descriptor)) => {
+            state
+                .devices
+                .set_power(descriptor, true, &state.groups, &state.scenes)
+                .await;
+
+            Ok(())
+        }
+        Message::Action(Action::Brighten(DimDescriptor {
+            device_keys,
+            group_keys,
+            areas,
+            step,
+        })) => {
+            state
+                .devices
+                .dim(
+                    device_keys,
+                    group_keys,
+                    areas,
+                    -step.unwrap_or(0.1),
+                    &state.groups,
+                    &state.scenes,
+                )
                 .await;
 
             Ok(())
@@ -164,13 +761,113 @@ pub async fn handle_message(state: &mut AppState, msg: &Message) -> Result<()> {
                 .run_integration_action(integration_id, payload)
                 .await
         }
+        Message::Action(Action::Notify(notification)) => {
+            state
+                .notifications
+                .notify(
+                    notification,
+                    &state.integrations,
+                    state.quiet_hours.is_quiet(),
+                )
+                .await
+        }
+        Message::Action(Action::SetMode(SetModeDescriptor { mode })) => {
+            state.modes.set_mode(mode)?;
+
+            // Zones may have an away setpoint
+            state
+                .heating
+                .refresh(state.modes.get_mode(), &state.integrations)
+                .await;
+
+            Ok(())
+        }
+        Message::Action(Action::SetDoNotDisturb(DoNotDisturbDescriptor { enabled })) => {
+            if state.quiet_hours.set_dnd(*enabled, &state.devices) {
+                state
+                    .notifications
+                    .flush_deferred(&state.integrations)
+                    .await;
+            }
+
+            Ok(())
+        }
+        Message::Action(Action::PurgeUnseenDevices(PurgeUnseenDevicesDescriptor {
+            not_seen_for_days,
+        })) => purge_unseen_devices(state, *not_seen_for_days).await,
         Message::Action(Action::ForceTriggerRoutine(ForceTriggerRoutineDescriptor {
             routine_id,
-        })) => state.rules.force_trigger_routine(routine_id),
+        })) => state.rules.force_trigger_routine(
+            routine_id,
+            &state.devices,
+            &state.groups,
+            &state.expr,
+        ),
         Message::Action(Action::SetDeviceState(device)) => {
+            let group_id = group_id_from_device_key(&device.get_device_key());
+
+            match (group_id, device.get_controllable_state()) {
+                (Some(group_id), Some(group_state)) => {
+                    state
+                        .devices
+                        .set_group_state(&group_id, group_state, &state.groups, &state.scenes)
+                        .await;
+                }
+                _ => {
+                    state
+                        .devices
+                        .set_device_state(device, &state.scenes, false, false, false)
+                        .await;
+                }
+            }
+
+            Ok(())
+        }
+        Message::Action(Action::UpdateDeviceState(UpdateDeviceStateDescriptor {
+            device_keys,
+            group_keys,
+            state: update,
+        })) => {
             state
                 .devices
-                .set_device_state(device, &state.scenes, false, false, false)
+                .update_device_state(
+                    device_keys,
+                    group_keys,
+                    update,
+                    &state.groups,
+                    &state.scenes,
+                )
+                .await;
+
+            Ok(())
+        }
+        Message::Action(Action::SetOverride(OverrideDescriptor {
+            device_keys,
+            group_keys,
+            duration_secs,
+        })) => {
+            let device_keys =
+                state
+                    .devices
+                    .resolve_device_keys(device_keys, group_keys, &state.groups);
+            state
+                .devices
+                .set_override(device_keys, duration_secs.map(Duration::from_secs));
+
+            Ok(())
+        }
+        Message::Action(Action::ClearOverride(OverrideDescriptor {
+            device_keys,
+            group_keys,
+            ..
+        })) => {
+            let device_keys =
+                state
+                    .devices
+                    .resolve_device_keys(device_keys, group_keys, &state.groups);
+            state
+                .devices
+                .clear_override(device_keys, &state.scenes)
                 .await;
 
             Ok(())
@@ -188,3 +885,101 @@ pub async fn handle_message(state: &mut AppState, msg: &Message) -> Result<()> {
         }
     }
 }
+
+/// Activates a scene, playing its keyframes first if it has any. Keyframe
+/// transitions of other scenes affecting the same devices are cancelled.
+async fn activate_scene(state: &mut AppState, sd: &SceneDescriptor) {
+    state.scenes.record_activation(&sd.scene_id);
+
+    let device_keys: HashSet<DeviceKey> = state
+        .scenes
+        .find_scene_devices_config(&state.devices, &state.groups, sd, state.expr.get_context())
+        .map(|scene_devices_config| scene_devices_config.into_keys().collect())
+        .unwrap_or_default();
+    state.scene_transitions.cancel(&device_keys);
+
+    let keyframes = state
+        .scenes
+        .find_scene(&sd.scene_id)
+        .and_then(|scene| scene.keyframes)
+        .filter(|keyframes| !keyframes.is_empty());
+
+    if let Some(keyframes) = keyframes {
+        let devices = device_keys
+            .iter()
+            .filter_map(|device_key| state.devices.get_device(device_key).cloned())
+            .collect();
+        state.scene_transitions.start(sd, keyframes, devices);
+        return;
+    }
+
+    apply_scene(state, sd).await;
+}
+
+/// Sets devices of a scene to the scene's states
+async fn apply_scene(state: &mut AppState, sd: &SceneDescriptor) {
+    let eval_context = state.expr.get_context();
+    state
+        .scenes
+        .prepare_activation(sd, &state.devices, &state.groups, eval_context);
+
+    state
+        .devices
+        .activate_scene(sd, &state.groups, &state.scenes, eval_context)
+        .await;
+}
+
+/// Deletes devices that haven't reported their state for given number of days
+/// from the DB, and drops them from devices state along with their metadata
+async fn purge_unseen_devices(state: &mut AppState, not_seen_for_days: u32) -> Result<()> {
+    let since = Utc::now() - chrono::Duration::days(not_seen_for_days.into());
+    let unseen = db_get_unseen_devices(since).await?;
+
+    if unseen.is_empty() {
+        return Ok(());
+    }
+
+    let old_state = state.devices.get_state().clone();
+
+    for device in &unseen {
+        info!(
+            "Purging device {} ({}), last seen at {}",
+            device.name, device.key, device.last_seen_at
+        );
+
+        db_delete_device(&device.key).await?;
+        state
+            .devices
+            .get_device_configs()
+            .remove_device_metadata(&device.key);
+
+        if let Some(removed) = state.devices.remove_device(&device.key) {
+            invalidate_removed_device(state, &old_state, &removed);
+        }
+    }
+
+    state.send_state_ws(None).await;
+
+    Ok(())
+}
+
+/// Recomputes groups, scenes and expression context after a device has been
+/// removed from devices state
+fn invalidate_removed_device(state: &mut AppState, old_state: &DevicesState, removed: &Device) {
+    let new_state = state.devices.get_state().clone();
+
+    state
+        .groups
+        .invalidate(old_state, &new_state, &state.devices);
+    state.scenes.invalidate(
+        old_state,
+        &new_state,
+        removed,
+        &state.devices,
+        &state.groups,
+        state.expr.get_context(),
+    );
+    state
+        .expr
+        .invalidate(&new_state, &state.groups, &state.scenes);
+}
@@ -1,10 +1,49 @@
+pub mod admin_cli;
+pub mod appliances;
+pub mod button_presses;
+pub mod calendar;
+pub mod circuit_breaker;
+pub mod clock;
+pub mod commands;
 pub mod config;
+pub mod config_reload;
+pub mod conflicts;
+pub mod covers;
+pub mod device_config;
+pub mod device_workers;
 pub mod devices;
+pub mod event_bus;
 pub mod expr;
 pub mod groups;
+pub mod ha_discovery;
+pub mod ha_import;
+pub mod heating;
+pub mod http;
+pub mod illuminance;
 pub mod integrations;
+pub mod logging;
 pub mod message;
+pub mod modes;
+pub mod motion_lighting;
+pub mod notifications;
+pub mod open_alerts;
+pub mod persons;
+pub mod polling;
+pub mod presence;
+pub mod quiet_hours;
+pub mod rate_alerts;
+pub mod routine_templates;
 pub mod rules;
+pub mod safety;
+pub mod scene_transitions;
 pub mod scenes;
+pub mod schema;
+pub mod sensor_filters;
+pub mod sentry;
+pub mod standby;
 pub mod state;
+pub mod sun;
+pub mod telemetry;
+pub mod triggers;
+pub mod utility_meters;
 pub mod websockets;
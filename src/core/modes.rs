@@ -0,0 +1,176 @@
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use color_eyre::Result;
+use eyre::eyre;
+
+use crate::db::{
+    actions::{db_get_helper_state, db_store_helper_state},
+    spawn_db_write,
+};
+use crate::types::{
+    action::Actions,
+    device::{Device, DeviceData, DeviceId, SensorDevice},
+    event::{Message, TxEventChannel},
+    integration::IntegrationId,
+    mode::{
+        ModeId, ModesConfig, BUILTIN_MODES, DEFAULT_MODE, MODES_INTEGRATION_ID, MODE_DEVICE_ID,
+    },
+};
+
+/// Helper state id of the current mode in DB
+const MODE_STATE_ID: &str = "mode";
+
+/// Returns the exit actions of `from` followed by the entry actions of `to`
+fn transition_actions(config: &ModesConfig, from: &ModeId, to: &ModeId) -> Actions {
+    let on_exit = config.get(from).map(|mode| mode.on_exit.clone());
+    let on_enter = config.get(to).map(|mode| mode.on_enter.clone());
+
+    on_exit.into_iter().chain(on_enter).flatten().collect()
+}
+
+/// Keeps track of which mode the house is in, e.g. home, away or night
+#[derive(Clone)]
+pub struct Modes {
+    config: ModesConfig,
+    event_tx: TxEventChannel,
+    mode: ModeId,
+}
+
+impl Modes {
+    pub fn new(config: ModesConfig, event_tx: TxEventChannel) -> Self {
+        Modes {
+            config,
+            event_tx,
+            mode: ModeId::from(DEFAULT_MODE.to_string()),
+        }
+    }
+
+    /// Restores the mode we were in before a restart, without running any
+    /// entry actions
+    pub async fn restore_db_state(&mut self) {
+        let mode: Option<ModeId> = db_get_helper_state(MODE_STATE_ID).await.ok().flatten();
+
+        match mode {
+            Some(mode) if self.get_modes().contains(&mode) => {
+                info!("Restored mode {} from DB", mode);
+                self.mode = mode;
+            }
+            Some(mode) => warn!(
+                "Not restoring mode {} from DB, it's no longer configured",
+                mode
+            ),
+            None => {}
+        }
+    }
+
+    pub fn get_mode(&self) -> &ModeId {
+        &self.mode
+    }
+
+    /// Returns built-in modes along with any configured modes
+    pub fn get_modes(&self) -> BTreeSet<ModeId> {
+        BUILTIN_MODES
+            .iter()
+            .map(|mode| ModeId::from(mode.to_string()))
+            .chain(self.config.keys().cloned())
+            .collect()
+    }
+
+    /// Switches to given mode, running exit actions of the previous mode and
+    /// entry actions of the new mode
+    pub fn set_mode(&mut self, mode: &ModeId) -> Result<()> {
+        if !self.get_modes().contains(mode) {
+            return Err(eyre!("Unknown mode {}", mode));
+        }
+
+        if &self.mode == mode {
+            return Ok(());
+        }
+
+        info!("Switching mode from {} to {}", self.mode, mode);
+
+        let actions = transition_actions(&self.config, &self.mode, mode);
+        self.mode = mode.clone();
+        self.report_mode();
+
+        let mode = mode.clone();
+        spawn_db_write(async move {
+            db_store_helper_state(MODE_STATE_ID, &mode).await.ok();
+        });
+
+        for action in actions {
+            self.event_tx.send(Message::Action(action));
+        }
+
+        Ok(())
+    }
+
+    /// Reports current mode as a virtual sensor, so that rules and
+    /// expressions can refer to it
+    pub fn report_mode(&self) {
+        let device = Device::new(
+            IntegrationId::from_str(MODES_INTEGRATION_ID).unwrap(),
+            DeviceId::new(MODE_DEVICE_ID),
+            "Mode".to_string(),
+            DeviceData::Sensor(SensorDevice::Text {
+                value: self.mode.to_string(),
+            }),
+        );
+
+        self.event_tx.send(Message::RecvDeviceState { device });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::{action::Action, mode::ModeConfig, quiet_hours::DoNotDisturbDescriptor};
+
+    use super::*;
+
+    fn dnd(enabled: bool) -> Action {
+        Action::SetDoNotDisturb(DoNotDisturbDescriptor {
+            enabled: Some(enabled),
+        })
+    }
+
+    #[test]
+    fn test_transition_actions() {
+        let away = ModeId::from("away".to_string());
+        let night = ModeId::from("night".to_string());
+        let home = ModeId::from("home".to_string());
+
+        let config = ModesConfig::from([
+            (
+                away.clone(),
+                ModeConfig {
+                    on_enter: vec![dnd(true)],
+                    on_exit: vec![dnd(false)],
+                },
+            ),
+            (
+                night.clone(),
+                ModeConfig {
+                    on_enter: vec![dnd(true)],
+                    on_exit: vec![],
+                },
+            ),
+        ]);
+
+        let enabled = |actions: Actions| -> Vec<Option<bool>> {
+            actions
+                .into_iter()
+                .map(|action| match action {
+                    Action::SetDoNotDisturb(DoNotDisturbDescriptor { enabled }) => enabled,
+                    _ => panic!("Unexpected action"),
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            enabled(transition_actions(&config, &away, &night)),
+            vec![Some(false), Some(true)]
+        );
+        assert!(transition_actions(&config, &night, &home).is_empty());
+    }
+}
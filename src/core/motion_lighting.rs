@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::types::{
+    action::Action,
+    device::{Device, SensorDevice},
+    event::{Message, TxEventChannel},
+    motion_lighting::{MotionLightingConfig, MotionLightingId, MotionLightingsConfig},
+    power::PowerDescriptor,
+    scene::SceneDescriptor,
+};
+
+use super::{devices::Devices, groups::Groups};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct MotionLightingState {
+    /// Lights were turned on by us
+    active: bool,
+
+    /// Lights were adjusted by someone else while active, leave them alone
+    /// until they're turned off
+    overridden: bool,
+
+    /// Incremented to cancel pending off timers
+    generation: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MotionEvent {
+    Motion { dark: bool },
+    NoMotion,
+    Timeout { generation: u64 },
+    TargetsChanged { all_off: bool, foreign_scene: bool },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Effect {
+    TurnOn,
+    TurnOff,
+    StartTimer { generation: u64 },
+}
+
+fn step(
+    mut state: MotionLightingState,
+    event: MotionEvent,
+) -> (MotionLightingState, Option<Effect>) {
+    let effect = match event {
+        MotionEvent::Motion { dark } => {
+            // Cancels any pending off timer
+            state.generation += 1;
+
+            if !state.active && !state.overridden && dark {
+                state.active = true;
+                Some(Effect::TurnOn)
+            } else {
+                None
+            }
+        }
+        MotionEvent::NoMotion if state.active && !state.overridden => {
+            state.generation += 1;
+            Some(Effect::StartTimer {
+                generation: state.generation,
+            })
+        }
+        MotionEvent::NoMotion => None,
+        MotionEvent::Timeout { generation }
+            if generation == state.generation && state.active && !state.overridden =>
+        {
+            state.active = false;
+            Some(Effect::TurnOff)
+        }
+        MotionEvent::Timeout { .. } => None,
+        MotionEvent::TargetsChanged { all_off: true, .. } => {
+            state.active = false;
+            state.overridden = false;
+            None
+        }
+        MotionEvent::TargetsChanged {
+            foreign_scene: true,
+            ..
+        } if state.active => {
+            state.overridden = true;
+            None
+        }
+        MotionEvent::TargetsChanged { .. } => None,
+    };
+
+    (state, effect)
+}
+
+fn is_dark(config: &MotionLightingConfig, devices: &Devices) -> bool {
+    let Some(illuminance) = &config.illuminance else {
+        return true;
+    };
+
+    let value = devices
+        .get_device_by_ref(&illuminance.sensor)
+        .and_then(|device| match device.get_sensor_state()? {
+            SensorDevice::Number { value, .. } => Some(value.0),
+            _ => None,
+        });
+
+    // Rather light up a room than leave someone in the dark
+    value.map_or(true, |value| value < illuminance.below)
+}
+
+/// Declarative motion activated lighting, replacing the usual combination of
+/// routines and timers
+#[derive(Clone)]
+pub struct MotionLighting {
+    config: MotionLightingsConfig,
+    event_tx: TxEventChannel,
+    states: HashMap<MotionLightingId, MotionLightingState>,
+}
+
+impl MotionLighting {
+    pub fn new(config: MotionLightingsConfig, event_tx: TxEventChannel) -> Self {
+        let config = config
+            .into_iter()
+            .filter(|(id, config)| {
+                let has_targets = config.device_keys.is_some() || config.group_keys.is_some();
+                if !has_targets {
+                    error!(
+                        "Motion lighting {} has no device_keys or group_keys, ignoring",
+                        id
+                    );
+                }
+                has_targets
+            })
+            .collect();
+
+        MotionLighting {
+            config,
+            event_tx,
+            states: Default::default(),
+        }
+    }
+
+    pub fn handle_internal_state_update(
+        &mut self,
+        new: &Device,
+        devices: &Devices,
+        groups: &Groups,
+    ) {
+        let device_key = new.get_device_key();
+        let ids: Vec<MotionLightingId> = self.config.keys().cloned().collect();
+
+        for id in ids {
+            let config = &self.config[&id];
+
+            let is_sensor = config.sensors.iter().any(|sensor| {
+                devices
+                    .get_device_by_ref(sensor)
+                    .map(|device| device.get_device_key())
+                    == Some(device_key.clone())
+            });
+
+            let targets =
+                devices.resolve_device_keys(&config.device_keys, &config.group_keys, groups);
+
+            let event = if is_sensor {
+                let motion = config.sensors.iter().any(|sensor| {
+                    matches!(
+                        devices
+                            .get_device_by_ref(sensor)
+                            .and_then(Device::get_sensor_state),
+                        Some(SensorDevice::Boolean { value: true })
+                    )
+                });
+
+                if motion {
+                    MotionEvent::Motion {
+                        dark: is_dark(config, devices),
+                    }
+                } else {
+                    MotionEvent::NoMotion
+                }
+            } else if targets.contains(&device_key) {
+                let all_off = targets.iter().all(|key| {
+                    devices.get_device(key).and_then(Device::is_powered_on) != Some(true)
+                });
+                let foreign_scene =
+                    new.is_powered_on() == Some(true) && new.get_scene() != config.scene_id;
+
+                MotionEvent::TargetsChanged {
+                    all_off,
+                    foreign_scene,
+                }
+            } else {
+                continue;
+            };
+
+            self.apply(&id, event);
+        }
+    }
+
+    /// Off delay has elapsed
+    pub fn handle_timeout(&mut self, id: &MotionLightingId, generation: u64) {
+        if self.config.contains_key(id) {
+            self.apply(id, MotionEvent::Timeout { generation });
+        }
+    }
+
+    fn apply(&mut self, id: &MotionLightingId, event: MotionEvent) {
+        let config = &self.config[id];
+        let state = self.states.get(id).copied().unwrap_or_default();
+        let (state, effect) = step(state, event);
+        self.states.insert(id.clone(), state);
+
+        let power_descriptor = PowerDescriptor {
+            device_keys: config.device_keys.clone(),
+            group_keys: config.group_keys.clone(),
+            areas: None,
+        };
+
+        match effect {
+            Some(Effect::TurnOn) => {
+                let action = match &config.scene_id {
+                    Some(scene_id) => Action::ActivateScene(SceneDescriptor {
+                        scene_id: scene_id.clone(),
+                        device_keys: config.device_keys.clone(),
+                        group_keys: config.group_keys.clone(),
+                        transition_ms: None,
+                        brightness: None,
+                        override_window: None,
+                    }),
+                    None => Action::TurnOn(power_descriptor),
+                };
+
+                self.event_tx.send(Message::Action(action));
+            }
+            Some(Effect::TurnOff) => {
+                self.event_tx
+                    .send(Message::Action(Action::TurnOff(power_descriptor)));
+            }
+            Some(Effect::StartTimer { generation }) => {
+                let event_tx = self.event_tx.clone();
+                let id = id.clone();
+                let delay = Duration::from_secs(config.off_delay_secs);
+
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    event_tx.send(Message::MotionLightingTimeout { id, generation });
+                });
+            }
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step() {
+        let state = MotionLightingState::default();
+
+        // Too bright
+        let (state, effect) = step(state, MotionEvent::Motion { dark: false });
+        assert_eq!(effect, None);
+
+        let (state, effect) = step(state, MotionEvent::Motion { dark: true });
+        assert_eq!(effect, Some(Effect::TurnOn));
+
+        let (state, effect) = step(state, MotionEvent::NoMotion);
+        let Some(Effect::StartTimer { generation }) = effect else {
+            panic!("Expected timer to start");
+        };
+
+        // Motion resumed before the timer fired
+        let (state, _) = step(state, MotionEvent::Motion { dark: true });
+        let (state, effect) = step(state, MotionEvent::Timeout { generation });
+        assert_eq!(effect, None);
+
+        let (state, effect) = step(state, MotionEvent::NoMotion);
+        let Some(Effect::StartTimer { generation }) = effect else {
+            panic!("Expected timer to start");
+        };
+        let (state, effect) = step(state, MotionEvent::Timeout { generation });
+        assert_eq!(effect, Some(Effect::TurnOff));
+        assert!(!state.active);
+    }
+
+    #[test]
+    fn test_step_override() {
+        let (state, _) = step(Default::default(), MotionEvent::Motion { dark: true });
+
+        let (state, _) = step(
+            state,
+            MotionEvent::TargetsChanged {
+                all_off: false,
+                foreign_scene: true,
+            },
+        );
+        assert!(state.overridden);
+
+        // Lights are left alone while overridden
+        let (state, effect) = step(state, MotionEvent::NoMotion);
+        assert_eq!(effect, None);
+
+        // Until they're turned off
+        let (state, _) = step(
+            state,
+            MotionEvent::TargetsChanged {
+                all_off: true,
+                foreign_scene: false,
+            },
+        );
+        let (_, effect) = step(state, MotionEvent::Motion { dark: true });
+        assert_eq!(effect, Some(Effect::TurnOn));
+    }
+}
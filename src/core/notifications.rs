@@ -0,0 +1,267 @@
+use std::collections::BTreeSet;
+
+use chrono::NaiveTime;
+use color_eyre::Result;
+
+use crate::types::{
+    integration::IntegrationActionPayload,
+    notification::{NotificationsConfig, NotifyDescriptor, Severity},
+    quiet_hours::QuietHoursBehavior,
+};
+use crate::utils::time_in_window;
+
+use super::clock::local_now;
+use super::integrations::Integrations;
+
+/// Channels a notification should be delivered to
+#[derive(Debug, Default, PartialEq)]
+struct Routing {
+    now: BTreeSet<String>,
+
+    /// Channels to deliver to once quiet hours end
+    deferred: BTreeSet<String>,
+}
+
+/// Finds the channels a notification should be delivered to at given time of
+/// day. Critical notifications are never held back by quiet hours.
+fn route_notification(
+    config: &NotificationsConfig,
+    notification: &NotifyDescriptor,
+    time: NaiveTime,
+    quiet: bool,
+) -> Routing {
+    let severity = notification.severity.unwrap_or_default();
+    let mut routing = Routing::default();
+
+    let routes = config.routes.iter().filter(|route| {
+        severity >= route.min_severity && time_in_window(route.from, route.to, time)
+    });
+
+    for route in routes {
+        let behavior = if quiet && severity < Severity::Critical {
+            route.quiet_hours
+        } else {
+            QuietHoursBehavior::Ignore
+        };
+
+        let channel_ids = route.channels.iter().filter(|channel_id| {
+            let Some(channel) = config.channels.get(*channel_id) else {
+                warn!(
+                    "Notification route refers to unknown channel {}",
+                    channel_id
+                );
+                return false;
+            };
+
+            match (&notification.persons, &channel.person) {
+                (Some(persons), Some(person)) => persons.contains(person),
+                _ => true,
+            }
+        });
+
+        match behavior {
+            QuietHoursBehavior::Ignore => routing.now.extend(channel_ids.cloned()),
+            QuietHoursBehavior::Suppress => {}
+            QuietHoursBehavior::Defer => routing.deferred.extend(channel_ids.cloned()),
+        }
+    }
+
+    // No need to deliver twice
+    routing
+        .deferred
+        .retain(|channel_id| !routing.now.contains(channel_id));
+
+    routing
+}
+
+fn render_payload(value: &serde_json::Value, notification: &NotifyDescriptor) -> serde_json::Value {
+    match value {
+        serde_json::Value::String(s) => serde_json::Value::String(
+            s.replace("{title}", notification.title.as_deref().unwrap_or_default())
+                .replace("{message}", &notification.message)
+                .replace(
+                    "{severity}",
+                    &notification.severity.unwrap_or_default().to_string(),
+                )
+                .replace("{image}", notification.image.as_deref().unwrap_or_default()),
+        ),
+        serde_json::Value::Array(values) => values
+            .iter()
+            .map(|value| render_payload(value, notification))
+            .collect(),
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.clone(), render_payload(value, notification)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Notifications {
+    config: NotificationsConfig,
+    deferred: Vec<(String, NotifyDescriptor)>,
+}
+
+impl Notifications {
+    pub fn new(config: NotificationsConfig) -> Self {
+        Notifications {
+            config,
+            deferred: Default::default(),
+        }
+    }
+
+    /// Delivers a notification to all channels it's routed to, or holds it
+    /// back until quiet hours end for routes that opt into that
+    pub async fn notify(
+        &mut self,
+        notification: &NotifyDescriptor,
+        integrations: &Integrations,
+        quiet: bool,
+    ) -> Result<()> {
+        let time = local_now().time();
+        let routing = route_notification(&self.config, notification, time, quiet);
+
+        if routing.now.is_empty() && routing.deferred.is_empty() {
+            warn!(
+                "Notification was not routed to any channel: {}",
+                notification.message
+            );
+        }
+
+        for channel_id in routing.deferred {
+            self.deferred.push((channel_id, notification.clone()));
+        }
+
+        for channel_id in routing.now {
+            self.deliver(&channel_id, notification, integrations).await;
+        }
+
+        Ok(())
+    }
+
+    /// Delivers notifications that were held back during quiet hours
+    pub async fn flush_deferred(&mut self, integrations: &Integrations) {
+        for (channel_id, notification) in std::mem::take(&mut self.deferred) {
+            self.deliver(&channel_id, &notification, integrations).await;
+        }
+    }
+
+    async fn deliver(
+        &self,
+        channel_id: &str,
+        notification: &NotifyDescriptor,
+        integrations: &Integrations,
+    ) {
+        let channel = &self.config.channels[channel_id];
+        let payload = match render_payload(&channel.payload, notification) {
+            serde_json::Value::String(payload) => payload,
+            payload => payload.to_string(),
+        };
+
+        // Keep delivering to other channels if one of them fails
+        let result = integrations
+            .run_integration_action(
+                &channel.integration_id,
+                &IntegrationActionPayload::from(payload),
+            )
+            .await;
+
+        if let Err(e) = result {
+            error!(
+                "Failed to deliver notification to channel {}: {:?}",
+                channel_id, e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::types::{
+        integration::IntegrationId,
+        notification::{NotificationChannelConfig, NotificationRouteConfig},
+        person::PersonId,
+    };
+
+    use super::*;
+
+    fn channel(person: Option<&str>) -> NotificationChannelConfig {
+        NotificationChannelConfig {
+            integration_id: IntegrationId::from("mqtt".to_string()),
+            payload: serde_json::Value::Null,
+            person: person.map(|person| PersonId(person.to_string())),
+        }
+    }
+
+    fn time(h: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_route_notification() {
+        let config = NotificationsConfig {
+            channels: BTreeMap::from([
+                ("alice_phone".to_string(), channel(Some("alice"))),
+                ("bob_phone".to_string(), channel(Some("bob"))),
+                ("speaker".to_string(), channel(None)),
+            ]),
+            routes: vec![
+                NotificationRouteConfig {
+                    channels: vec!["alice_phone".to_string(), "bob_phone".to_string()],
+                    min_severity: Severity::Info,
+                    from: None,
+                    to: None,
+                    quiet_hours: QuietHoursBehavior::Defer,
+                },
+                NotificationRouteConfig {
+                    channels: vec!["speaker".to_string()],
+                    min_severity: Severity::Critical,
+                    from: Some(time(22)),
+                    to: Some(time(7)),
+                    quiet_hours: QuietHoursBehavior::Ignore,
+                },
+            ],
+        };
+
+        let laundry = NotifyDescriptor {
+            title: None,
+            message: "Laundry done".to_string(),
+            severity: None,
+            persons: Some(vec![PersonId("alice".to_string())]),
+            image: None,
+        };
+        assert_eq!(
+            route_notification(&config, &laundry, time(12), false).now,
+            BTreeSet::from(["alice_phone".to_string()])
+        );
+        assert_eq!(
+            route_notification(&config, &laundry, time(23), true),
+            Routing {
+                now: BTreeSet::new(),
+                deferred: BTreeSet::from(["alice_phone".to_string()]),
+            }
+        );
+
+        let leak = NotifyDescriptor {
+            title: None,
+            message: "Water leak".to_string(),
+            severity: Some(Severity::Critical),
+            persons: None,
+            image: None,
+        };
+        assert_eq!(
+            route_notification(&config, &leak, time(12), false)
+                .now
+                .len(),
+            2
+        );
+        assert_eq!(
+            route_notification(&config, &leak, time(3), true).now.len(),
+            3
+        );
+    }
+}
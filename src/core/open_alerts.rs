@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::types::{
+    action::Action,
+    device::{Device, SensorDevice},
+    event::{Message, TxEventChannel},
+    notification::{NotifyDescriptor, Severity},
+    open_alert::{ClimateCondition, OpenAlertId, OpenAlertsConfig},
+};
+
+use super::devices::Devices;
+
+fn is_open(device: &Device) -> bool {
+    matches!(
+        device.get_sensor_state(),
+        Some(SensorDevice::Boolean { value: true })
+    )
+}
+
+/// Conditions on sensors without a reading are considered met
+fn climate_condition_met(condition: &ClimateCondition, value: Option<f64>) -> bool {
+    let Some(value) = value else {
+        return true;
+    };
+
+    condition.below.map_or(true, |below| value < below)
+        && condition.above.map_or(true, |above| value > above)
+}
+
+/// Watches contact sensors and notifies when they stay open for too long
+#[derive(Clone)]
+pub struct OpenAlerts {
+    config: OpenAlertsConfig,
+    event_tx: TxEventChannel,
+
+    /// Incremented whenever a sensor opens or closes to cancel pending
+    /// alerts
+    generations: HashMap<OpenAlertId, u64>,
+}
+
+impl OpenAlerts {
+    pub fn new(config: OpenAlertsConfig, event_tx: TxEventChannel) -> Self {
+        OpenAlerts {
+            config,
+            event_tx,
+            generations: Default::default(),
+        }
+    }
+
+    pub fn handle_internal_state_update(
+        &mut self,
+        old: &Option<Device>,
+        new: &Device,
+        devices: &Devices,
+    ) {
+        let was_open = old.as_ref().map_or(false, is_open);
+        let open = is_open(new);
+
+        if was_open == open {
+            return;
+        }
+
+        let device_key = new.get_device_key();
+
+        for (id, alert) in &self.config {
+            let is_sensor = devices
+                .get_device_by_ref(&alert.sensor)
+                .map(|device| device.get_device_key())
+                == Some(device_key.clone());
+
+            if !is_sensor {
+                continue;
+            }
+
+            let generation = self.generations.entry(id.clone()).or_default();
+            *generation += 1;
+
+            if open {
+                schedule_timeout(&self.event_tx, id, *generation, alert.after_secs);
+            }
+        }
+    }
+
+    /// Sensor has been open for the configured time, or a reminder is due
+    pub fn handle_timeout(&mut self, id: &OpenAlertId, generation: u64, devices: &Devices) {
+        let Some(alert) = self.config.get(id) else {
+            return;
+        };
+
+        if self.generations.get(id) != Some(&generation) {
+            return;
+        }
+
+        let Some(sensor) = devices.get_device_by_ref(&alert.sensor) else {
+            return;
+        };
+
+        if !is_open(sensor) {
+            return;
+        }
+
+        let condition_met = alert.climate.as_ref().map_or(true, |climate| {
+            let value = devices
+                .get_device_by_ref(&climate.sensor)
+                .and_then(|device| match device.get_sensor_state()? {
+                    SensorDevice::Number { value, .. } => Some(value.0),
+                    _ => None,
+                });
+
+            climate_condition_met(climate, value)
+        });
+
+        if condition_met {
+            let message = alert
+                .message
+                .clone()
+                .unwrap_or_else(|| format!("{} has been left open", sensor.name));
+
+            self.event_tx
+                .send(Message::Action(Action::Notify(NotifyDescriptor {
+                    title: None,
+                    message,
+                    severity: Some(alert.severity.unwrap_or(Severity::Warning)),
+                    persons: alert.persons.clone(),
+                    image: None,
+                })));
+        }
+
+        if let Some(repeat_secs) = alert.repeat_secs {
+            schedule_timeout(&self.event_tx, id, generation, repeat_secs);
+        }
+    }
+}
+
+fn schedule_timeout(event_tx: &TxEventChannel, id: &OpenAlertId, generation: u64, secs: u64) {
+    let event_tx = event_tx.clone();
+    let id = id.clone();
+
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(secs)).await;
+        event_tx.send(Message::OpenAlertTimeout { id, generation });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::{device::DeviceRef, integration::IntegrationId};
+
+    use super::*;
+
+    #[test]
+    fn test_climate_condition_met() {
+        let condition = ClimateCondition {
+            sensor: DeviceRef::new_with_name(
+                IntegrationId::from("mqtt".to_string()),
+                "Outdoor temperature".to_string(),
+            ),
+            below: Some(10.0),
+            above: None,
+        };
+
+        assert!(climate_condition_met(&condition, Some(-5.0)));
+        assert!(!climate_condition_met(&condition, Some(20.0)));
+        assert!(climate_condition_met(&condition, None));
+    }
+}
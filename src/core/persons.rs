@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::db::{
+    actions::{db_get_helper_state, db_store_helper_state},
+    spawn_db_write,
+};
+use crate::types::{
+    device::{Device, DeviceData, DeviceId, DeviceKey, SensorDevice},
+    event::{Message, TxEventChannel},
+    integration::IntegrationId,
+    person::{
+        PersonConfig, PersonsConfig, TrackerMerge, ANYONE_HOME_DEVICE_ID, PERSONS_INTEGRATION_ID,
+    },
+};
+
+use super::devices::Devices;
+use super::presence::{source_present, PresenceSensors};
+
+/// Helper state id of tracker change times in DB
+const TRACKERS_STATE_ID: &str = "persons.trackers_changed";
+
+/// State of a single tracker, along with when it last changed
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TrackerState {
+    home: bool,
+    changed: DateTime<Utc>,
+}
+
+/// Combines tracker states of a person, ordered by tracker priority. Returns
+/// None if no tracker has reported a state.
+fn merge_tracker_states(merge: TrackerMerge, states: &[Option<TrackerState>]) -> Option<bool> {
+    let mut known = states.iter().flatten();
+
+    match merge {
+        TrackerMerge::Any => {
+            let states: Vec<&TrackerState> = known.collect();
+            (!states.is_empty()).then(|| states.iter().any(|state| state.home))
+        }
+        TrackerMerge::Priority => known.next().map(|state| state.home),
+        TrackerMerge::Last => known
+            .max_by_key(|state| state.changed)
+            .map(|state| state.home),
+    }
+}
+
+/// Combines presence trackers into per-person presence sensors, and an
+/// "anyone home" sensor.
+#[derive(Clone)]
+pub struct Persons {
+    config: PersonsConfig,
+    event_tx: TxEventChannel,
+    last_changed: HashMap<DeviceKey, DateTime<Utc>>,
+    sensors: PresenceSensors,
+}
+
+impl Persons {
+    pub fn new(config: PersonsConfig, event_tx: TxEventChannel) -> Self {
+        Persons {
+            config,
+            sensors: PresenceSensors::new(PERSONS_INTEGRATION_ID, event_tx.clone()),
+            event_tx,
+            last_changed: Default::default(),
+        }
+    }
+
+    /// Restores when trackers last changed from before a restart, so that
+    /// merging by most recent change keeps working
+    pub async fn restore_db_state(&mut self) {
+        if let Ok(Some(last_changed)) = db_get_helper_state(TRACKERS_STATE_ID).await {
+            self.last_changed = last_changed;
+        }
+    }
+
+    /// Keeps track of tracker changes, and updates presence sensors of persons
+    /// accordingly
+    pub fn handle_internal_state_update(
+        &mut self,
+        old: &Option<Device>,
+        new: &Device,
+        devices: &Devices,
+    ) {
+        if self.config.is_empty() {
+            return;
+        }
+
+        if new.integration_id.to_string() == PERSONS_INTEGRATION_ID {
+            if new.id.to_string() != ANYONE_HOME_DEVICE_ID {
+                self.refresh_anyone_home(devices);
+            }
+
+            return;
+        }
+
+        let old_home = old.as_ref().and_then(source_present);
+        let new_home = source_present(new);
+
+        if new_home.is_none() || old_home == new_home {
+            return;
+        }
+
+        self.last_changed.insert(new.get_device_key(), Utc::now());
+
+        let last_changed = self.last_changed.clone();
+        spawn_db_write(async move {
+            db_store_helper_state(TRACKERS_STATE_ID, &last_changed)
+                .await
+                .ok();
+        });
+
+        for (person_id, person) in &self.config {
+            if let Some(home) = self.is_home(person, devices) {
+                self.sensors.update(
+                    DeviceId::new(&person_id.to_string()),
+                    &person.name,
+                    home,
+                    Duration::from_secs(person.away_timeout_secs.unwrap_or(0)),
+                    devices,
+                );
+            }
+        }
+
+        self.refresh_anyone_home(devices);
+    }
+
+    /// The away timeout of a person has passed
+    pub fn handle_presence_timeout(
+        &mut self,
+        device_key: &DeviceKey,
+        generation: u64,
+        devices: &Devices,
+    ) {
+        self.sensors
+            .handle_timeout(&device_key.device_id, generation, devices);
+    }
+
+    /// Anyone is home while the presence sensor of any person is on, which
+    /// only turns off after the person's away timeout
+    fn refresh_anyone_home(&self, devices: &Devices) {
+        let integration_id = IntegrationId::from_str(PERSONS_INTEGRATION_ID).unwrap();
+
+        let anyone_home = self.config.keys().any(|person_id| {
+            let device_key = DeviceKey::new(
+                integration_id.clone(),
+                DeviceId::new(&person_id.to_string()),
+            );
+
+            devices.get_device(&device_key).and_then(source_present) == Some(true)
+        });
+
+        self.set_sensor(devices, ANYONE_HOME_DEVICE_ID, "Anyone home", anyone_home);
+    }
+
+    fn is_home(&self, person: &PersonConfig, devices: &Devices) -> Option<bool> {
+        let states: Vec<Option<TrackerState>> = person
+            .trackers
+            .iter()
+            .map(|tracker| {
+                let device = devices.get_device_by_ref(tracker)?;
+                let device_key = device.get_device_key();
+
+                if devices.get_unavailable_devices().contains(&device_key) {
+                    return None;
+                }
+
+                Some(TrackerState {
+                    home: source_present(device)?,
+                    changed: *self.last_changed.get(&device_key)?,
+                })
+            })
+            .collect();
+
+        merge_tracker_states(person.merge, &states)
+    }
+
+    /// Reports new state of a virtual presence sensor, if it has changed
+    fn set_sensor(&self, devices: &Devices, device_id: &str, name: &str, home: bool) {
+        let device = Device::new(
+            IntegrationId::from_str(PERSONS_INTEGRATION_ID).unwrap(),
+            DeviceId::new(device_id),
+            name.to_string(),
+            DeviceData::Sensor(SensorDevice::Boolean { value: home }),
+        );
+
+        if devices.get_device(&device.get_device_key()) != Some(&device) {
+            self.event_tx.send(Message::RecvDeviceState { device });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_merge_tracker_states() {
+        let now = Utc::now();
+        let away = Some(TrackerState {
+            home: false,
+            changed: now + Duration::seconds(10),
+        });
+        let home = Some(TrackerState {
+            home: true,
+            changed: now,
+        });
+
+        let states = [None, away, home];
+
+        assert_eq!(merge_tracker_states(TrackerMerge::Any, &states), Some(true));
+        assert_eq!(
+            merge_tracker_states(TrackerMerge::Priority, &states),
+            Some(false)
+        );
+        assert_eq!(
+            merge_tracker_states(TrackerMerge::Last, &states),
+            Some(false)
+        );
+        assert_eq!(merge_tracker_states(TrackerMerge::Any, &[None, None]), None);
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::types::{
+    device::{Device, DeviceKey},
+    event::{Message, TxEventChannel},
+    polling::PollingConfig,
+};
+
+use super::devices::Devices;
+
+/// Keeps track of when devices last reported their state, so that devices
+/// that have gone quiet can be polled
+#[derive(Clone, Default)]
+pub struct Polling {
+    config: Option<PollingConfig>,
+    last_seen: HashMap<DeviceKey, Instant>,
+    unanswered_polls: HashMap<DeviceKey, u32>,
+}
+
+impl Polling {
+    pub fn new(config: Option<PollingConfig>) -> Self {
+        Polling {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Records that given device reported its state. Returns true if the
+    /// device had unanswered polls.
+    pub fn seen(&mut self, device_key: &DeviceKey) -> bool {
+        if self.config.is_none() {
+            return false;
+        }
+
+        self.last_seen.insert(device_key.clone(), Instant::now());
+        self.unanswered_polls.remove(device_key).is_some()
+    }
+
+    /// Returns devices that haven't reported their state in the configured
+    /// time
+    pub fn get_stale_devices(&self, devices: &Devices) -> Vec<Device> {
+        let Some(config) = &self.config else {
+            return vec![];
+        };
+
+        let stale_after = Duration::from_secs(config.stale_after_secs);
+
+        self.last_seen
+            .iter()
+            .filter(|(_, last_seen)| last_seen.elapsed() >= stale_after)
+            .filter_map(|(device_key, _)| devices.get_device(device_key))
+            .cloned()
+            .collect()
+    }
+
+    /// Records that given device was polled. Returns true if the device
+    /// should now be considered unavailable.
+    pub fn polled(&mut self, device_key: &DeviceKey) -> bool {
+        let Some(config) = &self.config else {
+            return false;
+        };
+
+        let unanswered_polls = self.unanswered_polls.entry(device_key.clone()).or_default();
+        *unanswered_polls += 1;
+
+        config
+            .unavailable_after_polls
+            .map_or(false, |max| *unanswered_polls > max)
+    }
+}
+
+/// Periodically polls devices that haven't reported their state in a while
+pub async fn poll_stale_devices(event_tx: TxEventChannel) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+        event_tx.send(Message::PollStaleDevices);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::types::{device::DeviceId, integration::IntegrationId};
+
+    use super::*;
+
+    #[test]
+    fn test_unavailable_after_unanswered_polls() {
+        let mut polling = Polling::new(Some(PollingConfig {
+            stale_after_secs: 600,
+            unavailable_after_polls: Some(2),
+        }));
+        let device_key = DeviceKey::new(
+            IntegrationId::from_str("test_integration").unwrap(),
+            DeviceId::new("light"),
+        );
+
+        assert!(!polling.polled(&device_key));
+        assert!(!polling.polled(&device_key));
+        assert!(polling.polled(&device_key));
+
+        // Reporting state resets the count
+        assert!(polling.seen(&device_key));
+        assert!(!polling.polled(&device_key));
+    }
+}
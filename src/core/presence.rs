@@ -0,0 +1,314 @@
+//! Combines presence sources such as motion sensors, ping trackers and phone
+//! trackers into presence sensors of areas, and keeps presence sensors of
+//! areas and persons on until their sources have been away for the configured
+//! timeout. Phones dropping off WiFi or someone sitting still in front of a
+//! motion sensor don't make anyone away right away.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::types::{
+    device::{Device, DeviceData, DeviceId, DeviceKey, SensorDevice},
+    event::{Message, TxEventChannel},
+    integration::IntegrationId,
+    presence::{PresenceConfig, PRESENCE_INTEGRATION_ID},
+};
+
+use super::devices::Devices;
+
+/// Whether a presence source detects presence. Sensors with a boolean value
+/// are present when true, sensors with a text value when the value is "home".
+pub fn source_present(device: &Device) -> Option<bool> {
+    match &device.data {
+        DeviceData::Sensor(SensorDevice::Boolean { value }) => Some(*value),
+        DeviceData::Sensor(SensorDevice::Text { value }) => Some(value == "home"),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct AwayState {
+    /// Presence last reported by the sensor
+    reported: Option<bool>,
+
+    /// Whether the sources are away, but the timeout hasn't passed yet
+    away_pending: bool,
+
+    /// Incremented to cancel pending timers
+    generation: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum AwayEvent {
+    Sources {
+        present: bool,
+        away_timeout: Duration,
+    },
+    Timeout {
+        generation: u64,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Effect {
+    Report(bool),
+    StartTimer { generation: u64, timeout: Duration },
+}
+
+fn report(state: &mut AwayState, present: bool) -> Vec<Effect> {
+    if state.reported == Some(present) {
+        return vec![];
+    }
+
+    state.reported = Some(present);
+    vec![Effect::Report(present)]
+}
+
+fn step(mut state: AwayState, event: AwayEvent) -> (AwayState, Vec<Effect>) {
+    let effects = match event {
+        AwayEvent::Sources { present: true, .. } => {
+            state.generation += 1;
+            state.away_pending = false;
+            report(&mut state, true)
+        }
+        AwayEvent::Sources { present: false, .. }
+            if state.away_pending || state.reported == Some(false) =>
+        {
+            vec![]
+        }
+        // Nobody to wait for if the sensor wasn't on before
+        AwayEvent::Sources {
+            present: false,
+            away_timeout,
+        } if state.reported.is_none() || away_timeout.is_zero() => report(&mut state, false),
+        AwayEvent::Sources {
+            present: false,
+            away_timeout,
+        } => {
+            state.generation += 1;
+            state.away_pending = true;
+            vec![Effect::StartTimer {
+                generation: state.generation,
+                timeout: away_timeout,
+            }]
+        }
+        AwayEvent::Timeout { generation }
+            if state.away_pending && generation == state.generation =>
+        {
+            state.away_pending = false;
+            report(&mut state, false)
+        }
+        AwayEvent::Timeout { .. } => vec![],
+    };
+
+    (state, effects)
+}
+
+/// Virtual presence sensors of an integration id, which turn off once their
+/// sources have been away for their away timeout
+#[derive(Clone)]
+pub struct PresenceSensors {
+    integration_id: IntegrationId,
+    event_tx: TxEventChannel,
+
+    /// Names and states of sensors by device id
+    sensors: HashMap<DeviceId, (String, AwayState)>,
+}
+
+impl PresenceSensors {
+    pub fn new(integration_id: &str, event_tx: TxEventChannel) -> Self {
+        PresenceSensors {
+            integration_id: IntegrationId::from_str(integration_id).unwrap(),
+            event_tx,
+            sensors: Default::default(),
+        }
+    }
+
+    /// Presence detected by the sources of given sensor
+    pub fn update(
+        &mut self,
+        device_id: DeviceId,
+        name: &str,
+        present: bool,
+        away_timeout: Duration,
+        devices: &Devices,
+    ) {
+        let (_, state) = self.sensors.remove(&device_id).unwrap_or_default();
+        let (state, effects) = step(
+            state,
+            AwayEvent::Sources {
+                present,
+                away_timeout,
+            },
+        );
+        self.sensors
+            .insert(device_id.clone(), (name.to_string(), state));
+
+        for effect in effects {
+            self.apply_effect(&device_id, name, effect, devices);
+        }
+    }
+
+    /// The away timeout of given sensor has passed
+    pub fn handle_timeout(&mut self, device_id: &DeviceId, generation: u64, devices: &Devices) {
+        let Some((name, state)) = self.sensors.remove(device_id) else {
+            return;
+        };
+
+        let (state, effects) = step(state, AwayEvent::Timeout { generation });
+        self.sensors
+            .insert(device_id.clone(), (name.clone(), state));
+
+        for effect in effects {
+            self.apply_effect(device_id, &name, effect, devices);
+        }
+    }
+
+    fn apply_effect(&self, device_id: &DeviceId, name: &str, effect: Effect, devices: &Devices) {
+        match effect {
+            Effect::Report(present) => {
+                let device = Device::new(
+                    self.integration_id.clone(),
+                    device_id.clone(),
+                    name.to_string(),
+                    DeviceData::Sensor(SensorDevice::Boolean { value: present }),
+                );
+
+                if devices.get_device(&device.get_device_key()) != Some(&device) {
+                    self.event_tx.send(Message::RecvDeviceState { device });
+                }
+            }
+            Effect::StartTimer {
+                generation,
+                timeout,
+            } => {
+                let device_key = DeviceKey::new(self.integration_id.clone(), device_id.clone());
+                let event_tx = self.event_tx.clone();
+
+                tokio::spawn(async move {
+                    tokio::time::sleep(timeout).await;
+                    event_tx.send(Message::PresenceTimeout {
+                        device_key,
+                        generation,
+                    });
+                });
+            }
+        }
+    }
+}
+
+/// Presence sensors of areas, on while any of the sources of the area detects
+/// presence
+#[derive(Clone)]
+pub struct Presence {
+    config: PresenceConfig,
+    sensors: PresenceSensors,
+}
+
+impl Presence {
+    pub fn new(config: PresenceConfig, event_tx: TxEventChannel) -> Self {
+        Presence {
+            config,
+            sensors: PresenceSensors::new(PRESENCE_INTEGRATION_ID, event_tx),
+        }
+    }
+
+    /// Updates presence sensors of the areas that have `new` as a source
+    pub fn handle_internal_state_update(&mut self, new: &Device, devices: &Devices) {
+        if self.config.is_empty() || source_present(new).is_none() {
+            return;
+        }
+
+        let device_key = new.get_device_key();
+
+        for (area_id, area) in &self.config {
+            let sources: Vec<&Device> = area
+                .sources
+                .iter()
+                .filter_map(|source| devices.get_device_by_ref(source))
+                .collect();
+
+            if !sources
+                .iter()
+                .any(|source| source.get_device_key() == device_key)
+            {
+                continue;
+            }
+
+            let present = sources.iter().any(|source| {
+                !devices
+                    .get_unavailable_devices()
+                    .contains(&source.get_device_key())
+                    && source_present(source) == Some(true)
+            });
+
+            self.sensors.update(
+                DeviceId::new(&area_id.to_string()),
+                &area.name,
+                present,
+                Duration::from_secs(area.away_timeout_secs.unwrap_or(0)),
+                devices,
+            );
+        }
+    }
+
+    pub fn handle_timeout(&mut self, device_key: &DeviceKey, generation: u64, devices: &Devices) {
+        self.sensors
+            .handle_timeout(&device_key.device_id, generation, devices);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(present: bool) -> AwayEvent {
+        AwayEvent::Sources {
+            present,
+            away_timeout: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn test_step_away_timeout() {
+        let (state, effects) = step(Default::default(), sources(true));
+        assert_eq!(effects, vec![Effect::Report(true)]);
+
+        let (state, effects) = step(state, sources(false));
+        let [Effect::StartTimer { generation, .. }] = effects[..] else {
+            panic!("Expected timer to start, got {:?}", effects);
+        };
+
+        // Sources dropping out again don't restart the timer
+        let (state, effects) = step(state, sources(false));
+        assert_eq!(effects, vec![]);
+
+        let (state, effects) = step(state, AwayEvent::Timeout { generation });
+        assert_eq!(effects, vec![Effect::Report(false)]);
+        assert_eq!(state.reported, Some(false));
+    }
+
+    #[test]
+    fn test_step_back_before_timeout() {
+        let (state, _) = step(Default::default(), sources(true));
+        let (state, effects) = step(state, sources(false));
+        let [Effect::StartTimer { generation, .. }] = effects[..] else {
+            panic!("Expected timer to start, got {:?}", effects);
+        };
+
+        let (state, effects) = step(state, sources(true));
+        assert_eq!(effects, vec![]);
+
+        // Timer of the earlier absence is stale
+        let (state, effects) = step(state, AwayEvent::Timeout { generation });
+        assert_eq!(effects, vec![]);
+        assert_eq!(state.reported, Some(true));
+    }
+
+    #[test]
+    fn test_step_initially_away() {
+        let (_, effects) = step(Default::default(), sources(false));
+        assert_eq!(effects, vec![Effect::Report(false)]);
+    }
+}
@@ -0,0 +1,137 @@
+use chrono::NaiveTime;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::db::{
+    actions::{db_get_helper_state, db_store_helper_state},
+    spawn_db_write,
+};
+use crate::types::{
+    action::Actions,
+    device::{Device, DeviceData, DeviceId, SensorDevice},
+    event::{Message, TxEventChannel},
+    integration::IntegrationId,
+    quiet_hours::{
+        QuietHoursBehavior, QuietHoursConfig, QUIET_HOURS_DEVICE_ID, QUIET_HOURS_INTEGRATION_ID,
+    },
+};
+use crate::utils::time_in_window;
+
+use super::clock::local_now;
+use super::devices::Devices;
+
+/// Helper state id of the do not disturb toggle in DB
+const DND_STATE_ID: &str = "do_not_disturb";
+
+/// Keeps track of quiet hours and the do not disturb toggle, and holds back
+/// actions of routines that opt into respecting them.
+#[derive(Clone)]
+pub struct QuietHours {
+    config: Option<QuietHoursConfig>,
+    event_tx: TxEventChannel,
+    dnd: bool,
+    deferred_actions: Actions,
+}
+
+impl QuietHours {
+    pub fn new(config: Option<QuietHoursConfig>, event_tx: TxEventChannel) -> Self {
+        QuietHours {
+            config,
+            event_tx,
+            dnd: false,
+            deferred_actions: Default::default(),
+        }
+    }
+
+    /// Restores the do not disturb toggle from before a restart
+    pub async fn restore_db_state(&mut self) {
+        if let Ok(Some(dnd)) = db_get_helper_state(DND_STATE_ID).await {
+            self.dnd = dnd;
+
+            // Report the sensor even if quiet hours aren't configured
+            self.event_tx.send(Message::RefreshQuietHours);
+        }
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        self.is_quiet_at(local_now().time())
+    }
+
+    /// Whether it's quiet at the given local time
+    pub fn is_quiet_at(&self, time: NaiveTime) -> bool {
+        let in_quiet_hours = self.config.as_ref().map_or(false, |config| {
+            time_in_window(Some(config.from), Some(config.to), time)
+        });
+
+        self.dnd || in_quiet_hours
+    }
+
+    pub fn is_dnd(&self) -> bool {
+        self.dnd
+    }
+
+    /// Returns true if this ended quiet hours, see [QuietHours::refresh]
+    pub fn set_dnd(&mut self, enabled: Option<bool>, devices: &Devices) -> bool {
+        self.dnd = enabled.unwrap_or(!self.dnd);
+
+        let dnd = self.dnd;
+        spawn_db_write(async move {
+            db_store_helper_state(DND_STATE_ID, &dnd).await.ok();
+        });
+
+        self.refresh(devices)
+    }
+
+    /// Returns the actions that should run right away, deferring or dropping
+    /// the rest depending on `behavior`.
+    pub fn filter_actions(&mut self, behavior: QuietHoursBehavior, actions: Actions) -> Actions {
+        if behavior == QuietHoursBehavior::Ignore || !self.is_quiet() {
+            return actions;
+        }
+
+        if behavior == QuietHoursBehavior::Defer {
+            self.deferred_actions.extend(actions);
+        }
+
+        vec![]
+    }
+
+    /// Reports new state of the virtual quiet hours sensor if it has changed.
+    /// Returns true if quiet hours just ended, in which case deferred actions
+    /// have been sent.
+    pub fn refresh(&mut self, devices: &Devices) -> bool {
+        let quiet = self.is_quiet();
+        let device = Device::new(
+            IntegrationId::from_str(QUIET_HOURS_INTEGRATION_ID).unwrap(),
+            DeviceId::new(QUIET_HOURS_DEVICE_ID),
+            "Quiet hours".to_string(),
+            DeviceData::Sensor(SensorDevice::Boolean { value: quiet }),
+        );
+
+        let prev = devices.get_device(&device.get_device_key());
+        if prev == Some(&device) {
+            return false;
+        }
+
+        self.event_tx.send(Message::RecvDeviceState { device });
+
+        let ended = prev.is_some() && !quiet;
+        if ended {
+            for action in self.deferred_actions.drain(..) {
+                self.event_tx.send(Message::Action(action));
+            }
+        }
+
+        ended
+    }
+}
+
+/// Periodically re-evaluates whether we're within quiet hours
+pub async fn refresh_quiet_hours(event_tx: TxEventChannel) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+        event_tx.send(Message::RefreshQuietHours);
+    }
+}
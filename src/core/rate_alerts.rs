@@ -0,0 +1,192 @@
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::types::{
+    device::{Device, DeviceData, DeviceId, SensorDevice},
+    event::{Message, TxEventChannel},
+    integration::IntegrationId,
+    rate_alert::{RateAlertConfig, RateAlertId, RateAlertsConfig, RATE_ALERTS_INTEGRATION_ID},
+};
+
+use super::devices::Devices;
+
+/// Readings of a sensor within the window of a rate alert, oldest first
+#[derive(Clone, Debug, Default)]
+struct Samples(VecDeque<(Instant, f64)>);
+
+impl Samples {
+    fn push(&mut self, at: Instant, value: f64) {
+        self.0.push_back((at, value));
+    }
+
+    /// Drops readings older than `window`, always keeping the latest one
+    fn prune(&mut self, window: Duration, now: Instant) {
+        while self.0.len() > 1
+            && self
+                .0
+                .front()
+                .map_or(false, |(at, _)| now.duration_since(*at) > window)
+        {
+            self.0.pop_front();
+        }
+    }
+}
+
+/// Whether readings have changed faster than the alert allows
+fn is_alerting(config: &RateAlertConfig, samples: &Samples) -> bool {
+    let Some((_, latest)) = samples.0.back() else {
+        return false;
+    };
+
+    let values = samples.0.iter().map(|(_, value)| *value);
+    let highest = values.clone().fold(f64::NEG_INFINITY, f64::max);
+    let lowest = values.fold(f64::INFINITY, f64::min);
+
+    let fell = config
+        .falls_by
+        .map_or(false, |falls_by| highest - latest >= falls_by);
+    let rose = config
+        .rises_by
+        .map_or(false, |rises_by| latest - lowest >= rises_by);
+
+    fell || rose
+}
+
+fn get_value(device: &Device) -> Option<f64> {
+    match device.get_sensor_state()? {
+        SensorDevice::Number { value, .. } => Some(value.0),
+        _ => None,
+    }
+}
+
+/// Derives on/off sensors that are on while a numeric sensor is changing
+/// abnormally fast, for routines to act on
+#[derive(Clone)]
+pub struct RateAlerts {
+    config: RateAlertsConfig,
+    event_tx: TxEventChannel,
+    samples: HashMap<RateAlertId, Samples>,
+}
+
+impl RateAlerts {
+    pub fn new(config: RateAlertsConfig, event_tx: TxEventChannel) -> Self {
+        RateAlerts {
+            config,
+            event_tx,
+            samples: Default::default(),
+        }
+    }
+
+    /// Records new readings of watched sensors
+    pub fn handle_internal_state_update(&mut self, new: &Device, devices: &Devices) {
+        let Some(value) = get_value(new) else {
+            return;
+        };
+
+        let device_key = new.get_device_key();
+        let now = Instant::now();
+
+        let alert_ids: Vec<RateAlertId> = self
+            .config
+            .iter()
+            .filter(|(_, alert)| {
+                devices
+                    .get_device_by_ref(&alert.sensor)
+                    .map(|device| device.get_device_key())
+                    == Some(device_key.clone())
+            })
+            .map(|(alert_id, _)| alert_id.clone())
+            .collect();
+
+        for alert_id in alert_ids {
+            self.samples
+                .entry(alert_id.clone())
+                .or_default()
+                .push(now, value);
+            self.evaluate(&alert_id, devices, now);
+        }
+    }
+
+    /// Re-evaluates all alerts, used when old readings leave the window
+    pub fn refresh(&mut self, devices: &Devices) {
+        let alert_ids: Vec<RateAlertId> = self.config.keys().cloned().collect();
+        let now = Instant::now();
+
+        for alert_id in alert_ids {
+            self.evaluate(&alert_id, devices, now);
+        }
+    }
+
+    fn evaluate(&mut self, alert_id: &RateAlertId, devices: &Devices, now: Instant) {
+        let alert = &self.config[alert_id];
+        let window = Duration::from_secs(alert.window_secs);
+
+        let samples = self.samples.entry(alert_id.clone()).or_default();
+        samples.prune(window, now);
+        let alerting = is_alerting(alert, samples);
+
+        // Alerts clear once the readings that caused them leave the window
+        if alerting {
+            let event_tx = self.event_tx.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(window).await;
+                event_tx.send(Message::RefreshRateAlerts);
+            });
+        }
+
+        let device = Device::new(
+            IntegrationId::from_str(RATE_ALERTS_INTEGRATION_ID).unwrap(),
+            DeviceId::new(&alert_id.to_string()),
+            alert.name.clone(),
+            DeviceData::Sensor(SensorDevice::Boolean { value: alerting }),
+        );
+
+        if devices.get_device(&device.get_device_key()) != Some(&device) {
+            self.event_tx.send(Message::RecvDeviceState { device });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::device::DeviceRef;
+
+    use super::*;
+
+    #[test]
+    fn test_is_alerting() {
+        let config = RateAlertConfig {
+            name: "Living room cooling fast".to_string(),
+            sensor: DeviceRef::new_with_name(
+                IntegrationId::from("zigbee".to_string()),
+                "Living room temperature".to_string(),
+            ),
+            window_secs: 900,
+            falls_by: Some(3.0),
+            rises_by: None,
+        };
+
+        let now = Instant::now();
+        let secs = |secs| now + Duration::from_secs(secs);
+        let window = Duration::from_secs(config.window_secs);
+        let mut samples = Samples::default();
+
+        samples.push(now, 22.0);
+        samples.push(secs(300), 21.0);
+        assert!(!is_alerting(&config, &samples));
+
+        samples.push(secs(600), 18.5);
+        samples.prune(window, secs(600));
+        assert!(is_alerting(&config, &samples));
+
+        // The 22.0 reading has left the window
+        samples.push(secs(1000), 18.5);
+        samples.prune(window, secs(1000));
+        assert!(!is_alerting(&config, &samples));
+
+        // Rising temperatures don't count
+        samples.push(secs(1100), 25.0);
+        assert!(!is_alerting(&config, &samples));
+    }
+}
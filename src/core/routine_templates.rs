@@ -0,0 +1,183 @@
+//! Instantiates routine templates, so that nearly identical routines such as
+//! motion lighting in every room can share a single definition
+
+use std::collections::HashMap;
+
+use color_eyre::Result;
+use eyre::{eyre, Context};
+use serde_json::Value;
+
+use crate::types::rule::{
+    Routine, RoutineConfig, RoutineConfigs, RoutineTemplateInstance, RoutineTemplatesConfig,
+    RoutinesConfig,
+};
+
+/// Substitutes variables into all strings of given template value
+fn substitute(value: &Value, vars: &HashMap<String, Value>) -> Result<Value> {
+    let substituted = match value {
+        Value::String(s) => substitute_str(s, vars)?,
+        Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| substitute(value, vars))
+                .collect::<Result<_>>()?,
+        ),
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), substitute(value, vars)?)))
+                .collect::<Result<_>>()?,
+        ),
+        _ => value.clone(),
+    };
+
+    Ok(substituted)
+}
+
+fn substitute_str(s: &str, vars: &HashMap<String, Value>) -> Result<Value> {
+    let lookup = |name: &str| {
+        vars.get(name.trim())
+            .ok_or_else(|| eyre!("Undefined variable {}", name.trim()))
+    };
+
+    // A lone placeholder keeps the type of the variable
+    if let Some(name) = s
+        .strip_prefix("{{")
+        .and_then(|rest| rest.strip_suffix("}}"))
+        .filter(|name| !name.contains("{{") && !name.contains("}}"))
+    {
+        return lookup(name).cloned();
+    }
+
+    let mut result = String::new();
+    let mut rest = s;
+
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| eyre!("Unterminated placeholder in {:?}", s))?;
+
+        result.push_str(&rest[..start]);
+        match lookup(&rest[start + 2..start + end])? {
+            Value::String(value) => result.push_str(value),
+            value @ (Value::Number(_) | Value::Bool(_)) => result.push_str(&value.to_string()),
+            value => {
+                return Err(eyre!(
+                    "Variable {} can't be used within a string: {}",
+                    &rest[start + 2..start + end],
+                    value
+                ))
+            }
+        }
+
+        rest = &rest[start + end + 2..];
+    }
+
+    result.push_str(rest);
+
+    Ok(Value::String(result))
+}
+
+fn instantiate(
+    instance: &RoutineTemplateInstance,
+    templates: &RoutineTemplatesConfig,
+) -> Result<Routine> {
+    let template = templates
+        .get(&instance.template)
+        .ok_or_else(|| eyre!("Unknown routine template {}", instance.template))?;
+
+    let routine = substitute(template, &instance.vars)?;
+
+    Ok(serde_json::from_value(routine)?)
+}
+
+/// Returns routines of the `[routines]` config section, with instances of
+/// templates replaced by the routines they expand to
+pub fn instantiate_routines(
+    routines: RoutineConfigs,
+    templates: &RoutineTemplatesConfig,
+) -> Result<RoutinesConfig> {
+    routines
+        .into_iter()
+        .map(|(routine_id, routine)| {
+            let routine = match routine {
+                RoutineConfig::Routine(routine) => routine,
+                RoutineConfig::Template(instance) => instantiate(&instance, templates)
+                    .wrap_err_with(|| format!("Failed to instantiate routine {}", routine_id))?,
+            };
+
+            Ok((routine_id, routine))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::rule::{RoutineId, RoutineTemplateId, Rule};
+    use serde_json::json;
+
+    fn mk_templates() -> RoutineTemplatesConfig {
+        HashMap::from([(
+            RoutineTemplateId("motion".to_string()),
+            json!({
+                "name": "Motion in {{room}}",
+                "rules": [
+                    { "integration_id": "hue", "name": "{{room}} motion", "state": { "value": true } },
+                    { "group_id": "{{group}}", "power": false },
+                ],
+                "actions": [
+                    { "action": "ActivateScene", "scene_id": "normal", "group_keys": "{{groups}}" },
+                ],
+            }),
+        )])
+    }
+
+    fn mk_instance(vars: Value) -> RoutineConfig {
+        RoutineConfig::Template(RoutineTemplateInstance {
+            template: RoutineTemplateId("motion".to_string()),
+            vars: serde_json::from_value(vars).unwrap(),
+        })
+    }
+
+    #[test]
+    fn test_instantiate_routines() {
+        let routine_id = RoutineId("kitchen_motion".to_string());
+        let routines = HashMap::from([(
+            routine_id.clone(),
+            mk_instance(json!({ "room": "Kitchen", "group": "kitchen", "groups": ["kitchen"] })),
+        )]);
+
+        let routines = instantiate_routines(routines, &mk_templates()).unwrap();
+        let routine = &routines[&routine_id];
+
+        assert_eq!(routine.name, "Motion in Kitchen");
+        assert!(matches!(&routine.rules[1], Rule::Group(rule) if rule.group_id.0 == "kitchen"));
+    }
+
+    #[test]
+    fn test_undefined_variable() {
+        let routines = HashMap::from([(
+            RoutineId("kitchen_motion".to_string()),
+            mk_instance(json!({ "room": "Kitchen" })),
+        )]);
+
+        assert!(instantiate_routines(routines, &mk_templates()).is_err());
+    }
+
+    #[test]
+    fn test_substitute_str() {
+        let vars = HashMap::from([
+            ("room".to_string(), json!("Hall")),
+            ("level".to_string(), json!(10)),
+        ]);
+
+        assert_eq!(
+            substitute_str("{{ room }} below {{level}} lx", &vars).unwrap(),
+            json!("Hall below 10 lx")
+        );
+        assert_eq!(substitute_str("{{level}}", &vars).unwrap(), json!(10));
+        assert_eq!(substitute_str("plain", &vars).unwrap(), json!("plain"));
+        assert!(substitute_str("{{room", &vars).is_err());
+    }
+}
@@ -1,21 +1,45 @@
-use evalexpr::HashMapContext;
+use chrono::NaiveDate;
 use eyre::{ContextCompat, Result};
 
 use crate::types::{
     action::Actions,
-    device::{Device, DevicesState, SensorDevice},
-    event::{Message, TxEventChannel},
-    rule::{AnyRule, DeviceRule, GroupRule, Routine, RoutineId, RoutinesConfig, Rule},
+    device::{Device, DeviceData, DevicesState, SensorDevice},
+    event::{mk_event_channel, CorrelationId, Message, TxEventChannel},
+    quiet_hours::QuietHoursBehavior,
+    rule::{
+        AnyRule, DeviceRule, GroupRule, IlluminanceRule, Routine, RoutineAction, RoutineBranch,
+        RoutineId, RoutineStats, RoutinesConfig, Rule, StartupTrigger, Trigger,
+    },
+    simulation::{SimulatedOutcome, SimulatedRoutine, SimulationDescriptor, SimulationStep},
+    sun::LocationConfig,
 };
-use std::collections::HashSet;
+use chrono::Utc;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::instrument;
 
-use super::{devices::Devices, expr::Expr, groups::Groups};
+use super::{
+    clock::{local_now, local_today},
+    devices::Devices,
+    expr::Expr,
+    groups::Groups,
+    illuminance::IlluminanceStates,
+    quiet_hours::QuietHours,
+    scenes::Scenes,
+    telemetry::record_duration,
+    triggers::{spawn_triggers, validate_triggers},
+};
 
 #[derive(Clone)]
 pub struct Rules {
     config: RoutinesConfig,
     event_tx: TxEventChannel,
     prev_triggered_routine_ids: Option<HashSet<RoutineId>>,
+    illuminance: IlluminanceStates,
+    stats: HashMap<RoutineId, RoutineStats>,
+    trigger_tasks: Arc<Vec<JoinHandle<()>>>,
 }
 
 impl Rules {
@@ -24,66 +48,354 @@ impl Rules {
             config,
             event_tx,
             prev_triggered_routine_ids: Default::default(),
+            illuminance: Default::default(),
+            stats: Default::default(),
+            trigger_tasks: Default::default(),
+        }
+    }
+
+    /// Spawns tasks for the triggers of all routines, replacing any that were
+    /// spawned before
+    pub fn spawn_triggers(&mut self, location: Option<&LocationConfig>) -> Result<()> {
+        let trigger_tasks = spawn_triggers(self.get_triggers(), location, &self.event_tx)?;
+
+        for task in self.trigger_tasks.iter() {
+            task.abort();
         }
+        self.trigger_tasks = Arc::new(trigger_tasks);
+
+        Ok(())
+    }
+
+    /// Replaces the routines config after the config file has changed, keeping
+    /// the previous config if a trigger is invalid. Routines whose rules
+    /// currently match are considered already triggered, so that reloading
+    /// doesn't run them.
+    pub fn reload_config(
+        &mut self,
+        config: RoutinesConfig,
+        location: Option<&LocationConfig>,
+        devices: &Devices,
+        groups: &Groups,
+        expr: &Expr,
+    ) -> Result<()> {
+        let prev_config = std::mem::replace(&mut self.config, config);
+        if let Err(e) = self.spawn_triggers(location) {
+            self.config = prev_config;
+            return Err(e);
+        }
+
+        self.stats
+            .retain(|routine_id, _| self.config.contains_key(routine_id));
+        self.prev_triggered_routine_ids =
+            Some(self.get_triggered_routine_ids(devices, groups, expr, local_today()));
+
+        Ok(())
+    }
+
+    /// Execution statistics of every configured routine
+    pub fn get_stats(&self) -> HashMap<RoutineId, RoutineStats> {
+        self.config
+            .keys()
+            .map(|routine_id| {
+                let stats = self.stats.get(routine_id).cloned().unwrap_or_default();
+                (routine_id.clone(), stats)
+            })
+            .collect()
+    }
+
+    fn record_evaluation(&mut self, routine_id: &RoutineId, duration: Duration) {
+        record_duration(
+            "homectl.rules.routine_evaluation",
+            vec![("routine_id", routine_id.to_string())],
+            duration,
+        );
+
+        let micros = duration.as_micros() as u64;
+        let stats = self.stats.entry(routine_id.clone()).or_default();
+        stats.evaluation_count += 1;
+        stats.evaluation_total_us += micros;
+        stats.evaluation_max_us = stats.evaluation_max_us.max(micros);
     }
 
     /// An internal state update has occurred, we need to check if any rules are
-    /// triggered by this change and run actions of triggered rules.
+    /// triggered by this change and run actions of triggered rules. Actions of
+    /// routines that respect quiet hours may be held back.
+    #[instrument(skip_all)]
     pub async fn handle_internal_state_update(
         &mut self,
         old_state: &DevicesState,
         new_state: &DevicesState,
-        old: &Option<Device>,
         devices: &Devices,
         groups: &Groups,
         expr: &Expr,
+        quiet_hours: &mut QuietHours,
     ) {
-        match old {
-            Some(_) => {
-                let matching_actions =
-                    self.find_matching_actions(old_state, new_state, devices, groups, expr);
-
-                for action in matching_actions {
-                    self.event_tx.send(Message::Action(action.clone()));
-                }
+        let started_at = Instant::now();
+        let today = local_today();
+        let matching_routines =
+            self.find_matching_routines(old_state, new_state, devices, groups, expr, today);
+        record_duration("homectl.rules.evaluation", vec![], started_at.elapsed());
+
+        for (routine_id, routine) in matching_routines {
+            let actions = resolve_actions(
+                devices,
+                groups,
+                &self.illuminance,
+                &routine,
+                &routine.actions,
+                expr,
+                today,
+            );
+            let actions = quiet_hours.filter_actions(routine.quiet_hours, actions);
+            self.send_routine_actions(routine_id, actions);
+        }
+    }
+
+    /// Triggers of routines that run at a given moment rather than when their
+    /// rules start matching
+    pub fn get_triggers(&self) -> Vec<(RoutineId, Trigger)> {
+        routine_triggers(&self.config)
+    }
+
+    /// Integrations have completed their start pass, fires startup triggers
+    pub fn handle_integrations_started(&self) {
+        for (routine_id, trigger) in self.get_triggers() {
+            if matches!(
+                trigger,
+                Trigger::Startup(StartupTrigger { on_startup: true })
+            ) {
+                info!("Running startup routine {}", routine_id);
+                self.event_tx
+                    .send(Message::RoutineTriggerFired { routine_id });
             }
-            None => {}
         }
     }
 
-    pub fn force_trigger_routine(&self, routine_id: &RoutineId) -> Result<()> {
+    /// The trigger of a routine has fired, run its actions if its rules match
+    pub fn handle_trigger(
+        &mut self,
+        routine_id: &RoutineId,
+        devices: &Devices,
+        groups: &Groups,
+        expr: &Expr,
+        quiet_hours: &mut QuietHours,
+    ) -> Result<()> {
         let routine = self
             .config
             .get(routine_id)
             .with_context(|| eyre!("Routine not found"))?;
 
-        let routine_actions = routine.actions.clone();
+        let started_at = Instant::now();
+        let today = local_today();
+        let matching = are_rules_matching(
+            devices,
+            groups,
+            &self.illuminance,
+            &routine.name,
+            &routine.rules,
+            expr,
+            today,
+        );
+        let actions = matching.then(|| {
+            let actions = resolve_actions(
+                devices,
+                groups,
+                &self.illuminance,
+                routine,
+                &routine.actions,
+                expr,
+                today,
+            );
+            quiet_hours.filter_actions(routine.quiet_hours, actions)
+        });
+        self.record_evaluation(routine_id, started_at.elapsed());
 
-        for action in routine_actions {
-            self.event_tx.send(Message::Action(action.clone()));
-        }
+        let Some(actions) = actions else {
+            debug!("Routine {} fired, but its rules don't match", routine_id);
+            return Ok(());
+        };
+
+        self.send_routine_actions(routine_id.clone(), actions);
+
+        Ok(())
+    }
+
+    /// Runs actions of a routine regardless of its rules, branches within its
+    /// actions are still evaluated
+    pub fn force_trigger_routine(
+        &mut self,
+        routine_id: &RoutineId,
+        devices: &Devices,
+        groups: &Groups,
+        expr: &Expr,
+    ) -> Result<()> {
+        let routine = self
+            .config
+            .get(routine_id)
+            .with_context(|| eyre!("Routine not found"))?;
+
+        let actions = resolve_actions(
+            devices,
+            groups,
+            &self.illuminance,
+            routine,
+            &routine.actions,
+            expr,
+            local_today(),
+        );
+        self.send_routine_actions(routine_id.clone(), actions);
 
         Ok(())
     }
 
-    /// Find any rules that were triggered by transitioning from `old_state` to
-    /// `new_state`, and return all actions of those rules.
-    fn find_matching_actions(
+    /// Sends actions of a routine as a new message chain, so that device
+    /// writes can be attributed to the routine that caused them.
+    fn send_routine_actions(&mut self, routine_id: RoutineId, actions: Actions) {
+        let stats = self.stats.entry(routine_id.clone()).or_default();
+        stats.trigger_count += 1;
+        stats.last_triggered_at = Some(Utc::now());
+
+        let correlation_id = CorrelationId::next();
+        debug!(
+            "Routine {} triggered, continuing as {}",
+            routine_id, correlation_id
+        );
+
+        self.event_tx
+            .send_with_correlation_id(correlation_id, Message::RoutineTriggered { routine_id });
+
+        for action in actions {
+            self.event_tx
+                .send_with_correlation_id(correlation_id, Message::Action(action));
+        }
+    }
+
+    /// Feeds synthetic sensor events through a copy of current state and the
+    /// rules engine, using a virtual clock for quiet hours. Actions of
+    /// triggered routines are reported, but not run. Calendar rules are checked
+    /// against the date of the virtual clock.
+    pub async fn simulate(
+        &self,
+        descriptor: &SimulationDescriptor,
+        devices: &Devices,
+        scenes: &Scenes,
+        groups: &Groups,
+        expr: &Expr,
+        quiet_hours: &QuietHours,
+    ) -> Vec<SimulationStep> {
+        // Messages sent by the copies are dropped along with the receiver
+        let (event_tx, _event_rx) = mk_event_channel();
+        let mut devices = devices.detached(event_tx.clone());
+        let mut expr = expr.clone();
+        let mut rules = Rules {
+            event_tx,
+            ..self.clone()
+        };
+
+        let start = descriptor.start.unwrap_or_else(local_now);
+
+        let mut events = descriptor.events.clone();
+        events.sort_by_key(|event| event.after_secs);
+
+        let mut steps = vec![];
+
+        for event in events {
+            let at = start + chrono::Duration::seconds(event.after_secs as i64);
+            let mut step = SimulationStep {
+                at,
+                device: event.device.clone(),
+                device_key: None,
+                routines: vec![],
+            };
+
+            let Some(device) = devices.get_device_by_ref(&event.device) else {
+                steps.push(step);
+                continue;
+            };
+
+            let mut device = device.clone();
+            device.data = DeviceData::Sensor(event.state);
+            step.device_key = Some(device.get_device_key());
+
+            let old_state = devices.get_state().clone();
+            devices
+                .set_device_state(&device, scenes, false, true, true)
+                .await;
+            let new_state = devices.get_state().clone();
+            expr.invalidate(&new_state, groups, scenes);
+
+            let quiet = quiet_hours.is_quiet_at(at.time());
+            let matching_routines = rules.find_matching_routines(
+                &old_state,
+                &new_state,
+                &devices,
+                groups,
+                &expr,
+                at.date(),
+            );
+
+            for (routine_id, routine) in matching_routines {
+                let outcome = match routine.quiet_hours {
+                    QuietHoursBehavior::Ignore => SimulatedOutcome::Run,
+                    _ if !quiet => SimulatedOutcome::Run,
+                    QuietHoursBehavior::Defer => SimulatedOutcome::Deferred,
+                    QuietHoursBehavior::Suppress => SimulatedOutcome::Suppressed,
+                };
+
+                let actions = resolve_actions(
+                    &devices,
+                    groups,
+                    &rules.illuminance,
+                    &routine,
+                    &routine.actions,
+                    &expr,
+                    at.date(),
+                );
+
+                step.routines.push(SimulatedRoutine {
+                    routine_id,
+                    name: routine.name,
+                    outcome,
+                    actions,
+                });
+            }
+
+            steps.push(step);
+        }
+
+        steps
+    }
+
+    /// Find any routines that were triggered by transitioning from `old_state`
+    /// to `new_state`.
+    fn find_matching_routines(
         &mut self,
         old_state: &DevicesState,
         new_state: &DevicesState,
         devices: &Devices,
         groups: &Groups,
         expr: &Expr,
-    ) -> Actions {
+        today: NaiveDate,
+    ) -> Vec<(RoutineId, Routine)> {
         // if states are equal we can bail out early
         if old_state == new_state {
             return vec![];
         }
 
+        let illuminance_rules = self.config.values().flat_map(|routine| {
+            let mut rules = illuminance_rules(&routine.rules);
+            for condition in branch_conditions(&routine.actions) {
+                rules.extend(illuminance_rules(condition));
+            }
+            rules
+        });
+        self.illuminance.update(illuminance_rules, devices);
+
         let prev_triggered_routine_ids =
             self.prev_triggered_routine_ids.clone().unwrap_or_default();
-        let new_triggered_routine_ids = self.get_triggered_routine_ids(devices, groups, expr);
+        let new_triggered_routine_ids =
+            self.get_triggered_routine_ids(devices, groups, expr, today);
 
         {
             self.prev_triggered_routine_ids = Some(new_triggered_routine_ids.clone());
@@ -94,12 +406,12 @@ impl Rules {
         let triggered_routine_ids =
             new_triggered_routine_ids.difference(&prev_triggered_routine_ids);
 
-        triggered_routine_ids.flat_map(|id| {
+        triggered_routine_ids.map(|id| {
                 let routine = self
                     .config
                     .get(id)
                     .expect("Expected triggered_routine_ids to only contain ids of routines existing in the RoutinesConfig");
-                routine.actions.clone()
+                (id.clone(), routine.clone())
             })
             .collect()
     }
@@ -107,41 +419,148 @@ impl Rules {
     /// Returns a set of routine ids that are currently triggered with the given
     /// state.
     fn get_triggered_routine_ids(
-        &self,
+        &mut self,
         devices: &Devices,
         groups: &Groups,
         expr: &Expr,
+        today: NaiveDate,
     ) -> HashSet<RoutineId> {
-        let eval_context = expr.get_context();
+        let mut triggered_routine_ids = HashSet::new();
+        let mut durations = vec![];
 
-        let triggered_routine_ids: HashSet<RoutineId> = self
-            .config
-            .iter()
-            .filter(|(_, routine)| is_routine_triggered(devices, groups, routine, eval_context))
-            .map(|(routine_id, _)| routine_id.clone())
-            .collect();
+        for (routine_id, routine) in &self.config {
+            if routine.trigger.is_some() {
+                continue;
+            }
+
+            let started_at = Instant::now();
+            let triggered =
+                is_routine_triggered(devices, groups, &self.illuminance, routine, expr, today);
+            durations.push((routine_id.clone(), started_at.elapsed()));
+
+            if triggered {
+                triggered_routine_ids.insert(routine_id.clone());
+            }
+        }
+
+        for (routine_id, duration) in durations {
+            self.record_evaluation(&routine_id, duration);
+        }
 
         triggered_routine_ids
     }
 }
 
+/// Returns all illuminance rules, including ones nested in [Rule::Any]
+fn illuminance_rules(rules: &[Rule]) -> Vec<&IlluminanceRule> {
+    rules
+        .iter()
+        .flat_map(|rule| match rule {
+            Rule::Illuminance(rule) => vec![rule],
+            Rule::Any(AnyRule { any }) => illuminance_rules(any),
+            _ => vec![],
+        })
+        .collect()
+}
+
+/// Returns conditions of all branches in given actions, including nested ones
+fn branch_conditions(actions: &[RoutineAction]) -> Vec<&[Rule]> {
+    actions
+        .iter()
+        .flat_map(|action| match action {
+            RoutineAction::Branch(RoutineBranch {
+                condition,
+                then,
+                otherwise,
+            }) => {
+                let mut conditions = vec![condition.as_slice()];
+                conditions.extend(branch_conditions(then));
+                conditions.extend(branch_conditions(otherwise));
+                conditions
+            }
+            RoutineAction::Action(_) => vec![],
+        })
+        .collect()
+}
+
+/// Replaces branches in given actions of a routine with the actions of the
+/// side that applies to current state
+fn resolve_actions(
+    devices: &Devices,
+    groups: &Groups,
+    illuminance: &IlluminanceStates,
+    routine: &Routine,
+    actions: &[RoutineAction],
+    expr: &Expr,
+    today: NaiveDate,
+) -> Actions {
+    actions
+        .iter()
+        .flat_map(|action| match action {
+            RoutineAction::Action(action) => vec![action.clone()],
+            RoutineAction::Branch(RoutineBranch {
+                condition,
+                then,
+                otherwise,
+            }) => {
+                let matching = are_rules_matching(
+                    devices,
+                    groups,
+                    illuminance,
+                    &routine.name,
+                    condition,
+                    expr,
+                    today,
+                );
+                let actions = if matching { then } else { otherwise };
+
+                resolve_actions(devices, groups, illuminance, routine, actions, expr, today)
+            }
+        })
+        .collect()
+}
+
 /// Returns true if all rules of the given routine are triggered.
 fn is_routine_triggered(
     devices: &Devices,
     groups: &Groups,
+    illuminance: &IlluminanceStates,
     routine: &Routine,
-    eval_context: &HashMapContext,
+    expr: &Expr,
+    today: NaiveDate,
 ) -> bool {
     if routine.rules.is_empty() {
         return false;
     }
 
-    routine.rules.iter().all(|rule| {
-        let result = is_rule_triggered(devices, groups, rule, eval_context);
+    are_rules_matching(
+        devices,
+        groups,
+        illuminance,
+        &routine.name,
+        &routine.rules,
+        expr,
+        today,
+    )
+}
+
+/// Returns true if all given rules of the named routine match, which is also
+/// the case if there are none.
+fn are_rules_matching(
+    devices: &Devices,
+    groups: &Groups,
+    illuminance: &IlluminanceStates,
+    routine_name: &str,
+    rules: &[Rule],
+    expr: &Expr,
+    today: NaiveDate,
+) -> bool {
+    rules.iter().all(|rule| {
+        let result = is_rule_triggered(devices, groups, illuminance, rule, expr, today);
         match result {
             Ok(result) => result,
             Err(error) => {
-                error!("Error while checking routine {}: {}", routine.name, error);
+                error!("Error while checking routine {}: {}", routine_name, error);
                 false
             }
         }
@@ -153,8 +572,10 @@ fn compare_rule_device_state(rule: &Rule, device: &Device) -> Result<bool> {
     let sensor_state: Option<&SensorDevice> = device.get_sensor_state();
 
     match rule {
-        Rule::Any(_) | Rule::EvalExpr(_) => {
-            unreachable!("compare_rule_device_state() cannot be called for Any or EvalExpr rules");
+        Rule::Any(_) | Rule::EvalExpr(_) | Rule::Illuminance(_) | Rule::Calendar(_) => {
+            unreachable!(
+                "compare_rule_device_state() cannot be called for Any, EvalExpr, Illuminance or Calendar rules"
+            );
         }
         // Check for sensor value matches
         Rule::Sensor(rule) => match (&rule.state, sensor_state) {
@@ -170,6 +591,15 @@ fn compare_rule_device_state(rule: &Rule, device: &Device) -> Result<bool> {
                     value: sensor_value,
                 }),
             ) => Ok(rule_value == sensor_value),
+            (
+                SensorDevice::Number {
+                    value: rule_value, ..
+                },
+                Some(SensorDevice::Number {
+                    value: sensor_value,
+                    ..
+                }),
+            ) => Ok(rule_value == sensor_value),
             (rule, sensor) => Err(eyre!(
                 "Unknown sensor states encountered when processing rule {:?}. (sensor: {:?})",
                 rule,
@@ -199,15 +629,17 @@ fn compare_rule_device_state(rule: &Rule, device: &Device) -> Result<bool> {
 fn is_rule_triggered(
     devices: &Devices,
     groups: &Groups,
+    illuminance: &IlluminanceStates,
     rule: &Rule,
-    eval_context: &HashMapContext,
+    expr: &Expr,
+    today: NaiveDate,
 ) -> Result<bool> {
     // Try finding matching device
     let devices = match rule {
         Rule::Any(AnyRule { any: rules }) => {
             let any_triggered = rules
                 .iter()
-                .map(|rule| is_rule_triggered(devices, groups, rule, eval_context))
+                .map(|rule| is_rule_triggered(devices, groups, illuminance, rule, expr, today))
                 .any(|result| matches!(result, Ok(true)));
 
             return Ok(any_triggered);
@@ -223,10 +655,12 @@ fn is_rule_triggered(
                 .ok_or(eyre!("Could not find matching device for rule: {:?}", rule))?]
         }
         Rule::Group(rule) => groups.find_group_devices(devices.get_state(), &rule.group_id),
-        Rule::EvalExpr(expr) => {
-            let result = expr.eval_boolean_with_context(eval_context)?;
+        Rule::EvalExpr(node) => {
+            let result = node.eval_boolean_with_context(expr.get_context())?;
             return Ok(result);
         }
+        Rule::Illuminance(rule) => return Ok(illuminance.is_dark(rule)),
+        Rule::Calendar(rule) => return Ok(expr.get_calendar().is_day(rule.calendar, today)),
     };
 
     // Make sure we found at least one device to check against
@@ -244,3 +678,16 @@ fn is_rule_triggered(
 
     Ok(true)
 }
+
+fn routine_triggers(config: &RoutinesConfig) -> Vec<(RoutineId, Trigger)> {
+    config
+        .iter()
+        .filter_map(|(routine_id, routine)| Some((routine_id.clone(), routine.trigger.clone()?)))
+        .collect()
+}
+
+/// Checks that the triggers of given routines can be scheduled, so that a
+/// config reload can be rejected before anything is changed
+pub fn validate_routines(config: &RoutinesConfig, location: Option<&LocationConfig>) -> Result<()> {
+    validate_triggers(&routine_triggers(config), location)
+}
@@ -0,0 +1,98 @@
+use crate::types::{
+    action::Action,
+    device::{Device, DeviceData, SensorDevice},
+    event::{Message, TxEventChannel},
+    notification::{NotifyDescriptor, Severity},
+    safety::SafetyConfig,
+};
+
+use super::devices::Devices;
+
+fn hazard_detected(device: &Device) -> bool {
+    matches!(
+        device.data,
+        DeviceData::Sensor(SensorDevice::Boolean { value: true })
+    )
+}
+
+/// Returns true if a safety sensor went from clear (or unknown) to detecting a
+/// hazard
+fn is_triggered(old: &Option<Device>, new: &Device) -> bool {
+    hazard_detected(new) && !old.as_ref().map_or(false, hazard_detected)
+}
+
+/// Sends critical notifications and runs configured actions as soon as a
+/// smoke, carbon monoxide or water leak sensor triggers. Critical
+/// notifications are delivered regardless of quiet hours.
+#[derive(Clone)]
+pub struct Safety {
+    config: SafetyConfig,
+    event_tx: TxEventChannel,
+}
+
+impl Safety {
+    pub fn new(config: SafetyConfig, event_tx: TxEventChannel) -> Self {
+        Safety { config, event_tx }
+    }
+
+    pub fn handle_internal_state_update(
+        &self,
+        old: &Option<Device>,
+        new: &Device,
+        devices: &Devices,
+    ) {
+        if !is_triggered(old, new) {
+            return;
+        }
+
+        let device_key = new.get_device_key();
+        let sensors = self.config.sensors.iter().filter(|sensor| {
+            devices
+                .get_device_by_ref(&sensor.device_ref)
+                .map(|device| device.get_device_key())
+                == Some(device_key.clone())
+        });
+
+        for sensor in sensors {
+            warn!("{}: {}", sensor.kind.description(), new.name);
+
+            self.event_tx
+                .send(Message::Action(Action::Notify(NotifyDescriptor {
+                    title: Some(sensor.kind.description().to_string()),
+                    message: format!("{}: {}", sensor.kind.description(), new.name),
+                    severity: Some(Severity::Critical),
+                    persons: None,
+                    image: None,
+                })));
+
+            let kind_actions = self.config.actions.get(&sensor.kind).into_iter().flatten();
+            for action in kind_actions.chain(&sensor.actions) {
+                self.event_tx.send(Message::Action(action.clone()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::{device::DeviceId, integration::IntegrationId};
+
+    use super::*;
+
+    fn sensor(value: bool) -> Device {
+        Device::new(
+            IntegrationId::from("zigbee".to_string()),
+            DeviceId::new("leak"),
+            "Basement leak sensor".to_string(),
+            DeviceData::Sensor(SensorDevice::Boolean { value }),
+        )
+    }
+
+    #[test]
+    fn test_is_triggered() {
+        assert!(is_triggered(&None, &sensor(true)));
+        assert!(is_triggered(&Some(sensor(false)), &sensor(true)));
+        assert!(!is_triggered(&Some(sensor(true)), &sensor(true)));
+        assert!(!is_triggered(&Some(sensor(true)), &sensor(false)));
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use ordered_float::OrderedFloat;
+
+use crate::types::{
+    device::{ControllableState, Device, DeviceKey},
+    event::{Message, TxEventChannel},
+    scene::{SceneDescriptor, SceneId, SceneKeyframe},
+};
+
+/// Keyframe transitions are sent to devices in steps no shorter than this,
+/// devices fade between steps on their own
+const MIN_STEP_MS: u64 = 1000;
+
+/// Long keyframe transitions are split into at most this many steps
+const MAX_STEPS: u64 = 100;
+
+/// State of a device `t` (0.0 - 1.0, already eased) of the way from `from` to
+/// the keyframe
+pub fn interpolate_keyframe(
+    from: &ControllableState,
+    keyframe: &SceneKeyframe,
+    t: f32,
+) -> ControllableState {
+    let lerp = |a: f32, b: f32| a + (b - a) * t;
+
+    let power = match keyframe.power {
+        Some(true) => true,
+        Some(false) if t >= 1.0 => false,
+        _ => from.power,
+    };
+
+    // Devices that are off fade in from darkness
+    let from_brightness = match (from.power, from.brightness) {
+        (false, _) => 0.0,
+        (true, Some(brightness)) => brightness.0,
+        (true, None) => 1.0,
+    };
+
+    let brightness = match keyframe.brightness {
+        Some(to) => Some(OrderedFloat(lerp(from_brightness, to.0))),
+        None => from.brightness,
+    };
+
+    let color = match (&from.color, &keyframe.color) {
+        (Some(from), Some(to)) => Some(from.interpolate(to, t)),
+        (None, Some(to)) => Some(to.clone()),
+        (from, None) => from.clone(),
+    };
+
+    ControllableState {
+        power,
+        brightness,
+        color,
+        segments: from.segments.clone(),
+        transition_ms: None,
+    }
+}
+
+/// Sends interpolated device states for each keyframe in turn, then asks for
+/// the scene's own states to be applied. Stops as soon as `cancelled` is set.
+async fn run_keyframes(
+    sd: SceneDescriptor,
+    keyframes: Vec<SceneKeyframe>,
+    mut devices: Vec<Device>,
+    generation: u64,
+    cancelled: Arc<AtomicBool>,
+    event_tx: TxEventChannel,
+) {
+    for keyframe in keyframes {
+        let steps = (keyframe.duration_ms / MIN_STEP_MS).clamp(1, MAX_STEPS);
+        let step_ms = keyframe.duration_ms / steps;
+
+        for step in 1..=steps {
+            if cancelled.load(Ordering::Relaxed) {
+                return;
+            }
+
+            let t = keyframe.easing.apply(step as f32 / steps as f32);
+
+            for device in &devices {
+                let Some(from) = device.get_controllable_state() else {
+                    continue;
+                };
+
+                let mut state = interpolate_keyframe(from, &keyframe, t);
+                state.transition_ms = Some(step_ms);
+
+                if let Some(capabilities) = device.get_supported_color_modes() {
+                    state = state.color_to_device_preferred_mode(capabilities);
+                }
+
+                // Clearing the scene keeps the device's previous scene from
+                // overriding the interpolated state
+                let device = device.set_scene(None).set_controllable_state(state);
+
+                event_tx.send(Message::SetExpectedState {
+                    device,
+                    set_scene: true,
+                    skip_send: false,
+                });
+            }
+
+            tokio::time::sleep(Duration::from_millis(step_ms)).await;
+        }
+
+        // The next keyframe starts from where this one ended
+        devices = devices
+            .into_iter()
+            .map(|device| match device.get_controllable_state() {
+                Some(from) => {
+                    let state = interpolate_keyframe(from, &keyframe, 1.0);
+                    device.set_controllable_state(state)
+                }
+                None => device,
+            })
+            .collect();
+    }
+
+    event_tx.send(Message::SceneTransitionDone {
+        scene_descriptor: sd,
+        generation,
+    });
+}
+
+#[derive(Clone)]
+struct Run {
+    generation: u64,
+    device_keys: HashSet<DeviceKey>,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Plays keyframes of scenes that define them
+#[derive(Clone)]
+pub struct SceneTransitions {
+    event_tx: TxEventChannel,
+    runs: HashMap<SceneId, Run>,
+    next_generation: u64,
+}
+
+impl SceneTransitions {
+    pub fn new(event_tx: TxEventChannel) -> Self {
+        SceneTransitions {
+            event_tx,
+            runs: Default::default(),
+            next_generation: 0,
+        }
+    }
+
+    /// Starts playing keyframes of a scene for given devices, starting from
+    /// their current states
+    pub fn start(
+        &mut self,
+        sd: &SceneDescriptor,
+        keyframes: Vec<SceneKeyframe>,
+        devices: Vec<Device>,
+    ) {
+        let device_keys: HashSet<DeviceKey> = devices
+            .iter()
+            .map(|device| device.get_device_key())
+            .collect();
+        self.cancel(&device_keys);
+
+        self.next_generation += 1;
+        let generation = self.next_generation;
+
+        info!("Starting keyframe transition of scene {}", sd.scene_id);
+
+        let cancelled: Arc<AtomicBool> = Default::default();
+        tokio::spawn(run_keyframes(
+            sd.clone(),
+            keyframes,
+            devices,
+            generation,
+            cancelled.clone(),
+            self.event_tx.clone(),
+        ));
+
+        self.runs.insert(
+            sd.scene_id.clone(),
+            Run {
+                generation,
+                device_keys,
+                cancelled,
+            },
+        );
+    }
+
+    /// Stops transitions affecting any of given devices, e.g. when another
+    /// scene is activated for them
+    pub fn cancel(&mut self, device_keys: &HashSet<DeviceKey>) {
+        self.runs.retain(|scene_id, run| {
+            if run.device_keys.is_disjoint(device_keys) {
+                return true;
+            }
+
+            info!("Cancelling keyframe transition of scene {}", scene_id);
+            run.cancelled.store(true, Ordering::Relaxed);
+            false
+        });
+    }
+
+    /// Forgets a finished transition, returns whether it was still running,
+    /// i.e. the scene's own states should be applied
+    pub fn finish(&mut self, scene_id: &SceneId, generation: u64) -> bool {
+        match self.runs.get(scene_id) {
+            Some(run) if run.generation == generation => {
+                self.runs.remove(scene_id);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{color::DeviceColor, scene::Easing};
+
+    #[test]
+    fn test_interpolate_keyframe() {
+        let off = ControllableState {
+            power: false,
+            brightness: Some(OrderedFloat(1.0)),
+            color: Some(DeviceColor::new_from_ct(2000)),
+            segments: None,
+            transition_ms: None,
+        };
+        let sunrise = SceneKeyframe {
+            duration_ms: 60000,
+            easing: Easing::Linear,
+            power: Some(true),
+            brightness: Some(OrderedFloat(0.5)),
+            color: Some(DeviceColor::new_from_ct(4000)),
+        };
+
+        let halfway = interpolate_keyframe(&off, &sunrise, 0.5);
+        assert!(halfway.power);
+        assert_eq!(halfway.brightness, Some(OrderedFloat(0.25)));
+        assert_eq!(halfway.color, Some(DeviceColor::new_from_ct(3000)));
+
+        let sunset = SceneKeyframe {
+            power: Some(false),
+            brightness: Some(OrderedFloat(0.0)),
+            color: None,
+            ..sunrise
+        };
+
+        let fading = interpolate_keyframe(&halfway, &sunset, 0.5);
+        assert!(fading.power);
+        assert_eq!(fading.brightness, Some(OrderedFloat(0.125)));
+        assert_eq!(fading.color, halfway.color);
+        assert!(!interpolate_keyframe(&halfway, &sunset, 1.0).power);
+    }
+}
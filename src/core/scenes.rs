@@ -1,19 +1,27 @@
 use crate::types::{
+    color::DeviceColor,
     device::{
-        ControllableState, Device, DeviceData, DeviceKey, DeviceRef, DevicesState, SensorDevice,
+        ControllableDevice, ControllableState, Device, DeviceData, DeviceKey, DeviceRef,
+        DevicesState, ManageKind, SensorDevice,
     },
     scene::{
-        FlattenedSceneConfig, FlattenedScenesConfig, SceneConfig, SceneDescriptor,
-        SceneDeviceConfig, SceneDeviceStates, SceneDevicesConfig, SceneDevicesConfigs, SceneId,
-        ScenesConfig,
+        FlattenedSceneConfig, FlattenedScenesConfig, PaletteMode, SceneConfig, SceneDescriptor,
+        SceneDeviceAdjustment, SceneDeviceConfig, SceneDeviceStates, SceneDevicesConfig,
+        SceneDevicesConfigs, SceneId, ScenePaletteConfig, SceneStats, ScenesConfig,
     },
 };
+use chrono::{NaiveTime, Utc};
 use itertools::Itertools;
 use ordered_float::OrderedFloat;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
 
-use crate::db::actions::db_get_scenes;
+use crate::db::{
+    actions::{db_get_scene_stats, db_get_scenes, db_store_scene_stats},
+    spawn_db_write,
+};
 
 use super::{
+    clock::local_now,
     devices::Devices,
     expr::{
         eval_scene_expr, get_expr_device_deps, get_expr_group_device_deps, get_expr_scene_deps,
@@ -21,7 +29,7 @@ use super::{
     },
     groups::Groups,
 };
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 #[derive(Clone, Default)]
 pub struct Scenes {
@@ -30,6 +38,89 @@ pub struct Scenes {
     flattened_scenes: FlattenedScenesConfig,
     scene_devices_configs: SceneDevicesConfigs,
     device_invalidation_map: HashMap<DeviceKey, HashSet<SceneId>>,
+
+    /// Colors picked from scene palettes on the latest activation
+    palette_colors: HashMap<SceneId, HashMap<DeviceKey, DeviceColor>>,
+
+    /// Number of times each palette scene has been activated
+    palette_activations: HashMap<SceneId, u64>,
+
+    /// Relatively adjusted device states as of the latest activation
+    adjusted_states: HashMap<SceneId, HashMap<DeviceKey, ControllableState>>,
+
+    /// Brightness multipliers of devices, as given when activating the scene
+    brightness_scales: HashMap<SceneId, HashMap<DeviceKey, OrderedFloat<f32>>>,
+
+    /// Scene most recently cycled to in each area
+    cycle_positions: HashMap<CycleArea, SceneDescriptor>,
+
+    /// Activation counts of scenes, persisted to the DB
+    stats: BTreeMap<SceneId, SceneStats>,
+}
+
+/// Applies a relative adjustment to the current state of a device
+fn adjust_device_state(
+    device: &ControllableDevice,
+    adjustment: &SceneDeviceAdjustment,
+) -> ControllableState {
+    let mut device = device.clone();
+
+    if let Some(brightness) = adjustment.brightness {
+        device.dim(-brightness);
+    }
+
+    if let Some(ct) = adjustment.ct {
+        device.step_color_temperature(ct);
+    }
+
+    if adjustment.hue.is_some() || adjustment.saturation.is_some() {
+        device.nudge_color(
+            adjustment.hue.unwrap_or_default(),
+            adjustment.saturation.unwrap_or_default(),
+        );
+    }
+
+    device.state.transition_ms = adjustment.transition_ms;
+    device.state
+}
+
+/// Picks a color from the palette for each of given devices.
+///
+/// Seeded random picks only depend on the seed and `activation`, so that
+/// repeated activations still differ from each other.
+fn pick_palette_colors(
+    palette: &ScenePaletteConfig,
+    device_keys: &[DeviceKey],
+    activation: u64,
+) -> HashMap<DeviceKey, DeviceColor> {
+    if palette.colors.is_empty() {
+        return Default::default();
+    }
+
+    match palette.mode.unwrap_or_default() {
+        PaletteMode::Random => {
+            let mut rng = match palette.seed {
+                Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(activation)),
+                None => StdRng::from_entropy(),
+            };
+
+            device_keys
+                .iter()
+                .filter_map(|device_key| {
+                    let color = palette.colors.choose(&mut rng)?;
+                    Some((device_key.clone(), color.clone()))
+                })
+                .collect()
+        }
+        PaletteMode::Rotate => device_keys
+            .iter()
+            .enumerate()
+            .map(|(index, device_key)| {
+                let index = (index as u64 + activation) % palette.colors.len() as u64;
+                (device_key.clone(), palette.colors[index as usize].clone())
+            })
+            .collect(),
+    }
 }
 
 /// Evaluates current state of given device in some given scene
@@ -83,12 +174,16 @@ fn compute_scene_device_state(
             )
         }
 
+        // Computed from current device state on activation
+        SceneDeviceConfig::Adjust(_) => None,
+
         SceneDeviceConfig::DeviceState(scene_device) => {
             Some(
                 // Use state from scene_device
                 ControllableState {
                     brightness: scene_device.brightness,
                     color: scene_device.color.clone(),
+                    segments: scene_device.segments.clone(),
                     power: scene_device.power.unwrap_or(true),
                     transition_ms: scene_device.transition_ms,
                 },
@@ -97,6 +192,23 @@ fn compute_scene_device_state(
     }
 }
 
+/// Finds the management override of given device in some given scene, if any
+fn compute_scene_device_manage_kind(
+    scene_id: &SceneId,
+    device_key: &DeviceKey,
+    scene_devices_configs: &SceneDevicesConfigs,
+) -> Option<ManageKind> {
+    let (_scene_config, scene_devices_config) = scene_devices_configs.get(scene_id)?;
+
+    match scene_devices_config.get(device_key)? {
+        SceneDeviceConfig::DeviceLink(_) | SceneDeviceConfig::Adjust(_) => None,
+        SceneDeviceConfig::SceneLink(link) => {
+            compute_scene_device_manage_kind(&link.scene_id, device_key, scene_devices_configs)
+        }
+        SceneDeviceConfig::DeviceState(scene_device) => scene_device.managed.clone(),
+    }
+}
+
 type SceneDeviceList = HashSet<DeviceKey>;
 /// Gathers a Vec<HashSet<DeviceKey>> of all devices in provided scenes
 fn find_scene_device_lists(
@@ -133,20 +245,30 @@ fn find_scenes_common_devices(scene_device_lists: Vec<SceneDeviceList>) -> HashS
     scenes_common_devices
 }
 
-/// Finds index of active scene (if any) in given list of scenes.
+/// Devices that a list of cycled scenes applies to. Cycle positions are
+/// tracked per area, so that cycling scenes in one room doesn't affect where
+/// another room continues from.
+type CycleArea = BTreeSet<DeviceKey>;
+
+/// Gathers all devices of the given scenes
+fn find_cycle_area(scene_device_lists: &[SceneDeviceList]) -> CycleArea {
+    scene_device_lists.iter().flatten().cloned().collect()
+}
+
+/// Finds indices of active scenes in given list of scenes.
 ///
 /// Arguments:
 /// * `scene_devices_configs` - list of scenes with their device configs
 /// * `scenes_common_devices` - list of devices that are common in all given scenes
 /// * `devices` - current state of devices
-fn find_active_scene_index(
+fn find_active_scene_indices(
     scene_devices_configs: &[(&SceneDescriptor, Option<SceneDevicesConfig>)],
     scenes_common_devices: &HashSet<DeviceKey>,
     devices: &Devices,
-) -> Option<usize> {
+) -> Vec<usize> {
     scene_devices_configs
         .iter()
-        .position(|(sd, scene_devices_config)| {
+        .positions(|(sd, scene_devices_config)| {
             // try finding any device in scene_devices_config that has this scene active
             let Some(scene_devices_config) = scene_devices_config else {
                 return false;
@@ -164,23 +286,40 @@ fn find_active_scene_index(
                 device_scene.map_or(false, |ds| ds == sd.scene_id)
             })
         })
+        .collect()
 }
 
-/// Gets next scene from a list of scene descriptors to cycle through.
+/// Picks the current position among active scenes, preferring the scene
+/// that was last cycled to in the area. The same scene may appear several
+/// times in the list, e.g. with different brightness.
+fn find_cycle_position(
+    scene_descriptors: &[SceneDescriptor],
+    active_scene_indices: &[usize],
+    last_cycled: Option<&SceneDescriptor>,
+) -> Option<usize> {
+    active_scene_indices
+        .iter()
+        .find(|&&index| Some(&scene_descriptors[index]) == last_cycled)
+        .or(active_scene_indices.first())
+        .copied()
+}
+
+/// Gets next scene from a list of scene descriptors to cycle through, along
+/// with the area it's cycled in.
 ///
 /// Arguments:
 /// * `scene_descriptors` - list of scene descriptors to cycle through
 /// * `nowrap` - whether to cycle back to first scene when last scene is reached
 /// * `devices` - current state of devices
 /// * `scenes` - current state of scenes
-pub fn get_next_cycled_scene(
+fn get_next_cycled_scene(
     scene_descriptors: &[SceneDescriptor],
     nowrap: bool,
     devices: &Devices,
     groups: &Groups,
     scenes: &Scenes,
     eval_context: &EvalContext,
-) -> Option<SceneDescriptor> {
+) -> Option<(CycleArea, SceneDescriptor)> {
     let scene_devices_configs: Vec<(&SceneDescriptor, Option<SceneDevicesConfig>)> =
         scene_descriptors
             .iter()
@@ -195,25 +334,46 @@ pub fn get_next_cycled_scene(
     // gather a Vec<HashSet<DeviceKey>> of all devices in cycled scenes
     let scene_device_lists = find_scene_device_lists(&scene_devices_configs);
 
+    let area = find_cycle_area(&scene_device_lists);
+
     // gather devices which exist in all cycled scenes
     let scenes_common_devices = find_scenes_common_devices(scene_device_lists);
 
-    let active_scene_index =
-        find_active_scene_index(&scene_devices_configs, &scenes_common_devices, devices);
+    let active_scene_indices =
+        find_active_scene_indices(&scene_devices_configs, &scenes_common_devices, devices);
+    let active_scene_index = find_cycle_position(
+        scene_descriptors,
+        &active_scene_indices,
+        scenes.cycle_positions.get(&area),
+    );
 
-    let next_scene = match active_scene_index {
-        Some(index) => {
-            let next_scene_index = if nowrap {
-                (index + 1).min(scene_descriptors.len() - 1)
-            } else {
-                (index + 1) % scene_descriptors.len()
-            };
-            scene_descriptors.get(next_scene_index)
-        }
-        None => scene_descriptors.first(),
-    }?;
+    let now = local_now().time();
+    let in_window: Vec<bool> = scene_descriptors
+        .iter()
+        .map(|sd| scenes.is_within_window(sd, now))
+        .collect();
+
+    let next_scene_index = find_next_cycle_index(active_scene_index, &in_window, nowrap)?;
 
-    Some(next_scene.clone())
+    Some((area, scene_descriptors[next_scene_index].clone()))
+}
+
+/// Finds index of the scene to cycle to after the one at `current`, skipping
+/// scenes outside of their activation window.
+fn find_next_cycle_index(
+    current: Option<usize>,
+    in_window: &[bool],
+    nowrap: bool,
+) -> Option<usize> {
+    let len = in_window.len();
+    let mut candidates: Box<dyn Iterator<Item = usize>> = match current {
+        // Stay at the last scene when nowrap is set
+        Some(index) if nowrap => Box::new((index + 1..len).chain(std::iter::once(index))),
+        Some(index) => Box::new((index + 1..len).chain(0..=index)),
+        None => Box::new(0..len),
+    };
+
+    candidates.find(|&index| in_window[index])
 }
 
 impl Scenes {
@@ -227,6 +387,124 @@ impl Scenes {
     pub async fn refresh_db_scenes(&mut self) {
         let db_scenes = db_get_scenes().await.unwrap_or_default();
         self.db_scenes = db_scenes;
+
+        // Forget computed state of deleted scenes
+        let deleted_scenes = self
+            .scene_devices_configs
+            .keys()
+            .chain(self.flattened_scenes.0.keys())
+            .filter(|scene_id| !self.scene_exists(scene_id))
+            .cloned()
+            .collect_vec();
+
+        for scene_id in deleted_scenes {
+            self.scene_devices_configs.remove(&scene_id);
+            self.flattened_scenes.0.remove(&scene_id);
+        }
+    }
+
+    /// Replaces the scenes config after the config file has changed, and
+    /// recomputes all scenes
+    pub async fn reload_config(
+        &mut self,
+        config: ScenesConfig,
+        devices: &Devices,
+        groups: &Groups,
+        eval_context: &EvalContext,
+    ) {
+        self.config = config;
+        self.refresh_db_scenes().await;
+
+        let scene_ids: HashSet<SceneId> = self.get_scene_ids().into_iter().collect();
+        self.update_scene_devices_configs(devices, groups, &scene_ids, eval_context);
+        self.update_flattened_scenes(devices, &scene_ids);
+        self.device_invalidation_map = self.mk_device_invalidation_map(devices, groups);
+    }
+
+    /// Gets next scene from a list of scenes to cycle through, and remembers
+    /// it as the position of the area the scenes are cycled in
+    pub fn cycle_scenes(
+        &mut self,
+        scene_descriptors: &[SceneDescriptor],
+        nowrap: bool,
+        devices: &Devices,
+        groups: &Groups,
+        eval_context: &EvalContext,
+    ) -> Option<SceneDescriptor> {
+        let (area, next_scene) = get_next_cycled_scene(
+            scene_descriptors,
+            nowrap,
+            devices,
+            groups,
+            self,
+            eval_context,
+        )?;
+
+        self.cycle_positions.insert(area, next_scene.clone());
+
+        Some(next_scene)
+    }
+
+    /// Restores activation counts of scenes from the DB
+    pub async fn load_stats(&mut self) {
+        self.stats = db_get_scene_stats().await.unwrap_or_default();
+    }
+
+    /// Counts an activation of the scene
+    pub fn record_activation(&mut self, scene_id: &SceneId) {
+        let stats = self.stats.entry(scene_id.clone()).or_default();
+        stats.activation_count += 1;
+        stats.last_activated_at = Some(Utc::now());
+
+        let scene_id = scene_id.clone();
+        let stats = stats.clone();
+        spawn_db_write(async move {
+            db_store_scene_stats(&scene_id, &stats).await.ok();
+        });
+    }
+
+    /// Usage of all scenes, including ones that were never activated
+    pub fn get_stats(&self) -> BTreeMap<SceneId, SceneStats> {
+        self.get_scene_ids()
+            .into_iter()
+            .map(|scene_id| {
+                let stats = self.stats.get(&scene_id).cloned().unwrap_or_default();
+                (scene_id, stats)
+            })
+            .collect()
+    }
+
+    /// Whether the scene may be activated at the given local time
+    pub fn is_within_window(&self, sd: &SceneDescriptor, time: NaiveTime) -> bool {
+        sd.override_window == Some(true)
+            || self
+                .find_scene(&sd.scene_id)
+                .and_then(|scene| scene.window)
+                .map_or(true, |window| window.contains(time))
+    }
+
+    /// Devices of the given cycled scenes
+    pub fn get_cycle_area(
+        &self,
+        scene_descriptors: &[SceneDescriptor],
+        devices: &Devices,
+        groups: &Groups,
+        eval_context: &EvalContext,
+    ) -> Vec<DeviceKey> {
+        let scene_devices_configs: Vec<(&SceneDescriptor, Option<SceneDevicesConfig>)> =
+            scene_descriptors
+                .iter()
+                .map(|sd| {
+                    (
+                        sd,
+                        self.find_scene_devices_config(devices, groups, sd, eval_context),
+                    )
+                })
+                .collect();
+
+        find_cycle_area(&find_scene_device_lists(&scene_devices_configs))
+            .into_iter()
+            .collect()
     }
 
     pub fn get_scenes(&self) -> ScenesConfig {
@@ -235,12 +513,30 @@ impl Scenes {
         db_scenes
     }
 
+    /// Scenes created through the API, as opposed to configured ones
+    pub fn get_db_scenes(&self) -> &ScenesConfig {
+        &self.db_scenes
+    }
+
     pub fn get_scene_ids(&self) -> Vec<SceneId> {
-        self.get_scenes().keys().cloned().collect()
+        self.db_scenes
+            .keys()
+            .merge(self.config.keys())
+            .dedup()
+            .cloned()
+            .collect()
     }
 
     pub fn find_scene(&self, scene_id: &SceneId) -> Option<SceneConfig> {
-        Some(self.get_scenes().get(scene_id)?.clone())
+        // Configured scenes take precedence over DB scenes
+        self.config
+            .get(scene_id)
+            .or_else(|| self.db_scenes.get(scene_id))
+            .cloned()
+    }
+
+    fn scene_exists(&self, scene_id: &SceneId) -> bool {
+        self.config.contains_key(scene_id) || self.db_scenes.contains_key(scene_id)
     }
 
     pub fn find_scene_devices_config(
@@ -267,9 +563,23 @@ impl Scenes {
             .map(|devices| devices.0)
             .unwrap_or_default();
 
+        let excluded_keys = scene
+            .exclude
+            .clone()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|device_ref| devices.get_device_by_ref(device_ref))
+            .map(|device| device.get_device_key())
+            .collect_vec();
+
         let filter_device_by_keys = |device: &Device| -> bool {
             let device_key = &DeviceKey::new(device.integration_id.clone(), device.id.clone());
 
+            // Skip this device if the scene excludes it
+            if excluded_keys.contains(device_key) {
+                return false;
+            }
+
             // Skip this device if it's not in device_keys
             if let Some(device_keys) = &sd.device_keys {
                 if !device_keys.contains(device_key) {
@@ -306,7 +616,7 @@ impl Scenes {
             let group_devices = groups.find_group_devices(devices.get_state(), &group_id);
 
             for device in group_devices {
-                // Skip this device if it's not in device_keys or group_keys
+                // Skip this device if it's excluded or not in device_keys or group_keys
                 if !filter_device_by_keys(device) {
                     continue;
                 }
@@ -332,7 +642,7 @@ impl Scenes {
                     continue;
                 };
 
-                // Skip this device if it's not in device_keys or group_keys
+                // Skip this device if it's excluded or not in device_keys or group_keys
                 if !filter_device_by_keys(device) {
                     continue;
                 }
@@ -349,11 +659,41 @@ impl Scenes {
         Some(scene_devices_config)
     }
 
-    /// Finds current state of given device in its current scene
-    pub fn find_scene_device_state(&self, device: &Device) -> Option<&ControllableState> {
+    /// Finds current state of given device in its current scene, optionally
+    /// overriding the transition time specified by the scene
+    pub fn find_scene_device_state(
+        &self,
+        device: &Device,
+        transition_ms: Option<u64>,
+    ) -> Option<ControllableState> {
         let scene_id = device.get_scene()?;
         let scene = self.flattened_scenes.0.get(&scene_id)?;
-        scene.devices.0.get(&device.get_device_key())
+        let mut state = scene.devices.0.get(&device.get_device_key())?.clone();
+
+        let scale = self
+            .brightness_scales
+            .get(&scene_id)
+            .and_then(|scales| scales.get(&device.get_device_key()));
+        if let (Some(scale), true) = (scale, state.power) {
+            let brightness = state.brightness.map_or(1.0, |b| b.0) * scale.0;
+            state.brightness = Some(OrderedFloat(brightness.clamp(0.0, 1.0)));
+        }
+
+        if transition_ms.is_some() {
+            state.transition_ms = transition_ms;
+        }
+
+        Some(state)
+    }
+
+    /// Finds the management override of given device in its current scene
+    pub fn find_scene_device_manage_kind(&self, device: &Device) -> Option<ManageKind> {
+        let scene_id = device.get_scene()?;
+        compute_scene_device_manage_kind(
+            &scene_id,
+            &device.get_device_key(),
+            &self.scene_devices_configs,
+        )
     }
 
     pub fn mk_flattened_scene(
@@ -375,7 +715,22 @@ impl Scenes {
                         devices,
                         &self.scene_devices_configs,
                         false,
-                    )?;
+                    );
+
+                    // Relative adjustments are only known after activation
+                    let mut device_state = match device_state {
+                        Some(device_state) => device_state,
+                        None => self.adjusted_states.get(scene_id)?.get(device_key)?.clone(),
+                    };
+
+                    // Colors picked from the scene palette take precedence
+                    let palette_color = self
+                        .palette_colors
+                        .get(scene_id)
+                        .and_then(|colors| colors.get(device_key));
+                    if let Some(color) = palette_color {
+                        device_state.color = Some(color.clone());
+                    }
 
                     Some((device_key.clone(), device_state))
                 }
@@ -389,58 +744,141 @@ impl Scenes {
         })
     }
 
-    pub fn mk_scene_devices_configs(
-        &self,
+    /// Computes the device states of given scene that depend on how the scene
+    /// is activated, i.e. colors picked from the scene palette, relative
+    /// adjustments of current device states and brightness scaling. Should be
+    /// called right before activating the scene.
+    pub fn prepare_activation(
+        &mut self,
+        sd: &SceneDescriptor,
         devices: &Devices,
         groups: &Groups,
-        invalidated_scenes: &HashSet<SceneId>,
         eval_context: &EvalContext,
-    ) -> SceneDevicesConfigs {
-        self.get_scene_ids()
+    ) {
+        let scene_id = &sd.scene_id;
+
+        // Brightness scaling only applies to the devices being activated
+        if let Some(scene_devices_config) =
+            self.find_scene_devices_config(devices, groups, sd, eval_context)
+        {
+            let scales = self.brightness_scales.entry(scene_id.clone()).or_default();
+
+            for device_key in scene_devices_config.into_keys() {
+                match sd.brightness {
+                    Some(scale) => scales.insert(device_key, scale),
+                    None => scales.remove(&device_key),
+                };
+            }
+        }
+
+        let Some((scene_config, scene_devices_config)) = self.scene_devices_configs.get(scene_id)
+        else {
+            return;
+        };
+
+        let adjusted_states: HashMap<DeviceKey, ControllableState> = scene_devices_config
             .iter()
-            .filter_map(|scene_id| {
-                let scene_devices_config = if invalidated_scenes.contains(scene_id) {
-                    let scene_config = self.find_scene(scene_id)?;
-                    let scene_devices_config = self.find_scene_devices_config(
-                        devices,
-                        groups,
-                        &SceneDescriptor {
-                            scene_id: scene_id.clone(),
-                            device_keys: None,
-                            group_keys: None,
-                        },
-                        eval_context,
-                    )?;
-
-                    Some((scene_config, scene_devices_config))
-                } else {
-                    self.scene_devices_configs.get(scene_id).cloned()
-                }?;
+            .filter_map(|(device_key, scene_device_config)| {
+                let SceneDeviceConfig::Adjust(adjust) = scene_device_config else {
+                    return None;
+                };
+                let DeviceData::Controllable(controllable) = &devices.get_device(device_key)?.data
+                else {
+                    return None;
+                };
 
-                Some((scene_id.clone(), scene_devices_config))
+                Some((
+                    device_key.clone(),
+                    adjust_device_state(controllable, &adjust.adjust),
+                ))
             })
-            .collect()
+            .collect();
+
+        if let Some(palette) = &scene_config.palette {
+            let device_keys = scene_devices_config.keys().cloned().sorted().collect_vec();
+
+            let activation = self
+                .palette_activations
+                .entry(scene_id.clone())
+                .or_default();
+            let colors = pick_palette_colors(palette, &device_keys, *activation);
+            *activation += 1;
+
+            self.palette_colors.insert(scene_id.clone(), colors);
+        }
+
+        self.adjusted_states
+            .insert(scene_id.clone(), adjusted_states);
+        self.update_flattened_scenes(devices, &HashSet::from([scene_id.clone()]));
     }
 
-    pub fn mk_flattened_scenes(
+    fn mk_scene_devices_config(
         &self,
+        scene_id: &SceneId,
         devices: &Devices,
+        groups: &Groups,
+        eval_context: &EvalContext,
+    ) -> Option<(SceneConfig, SceneDevicesConfig)> {
+        let scene_config = self.find_scene(scene_id)?;
+        let scene_devices_config = self.find_scene_devices_config(
+            devices,
+            groups,
+            &SceneDescriptor {
+                scene_id: scene_id.clone(),
+                device_keys: None,
+                group_keys: None,
+                transition_ms: None,
+                brightness: None,
+                override_window: None,
+            },
+            eval_context,
+        )?;
+
+        Some((scene_config, scene_devices_config))
+    }
+
+    /// Recomputes devices configs of the invalidated scenes in place, all
+    /// against the previous configs of other scenes
+    fn update_scene_devices_configs(
+        &mut self,
+        devices: &Devices,
+        groups: &Groups,
         invalidated_scenes: &HashSet<SceneId>,
-    ) -> FlattenedScenesConfig {
-        FlattenedScenesConfig(
-            self.get_scene_ids()
-                .iter()
-                .filter_map(|scene_id| {
-                    let flattened_scene = if invalidated_scenes.contains(scene_id) {
-                        self.mk_flattened_scene(scene_id, devices)?
-                    } else {
-                        self.flattened_scenes.0.get(scene_id)?.clone()
-                    };
+        eval_context: &EvalContext,
+    ) {
+        let updated = invalidated_scenes
+            .iter()
+            .map(|scene_id| {
+                let config = self.mk_scene_devices_config(scene_id, devices, groups, eval_context);
+                (scene_id.clone(), config)
+            })
+            .collect_vec();
 
-                    Some((scene_id.clone(), flattened_scene))
-                })
-                .collect(),
-        )
+        for (scene_id, config) in updated {
+            match config {
+                Some(config) => self.scene_devices_configs.insert(scene_id, config),
+                None => self.scene_devices_configs.remove(&scene_id),
+            };
+        }
+    }
+
+    /// Recomputes the invalidated flattened scenes in place
+    fn update_flattened_scenes(
+        &mut self,
+        devices: &Devices,
+        invalidated_scenes: &HashSet<SceneId>,
+    ) {
+        let updated = invalidated_scenes
+            .iter()
+            .map(|scene_id| (scene_id.clone(), self.mk_flattened_scene(scene_id, devices)))
+            .collect_vec();
+
+        for (scene_id, flattened_scene) in updated {
+            match flattened_scene {
+                Some(flattened_scene) => self.flattened_scenes.0.insert(scene_id, flattened_scene),
+                None => self.flattened_scenes.0.remove(&scene_id),
+            };
+        }
     }
 
     pub fn get_flattened_scenes(&self) -> &FlattenedScenesConfig {
@@ -487,7 +925,7 @@ impl Scenes {
                 }
                 SceneDeviceConfig::SceneLink(s) => invalidated_devices
                     .extend(self.get_invalidated_devices_for_scene(devices, groups, &s.scene_id)),
-                SceneDeviceConfig::DeviceState(_) => {}
+                SceneDeviceConfig::DeviceState(_) | SceneDeviceConfig::Adjust(_) => {}
             };
         }
 
@@ -548,9 +986,9 @@ impl Scenes {
                 }
             });
 
-        self.scene_devices_configs =
-            self.mk_scene_devices_configs(devices, groups, &invalidated_scenes, eval_context);
-        self.flattened_scenes = self.mk_flattened_scenes(devices, &invalidated_scenes);
+        // Only scenes referencing the device need to be recomputed
+        self.update_scene_devices_configs(devices, groups, &invalidated_scenes, eval_context);
+        self.update_flattened_scenes(devices, &invalidated_scenes);
 
         // Recompute device_invalidation_map if device was recently discovered
         if is_new_device {
@@ -560,3 +998,180 @@ impl Scenes {
         invalidated_scenes
     }
 }
+
+#[cfg(test)]
+mod pick_palette_colors_tests {
+    use std::str::FromStr;
+
+    use crate::types::{device::DeviceId, integration::IntegrationId};
+
+    use super::*;
+
+    fn mk_device_keys(n: usize) -> Vec<DeviceKey> {
+        (0..n)
+            .map(|i| {
+                DeviceKey::new(
+                    IntegrationId::from_str("test_integration").unwrap(),
+                    DeviceId::new(&format!("light{i}")),
+                )
+            })
+            .collect()
+    }
+
+    fn mk_palette(mode: PaletteMode, seed: Option<u64>) -> ScenePaletteConfig {
+        ScenePaletteConfig {
+            colors: vec![
+                DeviceColor::new_from_ct(2700),
+                DeviceColor::new_from_ct(4000),
+                DeviceColor::new_from_ct(6500),
+            ],
+            mode: Some(mode),
+            seed,
+        }
+    }
+
+    #[test]
+    fn test_rotate_shifts_colors_per_activation() {
+        let palette = mk_palette(PaletteMode::Rotate, None);
+        let device_keys = mk_device_keys(2);
+
+        let first = pick_palette_colors(&palette, &device_keys, 0);
+        let second = pick_palette_colors(&palette, &device_keys, 1);
+
+        assert_eq!(first[&device_keys[0]], palette.colors[0]);
+        assert_eq!(first[&device_keys[1]], palette.colors[1]);
+        assert_eq!(second[&device_keys[0]], palette.colors[1]);
+        assert_eq!(second[&device_keys[1]], palette.colors[2]);
+    }
+
+    #[test]
+    fn test_seeded_random_is_reproducible() {
+        let palette = mk_palette(PaletteMode::Random, Some(42));
+        let device_keys = mk_device_keys(8);
+
+        let a = pick_palette_colors(&palette, &device_keys, 3);
+        let b = pick_palette_colors(&palette, &device_keys, 3);
+
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 8);
+        assert!(a.values().all(|color| palette.colors.contains(color)));
+    }
+}
+
+#[cfg(test)]
+mod adjust_device_state_tests {
+    use crate::types::{color::Capabilities, device::ManageKind};
+
+    use super::*;
+
+    fn mk_adjustment() -> SceneDeviceAdjustment {
+        SceneDeviceAdjustment {
+            brightness: None,
+            ct: None,
+            hue: None,
+            saturation: None,
+            transition_ms: None,
+        }
+    }
+
+    #[test]
+    fn test_adjusts_brightness_and_ct() {
+        let device = ControllableDevice::new(
+            None,
+            true,
+            Some(0.5),
+            Some(DeviceColor::new_from_ct(3000)),
+            None,
+            Capabilities::default(),
+            ManageKind::Full,
+        );
+
+        let state = adjust_device_state(
+            &device,
+            &SceneDeviceAdjustment {
+                brightness: Some(-0.2),
+                ct: Some(500),
+                ..mk_adjustment()
+            },
+        );
+
+        assert!((state.brightness.unwrap().0 - 0.3).abs() < 0.001);
+        assert_eq!(state.color, Some(DeviceColor::new_from_ct(3500)));
+    }
+
+    #[test]
+    fn test_leaves_powered_off_devices_alone() {
+        let device = ControllableDevice::new(
+            None,
+            false,
+            Some(0.5),
+            None,
+            None,
+            Capabilities::default(),
+            ManageKind::Full,
+        );
+
+        let state = adjust_device_state(
+            &device,
+            &SceneDeviceAdjustment {
+                brightness: Some(-0.2),
+                ..mk_adjustment()
+            },
+        );
+
+        assert_eq!(state, device.state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mk_descriptor(scene_id: &str, brightness: Option<f32>) -> SceneDescriptor {
+        SceneDescriptor {
+            scene_id: SceneId::new(scene_id.to_string()),
+            device_keys: None,
+            group_keys: None,
+            transition_ms: None,
+            brightness: brightness.map(OrderedFloat),
+            override_window: None,
+        }
+    }
+
+    #[test]
+    fn test_find_cycle_position() {
+        let scene_descriptors = vec![
+            mk_descriptor("bright", None),
+            mk_descriptor("bright", Some(0.5)),
+            mk_descriptor("night", None),
+        ];
+
+        // Both "bright" entries look active, the area's last position decides
+        assert_eq!(
+            find_cycle_position(&scene_descriptors, &[0, 1], Some(&scene_descriptors[1])),
+            Some(1)
+        );
+        assert_eq!(
+            find_cycle_position(&scene_descriptors, &[0, 1], None),
+            Some(0)
+        );
+
+        // Last position is stale if its scene is no longer active
+        assert_eq!(
+            find_cycle_position(&scene_descriptors, &[2], Some(&scene_descriptors[1])),
+            Some(2)
+        );
+        assert_eq!(find_cycle_position(&scene_descriptors, &[], None), None);
+    }
+
+    #[test]
+    fn test_find_next_cycle_index() {
+        let in_window = [true, false, true];
+
+        assert_eq!(find_next_cycle_index(None, &in_window, false), Some(0));
+        assert_eq!(find_next_cycle_index(Some(0), &in_window, false), Some(2));
+        assert_eq!(find_next_cycle_index(Some(2), &in_window, false), Some(0));
+        assert_eq!(find_next_cycle_index(Some(2), &in_window, true), Some(2));
+        assert_eq!(find_next_cycle_index(Some(1), &[false; 3], false), None);
+    }
+}
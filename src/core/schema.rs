@@ -0,0 +1,354 @@
+//! JSON Schema of the config format, for editor autocompletion and validation
+//! of config files. Schemas are derived from the serde types with
+//! `#[derive(JsonSchema)]`.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::PathBuf;
+
+use chrono::{DateTime, NaiveDateTime, NaiveTime, Utc};
+use ordered_float::OrderedFloat;
+use serde_json::{json, Map, Value};
+
+pub use homectl_schema_derive::JsonSchema;
+
+use super::{config::Config, integrations::integration_config_schemas};
+
+/// Describes the values accepted when deserializing a type
+pub trait JsonSchema {
+    /// Name of the type in `$defs`, types without a name are inlined
+    fn schema_name() -> Option<String> {
+        None
+    }
+
+    /// Whether struct fields of this type may be left out
+    fn optional() -> bool {
+        false
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Value;
+}
+
+/// Collects schemas of named types into `$defs`
+#[derive(Default)]
+pub struct SchemaGenerator {
+    defs: BTreeMap<String, Value>,
+}
+
+impl SchemaGenerator {
+    /// Returns the schema of `T`, or a reference to it for named types
+    pub fn subschema_for<T: JsonSchema>(&mut self) -> Value {
+        let Some(name) = T::schema_name() else {
+            return T::json_schema(self);
+        };
+
+        if !self.defs.contains_key(&name) {
+            // Recursive types refer to the placeholder
+            self.defs.insert(name.clone(), Value::Null);
+            let schema = T::json_schema(self);
+            self.defs.insert(name.clone(), schema);
+        }
+
+        json!({ "$ref": format!("#/$defs/{name}") })
+    }
+
+    /// Adds given schemas to the named type, all of which must match
+    fn extend_def(&mut self, name: &str, schemas: Vec<Value>) {
+        if let Some(def) = self.defs.get_mut(name) {
+            let mut all_of = vec![def.take()];
+            all_of.extend(schemas);
+            *def = json!({ "allOf": all_of });
+        }
+    }
+
+    fn into_root_schema(self, root: Value) -> Value {
+        let mut schema = json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "$defs": self.defs,
+        });
+        if let (Value::Object(schema), Value::Object(root)) = (&mut schema, root) {
+            schema.extend(root);
+        }
+
+        schema
+    }
+}
+
+/// Schema of an object with known properties
+pub struct ObjectSchema {
+    description: Option<&'static str>,
+    properties: Map<String, Value>,
+    required: Vec<String>,
+    all_of: Vec<Value>,
+}
+
+impl ObjectSchema {
+    pub fn new(description: Option<&'static str>) -> ObjectSchema {
+        ObjectSchema {
+            description,
+            properties: Map::new(),
+            required: vec![],
+            all_of: vec![],
+        }
+    }
+
+    pub fn property<T: JsonSchema>(
+        &mut self,
+        gen: &mut SchemaGenerator,
+        name: &str,
+        description: Option<&str>,
+        default: bool,
+    ) {
+        let schema = with_description(gen.subschema_for::<T>(), description);
+        self.property_schema(name, schema, default || T::optional());
+    }
+
+    pub fn property_schema(&mut self, name: &str, schema: Value, optional: bool) {
+        self.properties.insert(name.to_string(), schema);
+        if !optional {
+            self.required.push(name.to_string());
+        }
+    }
+
+    /// Property holding the name of an internally tagged enum variant
+    pub fn tag(&mut self, tag: &str, variant: &str) {
+        self.property_schema(tag, json!({ "const": variant }), false);
+    }
+
+    /// Properties of `T` are accepted alongside the properties of this object
+    pub fn flatten<T: JsonSchema>(&mut self, gen: &mut SchemaGenerator) {
+        self.all_of(gen.subschema_for::<T>());
+    }
+
+    pub fn all_of(&mut self, schema: Value) {
+        self.all_of.push(schema);
+    }
+
+    pub fn into_value(self) -> Value {
+        let mut schema = json!({
+            "type": "object",
+            "properties": self.properties,
+        });
+        if !self.required.is_empty() {
+            schema["required"] = json!(self.required);
+        }
+        if !self.all_of.is_empty() {
+            schema["allOf"] = json!(self.all_of);
+        }
+
+        with_description(schema, self.description)
+    }
+}
+
+pub fn with_description(mut schema: Value, description: Option<&str>) -> Value {
+    if let (Value::Object(schema), Some(description)) = (&mut schema, description) {
+        schema.insert("description".to_string(), json!(description));
+    }
+
+    schema
+}
+
+pub fn const_schema(value: &str, description: Option<&str>) -> Value {
+    with_description(json!({ "const": value }), description)
+}
+
+/// Externally tagged enum variant, e.g. `{ "Variant": content }`
+pub fn external_tag(variant: &str, content: Value) -> Value {
+    json!({
+        "type": "object",
+        "properties": { variant: content },
+        "required": [variant],
+        "additionalProperties": false,
+    })
+}
+
+pub fn any_of(variants: Vec<Value>, description: Option<&str>) -> Value {
+    with_description(json!({ "anyOf": variants }), description)
+}
+
+pub fn tuple_schema(items: Vec<Value>) -> Value {
+    json!({
+        "type": "array",
+        "prefixItems": items,
+        "minItems": items.len(),
+        "maxItems": items.len(),
+    })
+}
+
+macro_rules! impl_json_schema {
+    ($schema:tt: $($ty:ty),*) => {
+        $(
+            impl JsonSchema for $ty {
+                fn json_schema(_: &mut SchemaGenerator) -> Value {
+                    json!($schema)
+                }
+            }
+        )*
+    };
+}
+
+impl_json_schema!({ "type": "boolean" }: bool);
+impl_json_schema!({ "type": "string" }: String, PathBuf);
+impl_json_schema!({ "type": "integer", "minimum": 0 }: u8, u16, u32, u64, usize);
+impl_json_schema!({ "type": "integer" }: i8, i16, i32, i64, isize);
+impl_json_schema!({ "type": "number" }: f32, f64);
+impl_json_schema!({ "type": "string", "pattern": "^\\d{1,2}:\\d{2}(:\\d{2})?$" }: NaiveTime);
+impl_json_schema!({ "type": "string", "format": "date-time" }: NaiveDateTime, DateTime<Utc>);
+impl_json_schema!({}: serde_json::Value);
+
+impl JsonSchema for chrono::Weekday {
+    fn json_schema(_: &mut SchemaGenerator) -> Value {
+        json!({ "type": "string", "description": "Day of the week, e.g. mon or monday" })
+    }
+}
+
+impl JsonSchema for jsonptr::Pointer {
+    fn json_schema(_: &mut SchemaGenerator) -> Value {
+        json!({ "type": "string", "description": "JSON pointer, e.g. /state/brightness" })
+    }
+}
+
+impl JsonSchema for evalexpr::Node {
+    fn json_schema(_: &mut SchemaGenerator) -> Value {
+        json!({ "type": "string", "description": "evalexpr expression" })
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Option<T> {
+    fn optional() -> bool {
+        true
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Value {
+        gen.subschema_for::<T>()
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Box<T> {
+    fn json_schema(gen: &mut SchemaGenerator) -> Value {
+        gen.subschema_for::<T>()
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for OrderedFloat<T> {
+    fn json_schema(gen: &mut SchemaGenerator) -> Value {
+        gen.subschema_for::<T>()
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for std::ops::Range<T> {
+    fn json_schema(gen: &mut SchemaGenerator) -> Value {
+        let mut schema = ObjectSchema::new(None);
+        schema.property::<T>(gen, "start", None, false);
+        schema.property::<T>(gen, "end", None, false);
+
+        schema.into_value()
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Vec<T> {
+    fn json_schema(gen: &mut SchemaGenerator) -> Value {
+        json!({ "type": "array", "items": gen.subschema_for::<T>() })
+    }
+}
+
+impl<T: JsonSchema, S> JsonSchema for HashSet<T, S> {
+    fn json_schema(gen: &mut SchemaGenerator) -> Value {
+        json!({ "type": "array", "items": gen.subschema_for::<T>(), "uniqueItems": true })
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for BTreeSet<T> {
+    fn json_schema(gen: &mut SchemaGenerator) -> Value {
+        json!({ "type": "array", "items": gen.subschema_for::<T>(), "uniqueItems": true })
+    }
+}
+
+// Keys are strings in config files, whatever type they're parsed into
+impl<K, V: JsonSchema, S> JsonSchema for HashMap<K, V, S> {
+    fn json_schema(gen: &mut SchemaGenerator) -> Value {
+        json!({ "type": "object", "additionalProperties": gen.subschema_for::<V>() })
+    }
+}
+
+impl<K, V: JsonSchema> JsonSchema for BTreeMap<K, V> {
+    fn json_schema(gen: &mut SchemaGenerator) -> Value {
+        json!({ "type": "object", "additionalProperties": gen.subschema_for::<V>() })
+    }
+}
+
+/// Returns the JSON Schema of the config file
+pub fn config_schema() -> Value {
+    let mut gen = SchemaGenerator::default();
+    let root = gen.subschema_for::<Config>();
+
+    // Remaining fields of integration configs depend on the plugin
+    let plugins: Vec<Value> = integration_config_schemas(&mut gen)
+        .into_iter()
+        .map(|(plugin, schema)| {
+            json!({
+                "if": { "properties": { "plugin": { "const": plugin } } },
+                "then": schema,
+            })
+        })
+        .collect();
+    gen.extend_def("IntegrationConfig", plugins);
+
+    gen.into_root_schema(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[allow(dead_code)]
+    #[derive(Default, Deserialize, JsonSchema)]
+    #[serde(rename_all = "snake_case")]
+    enum Speed {
+        /// Slowly
+        #[default]
+        Slow,
+        VeryFast,
+    }
+
+    /// A fan
+    #[allow(dead_code)]
+    #[derive(Deserialize, JsonSchema)]
+    struct Fan {
+        name: String,
+        #[serde(default)]
+        speed: Speed,
+        rpm: Option<u32>,
+    }
+
+    #[test]
+    fn test_derived_schema() {
+        let mut gen = SchemaGenerator::default();
+        let root = gen.subschema_for::<Fan>();
+        let schema = gen.into_root_schema(root);
+
+        assert_eq!(schema["$ref"], "#/$defs/Fan");
+        assert_eq!(
+            schema["$defs"]["Fan"],
+            json!({
+                "type": "object",
+                "description": "A fan",
+                "properties": {
+                    "name": { "type": "string" },
+                    "speed": { "$ref": "#/$defs/Speed" },
+                    "rpm": { "type": "integer", "minimum": 0 },
+                },
+                "required": ["name"],
+            })
+        );
+        assert_eq!(
+            schema["$defs"]["Speed"],
+            json!({
+                "anyOf": [
+                    { "const": "slow", "description": "Slowly" },
+                    { "const": "very_fast" },
+                ]
+            })
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::types::{device::DeviceKey, device_config::SensorFilterConfig};
+
+fn push_window(window: &mut VecDeque<f64>, value: f64, len: usize) {
+    window.push_back(value);
+    while window.len() > len.max(1) {
+        window.pop_front();
+    }
+}
+
+fn median(window: &VecDeque<f64>) -> f64 {
+    let mut values: Vec<f64> = window.iter().copied().collect();
+    values.sort_by(f64::total_cmp);
+
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+fn mean(window: &VecDeque<f64>) -> f64 {
+    window.iter().sum::<f64>() / window.len() as f64
+}
+
+#[derive(Clone, Debug, Default)]
+struct FilterState {
+    /// Latest raw readings, for the median
+    raw: VecDeque<f64>,
+
+    /// Latest readings after the median, for the moving average
+    medians: VecDeque<f64>,
+}
+
+/// Keeps recent readings of sensors with a filter configured
+#[derive(Clone, Default)]
+pub struct SensorFilters {
+    states: BTreeMap<DeviceKey, FilterState>,
+}
+
+impl SensorFilters {
+    /// Returns the filtered value of a new reading, or None if it's within the
+    /// deadband of the `stored` value and should be ignored
+    pub fn apply(
+        &mut self,
+        device_key: &DeviceKey,
+        config: &SensorFilterConfig,
+        value: f64,
+        stored: Option<f64>,
+    ) -> Option<f64> {
+        let state = self.states.entry(device_key.clone()).or_default();
+        let mut value = value;
+
+        if let Some(len) = config.median {
+            push_window(&mut state.raw, value, len);
+            value = median(&state.raw);
+        }
+
+        if let Some(len) = config.moving_average {
+            push_window(&mut state.medians, value, len);
+            value = mean(&state.medians);
+        }
+
+        match (config.deadband, stored) {
+            (Some(deadband), Some(stored)) if (value - stored).abs() < deadband => None,
+            _ => Some(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{device::DeviceId, integration::IntegrationId};
+
+    #[test]
+    fn test_sensor_filters() {
+        let device_key = DeviceKey::new(
+            IntegrationId::from("zigbee".to_string()),
+            DeviceId::new("hall_temp"),
+        );
+        let mut filters = SensorFilters::default();
+
+        let config = SensorFilterConfig {
+            median: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(filters.apply(&device_key, &config, 21.0, None), Some(21.0));
+        assert_eq!(filters.apply(&device_key, &config, 21.5, None), Some(21.25));
+        // Outlier is dropped
+        assert_eq!(filters.apply(&device_key, &config, 85.0, None), Some(21.5));
+
+        let mut filters = SensorFilters::default();
+        let config = SensorFilterConfig {
+            moving_average: Some(2),
+            deadband: Some(0.5),
+            ..Default::default()
+        };
+        assert_eq!(filters.apply(&device_key, &config, 20.0, None), Some(20.0));
+        assert_eq!(filters.apply(&device_key, &config, 20.5, Some(20.0)), None);
+        assert_eq!(
+            filters.apply(&device_key, &config, 21.5, Some(20.0)),
+            Some(21.0)
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::{Duration, Instant};
+
+use color_eyre::Result;
+use futures::{SinkExt, StreamExt};
+use tokio_tungstenite::{connect_async, tungstenite};
+
+use crate::types::{
+    event::{Message, TxEventChannel},
+    standby::StandbyConfig,
+    websockets::{WebSocketAuth, WebSocketRequest, WebSocketResponse},
+};
+
+/// Follows device state of the primary instance until it stops responding,
+/// then sends [Message::PromoteStandby].
+pub async fn follow_primary(config: StandbyConfig, event_tx: TxEventChannel) {
+    let timeout = Duration::from_secs(config.heartbeat_timeout_secs.unwrap_or(15));
+    let mut last_seen = Instant::now();
+
+    while last_seen.elapsed() < timeout {
+        if let Err(e) = follow(&config, timeout, &mut last_seen, &event_tx).await {
+            warn!(
+                "Lost connection to primary at {}: {:?}",
+                config.primary_url, e
+            );
+        }
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    warn!(
+        "Primary at {} has not responded in {:?}, taking over",
+        config.primary_url, timeout
+    );
+    event_tx.send(Message::PromoteStandby);
+}
+
+/// Mirrors state of the primary into our own devices, returns once the
+/// connection fails or the primary stops responding to pings.
+async fn follow(
+    config: &StandbyConfig,
+    timeout: Duration,
+    last_seen: &mut Instant,
+    event_tx: &TxEventChannel,
+) -> Result<()> {
+    let url = &config.primary_url;
+    let (ws, _) = connect_async(url).await?;
+    let (mut ws_tx, mut ws_rx) = ws.split();
+
+    let auth = WebSocketRequest::Auth(WebSocketAuth::homectl(config.token.clone()));
+    ws_tx
+        .send(tungstenite::Message::Text(serde_json::to_string(&auth)?))
+        .await?;
+
+    info!("Following primary at {}", url);
+    *last_seen = Instant::now();
+
+    let mut ping_interval = tokio::time::interval(timeout / 3);
+
+    loop {
+        tokio::select! {
+            msg = ws_rx.next() => {
+                let Some(msg) = msg else {
+                    return Ok(());
+                };
+
+                *last_seen = Instant::now();
+
+                if let tungstenite::Message::Text(json) = msg? {
+                    let WebSocketResponse::State(state) = serde_json::from_str(&json)? else {
+                        continue;
+                    };
+
+                    for device in state.devices.0.into_values() {
+                        event_tx.send(Message::SetExpectedState {
+                            device,
+                            set_scene: true,
+                            skip_send: true,
+                        });
+                    }
+                }
+            }
+            _ = ping_interval.tick() => {
+                if last_seen.elapsed() >= timeout {
+                    return Ok(());
+                }
+
+                ws_tx.send(tungstenite::Message::Ping(vec![])).await?;
+            }
+        }
+    }
+}
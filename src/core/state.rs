@@ -2,12 +2,19 @@ use crate::types::{
     color::ColorMode,
     device::DevicesState,
     event::TxEventChannel,
+    snapshot::Snapshot,
     websockets::{StateUpdate, WebSocketResponse},
 };
 
 use super::{
-    devices::Devices, expr::Expr, groups::Groups, integrations::Integrations, rules::Rules,
-    scenes::Scenes, websockets::WebSockets,
+    appliances::Appliances, button_presses::ButtonPresses, commands::Commands,
+    conflicts::Conflicts, covers::Covers, devices::Devices, event_bus::EventBus, expr::Expr,
+    groups::Groups, ha_discovery::HaDiscovery, heating::Heating, integrations::Integrations,
+    modes::Modes, motion_lighting::MotionLighting, notifications::Notifications,
+    open_alerts::OpenAlerts, persons::Persons, polling::Polling, presence::Presence,
+    quiet_hours::QuietHours, rate_alerts::RateAlerts, rules::Rules, safety::Safety,
+    scene_transitions::SceneTransitions, scenes::Scenes, sun::Sun, utility_meters::UtilityMeters,
+    websockets::WebSockets,
 };
 
 #[derive(Clone)]
@@ -15,14 +22,51 @@ pub struct AppState {
     pub integrations: Integrations,
     pub groups: Groups,
     pub scenes: Scenes,
+    pub scene_transitions: SceneTransitions,
+    pub button_presses: ButtonPresses,
     pub devices: Devices,
     pub rules: Rules,
+    pub persons: Persons,
+    pub presence: Presence,
+    pub notifications: Notifications,
+    pub quiet_hours: QuietHours,
+    pub modes: Modes,
+    pub safety: Safety,
+    pub appliances: Appliances,
+    pub rate_alerts: RateAlerts,
+    pub utility_meters: UtilityMeters,
+    pub heating: Heating,
+    pub sun: Sun,
+    pub covers: Covers,
+    pub motion_lighting: MotionLighting,
+    pub open_alerts: OpenAlerts,
+    pub commands: Commands,
+    pub polling: Polling,
+    pub conflicts: Conflicts,
     pub event_tx: TxEventChannel,
+    pub event_bus: EventBus,
+    pub ha_discovery: HaDiscovery,
     pub expr: Expr,
     pub ws: WebSockets,
+
+    /// Whether we're a standby instance following a primary instance
+    pub standby: bool,
 }
 
 impl AppState {
+    pub fn get_snapshot(&self) -> Snapshot {
+        Snapshot {
+            created_at: chrono::Utc::now(),
+            devices: self.devices.get_state().clone(),
+            db_scenes: self.scenes.get_db_scenes().clone(),
+            device_metadata: self.devices.get_device_configs().get_device_metadata(),
+            mode: self.modes.get_mode().clone(),
+            do_not_disturb: self.quiet_hours.is_dnd(),
+            scenes: self.scenes.get_flattened_scenes().clone(),
+            groups: self.groups.get_flattened_groups().clone(),
+        }
+    }
+
     /// Sends current state over WebSockets. If user_id is omitted, the message
     /// is broadcast to all connected peers.
     pub async fn send_state_ws(&self, user_id: Option<usize>) {
@@ -35,6 +79,7 @@ impl AppState {
         }
 
         let devices = self.devices.get_state();
+        let device_configs = self.devices.get_device_configs();
         let scenes = self.scenes.get_flattened_scenes().clone();
         let groups = self.groups.get_flattened_groups().clone();
 
@@ -44,15 +89,30 @@ impl AppState {
             .map(|device| {
                 (
                     device.get_device_key(),
-                    device.color_to_mode(ColorMode::Hs, true),
+                    device_configs
+                        .apply_display_name(device)
+                        .color_to_mode(ColorMode::Hs, true),
                 )
             })
             .collect();
 
+        let group_devices = self
+            .groups
+            .get_group_devices(devices)
+            .0
+            .into_iter()
+            .map(|(device_key, device)| (device_key, device.color_to_mode(ColorMode::Hs, true)))
+            .collect();
+
         let message = WebSocketResponse::State(StateUpdate {
             devices: DevicesState(devices_converted),
+            unavailable_devices: self.devices.get_unavailable_devices().clone(),
+            overridden_devices: self.devices.get_overridden_devices(),
             scenes,
             groups,
+            group_devices: DevicesState(group_devices),
+            device_metadata: device_configs.get_device_metadata(),
+            command_statuses: self.commands.get_statuses().clone(),
         });
 
         self.ws.send(user_id, &message).await;
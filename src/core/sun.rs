@@ -0,0 +1,412 @@
+use std::f64::consts::TAU;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+use ordered_float::OrderedFloat;
+
+use crate::types::{
+    device::{Device, DeviceData, DeviceId, SensorDevice},
+    event::{Message, TxEventChannel},
+    integration::IntegrationId,
+    sun::{
+        LocationConfig, MoonPhase, SunPosition, SunTrigger, Twilight, CIVIL_TWILIGHT_ELEVATION,
+        NAUTICAL_TWILIGHT_ELEVATION, SUNRISE_ELEVATION, SUN_INTEGRATION_ID,
+    },
+};
+
+use super::{clock::local_today, devices::Devices};
+
+/// Mean length of the lunar cycle in days
+const SYNODIC_MONTH: f64 = 29.530588853;
+
+/// A new moon on 2000-01-06 18:14 UTC
+const KNOWN_NEW_MOON: i64 = 947182440;
+
+/// Days since the J2000.0 epoch
+fn j2000_days(time: DateTime<Utc>) -> f64 {
+    time.timestamp() as f64 / 86400.0 + 2440587.5 - 2451545.0
+}
+
+/// Hour angle and declination of the sun in radians, with a low precision
+/// (~1 degree) approximation, see
+/// <https://en.wikipedia.org/wiki/Position_of_the_Sun>
+fn hour_angle_and_declination(location: &LocationConfig, time: DateTime<Utc>) -> (f64, f64) {
+    let n = j2000_days(time);
+
+    let mean_longitude = (280.460 + 0.9856474 * n).rem_euclid(360.0);
+    let mean_anomaly = (357.528 + 0.9856003 * n).rem_euclid(360.0).to_radians();
+    let ecliptic_longitude =
+        (mean_longitude + 1.915 * mean_anomaly.sin() + 0.020 * (2.0 * mean_anomaly).sin())
+            .to_radians();
+    let obliquity = (23.439 - 0.0000004 * n).to_radians();
+
+    let right_ascension =
+        (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+
+    let sidereal_time = (18.697374558 + 24.06570982441908 * n).rem_euclid(24.0) * 15.0;
+    let hour_angle = (sidereal_time + location.longitude).to_radians() - right_ascension;
+
+    (hour_angle, declination)
+}
+
+/// Computes the position of the sun with a low precision (~1 degree)
+/// approximation
+pub fn sun_position(location: &LocationConfig, time: DateTime<Utc>) -> SunPosition {
+    let (hour_angle, declination) = hour_angle_and_declination(location, time);
+    let latitude = location.latitude.to_radians();
+
+    let elevation = (latitude.sin() * declination.sin()
+        + latitude.cos() * declination.cos() * hour_angle.cos())
+    .asin();
+    let azimuth = (-hour_angle.sin())
+        .atan2(declination.tan() * latitude.cos() - latitude.sin() * hour_angle.cos());
+
+    SunPosition {
+        azimuth: azimuth.to_degrees().rem_euclid(360.0),
+        elevation: elevation.to_degrees(),
+    }
+}
+
+/// Time when the sun is highest on given day
+fn solar_noon(location: &LocationConfig, date: NaiveDate) -> DateTime<Utc> {
+    // Initial guess from longitude, refined by the hour angle at that time.
+    // The hour angle grows 15 degrees an hour.
+    let mut noon = Utc.from_utc_datetime(&date.and_hms_opt(12, 0, 0).unwrap_or_default())
+        - chrono::Duration::seconds((location.longitude / 15.0 * 3600.0) as i64);
+
+    for _ in 0..2 {
+        let (hour_angle, _) = hour_angle_and_declination(location, noon);
+        let hour_angle = (hour_angle.to_degrees() + 180.0).rem_euclid(360.0) - 180.0;
+        noon -= chrono::Duration::seconds((hour_angle / 15.0 * 3600.0) as i64);
+    }
+
+    noon
+}
+
+/// Times when the sun passes given elevation before and after solar noon on
+/// given day, or None if it stays above or below that elevation all day
+pub fn sun_crossings(
+    location: &LocationConfig,
+    date: NaiveDate,
+    elevation: f64,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let noon = solar_noon(location, date);
+    let (_, declination) = hour_angle_and_declination(location, noon);
+    let latitude = location.latitude.to_radians();
+
+    let cos_hour_angle = (elevation.to_radians().sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+
+    let hours = cos_hour_angle.acos().to_degrees() / 15.0;
+    let offset = chrono::Duration::seconds((hours * 3600.0) as i64);
+
+    Some((noon - offset, noon + offset))
+}
+
+/// Hours between sunrise and sunset on given day
+pub fn day_length(location: &LocationConfig, date: NaiveDate) -> f64 {
+    match sun_crossings(location, date, SUNRISE_ELEVATION) {
+        Some((sunrise, sunset)) => (sunset - sunrise).num_seconds() as f64 / 3600.0,
+        // Midnight sun
+        None if sun_position(location, solar_noon(location, date)).elevation
+            > SUNRISE_ELEVATION =>
+        {
+            24.0
+        }
+        // Polar night
+        None => 0.0,
+    }
+}
+
+/// Next time a sun trigger fires after given time, or None if the sun doesn't
+/// reach its elevation within a year
+pub fn next_sun_trigger(
+    location: &LocationConfig,
+    trigger: &SunTrigger,
+    after: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    let offset = chrono::Duration::seconds(trigger.offset.0);
+
+    // Start from the day before, in case a positive offset pushes yesterday's
+    // event past the given time
+    let start = after.with_timezone(&Local).date_naive().pred_opt()?;
+
+    start
+        .iter_days()
+        .take(367)
+        .filter_map(|date| {
+            let (rising, setting) = sun_crossings(location, date, trigger.sun.elevation())?;
+            Some(
+                if trigger.sun.is_rising() {
+                    rising
+                } else {
+                    setting
+                } + offset,
+            )
+        })
+        .find(|time| *time > after)
+}
+
+/// Computes the phase of the moon from the mean length of the lunar cycle,
+/// which is accurate to within a day
+pub fn moon_phase(time: DateTime<Utc>) -> MoonPhase {
+    let days = (time.timestamp() - KNOWN_NEW_MOON) as f64 / 86400.0;
+    let phase = (days / SYNODIC_MONTH).rem_euclid(1.0);
+
+    MoonPhase {
+        phase,
+        illumination: (1.0 - (phase * TAU).cos()) / 2.0,
+    }
+}
+
+fn number_sensor(value: f64, decimals: i32) -> SensorDevice {
+    // Avoid flooding state updates with tiny changes
+    let scale = 10f64.powi(decimals);
+
+    SensorDevice::Number {
+        value: OrderedFloat((value * scale).round() / scale),
+        unit: None,
+        raw: None,
+    }
+}
+
+/// Local time of day as "HH:MM", or an empty string if there's no such time
+/// today
+fn time_sensor(time: Option<DateTime<Utc>>) -> SensorDevice {
+    SensorDevice::Text {
+        value: time
+            .map(|time| time.with_timezone(&Local).format("%H:%M").to_string())
+            .unwrap_or_default(),
+    }
+}
+
+/// Reports the position of the sun, twilight and the phase of the moon as
+/// virtual sensors
+#[derive(Clone)]
+pub struct Sun {
+    location: Option<LocationConfig>,
+    event_tx: TxEventChannel,
+}
+
+impl Sun {
+    pub fn new(location: Option<LocationConfig>, event_tx: TxEventChannel) -> Self {
+        Sun { location, event_tx }
+    }
+
+    /// Current position of the sun, if location has been configured
+    pub fn get_position(&self) -> Option<SunPosition> {
+        self.location
+            .as_ref()
+            .map(|location| sun_position(location, Utc::now()))
+    }
+
+    pub fn refresh(&self, devices: &Devices) {
+        let Some(location) = &self.location else {
+            return;
+        };
+
+        let now = Utc::now();
+        let today = local_today();
+        let position = sun_position(location, now);
+        let twilight = Twilight::from_elevation(position.elevation);
+        let moon = moon_phase(now);
+
+        let mut sensors = vec![
+            ("azimuth", "Sun azimuth", number_sensor(position.azimuth, 1)),
+            (
+                "elevation",
+                "Sun elevation",
+                number_sensor(position.elevation, 1),
+            ),
+            (
+                "twilight",
+                "Twilight",
+                SensorDevice::Text {
+                    value: twilight.name().to_string(),
+                },
+            ),
+            (
+                "day_length",
+                "Day length",
+                number_sensor(day_length(location, today), 2),
+            ),
+            ("moon_phase", "Moon phase", number_sensor(moon.phase, 2)),
+            (
+                "moon_illumination",
+                "Moon illumination",
+                number_sensor(moon.illumination * 100.0, 0),
+            ),
+            (
+                "moon_phase_name",
+                "Moon phase name",
+                SensorDevice::Text {
+                    value: moon.name().to_string(),
+                },
+            ),
+        ];
+
+        let boundaries = [
+            (
+                SUNRISE_ELEVATION,
+                ("sunrise", "Sunrise"),
+                ("sunset", "Sunset"),
+            ),
+            (
+                CIVIL_TWILIGHT_ELEVATION,
+                ("civil_dawn", "Civil dawn"),
+                ("civil_dusk", "Civil dusk"),
+            ),
+            (
+                NAUTICAL_TWILIGHT_ELEVATION,
+                ("nautical_dawn", "Nautical dawn"),
+                ("nautical_dusk", "Nautical dusk"),
+            ),
+        ];
+
+        for (elevation, (dawn_id, dawn_name), (dusk_id, dusk_name)) in boundaries {
+            let crossings = sun_crossings(location, today, elevation);
+            sensors.push((dawn_id, dawn_name, time_sensor(crossings.map(|c| c.0))));
+            sensors.push((dusk_id, dusk_name, time_sensor(crossings.map(|c| c.1))));
+        }
+
+        for (device_id, name, state) in sensors {
+            let device = Device::new(
+                IntegrationId::from_str(SUN_INTEGRATION_ID).unwrap(),
+                DeviceId::new(device_id),
+                name.to_string(),
+                DeviceData::Sensor(state),
+            );
+
+            if devices.get_device(&device.get_device_key()) != Some(&device) {
+                self.event_tx.send(Message::RecvDeviceState { device });
+            }
+        }
+    }
+}
+
+/// Periodically updates sun position and anything depending on it
+pub async fn refresh_sun(event_tx: TxEventChannel) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+        event_tx.send(Message::RefreshSun);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::types::sun::{SunEvent, SunOffset};
+
+    #[test]
+    fn test_sun_position() {
+        let helsinki = LocationConfig {
+            latitude: 60.17,
+            longitude: 24.94,
+            timezone: None,
+        };
+
+        // Around solar noon on the summer solstice
+        let noon = Utc.with_ymd_and_hms(2024, 6, 20, 10, 20, 0).unwrap();
+        let position = sun_position(&helsinki, noon);
+        assert!((position.elevation - 53.3).abs() < 1.0, "{position:?}");
+        assert!((position.azimuth - 180.0).abs() < 5.0, "{position:?}");
+
+        // Sunset is in the northwest
+        let evening = Utc.with_ymd_and_hms(2024, 6, 20, 19, 0, 0).unwrap();
+        let position = sun_position(&helsinki, evening);
+        assert!(position.elevation < 5.0, "{position:?}");
+        assert!(position.azimuth > 270.0, "{position:?}");
+
+        let midnight = Utc.with_ymd_and_hms(2024, 12, 21, 22, 0, 0).unwrap();
+        assert!(sun_position(&helsinki, midnight).elevation < -30.0);
+    }
+
+    #[test]
+    fn test_sun_crossings() {
+        let helsinki = LocationConfig {
+            latitude: 60.17,
+            longitude: 24.94,
+            timezone: None,
+        };
+        let solstice = NaiveDate::from_ymd_opt(2024, 6, 20).unwrap();
+
+        // Sunrise at 03:54 and sunset at 22:50 local time (UTC+3)
+        let (sunrise, sunset) = sun_crossings(&helsinki, solstice, SUNRISE_ELEVATION).unwrap();
+        let expected_sunrise = Utc.with_ymd_and_hms(2024, 6, 20, 0, 54, 0).unwrap();
+        let expected_sunset = Utc.with_ymd_and_hms(2024, 6, 20, 19, 50, 0).unwrap();
+        assert!(
+            (sunrise - expected_sunrise).num_minutes().abs() < 10,
+            "{sunrise}"
+        );
+        assert!(
+            (sunset - expected_sunset).num_minutes().abs() < 10,
+            "{sunset}"
+        );
+        assert!((day_length(&helsinki, solstice) - 18.9).abs() < 0.2);
+
+        // It doesn't get darker than civil twilight
+        assert!(sun_crossings(&helsinki, solstice, CIVIL_TWILIGHT_ELEVATION).is_some());
+        assert!(sun_crossings(&helsinki, solstice, NAUTICAL_TWILIGHT_ELEVATION).is_none());
+
+        let utqiagvik = LocationConfig {
+            latitude: 71.29,
+            longitude: -156.79,
+            timezone: None,
+        };
+        assert_eq!(day_length(&utqiagvik, solstice), 24.0);
+    }
+
+    #[test]
+    fn test_next_sun_trigger() {
+        let helsinki = LocationConfig {
+            latitude: 60.17,
+            longitude: 24.94,
+            timezone: None,
+        };
+        let noon = Utc.with_ymd_and_hms(2024, 6, 20, 12, 0, 0).unwrap();
+
+        let trigger = SunTrigger {
+            sun: SunEvent::Sunset,
+            offset: "-30m".parse().unwrap(),
+        };
+        let expected = Utc.with_ymd_and_hms(2024, 6, 20, 19, 20, 0).unwrap();
+        let at = next_sun_trigger(&helsinki, &trigger, noon).unwrap();
+        assert!((at - expected).num_minutes().abs() < 10, "{at}");
+
+        // Today's sunrise has passed
+        let trigger = SunTrigger {
+            sun: SunEvent::Sunrise,
+            offset: SunOffset::default(),
+        };
+        let expected = Utc.with_ymd_and_hms(2024, 6, 21, 0, 54, 0).unwrap();
+        let at = next_sun_trigger(&helsinki, &trigger, noon).unwrap();
+        assert!((at - expected).num_minutes().abs() < 10, "{at}");
+
+        assert_eq!("+1h15m".parse(), Ok(SunOffset(4500)));
+        assert_eq!("-90s".parse(), Ok(SunOffset(-90)));
+        assert!("30".parse::<SunOffset>().is_err());
+        assert!("m".parse::<SunOffset>().is_err());
+    }
+
+    #[test]
+    fn test_moon_phase() {
+        let full_moon = Utc.with_ymd_and_hms(2024, 6, 22, 1, 8, 0).unwrap();
+        let moon = moon_phase(full_moon);
+        assert!((moon.phase - 0.5).abs() < 0.03, "{moon:?}");
+        assert!(moon.illumination > 0.99, "{moon:?}");
+        assert_eq!(moon.name(), "full_moon");
+
+        let first_quarter = Utc.with_ymd_and_hms(2024, 6, 14, 5, 18, 0).unwrap();
+        assert_eq!(moon_phase(first_quarter).name(), "first_quarter");
+    }
+}
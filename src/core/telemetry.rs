@@ -0,0 +1,495 @@
+//! Minimal OpenTelemetry exporter, sending spans and duration histograms to an
+//! OTLP/HTTP endpoint using JSON encoding. Configured with the standard
+//! `OTEL_*` environment variables.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use color_eyre::Result;
+use eyre::eyre;
+use once_cell::sync::OnceCell;
+use serde_json::{json, Value};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use super::http::post;
+
+/// Upper bounds of histogram buckets, in milliseconds
+const BUCKET_BOUNDS_MS: [f64; 12] = [
+    1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0,
+];
+
+/// Finished spans waiting to be exported are dropped beyond this
+const MAX_QUEUED_SPANS: usize = 4096;
+
+static METRICS: OnceCell<Metrics> = OnceCell::new();
+
+#[derive(Clone, Debug)]
+struct OtlpConfig {
+    traces_endpoint: Option<String>,
+    metrics_endpoint: Option<String>,
+    headers: Vec<(String, String)>,
+    service_name: String,
+    traces_interval: Duration,
+    metrics_interval: Duration,
+}
+
+fn env(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+fn env_millis(key: &str, default: u64) -> Duration {
+    let millis = env(key).and_then(|value| value.parse().ok());
+    Duration::from_millis(millis.unwrap_or(default))
+}
+
+/// Resolves the endpoint of a signal, which may be given directly or derived
+/// from the common base endpoint
+fn signal_endpoint(base: Option<&str>, specific: Option<&str>, path: &str) -> Option<String> {
+    specific
+        .map(str::to_string)
+        .or_else(|| base.map(|base| format!("{}/{path}", base.trim_end_matches('/'))))
+}
+
+/// Parses headers in `key1=value1,key2=value2` form
+fn parse_headers(headers: &str) -> Vec<(String, String)> {
+    headers
+        .split(',')
+        .filter_map(|header| header.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+impl OtlpConfig {
+    fn from_env() -> Option<OtlpConfig> {
+        if env("OTEL_SDK_DISABLED").as_deref() == Some("true") {
+            return None;
+        }
+
+        let base = env("OTEL_EXPORTER_OTLP_ENDPOINT");
+        let enabled = |exporter: &str| env(exporter).as_deref() != Some("none");
+
+        let traces_endpoint = signal_endpoint(
+            base.as_deref(),
+            env("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").as_deref(),
+            "v1/traces",
+        )
+        .filter(|_| enabled("OTEL_TRACES_EXPORTER"));
+
+        let metrics_endpoint = signal_endpoint(
+            base.as_deref(),
+            env("OTEL_EXPORTER_OTLP_METRICS_ENDPOINT").as_deref(),
+            "v1/metrics",
+        )
+        .filter(|_| enabled("OTEL_METRICS_EXPORTER"));
+
+        if traces_endpoint.is_none() && metrics_endpoint.is_none() {
+            return None;
+        }
+
+        Some(OtlpConfig {
+            traces_endpoint,
+            metrics_endpoint,
+            headers: env("OTEL_EXPORTER_OTLP_HEADERS")
+                .map(|headers| parse_headers(&headers))
+                .unwrap_or_default(),
+            service_name: env("OTEL_SERVICE_NAME").unwrap_or_else(|| "homectl-server".to_string()),
+            traces_interval: env_millis("OTEL_BSP_SCHEDULE_DELAY", 5000),
+            metrics_interval: env_millis("OTEL_METRIC_EXPORT_INTERVAL", 60000),
+        })
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+fn mk_attribute(key: &str, value: Value) -> Value {
+    json!({ "key": key, "value": value })
+}
+
+fn mk_resource(config: &OtlpConfig) -> Value {
+    json!({
+        "attributes": [mk_attribute("service.name", json!({ "stringValue": config.service_name }))]
+    })
+}
+
+fn mk_scope() -> Value {
+    json!({ "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") })
+}
+
+/// Span data kept in span extensions while the span is open
+struct SpanData {
+    trace_id: u128,
+    span_id: u64,
+    parent_span_id: Option<u64>,
+    name: &'static str,
+    start: SystemTime,
+    attributes: Vec<Value>,
+}
+
+struct FinishedSpan {
+    data: SpanData,
+    end: SystemTime,
+}
+
+impl FinishedSpan {
+    fn to_json(&self) -> Value {
+        let data = &self.data;
+
+        json!({
+            "traceId": format!("{:032x}", data.trace_id),
+            "spanId": format!("{:016x}", data.span_id),
+            "parentSpanId": data.parent_span_id.map(|id| format!("{id:016x}")).unwrap_or_default(),
+            "name": data.name,
+            // SPAN_KIND_INTERNAL
+            "kind": 1,
+            "startTimeUnixNano": unix_nanos(data.start),
+            "endTimeUnixNano": unix_nanos(self.end),
+            "attributes": data.attributes,
+        })
+    }
+}
+
+struct AttributeVisitor<'a>(&'a mut Vec<Value>);
+
+impl Visit for AttributeVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0
+            .push(mk_attribute(field.name(), json!({ "stringValue": value })));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.push(mk_attribute(
+            field.name(),
+            json!({ "intValue": value.to_string() }),
+        ));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.push(mk_attribute(
+            field.name(),
+            json!({ "intValue": value.to_string() }),
+        ));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0
+            .push(mk_attribute(field.name(), json!({ "boolValue": value })));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0
+            .push(mk_attribute(field.name(), json!({ "doubleValue": value })));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record_str(field, &format!("{value:?}"));
+    }
+}
+
+/// Collects finished spans for export
+pub struct OtlpLayer {
+    spans: Arc<Mutex<Vec<FinishedSpan>>>,
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        // Spans without a parent start a new trace
+        let parent = span.parent().and_then(|parent| {
+            let extensions = parent.extensions();
+            let parent = extensions.get::<SpanData>()?;
+            Some((parent.trace_id, parent.span_id))
+        });
+        let (trace_id, parent_span_id) = match parent {
+            Some((trace_id, span_id)) => (trace_id, Some(span_id)),
+            None => (rand::random(), None),
+        };
+
+        let mut data = SpanData {
+            trace_id,
+            span_id: rand::random(),
+            parent_span_id,
+            name: attrs.metadata().name(),
+            start: SystemTime::now(),
+            attributes: vec![],
+        };
+        attrs.record(&mut AttributeVisitor(&mut data.attributes));
+
+        span.extensions_mut().insert(data);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut extensions = span.extensions_mut();
+        if let Some(data) = extensions.get_mut::<SpanData>() {
+            values.record(&mut AttributeVisitor(&mut data.attributes));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+
+        let Some(data) = span.extensions_mut().remove::<SpanData>() else {
+            return;
+        };
+
+        let mut spans = self.spans.lock().unwrap();
+        if spans.len() < MAX_QUEUED_SPANS {
+            spans.push(FinishedSpan {
+                data,
+                end: SystemTime::now(),
+            });
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    bucket_counts: [u64; BUCKET_BOUNDS_MS.len() + 1],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn record(&mut self, value_ms: f64) {
+        let bucket = BUCKET_BOUNDS_MS
+            .iter()
+            .position(|bound| value_ms <= *bound)
+            .unwrap_or(BUCKET_BOUNDS_MS.len());
+
+        self.bucket_counts[bucket] += 1;
+        self.count += 1;
+        self.sum += value_ms;
+    }
+}
+
+type HistogramKey = (&'static str, Vec<(&'static str, String)>);
+
+struct Metrics {
+    start: SystemTime,
+    histograms: Mutex<HashMap<HistogramKey, Histogram>>,
+}
+
+/// Records a duration into the histogram `name`, if metrics are exported
+pub fn record_duration(
+    name: &'static str,
+    attributes: Vec<(&'static str, String)>,
+    duration: Duration,
+) {
+    let Some(metrics) = METRICS.get() else {
+        return;
+    };
+
+    metrics
+        .histograms
+        .lock()
+        .unwrap()
+        .entry((name, attributes))
+        .or_default()
+        .record(duration.as_secs_f64() * 1000.0);
+}
+
+impl Metrics {
+    fn to_json(&self, config: &OtlpConfig) -> Value {
+        let now = unix_nanos(SystemTime::now());
+        let start = unix_nanos(self.start);
+
+        let mut data_points: HashMap<&'static str, Vec<Value>> = HashMap::new();
+        for ((name, attributes), histogram) in self.histograms.lock().unwrap().iter() {
+            data_points.entry(name).or_default().push(json!({
+                "attributes": attributes
+                    .iter()
+                    .map(|(key, value)| mk_attribute(key, json!({ "stringValue": value })))
+                    .collect::<Vec<_>>(),
+                "startTimeUnixNano": start,
+                "timeUnixNano": now,
+                "count": histogram.count.to_string(),
+                "sum": histogram.sum,
+                "bucketCounts": histogram
+                    .bucket_counts
+                    .iter()
+                    .map(u64::to_string)
+                    .collect::<Vec<_>>(),
+                "explicitBounds": BUCKET_BOUNDS_MS,
+            }));
+        }
+
+        let metrics: Vec<Value> = data_points
+            .into_iter()
+            .map(|(name, data_points)| {
+                json!({
+                    "name": name,
+                    "unit": "ms",
+                    "histogram": {
+                        "dataPoints": data_points,
+                        // AGGREGATION_TEMPORALITY_CUMULATIVE
+                        "aggregationTemporality": 2,
+                    },
+                })
+            })
+            .collect();
+
+        json!({
+            "resourceMetrics": [{
+                "resource": mk_resource(config),
+                "scopeMetrics": [{ "scope": mk_scope(), "metrics": metrics }],
+            }]
+        })
+    }
+}
+
+async fn post_json(config: &OtlpConfig, endpoint: &str, body: &Value) -> Result<()> {
+    let mut headers = config.headers.clone();
+    headers.push(("content-type".to_string(), "application/json".to_string()));
+
+    let status = post(endpoint, &headers, serde_json::to_vec(body)?).await?;
+
+    if !status.is_success() {
+        return Err(eyre!("{} responded with {}", endpoint, status));
+    }
+
+    Ok(())
+}
+
+async fn export_traces(config: OtlpConfig, endpoint: String, spans: Arc<Mutex<Vec<FinishedSpan>>>) {
+    let mut interval = tokio::time::interval(config.traces_interval);
+
+    loop {
+        interval.tick().await;
+
+        let spans = std::mem::take(&mut *spans.lock().unwrap());
+        if spans.is_empty() {
+            continue;
+        }
+
+        let body = json!({
+            "resourceSpans": [{
+                "resource": mk_resource(&config),
+                "scopeSpans": [{
+                    "scope": mk_scope(),
+                    "spans": spans.iter().map(FinishedSpan::to_json).collect::<Vec<_>>(),
+                }],
+            }]
+        });
+
+        if let Err(e) = post_json(&config, &endpoint, &body).await {
+            warn!("Failed to export {} spans: {}", spans.len(), e);
+        }
+    }
+}
+
+async fn export_metrics(config: OtlpConfig, endpoint: String) {
+    let mut interval = tokio::time::interval(config.metrics_interval);
+
+    loop {
+        interval.tick().await;
+
+        let Some(metrics) = METRICS.get() else {
+            continue;
+        };
+
+        if let Err(e) = post_json(&config, &endpoint, &metrics.to_json(&config)).await {
+            warn!("Failed to export metrics: {}", e);
+        }
+    }
+}
+
+/// Warns about an OTLP protocol other than the supported one. Called once the
+/// subscriber is installed, as config is read before that.
+pub fn check_otlp_protocol() {
+    if let Some(protocol) = env("OTEL_EXPORTER_OTLP_PROTOCOL") {
+        if protocol != "http/json" {
+            warn!("OTEL_EXPORTER_OTLP_PROTOCOL={protocol} is not supported, using http/json");
+        }
+    }
+}
+
+/// Starts exporting telemetry if an OTLP endpoint is configured, returning
+/// the layer collecting spans. Must be called within a tokio runtime.
+pub fn init_telemetry() -> Option<OtlpLayer> {
+    let config = OtlpConfig::from_env()?;
+
+    if let Some(endpoint) = &config.metrics_endpoint {
+        METRICS
+            .set(Metrics {
+                start: SystemTime::now(),
+                histograms: Default::default(),
+            })
+            .ok();
+
+        tokio::spawn(export_metrics(config.clone(), endpoint.clone()));
+    }
+
+    let endpoint = config.traces_endpoint.clone()?;
+    let spans: Arc<Mutex<Vec<FinishedSpan>>> = Default::default();
+    tokio::spawn(export_traces(config, endpoint, spans.clone()));
+
+    Some(OtlpLayer { spans })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signal_endpoint() {
+        assert_eq!(
+            signal_endpoint(Some("http://collector:4318/"), None, "v1/traces"),
+            Some("http://collector:4318/v1/traces".to_string())
+        );
+        assert_eq!(
+            signal_endpoint(
+                Some("http://collector:4318"),
+                Some("http://other:4318/traces"),
+                "v1/traces"
+            ),
+            Some("http://other:4318/traces".to_string())
+        );
+        assert_eq!(signal_endpoint(None, None, "v1/traces"), None);
+    }
+
+    #[test]
+    fn test_parse_headers() {
+        assert_eq!(
+            parse_headers("api-key=secret, tenant=home"),
+            vec![
+                ("api-key".to_string(), "secret".to_string()),
+                ("tenant".to_string(), "home".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut histogram = Histogram::default();
+        histogram.record(0.5);
+        histogram.record(5.0);
+        histogram.record(20000.0);
+
+        assert_eq!(histogram.count, 3);
+        assert_eq!(histogram.bucket_counts[0], 1);
+        assert_eq!(histogram.bucket_counts[1], 1);
+        assert_eq!(histogram.bucket_counts[BUCKET_BOUNDS_MS.len()], 1);
+    }
+}
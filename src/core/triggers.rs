@@ -0,0 +1,177 @@
+//! Schedules triggers of routines that run at a given moment rather than when
+//! their rules start matching
+
+use chrono::{Local, Utc};
+use color_eyre::Result;
+use eyre::{eyre, Context};
+
+use tokio::task::JoinHandle;
+
+use crate::types::{
+    event::{Message, TxEventChannel},
+    rule::{CronTrigger, RoutineId, Trigger},
+    sun::{LocationConfig, SunTrigger},
+};
+
+use super::{
+    clock::{next_cron_occurrence, sleep_until_utc},
+    sun::next_sun_trigger,
+};
+
+/// Spawns a task per trigger, sending [Message::RoutineTriggerFired] whenever
+/// it fires. Fails on invalid cron expressions, and on sun triggers if no
+/// location has been configured.
+pub fn spawn_triggers(
+    triggers: Vec<(RoutineId, Trigger)>,
+    location: Option<&LocationConfig>,
+    event_tx: &TxEventChannel,
+) -> Result<Vec<JoinHandle<()>>> {
+    let mut tasks = vec![];
+
+    for (routine_id, trigger) in triggers {
+        match spawn_trigger(routine_id, trigger, location, event_tx) {
+            Ok(task) => tasks.extend(task),
+            Err(e) => {
+                // Don't leave triggers of a partially valid config running
+                for task in tasks {
+                    task.abort();
+                }
+
+                return Err(e);
+            }
+        }
+    }
+
+    Ok(tasks)
+}
+
+/// Checks that [spawn_triggers] would succeed, without spawning anything
+pub fn validate_triggers(
+    triggers: &[(RoutineId, Trigger)],
+    location: Option<&LocationConfig>,
+) -> Result<()> {
+    for (routine_id, trigger) in triggers {
+        match trigger {
+            Trigger::Sun(_) => {
+                sun_trigger_location(routine_id, location)?;
+            }
+            Trigger::Cron(trigger) => {
+                parse_cron_trigger(routine_id, trigger)?;
+            }
+            Trigger::Startup(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn sun_trigger_location(
+    routine_id: &RoutineId,
+    location: Option<&LocationConfig>,
+) -> Result<LocationConfig> {
+    location.cloned().ok_or_else(|| {
+        eyre!(
+            "Sun trigger of routine {} requires [location] to be configured",
+            routine_id
+        )
+    })
+}
+
+fn parse_cron_trigger(routine_id: &RoutineId, trigger: &CronTrigger) -> Result<croner::Cron> {
+    croner::Cron::new(&trigger.cron)
+        .parse()
+        .wrap_err_with(|| format!("Invalid cron trigger of routine {}", routine_id))
+}
+
+fn spawn_trigger(
+    routine_id: RoutineId,
+    trigger: Trigger,
+    location: Option<&LocationConfig>,
+    event_tx: &TxEventChannel,
+) -> Result<Option<JoinHandle<()>>> {
+    let task = match trigger {
+        Trigger::Sun(trigger) => {
+            let location = sun_trigger_location(&routine_id, location)?;
+
+            tokio::spawn(run_sun_trigger(
+                routine_id,
+                trigger,
+                location,
+                event_tx.clone(),
+            ))
+        }
+        Trigger::Cron(trigger) => {
+            let cron = parse_cron_trigger(&routine_id, &trigger)?;
+
+            tokio::spawn(run_cron_trigger(routine_id, cron, event_tx.clone()))
+        }
+        // Fired by Rules once integrations have started
+        Trigger::Startup(_) => return Ok(None),
+    };
+
+    Ok(Some(task))
+}
+
+async fn run_sun_trigger(
+    routine_id: RoutineId,
+    trigger: SunTrigger,
+    location: LocationConfig,
+    event_tx: TxEventChannel,
+) {
+    let mut after = Utc::now();
+
+    loop {
+        let Some(at) = next_sun_trigger(&location, &trigger, after) else {
+            warn!(
+                "Sun trigger of routine {} won't fire within a year",
+                routine_id
+            );
+            return;
+        };
+
+        sleep_until_utc(at).await;
+        after = at;
+
+        event_tx.send(Message::RoutineTriggerFired {
+            routine_id: routine_id.clone(),
+        });
+    }
+}
+
+async fn run_cron_trigger(routine_id: RoutineId, cron: croner::Cron, event_tx: TxEventChannel) {
+    let mut last = None;
+
+    loop {
+        let Some(next) = next_cron_occurrence(&cron, &Local::now(), last) else {
+            error!(
+                "Cron trigger of routine {} has no next occurrence",
+                routine_id
+            );
+            return;
+        };
+
+        sleep_until_utc(next.with_timezone(&Utc)).await;
+        last = Some(next.naive_local());
+
+        event_tx.send(Message::RoutineTriggerFired {
+            routine_id: routine_id.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Datelike, TimeZone, Timelike, Weekday};
+
+    #[test]
+    fn test_cron_trigger() {
+        let cron = croner::Cron::new("0 7 * * MON-FRI").parse().unwrap();
+
+        // Saturday morning
+        let now = Utc.with_ymd_and_hms(2024, 6, 22, 6, 0, 0).unwrap();
+        let next = next_cron_occurrence(&cron, &now, None).unwrap();
+        assert_eq!(next.weekday(), Weekday::Mon);
+        assert_eq!(next.hour(), 7);
+    }
+}
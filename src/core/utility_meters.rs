@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{Datelike, NaiveDate};
+use ordered_float::OrderedFloat;
+
+use crate::db::{actions::db_store_utility_meter, spawn_db_write};
+use crate::types::{
+    device::{Device, DeviceData, DeviceId, SensorDevice},
+    event::{Message, TxEventChannel},
+    integration::IntegrationId,
+    utility_meter::{
+        MeterPeriod, UtilityMeterConfig, UtilityMeterId, UtilityMeterState, UtilityMetersConfig,
+        UTILITY_METERS_INTEGRATION_ID,
+    },
+};
+
+use super::clock::local_today;
+use super::devices::Devices;
+
+fn period_start(period: MeterPeriod, date: NaiveDate) -> NaiveDate {
+    match period {
+        MeterPeriod::Daily => date,
+        MeterPeriod::Weekly => {
+            date - chrono::Duration::days(date.weekday().num_days_from_monday().into())
+        }
+        MeterPeriod::Monthly => date.with_day(1).unwrap(),
+    }
+}
+
+/// Resets totals of periods that have ended, and adds consumption since the
+/// previous reading if a new reading is given
+fn accumulate(
+    config: &UtilityMeterConfig,
+    mut state: UtilityMeterState,
+    reading: Option<f64>,
+    today: NaiveDate,
+) -> UtilityMeterState {
+    let delta = match (state.last_reading, reading) {
+        // Source meter was reset
+        (Some(last), Some(reading)) if reading < last => reading,
+        (Some(last), Some(reading)) => reading - last,
+        // Nothing to compare the first reading against
+        (None, _) | (_, None) => 0.0,
+    };
+
+    for period in &config.periods {
+        let start = period_start(*period, today);
+
+        if state.period_starts.get(period) != Some(&start) {
+            state.period_starts.insert(*period, start);
+            state.totals.insert(*period, 0.0);
+        }
+
+        *state.totals.entry(*period).or_default() += delta;
+    }
+
+    if reading.is_some() {
+        state.last_reading = reading;
+    }
+
+    state
+}
+
+fn get_reading(device: &Device) -> Option<f64> {
+    match device.get_sensor_state()? {
+        SensorDevice::Number { value, .. } => Some(value.0),
+        _ => None,
+    }
+}
+
+/// Accumulates source sensors into daily, weekly and monthly totals that are
+/// reported as virtual sensors
+#[derive(Clone)]
+pub struct UtilityMeters {
+    config: UtilityMetersConfig,
+    event_tx: TxEventChannel,
+    states: BTreeMap<UtilityMeterId, UtilityMeterState>,
+}
+
+impl UtilityMeters {
+    pub fn new(
+        config: UtilityMetersConfig,
+        event_tx: TxEventChannel,
+        states: BTreeMap<UtilityMeterId, UtilityMeterState>,
+    ) -> Self {
+        UtilityMeters {
+            config,
+            event_tx,
+            states,
+        }
+    }
+
+    /// Accumulates new readings of source sensors
+    pub fn handle_internal_state_update(&mut self, new: &Device, devices: &Devices) {
+        let Some(reading) = get_reading(new) else {
+            return;
+        };
+
+        let device_key = new.get_device_key();
+
+        let meter_ids: Vec<UtilityMeterId> = self
+            .config
+            .iter()
+            .filter(|(_, meter)| {
+                devices
+                    .get_device_by_ref(&meter.source)
+                    .map(|device| device.get_device_key())
+                    == Some(device_key.clone())
+            })
+            .map(|(meter_id, _)| meter_id.clone())
+            .collect();
+
+        for meter_id in meter_ids {
+            self.update(&meter_id, Some(reading), devices);
+        }
+    }
+
+    /// Resets totals at period boundaries even if no new readings arrive
+    pub fn refresh(&mut self, devices: &Devices) {
+        let meter_ids: Vec<UtilityMeterId> = self.config.keys().cloned().collect();
+
+        for meter_id in meter_ids {
+            self.update(&meter_id, None, devices);
+        }
+    }
+
+    fn update(&mut self, meter_id: &UtilityMeterId, reading: Option<f64>, devices: &Devices) {
+        let meter = &self.config[meter_id];
+        let today = local_today();
+
+        let prev = self.states.get(meter_id).cloned().unwrap_or_default();
+        let state = accumulate(meter, prev.clone(), reading, today);
+
+        for (period, total) in &state.totals {
+            let device = Device::new(
+                IntegrationId::from_str(UTILITY_METERS_INTEGRATION_ID).unwrap(),
+                DeviceId::new(&format!("{}_{}", meter_id, period.as_str())),
+                format!("{} {}", meter.name, period.as_str()),
+                DeviceData::Sensor(SensorDevice::Number {
+                    value: OrderedFloat(*total),
+                    unit: None,
+                    raw: None,
+                }),
+            );
+
+            if devices.get_device(&device.get_device_key()) != Some(&device) {
+                self.event_tx.send(Message::RecvDeviceState { device });
+            }
+        }
+
+        if state != prev {
+            let meter_id = meter_id.clone();
+            let db_state = state.clone();
+            spawn_db_write(async move {
+                db_store_utility_meter(&meter_id, &db_state).await.ok();
+            });
+        }
+
+        self.states.insert(meter_id.clone(), state);
+    }
+}
+
+/// Periodically resets utility meter totals at period boundaries
+pub async fn refresh_utility_meters(event_tx: TxEventChannel) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+        event_tx.send(Message::RefreshUtilityMeters);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::{device::DeviceRef, integration::IntegrationId};
+
+    use super::*;
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_period_start() {
+        // 2024-03-14 is a Thursday
+        assert_eq!(period_start(MeterPeriod::Daily, date(3, 14)), date(3, 14));
+        assert_eq!(period_start(MeterPeriod::Weekly, date(3, 14)), date(3, 11));
+        assert_eq!(period_start(MeterPeriod::Monthly, date(3, 14)), date(3, 1));
+    }
+
+    #[test]
+    fn test_accumulate() {
+        let config = UtilityMeterConfig {
+            name: "Energy".to_string(),
+            source: DeviceRef::new_with_name(
+                IntegrationId::from("mqtt".to_string()),
+                "Energy meter".to_string(),
+            ),
+            periods: vec![MeterPeriod::Daily, MeterPeriod::Monthly],
+        };
+
+        let state = accumulate(&config, Default::default(), Some(100.0), date(3, 14));
+        assert_eq!(state.totals[&MeterPeriod::Daily], 0.0);
+
+        let state = accumulate(&config, state, Some(102.5), date(3, 14));
+        assert_eq!(state.totals[&MeterPeriod::Daily], 2.5);
+
+        // New day, daily total is reset but monthly keeps going
+        let state = accumulate(&config, state, None, date(3, 15));
+        assert_eq!(state.totals[&MeterPeriod::Daily], 0.0);
+        assert_eq!(state.totals[&MeterPeriod::Monthly], 2.5);
+
+        // Source meter was reset
+        let state = accumulate(&config, state, Some(1.0), date(3, 15));
+        assert_eq!(state.totals[&MeterPeriod::Daily], 1.0);
+        assert_eq!(state.totals[&MeterPeriod::Monthly], 3.5);
+    }
+}
@@ -1,27 +1,112 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use crate::types::websockets::WebSocketResponse;
+use crate::types::websockets::{WebSocketResponse, WebSocketSession, WebSocketsConfig};
+use crate::utils::constant_time_eq;
 use tokio::sync::{
     mpsc::{self, UnboundedSender},
     RwLock,
 };
 
-type Users = Arc<RwLock<HashMap<usize, mpsc::UnboundedSender<warp::ws::Message>>>>;
+struct User {
+    sender: mpsc::UnboundedSender<warp::ws::Message>,
+    session: WebSocketSession,
+}
+
+type Users = Arc<RwLock<HashMap<usize, User>>>;
 
 #[derive(Clone, Default)]
 pub struct WebSockets {
+    config: WebSocketsConfig,
     users: Users,
 }
 
 impl WebSockets {
-    pub async fn user_connected(&self, user_id: usize, sender: UnboundedSender<warp::ws::Message>) {
-        self.users.write().await.insert(user_id, sender);
+    pub fn new(config: WebSocketsConfig) -> Self {
+        WebSockets {
+            config,
+            users: Default::default(),
+        }
+    }
+
+    /// Whether clients must authenticate before receiving state
+    pub fn requires_auth(&self) -> bool {
+        self.config
+            .tokens
+            .as_ref()
+            .map_or(false, |tokens| !tokens.is_empty())
+    }
+
+    pub fn auth_timeout(&self) -> Duration {
+        Duration::from_secs(self.config.auth_timeout_secs.unwrap_or(5))
+    }
+
+    pub fn is_valid_token(&self, token: Option<&str>) -> bool {
+        if !self.requires_auth() {
+            return true;
+        }
+
+        let tokens = self.config.tokens.as_deref().unwrap_or_default();
+        token.map_or(false, |token| {
+            tokens.iter().any(|t| constant_time_eq(t, token))
+        })
+    }
+
+    pub async fn user_connected(
+        &self,
+        session: WebSocketSession,
+        sender: UnboundedSender<warp::ws::Message>,
+    ) {
+        info!(
+            "WebSocket client {} connected from {}",
+            session.client.as_deref().unwrap_or("(unnamed)"),
+            session.remote_addr.as_deref().unwrap_or("unknown address")
+        );
+
+        self.users
+            .write()
+            .await
+            .insert(session.id, User { sender, session });
     }
 
     pub async fn user_disconnected(&self, user_id: usize) {
         self.users.write().await.remove(&user_id);
     }
 
+    pub async fn get_sessions(&self) -> Vec<WebSocketSession> {
+        self.users
+            .read()
+            .await
+            .values()
+            .map(|user| user.session.clone())
+            .collect()
+    }
+
+    /// Disconnects the given client, returns false if it wasn't connected
+    pub async fn kick(&self, user_id: usize) -> bool {
+        let Some(user) = self.users.write().await.remove(&user_id) else {
+            return false;
+        };
+
+        info!("Kicking WebSocket client {}", user_id);
+
+        // 4000: first application specific code
+        let msg = warp::ws::Message::close_with(4000u16, "Kicked");
+        user.sender.send(msg).ok();
+
+        true
+    }
+
+    /// Closes all websocket connections with given reason
+    pub async fn close_all(&self, reason: &'static str) {
+        let mut users = self.users.write().await;
+
+        // 1001: Going Away
+        let msg = warp::ws::Message::close_with(1001u16, reason);
+        for (_, user) in users.drain() {
+            user.sender.send(msg.clone()).ok();
+        }
+    }
+
     pub async fn num_users(&self) -> usize {
         self.users.read().await.len()
     }
@@ -34,13 +119,12 @@ impl WebSockets {
 
         match user_id {
             Some(user_id) => {
-                let user = users.get(&user_id).cloned()?;
-                user.send(msg).ok()
+                let user = users.get(&user_id)?;
+                user.sender.send(msg).ok()
             }
             None => {
-                let users = users.clone();
-                for (_, user) in users {
-                    user.send(msg.clone()).ok();
+                for user in users.values() {
+                    user.sender.send(msg.clone()).ok();
                 }
 
                 Some(())
@@ -48,3 +132,24 @@ impl WebSockets {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_token() {
+        let open = WebSockets::default();
+        assert!(!open.requires_auth());
+        assert!(open.is_valid_token(None));
+
+        let closed = WebSockets::new(WebSocketsConfig {
+            tokens: Some(vec!["secret".to_string()]),
+            auth_timeout_secs: None,
+        });
+        assert!(closed.requires_auth());
+        assert!(closed.is_valid_token(Some("secret")));
+        assert!(!closed.is_valid_token(Some("guess")));
+        assert!(!closed.is_valid_token(None));
+    }
+}
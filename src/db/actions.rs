@@ -1,149 +1,166 @@
 use super::get_db_connection;
-use crate::types::device::{Device, DeviceData, DeviceKey, DeviceRow};
+use crate::types::device::{Device, DeviceAlias, DeviceKey};
+use crate::types::device_config::DeviceMetadata;
+use crate::types::device_expiry::UnseenDevice;
+use crate::types::history::HistoryEntry;
+use crate::types::integration::IntegrationId;
+use crate::types::preferences::{UserId, UserPreferences};
 use crate::types::scene::ScenesConfig;
-use crate::types::scene::{SceneConfig, SceneId};
+use crate::types::scene::{SceneConfig, SceneId, SceneStats};
+use crate::types::utility_meter::{UtilityMeterId, UtilityMeterState};
+use chrono::{DateTime, Utc};
 use color_eyre::Result;
-use sqlx::types::Json;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeMap;
 
 pub async fn db_update_device(device: &Device) -> Result<Device> {
-    let db = get_db_connection().await?;
-
-    let row = sqlx::query_as!(
-        DeviceRow,
-        r#"
-            insert into devices (integration_id, device_id, name, state)
-            values ($1, $2, $3, $4)
-
-            on conflict (integration_id, device_id)
-            do update set
-                name = excluded.name,
-                state = excluded.state
-
-            returning
-                integration_id,
-                device_id,
-                name,
-                state as "state: Json<DeviceData>"
-        "#,
-        &device.integration_id.to_string(),
-        &device.id.to_string(),
-        &device.name,
-        Json(device.data.clone()) as _
-    )
-    .fetch_one(db)
-    .await?;
-
-    let device = row.into();
-
-    Ok(device)
+    get_db_connection()?.update_device(device).await
 }
 
 pub async fn db_find_device(key: &DeviceKey) -> Result<Device> {
-    let db = get_db_connection().await?;
-
-    let row = sqlx::query_as!(
-        DeviceRow,
-        r#"
-            select
-                integration_id,
-                device_id,
-                name,
-                state as "state: Json<DeviceData>"
-            from devices
-            where integration_id = $1
-              and device_id = $2
-        "#,
-        &key.integration_id.to_string(),
-        &key.device_id.to_string()
-    )
-    .fetch_one(db)
-    .await?;
-
-    let device = row.into();
-
-    Ok(device)
+    get_db_connection()?.find_device(key).await
 }
 
-pub async fn db_get_scenes() -> Result<ScenesConfig> {
-    let db = get_db_connection().await?;
+/// Records that the device reported its state at given time
+pub async fn db_touch_device(key: &DeviceKey, at: DateTime<Utc>) -> Result<()> {
+    get_db_connection()?.touch_device(key, at).await
+}
+
+/// Devices that haven't reported their state since given time, least
+/// recently seen first
+pub async fn db_get_unseen_devices(since: DateTime<Utc>) -> Result<Vec<UnseenDevice>> {
+    get_db_connection()?.get_unseen_devices(since).await
+}
 
-    let result = sqlx::query!(
-        r#"
-            select
-                scene_id,
-                config as "config: Json<SceneConfig>"
+/// Deletes the device along with its metadata and recorded history
+pub async fn db_delete_device(key: &DeviceKey) -> Result<()> {
+    get_db_connection()?.delete_device(key).await
+}
 
-            from scenes
-        "#
-    )
-    .fetch_all(db)
-    .await;
+pub async fn db_get_scenes() -> Result<ScenesConfig> {
+    let result = get_db_connection()?.get_scenes().await;
 
     if let Err(err) = &result {
         error!("Error fetching scenes from DB: {:?}", err);
     }
 
-    let scenes = result?
-        .into_iter()
-        .map(|row| (SceneId::new(row.scene_id), row.config.0))
-        .collect();
-
-    Ok(scenes)
+    result
 }
 
 pub async fn db_store_scene(scene_id: &SceneId, config: &SceneConfig) -> Result<()> {
-    let db = get_db_connection().await?;
+    get_db_connection()?.store_scene(scene_id, config).await
+}
+
+pub async fn db_delete_scene(scene_id: &SceneId) -> Result<()> {
+    get_db_connection()?.delete_scene(scene_id).await
+}
 
-    sqlx::query!(
-        r#"
-            insert into scenes (scene_id, config)
-            values ($1, $2)
+pub async fn db_edit_scene(scene_id: &SceneId, name: &String) -> Result<()> {
+    get_db_connection()?.edit_scene(scene_id, name).await
+}
 
-            on conflict (scene_id)
-            do update set
-                config = excluded.config
-        "#,
-        scene_id.to_string(),
-        Json(config) as _
-    )
-    .fetch_one(db)
-    .await?;
+pub async fn db_get_integrations() -> Result<Vec<(IntegrationId, serde_json::Value)>> {
+    get_db_connection()?.get_integrations().await
+}
 
-    Ok(())
+pub async fn db_store_integration(
+    integration_id: &IntegrationId,
+    config: &serde_json::Value,
+) -> Result<()> {
+    get_db_connection()?
+        .store_integration(integration_id, config)
+        .await
 }
 
-pub async fn db_delete_scene(scene_id: &SceneId) -> Result<()> {
-    let db = get_db_connection().await?;
+pub async fn db_delete_integration(integration_id: &IntegrationId) -> Result<()> {
+    get_db_connection()?
+        .delete_integration(integration_id)
+        .await
+}
 
-    sqlx::query!(
-        r#"
-            delete from scenes
-            where scene_id = $1
-        "#,
-        scene_id.to_string(),
-    )
-    .fetch_one(db)
-    .await?;
+pub async fn db_get_device_metadata() -> Result<BTreeMap<DeviceKey, DeviceMetadata>> {
+    get_db_connection()?.get_device_metadata().await
+}
 
-    Ok(())
+pub async fn db_store_device_metadata(key: &DeviceKey, metadata: &DeviceMetadata) -> Result<()> {
+    get_db_connection()?
+        .store_device_metadata(key, metadata)
+        .await
 }
 
-pub async fn db_edit_scene(scene_id: &SceneId, name: &String) -> Result<()> {
-    let db = get_db_connection().await?;
-
-    sqlx::query!(
-        r#"
-            update scenes
-            set
-                scene_id = $2,
-                config = config::jsonb || format('{"name":"%s"}', $2::text)::jsonb
-            where scene_id = $1;
-        "#,
-        scene_id.to_string(),
-        name
-    )
-    .fetch_one(db)
-    .await?;
-
-    Ok(())
+pub async fn db_get_device_aliases() -> Result<Vec<DeviceAlias>> {
+    get_db_connection()?.get_device_aliases().await
+}
+
+/// Stores the alias and moves persisted state and metadata of the aliased
+/// device over to the new device key, replacing any that the new device
+/// already has.
+pub async fn db_migrate_device(alias: &DeviceAlias) -> Result<()> {
+    get_db_connection()?.migrate_device(alias).await
+}
+
+pub async fn db_get_scene_stats() -> Result<BTreeMap<SceneId, SceneStats>> {
+    get_db_connection()?.get_scene_stats().await
+}
+
+pub async fn db_store_scene_stats(scene_id: &SceneId, stats: &SceneStats) -> Result<()> {
+    get_db_connection()?
+        .store_scene_stats(scene_id, stats)
+        .await
+}
+
+pub async fn db_get_utility_meters() -> Result<BTreeMap<UtilityMeterId, UtilityMeterState>> {
+    get_db_connection()?.get_utility_meters().await
+}
+
+pub async fn db_store_utility_meter(id: &UtilityMeterId, state: &UtilityMeterState) -> Result<()> {
+    get_db_connection()?.store_utility_meter(id, state).await
+}
+
+pub async fn db_get_user_preferences(user_id: &UserId) -> Result<Option<UserPreferences>> {
+    get_db_connection()?.get_user_preferences(user_id).await
+}
+
+pub async fn db_store_user_preferences(
+    user_id: &UserId,
+    preferences: &UserPreferences,
+) -> Result<()> {
+    get_db_connection()?
+        .store_user_preferences(user_id, preferences)
+        .await
+}
+
+/// Returns false if the user had no stored preferences
+pub async fn db_delete_user_preferences(user_id: &UserId) -> Result<bool> {
+    get_db_connection()?.delete_user_preferences(user_id).await
+}
+
+/// Returns state of a helper stored with [db_store_helper_state], e.g. the
+/// current mode, so that it survives a restart
+pub async fn db_get_helper_state<T: DeserializeOwned>(id: &str) -> Result<Option<T>> {
+    let state = get_db_connection()?.get_helper_state(id).await?;
+    let state = state.map(serde_json::from_value).transpose()?;
+
+    Ok(state)
+}
+
+pub async fn db_store_helper_state<T: Serialize + Sync>(id: &str, state: &T) -> Result<()> {
+    let state = serde_json::to_value(state)?;
+
+    get_db_connection()?.store_helper_state(id, &state).await
+}
+
+/// Records current state of a device in its history
+pub async fn db_store_device_history(device: &Device) -> Result<()> {
+    get_db_connection()?.store_device_history(device).await
+}
+
+/// Returns recorded states of a device between `from` (inclusive) and `to`
+/// (exclusive), oldest first
+pub async fn db_get_device_history(
+    key: &DeviceKey,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<HistoryEntry>> {
+    get_db_connection()?.get_device_history(key, from, to).await
 }
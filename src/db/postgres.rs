@@ -0,0 +1,772 @@
+use super::storage::Storage;
+use crate::types::device::{Device, DeviceAlias, DeviceData, DeviceKey, DeviceRow};
+use crate::types::device_config::DeviceMetadata;
+use crate::types::device_expiry::UnseenDevice;
+use crate::types::history::HistoryEntry;
+use crate::types::integration::IntegrationId;
+use crate::types::preferences::{UserId, UserPreferences};
+use crate::types::scene::ScenesConfig;
+use crate::types::scene::{SceneConfig, SceneId, SceneStats};
+use crate::types::utility_meter::{UtilityMeterId, UtilityMeterState};
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use color_eyre::Result;
+use sqlx::{types::Json, PgPool};
+use std::collections::BTreeMap;
+
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    pub fn new(pool: PgPool) -> Self {
+        PostgresStorage { pool }
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn update_device(&self, device: &Device) -> Result<Device> {
+        let db = &self.pool;
+
+        let row = sqlx::query_as!(
+            DeviceRow,
+            r#"
+            insert into devices (integration_id, device_id, name, state)
+            values ($1, $2, $3, $4)
+
+            on conflict (integration_id, device_id)
+            do update set
+                name = excluded.name,
+                state = excluded.state
+
+            returning
+                integration_id,
+                device_id,
+                name,
+                state as "state: Json<DeviceData>"
+        "#,
+            &device.integration_id.to_string(),
+            &device.id.to_string(),
+            &device.name,
+            Json(device.data.clone()) as _
+        )
+        .fetch_one(db)
+        .await?;
+
+        let device = row.into();
+
+        Ok(device)
+    }
+
+    async fn find_device(&self, key: &DeviceKey) -> Result<Device> {
+        let db = &self.pool;
+
+        let row = sqlx::query_as!(
+            DeviceRow,
+            r#"
+            select
+                integration_id,
+                device_id,
+                name,
+                state as "state: Json<DeviceData>"
+            from devices
+            where integration_id = $1
+              and device_id = $2
+        "#,
+            &key.integration_id.to_string(),
+            &key.device_id.to_string()
+        )
+        .fetch_one(db)
+        .await?;
+
+        let device = row.into();
+
+        Ok(device)
+    }
+
+    async fn touch_device(&self, key: &DeviceKey, at: DateTime<Utc>) -> Result<()> {
+        let db = &self.pool;
+
+        sqlx::query!(
+            r#"
+            update devices
+            set last_seen_at = $3
+            where integration_id = $1
+              and device_id = $2
+        "#,
+            &key.integration_id.to_string(),
+            &key.device_id.to_string(),
+            at
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_unseen_devices(&self, since: DateTime<Utc>) -> Result<Vec<UnseenDevice>> {
+        let db = &self.pool;
+
+        let rows = sqlx::query!(
+            r#"
+            select
+                integration_id,
+                device_id,
+                name,
+                last_seen_at
+            from devices
+            where last_seen_at < $1
+            order by last_seen_at
+        "#,
+            since
+        )
+        .fetch_all(db)
+        .await?;
+
+        let devices = rows
+            .into_iter()
+            .map(|row| UnseenDevice {
+                key: DeviceKey::new(row.integration_id.into(), row.device_id.into()),
+                name: row.name,
+                last_seen_at: row.last_seen_at,
+            })
+            .collect();
+
+        Ok(devices)
+    }
+
+    async fn delete_device(&self, key: &DeviceKey) -> Result<()> {
+        let db = &self.pool;
+        let mut tx = db.begin().await?;
+
+        let integration_id = key.integration_id.to_string();
+        let device_id = key.device_id.to_string();
+
+        sqlx::query!(
+            r#"
+            delete from devices
+            where integration_id = $1
+              and device_id = $2
+        "#,
+            &integration_id,
+            &device_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            delete from device_metadata
+            where integration_id = $1
+              and device_id = $2
+        "#,
+            &integration_id,
+            &device_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            delete from device_history
+            where integration_id = $1
+              and device_id = $2
+        "#,
+            &integration_id,
+            &device_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_scenes(&self) -> Result<ScenesConfig> {
+        let db = &self.pool;
+
+        let result = sqlx::query!(
+            r#"
+            select
+                scene_id,
+                config as "config: Json<SceneConfig>"
+
+            from scenes
+        "#
+        )
+        .fetch_all(db)
+        .await;
+
+        if let Err(err) = &result {
+            error!("Error fetching scenes from DB: {:?}", err);
+        }
+
+        let scenes = result?
+            .into_iter()
+            .map(|row| (SceneId::new(row.scene_id), row.config.0))
+            .collect();
+
+        Ok(scenes)
+    }
+
+    async fn store_scene(&self, scene_id: &SceneId, config: &SceneConfig) -> Result<()> {
+        let db = &self.pool;
+
+        sqlx::query!(
+            r#"
+            insert into scenes (scene_id, config)
+            values ($1, $2)
+
+            on conflict (scene_id)
+            do update set
+                config = excluded.config
+        "#,
+            scene_id.to_string(),
+            Json(config) as _
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_scene(&self, scene_id: &SceneId) -> Result<()> {
+        let db = &self.pool;
+
+        sqlx::query!(
+            r#"
+            delete from scenes
+            where scene_id = $1
+        "#,
+            scene_id.to_string(),
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn edit_scene(&self, scene_id: &SceneId, name: &str) -> Result<()> {
+        let db = &self.pool;
+
+        sqlx::query!(
+            r#"
+            update scenes
+            set
+                scene_id = $2,
+                config = config::jsonb || format('{"name":"%s"}', $2::text)::jsonb
+            where scene_id = $1;
+        "#,
+            scene_id.to_string(),
+            name
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_integrations(&self) -> Result<Vec<(IntegrationId, serde_json::Value)>> {
+        let db = &self.pool;
+
+        let rows = sqlx::query!(
+            r#"
+            select
+                integration_id,
+                config
+            from integrations
+        "#
+        )
+        .fetch_all(db)
+        .await?;
+
+        let integrations = rows
+            .into_iter()
+            .map(|row| (IntegrationId::from(row.integration_id), row.config))
+            .collect();
+
+        Ok(integrations)
+    }
+
+    async fn store_integration(
+        &self,
+        integration_id: &IntegrationId,
+        config: &serde_json::Value,
+    ) -> Result<()> {
+        let db = &self.pool;
+
+        sqlx::query!(
+            r#"
+            insert into integrations (integration_id, config)
+            values ($1, $2)
+
+            on conflict (integration_id)
+            do update set
+                config = excluded.config
+        "#,
+            integration_id.to_string(),
+            config
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_integration(&self, integration_id: &IntegrationId) -> Result<()> {
+        let db = &self.pool;
+
+        sqlx::query!(
+            r#"
+            delete from integrations
+            where integration_id = $1
+        "#,
+            integration_id.to_string()
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_device_metadata(&self) -> Result<BTreeMap<DeviceKey, DeviceMetadata>> {
+        let db = &self.pool;
+
+        let rows = sqlx::query!(
+            r#"
+            select
+                integration_id,
+                device_id,
+                metadata as "metadata: Json<DeviceMetadata>"
+            from device_metadata
+        "#
+        )
+        .fetch_all(db)
+        .await?;
+
+        let metadata = rows
+            .into_iter()
+            .map(|row| {
+                (
+                    DeviceKey::new(row.integration_id.into(), row.device_id.into()),
+                    row.metadata.0,
+                )
+            })
+            .collect();
+
+        Ok(metadata)
+    }
+
+    async fn store_device_metadata(
+        &self,
+        key: &DeviceKey,
+        metadata: &DeviceMetadata,
+    ) -> Result<()> {
+        let db = &self.pool;
+
+        sqlx::query!(
+            r#"
+            insert into device_metadata (integration_id, device_id, metadata)
+            values ($1, $2, $3)
+
+            on conflict (integration_id, device_id)
+            do update set
+                metadata = excluded.metadata
+        "#,
+            &key.integration_id.to_string(),
+            &key.device_id.to_string(),
+            Json(metadata) as _
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_device_aliases(&self) -> Result<Vec<DeviceAlias>> {
+        let db = &self.pool;
+
+        let rows = sqlx::query!(
+            r#"
+            select
+                integration_id,
+                device_id,
+                name,
+                target_integration_id,
+                target_device_id
+            from device_aliases
+        "#
+        )
+        .fetch_all(db)
+        .await?;
+
+        let aliases = rows
+            .into_iter()
+            .map(|row| DeviceAlias {
+                from: DeviceKey::new(row.integration_id.into(), row.device_id.into()),
+                name: row.name,
+                to: DeviceKey::new(
+                    row.target_integration_id.into(),
+                    row.target_device_id.into(),
+                ),
+            })
+            .collect();
+
+        Ok(aliases)
+    }
+
+    async fn migrate_device(&self, alias: &DeviceAlias) -> Result<()> {
+        let db = &self.pool;
+        let mut tx = db.begin().await?;
+
+        let from_integration_id = alias.from.integration_id.to_string();
+        let from_device_id = alias.from.device_id.to_string();
+        let to_integration_id = alias.to.integration_id.to_string();
+        let to_device_id = alias.to.device_id.to_string();
+
+        sqlx::query!(
+            r#"
+            insert into device_aliases (integration_id, device_id, name, target_integration_id, target_device_id)
+            values ($1, $2, $3, $4, $5)
+
+            on conflict (integration_id, device_id)
+            do update set
+                name = excluded.name,
+                target_integration_id = excluded.target_integration_id,
+                target_device_id = excluded.target_device_id
+        "#,
+            &from_integration_id,
+            &from_device_id,
+            alias.name.as_ref(),
+            &to_integration_id,
+            &to_device_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            delete from devices
+            where integration_id = $3
+              and device_id = $4
+              and exists (
+                select 1 from devices
+                where integration_id = $1
+                  and device_id = $2
+              )
+        "#,
+            &from_integration_id,
+            &from_device_id,
+            &to_integration_id,
+            &to_device_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            update devices
+            set integration_id = $3,
+                device_id = $4
+            where integration_id = $1
+              and device_id = $2
+        "#,
+            &from_integration_id,
+            &from_device_id,
+            &to_integration_id,
+            &to_device_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            delete from device_metadata
+            where integration_id = $3
+              and device_id = $4
+              and exists (
+                select 1 from device_metadata
+                where integration_id = $1
+                  and device_id = $2
+              )
+        "#,
+            &from_integration_id,
+            &from_device_id,
+            &to_integration_id,
+            &to_device_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            update device_metadata
+            set integration_id = $3,
+                device_id = $4
+            where integration_id = $1
+              and device_id = $2
+        "#,
+            &from_integration_id,
+            &from_device_id,
+            &to_integration_id,
+            &to_device_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_scene_stats(&self) -> Result<BTreeMap<SceneId, SceneStats>> {
+        let db = &self.pool;
+
+        let rows = sqlx::query!(
+            r#"
+            select
+                scene_id,
+                stats as "stats: Json<SceneStats>"
+            from scene_stats
+        "#
+        )
+        .fetch_all(db)
+        .await?;
+
+        let stats = rows
+            .into_iter()
+            .map(|row| (SceneId::new(row.scene_id), row.stats.0))
+            .collect();
+
+        Ok(stats)
+    }
+
+    async fn store_scene_stats(&self, scene_id: &SceneId, stats: &SceneStats) -> Result<()> {
+        let db = &self.pool;
+
+        sqlx::query!(
+            r#"
+            insert into scene_stats (scene_id, stats)
+            values ($1, $2)
+
+            on conflict (scene_id)
+            do update set
+                stats = excluded.stats
+        "#,
+            &scene_id.to_string(),
+            Json(stats) as _
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_utility_meters(&self) -> Result<BTreeMap<UtilityMeterId, UtilityMeterState>> {
+        let db = &self.pool;
+
+        let rows = sqlx::query!(
+            r#"
+            select
+                id,
+                state as "state: Json<UtilityMeterState>"
+            from utility_meters
+        "#
+        )
+        .fetch_all(db)
+        .await?;
+
+        let states = rows
+            .into_iter()
+            .map(|row| (UtilityMeterId(row.id), row.state.0))
+            .collect();
+
+        Ok(states)
+    }
+
+    async fn store_utility_meter(
+        &self,
+        id: &UtilityMeterId,
+        state: &UtilityMeterState,
+    ) -> Result<()> {
+        let db = &self.pool;
+
+        sqlx::query!(
+            r#"
+            insert into utility_meters (id, state)
+            values ($1, $2)
+
+            on conflict (id)
+            do update set
+                state = excluded.state
+        "#,
+            &id.to_string(),
+            Json(state) as _
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_user_preferences(&self, user_id: &UserId) -> Result<Option<UserPreferences>> {
+        let db = &self.pool;
+
+        let row = sqlx::query!(
+            r#"
+            select
+                preferences as "preferences: Json<UserPreferences>"
+            from user_preferences
+            where user_id = $1
+        "#,
+            &user_id.to_string()
+        )
+        .fetch_optional(db)
+        .await?;
+
+        Ok(row.map(|row| row.preferences.0))
+    }
+
+    async fn store_user_preferences(
+        &self,
+        user_id: &UserId,
+        preferences: &UserPreferences,
+    ) -> Result<()> {
+        let db = &self.pool;
+
+        sqlx::query!(
+            r#"
+            insert into user_preferences (user_id, preferences)
+            values ($1, $2)
+
+            on conflict (user_id)
+            do update set
+                preferences = excluded.preferences
+        "#,
+            &user_id.to_string(),
+            Json(preferences) as _
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_user_preferences(&self, user_id: &UserId) -> Result<bool> {
+        let db = &self.pool;
+
+        let result = sqlx::query!(
+            r#"
+            delete from user_preferences
+            where user_id = $1
+        "#,
+            &user_id.to_string()
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_helper_state(&self, id: &str) -> Result<Option<serde_json::Value>> {
+        let db = &self.pool;
+
+        let row = sqlx::query!(
+            r#"
+            select
+                state as "state: Json<serde_json::Value>"
+            from helper_states
+            where id = $1
+        "#,
+            id
+        )
+        .fetch_optional(db)
+        .await?;
+
+        Ok(row.map(|row| row.state.0))
+    }
+
+    async fn store_helper_state(&self, id: &str, state: &serde_json::Value) -> Result<()> {
+        let db = &self.pool;
+
+        sqlx::query!(
+            r#"
+            insert into helper_states (id, state)
+            values ($1, $2)
+
+            on conflict (id)
+            do update set
+                state = excluded.state
+        "#,
+            id,
+            Json(state) as _
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn store_device_history(&self, device: &Device) -> Result<()> {
+        let db = &self.pool;
+
+        sqlx::query!(
+            r#"
+            insert into device_history (integration_id, device_id, state)
+            values ($1, $2, $3)
+        "#,
+            &device.integration_id.to_string(),
+            &device.id.to_string(),
+            Json(device.data.clone()) as _
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_device_history(
+        &self,
+        key: &DeviceKey,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<HistoryEntry>> {
+        let db = &self.pool;
+
+        let rows = sqlx::query!(
+            r#"
+            select
+                (extract(epoch from recorded_at) * 1000)::bigint as "recorded_at!",
+                state as "state: Json<DeviceData>"
+            from device_history
+            where integration_id = $1
+              and device_id = $2
+              and recorded_at >= to_timestamp($3)
+              and recorded_at < to_timestamp($4)
+            order by recorded_at
+        "#,
+            &key.integration_id.to_string(),
+            &key.device_id.to_string(),
+            from.timestamp_millis() as f64 / 1000.0,
+            to.timestamp_millis() as f64 / 1000.0
+        )
+        .fetch_all(db)
+        .await?;
+
+        let entries = rows
+            .into_iter()
+            .filter_map(|row| {
+                let at = Utc.timestamp_millis_opt(row.recorded_at).single()?;
+
+                Some(HistoryEntry {
+                    at,
+                    state: row.state.0,
+                })
+            })
+            .collect();
+
+        Ok(entries)
+    }
+}
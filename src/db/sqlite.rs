@@ -0,0 +1,806 @@
+use super::storage::Storage;
+use crate::types::device::{Device, DeviceAlias, DeviceData, DeviceKey, DeviceRow};
+use crate::types::device_config::DeviceMetadata;
+use crate::types::device_expiry::UnseenDevice;
+use crate::types::history::HistoryEntry;
+use crate::types::integration::IntegrationId;
+use crate::types::preferences::{UserId, UserPreferences};
+use crate::types::scene::ScenesConfig;
+use crate::types::scene::{SceneConfig, SceneId, SceneStats};
+use crate::types::utility_meter::{UtilityMeterId, UtilityMeterState};
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use color_eyre::Result;
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    types::Json,
+    SqlitePool,
+};
+use std::{collections::BTreeMap, str::FromStr, time::Duration};
+
+/// Stores everything in a single SQLite database file, for small deployments
+/// that don't want to run a PostgreSQL server.
+///
+/// Queries are checked at runtime instead of with `sqlx::query!`, whose
+/// offline query data only covers PostgreSQL.
+pub struct SqliteStorage {
+    pool: SqlitePool,
+}
+
+impl SqliteStorage {
+    /// Opens the database at given `sqlite:` URL, creating it and its tables
+    /// if needed
+    pub async fn connect(database_url: &str) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(database_url)?.create_if_missing(true);
+
+        let pool = SqlitePoolOptions::new()
+            .acquire_timeout(Duration::from_secs(3))
+            .connect_with(options)
+            .await?;
+
+        sqlx::migrate!("./migrations_sqlite").run(&pool).await?;
+
+        Ok(SqliteStorage { pool })
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn update_device(&self, device: &Device) -> Result<Device> {
+        let row: DeviceRow = sqlx::query_as(
+            r#"
+            insert into devices (integration_id, device_id, name, state, last_seen_at)
+            values (?1, ?2, ?3, ?4, ?5)
+
+            on conflict (integration_id, device_id)
+            do update set
+                name = excluded.name,
+                state = excluded.state
+
+            returning
+                integration_id,
+                device_id,
+                name,
+                state
+        "#,
+        )
+        .bind(device.integration_id.to_string())
+        .bind(device.id.to_string())
+        .bind(&device.name)
+        .bind(Json(&device.data))
+        .bind(Utc::now().timestamp_millis())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.into())
+    }
+
+    async fn find_device(&self, key: &DeviceKey) -> Result<Device> {
+        let row: DeviceRow = sqlx::query_as(
+            r#"
+            select
+                integration_id,
+                device_id,
+                name,
+                state
+            from devices
+            where integration_id = ?1
+              and device_id = ?2
+        "#,
+        )
+        .bind(key.integration_id.to_string())
+        .bind(key.device_id.to_string())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.into())
+    }
+
+    async fn touch_device(&self, key: &DeviceKey, at: DateTime<Utc>) -> Result<()> {
+        sqlx::query(
+            r#"
+            update devices
+            set last_seen_at = ?3
+            where integration_id = ?1
+              and device_id = ?2
+        "#,
+        )
+        .bind(key.integration_id.to_string())
+        .bind(key.device_id.to_string())
+        .bind(at.timestamp_millis())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_unseen_devices(&self, since: DateTime<Utc>) -> Result<Vec<UnseenDevice>> {
+        let rows: Vec<(String, String, String, i64)> = sqlx::query_as(
+            r#"
+            select
+                integration_id,
+                device_id,
+                name,
+                last_seen_at
+            from devices
+            where last_seen_at < ?1
+            order by last_seen_at
+        "#,
+        )
+        .bind(since.timestamp_millis())
+        .fetch_all(&self.pool)
+        .await?;
+
+        let devices = rows
+            .into_iter()
+            .filter_map(|(integration_id, device_id, name, last_seen_at)| {
+                Some(UnseenDevice {
+                    key: DeviceKey::new(integration_id.into(), device_id.into()),
+                    name,
+                    last_seen_at: Utc.timestamp_millis_opt(last_seen_at).single()?,
+                })
+            })
+            .collect();
+
+        Ok(devices)
+    }
+
+    async fn delete_device(&self, key: &DeviceKey) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            delete from devices
+            where integration_id = ?1
+              and device_id = ?2
+        "#,
+        )
+        .bind(key.integration_id.to_string())
+        .bind(key.device_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            delete from device_metadata
+            where integration_id = ?1
+              and device_id = ?2
+        "#,
+        )
+        .bind(key.integration_id.to_string())
+        .bind(key.device_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            delete from device_history
+            where integration_id = ?1
+              and device_id = ?2
+        "#,
+        )
+        .bind(key.integration_id.to_string())
+        .bind(key.device_id.to_string())
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_scenes(&self) -> Result<ScenesConfig> {
+        let rows: Vec<(String, Json<SceneConfig>)> = sqlx::query_as(
+            r#"
+            select
+                scene_id,
+                config
+            from scenes
+        "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let scenes = rows
+            .into_iter()
+            .map(|(scene_id, config)| (SceneId::new(scene_id), config.0))
+            .collect();
+
+        Ok(scenes)
+    }
+
+    async fn store_scene(&self, scene_id: &SceneId, config: &SceneConfig) -> Result<()> {
+        sqlx::query(
+            r#"
+            insert into scenes (scene_id, config)
+            values (?1, ?2)
+
+            on conflict (scene_id)
+            do update set
+                config = excluded.config
+        "#,
+        )
+        .bind(scene_id.to_string())
+        .bind(Json(config))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_scene(&self, scene_id: &SceneId) -> Result<()> {
+        sqlx::query(
+            r#"
+            delete from scenes
+            where scene_id = ?1
+        "#,
+        )
+        .bind(scene_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn edit_scene(&self, scene_id: &SceneId, name: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            update scenes
+            set
+                scene_id = ?2,
+                config = json_set(config, '$.name', ?2)
+            where scene_id = ?1
+        "#,
+        )
+        .bind(scene_id.to_string())
+        .bind(name)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_integrations(&self) -> Result<Vec<(IntegrationId, serde_json::Value)>> {
+        let rows: Vec<(String, Json<serde_json::Value>)> = sqlx::query_as(
+            r#"
+            select
+                integration_id,
+                config
+            from integrations
+        "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let integrations = rows
+            .into_iter()
+            .map(|(integration_id, config)| (IntegrationId::from(integration_id), config.0))
+            .collect();
+
+        Ok(integrations)
+    }
+
+    async fn store_integration(
+        &self,
+        integration_id: &IntegrationId,
+        config: &serde_json::Value,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            insert into integrations (integration_id, config)
+            values (?1, ?2)
+
+            on conflict (integration_id)
+            do update set
+                config = excluded.config
+        "#,
+        )
+        .bind(integration_id.to_string())
+        .bind(Json(config))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_integration(&self, integration_id: &IntegrationId) -> Result<()> {
+        sqlx::query(
+            r#"
+            delete from integrations
+            where integration_id = ?1
+        "#,
+        )
+        .bind(integration_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_device_metadata(&self) -> Result<BTreeMap<DeviceKey, DeviceMetadata>> {
+        let rows: Vec<(String, String, Json<DeviceMetadata>)> = sqlx::query_as(
+            r#"
+            select
+                integration_id,
+                device_id,
+                metadata
+            from device_metadata
+        "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let metadata = rows
+            .into_iter()
+            .map(|(integration_id, device_id, metadata)| {
+                (
+                    DeviceKey::new(integration_id.into(), device_id.into()),
+                    metadata.0,
+                )
+            })
+            .collect();
+
+        Ok(metadata)
+    }
+
+    async fn store_device_metadata(
+        &self,
+        key: &DeviceKey,
+        metadata: &DeviceMetadata,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            insert into device_metadata (integration_id, device_id, metadata)
+            values (?1, ?2, ?3)
+
+            on conflict (integration_id, device_id)
+            do update set
+                metadata = excluded.metadata
+        "#,
+        )
+        .bind(key.integration_id.to_string())
+        .bind(key.device_id.to_string())
+        .bind(Json(metadata))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_device_aliases(&self) -> Result<Vec<DeviceAlias>> {
+        let rows: Vec<(String, String, Option<String>, String, String)> = sqlx::query_as(
+            r#"
+            select
+                integration_id,
+                device_id,
+                name,
+                target_integration_id,
+                target_device_id
+            from device_aliases
+        "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let aliases = rows
+            .into_iter()
+            .map(
+                |(integration_id, device_id, name, target_integration_id, target_device_id)| {
+                    DeviceAlias {
+                        from: DeviceKey::new(integration_id.into(), device_id.into()),
+                        name,
+                        to: DeviceKey::new(target_integration_id.into(), target_device_id.into()),
+                    }
+                },
+            )
+            .collect();
+
+        Ok(aliases)
+    }
+
+    async fn migrate_device(&self, alias: &DeviceAlias) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        let from_integration_id = alias.from.integration_id.to_string();
+        let from_device_id = alias.from.device_id.to_string();
+        let to_integration_id = alias.to.integration_id.to_string();
+        let to_device_id = alias.to.device_id.to_string();
+
+        sqlx::query(
+            r#"
+            insert into device_aliases (integration_id, device_id, name, target_integration_id, target_device_id)
+            values (?1, ?2, ?3, ?4, ?5)
+
+            on conflict (integration_id, device_id)
+            do update set
+                name = excluded.name,
+                target_integration_id = excluded.target_integration_id,
+                target_device_id = excluded.target_device_id
+        "#,
+        )
+        .bind(&from_integration_id)
+        .bind(&from_device_id)
+        .bind(alias.name.as_ref())
+        .bind(&to_integration_id)
+        .bind(&to_device_id)
+        .execute(&mut *tx)
+        .await?;
+
+        // Same statements for both tables: drop the target's row if the
+        // aliased device has one, then move the aliased device's row over
+        for table in ["devices", "device_metadata"] {
+            sqlx::query(&format!(
+                r#"
+                delete from {table}
+                where integration_id = ?3
+                  and device_id = ?4
+                  and exists (
+                    select 1 from {table}
+                    where integration_id = ?1
+                      and device_id = ?2
+                  )
+            "#
+            ))
+            .bind(&from_integration_id)
+            .bind(&from_device_id)
+            .bind(&to_integration_id)
+            .bind(&to_device_id)
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query(&format!(
+                r#"
+                update {table}
+                set integration_id = ?3,
+                    device_id = ?4
+                where integration_id = ?1
+                  and device_id = ?2
+            "#
+            ))
+            .bind(&from_integration_id)
+            .bind(&from_device_id)
+            .bind(&to_integration_id)
+            .bind(&to_device_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_scene_stats(&self) -> Result<BTreeMap<SceneId, SceneStats>> {
+        let rows: Vec<(String, Json<SceneStats>)> = sqlx::query_as(
+            r#"
+            select
+                scene_id,
+                stats
+            from scene_stats
+        "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let stats = rows
+            .into_iter()
+            .map(|(scene_id, stats)| (SceneId::new(scene_id), stats.0))
+            .collect();
+
+        Ok(stats)
+    }
+
+    async fn store_scene_stats(&self, scene_id: &SceneId, stats: &SceneStats) -> Result<()> {
+        sqlx::query(
+            r#"
+            insert into scene_stats (scene_id, stats)
+            values (?1, ?2)
+
+            on conflict (scene_id)
+            do update set
+                stats = excluded.stats
+        "#,
+        )
+        .bind(scene_id.to_string())
+        .bind(Json(stats))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_utility_meters(&self) -> Result<BTreeMap<UtilityMeterId, UtilityMeterState>> {
+        let rows: Vec<(String, Json<UtilityMeterState>)> = sqlx::query_as(
+            r#"
+            select
+                id,
+                state
+            from utility_meters
+        "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let states = rows
+            .into_iter()
+            .map(|(id, state)| (UtilityMeterId(id), state.0))
+            .collect();
+
+        Ok(states)
+    }
+
+    async fn store_utility_meter(
+        &self,
+        id: &UtilityMeterId,
+        state: &UtilityMeterState,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            insert into utility_meters (id, state)
+            values (?1, ?2)
+
+            on conflict (id)
+            do update set
+                state = excluded.state
+        "#,
+        )
+        .bind(id.to_string())
+        .bind(Json(state))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_user_preferences(&self, user_id: &UserId) -> Result<Option<UserPreferences>> {
+        let row: Option<(Json<UserPreferences>,)> = sqlx::query_as(
+            r#"
+            select
+                preferences
+            from user_preferences
+            where user_id = ?1
+        "#,
+        )
+        .bind(user_id.to_string())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|(preferences,)| preferences.0))
+    }
+
+    async fn store_user_preferences(
+        &self,
+        user_id: &UserId,
+        preferences: &UserPreferences,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            insert into user_preferences (user_id, preferences)
+            values (?1, ?2)
+
+            on conflict (user_id)
+            do update set
+                preferences = excluded.preferences
+        "#,
+        )
+        .bind(user_id.to_string())
+        .bind(Json(preferences))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_user_preferences(&self, user_id: &UserId) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            delete from user_preferences
+            where user_id = ?1
+        "#,
+        )
+        .bind(user_id.to_string())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_helper_state(&self, id: &str) -> Result<Option<serde_json::Value>> {
+        let row: Option<(Json<serde_json::Value>,)> = sqlx::query_as(
+            r#"
+            select
+                state
+            from helper_states
+            where id = ?1
+        "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|(state,)| state.0))
+    }
+
+    async fn store_helper_state(&self, id: &str, state: &serde_json::Value) -> Result<()> {
+        sqlx::query(
+            r#"
+            insert into helper_states (id, state)
+            values (?1, ?2)
+
+            on conflict (id)
+            do update set
+                state = excluded.state
+        "#,
+        )
+        .bind(id)
+        .bind(Json(state))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn store_device_history(&self, device: &Device) -> Result<()> {
+        sqlx::query(
+            r#"
+            insert into device_history (integration_id, device_id, recorded_at, state)
+            values (?1, ?2, ?3, ?4)
+        "#,
+        )
+        .bind(device.integration_id.to_string())
+        .bind(device.id.to_string())
+        .bind(Utc::now().timestamp_millis())
+        .bind(Json(&device.data))
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_device_history(
+        &self,
+        key: &DeviceKey,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<HistoryEntry>> {
+        let rows: Vec<(i64, Json<DeviceData>)> = sqlx::query_as(
+            r#"
+            select
+                recorded_at,
+                state
+            from device_history
+            where integration_id = ?1
+              and device_id = ?2
+              and recorded_at >= ?3
+              and recorded_at < ?4
+            order by recorded_at
+        "#,
+        )
+        .bind(key.integration_id.to_string())
+        .bind(key.device_id.to_string())
+        .bind(from.timestamp_millis())
+        .bind(to.timestamp_millis())
+        .fetch_all(&self.pool)
+        .await?;
+
+        let entries = rows
+            .into_iter()
+            .filter_map(|(recorded_at, state)| {
+                let at = Utc.timestamp_millis_opt(recorded_at).single()?;
+
+                Some(HistoryEntry { at, state: state.0 })
+            })
+            .collect();
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::device::{DeviceId, SensorDevice};
+
+    async fn mk_storage() -> SqliteStorage {
+        SqliteStorage::connect("sqlite::memory:").await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_scene_stats_roundtrip() {
+        let storage = mk_storage().await;
+        let scene_id = SceneId::new("night".to_string());
+
+        let stats = SceneStats {
+            activation_count: 3,
+            last_activated_at: Some(Utc.timestamp_millis_opt(1_700_000_000_000).unwrap()),
+        };
+        storage.store_scene_stats(&scene_id, &stats).await.unwrap();
+        storage.store_scene_stats(&scene_id, &stats).await.unwrap();
+
+        let stored = storage.get_scene_stats().await.unwrap();
+        assert_eq!(stored, BTreeMap::from([(scene_id, stats)]));
+    }
+
+    #[tokio::test]
+    async fn test_device_roundtrip() {
+        let storage = mk_storage().await;
+
+        let device = Device::new(
+            IntegrationId::from("dummy".to_string()),
+            DeviceId::new("sensor"),
+            "Sensor".to_string(),
+            DeviceData::Sensor(SensorDevice::Boolean { value: true }),
+        );
+
+        storage.update_device(&device).await.unwrap();
+        storage.store_device_history(&device).await.unwrap();
+
+        let key = device.get_device_key();
+        assert_eq!(storage.find_device(&key).await.unwrap(), device);
+
+        let history = storage
+            .get_device_history(
+                &key,
+                Utc::now() - chrono::Duration::hours(1),
+                Utc::now() + chrono::Duration::hours(1),
+            )
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].state, device.data);
+    }
+
+    #[tokio::test]
+    async fn test_unseen_devices() {
+        let storage = mk_storage().await;
+
+        let device = Device::new(
+            IntegrationId::from("dummy".to_string()),
+            DeviceId::new("sensor"),
+            "Sensor".to_string(),
+            DeviceData::Sensor(SensorDevice::Boolean { value: true }),
+        );
+        let key = device.get_device_key();
+
+        storage.update_device(&device).await.unwrap();
+        storage.store_device_history(&device).await.unwrap();
+
+        let later = Utc::now() + chrono::Duration::minutes(1);
+        assert_eq!(storage.get_unseen_devices(later).await.unwrap().len(), 1);
+
+        let last_seen_at = Utc.timestamp_millis_opt(1_700_000_000_000).unwrap();
+        storage.touch_device(&key, last_seen_at).await.unwrap();
+        assert_eq!(
+            storage.get_unseen_devices(last_seen_at).await.unwrap(),
+            vec![]
+        );
+        assert_eq!(
+            storage
+                .get_unseen_devices(last_seen_at + chrono::Duration::days(1))
+                .await
+                .unwrap(),
+            vec![UnseenDevice {
+                key: key.clone(),
+                name: device.name.clone(),
+                last_seen_at,
+            }]
+        );
+
+        storage.delete_device(&key).await.unwrap();
+        assert!(storage.find_device(&key).await.is_err());
+        assert_eq!(storage.get_unseen_devices(later).await.unwrap(), vec![]);
+    }
+
+    #[tokio::test]
+    async fn test_helper_state_roundtrip() {
+        let storage = mk_storage().await;
+
+        assert_eq!(storage.get_helper_state("mode").await.unwrap(), None);
+
+        let state = serde_json::json!({ "mode": "away" });
+        storage.store_helper_state("mode", &state).await.unwrap();
+        storage.store_helper_state("mode", &state).await.unwrap();
+
+        assert_eq!(storage.get_helper_state("mode").await.unwrap(), Some(state));
+    }
+}
@@ -0,0 +1,72 @@
+use crate::types::device::{Device, DeviceAlias, DeviceKey};
+use crate::types::device_config::DeviceMetadata;
+use crate::types::device_expiry::UnseenDevice;
+use crate::types::history::HistoryEntry;
+use crate::types::integration::IntegrationId;
+use crate::types::preferences::{UserId, UserPreferences};
+use crate::types::scene::{SceneConfig, SceneId, SceneStats, ScenesConfig};
+use crate::types::utility_meter::{UtilityMeterId, UtilityMeterState};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use color_eyre::Result;
+use std::collections::BTreeMap;
+
+/// Database backend, see the `db_*` functions in [super::actions] for what
+/// each method does
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn update_device(&self, device: &Device) -> Result<Device>;
+    async fn find_device(&self, key: &DeviceKey) -> Result<Device>;
+    async fn touch_device(&self, key: &DeviceKey, at: DateTime<Utc>) -> Result<()>;
+    async fn get_unseen_devices(&self, since: DateTime<Utc>) -> Result<Vec<UnseenDevice>>;
+    async fn delete_device(&self, key: &DeviceKey) -> Result<()>;
+
+    async fn get_scenes(&self) -> Result<ScenesConfig>;
+    async fn store_scene(&self, scene_id: &SceneId, config: &SceneConfig) -> Result<()>;
+    async fn delete_scene(&self, scene_id: &SceneId) -> Result<()>;
+    async fn edit_scene(&self, scene_id: &SceneId, name: &str) -> Result<()>;
+
+    async fn get_scene_stats(&self) -> Result<BTreeMap<SceneId, SceneStats>>;
+    async fn store_scene_stats(&self, scene_id: &SceneId, stats: &SceneStats) -> Result<()>;
+
+    async fn get_integrations(&self) -> Result<Vec<(IntegrationId, serde_json::Value)>>;
+    async fn store_integration(
+        &self,
+        integration_id: &IntegrationId,
+        config: &serde_json::Value,
+    ) -> Result<()>;
+    async fn delete_integration(&self, integration_id: &IntegrationId) -> Result<()>;
+
+    async fn get_device_metadata(&self) -> Result<BTreeMap<DeviceKey, DeviceMetadata>>;
+    async fn store_device_metadata(&self, key: &DeviceKey, metadata: &DeviceMetadata)
+        -> Result<()>;
+
+    async fn get_device_aliases(&self) -> Result<Vec<DeviceAlias>>;
+    async fn migrate_device(&self, alias: &DeviceAlias) -> Result<()>;
+
+    async fn get_utility_meters(&self) -> Result<BTreeMap<UtilityMeterId, UtilityMeterState>>;
+    async fn store_utility_meter(
+        &self,
+        id: &UtilityMeterId,
+        state: &UtilityMeterState,
+    ) -> Result<()>;
+
+    async fn get_user_preferences(&self, user_id: &UserId) -> Result<Option<UserPreferences>>;
+    async fn store_user_preferences(
+        &self,
+        user_id: &UserId,
+        preferences: &UserPreferences,
+    ) -> Result<()>;
+    async fn delete_user_preferences(&self, user_id: &UserId) -> Result<bool>;
+
+    async fn get_helper_state(&self, id: &str) -> Result<Option<serde_json::Value>>;
+    async fn store_helper_state(&self, id: &str, state: &serde_json::Value) -> Result<()>;
+
+    async fn store_device_history(&self, device: &Device) -> Result<()>;
+    async fn get_device_history(
+        &self,
+        key: &DeviceKey,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<HistoryEntry>>;
+}
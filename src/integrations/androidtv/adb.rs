@@ -0,0 +1,75 @@
+//! Client for the ADB server's smart socket protocol. The ADB server (started
+//! with `adb start-server`) takes care of connecting to and authenticating
+//! with devices, requests are length prefixed strings answered with OKAY or
+//! FAIL.
+
+use color_eyre::Result;
+use eyre::eyre;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+pub struct Adb {
+    /// Address of the ADB server
+    pub server: String,
+
+    /// Serial of the device, `host:port` for devices connected over network
+    pub serial: String,
+}
+
+async fn read_len_prefixed(stream: &mut TcpStream) -> Result<String> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len).await?;
+    let len = usize::from_str_radix(std::str::from_utf8(&len)?, 16)?;
+
+    let mut msg = vec![0; len];
+    stream.read_exact(&mut msg).await?;
+
+    Ok(String::from_utf8_lossy(&msg).into_owned())
+}
+
+async fn request(stream: &mut TcpStream, payload: &str) -> Result<()> {
+    stream
+        .write_all(format!("{:04x}{}", payload.len(), payload).as_bytes())
+        .await?;
+
+    let mut status = [0; 4];
+    stream.read_exact(&mut status).await?;
+
+    match &status {
+        b"OKAY" => Ok(()),
+        b"FAIL" => Err(eyre!(
+            "ADB request failed: {}",
+            read_len_prefixed(stream).await?
+        )),
+        _ => Err(eyre!("Unexpected ADB response {:?}", status)),
+    }
+}
+
+impl Adb {
+    /// Makes the ADB server connect to the device, if it isn't already
+    pub async fn connect(&self) -> Result<()> {
+        let mut stream = TcpStream::connect(&self.server).await?;
+        request(&mut stream, &format!("host:connect:{}", self.serial)).await?;
+
+        let reply = read_len_prefixed(&mut stream).await?;
+        if reply.starts_with("connected") || reply.starts_with("already connected") {
+            Ok(())
+        } else {
+            Err(eyre!("Could not connect to {}: {}", self.serial, reply))
+        }
+    }
+
+    /// Runs a shell command on the device, returning its output
+    pub async fn shell(&self, command: &str) -> Result<String> {
+        let mut stream = TcpStream::connect(&self.server).await?;
+        request(&mut stream, &format!("host:transport:{}", self.serial)).await?;
+        request(&mut stream, &format!("shell:{}", command)).await?;
+
+        let mut output = vec![];
+        stream.read_to_end(&mut output).await?;
+
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+}
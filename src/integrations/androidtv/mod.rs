@@ -0,0 +1,401 @@
+//! Android TV integration, controlling TVs over ADB through a local ADB
+//! server. Power is a controllable device, the foreground app and playback
+//! state are reported as sensors.
+
+mod adb;
+
+use crate::core::schema::JsonSchema;
+use crate::types::{
+    color::Capabilities,
+    device::{ControllableDevice, Device, DeviceData, DeviceId, ManageKind, SensorDevice},
+    event::{Message, TxEventChannel},
+    integration::{Integration, IntegrationActionPayload, IntegrationId},
+};
+use async_trait::async_trait;
+use color_eyre::Result;
+use eyre::{eyre, Context};
+use serde::Deserialize;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tokio::{sync::Notify, task::JoinHandle};
+
+use adb::Adb;
+
+const POWER_DEVICE_ID: &str = "power";
+const APP_DEVICE_ID: &str = "app";
+const PLAYBACK_DEVICE_ID: &str = "playback";
+
+const STATUS_COMMAND: &str = "dumpsys power | grep mWakefulness=; \
+    dumpsys window | grep mCurrentFocus=; \
+    dumpsys media_session | grep 'state=PlaybackState'";
+
+/// How long to wait for the TV to act on a command before polling its state
+const SETTLE_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct AndroidTvConfig {
+    /// Address of the TV, which must have network debugging enabled
+    host: String,
+
+    /// ADB port of the TV, defaults to 5555
+    port: Option<u16>,
+
+    /// Address of the ADB server, defaults to `127.0.0.1:5037`
+    adb_server: Option<String>,
+
+    /// Name of the power device, the app and playback sensors get " app" and
+    /// " playback" appended. Defaults to "TV".
+    name: Option<String>,
+
+    /// Apps that can be launched by name, e.g. `netflix = "com.netflix.ninja"`.
+    /// The app sensor reports these names instead of package names.
+    #[serde(default)]
+    apps: BTreeMap<String, String>,
+
+    /// Seconds between polling the state of the TV, defaults to 10
+    poll_secs: Option<u64>,
+}
+
+/// Payload of integration actions, e.g. `{ "launch": "netflix" }` or
+/// `{ "key": "KEYCODE_MEDIA_PLAY_PAUSE" }`
+#[derive(Debug, Deserialize)]
+struct AndroidTvActionPayload {
+    /// Configured app name or package name of an app to launch
+    launch: Option<String>,
+
+    /// Key event to send
+    key: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Playback {
+    Playing,
+    Paused,
+    Idle,
+}
+
+impl Playback {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Playback::Playing => "playing",
+            Playback::Paused => "paused",
+            Playback::Idle => "idle",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct TvStatus {
+    awake: bool,
+    package: Option<String>,
+    playback: Playback,
+}
+
+/// Parses output of [STATUS_COMMAND]
+fn parse_status(output: &str) -> TvStatus {
+    let mut status = TvStatus {
+        awake: false,
+        package: None,
+        playback: Playback::Idle,
+    };
+
+    for line in output.lines().map(str::trim) {
+        if let Some(wakefulness) = line.strip_prefix("mWakefulness=") {
+            status.awake = wakefulness == "Awake";
+        } else if let Some(focus) = line.strip_prefix("mCurrentFocus=") {
+            // e.g. Window{5e2a1b3 u0 com.netflix.ninja/com.netflix.ninja.MainActivity}
+            status.package = focus
+                .trim_end_matches('}')
+                .split_whitespace()
+                .last()
+                .and_then(|component| component.split_once('/'))
+                .map(|(package, _)| package.to_string());
+        } else if let Some(state) = line
+            .split_once("state=PlaybackState {state=")
+            .and_then(|(_, rest)| rest.split(|c: char| !c.is_ascii_digit()).next())
+        {
+            // Any playing session wins over paused ones
+            status.playback = match (state, status.playback) {
+                ("3", _) => Playback::Playing,
+                ("2", Playback::Idle) => Playback::Paused,
+                (_, playback) => playback,
+            };
+        }
+    }
+
+    status
+}
+
+/// Guards against shell injection through action payloads
+fn is_shell_safe(arg: &str) -> bool {
+    !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_')
+}
+
+pub struct AndroidTv {
+    id: IntegrationId,
+    config: AndroidTvConfig,
+    event_tx: TxEventChannel,
+    adb: Arc<Adb>,
+    refresh: Arc<Notify>,
+    poll_handle: Option<JoinHandle<()>>,
+}
+
+#[async_trait]
+impl Integration for AndroidTv {
+    fn new(id: &IntegrationId, config: &config::Value, event_tx: TxEventChannel) -> Result<Self> {
+        let config: AndroidTvConfig = config
+            .clone()
+            .try_deserialize()
+            .wrap_err("Failed to deserialize config of AndroidTv integration")?;
+
+        for (name, package) in &config.apps {
+            if !is_shell_safe(package) {
+                return Err(eyre!("Invalid package name {} of app {}", package, name));
+            }
+        }
+
+        let adb = Adb {
+            server: config
+                .adb_server
+                .clone()
+                .unwrap_or_else(|| "127.0.0.1:5037".to_string()),
+            serial: format!("{}:{}", config.host, config.port.unwrap_or(5555)),
+        };
+
+        Ok(AndroidTv {
+            id: id.clone(),
+            config,
+            event_tx,
+            adb: Arc::new(adb),
+            refresh: Default::default(),
+            poll_handle: None,
+        })
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        let poller = Poller {
+            id: self.id.clone(),
+            config: self.config.clone(),
+            event_tx: self.event_tx.clone(),
+            adb: self.adb.clone(),
+            refresh: self.refresh.clone(),
+        };
+
+        self.poll_handle = Some(tokio::spawn(poller.run()));
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        if let Some(poll_handle) = self.poll_handle.take() {
+            poll_handle.abort();
+        }
+
+        Ok(())
+    }
+
+    async fn set_integration_device_state(&mut self, device: &Device) -> Result<()> {
+        if device.id.to_string() != POWER_DEVICE_ID {
+            return Ok(());
+        }
+
+        let Some(state) = device.get_controllable_state() else {
+            return Ok(());
+        };
+
+        let key = if state.power {
+            "KEYCODE_WAKEUP"
+        } else {
+            "KEYCODE_SLEEP"
+        };
+
+        self.run_command(format!("input keyevent {}", key));
+
+        Ok(())
+    }
+
+    async fn run_integration_action(&mut self, payload: &IntegrationActionPayload) -> Result<()> {
+        let payload: AndroidTvActionPayload = serde_json::from_str(&payload.to_string())
+            .wrap_err("Failed to deserialize AndroidTv integration action payload")?;
+
+        let command = match (payload.launch, payload.key) {
+            (Some(app), _) => {
+                let package = self.config.apps.get(&app).unwrap_or(&app);
+
+                if !is_shell_safe(package) {
+                    return Err(eyre!("Invalid app {}", app));
+                }
+
+                format!(
+                    "monkey -p {} -c android.intent.category.LAUNCHER 1",
+                    package
+                )
+            }
+            (None, Some(key)) => {
+                if !is_shell_safe(&key) {
+                    return Err(eyre!("Invalid key {}", key));
+                }
+
+                format!("input keyevent {}", key)
+            }
+            (None, None) => return Err(eyre!("Expected either launch or key")),
+        };
+
+        self.run_command(command);
+
+        Ok(())
+    }
+
+    async fn poll_device(&mut self, _device: &Device) -> Result<bool> {
+        self.refresh.notify_one();
+
+        Ok(true)
+    }
+}
+
+impl AndroidTv {
+    /// Runs a shell command in the background, as the TV may take a while to
+    /// respond, then refreshes its state
+    fn run_command(&self, command: String) {
+        let id = self.id.clone();
+        let adb = self.adb.clone();
+        let refresh = self.refresh.clone();
+
+        tokio::spawn(async move {
+            let result = async {
+                adb.connect().await?;
+                adb.shell(&command).await
+            }
+            .await;
+
+            if let Err(e) = result {
+                warn!(integration_id = %id, "Running {} failed: {:?}", command, e);
+            }
+
+            tokio::time::sleep(SETTLE_DELAY).await;
+            refresh.notify_one();
+        });
+    }
+}
+
+struct Poller {
+    id: IntegrationId,
+    config: AndroidTvConfig,
+    event_tx: TxEventChannel,
+    adb: Arc<Adb>,
+    refresh: Arc<Notify>,
+}
+
+impl Poller {
+    async fn run(self) {
+        let poll_interval = Duration::from_secs(self.config.poll_secs.unwrap_or(10));
+        let mut reachable = true;
+
+        loop {
+            let result = async {
+                self.adb.connect().await?;
+                self.adb.shell(STATUS_COMMAND).await
+            }
+            .await;
+
+            match result {
+                Ok(output) => {
+                    reachable = true;
+                    self.report(parse_status(&output));
+                }
+                // The TV may well be unplugged, only warn once
+                Err(e) if reachable => {
+                    reachable = false;
+                    warn!(integration_id = %self.id, "Could not poll {}: {:?}", self.adb.serial, e);
+                }
+                Err(_) => {}
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(poll_interval) => {}
+                _ = self.refresh.notified() => {}
+            }
+        }
+    }
+
+    fn report(&self, status: TvStatus) {
+        let name = self.config.name.as_deref().unwrap_or("TV");
+
+        // Prefer configured app names over package names
+        let app = status.package.map_or_else(String::new, |package| {
+            self.config
+                .apps
+                .iter()
+                .find(|(_, app_package)| **app_package == package)
+                .map_or(package, |(app, _)| app.clone())
+        });
+
+        let devices = [
+            (
+                POWER_DEVICE_ID,
+                name.to_string(),
+                DeviceData::Controllable(ControllableDevice::new(
+                    None,
+                    status.awake,
+                    None,
+                    None,
+                    None,
+                    Capabilities::default(),
+                    ManageKind::Partial {
+                        prev_change_committed: false,
+                    },
+                )),
+            ),
+            (
+                APP_DEVICE_ID,
+                format!("{} app", name),
+                DeviceData::Sensor(SensorDevice::Text { value: app }),
+            ),
+            (
+                PLAYBACK_DEVICE_ID,
+                format!("{} playback", name),
+                DeviceData::Sensor(SensorDevice::Text {
+                    value: status.playback.as_str().to_string(),
+                }),
+            ),
+        ];
+
+        for (device_id, name, data) in devices {
+            let device = Device::new(self.id.clone(), DeviceId::new(device_id), name, data);
+            self.event_tx.send(Message::RecvDeviceState { device });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_status() {
+        let output = "  mWakefulness=Awake
+  mCurrentFocus=Window{5e2a1b3 u0 com.netflix.ninja/com.netflix.ninja.MainActivity}
+      state=PlaybackState {state=2, position=0, buffered position=0, speed=0.0}
+      state=PlaybackState {state=3, position=1234, buffered position=0, speed=1.0}
+";
+
+        assert_eq!(
+            parse_status(output),
+            TvStatus {
+                awake: true,
+                package: Some("com.netflix.ninja".to_string()),
+                playback: Playback::Playing,
+            }
+        );
+
+        let asleep = parse_status("  mWakefulness=Asleep\n  mCurrentFocus=null\n");
+        assert!(!asleep.awake);
+        assert_eq!(asleep.package, None);
+        assert_eq!(asleep.playback, Playback::Idle);
+
+        assert!(is_shell_safe("com.netflix.ninja"));
+        assert!(!is_shell_safe("x; reboot"));
+    }
+}
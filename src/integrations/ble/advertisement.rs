@@ -0,0 +1,317 @@
+//! Parsing of LE advertising reports and decoding of the sensor readings
+//! they carry, in ATC/pvvx, BTHome v2 or unencrypted MiBeacon format.
+
+const HCI_EVENT_PKT: u8 = 0x04;
+const EVT_LE_META_EVENT: u8 = 0x3e;
+const EVT_LE_ADVERTISING_REPORT: u8 = 0x02;
+
+const AD_SERVICE_DATA_16: u8 = 0x16;
+
+const UUID_ENVIRONMENTAL_SENSING: u16 = 0x181a;
+const UUID_BTHOME: u16 = 0xfcd2;
+const UUID_MIBEACON: u16 = 0xfe95;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Measurement {
+    Temperature,
+    Humidity,
+    Battery,
+    Voltage,
+    Pressure,
+    Illuminance,
+}
+
+impl Measurement {
+    pub fn id(&self) -> &'static str {
+        match self {
+            Measurement::Temperature => "temperature",
+            Measurement::Humidity => "humidity",
+            Measurement::Battery => "battery",
+            Measurement::Voltage => "voltage",
+            Measurement::Pressure => "pressure",
+            Measurement::Illuminance => "illuminance",
+        }
+    }
+
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Measurement::Temperature => "°C",
+            Measurement::Humidity | Measurement::Battery => "%",
+            Measurement::Voltage => "V",
+            Measurement::Pressure => "hPa",
+            Measurement::Illuminance => "lx",
+        }
+    }
+}
+
+pub type Reading = (Measurement, f64);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Advertisement {
+    /// Address of the advertiser, in display order
+    pub mac: [u8; 6],
+    pub data: Vec<u8>,
+}
+
+/// Formats an address as `A4:C1:38:11:22:33`
+pub fn format_mac(mac: &[u8; 6]) -> String {
+    let octets: Vec<String> = mac.iter().map(|octet| format!("{:02X}", octet)).collect();
+    octets.join(":")
+}
+
+/// Parses the advertising reports of an LE meta event packet as read from a
+/// raw HCI socket
+pub fn parse_advertising_reports(packet: &[u8]) -> Vec<Advertisement> {
+    let mut advertisements = vec![];
+
+    let [HCI_EVENT_PKT, EVT_LE_META_EVENT, _len, EVT_LE_ADVERTISING_REPORT, num_reports, reports @ ..] =
+        packet
+    else {
+        return advertisements;
+    };
+
+    let mut rest = reports;
+    for _ in 0..*num_reports {
+        // Event type, address type, address, data length
+        let [_event_type, _addr_type, a0, a1, a2, a3, a4, a5, len, after @ ..] = rest else {
+            break;
+        };
+
+        let len = *len as usize;
+        // Data is followed by RSSI
+        if after.len() < len + 1 {
+            break;
+        }
+
+        advertisements.push(Advertisement {
+            mac: [*a5, *a4, *a3, *a2, *a1, *a0],
+            data: after[..len].to_vec(),
+        });
+
+        rest = &after[len + 1..];
+    }
+
+    advertisements
+}
+
+/// Decodes readings from service data within advertising data
+pub fn decode_readings(data: &[u8]) -> Vec<Reading> {
+    let mut readings = vec![];
+    let mut rest = data;
+
+    while let [len, after @ ..] = rest {
+        let len = *len as usize;
+        if len == 0 || after.len() < len {
+            break;
+        }
+
+        if let [AD_SERVICE_DATA_16, uuid_lo, uuid_hi, service_data @ ..] = &after[..len] {
+            let uuid = u16::from_le_bytes([*uuid_lo, *uuid_hi]);
+
+            readings.extend(match uuid {
+                UUID_ENVIRONMENTAL_SENSING => decode_atc(service_data),
+                UUID_BTHOME => decode_bthome(service_data),
+                UUID_MIBEACON => decode_mibeacon(service_data),
+                _ => vec![],
+            });
+        }
+
+        rest = &after[len..];
+    }
+
+    readings
+}
+
+/// Custom formats of the ATC1441 and pvvx firmwares for Xiaomi thermometers
+fn decode_atc(data: &[u8]) -> Vec<Reading> {
+    match data {
+        // ATC1441: big endian, temperature in tenths of °C
+        [_, _, _, _, _, _, t0, t1, humidity, battery, _, _, _] => vec![
+            (
+                Measurement::Temperature,
+                i16::from_be_bytes([*t0, *t1]) as f64 / 10.0,
+            ),
+            (Measurement::Humidity, *humidity as f64),
+            (Measurement::Battery, *battery as f64),
+        ],
+        // pvvx: little endian, temperature and humidity in hundredths
+        [_, _, _, _, _, _, t0, t1, h0, h1, v0, v1, battery, _, _] => vec![
+            (
+                Measurement::Temperature,
+                i16::from_le_bytes([*t0, *t1]) as f64 / 100.0,
+            ),
+            (
+                Measurement::Humidity,
+                u16::from_le_bytes([*h0, *h1]) as f64 / 100.0,
+            ),
+            (
+                Measurement::Voltage,
+                u16::from_le_bytes([*v0, *v1]) as f64 / 1000.0,
+            ),
+            (Measurement::Battery, *battery as f64),
+        ],
+        _ => vec![],
+    }
+}
+
+fn le_unsigned(bytes: &[u8]) -> f64 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0u32, |value, byte| value << 8 | *byte as u32) as f64
+}
+
+fn le_signed(bytes: &[u8]) -> f64 {
+    let bits = bytes.len() * 8;
+    let value = le_unsigned(bytes) as i64;
+
+    // Sign extend from the most significant bit
+    (value << (64 - bits) >> (64 - bits)) as f64
+}
+
+/// BTHome v2, unencrypted only
+fn decode_bthome(data: &[u8]) -> Vec<Reading> {
+    let mut readings = vec![];
+
+    let [device_info, objects @ ..] = data else {
+        return readings;
+    };
+
+    let encrypted = device_info & 0x01 != 0;
+    let version = device_info >> 5;
+    if encrypted || version != 2 {
+        return readings;
+    }
+
+    let mut rest = objects;
+    while let [object_id, after @ ..] = rest {
+        // Length, whether it's signed, divisor and measurement of each
+        // object. Lengths of other objects are unknown, so we can't skip past
+        // them.
+        let (len, signed, divisor, measurement) = match object_id {
+            0x00 => (1, false, 1.0, None),
+            0x01 => (1, false, 1.0, Some(Measurement::Battery)),
+            0x02 => (2, true, 100.0, Some(Measurement::Temperature)),
+            0x03 => (2, false, 100.0, Some(Measurement::Humidity)),
+            0x04 => (3, false, 100.0, Some(Measurement::Pressure)),
+            0x05 => (3, false, 100.0, Some(Measurement::Illuminance)),
+            0x0c => (2, false, 1000.0, Some(Measurement::Voltage)),
+            0x2e => (1, false, 1.0, Some(Measurement::Humidity)),
+            0x45 => (2, true, 10.0, Some(Measurement::Temperature)),
+            _ => break,
+        };
+
+        if after.len() < len {
+            break;
+        }
+
+        let bytes = &after[..len];
+        if let Some(measurement) = measurement {
+            let value = if signed {
+                le_signed(bytes)
+            } else {
+                le_unsigned(bytes)
+            };
+
+            readings.push((measurement, value / divisor));
+        }
+
+        rest = &after[len..];
+    }
+
+    readings
+}
+
+/// Xiaomi MiBeacon, as sent by e.g. LYWSDCGQ thermometers. Encrypted
+/// beacons need a bind key and are skipped.
+fn decode_mibeacon(data: &[u8]) -> Vec<Reading> {
+    let [fc0, fc1, _, _, _, rest @ ..] = data else {
+        return vec![];
+    };
+
+    let frame_control = u16::from_le_bytes([*fc0, *fc1]);
+    let encrypted = frame_control & 0x0008 != 0;
+    let has_mac = frame_control & 0x0010 != 0;
+    let has_capability = frame_control & 0x0020 != 0;
+    let has_object = frame_control & 0x0040 != 0;
+
+    if encrypted || !has_object {
+        return vec![];
+    }
+
+    let skip = if has_mac { 6 } else { 0 } + usize::from(has_capability);
+    let Some([t0, t1, len, object @ ..]) = rest.get(skip..) else {
+        return vec![];
+    };
+
+    let Some(object) = object.get(..*len as usize) else {
+        return vec![];
+    };
+
+    let tenths = |lo: u8, hi: u8| i16::from_le_bytes([lo, hi]) as f64 / 10.0;
+
+    match (u16::from_le_bytes([*t0, *t1]), object) {
+        (0x1004, [lo, hi]) => vec![(Measurement::Temperature, tenths(*lo, *hi))],
+        (0x1006, [lo, hi]) => vec![(Measurement::Humidity, tenths(*lo, *hi))],
+        (0x100a, [battery]) => vec![(Measurement::Battery, *battery as f64)],
+        (0x100d, [t_lo, t_hi, h_lo, h_hi]) => vec![
+            (Measurement::Temperature, tenths(*t_lo, *t_hi)),
+            (Measurement::Humidity, tenths(*h_lo, *h_hi)),
+        ],
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advertisements() {
+        // LE meta event with a single report from A4:C1:38:11:22:33, carrying
+        // pvvx service data: 21.53 °C, 45.67 %, 2.987 V, 88 %
+        let packet = [
+            0x04, 0x3e, 0x1f, 0x02, 0x01, 0x00, 0x00, 0x33, 0x22, 0x11, 0x38, 0xc1, 0xa4, 0x13,
+            0x12, 0x16, 0x1a, 0x18, 0x33, 0x22, 0x11, 0x38, 0xc1, 0xa4, 0x69, 0x08, 0xd7, 0x11,
+            0xab, 0x0b, 0x58, 0x01, 0x04, 0xc5,
+        ];
+
+        let advertisements = parse_advertising_reports(&packet);
+        assert_eq!(advertisements.len(), 1);
+        assert_eq!(format_mac(&advertisements[0].mac), "A4:C1:38:11:22:33");
+        assert_eq!(
+            decode_readings(&advertisements[0].data),
+            vec![
+                (Measurement::Temperature, 21.53),
+                (Measurement::Humidity, 45.67),
+                (Measurement::Voltage, 2.987),
+                (Measurement::Battery, 88.0),
+            ]
+        );
+
+        // BTHome v2: packet id, battery 97 %, temperature -5.5 °C
+        let bthome = [
+            0x0b, 0x16, 0xd2, 0xfc, 0x40, 0x00, 0x07, 0x01, 0x61, 0x02, 0xda, 0xfd,
+        ];
+        assert_eq!(
+            decode_readings(&bthome),
+            vec![
+                (Measurement::Battery, 97.0),
+                (Measurement::Temperature, -5.5),
+            ]
+        );
+
+        // MiBeacon with MAC and a combined temperature and humidity object
+        let mibeacon = [
+            0x15, 0x16, 0x95, 0xfe, 0x50, 0x20, 0xaa, 0x01, 0x01, 0x33, 0x22, 0x11, 0x38, 0xc1,
+            0xa4, 0x0d, 0x10, 0x04, 0xdc, 0x00, 0xb0, 0x01,
+        ];
+        assert_eq!(
+            decode_readings(&mibeacon),
+            vec![
+                (Measurement::Temperature, 22.0),
+                (Measurement::Humidity, 43.2),
+            ]
+        );
+    }
+}
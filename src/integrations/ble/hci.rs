@@ -0,0 +1,189 @@
+use color_eyre::Result;
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+#[cfg(target_os = "linux")]
+use tokio::io::unix::AsyncFd;
+
+/// Raw HCI socket receiving LE advertising reports of a Bluetooth adapter
+#[cfg(target_os = "linux")]
+pub struct HciSocket {
+    fd: AsyncFd<OwnedFd>,
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    pub const BTPROTO_HCI: libc::c_int = 1;
+    pub const SOL_HCI: libc::c_int = 0;
+    pub const HCI_FILTER: libc::c_int = 2;
+    pub const HCI_CHANNEL_RAW: u16 = 0;
+
+    pub const HCI_COMMAND_PKT: u8 = 0x01;
+    pub const HCI_EVENT_PKT: u8 = 0x04;
+    pub const EVT_LE_META_EVENT: u32 = 0x3e;
+
+    /// OGF 0x08 (LE controller commands), OCF 0x000b and 0x000c
+    pub const LE_SET_SCAN_PARAMETERS: u16 = 0x200b;
+    pub const LE_SET_SCAN_ENABLE: u16 = 0x200c;
+
+    #[repr(C)]
+    pub struct SockaddrHci {
+        pub hci_family: libc::sa_family_t,
+        pub hci_dev: u16,
+        pub hci_channel: u16,
+    }
+
+    #[repr(C)]
+    pub struct HciFilter {
+        pub type_mask: u32,
+        pub event_mask: [u32; 2],
+        pub opcode: u16,
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl HciSocket {
+    /// Opens a raw socket on given adapter, e.g. 0 for hci0. Needs root or
+    /// the CAP_NET_RAW capability.
+    pub fn open(adapter: u16) -> Result<HciSocket> {
+        // SAFETY: plain socket calls, all pointers point to live, correctly
+        // sized values, and the returned fd is immediately owned
+        let fd = unsafe {
+            let fd = libc::socket(
+                libc::AF_BLUETOOTH,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                sys::BTPROTO_HCI,
+            );
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            let fd = OwnedFd::from_raw_fd(fd);
+
+            // Only receive LE meta events, which include advertising reports
+            let filter = sys::HciFilter {
+                type_mask: 1 << sys::HCI_EVENT_PKT,
+                event_mask: [0, 1 << (sys::EVT_LE_META_EVENT - 32)],
+                opcode: 0,
+            };
+            if libc::setsockopt(
+                fd.as_raw_fd(),
+                sys::SOL_HCI,
+                sys::HCI_FILTER,
+                &filter as *const _ as *const libc::c_void,
+                std::mem::size_of::<sys::HciFilter>() as libc::socklen_t,
+            ) != 0
+            {
+                return Err(std::io::Error::last_os_error().into());
+            }
+
+            let addr = sys::SockaddrHci {
+                hci_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+                hci_dev: adapter,
+                hci_channel: sys::HCI_CHANNEL_RAW,
+            };
+            if libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const _ as *const libc::sockaddr,
+                std::mem::size_of::<sys::SockaddrHci>() as libc::socklen_t,
+            ) != 0
+            {
+                return Err(std::io::Error::last_os_error().into());
+            }
+
+            fd
+        };
+
+        Ok(HciSocket {
+            fd: AsyncFd::new(fd)?,
+        })
+    }
+
+    fn send_command(&self, opcode: u16, params: &[u8]) -> Result<()> {
+        let [opcode_lo, opcode_hi] = opcode.to_le_bytes();
+        let mut packet = vec![
+            sys::HCI_COMMAND_PKT,
+            opcode_lo,
+            opcode_hi,
+            params.len() as u8,
+        ];
+        packet.extend_from_slice(params);
+
+        // SAFETY: packet is a live buffer of given length
+        let n = unsafe {
+            libc::write(
+                self.fd.get_ref().as_raw_fd(),
+                packet.as_ptr() as *const libc::c_void,
+                packet.len(),
+            )
+        };
+        if n < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(())
+    }
+
+    /// Starts passive scanning, without filtering duplicates so that changing
+    /// readings keep coming in
+    pub fn start_scan(&self) -> Result<()> {
+        // Scanning may have been left enabled, in which case parameters can't
+        // be changed
+        self.send_command(sys::LE_SET_SCAN_ENABLE, &[0x00, 0x00])?;
+
+        // Passive, 10 ms interval and window, public address, accept all
+        self.send_command(
+            sys::LE_SET_SCAN_PARAMETERS,
+            &[0x00, 0x10, 0x00, 0x10, 0x00, 0x00, 0x00],
+        )?;
+
+        self.send_command(sys::LE_SET_SCAN_ENABLE, &[0x01, 0x00])
+    }
+
+    pub async fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+
+            // SAFETY: buf is a live, writable buffer of given length
+            let result = guard.try_io(|fd| {
+                let n = unsafe {
+                    libc::read(
+                        fd.get_ref().as_raw_fd(),
+                        buf.as_mut_ptr() as *mut libc::c_void,
+                        buf.len(),
+                    )
+                };
+
+                if n < 0 {
+                    Err(std::io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            });
+
+            match result {
+                Ok(n) => return Ok(n?),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub struct HciSocket;
+
+#[cfg(not(target_os = "linux"))]
+impl HciSocket {
+    pub fn open(adapter: u16) -> Result<HciSocket> {
+        Err(eyre::eyre!(
+            "Bluetooth adapter hci{} is not supported on this platform",
+            adapter
+        ))
+    }
+
+    pub fn start_scan(&self) -> Result<()> {
+        Ok(())
+    }
+
+    pub async fn read(&self, _buf: &mut [u8]) -> Result<usize> {
+        Ok(0)
+    }
+}
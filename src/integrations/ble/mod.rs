@@ -0,0 +1,178 @@
+//! Bluetooth LE integration, passively scanning for advertisements of
+//! thermometers and other sensors on one of the host's Bluetooth adapters.
+//! Supports the ATC1441 and pvvx firmwares for Xiaomi thermometers, BTHome
+//! v2 and unencrypted Xiaomi MiBeacon advertisements.
+
+mod advertisement;
+mod hci;
+
+use crate::core::schema::JsonSchema;
+use crate::types::{
+    device::{Device, DeviceData, DeviceId, SensorDevice},
+    event::{Message, TxEventChannel},
+    integration::{Integration, IntegrationId},
+};
+use async_trait::async_trait;
+use color_eyre::Result;
+use eyre::Context;
+use ordered_float::OrderedFloat;
+use serde::Deserialize;
+use std::{collections::BTreeMap, time::Duration};
+use tokio::task::JoinHandle;
+
+use advertisement::{decode_readings, format_mac, parse_advertising_reports, Measurement};
+use hci::HciSocket;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct BleDeviceConfig {
+    /// Prefix of sensor names, e.g. "Bedroom" results in "Bedroom
+    /// temperature" and "Bedroom humidity"
+    pub name: String,
+
+    /// Bluetooth address, e.g. `A4:C1:38:11:22:33`
+    pub mac: String,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct BleConfig {
+    /// Index of the Bluetooth adapter, defaults to 0 for hci0
+    adapter: Option<u16>,
+
+    /// Seconds to wait before retrying after the adapter failed, defaults
+    /// to 5
+    reconnect_secs: Option<u64>,
+
+    /// Only listed devices are reported, as neighbours' sensors would also
+    /// be picked up otherwise
+    devices: Vec<BleDeviceConfig>,
+}
+
+pub struct Ble {
+    id: IntegrationId,
+    config: BleConfig,
+    event_tx: TxEventChannel,
+    scan_handle: Option<JoinHandle<()>>,
+}
+
+#[async_trait]
+impl Integration for Ble {
+    fn new(id: &IntegrationId, config: &config::Value, event_tx: TxEventChannel) -> Result<Self> {
+        let config = config
+            .clone()
+            .try_deserialize()
+            .wrap_err("Failed to deserialize config of Ble integration")?;
+
+        Ok(Ble {
+            id: id.clone(),
+            config,
+            event_tx,
+            scan_handle: None,
+        })
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        let devices = self
+            .config
+            .devices
+            .iter()
+            .map(|device| (device.mac.to_uppercase(), device.name.clone()))
+            .collect();
+
+        let scanner = Scanner {
+            id: self.id.clone(),
+            adapter: self.config.adapter.unwrap_or(0),
+            reconnect: Duration::from_secs(self.config.reconnect_secs.unwrap_or(5)),
+            event_tx: self.event_tx.clone(),
+            devices,
+            last_values: BTreeMap::new(),
+        };
+
+        self.scan_handle = Some(tokio::spawn(scanner.run()));
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        if let Some(scan_handle) = self.scan_handle.take() {
+            scan_handle.abort();
+        }
+
+        Ok(())
+    }
+}
+
+fn reading_device_id(mac: &str, measurement: Measurement) -> DeviceId {
+    let mac = mac.replace(':', "").to_lowercase();
+    DeviceId::new(&format!("{}-{}", mac, measurement.id()))
+}
+
+struct Scanner {
+    id: IntegrationId,
+    adapter: u16,
+    reconnect: Duration,
+    event_tx: TxEventChannel,
+
+    /// Names of configured devices by address
+    devices: BTreeMap<String, String>,
+
+    /// Sensors advertise several times a second, only report changes
+    last_values: BTreeMap<DeviceId, f64>,
+}
+
+impl Scanner {
+    async fn run(mut self) {
+        loop {
+            if let Err(e) = self.scan().await {
+                warn!(integration_id = %self.id, "Scanning on hci{} failed: {:?}", self.adapter, e);
+            }
+
+            tokio::time::sleep(self.reconnect).await;
+        }
+    }
+
+    async fn scan(&mut self) -> Result<()> {
+        let socket = HciSocket::open(self.adapter)?;
+        socket.start_scan()?;
+
+        info!(integration_id = %self.id, "Scanning for advertisements on hci{}", self.adapter);
+
+        let mut buf = [0; 512];
+
+        loop {
+            let n = socket.read(&mut buf).await?;
+
+            for advertisement in parse_advertising_reports(&buf[..n]) {
+                let mac = format_mac(&advertisement.mac);
+
+                let Some(name) = self.devices.get(&mac).cloned() else {
+                    continue;
+                };
+
+                for (measurement, value) in decode_readings(&advertisement.data) {
+                    self.report(&mac, &name, measurement, value);
+                }
+            }
+        }
+    }
+
+    fn report(&mut self, mac: &str, name: &str, measurement: Measurement, value: f64) {
+        let device_id = reading_device_id(mac, measurement);
+
+        if self.last_values.insert(device_id.clone(), value) == Some(value) {
+            return;
+        }
+
+        let device = Device::new(
+            self.id.clone(),
+            device_id,
+            format!("{} {}", name, measurement.id()),
+            DeviceData::Sensor(SensorDevice::Number {
+                value: OrderedFloat(value),
+                unit: Some(measurement.unit().to_string()),
+                raw: None,
+            }),
+        );
+
+        self.event_tx.send(Message::RecvDeviceState { device });
+    }
+}
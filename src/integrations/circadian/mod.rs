@@ -1,3 +1,5 @@
+use crate::core::clock::local_now;
+use crate::core::schema::JsonSchema;
 use crate::types::{
     color::DeviceColor,
     device::{ControllableState, Device, DeviceData, DeviceId, SensorDevice},
@@ -11,10 +13,11 @@ use eyre::Context;
 use ordered_float::OrderedFloat;
 use palette::Mix;
 use serde::Deserialize;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::time;
+use tokio::{task::JoinHandle, time};
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 pub struct CircadianConfig {
     device_name: String,
 
@@ -36,6 +39,7 @@ pub struct Circadian {
     id: IntegrationId,
     config: CircadianConfig,
     event_tx: TxEventChannel,
+    poll_handle: Option<Arc<JoinHandle<()>>>,
     converted_day_color: DeviceColor,
     converted_night_color: DeviceColor,
 }
@@ -52,6 +56,7 @@ impl Integration for Circadian {
             id: id.clone(),
             config: config.clone(),
             event_tx,
+            poll_handle: None,
             converted_day_color: config.day_color,
             converted_night_color: config.night_color,
         })
@@ -70,14 +75,23 @@ impl Integration for Circadian {
 
         // FIXME: can we restructure the integrations / devices systems such
         // that polling is not needed here?
-        tokio::spawn(async { poll_sensor(circadian).await });
+        let poll_handle = tokio::spawn(async { poll_sensor(circadian).await });
+        self.poll_handle = Some(Arc::new(poll_handle));
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        if let Some(poll_handle) = self.poll_handle.take() {
+            poll_handle.abort();
+        }
 
         Ok(())
     }
 }
 
 fn get_night_fade(circadian: &Circadian) -> f32 {
-    let local = chrono::Local::now().naive_local().time();
+    let local = local_now().time();
 
     let day_fade_start = circadian.config.day_fade_start;
     let day_fade_duration = chrono::Duration::hours(circadian.config.day_fade_duration_hours);
@@ -168,6 +182,7 @@ fn mk_circadian_device(circadian: &Circadian) -> Device {
     let state = DeviceData::Sensor(SensorDevice::Color(ControllableState {
         power: true,
         color: Some(get_circadian_color(circadian)),
+        segments: None,
         brightness: get_circadian_brightness(circadian).map(OrderedFloat),
         transition_ms: Some(POLL_RATE),
     }));
@@ -1,3 +1,5 @@
+use crate::core::clock::next_cron_occurrence;
+use crate::core::schema::JsonSchema;
 use crate::types::{
     action::Action,
     color::Capabilities,
@@ -6,17 +8,18 @@ use crate::types::{
     integration::{Integration, IntegrationActionPayload, IntegrationId},
 };
 use async_trait::async_trait;
-use chrono::Local;
+use chrono::{Local, Utc};
 use color_eyre::Result;
 use eyre::Context;
 use serde::Deserialize;
 use std::{collections::HashMap, sync::Arc};
 use tokio::{
     sync::RwLock,
+    task::JoinHandle,
     time::{sleep_until, Instant},
 };
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct CronScheduleConfig {
     name: String,
     schedule: String,
@@ -24,7 +27,7 @@ pub struct CronScheduleConfig {
     init_enabled: Option<bool>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct CronConfig {
     schedules: HashMap<DeviceId, CronScheduleConfig>,
 }
@@ -34,6 +37,7 @@ pub struct Cron {
     event_tx: TxEventChannel,
     config: CronConfig,
     devices: Arc<RwLock<HashMap<DeviceId, Device>>>,
+    schedule_handles: Vec<JoinHandle<()>>,
 }
 
 #[async_trait]
@@ -49,6 +53,7 @@ impl Integration for Cron {
             config,
             event_tx,
             devices: Default::default(),
+            schedule_handles: Vec::new(),
         })
     }
 
@@ -84,13 +89,22 @@ impl Integration for Cron {
 
             let cron = croner::Cron::new(&config.schedule).parse()?;
 
-            tokio::spawn(async move {
-                loop {
-                    let next = cron.find_next_occurrence(&Local::now(), false).unwrap();
+            let schedule_handle = tokio::spawn(async move {
+                let mut last = None;
 
-                    let duration = next - Local::now();
+                loop {
+                    let Some(next) = next_cron_occurrence(&cron, &Local::now(), last) else {
+                        error!("Cron schedule of device {} has no next occurrence", id);
+                        return;
+                    };
+
+                    // Sleep based on UTC so that DST transitions don't matter
+                    let duration = (next.with_timezone(&Utc) - Utc::now())
+                        .to_std()
+                        .unwrap_or_default();
                     trace!("Sleeping for {:?}", duration);
-                    sleep_until(Instant::now() + duration.to_std().unwrap()).await;
+                    sleep_until(Instant::now() + duration).await;
+                    last = Some(next.naive_local());
 
                     debug!("Running cron job for device {}", id);
 
@@ -101,6 +115,16 @@ impl Integration for Cron {
                     }
                 }
             });
+
+            self.schedule_handles.push(schedule_handle);
+        }
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        for schedule_handle in self.schedule_handles.drain(..) {
+            schedule_handle.abort();
         }
 
         Ok(())
@@ -0,0 +1,269 @@
+//! DoorBird integration, following ring and motion events through the LAN
+//! API monitor stream and taking a snapshot whenever the doorbell rings.
+
+use crate::core::{
+    http::{basic_auth, get, get_stream},
+    schema::JsonSchema,
+};
+use crate::types::{
+    device::{Device, DeviceData, DeviceId, SensorDevice},
+    event::{Message, TxEventChannel},
+    integration::{Integration, IntegrationId},
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use color_eyre::Result;
+use eyre::{eyre, Context};
+use futures::StreamExt;
+use serde::Deserialize;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+
+const DOORBELL_DEVICE_ID: &str = "doorbell";
+const MOTION_DEVICE_ID: &str = "motion";
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct DoorbirdConfig {
+    /// Address of the DoorBird, e.g. `doorbird.lan`
+    host: String,
+
+    /// User with API permissions, created in the DoorBird app
+    user: String,
+    password: String,
+
+    /// Name of the doorbell sensor, the motion sensor gets " motion" appended.
+    /// Defaults to "Doorbell".
+    name: Option<String>,
+
+    /// Seconds after which ring and motion sensors turn off again, so that
+    /// repeated rings trigger routines again. Defaults to 5.
+    reset_secs: Option<u64>,
+
+    /// Seconds to wait before reconnecting, defaults to 5
+    reconnect_secs: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DoorbirdEvent {
+    Doorbell,
+    Motion,
+}
+
+/// Parses a line of the monitor stream such as `doorbell:H`, where H means
+/// the event is happening and L that it isn't
+fn parse_event(line: &str) -> Option<(DoorbirdEvent, bool)> {
+    let (event, state) = line.trim().split_once(':')?;
+
+    let event = match event {
+        "doorbell" => DoorbirdEvent::Doorbell,
+        "motionsensor" => DoorbirdEvent::Motion,
+        _ => return None,
+    };
+
+    match state {
+        "H" => Some((event, true)),
+        "L" => Some((event, false)),
+        _ => None,
+    }
+}
+
+pub struct Doorbird {
+    id: IntegrationId,
+    config: DoorbirdConfig,
+    event_tx: TxEventChannel,
+    snapshot: Arc<Mutex<Option<Bytes>>>,
+    monitor_handle: Option<JoinHandle<()>>,
+}
+
+#[async_trait]
+impl Integration for Doorbird {
+    fn new(id: &IntegrationId, config: &config::Value, event_tx: TxEventChannel) -> Result<Self> {
+        let config = config
+            .clone()
+            .try_deserialize()
+            .wrap_err("Failed to deserialize config of Doorbird integration")?;
+
+        Ok(Doorbird {
+            id: id.clone(),
+            config,
+            event_tx,
+            snapshot: Default::default(),
+            monitor_handle: None,
+        })
+    }
+
+    async fn register(&mut self) -> Result<()> {
+        let monitor = self.monitor();
+
+        for event in [DoorbirdEvent::Doorbell, DoorbirdEvent::Motion] {
+            monitor.set_sensor(event, false);
+        }
+
+        Ok(())
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        self.monitor_handle = Some(tokio::spawn(self.monitor().run()));
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        if let Some(monitor_handle) = self.monitor_handle.take() {
+            monitor_handle.abort();
+        }
+
+        Ok(())
+    }
+
+    async fn get_snapshot(&mut self) -> Option<Bytes> {
+        self.snapshot.lock().unwrap().clone()
+    }
+}
+
+impl Doorbird {
+    fn monitor(&self) -> Monitor {
+        Monitor {
+            id: self.id.clone(),
+            config: self.config.clone(),
+            event_tx: self.event_tx.clone(),
+            snapshot: self.snapshot.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Monitor {
+    id: IntegrationId,
+    config: DoorbirdConfig,
+    event_tx: TxEventChannel,
+    snapshot: Arc<Mutex<Option<Bytes>>>,
+}
+
+impl Monitor {
+    fn url(&self, path: &str) -> String {
+        format!("http://{}/bha-api/{}", self.config.host, path)
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        vec![basic_auth(&self.config.user, &self.config.password)]
+    }
+
+    async fn run(self) {
+        let reconnect = Duration::from_secs(self.config.reconnect_secs.unwrap_or(5));
+
+        loop {
+            match self.follow().await {
+                Ok(()) => {
+                    warn!(integration_id = %self.id, "Monitor stream of {} closed", self.config.host);
+                }
+                Err(e) => {
+                    warn!(integration_id = %self.id, "Monitor stream of {} failed: {:?}", self.config.host, e);
+                }
+            }
+
+            tokio::time::sleep(reconnect).await;
+        }
+    }
+
+    async fn follow(&self) -> Result<()> {
+        let url = self.url("monitor.cgi?ring=doorbell,motionsensor");
+        let (status, mut body) = get_stream(&url, &self.headers()).await?;
+
+        if !status.is_success() {
+            return Err(eyre!("Unexpected status {}", status));
+        }
+
+        info!(integration_id = %self.id, "Following events of {}", self.config.host);
+
+        // Events are sent as parts of a multipart response, one per line
+        let mut buf = String::new();
+
+        while let Some(chunk) = body.next().await {
+            buf.push_str(&String::from_utf8_lossy(&chunk?));
+
+            while let Some(newline) = buf.find('\n') {
+                let line: String = buf.drain(..=newline).collect();
+
+                if let Some((event, active)) = parse_event(&line) {
+                    self.handle_event(event, active);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_event(&self, event: DoorbirdEvent, active: bool) {
+        self.set_sensor(event, active);
+
+        if !active {
+            return;
+        }
+
+        let monitor = self.clone();
+        tokio::spawn(async move {
+            if event == DoorbirdEvent::Doorbell {
+                monitor.take_snapshot().await;
+            }
+
+            let reset = Duration::from_secs(monitor.config.reset_secs.unwrap_or(5));
+            tokio::time::sleep(reset).await;
+            monitor.set_sensor(event, false);
+        });
+    }
+
+    async fn take_snapshot(&self) {
+        match get(&self.url("image.cgi"), &self.headers()).await {
+            Ok((status, image)) if status.is_success() => {
+                *self.snapshot.lock().unwrap() = Some(image);
+            }
+            Ok((status, _)) => {
+                warn!(integration_id = %self.id, "Taking snapshot failed with status {}", status);
+            }
+            Err(e) => {
+                warn!(integration_id = %self.id, "Taking snapshot failed: {:?}", e);
+            }
+        }
+    }
+
+    fn set_sensor(&self, event: DoorbirdEvent, value: bool) {
+        let name = self.config.name.as_deref().unwrap_or("Doorbell");
+
+        let (device_id, name) = match event {
+            DoorbirdEvent::Doorbell => (DOORBELL_DEVICE_ID, name.to_string()),
+            DoorbirdEvent::Motion => (MOTION_DEVICE_ID, format!("{} motion", name)),
+        };
+
+        let device = Device::new(
+            self.id.clone(),
+            DeviceId::new(device_id),
+            name,
+            DeviceData::Sensor(SensorDevice::Boolean { value }),
+        );
+
+        self.event_tx.send(Message::RecvDeviceState { device });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_event() {
+        assert_eq!(
+            parse_event("doorbell:H\r\n"),
+            Some((DoorbirdEvent::Doorbell, true))
+        );
+        assert_eq!(
+            parse_event("motionsensor:L"),
+            Some((DoorbirdEvent::Motion, false))
+        );
+        assert_eq!(parse_event("--ioboundary"), None);
+        assert_eq!(parse_event("Content-Type: text/plain"), None);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::core::schema::JsonSchema;
 use crate::types::{
     color::Capabilities,
     device::{ControllableDevice, Device, DeviceData, DeviceId, ManageKind},
@@ -10,13 +11,13 @@ use eyre::Context;
 use serde::Deserialize;
 use std::collections::HashMap;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct DummyDeviceConfig {
     name: String,
     init_state: Option<DeviceData>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct DummyConfig {
     devices: HashMap<DeviceId, DummyDeviceConfig>,
 }
@@ -60,6 +61,7 @@ impl Integration for Dummy {
                 )));
 
             let device = Device::new(self.id.clone(), id.clone(), device.name.clone(), state);
+            self.devices.insert(id.clone(), device.clone());
             self.event_tx.send(Message::RecvDeviceState { device });
         }
 
@@ -80,4 +82,14 @@ impl Integration for Dummy {
         // do nothing
         Ok(())
     }
+
+    async fn poll_device(&mut self, device: &Device) -> Result<bool> {
+        if let Some(device) = self.devices.get(&device.id) {
+            self.event_tx.send(Message::RecvDeviceState {
+                device: device.clone(),
+            });
+        }
+
+        Ok(true)
+    }
 }
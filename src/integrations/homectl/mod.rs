@@ -0,0 +1,233 @@
+//! Mirrors devices of another homectl instance over its WebSocket API, and
+//! forwards state changes of mirrored devices back to it.
+
+use crate::core::schema::JsonSchema;
+use crate::types::{
+    action::Action,
+    device::{Device, DeviceData, DeviceId, ManageKind},
+    event::{Message, TxEventChannel},
+    integration::{Integration, IntegrationActionPayload, IntegrationId},
+    websockets::{WebSocketAuth, WebSocketRequest, WebSocketResponse},
+};
+use async_trait::async_trait;
+use color_eyre::Result;
+use eyre::{eyre, Context};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        RwLock,
+    },
+    task::JoinHandle,
+};
+use tokio_tungstenite::{connect_async, tungstenite};
+
+/// Separates the remote integration id from the remote device id in ids of
+/// mirrored devices
+const REMOTE_ID_SEPARATOR: char = ':';
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct HomectlConfig {
+    /// WebSocket endpoint of the remote instance, e.g.
+    /// `ws://outbuilding:45289/ws`
+    url: String,
+
+    /// Token for authenticating with the remote instance, if it requires one
+    token: Option<String>,
+
+    /// Seconds to wait before reconnecting, defaults to 5
+    reconnect_secs: Option<u64>,
+}
+
+/// Latest state of remote devices, by id of the mirrored device
+type RemoteDevices = Arc<RwLock<HashMap<DeviceId, Device>>>;
+
+pub struct Homectl {
+    id: IntegrationId,
+    config: HomectlConfig,
+    event_tx: TxEventChannel,
+    remote_devices: RemoteDevices,
+    request_tx: Option<UnboundedSender<WebSocketRequest>>,
+    connection_handle: Option<JoinHandle<()>>,
+}
+
+#[async_trait]
+impl Integration for Homectl {
+    fn new(id: &IntegrationId, config: &config::Value, event_tx: TxEventChannel) -> Result<Self> {
+        let config = config
+            .clone()
+            .try_deserialize()
+            .wrap_err("Failed to deserialize config of Homectl integration")?;
+
+        Ok(Homectl {
+            id: id.clone(),
+            config,
+            event_tx,
+            remote_devices: Default::default(),
+            request_tx: None,
+            connection_handle: None,
+        })
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        let (request_tx, request_rx) = unbounded_channel();
+        self.request_tx = Some(request_tx);
+
+        let connection = Connection {
+            id: self.id.clone(),
+            url: self.config.url.clone(),
+            token: self.config.token.clone(),
+            reconnect: Duration::from_secs(self.config.reconnect_secs.unwrap_or(5)),
+            event_tx: self.event_tx.clone(),
+            remote_devices: self.remote_devices.clone(),
+        };
+
+        self.connection_handle = Some(tokio::spawn(connection.run(request_rx)));
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        self.request_tx = None;
+
+        if let Some(connection_handle) = self.connection_handle.take() {
+            connection_handle.abort();
+        }
+
+        Ok(())
+    }
+
+    async fn set_integration_device_state(&mut self, device: &Device) -> Result<()> {
+        let request_tx = self
+            .request_tx
+            .as_ref()
+            .expect("Expected self.request_tx to be set in start phase");
+
+        let remote_device = {
+            let remote_devices = self.remote_devices.read().await;
+            remote_devices
+                .get(&device.id)
+                .cloned()
+                .ok_or_else(|| eyre!("Remote device {} not found", device.id))?
+        };
+
+        let Some(state) = device.get_controllable_state() else {
+            return Ok(());
+        };
+
+        let remote_device = remote_device.set_controllable_state(state.clone());
+
+        request_tx
+            .send(WebSocketRequest::Message(Message::Action(
+                Action::SetDeviceState(remote_device),
+            )))
+            .map_err(|_| eyre!("Connection to remote homectl instance has been closed"))?;
+
+        Ok(())
+    }
+
+    async fn run_integration_action(&mut self, _: &IntegrationActionPayload) -> Result<()> {
+        // do nothing
+        Ok(())
+    }
+}
+
+struct Connection {
+    id: IntegrationId,
+    url: String,
+    token: Option<String>,
+    reconnect: Duration,
+    event_tx: TxEventChannel,
+    remote_devices: RemoteDevices,
+}
+
+impl Connection {
+    async fn run(self, mut request_rx: UnboundedReceiver<WebSocketRequest>) {
+        loop {
+            match self.connect(&mut request_rx).await {
+                Ok(()) => {
+                    warn!(integration_id = %self.id, "Connection to {} closed", self.url);
+                }
+                Err(e) => {
+                    warn!(integration_id = %self.id, "Connection to {} failed: {:?}", self.url, e);
+                }
+            }
+
+            tokio::time::sleep(self.reconnect).await;
+        }
+    }
+
+    async fn connect(&self, request_rx: &mut UnboundedReceiver<WebSocketRequest>) -> Result<()> {
+        let (ws, _) = connect_async(&self.url).await?;
+        let (mut ws_tx, mut ws_rx) = ws.split();
+
+        let auth = WebSocketRequest::Auth(WebSocketAuth::homectl(self.token.clone()));
+        ws_tx
+            .send(tungstenite::Message::Text(serde_json::to_string(&auth)?))
+            .await?;
+
+        info!(integration_id = %self.id, "Connected to {}", self.url);
+
+        loop {
+            tokio::select! {
+                msg = ws_rx.next() => {
+                    let Some(msg) = msg else {
+                        return Ok(());
+                    };
+
+                    if let tungstenite::Message::Text(json) = msg? {
+                        match serde_json::from_str::<WebSocketResponse>(&json) {
+                            Ok(WebSocketResponse::State(state)) => {
+                                self.mirror_devices(state.devices.0.into_values()).await;
+                            }
+                            Ok(WebSocketResponse::SceneProgress(_))
+                            | Ok(WebSocketResponse::CommandResult(_)) => {}
+                            Err(e) => {
+                                warn!(integration_id = %self.id, "Error while deserializing state: {}", e);
+                            }
+                        }
+                    }
+                }
+                request = request_rx.recv() => {
+                    let Some(request) = request else {
+                        return Ok(());
+                    };
+
+                    let json = serde_json::to_string(&request)?;
+                    ws_tx.send(tungstenite::Message::Text(json)).await?;
+                }
+            }
+        }
+    }
+
+    async fn mirror_devices(&self, remote: impl Iterator<Item = Device>) {
+        let mut remote_devices = self.remote_devices.write().await;
+
+        for remote_device in remote {
+            let id = DeviceId::new(&format!(
+                "{}{}{}",
+                remote_device.integration_id, REMOTE_ID_SEPARATOR, remote_device.id
+            ));
+
+            // The remote instance manages its own devices, and its scenes
+            // don't exist here
+            let mut data = remote_device.data.clone();
+            if let DeviceData::Controllable(controllable) = &mut data {
+                controllable.scene = None;
+                controllable.managed = ManageKind::Unmanaged;
+            }
+
+            let device = Device::new(
+                self.id.clone(),
+                id.clone(),
+                remote_device.name.clone(),
+                data,
+            );
+
+            remote_devices.insert(id, remote_device);
+            self.event_tx.send(Message::RecvDeviceState { device });
+        }
+    }
+}
@@ -1,6 +1,18 @@
+pub mod androidtv;
+pub mod ble;
 pub mod circadian;
 pub mod cron;
+pub mod doorbird;
 pub mod dummy;
+pub mod homectl;
 pub mod mqtt;
+pub mod niko;
+pub mod nut;
+pub mod ocpp;
+pub mod ping;
 pub mod random;
+pub mod snmp;
 pub mod timer;
+pub mod velbus;
+pub mod victron;
+pub mod zigbee2mqtt;
@@ -0,0 +1,103 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::types::integration::{CapturedMessage, CapturedTraffic, TrafficDirection};
+
+/// Keeps the latest raw payloads exchanged with each device in ring buffers
+pub struct TrafficCapture {
+    size: usize,
+    buffers: HashMap<String, VecDeque<CapturedMessage>>,
+}
+
+impl TrafficCapture {
+    pub fn new(size: usize) -> Self {
+        TrafficCapture {
+            size,
+            buffers: Default::default(),
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        device_id: String,
+        direction: TrafficDirection,
+        topic: &str,
+        payload: &[u8],
+        error: Option<String>,
+    ) {
+        let buffer = self.buffers.entry(device_id).or_default();
+
+        buffer.push_back(CapturedMessage {
+            direction,
+            at: chrono::Utc::now(),
+            topic: topic.to_string(),
+            payload: String::from_utf8_lossy(payload).to_string(),
+            error,
+        });
+
+        while buffer.len() > self.size {
+            buffer.pop_front();
+        }
+    }
+
+    pub fn get(&self) -> CapturedTraffic {
+        self.buffers
+            .iter()
+            .map(|(device_id, buffer)| (device_id.clone(), buffer.iter().cloned().collect()))
+            .collect()
+    }
+}
+
+/// Extracts the device id from a topic matching `pattern`, where `{id}` stands
+/// for the device id
+pub fn device_id_from_topic(pattern: &str, topic: &str) -> Option<String> {
+    let (prefix, suffix) = pattern.split_once("{id}")?;
+    let device_id = topic.strip_prefix(prefix)?.strip_suffix(suffix)?;
+
+    (!device_id.is_empty()).then(|| device_id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_id_from_topic() {
+        assert_eq!(
+            device_id_from_topic("zigbee2mqtt/{id}/set", "zigbee2mqtt/lamp/set"),
+            Some("lamp".to_string())
+        );
+        assert_eq!(
+            device_id_from_topic("zigbee2mqtt/{id}", "zigbee2mqtt/lamp"),
+            Some("lamp".to_string())
+        );
+        assert_eq!(
+            device_id_from_topic("zigbee2mqtt/{id}/set", "other/lamp/set"),
+            None
+        );
+        assert_eq!(
+            device_id_from_topic("zigbee2mqtt/lamp", "zigbee2mqtt/lamp"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let mut capture = TrafficCapture::new(2);
+
+        for payload in ["1", "2", "3"] {
+            capture.record(
+                "lamp".to_string(),
+                TrafficDirection::Inbound,
+                "lamp",
+                payload.as_bytes(),
+                None,
+            );
+        }
+
+        let payloads: Vec<_> = capture.get()["lamp"]
+            .iter()
+            .map(|msg| msg.payload.clone())
+            .collect();
+        assert_eq!(payloads, vec!["2", "3"]);
+    }
+}
@@ -1,38 +1,57 @@
 #![allow(clippy::redundant_closure_call)]
 
+mod capture;
 mod utils;
 
+use crate::core::schema::JsonSchema;
 use crate::types::{
     device::{Device, ManageKind},
     event::{Message, TxEventChannel},
-    integration::{Integration, IntegrationActionPayload, IntegrationId},
+    integration::{
+        CapturedTraffic, Integration, IntegrationActionPayload, IntegrationId, TrafficDirection,
+    },
 };
 use async_trait::async_trait;
 use color_eyre::Result;
 use eyre::Context;
 use rand::{distributions::Alphanumeric, Rng};
-use rumqttc::{AsyncClient, MqttOptions, QoS};
+use rumqttc::{AsyncClient, LastWill, MqttOptions, QoS};
 use serde::Deserialize;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::task;
+use tokio::task::{self, JoinHandle};
+use tracing::Instrument;
 
 use crate::integrations::mqtt::utils::mqtt_to_homectl;
 
+use self::capture::{device_id_from_topic, TrafficCapture};
 use self::utils::homectl_to_mqtt;
 
-#[derive(Default, Debug, Deserialize, Clone)]
+#[derive(Default, Debug, Deserialize, JsonSchema, Clone)]
 pub struct MqttConfig {
     host: String,
     port: u16,
     topic: String,
     topic_set: String,
 
+    /// If set, homectl publishes an empty JSON object to this topic when it
+    /// wants a device to report its state, e.g. `zigbee2mqtt/{id}/get`.
+    topic_get: Option<String>,
+
     /// Can be used to control whether the devices published by this integration
     /// are "managed" or not, i.e.  whether homectl should keep track of the
     /// devices' expected states or not.
     managed: Option<ManageKind>,
 
+    /// If set, homectl publishes "online" to this topic when connected, and
+    /// "offline" when shutting down or when the connection is lost.
+    availability_topic: Option<String>,
+
+    /// If set, the latest `capture_size` raw payloads sent to and received
+    /// from each device are kept for diagnosing mapping problems, see
+    /// `GET /api/v1/integrations/{integration_id}/traffic`.
+    capture_size: Option<usize>,
+
     id_field: Option<jsonptr::Pointer>,
     name_field: Option<jsonptr::Pointer>,
     color_field: Option<jsonptr::Pointer>,
@@ -48,6 +67,8 @@ pub struct Mqtt {
     event_tx: TxEventChannel,
     config: MqttConfig,
     client: Option<AsyncClient>,
+    eventloop_handle: Option<JoinHandle<()>>,
+    capture: Option<Arc<Mutex<TrafficCapture>>>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -59,16 +80,22 @@ pub struct CustomMqttAction {
 #[async_trait]
 impl Integration for Mqtt {
     fn new(id: &IntegrationId, config: &config::Value, event_tx: TxEventChannel) -> Result<Self> {
-        let config = config
+        let config: MqttConfig = config
             .clone()
             .try_deserialize()
             .wrap_err("Failed to deserialize config of Mqtt integration")?;
 
+        let capture = config
+            .capture_size
+            .map(|size| Arc::new(Mutex::new(TrafficCapture::new(size))));
+
         Ok(Mqtt {
             id: id.clone(),
             config,
             event_tx,
             client: None,
+            eventloop_handle: None,
+            capture,
         })
     }
 
@@ -85,6 +112,14 @@ impl Integration for Mqtt {
             self.config.port,
         );
         options.set_keep_alive(Duration::from_secs(5));
+        if let Some(availability_topic) = &self.config.availability_topic {
+            options.set_last_will(LastWill::new(
+                availability_topic,
+                "offline",
+                QoS::AtLeastOnce,
+                true,
+            ));
+        }
         let (client, mut eventloop) = AsyncClient::new(options, 10);
 
         self.client = Some(client.clone());
@@ -92,44 +127,112 @@ impl Integration for Mqtt {
         let id = self.id.clone();
         let event_tx = self.event_tx.clone();
         let config = Arc::new(self.config.clone());
+        let capture = self.capture.clone();
 
-        task::spawn(async move {
-            loop {
-                let notification = eventloop.poll().await;
+        // Logs of the event loop are tagged with the id of this instance, so
+        // that a log filter can target a single MQTT integration
+        let span = info_span!("integration", integration_id = %id);
 
-                let id = id.clone();
-                let event_tx = event_tx.clone();
-                let config = Arc::clone(&config);
+        let eventloop_handle = task::spawn(
+            async move {
+                loop {
+                    let notification = eventloop.poll().await;
 
-                let res = (|| async {
-                    match notification? {
-                        rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_)) => {
-                            client
-                                .subscribe(config.topic.replace("{id}", "+"), QoS::AtMostOnce)
-                                .await?;
-                        }
+                    // Graceful disconnect was requested in stop()
+                    if let Ok(rumqttc::Event::Outgoing(rumqttc::Outgoing::Disconnect)) =
+                        notification
+                    {
+                        break;
+                    }
+
+                    let id = id.clone();
+                    let event_tx = event_tx.clone();
+                    let config = Arc::clone(&config);
+                    let capture = capture.clone();
+
+                    let res = (|| async {
+                        match notification? {
+                            rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_)) => {
+                                client
+                                    .subscribe(config.topic.replace("{id}", "+"), QoS::AtMostOnce)
+                                    .await?;
+
+                                if let Some(availability_topic) = &config.availability_topic {
+                                    client
+                                        .publish(
+                                            availability_topic,
+                                            QoS::AtLeastOnce,
+                                            true,
+                                            "online",
+                                        )
+                                        .await?;
+                                }
+                            }
+
+                            rumqttc::Event::Incoming(rumqttc::Packet::Publish(msg)) => {
+                                let device = mqtt_to_homectl(&msg.payload, id.clone(), &config);
+
+                                if let Some(capture) = &capture {
+                                    let device_id = match &device {
+                                        Ok(device) => Some(device.id.to_string()),
+                                        Err(_) => device_id_from_topic(&config.topic, &msg.topic),
+                                    };
 
-                        rumqttc::Event::Incoming(rumqttc::Packet::Publish(msg)) => {
-                            let device = mqtt_to_homectl(&msg.payload, id.clone(), &config)?;
-                            let msg = Message::RecvDeviceState { device };
-                            event_tx.send(msg);
+                                    capture.lock().unwrap().record(
+                                        device_id.unwrap_or_else(|| msg.topic.clone()),
+                                        TrafficDirection::Inbound,
+                                        &msg.topic,
+                                        &msg.payload,
+                                        device.as_ref().err().map(|e| e.to_string()),
+                                    );
+                                }
+
+                                let msg = Message::RecvDeviceState { device: device? };
+                                event_tx.send(msg);
+                            }
+                            _ => {}
                         }
-                        _ => {}
-                    }
 
-                    Ok::<(), Box<dyn std::error::Error + Sync + Send>>(())
-                })()
-                .await;
+                        Ok::<(), Box<dyn std::error::Error + Sync + Send>>(())
+                    })()
+                    .await;
 
-                if let Err(e) = res {
-                    error!(
-                        target: &format!("homectl_server::integrations::mqtt::{}", id),
-                        "MQTT error: {:?}", e
-                    );
-                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    if let Err(e) = res {
+                        error!("MQTT error: {:?}", e);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
                 }
             }
-        });
+            .instrument(span),
+        );
+
+        self.eventloop_handle = Some(eventloop_handle);
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        let Some(client) = self.client.take() else {
+            return Ok(());
+        };
+
+        if let Some(availability_topic) = &self.config.availability_topic {
+            client
+                .publish(availability_topic, QoS::AtLeastOnce, true, "offline")
+                .await?;
+        }
+
+        client
+            .unsubscribe(self.config.topic.replace("{id}", "+"))
+            .await?;
+        client.disconnect().await?;
+
+        // Give the event loop a chance to flush queued packets to the broker
+        if let Some(eventloop_handle) = self.eventloop_handle.take() {
+            tokio::time::timeout(Duration::from_secs(2), eventloop_handle)
+                .await
+                .ok();
+        }
 
         Ok(())
     }
@@ -147,12 +250,30 @@ impl Integration for Mqtt {
 
         let mqtt_device = homectl_to_mqtt(device.clone(), &self.config)?;
         let json = serde_json::to_string(&mqtt_device)?;
+        self.capture_outbound(device.id.to_string(), &topic, &json);
 
         client.publish(topic, QoS::AtLeastOnce, true, json).await?;
 
         Ok(())
     }
 
+    async fn poll_device(&mut self, device: &Device) -> Result<bool> {
+        let Some(topic_get) = &self.config.topic_get else {
+            return Ok(false);
+        };
+
+        let client = self
+            .client
+            .as_ref()
+            .expect("Expected self.client to be set in start phase");
+
+        let topic = topic_get.replace("{id}", &device.id.to_string());
+        self.capture_outbound(device.id.to_string(), &topic, "{}");
+        client.publish(topic, QoS::AtLeastOnce, false, "{}").await?;
+
+        Ok(true)
+    }
+
     /// Can be used for pushing arbitrary values to the MQTT broker
     async fn run_integration_action(&mut self, payload: &IntegrationActionPayload) -> Result<()> {
         let action: CustomMqttAction = serde_json::from_str(&payload.to_string())?;
@@ -162,10 +283,35 @@ impl Integration for Mqtt {
             .as_ref()
             .expect("Expected self.client to be set in start phase");
 
+        let device_id = device_id_from_topic(&self.config.topic_set, &action.topic)
+            .unwrap_or_else(|| action.topic.clone());
+        self.capture_outbound(device_id, &action.topic, &action.json);
+
         client
             .publish(action.topic, QoS::AtLeastOnce, true, action.json)
             .await?;
 
         Ok(())
     }
+
+    async fn get_captured_traffic(&mut self) -> Option<CapturedTraffic> {
+        let capture = self.capture.as_ref()?;
+        let traffic = capture.lock().unwrap().get();
+
+        Some(traffic)
+    }
+}
+
+impl Mqtt {
+    fn capture_outbound(&self, device_id: String, topic: &str, payload: &str) {
+        if let Some(capture) = &self.capture {
+            capture.lock().unwrap().record(
+                device_id,
+                TrafficDirection::Outbound,
+                topic,
+                payload.as_bytes(),
+                None,
+            );
+        }
+    }
 }
@@ -7,6 +7,7 @@ use crate::types::{
 use color_eyre::Result;
 use eyre::eyre;
 use jsonptr::Assign;
+use ordered_float::OrderedFloat;
 
 pub fn mqtt_to_homectl(
     payload: &[u8],
@@ -75,6 +76,15 @@ pub fn mqtt_to_homectl(
             .parse::<bool>()
         {
             DeviceData::Sensor(SensorDevice::Boolean { value })
+        } else if let Some(value) = value
+            .pointer(sensor_value_field)
+            .and_then(serde_json::Value::as_f64)
+        {
+            DeviceData::Sensor(SensorDevice::Number {
+                value: OrderedFloat(value),
+                unit: None,
+                raw: None,
+            })
         } else {
             DeviceData::Sensor(SensorDevice::Text {
                 value: value
@@ -0,0 +1,475 @@
+//! Niko Home Control (NHC I) integration, talking to the connected controller
+//! over its local JSON API. Switches and dimmers become controllable devices,
+//! motors become position sensors controlled through integration actions.
+
+use crate::core::schema::JsonSchema;
+use crate::types::{
+    color::Capabilities,
+    device::{ControllableDevice, Device, DeviceData, DeviceId, ManageKind, SensorDevice},
+    event::{Message, TxEventChannel},
+    integration::{Integration, IntegrationActionPayload, IntegrationId},
+};
+use async_trait::async_trait;
+use color_eyre::Result;
+use eyre::{eyre, Context};
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        RwLock,
+    },
+    task::JoinHandle,
+};
+
+const DEFAULT_PORT: u16 = 8000;
+
+/// Action types of the NHC I API
+const TYPE_SWITCH: u8 = 1;
+const TYPE_DIMMER: u8 = 2;
+const TYPE_MOTOR: u8 = 4;
+
+/// Values of motor actions that move the motor rather than position it
+const MOTOR_STOP: u8 = 253;
+const MOTOR_CLOSE: u8 = 254;
+const MOTOR_OPEN: u8 = 255;
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct NikoConfig {
+    /// Address of the connected controller
+    host: String,
+
+    /// Port of the local API, defaults to 8000
+    port: Option<u16>,
+
+    /// Seconds to wait before reconnecting, defaults to 5
+    reconnect_secs: Option<u64>,
+
+    /// How lights are managed, defaults to partially managed as they're
+    /// usually also switched from wall buttons
+    managed: Option<ManageKind>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct NikoAction {
+    id: u32,
+    name: String,
+    #[serde(rename = "type")]
+    kind: u8,
+    value1: u8,
+}
+
+#[derive(Debug, Deserialize)]
+struct NikoActionValue {
+    id: u32,
+    value1: u8,
+}
+
+/// Responses to commands have `cmd` set, pushed events have `event` set
+#[derive(Debug, Deserialize)]
+struct NikoMessage {
+    cmd: Option<String>,
+    event: Option<String>,
+    #[serde(default)]
+    data: serde_json::Value,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "cmd", rename_all = "lowercase")]
+enum NikoCommand {
+    ListActions,
+    StartEvents,
+    ExecuteActions { id: u32, value1: u8 },
+}
+
+/// Payload of integration actions, e.g. `{ "cover": "Kitchen blinds",
+/// "position": 30 }` or `{ "cover": "Kitchen blinds", "command": "stop" }`
+#[derive(Debug, Deserialize)]
+struct NikoCoverPayload {
+    /// Name or id of the motor action
+    cover: String,
+
+    /// Position in percent, also accepted as a string so that it can be
+    /// filled in by `{position}` of covers
+    position: Option<serde_json::Value>,
+
+    command: Option<NikoCoverCommand>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum NikoCoverCommand {
+    Open,
+    Close,
+    Stop,
+}
+
+/// Latest known actions of the controller, by id
+type NikoActions = Arc<RwLock<HashMap<u32, NikoAction>>>;
+
+pub struct Niko {
+    id: IntegrationId,
+    config: NikoConfig,
+    event_tx: TxEventChannel,
+    actions: NikoActions,
+    command_tx: Option<UnboundedSender<NikoCommand>>,
+    connection_handle: Option<JoinHandle<()>>,
+}
+
+#[async_trait]
+impl Integration for Niko {
+    fn new(id: &IntegrationId, config: &config::Value, event_tx: TxEventChannel) -> Result<Self> {
+        let config = config
+            .clone()
+            .try_deserialize()
+            .wrap_err("Failed to deserialize config of Niko integration")?;
+
+        Ok(Niko {
+            id: id.clone(),
+            config,
+            event_tx,
+            actions: Default::default(),
+            command_tx: None,
+            connection_handle: None,
+        })
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        let (command_tx, command_rx) = unbounded_channel();
+        self.command_tx = Some(command_tx);
+
+        let connection = Connection {
+            id: self.id.clone(),
+            address: format!(
+                "{}:{}",
+                self.config.host,
+                self.config.port.unwrap_or(DEFAULT_PORT)
+            ),
+            reconnect: Duration::from_secs(self.config.reconnect_secs.unwrap_or(5)),
+            managed: self.config.managed.clone().unwrap_or(ManageKind::Partial {
+                prev_change_committed: false,
+            }),
+            event_tx: self.event_tx.clone(),
+            actions: self.actions.clone(),
+        };
+
+        self.connection_handle = Some(tokio::spawn(connection.run(command_rx)));
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        self.command_tx = None;
+
+        if let Some(connection_handle) = self.connection_handle.take() {
+            connection_handle.abort();
+        }
+
+        Ok(())
+    }
+
+    async fn set_integration_device_state(&mut self, device: &Device) -> Result<()> {
+        let Some(state) = device.get_controllable_state() else {
+            return Ok(());
+        };
+
+        let id: u32 = device
+            .id
+            .to_string()
+            .parse()
+            .map_err(|_| eyre!("Invalid Niko action id {}", device.id))?;
+
+        let value1 = match (state.power, state.brightness) {
+            (false, _) => 0,
+            (true, Some(brightness)) => (brightness.0 * 100.0).round().clamp(1.0, 100.0) as u8,
+            (true, None) => 100,
+        };
+
+        self.send(NikoCommand::ExecuteActions { id, value1 })
+    }
+
+    async fn run_integration_action(&mut self, payload: &IntegrationActionPayload) -> Result<()> {
+        let payload: NikoCoverPayload = serde_json::from_str(&payload.to_string())
+            .wrap_err("Failed to deserialize Niko integration action payload")?;
+
+        let id = {
+            let actions = self.actions.read().await;
+            actions
+                .values()
+                .find(|action| {
+                    action.kind == TYPE_MOTOR
+                        && (action.name == payload.cover || action.id.to_string() == payload.cover)
+                })
+                .map(|action| action.id)
+                .ok_or_else(|| eyre!("Niko motor {} not found", payload.cover))?
+        };
+
+        let position = payload.position.as_ref().and_then(|position| {
+            position
+                .as_u64()
+                .or_else(|| position.as_str()?.parse().ok())
+        });
+
+        let value1 = match (payload.command, position) {
+            (Some(NikoCoverCommand::Open), _) | (None, Some(100..)) => MOTOR_OPEN,
+            (Some(NikoCoverCommand::Close), _) | (None, Some(0)) => MOTOR_CLOSE,
+            (Some(NikoCoverCommand::Stop), _) => MOTOR_STOP,
+            (None, Some(position)) => position as u8,
+            (None, None) => return Err(eyre!("Expected either position or command")),
+        };
+
+        self.send(NikoCommand::ExecuteActions { id, value1 })
+    }
+
+    async fn poll_device(&mut self, _device: &Device) -> Result<bool> {
+        // The controller only lists all actions at once
+        self.send(NikoCommand::ListActions)?;
+
+        Ok(true)
+    }
+}
+
+impl Niko {
+    fn send(&self, command: NikoCommand) -> Result<()> {
+        self.command_tx
+            .as_ref()
+            .expect("Expected self.command_tx to be set in start phase")
+            .send(command)
+            .map_err(|_| eyre!("Connection to Niko controller has been closed"))
+    }
+}
+
+fn mk_device_data(action: &NikoAction, managed: &ManageKind) -> Option<DeviceData> {
+    let power = action.value1 > 0;
+
+    let data = match action.kind {
+        TYPE_SWITCH => DeviceData::Controllable(ControllableDevice::new(
+            None,
+            power,
+            None,
+            None,
+            None,
+            Capabilities::default(),
+            managed.clone(),
+        )),
+        TYPE_DIMMER => DeviceData::Controllable(ControllableDevice::new(
+            None,
+            power,
+            Some(action.value1 as f32 / 100.0),
+            None,
+            None,
+            Capabilities::default(),
+            managed.clone(),
+        )),
+        TYPE_MOTOR => DeviceData::Sensor(SensorDevice::Number {
+            value: OrderedFloat(action.value1 as f64),
+            unit: Some("%".to_string()),
+            raw: None,
+        }),
+        _ => return None,
+    };
+
+    Some(data)
+}
+
+/// Takes complete JSON messages from the start of `buf`, leaving any
+/// partially received message in place. The controller doesn't reliably
+/// delimit its messages, so they're split by parsing.
+fn take_messages(buf: &mut Vec<u8>) -> Vec<NikoMessage> {
+    let mut stream = serde_json::Deserializer::from_slice(buf).into_iter::<NikoMessage>();
+    let mut messages = vec![];
+
+    let consumed = loop {
+        let offset = stream.byte_offset();
+
+        match stream.next() {
+            Some(Ok(msg)) => messages.push(msg),
+            None => break offset,
+            Some(Err(e)) if e.is_eof() => break offset,
+            Some(Err(e)) => {
+                warn!("Discarding malformed message from Niko controller: {}", e);
+                break buf.len();
+            }
+        }
+    };
+
+    buf.drain(..consumed);
+    messages
+}
+
+struct Connection {
+    id: IntegrationId,
+    address: String,
+    reconnect: Duration,
+    managed: ManageKind,
+    event_tx: TxEventChannel,
+    actions: NikoActions,
+}
+
+impl Connection {
+    async fn run(self, mut command_rx: UnboundedReceiver<NikoCommand>) {
+        loop {
+            match self.connect(&mut command_rx).await {
+                Ok(()) => {
+                    warn!(integration_id = %self.id, "Connection to {} closed", self.address);
+                }
+                Err(e) => {
+                    warn!(integration_id = %self.id, "Connection to {} failed: {:?}", self.address, e);
+                }
+            }
+
+            tokio::time::sleep(self.reconnect).await;
+        }
+    }
+
+    async fn connect(&self, command_rx: &mut UnboundedReceiver<NikoCommand>) -> Result<()> {
+        let mut stream = TcpStream::connect(&self.address).await?;
+
+        for command in [NikoCommand::ListActions, NikoCommand::StartEvents] {
+            stream
+                .write_all(serde_json::to_string(&command)?.as_bytes())
+                .await?;
+        }
+
+        info!(integration_id = %self.id, "Connected to {}", self.address);
+
+        let mut buf = vec![];
+        let mut chunk = [0; 4096];
+
+        loop {
+            tokio::select! {
+                n = stream.read(&mut chunk) => {
+                    let n = n?;
+                    if n == 0 {
+                        return Ok(());
+                    }
+
+                    buf.extend_from_slice(&chunk[..n]);
+
+                    for msg in take_messages(&mut buf) {
+                        self.handle_message(msg).await;
+                    }
+                }
+                command = command_rx.recv() => {
+                    let Some(command) = command else {
+                        return Ok(());
+                    };
+
+                    stream
+                        .write_all(serde_json::to_string(&command)?.as_bytes())
+                        .await?;
+                }
+            }
+        }
+    }
+
+    async fn handle_message(&self, msg: NikoMessage) {
+        let mut actions = self.actions.write().await;
+
+        let changed: Vec<u32> = match (msg.cmd.as_deref(), msg.event.as_deref()) {
+            (Some("listactions"), _) => match serde_json::from_value::<Vec<NikoAction>>(msg.data) {
+                Ok(listed) => listed
+                    .into_iter()
+                    .map(|action| {
+                        let id = action.id;
+                        actions.insert(id, action);
+                        id
+                    })
+                    .collect(),
+                Err(e) => {
+                    warn!(integration_id = %self.id, "Error while deserializing actions: {}", e);
+                    return;
+                }
+            },
+            (_, Some("listactions")) => {
+                match serde_json::from_value::<Vec<NikoActionValue>>(msg.data) {
+                    Ok(values) => values
+                        .into_iter()
+                        .filter_map(|value| {
+                            let action = actions.get_mut(&value.id)?;
+                            action.value1 = value.value1;
+                            Some(value.id)
+                        })
+                        .collect(),
+                    Err(e) => {
+                        warn!(integration_id = %self.id, "Error while deserializing event: {}", e);
+                        return;
+                    }
+                }
+            }
+            (Some(cmd), _) => {
+                if let Some(error) = msg.data.get("error").and_then(|e| e.as_u64()) {
+                    if error != 0 {
+                        warn!(integration_id = %self.id, "Command {} failed with error {}", cmd, error);
+                    }
+                }
+                return;
+            }
+            _ => return,
+        };
+
+        for id in changed {
+            let Some(action) = actions.get(&id) else {
+                continue;
+            };
+            let Some(data) = mk_device_data(action, &self.managed) else {
+                continue;
+            };
+
+            let device = Device::new(
+                self.id.clone(),
+                DeviceId::new(&id.to_string()),
+                action.name.clone(),
+                data,
+            );
+
+            self.event_tx.send(Message::RecvDeviceState { device });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_messages() {
+        let mut buf = br#"{"cmd":"startevents","data":{"error":0}}{"event":"listactions","data":[{"id":3,"value1":40}]}{"event":"list"#.to_vec();
+
+        let messages = take_messages(&mut buf);
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].cmd.as_deref(), Some("startevents"));
+        assert_eq!(messages[1].event.as_deref(), Some("listactions"));
+        assert_eq!(buf, br#"{"event":"list"#);
+
+        assert_eq!(
+            serde_json::to_string(&NikoCommand::ExecuteActions { id: 3, value1: 40 }).unwrap(),
+            r#"{"cmd":"executeactions","id":3,"value1":40}"#
+        );
+    }
+
+    #[test]
+    fn test_mk_device_data() {
+        let dimmer = NikoAction {
+            id: 3,
+            name: "Kitchen".to_string(),
+            kind: TYPE_DIMMER,
+            value1: 40,
+        };
+
+        let Some(DeviceData::Controllable(controllable)) =
+            mk_device_data(&dimmer, &ManageKind::Full)
+        else {
+            panic!("Expected dimmer to be controllable");
+        };
+        assert!(controllable.state.power);
+        assert_eq!(controllable.state.brightness, Some(OrderedFloat(0.4)));
+
+        let scene = NikoAction { kind: 0, ..dimmer };
+        assert!(mk_device_data(&scene, &ManageKind::Full).is_none());
+    }
+}
@@ -0,0 +1,289 @@
+//! Network UPS Tools integration, polling the variables of a UPS from a NUT
+//! server (upsd) and reporting battery charge, load and power status as
+//! sensors.
+
+use crate::core::schema::JsonSchema;
+use crate::types::{
+    device::{Device, DeviceData, DeviceId, SensorDevice},
+    event::{Message, TxEventChannel},
+    integration::{Integration, IntegrationId},
+};
+use async_trait::async_trait;
+use color_eyre::Result;
+use eyre::{eyre, Context};
+use ordered_float::OrderedFloat;
+use serde::Deserialize;
+use std::{collections::BTreeMap, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    task::JoinHandle,
+};
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct NutConfig {
+    /// Address of the NUT server, e.g. `nas.lan`
+    host: String,
+
+    /// Port of the NUT server, defaults to 3493
+    port: Option<u16>,
+
+    /// Name of the UPS as configured in ups.conf, defaults to "ups"
+    ups: Option<String>,
+
+    /// Prefix of sensor names, defaults to "UPS"
+    name: Option<String>,
+
+    /// Seconds between polling the UPS, defaults to 30
+    poll_secs: Option<u64>,
+
+    /// Seconds to wait before reconnecting, defaults to 5
+    reconnect_secs: Option<u64>,
+}
+
+/// Parses a `VAR <ups> <name> "<value>"` line of a `LIST VAR` response
+fn parse_var(line: &str) -> Option<(String, String)> {
+    let rest = line.strip_prefix("VAR ")?;
+    let (_ups, rest) = rest.split_once(' ')?;
+    let (name, value) = rest.split_once(' ')?;
+    let value = value.strip_prefix('"')?.strip_suffix('"')?;
+
+    // Quotes and backslashes within values are escaped with a backslash
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+
+    Some((name.to_string(), unescaped))
+}
+
+#[derive(Debug, PartialEq)]
+struct UpsStatus {
+    battery_charge: Option<f64>,
+    battery_runtime: Option<f64>,
+    load: Option<f64>,
+    on_battery: bool,
+    low_battery: bool,
+}
+
+impl UpsStatus {
+    fn from_vars(vars: &BTreeMap<String, String>) -> UpsStatus {
+        let number = |name: &str| vars.get(name).and_then(|value| value.parse().ok());
+
+        // e.g. "OL CHRG", "OB DISCHRG" or "OB LB"
+        let flags: Vec<&str> = vars
+            .get("ups.status")
+            .map(|status| status.split_whitespace().collect())
+            .unwrap_or_default();
+
+        UpsStatus {
+            battery_charge: number("battery.charge"),
+            battery_runtime: number("battery.runtime"),
+            load: number("ups.load"),
+            on_battery: flags.contains(&"OB"),
+            low_battery: flags.contains(&"LB"),
+        }
+    }
+}
+
+pub struct Nut {
+    id: IntegrationId,
+    config: NutConfig,
+    event_tx: TxEventChannel,
+    connection_handle: Option<JoinHandle<()>>,
+}
+
+#[async_trait]
+impl Integration for Nut {
+    fn new(id: &IntegrationId, config: &config::Value, event_tx: TxEventChannel) -> Result<Self> {
+        let config = config
+            .clone()
+            .try_deserialize()
+            .wrap_err("Failed to deserialize config of Nut integration")?;
+
+        Ok(Nut {
+            id: id.clone(),
+            config,
+            event_tx,
+            connection_handle: None,
+        })
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        let connection = Connection {
+            id: self.id.clone(),
+            config: self.config.clone(),
+            event_tx: self.event_tx.clone(),
+        };
+
+        self.connection_handle = Some(tokio::spawn(connection.run()));
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        if let Some(connection_handle) = self.connection_handle.take() {
+            connection_handle.abort();
+        }
+
+        Ok(())
+    }
+}
+
+struct Connection {
+    id: IntegrationId,
+    config: NutConfig,
+    event_tx: TxEventChannel,
+}
+
+impl Connection {
+    fn addr(&self) -> String {
+        format!("{}:{}", self.config.host, self.config.port.unwrap_or(3493))
+    }
+
+    fn ups(&self) -> &str {
+        self.config.ups.as_deref().unwrap_or("ups")
+    }
+
+    async fn run(self) {
+        let reconnect = Duration::from_secs(self.config.reconnect_secs.unwrap_or(5));
+
+        loop {
+            if let Err(e) = self.connect().await {
+                warn!(integration_id = %self.id, "Connection to {} failed: {:?}", self.addr(), e);
+            }
+
+            tokio::time::sleep(reconnect).await;
+        }
+    }
+
+    async fn connect(&self) -> Result<()> {
+        let poll_interval = Duration::from_secs(self.config.poll_secs.unwrap_or(30));
+        let (reader, mut writer) = TcpStream::connect(self.addr()).await?.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        info!(integration_id = %self.id, "Connected to {}", self.addr());
+
+        loop {
+            writer
+                .write_all(format!("LIST VAR {}\n", self.ups()).as_bytes())
+                .await?;
+
+            let mut vars = BTreeMap::new();
+
+            loop {
+                let line = lines
+                    .next_line()
+                    .await?
+                    .ok_or_else(|| eyre!("Connection closed"))?;
+
+                if let Some(err) = line.strip_prefix("ERR ") {
+                    return Err(eyre!("Listing variables of {} failed: {}", self.ups(), err));
+                }
+
+                if line.starts_with("END LIST VAR") {
+                    break;
+                }
+
+                if let Some((name, value)) = parse_var(&line) {
+                    vars.insert(name, value);
+                }
+            }
+
+            self.report(UpsStatus::from_vars(&vars));
+
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+
+    fn report(&self, status: UpsStatus) {
+        let name = self.config.name.as_deref().unwrap_or("UPS");
+
+        let number = |value: f64, unit: &str| SensorDevice::Number {
+            value: OrderedFloat(value),
+            unit: Some(unit.to_string()),
+            raw: None,
+        };
+
+        let sensors = [
+            (
+                "battery_charge",
+                "battery charge",
+                status.battery_charge.map(|value| number(value, "%")),
+            ),
+            (
+                "battery_runtime",
+                "battery runtime",
+                status.battery_runtime.map(|value| number(value, "s")),
+            ),
+            ("load", "load", status.load.map(|value| number(value, "%"))),
+            (
+                "on_battery",
+                "on battery",
+                Some(SensorDevice::Boolean {
+                    value: status.on_battery,
+                }),
+            ),
+            (
+                "low_battery",
+                "low battery",
+                Some(SensorDevice::Boolean {
+                    value: status.low_battery,
+                }),
+            ),
+        ];
+
+        // Not every UPS reports every variable
+        for (device_id, suffix, sensor) in sensors {
+            let Some(sensor) = sensor else {
+                continue;
+            };
+
+            let device = Device::new(
+                self.id.clone(),
+                DeviceId::new(device_id),
+                format!("{} {}", name, suffix),
+                DeviceData::Sensor(sensor),
+            );
+
+            self.event_tx.send(Message::RecvDeviceState { device });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ups_status() {
+        let response = r#"BEGIN LIST VAR ups
+VAR ups battery.charge "87"
+VAR ups battery.runtime "1260"
+VAR ups device.mfr "American \"Power\" Conversion"
+VAR ups ups.load "23"
+VAR ups ups.status "OB DISCHRG"
+END LIST VAR ups"#;
+
+        let vars: BTreeMap<_, _> = response.lines().filter_map(parse_var).collect();
+
+        assert_eq!(
+            vars.get("device.mfr").map(String::as_str),
+            Some(r#"American "Power" Conversion"#)
+        );
+        assert_eq!(
+            UpsStatus::from_vars(&vars),
+            UpsStatus {
+                battery_charge: Some(87.0),
+                battery_runtime: Some(1260.0),
+                load: Some(23.0),
+                on_battery: true,
+                low_battery: false,
+            }
+        );
+    }
+}
@@ -0,0 +1,203 @@
+//! OCPP-J framing and the few OCPP 1.6 message payloads we need to read
+
+use color_eyre::Result;
+use eyre::eyre;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+const MESSAGE_TYPE_CALL: u64 = 2;
+const MESSAGE_TYPE_CALL_RESULT: u64 = 3;
+const MESSAGE_TYPE_CALL_ERROR: u64 = 4;
+
+#[derive(Debug, PartialEq)]
+pub enum Frame {
+    /// `[2, id, action, payload]`
+    Call {
+        id: String,
+        action: String,
+        payload: Value,
+    },
+    /// `[3, id, payload]`
+    CallResult { id: String, payload: Value },
+    /// `[4, id, code, description, details]`
+    CallError {
+        id: String,
+        code: String,
+        description: String,
+    },
+}
+
+impl Frame {
+    pub fn parse(text: &str) -> Result<Frame> {
+        let value: Vec<Value> = serde_json::from_str(text)?;
+        let invalid = || eyre!("Invalid OCPP message {}", text);
+
+        let string = |i: usize| {
+            value
+                .get(i)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(invalid)
+        };
+
+        let frame = match value.first().and_then(Value::as_u64) {
+            Some(MESSAGE_TYPE_CALL) => Frame::Call {
+                id: string(1)?,
+                action: string(2)?,
+                payload: value.get(3).cloned().ok_or_else(invalid)?,
+            },
+            Some(MESSAGE_TYPE_CALL_RESULT) => Frame::CallResult {
+                id: string(1)?,
+                payload: value.get(2).cloned().ok_or_else(invalid)?,
+            },
+            Some(MESSAGE_TYPE_CALL_ERROR) => Frame::CallError {
+                id: string(1)?,
+                code: string(2)?,
+                description: string(3).unwrap_or_default(),
+            },
+            _ => return Err(invalid()),
+        };
+
+        Ok(frame)
+    }
+
+    pub fn to_text(&self) -> String {
+        let value = match self {
+            Frame::Call {
+                id,
+                action,
+                payload,
+            } => json!([MESSAGE_TYPE_CALL, id, action, payload]),
+            Frame::CallResult { id, payload } => json!([MESSAGE_TYPE_CALL_RESULT, id, payload]),
+            Frame::CallError {
+                id,
+                code,
+                description,
+            } => json!([MESSAGE_TYPE_CALL_ERROR, id, code, description, {}]),
+        };
+
+        value.to_string()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusNotification {
+    pub connector_id: u32,
+
+    /// e.g. "Available", "Preparing", "Charging", "SuspendedEV" or "Faulted"
+    pub status: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeterValues {
+    pub connector_id: u32,
+    pub meter_value: Vec<MeterValue>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeterValue {
+    pub sampled_value: Vec<SampledValue>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SampledValue {
+    pub value: String,
+    pub measurand: Option<String>,
+    pub unit: Option<String>,
+    pub phase: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Meter {
+    pub power_w: Option<f64>,
+    pub energy_kwh: Option<f64>,
+}
+
+/// Reads active power and imported energy from meter values. Power that is
+/// only reported per phase is summed up.
+pub fn read_meter(meter_values: &[MeterValue]) -> Meter {
+    let mut meter = Meter::default();
+    let mut phase_power_w: Option<f64> = None;
+
+    let sampled_values = meter_values
+        .iter()
+        .flat_map(|meter_value| &meter_value.sampled_value);
+
+    for sampled in sampled_values {
+        let Ok(value) = sampled.value.parse::<f64>() else {
+            continue;
+        };
+
+        let kilo = matches!(sampled.unit.as_deref(), Some("kW" | "kWh"));
+
+        // Energy.Active.Import.Register is the default measurand
+        match sampled.measurand.as_deref() {
+            Some("Power.Active.Import") => {
+                let power_w = if kilo { value * 1000.0 } else { value };
+
+                match sampled.phase.as_deref() {
+                    None => meter.power_w = Some(power_w),
+                    // Line to neutral values would be counted twice
+                    Some("L1" | "L2" | "L3") => {
+                        *phase_power_w.get_or_insert(0.0) += power_w;
+                    }
+                    Some(_) => {}
+                }
+            }
+            None | Some("Energy.Active.Import.Register") if sampled.phase.is_none() => {
+                meter.energy_kwh = Some(if kilo { value } else { value / 1000.0 });
+            }
+            _ => {}
+        }
+    }
+
+    meter.power_w = meter.power_w.or(phase_power_w);
+    meter
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames() {
+        let text = r#"[2,"19223201","MeterValues",{"connectorId":1,"transactionId":42,"meterValue":[{"timestamp":"2024-05-01T12:00:00Z","sampledValue":[
+            {"value":"12345","unit":"Wh"},
+            {"value":"2.5","measurand":"Power.Active.Import","unit":"kW","phase":"L1"},
+            {"value":"1.5","measurand":"Power.Active.Import","unit":"kW","phase":"L2"},
+            {"value":"10.0","measurand":"Current.Import","unit":"A","phase":"L1"}
+        ]}]}]"#;
+
+        let Frame::Call {
+            id,
+            action,
+            payload,
+        } = Frame::parse(text).unwrap()
+        else {
+            panic!("Expected a call");
+        };
+        assert_eq!(id, "19223201");
+        assert_eq!(action, "MeterValues");
+
+        let meter_values: MeterValues = serde_json::from_value(payload).unwrap();
+        assert_eq!(
+            read_meter(&meter_values.meter_value),
+            Meter {
+                power_w: Some(4000.0),
+                energy_kwh: Some(12.345),
+            }
+        );
+
+        let result = Frame::CallResult {
+            id: "1".to_string(),
+            payload: json!({ "status": "Accepted" }),
+        };
+        assert_eq!(result.to_text(), r#"[3,"1",{"status":"Accepted"}]"#);
+        assert_eq!(Frame::parse(&result.to_text()).unwrap(), result);
+
+        assert!(Frame::parse(r#"[5,"1"]"#).is_err());
+    }
+}
@@ -0,0 +1,640 @@
+//! OCPP 1.6J central system, letting EV chargers connect to homectl over
+//! WebSockets. Charging state, power and energy are reported as sensors,
+//! integration actions start and stop charging and limit the charging rate.
+
+mod messages;
+
+use crate::core::{http::basic_auth, schema::JsonSchema};
+use crate::types::{
+    device::{Device, DeviceData, DeviceId, DeviceKey, SensorDevice},
+    event::{Message, TxEventChannel},
+    integration::{Integration, IntegrationActionPayload, IntegrationId},
+};
+use async_trait::async_trait;
+use chrono::{SecondsFormat, Utc};
+use color_eyre::Result;
+use eyre::{eyre, Context};
+use futures::{SinkExt, StreamExt};
+use ordered_float::OrderedFloat;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    task::{JoinHandle, JoinSet},
+};
+use tokio_tungstenite::tungstenite::{
+    self,
+    handshake::server::{ErrorResponse, Request, Response},
+    http::{HeaderValue, StatusCode},
+};
+
+use messages::{read_meter, Frame, MeterValues, StatusNotification};
+
+const SUBPROTOCOL: &str = "ocpp1.6";
+
+/// Interval chargers are asked to send heartbeats in
+const HEARTBEAT_INTERVAL_SECS: u64 = 300;
+
+/// How long to wait for chargers to respond to our calls
+const CALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Id of the charging profile set by limit actions, which replaces the
+/// previous limit
+const CHARGING_PROFILE_ID: u32 = 1;
+
+const STATUS_DEVICE_ID: &str = "status";
+const CHARGING_DEVICE_ID: &str = "charging";
+const POWER_DEVICE_ID: &str = "power";
+const ENERGY_DEVICE_ID: &str = "energy";
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct OcppChargerConfig {
+    /// Charge point identity, the last path segment of the URL the charger
+    /// connects to
+    pub id: String,
+
+    /// Prefix of sensor names, e.g. "Garage charger"
+    pub name: String,
+
+    /// If set, the charger must authenticate with HTTP basic auth, using its
+    /// identity as user name (OCPP security profile 1)
+    pub password: Option<String>,
+
+    /// Connector whose state is reported, defaults to 1
+    pub connector_id: Option<u32>,
+}
+
+impl OcppChargerConfig {
+    fn connector_id(&self) -> u32 {
+        self.connector_id.unwrap_or(1)
+    }
+
+    fn device_ids(&self) -> Vec<DeviceId> {
+        [
+            STATUS_DEVICE_ID,
+            CHARGING_DEVICE_ID,
+            POWER_DEVICE_ID,
+            ENERGY_DEVICE_ID,
+        ]
+        .into_iter()
+        .map(|suffix| DeviceId::new(&format!("{}:{}", self.id, suffix)))
+        .collect()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct OcppConfig {
+    /// Address to listen on for charger connections, defaults to
+    /// `0.0.0.0:9000`
+    listen: Option<String>,
+
+    /// Only these chargers are accepted
+    chargers: Vec<OcppChargerConfig>,
+}
+
+/// Payload of integration actions, e.g.
+/// `{ "charger": "garage", "command": "limit", "amps": 10 }`
+#[derive(Debug, Deserialize)]
+struct OcppActionPayload {
+    /// Charge point identity
+    charger: String,
+
+    #[serde(flatten)]
+    command: OcppCommand,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum OcppCommand {
+    Start {
+        id_tag: Option<String>,
+    },
+    Stop,
+    Limit {
+        amps: Option<f64>,
+        watts: Option<f64>,
+    },
+}
+
+/// Call from us to a charger
+struct Call {
+    action: &'static str,
+    payload: Value,
+    response_tx: oneshot::Sender<Result<Value>>,
+}
+
+struct Session {
+    call_tx: UnboundedSender<Call>,
+    transaction_id: Option<i32>,
+}
+
+/// Sessions of connected chargers by identity
+type Sessions = Arc<Mutex<BTreeMap<String, Session>>>;
+
+pub struct Ocpp {
+    id: IntegrationId,
+    config: OcppConfig,
+    event_tx: TxEventChannel,
+    sessions: Sessions,
+    server_handle: Option<JoinHandle<()>>,
+}
+
+#[async_trait]
+impl Integration for Ocpp {
+    fn new(id: &IntegrationId, config: &config::Value, event_tx: TxEventChannel) -> Result<Self> {
+        let config = config
+            .clone()
+            .try_deserialize()
+            .wrap_err("Failed to deserialize config of Ocpp integration")?;
+
+        Ok(Ocpp {
+            id: id.clone(),
+            config,
+            event_tx,
+            sessions: Default::default(),
+            server_handle: None,
+        })
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        let listen = self.config.listen.as_deref().unwrap_or("0.0.0.0:9000");
+        let listener = TcpListener::bind(listen)
+            .await
+            .wrap_err_with(|| format!("Failed to listen on {}", listen))?;
+
+        info!(integration_id = %self.id, "Accepting OCPP connections on {}", listen);
+
+        let server = Arc::new(Server {
+            id: self.id.clone(),
+            config: self.config.clone(),
+            event_tx: self.event_tx.clone(),
+            sessions: self.sessions.clone(),
+            // Transaction ids must stay unique across restarts
+            next_transaction_id: AtomicI32::new(Utc::now().timestamp() as i32),
+        });
+
+        self.server_handle = Some(tokio::spawn(server.run(listener)));
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        // Dropping the server's JoinSet closes all connections
+        if let Some(server_handle) = self.server_handle.take() {
+            server_handle.abort();
+        }
+
+        self.sessions.lock().unwrap().clear();
+
+        Ok(())
+    }
+
+    async fn run_integration_action(&mut self, payload: &IntegrationActionPayload) -> Result<()> {
+        let payload: OcppActionPayload = serde_json::from_str(&payload.to_string())
+            .wrap_err("Failed to deserialize Ocpp integration action payload")?;
+
+        let charger = self
+            .config
+            .chargers
+            .iter()
+            .find(|charger| charger.id == payload.charger)
+            .ok_or_else(|| eyre!("OCPP charger {} not configured", payload.charger))?;
+
+        let (call_tx, transaction_id) = {
+            let sessions = self.sessions.lock().unwrap();
+            let session = sessions
+                .get(&charger.id)
+                .ok_or_else(|| eyre!("OCPP charger {} is not connected", charger.id))?;
+
+            (session.call_tx.clone(), session.transaction_id)
+        };
+
+        let (action, request) = match payload.command {
+            OcppCommand::Start { id_tag } => (
+                "RemoteStartTransaction",
+                json!({
+                    "connectorId": charger.connector_id(),
+                    "idTag": id_tag.unwrap_or_else(|| "homectl".to_string()),
+                }),
+            ),
+            OcppCommand::Stop => {
+                let transaction_id = transaction_id
+                    .ok_or_else(|| eyre!("No transaction running on {}", charger.id))?;
+
+                (
+                    "RemoteStopTransaction",
+                    json!({ "transactionId": transaction_id }),
+                )
+            }
+            OcppCommand::Limit { amps, watts } => {
+                let (unit, limit) = match (amps, watts) {
+                    (Some(amps), None) => ("A", amps),
+                    (None, Some(watts)) => ("W", watts),
+                    _ => return Err(eyre!("Expected either amps or watts")),
+                };
+
+                // A default profile for all connectors, also applying to
+                // running transactions
+                (
+                    "SetChargingProfile",
+                    json!({
+                        "connectorId": 0,
+                        "csChargingProfiles": {
+                            "chargingProfileId": CHARGING_PROFILE_ID,
+                            "stackLevel": 0,
+                            "chargingProfilePurpose": "TxDefaultProfile",
+                            "chargingProfileKind": "Relative",
+                            "chargingSchedule": {
+                                "chargingRateUnit": unit,
+                                "chargingSchedulePeriod": [{ "startPeriod": 0, "limit": limit }],
+                            },
+                        },
+                    }),
+                )
+            }
+        };
+
+        let (response_tx, response_rx) = oneshot::channel();
+        call_tx
+            .send(Call {
+                action,
+                payload: request,
+                response_tx,
+            })
+            .map_err(|_| eyre!("OCPP charger {} is not connected", charger.id))?;
+
+        // Don't hold up other integrations while the charger responds
+        let id = self.id.clone();
+        let charger_id = charger.id.clone();
+        tokio::spawn(async move {
+            let result = tokio::time::timeout(CALL_TIMEOUT, response_rx)
+                .await
+                .map_err(|_| eyre!("Timed out"))
+                .and_then(|response| response.map_err(|_| eyre!("Connection closed")))
+                .and_then(|response| response);
+
+            match result {
+                Ok(response) if response["status"] == "Accepted" => {
+                    info!(integration_id = %id, "{} accepted {}", charger_id, action);
+                }
+                Ok(response) => {
+                    warn!(integration_id = %id, "{} did not accept {}: {}", charger_id, action, response);
+                }
+                Err(e) => {
+                    warn!(integration_id = %id, "{} to {} failed: {:?}", action, charger_id, e);
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// OCPP error code and description, sent in response to calls we can't
+/// handle
+struct CallError(&'static str, String);
+
+fn parse_payload<T: serde::de::DeserializeOwned>(payload: Value) -> Result<T, CallError> {
+    serde_json::from_value(payload).map_err(|e| CallError("FormationViolation", e.to_string()))
+}
+
+struct Server {
+    id: IntegrationId,
+    config: OcppConfig,
+    event_tx: TxEventChannel,
+    sessions: Sessions,
+    next_transaction_id: AtomicI32,
+}
+
+impl Server {
+    async fn run(self: Arc<Self>, listener: TcpListener) {
+        let mut connections = JoinSet::new();
+
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, addr) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            warn!(integration_id = %self.id, "Accepting connection failed: {:?}", e);
+                            continue;
+                        }
+                    };
+
+                    let server = self.clone();
+                    connections.spawn(async move {
+                        if let Err(e) = server.handle_connection(stream).await {
+                            warn!(integration_id = %server.id, "Connection from {} failed: {:?}", addr, e);
+                        }
+                    });
+                }
+                // Reap finished connections
+                Some(_) = connections.join_next() => {}
+            }
+        }
+    }
+
+    /// Accepts configured chargers, checking their password if set
+    fn check_handshake(&self, request: &Request) -> Result<&OcppChargerConfig, StatusCode> {
+        let identity = request.uri().path().rsplit('/').next().unwrap_or_default();
+
+        let charger = self
+            .config
+            .chargers
+            .iter()
+            .find(|charger| charger.id == identity)
+            .ok_or(StatusCode::NOT_FOUND)?;
+
+        if let Some(password) = &charger.password {
+            let (_, expected) = basic_auth(&charger.id, password);
+            let authorization = request
+                .headers()
+                .get("authorization")
+                .and_then(|value| value.to_str().ok());
+
+            if authorization != Some(expected.as_str()) {
+                return Err(StatusCode::UNAUTHORIZED);
+            }
+        }
+
+        Ok(charger)
+    }
+
+    async fn handle_connection(&self, stream: TcpStream) -> Result<()> {
+        let mut charger = None;
+
+        let ws = tokio_tungstenite::accept_hdr_async(
+            stream,
+            |request: &Request, mut response: Response| match self.check_handshake(request) {
+                Ok(accepted) => {
+                    charger = Some(accepted);
+
+                    let protocols = request
+                        .headers()
+                        .get("sec-websocket-protocol")
+                        .and_then(|value| value.to_str().ok())
+                        .unwrap_or_default();
+
+                    if protocols.split(',').any(|p| p.trim() == SUBPROTOCOL) {
+                        response.headers_mut().insert(
+                            "sec-websocket-protocol",
+                            HeaderValue::from_static(SUBPROTOCOL),
+                        );
+                    }
+
+                    Ok(response)
+                }
+                Err(status) => {
+                    let mut response = ErrorResponse::new(None);
+                    *response.status_mut() = status;
+                    Err(response)
+                }
+            },
+        )
+        .await?;
+
+        let charger = charger.expect("Expected charger to be set on successful handshake");
+
+        info!(integration_id = %self.id, "Charger {} connected", charger.id);
+
+        let (call_tx, call_rx) = unbounded_channel();
+        self.sessions.lock().unwrap().insert(
+            charger.id.clone(),
+            Session {
+                call_tx: call_tx.clone(),
+                transaction_id: None,
+            },
+        );
+        self.set_available(charger, true);
+
+        let result = self.run_session(charger, ws, call_rx).await;
+
+        // The charger may have reconnected meanwhile
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions
+            .get(&charger.id)
+            .is_some_and(|session| session.call_tx.same_channel(&call_tx))
+        {
+            sessions.remove(&charger.id);
+            self.set_available(charger, false);
+        }
+
+        result
+    }
+
+    async fn run_session(
+        &self,
+        charger: &OcppChargerConfig,
+        ws: tokio_tungstenite::WebSocketStream<TcpStream>,
+        mut call_rx: UnboundedReceiver<Call>,
+    ) -> Result<()> {
+        let (mut ws_tx, mut ws_rx) = ws.split();
+        let mut pending: HashMap<String, oneshot::Sender<Result<Value>>> = HashMap::new();
+        let mut next_call_id: u64 = 0;
+
+        loop {
+            tokio::select! {
+                msg = ws_rx.next() => {
+                    let text = match msg {
+                        None | Some(Ok(tungstenite::Message::Close(_))) => return Ok(()),
+                        Some(Ok(tungstenite::Message::Text(text))) => text,
+                        Some(Ok(_)) => continue,
+                        Some(Err(e)) => return Err(e.into()),
+                    };
+
+                    match Frame::parse(&text)? {
+                        Frame::Call { id, action, payload } => {
+                            let response = match self.handle_call(charger, &action, payload) {
+                                Ok(payload) => Frame::CallResult { id, payload },
+                                Err(CallError(code, description)) => Frame::CallError {
+                                    id,
+                                    code: code.to_string(),
+                                    description,
+                                },
+                            };
+
+                            ws_tx.send(tungstenite::Message::Text(response.to_text())).await?;
+                        }
+                        Frame::CallResult { id, payload } => {
+                            if let Some(response_tx) = pending.remove(&id) {
+                                response_tx.send(Ok(payload)).ok();
+                            }
+                        }
+                        Frame::CallError { id, code, description } => {
+                            if let Some(response_tx) = pending.remove(&id) {
+                                response_tx.send(Err(eyre!("{}: {}", code, description))).ok();
+                            }
+                        }
+                    }
+                }
+                call = call_rx.recv() => {
+                    let Some(call) = call else {
+                        return Ok(());
+                    };
+
+                    next_call_id += 1;
+                    let id = format!("homectl-{}", next_call_id);
+
+                    let frame = Frame::Call {
+                        id: id.clone(),
+                        action: call.action.to_string(),
+                        payload: call.payload,
+                    };
+                    ws_tx.send(tungstenite::Message::Text(frame.to_text())).await?;
+
+                    pending.insert(id, call.response_tx);
+                }
+            }
+        }
+    }
+
+    fn handle_call(
+        &self,
+        charger: &OcppChargerConfig,
+        action: &str,
+        payload: Value,
+    ) -> Result<Value, CallError> {
+        let response = match action {
+            "BootNotification" => json!({
+                "status": "Accepted",
+                "currentTime": now(),
+                "interval": HEARTBEAT_INTERVAL_SECS,
+            }),
+            "Heartbeat" => json!({ "currentTime": now() }),
+            "StatusNotification" => {
+                let notification: StatusNotification = parse_payload(payload)?;
+
+                if notification.connector_id == charger.connector_id() {
+                    self.report_status(charger, &notification.status);
+                }
+
+                json!({})
+            }
+            "MeterValues" => {
+                let meter_values: MeterValues = parse_payload(payload)?;
+
+                if meter_values.connector_id == charger.connector_id() {
+                    self.report_meter(charger, &meter_values);
+                }
+
+                json!({})
+            }
+            "StartTransaction" => {
+                let transaction_id = self.next_transaction_id.fetch_add(1, Ordering::Relaxed);
+
+                if let Some(session) = self.sessions.lock().unwrap().get_mut(&charger.id) {
+                    session.transaction_id = Some(transaction_id);
+                }
+
+                json!({
+                    "transactionId": transaction_id,
+                    "idTagInfo": { "status": "Accepted" },
+                })
+            }
+            "StopTransaction" => {
+                if let Some(session) = self.sessions.lock().unwrap().get_mut(&charger.id) {
+                    session.transaction_id = None;
+                }
+
+                json!({ "idTagInfo": { "status": "Accepted" } })
+            }
+            // Only configured chargers can connect, so any id tag is fine
+            "Authorize" => json!({ "idTagInfo": { "status": "Accepted" } }),
+            "DataTransfer" => json!({ "status": "UnknownVendorId" }),
+            "FirmwareStatusNotification" | "DiagnosticsStatusNotification" => json!({}),
+            action => {
+                return Err(CallError(
+                    "NotImplemented",
+                    format!("{} is not supported", action),
+                ))
+            }
+        };
+
+        Ok(response)
+    }
+
+    fn report(&self, charger: &OcppChargerConfig, suffix: &str, name: &str, data: SensorDevice) {
+        let device = Device::new(
+            self.id.clone(),
+            DeviceId::new(&format!("{}:{}", charger.id, suffix)),
+            format!("{} {}", charger.name, name),
+            DeviceData::Sensor(data),
+        );
+
+        self.event_tx.send(Message::RecvDeviceState { device });
+    }
+
+    fn report_status(&self, charger: &OcppChargerConfig, status: &str) {
+        self.report(
+            charger,
+            STATUS_DEVICE_ID,
+            "status",
+            SensorDevice::Text {
+                value: status.to_string(),
+            },
+        );
+        self.report(
+            charger,
+            CHARGING_DEVICE_ID,
+            "charging",
+            SensorDevice::Boolean {
+                value: status == "Charging",
+            },
+        );
+    }
+
+    fn report_meter(&self, charger: &OcppChargerConfig, meter_values: &MeterValues) {
+        let meter = read_meter(&meter_values.meter_value);
+
+        if let Some(power_w) = meter.power_w {
+            self.report(
+                charger,
+                POWER_DEVICE_ID,
+                "power",
+                SensorDevice::Number {
+                    value: OrderedFloat(power_w),
+                    unit: Some("W".to_string()),
+                    raw: None,
+                },
+            );
+        }
+
+        if let Some(energy_kwh) = meter.energy_kwh {
+            self.report(
+                charger,
+                ENERGY_DEVICE_ID,
+                "energy",
+                SensorDevice::Number {
+                    value: OrderedFloat(energy_kwh),
+                    unit: Some("kWh".to_string()),
+                    raw: None,
+                },
+            );
+        }
+    }
+
+    fn set_available(&self, charger: &OcppChargerConfig, available: bool) {
+        for device_id in charger.device_ids() {
+            self.event_tx.send(Message::SetDeviceAvailability {
+                device_key: DeviceKey::new(self.id.clone(), device_id),
+                available,
+            });
+        }
+    }
+}
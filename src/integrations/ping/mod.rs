@@ -0,0 +1,228 @@
+//! Presence detection by network, periodically pinging hosts and looking up
+//! MAC addresses in the host's ARP table. Reports a boolean sensor per
+//! device, true while the device is on the network. Phones often don't answer
+//! pings while asleep, so give such sensors an away timeout, see
+//! [crate::core::presence].
+
+use crate::core::schema::JsonSchema;
+use crate::types::{
+    device::{Device, DeviceData, DeviceId, SensorDevice},
+    event::{Message, TxEventChannel},
+    integration::{Integration, IntegrationId},
+};
+use async_trait::async_trait;
+use color_eyre::Result;
+use eyre::{eyre, Context};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    process::Stdio,
+    time::Duration,
+};
+use tokio::{process::Command, task::JoinHandle};
+
+/// ARP table of the host, see arp(7)
+const ARP_TABLE_PATH: &str = "/proc/net/arp";
+
+/// Flag of completed ARP table entries
+const ATF_COM: u32 = 0x2;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct PingDeviceConfig {
+    pub name: String,
+
+    /// Host name or IP address to ping
+    pub host: Option<String>,
+
+    /// MAC address to look up in the ARP table, e.g. `A4:C1:38:11:22:33`.
+    /// Only devices on the same network segment as the host show up there.
+    pub mac: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct PingConfig {
+    /// Seconds between scans, defaults to 30
+    interval_secs: Option<u64>,
+
+    /// Each device needs a host, a MAC address or both. Devices with both
+    /// are present if either of them is found.
+    devices: Vec<PingDeviceConfig>,
+}
+
+pub struct Ping {
+    id: IntegrationId,
+    config: PingConfig,
+    event_tx: TxEventChannel,
+    scan_handle: Option<JoinHandle<()>>,
+}
+
+#[async_trait]
+impl Integration for Ping {
+    fn new(id: &IntegrationId, config: &config::Value, event_tx: TxEventChannel) -> Result<Self> {
+        let config: PingConfig = config
+            .clone()
+            .try_deserialize()
+            .wrap_err("Failed to deserialize config of Ping integration")?;
+
+        if let Some(device) = config
+            .devices
+            .iter()
+            .find(|device| device.host.is_none() && device.mac.is_none())
+        {
+            return Err(eyre!("Ping device {} needs a host or mac", device.name));
+        }
+
+        Ok(Ping {
+            id: id.clone(),
+            config,
+            event_tx,
+            scan_handle: None,
+        })
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        let scanner = Scanner {
+            id: self.id.clone(),
+            interval: Duration::from_secs(self.config.interval_secs.unwrap_or(30)),
+            devices: self.config.devices.clone(),
+            event_tx: self.event_tx.clone(),
+            last_values: BTreeMap::new(),
+        };
+
+        self.scan_handle = Some(tokio::spawn(scanner.run()));
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        if let Some(scan_handle) = self.scan_handle.take() {
+            scan_handle.abort();
+        }
+
+        Ok(())
+    }
+}
+
+/// MAC addresses of completed entries in given ARP table, uppercase
+fn parse_arp_table(table: &str) -> BTreeSet<String> {
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            let flags = u32::from_str_radix(columns.get(2)?.trim_start_matches("0x"), 16).ok()?;
+            let mac = columns.get(3)?;
+
+            (flags & ATF_COM != 0).then(|| mac.to_uppercase())
+        })
+        .collect()
+}
+
+fn ping_device_id(device: &PingDeviceConfig) -> DeviceId {
+    let address = device.mac.as_ref().or(device.host.as_ref());
+    let address = address.map(|address| address.replace(':', "").to_lowercase());
+
+    DeviceId::new(&address.unwrap_or_default())
+}
+
+async fn ping(host: &str) -> bool {
+    let status = Command::new("ping")
+        .args(["-c", "1", "-W", "1", host])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await;
+
+    status.map_or(false, |status| status.success())
+}
+
+struct Scanner {
+    id: IntegrationId,
+    interval: Duration,
+    devices: Vec<PingDeviceConfig>,
+    event_tx: TxEventChannel,
+
+    /// Only report changes
+    last_values: BTreeMap<DeviceId, bool>,
+}
+
+impl Scanner {
+    async fn run(mut self) {
+        let mut interval = tokio::time::interval(self.interval);
+
+        loop {
+            interval.tick().await;
+            self.scan().await;
+        }
+    }
+
+    async fn scan(&mut self) {
+        let pings = self.devices.iter().map(|device| async {
+            match &device.host {
+                Some(host) => ping(host).await,
+                None => false,
+            }
+        });
+        let pinged = futures::future::join_all(pings).await;
+
+        // Read after pinging, as pings refresh the ARP table entries
+        let arp_table = match tokio::fs::read_to_string(ARP_TABLE_PATH).await {
+            Ok(table) => parse_arp_table(&table),
+            Err(e) => {
+                warn!(integration_id = %self.id, "Could not read ARP table: {:?}", e);
+                BTreeSet::new()
+            }
+        };
+
+        let devices = self.devices.clone();
+
+        for (device, pinged) in devices.iter().zip(pinged) {
+            let in_arp_table = device
+                .mac
+                .as_ref()
+                .map_or(false, |mac| arp_table.contains(&mac.to_uppercase()));
+
+            self.report(device, pinged || in_arp_table);
+        }
+    }
+
+    fn report(&mut self, device: &PingDeviceConfig, present: bool) {
+        let device_id = ping_device_id(device);
+
+        if self.last_values.insert(device_id.clone(), present) == Some(present) {
+            return;
+        }
+
+        let device = Device::new(
+            self.id.clone(),
+            device_id,
+            device.name.clone(),
+            DeviceData::Sensor(SensorDevice::Boolean { value: present }),
+        );
+
+        self.event_tx.send(Message::RecvDeviceState { device });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_arp_table() {
+        let table = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.1      0x1         0x2         aa:bb:cc:dd:ee:ff     *        eth0
+192.168.1.23     0x1         0x0         00:00:00:00:00:00     *        eth0
+192.168.1.42     0x1         0x2         a4:c1:38:11:22:33     *        wlan0
+";
+
+        assert_eq!(
+            parse_arp_table(table),
+            BTreeSet::from([
+                "AA:BB:CC:DD:EE:FF".to_string(),
+                "A4:C1:38:11:22:33".to_string()
+            ])
+        );
+    }
+}
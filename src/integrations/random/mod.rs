@@ -1,3 +1,4 @@
+use crate::core::schema::JsonSchema;
 use crate::types::{
     color::DeviceColor,
     device::{ControllableState, Device, DeviceData, DeviceId, SensorDevice},
@@ -10,10 +11,11 @@ use eyre::Context;
 use ordered_float::OrderedFloat;
 use rand::prelude::*;
 use serde::Deserialize;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::time;
+use tokio::{task::JoinHandle, time};
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 pub struct RandomConfig {
     device_name: String,
 }
@@ -23,6 +25,7 @@ pub struct Random {
     id: IntegrationId,
     config: RandomConfig,
     event_tx: TxEventChannel,
+    poll_handle: Option<Arc<JoinHandle<()>>>,
 }
 
 #[async_trait]
@@ -37,6 +40,7 @@ impl Integration for Random {
             id: id.clone(),
             config,
             event_tx,
+            poll_handle: None,
         })
     }
 
@@ -53,7 +57,16 @@ impl Integration for Random {
 
         // FIXME: can we restructure the integrations / devices systems such
         // that polling is not needed here?
-        tokio::spawn(async { poll_sensor(random).await });
+        let poll_handle = tokio::spawn(async { poll_sensor(random).await });
+        self.poll_handle = Some(Arc::new(poll_handle));
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        if let Some(poll_handle) = self.poll_handle.take() {
+            poll_handle.abort();
+        }
 
         Ok(())
     }
@@ -88,6 +101,7 @@ fn mk_random_device(random: &Random) -> Device {
     let state = DeviceData::Sensor(SensorDevice::Color(ControllableState {
         power: true,
         color: Some(get_random_color()),
+        segments: None,
         brightness: Some(OrderedFloat(1.0)),
         transition_ms: Some(1000),
     }));
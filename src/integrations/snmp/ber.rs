@@ -0,0 +1,446 @@
+//! Minimal BER encoding of SNMPv2c messages, covering GET and SET requests
+//! and their responses.
+
+use color_eyre::Result;
+use eyre::eyre;
+use std::{fmt, str::FromStr};
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_NULL: u8 = 0x05;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_IP_ADDRESS: u8 = 0x40;
+const TAG_COUNTER32: u8 = 0x41;
+const TAG_GAUGE32: u8 = 0x42;
+const TAG_TIMETICKS: u8 = 0x43;
+const TAG_COUNTER64: u8 = 0x46;
+const TAG_NO_SUCH_OBJECT: u8 = 0x80;
+const TAG_NO_SUCH_INSTANCE: u8 = 0x81;
+const TAG_END_OF_MIB_VIEW: u8 = 0x82;
+
+pub const PDU_GET: u8 = 0xa0;
+pub const PDU_RESPONSE: u8 = 0xa2;
+pub const PDU_SET: u8 = 0xa3;
+
+const VERSION_2C: i64 = 1;
+
+/// Object identifier, e.g. `1.3.6.1.2.1.1.3.0`
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Oid(Vec<u32>);
+
+impl FromStr for Oid {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Oid> {
+        let arcs = s
+            .trim_start_matches('.')
+            .split('.')
+            .map(str::parse)
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|_| eyre!("Invalid OID {}", s))?;
+
+        // The first two arcs are encoded in a single byte
+        let valid = matches!(arcs.as_slice(), [0 | 1, second, ..] if *second < 40)
+            || matches!(arcs.as_slice(), [2, second, ..] if *second <= u32::MAX - 80);
+
+        if !valid {
+            return Err(eyre!("Invalid OID {}", s));
+        }
+
+        Ok(Oid(arcs))
+    }
+}
+
+impl fmt::Display for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arcs: Vec<String> = self.0.iter().map(u32::to_string).collect();
+        f.write_str(&arcs.join("."))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Integer(i64),
+    OctetString(Vec<u8>),
+    Null,
+    Oid(Oid),
+    IpAddress([u8; 4]),
+    Counter32(u32),
+    Gauge32(u32),
+    TimeTicks(u32),
+    Counter64(u64),
+    /// The agent has no value for the requested OID
+    Missing,
+}
+
+impl Value {
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Integer(value) => Some(*value as f64),
+            Value::Counter32(value) | Value::Gauge32(value) | Value::TimeTicks(value) => {
+                Some(*value as f64)
+            }
+            Value::Counter64(value) => Some(*value as f64),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::OctetString(bytes) => f.write_str(&String::from_utf8_lossy(bytes)),
+            Value::Null | Value::Missing => Ok(()),
+            Value::Oid(oid) => oid.fmt(f),
+            Value::IpAddress([a, b, c, d]) => write!(f, "{}.{}.{}.{}", a, b, c, d),
+            value => write!(f, "{}", value.as_f64().unwrap_or_default()),
+        }
+    }
+}
+
+fn push_tlv(out: &mut Vec<u8>, tag: u8, content: &[u8]) {
+    out.push(tag);
+
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+
+    out.extend_from_slice(content);
+}
+
+/// Strips redundant leading bytes of a big endian two's complement integer
+fn minimal_int(bytes: &[u8]) -> &[u8] {
+    let mut bytes = bytes;
+
+    while let [first, second, ..] = bytes {
+        let redundant =
+            (*first == 0x00 && second & 0x80 == 0) || (*first == 0xff && second & 0x80 != 0);
+        if !redundant {
+            break;
+        }
+        bytes = &bytes[1..];
+    }
+
+    bytes
+}
+
+fn encode_unsigned(value: u64) -> Vec<u8> {
+    let mut bytes = vec![0];
+    bytes.extend_from_slice(&value.to_be_bytes());
+    minimal_int(&bytes).to_vec()
+}
+
+fn push_base128(out: &mut Vec<u8>, value: u32) {
+    let mut groups = vec![(value & 0x7f) as u8];
+    let mut rest = value >> 7;
+
+    while rest > 0 {
+        groups.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+
+    out.extend(groups.iter().rev());
+}
+
+fn encode_oid(oid: &Oid) -> Vec<u8> {
+    let mut out = vec![];
+    push_base128(&mut out, oid.0[0] * 40 + oid.0[1]);
+
+    for arc in &oid.0[2..] {
+        push_base128(&mut out, *arc);
+    }
+
+    out
+}
+
+fn push_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Integer(value) => push_tlv(out, TAG_INTEGER, minimal_int(&value.to_be_bytes())),
+        Value::OctetString(bytes) => push_tlv(out, TAG_OCTET_STRING, bytes),
+        Value::Null => push_tlv(out, TAG_NULL, &[]),
+        Value::Oid(oid) => push_tlv(out, TAG_OID, &encode_oid(oid)),
+        Value::IpAddress(addr) => push_tlv(out, TAG_IP_ADDRESS, addr),
+        Value::Counter32(value) => push_tlv(out, TAG_COUNTER32, &encode_unsigned(*value as u64)),
+        Value::Gauge32(value) => push_tlv(out, TAG_GAUGE32, &encode_unsigned(*value as u64)),
+        Value::TimeTicks(value) => push_tlv(out, TAG_TIMETICKS, &encode_unsigned(*value as u64)),
+        Value::Counter64(value) => push_tlv(out, TAG_COUNTER64, &encode_unsigned(*value)),
+        Value::Missing => push_tlv(out, TAG_NO_SUCH_OBJECT, &[]),
+    }
+}
+
+/// Encodes a message with given PDU, which is one of [PDU_GET], [PDU_SET] or
+/// [PDU_RESPONSE]
+pub fn encode_message(
+    community: &str,
+    pdu_type: u8,
+    request_id: i32,
+    varbinds: &[(Oid, Value)],
+) -> Vec<u8> {
+    let mut varbinds_content = vec![];
+    for (oid, value) in varbinds {
+        let mut varbind = vec![];
+        push_value(&mut varbind, &Value::Oid(oid.clone()));
+        push_value(&mut varbind, value);
+        push_tlv(&mut varbinds_content, TAG_SEQUENCE, &varbind);
+    }
+
+    let mut pdu = vec![];
+    push_value(&mut pdu, &Value::Integer(request_id as i64));
+    // Error status and index
+    push_value(&mut pdu, &Value::Integer(0));
+    push_value(&mut pdu, &Value::Integer(0));
+    push_tlv(&mut pdu, TAG_SEQUENCE, &varbinds_content);
+
+    let mut message = vec![];
+    push_value(&mut message, &Value::Integer(VERSION_2C));
+    push_value(
+        &mut message,
+        &Value::OctetString(community.as_bytes().to_vec()),
+    );
+    push_tlv(&mut message, pdu_type, &pdu);
+
+    let mut out = vec![];
+    push_tlv(&mut out, TAG_SEQUENCE, &message);
+    out
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    fn read_tlv(&mut self) -> Result<(u8, &'a [u8])> {
+        let truncated = || eyre!("Truncated SNMP message");
+
+        let (&tag, rest) = self.buf.split_first().ok_or_else(truncated)?;
+        let (&len, mut rest) = rest.split_first().ok_or_else(truncated)?;
+
+        let len = if len & 0x80 == 0 {
+            len as usize
+        } else {
+            let num_bytes = (len & 0x7f) as usize;
+            if num_bytes > 4 || rest.len() < num_bytes {
+                return Err(truncated());
+            }
+
+            let (len_bytes, after) = rest.split_at(num_bytes);
+            rest = after;
+            len_bytes
+                .iter()
+                .fold(0usize, |len, byte| len << 8 | *byte as usize)
+        };
+
+        if rest.len() < len {
+            return Err(truncated());
+        }
+
+        let (content, rest) = rest.split_at(len);
+        self.buf = rest;
+
+        Ok((tag, content))
+    }
+
+    fn read_expected(&mut self, expected: u8) -> Result<&'a [u8]> {
+        let (tag, content) = self.read_tlv()?;
+
+        if tag != expected {
+            return Err(eyre!("Expected tag {:#x}, got {:#x}", expected, tag));
+        }
+
+        Ok(content)
+    }
+
+    fn read_integer(&mut self) -> Result<i64> {
+        decode_integer(self.read_expected(TAG_INTEGER)?)
+    }
+}
+
+fn decode_integer(content: &[u8]) -> Result<i64> {
+    if content.is_empty() || content.len() > 8 {
+        return Err(eyre!("Invalid SNMP integer"));
+    }
+
+    // Sign extend from the first byte
+    let init = if content[0] & 0x80 != 0 { -1 } else { 0 };
+    Ok(content
+        .iter()
+        .fold(init, |value, byte| value << 8 | *byte as i64))
+}
+
+fn decode_unsigned(content: &[u8]) -> Result<u64> {
+    let content = match content {
+        [0, rest @ ..] => rest,
+        content => content,
+    };
+
+    if content.len() > 8 {
+        return Err(eyre!("Invalid SNMP unsigned integer"));
+    }
+
+    Ok(content
+        .iter()
+        .fold(0, |value, byte| value << 8 | *byte as u64))
+}
+
+fn decode_oid(content: &[u8]) -> Result<Oid> {
+    let mut values = vec![];
+    let mut value: u32 = 0;
+
+    for byte in content {
+        value = value
+            .checked_mul(128)
+            .ok_or_else(|| eyre!("Invalid SNMP OID"))?
+            | (byte & 0x7f) as u32;
+
+        if byte & 0x80 == 0 {
+            values.push(value);
+            value = 0;
+        }
+    }
+
+    let Some((first, rest)) = values.split_first() else {
+        return Err(eyre!("Invalid SNMP OID"));
+    };
+
+    let mut arcs = match first {
+        0..=39 => vec![0, *first],
+        40..=79 => vec![1, first - 40],
+        _ => vec![2, first - 80],
+    };
+    arcs.extend_from_slice(rest);
+
+    Ok(Oid(arcs))
+}
+
+fn decode_value(tag: u8, content: &[u8]) -> Result<Value> {
+    let value = match tag {
+        TAG_INTEGER => Value::Integer(decode_integer(content)?),
+        TAG_OCTET_STRING => Value::OctetString(content.to_vec()),
+        TAG_NULL => Value::Null,
+        TAG_OID => Value::Oid(decode_oid(content)?),
+        TAG_IP_ADDRESS => Value::IpAddress(
+            content
+                .try_into()
+                .map_err(|_| eyre!("Invalid SNMP IP address"))?,
+        ),
+        TAG_COUNTER32 => Value::Counter32(decode_unsigned(content)? as u32),
+        TAG_GAUGE32 => Value::Gauge32(decode_unsigned(content)? as u32),
+        TAG_TIMETICKS => Value::TimeTicks(decode_unsigned(content)? as u32),
+        TAG_COUNTER64 => Value::Counter64(decode_unsigned(content)?),
+        TAG_NO_SUCH_OBJECT | TAG_NO_SUCH_INSTANCE | TAG_END_OF_MIB_VIEW => Value::Missing,
+        tag => return Err(eyre!("Unsupported SNMP value type {:#x}", tag)),
+    };
+
+    Ok(value)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Response {
+    pub request_id: i32,
+
+    /// Non-zero if the request failed, e.g. 2 (noSuchName) or 17 (notWritable)
+    pub error_status: i64,
+
+    pub varbinds: Vec<(Oid, Value)>,
+}
+
+pub fn decode_response(buf: &[u8]) -> Result<Response> {
+    let mut message = Reader {
+        buf: Reader { buf }.read_expected(TAG_SEQUENCE)?,
+    };
+
+    let version = message.read_integer()?;
+    if version != VERSION_2C {
+        return Err(eyre!("Unexpected SNMP version {}", version));
+    }
+
+    // Community
+    message.read_expected(TAG_OCTET_STRING)?;
+
+    let mut pdu = Reader {
+        buf: message.read_expected(PDU_RESPONSE)?,
+    };
+
+    let request_id = pdu.read_integer()? as i32;
+    let error_status = pdu.read_integer()?;
+    // Error index
+    pdu.read_integer()?;
+
+    let mut varbinds_reader = Reader {
+        buf: pdu.read_expected(TAG_SEQUENCE)?,
+    };
+
+    let mut varbinds = vec![];
+    while !varbinds_reader.is_empty() {
+        let mut varbind = Reader {
+            buf: varbinds_reader.read_expected(TAG_SEQUENCE)?,
+        };
+
+        let oid = decode_oid(varbind.read_expected(TAG_OID)?)?;
+        let (tag, content) = varbind.read_tlv()?;
+        varbinds.push((oid, decode_value(tag, content)?));
+    }
+
+    Ok(Response {
+        request_id,
+        error_status,
+        varbinds,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages() {
+        let sys_uptime: Oid = "1.3.6.1.2.1.1.3.0".parse().unwrap();
+        assert_eq!(sys_uptime.to_string(), "1.3.6.1.2.1.1.3.0");
+        assert!("1.40.1".parse::<Oid>().is_err());
+
+        let get = encode_message("public", PDU_GET, 1, &[(sys_uptime.clone(), Value::Null)]);
+        assert_eq!(
+            get,
+            [
+                0x30, 0x26, 0x02, 0x01, 0x01, 0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c', 0xa0,
+                0x19, 0x02, 0x01, 0x01, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30, 0x0e, 0x30, 0x0c,
+                0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x03, 0x00, 0x05, 0x00
+            ]
+        );
+
+        let outlet: Oid = "1.3.6.1.4.1.318.1.1.12.3.3.1.1.4.1".parse().unwrap();
+        let varbinds = vec![
+            (sys_uptime, Value::TimeTicks(3_000_000_000)),
+            (outlet, Value::Integer(-200)),
+            (
+                "1.3.6.1.2.1.1.5.0".parse().unwrap(),
+                Value::OctetString(b"pdu".to_vec()),
+            ),
+            ("1.3.6.1.2.1.1.6.0".parse().unwrap(), Value::Missing),
+        ];
+
+        let response = encode_message("private", PDU_RESPONSE, -7, &varbinds);
+        assert_eq!(
+            decode_response(&response).unwrap(),
+            Response {
+                request_id: -7,
+                error_status: 0,
+                varbinds,
+            }
+        );
+
+        assert!(decode_response(&get).is_err());
+        assert!(decode_response(&response[..response.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,404 @@
+//! SNMP integration, polling configured OIDs of switches, PDUs, NAS devices
+//! and the like over SNMPv2c. OIDs are reported as sensors, PDU outlets
+//! become controllable devices switched with SET requests.
+
+mod ber;
+
+use crate::core::schema::JsonSchema;
+use crate::types::{
+    color::Capabilities,
+    device::{ControllableDevice, Device, DeviceData, DeviceId, ManageKind, SensorDevice},
+    event::{Message, TxEventChannel},
+    integration::{Integration, IntegrationId},
+};
+use async_trait::async_trait;
+use color_eyre::Result;
+use eyre::{eyre, Context};
+use ordered_float::OrderedFloat;
+use serde::Deserialize;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tokio::{net::UdpSocket, sync::Notify, task::JoinHandle};
+
+use ber::{Oid, Value, PDU_GET, PDU_SET};
+
+/// How many OIDs to request at once, as agents limit the size of responses
+const MAX_OIDS_PER_REQUEST: usize = 16;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct SnmpSensorConfig {
+    pub name: String,
+
+    /// e.g. `1.3.6.1.4.1.318.1.1.12.2.3.1.1.2.1`
+    pub oid: String,
+
+    /// Unit of the value after scaling, e.g. "A"
+    pub unit: Option<String>,
+
+    /// Factor numeric values are multiplied with, e.g. 0.1 for values
+    /// reported in tenths. Defaults to 1.
+    pub scale: Option<f64>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct SnmpOutletConfig {
+    pub name: String,
+
+    /// OID of the outlet control, e.g. `1.3.6.1.4.1.318.1.1.12.3.3.1.1.4.1`
+    pub oid: String,
+
+    /// Value meaning the outlet is on, defaults to 1
+    pub on_value: Option<i64>,
+
+    /// Value meaning the outlet is off, defaults to 2
+    pub off_value: Option<i64>,
+}
+
+impl SnmpOutletConfig {
+    fn on_value(&self) -> i64 {
+        self.on_value.unwrap_or(1)
+    }
+
+    fn off_value(&self) -> i64 {
+        self.off_value.unwrap_or(2)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct SnmpConfig {
+    /// Address of the SNMP agent
+    host: String,
+
+    /// Defaults to 161
+    port: Option<u16>,
+
+    /// Community used for GET requests, defaults to "public"
+    community: Option<String>,
+
+    /// Community used for SET requests, defaults to `community`
+    write_community: Option<String>,
+
+    /// Seconds between polling the agent, defaults to 30
+    poll_secs: Option<u64>,
+
+    /// Milliseconds to wait for a response, defaults to 2000
+    timeout_ms: Option<u64>,
+
+    /// How outlets are managed, defaults to partially managed as they can
+    /// also be switched from the web interface of the PDU
+    managed: Option<ManageKind>,
+
+    #[serde(default)]
+    sensors: Vec<SnmpSensorConfig>,
+
+    #[serde(default)]
+    outlets: Vec<SnmpOutletConfig>,
+}
+
+/// Sensors and outlets by OID
+#[derive(Default)]
+struct Points {
+    sensors: BTreeMap<Oid, SnmpSensorConfig>,
+    outlets: BTreeMap<Oid, SnmpOutletConfig>,
+}
+
+#[derive(Clone)]
+struct Client {
+    addr: String,
+    timeout: Duration,
+}
+
+impl Client {
+    async fn request(
+        &self,
+        community: &str,
+        pdu_type: u8,
+        varbinds: &[(Oid, Value)],
+    ) -> Result<Vec<(Oid, Value)>> {
+        let request_id = rand::random::<i32>();
+        let message = ber::encode_message(community, pdu_type, request_id, varbinds);
+
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect(&self.addr).await?;
+        socket.send(&message).await?;
+
+        let mut buf = vec![0; 65535];
+
+        let response = tokio::time::timeout(self.timeout, async {
+            loop {
+                let n = socket.recv(&mut buf).await?;
+
+                // Skip late responses to earlier requests
+                match ber::decode_response(&buf[..n]) {
+                    Ok(response) if response.request_id == request_id => {
+                        return Ok::<_, eyre::Report>(response)
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .await
+        .map_err(|_| eyre!("Timed out waiting for response from {}", self.addr))??;
+
+        if response.error_status != 0 {
+            return Err(eyre!(
+                "{} responded with error status {}",
+                self.addr,
+                response.error_status
+            ));
+        }
+
+        Ok(response.varbinds)
+    }
+}
+
+pub struct Snmp {
+    id: IntegrationId,
+    config: SnmpConfig,
+    event_tx: TxEventChannel,
+    points: Arc<Points>,
+    client: Client,
+    refresh: Arc<Notify>,
+    poll_handle: Option<JoinHandle<()>>,
+}
+
+#[async_trait]
+impl Integration for Snmp {
+    fn new(id: &IntegrationId, config: &config::Value, event_tx: TxEventChannel) -> Result<Self> {
+        let config: SnmpConfig = config
+            .clone()
+            .try_deserialize()
+            .wrap_err("Failed to deserialize config of Snmp integration")?;
+
+        let mut points = Points::default();
+        for sensor in &config.sensors {
+            points.sensors.insert(sensor.oid.parse()?, sensor.clone());
+        }
+        for outlet in &config.outlets {
+            points.outlets.insert(outlet.oid.parse()?, outlet.clone());
+        }
+
+        let client = Client {
+            addr: format!("{}:{}", config.host, config.port.unwrap_or(161)),
+            timeout: Duration::from_millis(config.timeout_ms.unwrap_or(2000)),
+        };
+
+        Ok(Snmp {
+            id: id.clone(),
+            config,
+            event_tx,
+            points: Arc::new(points),
+            client,
+            refresh: Default::default(),
+            poll_handle: None,
+        })
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        let poller = Poller {
+            reporter: self.reporter(),
+            client: self.client.clone(),
+            community: self.community().to_string(),
+            poll_interval: Duration::from_secs(self.config.poll_secs.unwrap_or(30)),
+            refresh: self.refresh.clone(),
+        };
+
+        self.poll_handle = Some(tokio::spawn(poller.run()));
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        if let Some(poll_handle) = self.poll_handle.take() {
+            poll_handle.abort();
+        }
+
+        Ok(())
+    }
+
+    async fn set_integration_device_state(&mut self, device: &Device) -> Result<()> {
+        let Some(state) = device.get_controllable_state() else {
+            return Ok(());
+        };
+
+        let oid: Oid = device.id.to_string().parse()?;
+        let outlet = self
+            .points
+            .outlets
+            .get(&oid)
+            .ok_or_else(|| eyre!("SNMP outlet {} not found", oid))?;
+
+        let value = if state.power {
+            outlet.on_value()
+        } else {
+            outlet.off_value()
+        };
+
+        let client = self.client.clone();
+        let reporter = self.reporter();
+        let community = self
+            .config
+            .write_community
+            .clone()
+            .unwrap_or_else(|| self.community().to_string());
+
+        // PDUs may take a while to switch relays, don't hold up other
+        // integrations meanwhile
+        tokio::spawn(async move {
+            let varbinds = [(oid.clone(), Value::Integer(value))];
+
+            match client.request(&community, PDU_SET, &varbinds).await {
+                Ok(varbinds) => reporter.report(varbinds),
+                Err(e) => {
+                    warn!(integration_id = %reporter.id, "Switching outlet {} failed: {:?}", oid, e);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn poll_device(&mut self, device: &Device) -> Result<bool> {
+        let Ok(oid) = device.id.to_string().parse::<Oid>() else {
+            return Ok(false);
+        };
+
+        let known =
+            self.points.sensors.contains_key(&oid) || self.points.outlets.contains_key(&oid);
+        if known {
+            self.refresh.notify_one();
+        }
+
+        Ok(known)
+    }
+}
+
+impl Snmp {
+    fn community(&self) -> &str {
+        self.config.community.as_deref().unwrap_or("public")
+    }
+
+    fn reporter(&self) -> Reporter {
+        Reporter {
+            id: self.id.clone(),
+            event_tx: self.event_tx.clone(),
+            points: self.points.clone(),
+            managed: self.config.managed.clone().unwrap_or(ManageKind::Partial {
+                prev_change_committed: false,
+            }),
+        }
+    }
+}
+
+struct Reporter {
+    id: IntegrationId,
+    event_tx: TxEventChannel,
+    points: Arc<Points>,
+    managed: ManageKind,
+}
+
+impl Reporter {
+    fn report(&self, varbinds: Vec<(Oid, Value)>) {
+        for (oid, value) in varbinds {
+            let Some((name, data)) = self.device_data(&oid, value) else {
+                continue;
+            };
+
+            let device_id = DeviceId::new(&oid.to_string());
+            let device = Device::new(self.id.clone(), device_id, name, data);
+            self.event_tx.send(Message::RecvDeviceState { device });
+        }
+    }
+
+    fn device_data(&self, oid: &Oid, value: Value) -> Option<(String, DeviceData)> {
+        if value == Value::Missing {
+            warn!(integration_id = %self.id, "Agent has no value for OID {}", oid);
+            return None;
+        }
+
+        if let Some(outlet) = self.points.outlets.get(oid) {
+            let Value::Integer(value) = value else {
+                return None;
+            };
+
+            let data = DeviceData::Controllable(ControllableDevice::new(
+                None,
+                value == outlet.on_value(),
+                None,
+                None,
+                None,
+                Capabilities::default(),
+                self.managed.clone(),
+            ));
+
+            return Some((outlet.name.clone(), data));
+        }
+
+        let sensor = self.points.sensors.get(oid)?;
+
+        let data = match value.as_f64() {
+            Some(number) => SensorDevice::Number {
+                value: OrderedFloat(number * sensor.scale.unwrap_or(1.0)),
+                unit: sensor.unit.clone(),
+                raw: None,
+            },
+            None => SensorDevice::Text {
+                value: value.to_string(),
+            },
+        };
+
+        Some((sensor.name.clone(), DeviceData::Sensor(data)))
+    }
+}
+
+struct Poller {
+    reporter: Reporter,
+    client: Client,
+    community: String,
+    poll_interval: Duration,
+    refresh: Arc<Notify>,
+}
+
+impl Poller {
+    async fn run(self) {
+        let points = &self.reporter.points;
+        let oids: Vec<Oid> = points
+            .sensors
+            .keys()
+            .chain(points.outlets.keys())
+            .cloned()
+            .collect();
+
+        let mut reachable = true;
+
+        loop {
+            match self.poll(&oids).await {
+                Ok(()) => reachable = true,
+                // Only warn once while the agent stays unreachable
+                Err(e) if reachable => {
+                    reachable = false;
+                    warn!(integration_id = %self.reporter.id, "Polling {} failed: {:?}", self.client.addr, e);
+                }
+                Err(_) => {}
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(self.poll_interval) => {}
+                _ = self.refresh.notified() => {}
+            }
+        }
+    }
+
+    async fn poll(&self, oids: &[Oid]) -> Result<()> {
+        for chunk in oids.chunks(MAX_OIDS_PER_REQUEST) {
+            let varbinds: Vec<_> = chunk.iter().map(|oid| (oid.clone(), Value::Null)).collect();
+            let varbinds = self
+                .client
+                .request(&self.community, PDU_GET, &varbinds)
+                .await?;
+
+            self.reporter.report(varbinds);
+        }
+
+        Ok(())
+    }
+}
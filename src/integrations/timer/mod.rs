@@ -1,9 +1,15 @@
+use crate::core::schema::JsonSchema;
+use crate::db::{
+    actions::{db_get_helper_state, db_store_helper_state},
+    spawn_db_write,
+};
 use crate::types::{
     device::{Device, DeviceData, DeviceId, SensorDevice},
     event::{Message, TxEventChannel},
     integration::{Integration, IntegrationActionPayload, IntegrationId},
 };
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use color_eyre::Result;
 use eyre::Context;
 use serde::Deserialize;
@@ -11,7 +17,7 @@ use std::time::Duration;
 use tokio::task::JoinHandle;
 use tokio::time;
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
 pub struct TimerConfig {
     device_name: String,
 }
@@ -40,10 +46,21 @@ impl Integration for Timer {
     }
 
     async fn register(&mut self) -> Result<()> {
-        let device = mk_timer_device(&self.id, &self.config, false);
+        // Resume a timer that was running before a restart
+        let deadline: Option<DateTime<Utc>> = db_get_helper_state(&deadline_state_id(&self.id))
+            .await
+            .ok()
+            .flatten();
+        let remaining = deadline.and_then(|deadline| (deadline - Utc::now()).to_std().ok());
+
+        let device = mk_timer_device(&self.id, &self.config, remaining.is_some());
 
         self.event_tx.send(Message::RecvDeviceState { device });
 
+        if let Some(remaining) = remaining {
+            self.start_timer(remaining);
+        }
+
         Ok(())
     }
 
@@ -54,13 +71,37 @@ impl Integration for Timer {
 
         let payload = action.to_string();
         let timeout_ms: u64 = payload.parse()?;
+        let timeout = Duration::from_millis(timeout_ms);
+
+        let state_id = deadline_state_id(&self.id);
+        let deadline = Utc::now() + chrono::Duration::from_std(timeout)?;
+        spawn_db_write(async move {
+            db_store_helper_state(&state_id, &deadline).await.ok();
+        });
+
+        self.start_timer(timeout);
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        // The deadline stays in DB, so that the timer resumes once started again
+        if let Some(timer_task) = self.timer_task.take() {
+            timer_task.abort();
+        }
 
+        Ok(())
+    }
+}
+
+impl Timer {
+    /// Reports the timer as stopped once given duration has passed
+    fn start_timer(&mut self, duration: Duration) {
         let sender = self.event_tx.clone();
         let id = self.id.clone();
         let config = self.config.clone();
         let timer_task = tokio::spawn(async move {
-            let sleep_duration = Duration::from_millis(timeout_ms);
-            time::sleep(sleep_duration).await;
+            time::sleep(duration).await;
 
             let device = mk_timer_device(&id, &config, false);
             sender.send(Message::RecvDeviceState { device });
@@ -71,11 +112,14 @@ impl Integration for Timer {
         }
 
         self.timer_task = Some(timer_task);
-
-        Ok(())
     }
 }
 
+/// Helper state id of the deadline of a running timer in DB
+fn deadline_state_id(id: &IntegrationId) -> String {
+    format!("timers.{}", id)
+}
+
 fn mk_timer_device(id: &IntegrationId, config: &TimerConfig, value: bool) -> Device {
     let state = DeviceData::Sensor(SensorDevice::Boolean { value });
 
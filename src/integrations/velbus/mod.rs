@@ -0,0 +1,416 @@
+//! Velbus integration, talking to the bus through a USB or RS-232 interface,
+//! or through a TCP bridge such as velbus-tcp. Relay and dimmer channels
+//! become controllable devices, push buttons become sensors.
+
+mod protocol;
+mod serial;
+
+use crate::core::schema::JsonSchema;
+use crate::types::{
+    color::Capabilities,
+    device::{ControllableDevice, Device, DeviceData, DeviceId, ManageKind, SensorDevice},
+    event::{Message, TxEventChannel},
+    integration::{Integration, IntegrationActionPayload, IntegrationId},
+};
+use async_trait::async_trait;
+use color_eyre::Result;
+use eyre::{eyre, Context};
+use serde::Deserialize;
+use std::{collections::BTreeMap, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+};
+
+use protocol::*;
+
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VelbusChannelKind {
+    Relay,
+    Dimmer,
+    /// Push button, reported as a sensor that is on while pressed
+    Button,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct VelbusChannelConfig {
+    pub name: String,
+
+    /// Bus address of the module
+    pub address: u8,
+
+    /// Channel of the module, starting from 1
+    pub channel: u8,
+
+    pub kind: VelbusChannelKind,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct VelbusConfig {
+    /// Serial device of a USB or RS-232 interface, e.g. `/dev/ttyACM0`
+    port: Option<String>,
+
+    /// Address of a TCP bridge, e.g. `velbus.lan:6000`. Used instead of
+    /// `port`.
+    host: Option<String>,
+
+    /// Seconds to wait before reconnecting, defaults to 5
+    reconnect_secs: Option<u64>,
+
+    /// How relays and dimmers are managed, defaults to partially managed as
+    /// they're usually also switched from push buttons on the bus
+    managed: Option<ManageKind>,
+
+    channels: Vec<VelbusChannelConfig>,
+}
+
+type Reader = Box<dyn AsyncRead + Send + Unpin>;
+type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+fn channel_device_id(address: u8, channel: u8) -> DeviceId {
+    DeviceId::new(&format!("{:02x}-{}", address, channel))
+}
+
+/// Asks modules to report the state of given channels
+fn status_requests<'a>(channels: impl Iterator<Item = &'a VelbusChannelConfig>) -> Vec<Packet> {
+    let mut masks: BTreeMap<u8, u8> = BTreeMap::new();
+
+    for channel in channels.filter(|channel| channel.kind != VelbusChannelKind::Button) {
+        *masks.entry(channel.address).or_default() |= channel_mask(channel.channel);
+    }
+
+    masks
+        .into_iter()
+        .map(|(address, mask)| {
+            Packet::new(
+                PRIORITY_LOW,
+                address,
+                vec![COMMAND_MODULE_STATUS_REQUEST, mask],
+            )
+        })
+        .collect()
+}
+
+pub struct Velbus {
+    id: IntegrationId,
+    config: VelbusConfig,
+    event_tx: TxEventChannel,
+    channels: BTreeMap<DeviceId, VelbusChannelConfig>,
+    packet_tx: Option<UnboundedSender<Packet>>,
+    connection_handle: Option<JoinHandle<()>>,
+}
+
+#[async_trait]
+impl Integration for Velbus {
+    fn new(id: &IntegrationId, config: &config::Value, event_tx: TxEventChannel) -> Result<Self> {
+        let config: VelbusConfig = config
+            .clone()
+            .try_deserialize()
+            .wrap_err("Failed to deserialize config of Velbus integration")?;
+
+        if config.port.is_some() == config.host.is_some() {
+            return Err(eyre!("Expected Velbus config to set either port or host"));
+        }
+
+        let mut channels = BTreeMap::new();
+        for channel in &config.channels {
+            if !(1..=8).contains(&channel.channel) {
+                return Err(eyre!(
+                    "Invalid channel {} of Velbus device {}, expected 1-8",
+                    channel.channel,
+                    channel.name
+                ));
+            }
+
+            channels.insert(
+                channel_device_id(channel.address, channel.channel),
+                channel.clone(),
+            );
+        }
+
+        Ok(Velbus {
+            id: id.clone(),
+            config,
+            event_tx,
+            channels,
+            packet_tx: None,
+            connection_handle: None,
+        })
+    }
+
+    async fn register(&mut self) -> Result<()> {
+        // Relays and dimmers show up once they have reported their state,
+        // push buttons only report anything when pressed
+        let buttons = self
+            .channels
+            .iter()
+            .filter(|(_, channel)| channel.kind == VelbusChannelKind::Button);
+
+        for (device_id, channel) in buttons {
+            let device = Device::new(
+                self.id.clone(),
+                device_id.clone(),
+                channel.name.clone(),
+                DeviceData::Sensor(SensorDevice::Boolean { value: false }),
+            );
+
+            self.event_tx.send(Message::RecvDeviceState { device });
+        }
+
+        Ok(())
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        let (packet_tx, packet_rx) = unbounded_channel();
+        self.packet_tx = Some(packet_tx);
+
+        let connection = Connection {
+            id: self.id.clone(),
+            port: self.config.port.clone(),
+            host: self.config.host.clone(),
+            reconnect: Duration::from_secs(self.config.reconnect_secs.unwrap_or(5)),
+            managed: self.managed(),
+            event_tx: self.event_tx.clone(),
+            channels: self.channels.clone(),
+        };
+
+        self.connection_handle = Some(tokio::spawn(connection.run(packet_rx)));
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        self.packet_tx = None;
+
+        if let Some(connection_handle) = self.connection_handle.take() {
+            connection_handle.abort();
+        }
+
+        Ok(())
+    }
+
+    async fn set_integration_device_state(&mut self, device: &Device) -> Result<()> {
+        let Some(state) = device.get_controllable_state() else {
+            return Ok(());
+        };
+
+        let channel = self
+            .channels
+            .get(&device.id)
+            .ok_or_else(|| eyre!("Velbus channel {} not found", device.id))?;
+        let mask = channel_mask(channel.channel);
+
+        let data = match channel.kind {
+            VelbusChannelKind::Relay if state.power => vec![COMMAND_SWITCH_RELAY_ON, mask],
+            VelbusChannelKind::Relay => vec![COMMAND_SWITCH_RELAY_OFF, mask],
+            VelbusChannelKind::Dimmer => {
+                let value = match state.brightness {
+                    _ if !state.power => 0,
+                    Some(brightness) => (brightness.0 * 100.0).round().clamp(1.0, 100.0) as u8,
+                    None => 100,
+                };
+                let transition_secs = state
+                    .transition_ms
+                    .map_or(0, |ms| (ms / 1000).min(u16::MAX as u64) as u16);
+
+                let [time_hi, time_lo] = transition_secs.to_be_bytes();
+                vec![COMMAND_SET_DIMVALUE, mask, value, time_hi, time_lo]
+            }
+            VelbusChannelKind::Button => return Ok(()),
+        };
+
+        self.send(Packet::new(PRIORITY_HIGH, channel.address, data))
+    }
+
+    async fn run_integration_action(&mut self, _: &IntegrationActionPayload) -> Result<()> {
+        // do nothing
+        Ok(())
+    }
+
+    async fn poll_device(&mut self, device: &Device) -> Result<bool> {
+        let Some(channel) = self.channels.get(&device.id) else {
+            return Ok(false);
+        };
+
+        let requests = status_requests(std::iter::once(channel));
+        if requests.is_empty() {
+            return Ok(false);
+        }
+
+        for request in requests {
+            self.send(request)?;
+        }
+
+        Ok(true)
+    }
+}
+
+impl Velbus {
+    fn managed(&self) -> ManageKind {
+        self.config.managed.clone().unwrap_or(ManageKind::Partial {
+            prev_change_committed: false,
+        })
+    }
+
+    fn send(&self, packet: Packet) -> Result<()> {
+        self.packet_tx
+            .as_ref()
+            .expect("Expected self.packet_tx to be set in start phase")
+            .send(packet)
+            .map_err(|_| eyre!("Connection to Velbus has been closed"))
+    }
+}
+
+fn mk_controllable(power: bool, brightness: Option<f32>, managed: &ManageKind) -> DeviceData {
+    DeviceData::Controllable(ControllableDevice::new(
+        None,
+        power,
+        brightness,
+        None,
+        None,
+        Capabilities::default(),
+        managed.clone(),
+    ))
+}
+
+struct Connection {
+    id: IntegrationId,
+    port: Option<String>,
+    host: Option<String>,
+    reconnect: Duration,
+    managed: ManageKind,
+    event_tx: TxEventChannel,
+    channels: BTreeMap<DeviceId, VelbusChannelConfig>,
+}
+
+impl Connection {
+    fn describe(&self) -> &str {
+        self.port
+            .as_deref()
+            .or(self.host.as_deref())
+            .unwrap_or_default()
+    }
+
+    async fn run(self, mut packet_rx: UnboundedReceiver<Packet>) {
+        loop {
+            match self.connect(&mut packet_rx).await {
+                Ok(()) => {
+                    warn!(integration_id = %self.id, "Connection to {} closed", self.describe());
+                }
+                Err(e) => {
+                    warn!(integration_id = %self.id, "Connection to {} failed: {:?}", self.describe(), e);
+                }
+            }
+
+            tokio::time::sleep(self.reconnect).await;
+        }
+    }
+
+    async fn open(&self) -> Result<(Reader, Writer)> {
+        if let Some(port) = &self.port {
+            let (reader, writer) = serial::open_serial(port)?;
+            Ok((Box::new(reader), Box::new(writer)))
+        } else {
+            let host = self.host.as_deref().unwrap_or_default();
+            let (reader, writer) = TcpStream::connect(host).await?.into_split();
+            Ok((Box::new(reader), Box::new(writer)))
+        }
+    }
+
+    async fn connect(&self, packet_rx: &mut UnboundedReceiver<Packet>) -> Result<()> {
+        let (mut reader, mut writer) = self.open().await?;
+
+        info!(integration_id = %self.id, "Connected to {}", self.describe());
+
+        for request in status_requests(self.channels.values()) {
+            writer.write_all(&request.encode()).await?;
+        }
+        writer.flush().await?;
+
+        let mut buf = vec![];
+        let mut chunk = [0; 1024];
+
+        loop {
+            tokio::select! {
+                n = reader.read(&mut chunk) => {
+                    let n = n?;
+                    if n == 0 {
+                        return Ok(());
+                    }
+
+                    buf.extend_from_slice(&chunk[..n]);
+
+                    for packet in take_packets(&mut buf) {
+                        if let Some(event) = decode(&packet) {
+                            self.handle_event(event);
+                        }
+                    }
+                }
+                packet = packet_rx.recv() => {
+                    let Some(packet) = packet else {
+                        return Ok(());
+                    };
+
+                    writer.write_all(&packet.encode()).await?;
+                    writer.flush().await?;
+                }
+            }
+        }
+    }
+
+    fn handle_event(&self, event: BusEvent) {
+        let states = match event {
+            BusEvent::Relay {
+                address,
+                channel,
+                on,
+            } => vec![(
+                address,
+                channel,
+                VelbusChannelKind::Relay,
+                mk_controllable(on, None, &self.managed),
+            )],
+            BusEvent::Dimmer {
+                address,
+                channel,
+                value,
+            } => vec![(
+                address,
+                channel,
+                VelbusChannelKind::Dimmer,
+                mk_controllable(value > 0, Some(value as f32 / 100.0), &self.managed),
+            )],
+            BusEvent::Buttons {
+                address,
+                pressed,
+                released,
+            } => (1..=8)
+                .filter(|channel| (pressed | released) & channel_mask(*channel) != 0)
+                .map(|channel| {
+                    let value = pressed & channel_mask(channel) != 0;
+                    (
+                        address,
+                        channel,
+                        VelbusChannelKind::Button,
+                        DeviceData::Sensor(SensorDevice::Boolean { value }),
+                    )
+                })
+                .collect(),
+        };
+
+        for (address, channel, kind, data) in states {
+            let device_id = channel_device_id(address, channel);
+
+            // Other modules on the bus aren't of interest
+            let Some(config) = self.channels.get(&device_id).filter(|c| c.kind == kind) else {
+                continue;
+            };
+
+            let device = Device::new(self.id.clone(), device_id, config.name.clone(), data);
+            self.event_tx.send(Message::RecvDeviceState { device });
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Framing and decoding of Velbus packets:
+//! `STX, priority, address, RTR | size, data.., checksum, ETX`
+
+pub const STX: u8 = 0x0f;
+pub const ETX: u8 = 0x04;
+
+pub const PRIORITY_HIGH: u8 = 0xf8;
+pub const PRIORITY_LOW: u8 = 0xfb;
+
+const RTR: u8 = 0x40;
+const MAX_DATA_SIZE: usize = 8;
+
+/// STX, priority, address and size, followed by data
+const HEADER_SIZE: usize = 4;
+/// Checksum and ETX
+const TRAILER_SIZE: usize = 2;
+
+pub const COMMAND_PUSH_BUTTON_STATUS: u8 = 0x00;
+pub const COMMAND_SWITCH_RELAY_OFF: u8 = 0x01;
+pub const COMMAND_SWITCH_RELAY_ON: u8 = 0x02;
+pub const COMMAND_SET_DIMVALUE: u8 = 0x07;
+pub const COMMAND_DIMMER_STATUS: u8 = 0xb8;
+pub const COMMAND_MODULE_STATUS_REQUEST: u8 = 0xfa;
+pub const COMMAND_RELAY_STATUS: u8 = 0xfb;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Packet {
+    pub priority: u8,
+    pub address: u8,
+    pub rtr: bool,
+    pub data: Vec<u8>,
+}
+
+impl Packet {
+    pub fn new(priority: u8, address: u8, data: Vec<u8>) -> Packet {
+        Packet {
+            priority,
+            address,
+            rtr: false,
+            data,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let size = self.data.len() as u8 | if self.rtr { RTR } else { 0 };
+
+        let mut bytes = vec![STX, self.priority, self.address, size];
+        bytes.extend_from_slice(&self.data);
+        bytes.push(checksum(&bytes));
+        bytes.push(ETX);
+
+        bytes
+    }
+}
+
+/// Two's complement of the sum of all preceding bytes
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+        .wrapping_neg()
+}
+
+/// Takes complete packets from the start of `buf`, leaving any partially
+/// received packet in place. Bytes that don't form a valid packet are
+/// skipped, so that we can resynchronize after joining the bus midway.
+pub fn take_packets(buf: &mut Vec<u8>) -> Vec<Packet> {
+    let mut packets = vec![];
+    let mut pos = 0;
+
+    while let Some(start) = buf[pos..].iter().position(|b| *b == STX).map(|i| pos + i) {
+        let Some(header) = buf.get(start..start + HEADER_SIZE) else {
+            pos = start;
+            break;
+        };
+
+        let size = (header[3] & 0x0f) as usize;
+        let end = start + HEADER_SIZE + size + TRAILER_SIZE;
+        let valid_header =
+            matches!(header[1], PRIORITY_HIGH | PRIORITY_LOW) && size <= MAX_DATA_SIZE;
+
+        if valid_header && buf.len() < end {
+            pos = start;
+            break;
+        }
+
+        let valid =
+            valid_header && buf[end - 1] == ETX && buf[end - 2] == checksum(&buf[start..end - 2]);
+
+        if !valid {
+            pos = start + 1;
+            continue;
+        }
+
+        packets.push(Packet {
+            priority: header[1],
+            address: header[2],
+            rtr: header[3] & RTR != 0,
+            data: buf[start + HEADER_SIZE..end - TRAILER_SIZE].to_vec(),
+        });
+        pos = end;
+    }
+
+    // Nothing before pos can start a packet anymore
+    if !buf[pos..].contains(&STX) {
+        pos = buf.len();
+    }
+    buf.drain(..pos);
+
+    packets
+}
+
+/// Bit of given channel (1-8) in channel bitmasks
+pub fn channel_mask(channel: u8) -> u8 {
+    1 << (channel - 1)
+}
+
+fn mask_channel(mask: u8) -> Option<u8> {
+    mask.is_power_of_two()
+        .then(|| mask.trailing_zeros() as u8 + 1)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BusEvent {
+    Relay {
+        address: u8,
+        channel: u8,
+        on: bool,
+    },
+    Dimmer {
+        address: u8,
+        channel: u8,
+        value: u8,
+    },
+    Buttons {
+        address: u8,
+        pressed: u8,
+        released: u8,
+    },
+}
+
+/// Decodes packets reporting state of relays, dimmers and push buttons
+pub fn decode(packet: &Packet) -> Option<BusEvent> {
+    let address = packet.address;
+
+    match packet.data.as_slice() {
+        [COMMAND_PUSH_BUTTON_STATUS, pressed, released, ..] => Some(BusEvent::Buttons {
+            address,
+            pressed: *pressed,
+            released: *released,
+        }),
+        [COMMAND_RELAY_STATUS, mask, _, status, ..] => Some(BusEvent::Relay {
+            address,
+            channel: mask_channel(*mask)?,
+            on: *status != 0,
+        }),
+        [COMMAND_DIMMER_STATUS, mask, _, value, ..] => Some(BusEvent::Dimmer {
+            address,
+            channel: mask_channel(*mask)?,
+            value: *value,
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packets() {
+        let switch_on = Packet::new(PRIORITY_HIGH, 0x21, vec![COMMAND_SWITCH_RELAY_ON, 0x01]);
+        let encoded = switch_on.encode();
+        assert_eq!(encoded, [0x0f, 0xf8, 0x21, 0x02, 0x02, 0x01, 0xd3, 0x04]);
+
+        let relay_status = Packet::new(
+            PRIORITY_LOW,
+            0x21,
+            vec![
+                COMMAND_RELAY_STATUS,
+                0x04,
+                0x00,
+                0x01,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+            ],
+        );
+
+        // Garbage, a complete packet and the start of another one
+        let mut buf = vec![0x55, 0x0f, 0x00];
+        buf.extend(relay_status.encode());
+        buf.extend(&encoded[..5]);
+
+        assert_eq!(take_packets(&mut buf), vec![relay_status.clone()]);
+        assert_eq!(buf, encoded[..5]);
+
+        buf.extend(&encoded[5..]);
+        assert_eq!(take_packets(&mut buf), vec![switch_on]);
+        assert!(buf.is_empty());
+
+        assert_eq!(
+            decode(&relay_status),
+            Some(BusEvent::Relay {
+                address: 0x21,
+                channel: 3,
+                on: true
+            })
+        );
+    }
+}
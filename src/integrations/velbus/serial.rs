@@ -0,0 +1,49 @@
+use color_eyre::Result;
+use tokio::fs::File;
+
+/// Baud rate of the Velbus serial interfaces
+#[cfg(unix)]
+const BAUD_RATE: libc::speed_t = libc::B38400;
+
+/// Opens a serial device in raw mode, returning separate handles for reading
+/// and writing so that both can be in flight at once
+#[cfg(unix)]
+pub fn open_serial(path: &str) -> Result<(File, File)> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)?;
+
+    let fd = file.as_raw_fd();
+
+    // SAFETY: fd is a valid open file descriptor for as long as `file` lives,
+    // and termios is fully initialized by tcgetattr before use
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+
+        if libc::tcgetattr(fd, &mut termios) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        libc::cfmakeraw(&mut termios);
+        libc::cfsetspeed(&mut termios, BAUD_RATE);
+
+        if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+
+    let writer = file.try_clone()?;
+
+    Ok((File::from_std(file), File::from_std(writer)))
+}
+
+#[cfg(not(unix))]
+pub fn open_serial(path: &str) -> Result<(File, File)> {
+    Err(eyre::eyre!(
+        "Serial port {} is not supported on this platform, use a TCP bridge instead",
+        path
+    ))
+}
@@ -0,0 +1,347 @@
+//! Victron integration, reading the system overview of a GX device (Cerbo
+//! GX, Venus GX or a Raspberry Pi running Venus OS) from its MQTT broker.
+//! Battery state of charge and grid, PV and battery power are reported as
+//! sensors.
+
+use crate::core::schema::JsonSchema;
+use crate::types::{
+    device::{Device, DeviceData, DeviceId, SensorDevice},
+    event::{Message, TxEventChannel},
+    integration::{Integration, IntegrationId},
+};
+use async_trait::async_trait;
+use color_eyre::Result;
+use eyre::Context;
+use ordered_float::OrderedFloat;
+use rand::{distributions::Alphanumeric, Rng};
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use serde::Deserialize;
+use std::{collections::BTreeMap, time::Duration};
+use tokio::task::JoinHandle;
+
+/// Venus OS stops publishing unless a keepalive is received every 60 seconds
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+const PHASES: [&str; 3] = ["L1", "L2", "L3"];
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct VictronConfig {
+    /// Address of the GX device, which runs an MQTT broker when enabled in
+    /// Settings > Services
+    host: String,
+
+    /// Defaults to 1883
+    port: Option<u16>,
+
+    /// VRM portal id of the GX device, discovered from its broker if not set
+    portal_id: Option<String>,
+}
+
+/// Reported sensors, computed from paths of the `system` service
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Reading {
+    BatterySoc,
+    BatteryPower,
+    BatteryVoltage,
+    GridPower,
+    PvPower,
+    Consumption,
+}
+
+impl Reading {
+    const ALL: [Reading; 6] = [
+        Reading::BatterySoc,
+        Reading::BatteryPower,
+        Reading::BatteryVoltage,
+        Reading::GridPower,
+        Reading::PvPower,
+        Reading::Consumption,
+    ];
+
+    fn id(&self) -> &'static str {
+        match self {
+            Reading::BatterySoc => "battery_soc",
+            Reading::BatteryPower => "battery_power",
+            Reading::BatteryVoltage => "battery_voltage",
+            Reading::GridPower => "grid_power",
+            Reading::PvPower => "pv_power",
+            Reading::Consumption => "consumption",
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Reading::BatterySoc => "Battery state of charge",
+            Reading::BatteryPower => "Battery power",
+            Reading::BatteryVoltage => "Battery voltage",
+            Reading::GridPower => "Grid power",
+            Reading::PvPower => "PV power",
+            Reading::Consumption => "Consumption",
+        }
+    }
+
+    fn unit(&self) -> &'static str {
+        match self {
+            Reading::BatterySoc => "%",
+            Reading::BatteryVoltage => "V",
+            _ => "W",
+        }
+    }
+
+    /// Paths whose values are summed up, e.g. per phase power or PV
+    /// connected to both MPPTs and AC inputs
+    fn paths(&self) -> Vec<String> {
+        let per_phase = |prefix: &str| {
+            PHASES
+                .iter()
+                .map(|phase| format!("{}/{}/Power", prefix, phase))
+                .collect::<Vec<_>>()
+        };
+
+        match self {
+            Reading::BatterySoc => vec!["Dc/Battery/Soc".to_string()],
+            Reading::BatteryPower => vec!["Dc/Battery/Power".to_string()],
+            Reading::BatteryVoltage => vec!["Dc/Battery/Voltage".to_string()],
+            Reading::GridPower => per_phase("Ac/Grid"),
+            Reading::PvPower => [
+                vec!["Dc/Pv/Power".to_string()],
+                per_phase("Ac/PvOnGrid"),
+                per_phase("Ac/PvOnOutput"),
+            ]
+            .concat(),
+            Reading::Consumption => per_phase("Ac/Consumption"),
+        }
+    }
+
+    /// Sum of the values of its paths, or None if none are known
+    fn compute(&self, values: &BTreeMap<String, f64>) -> Option<f64> {
+        self.paths()
+            .iter()
+            .filter_map(|path| values.get(path))
+            .fold(None, |sum, value| Some(sum.unwrap_or(0.0) + value))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ValuePayload {
+    value: Option<serde_json::Value>,
+}
+
+/// Splits `N/<portal id>/system/0/<path>` topics into portal id and path
+fn parse_topic(topic: &str) -> Option<(&str, &str)> {
+    let rest = topic.strip_prefix("N/")?;
+    let (portal_id, rest) = rest.split_once('/')?;
+    let path = rest.strip_prefix("system/0/")?;
+
+    Some((portal_id, path))
+}
+
+pub struct Victron {
+    id: IntegrationId,
+    config: VictronConfig,
+    event_tx: TxEventChannel,
+    eventloop_handle: Option<JoinHandle<()>>,
+}
+
+#[async_trait]
+impl Integration for Victron {
+    fn new(id: &IntegrationId, config: &config::Value, event_tx: TxEventChannel) -> Result<Self> {
+        let config = config
+            .clone()
+            .try_deserialize()
+            .wrap_err("Failed to deserialize config of Victron integration")?;
+
+        Ok(Victron {
+            id: id.clone(),
+            config,
+            event_tx,
+            eventloop_handle: None,
+        })
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        let random_string: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(8)
+            .map(char::from)
+            .collect();
+
+        let mut options = MqttOptions::new(
+            format!("{}-{}", self.id, random_string),
+            self.config.host.clone(),
+            self.config.port.unwrap_or(1883),
+        );
+        options.set_keep_alive(Duration::from_secs(5));
+
+        let (client, mut eventloop) = AsyncClient::new(options, 10);
+
+        let mut gx = Gx {
+            id: self.id.clone(),
+            event_tx: self.event_tx.clone(),
+            portal_id: self.config.portal_id.clone(),
+            values: BTreeMap::new(),
+            last_readings: BTreeMap::new(),
+        };
+
+        let eventloop_handle = tokio::spawn(async move {
+            let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    notification = eventloop.poll() => {
+                        let res = match notification {
+                            Ok(rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_))) => {
+                                let portal_id = gx.portal_id.as_deref().unwrap_or("+");
+                                client
+                                    .try_subscribe(format!("N/{}/system/0/#", portal_id), QoS::AtMostOnce)
+                                    .and_then(|_| gx.send_keepalive(&client))
+                                    .map_err(Into::into)
+                            }
+                            Ok(rumqttc::Event::Incoming(rumqttc::Packet::Publish(msg))) => {
+                                let discovered = gx.portal_id.is_none();
+                                let res = gx.handle_publish(&msg.topic, &msg.payload);
+
+                                // Start receiving everything else as soon as the
+                                // portal id is known
+                                if discovered && gx.portal_id.is_some() {
+                                    gx.send_keepalive(&client).ok();
+                                }
+
+                                res
+                            }
+                            Ok(_) => Ok(()),
+                            Err(e) => Err(e.into()),
+                        };
+
+                        if let Err(e) = res {
+                            error!(integration_id = %gx.id, "Victron error: {:?}", e);
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                        }
+                    }
+                    _ = keepalive.tick() => {
+                        gx.send_keepalive(&client).ok();
+                    }
+                }
+            }
+        });
+
+        self.eventloop_handle = Some(eventloop_handle);
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        if let Some(eventloop_handle) = self.eventloop_handle.take() {
+            eventloop_handle.abort();
+        }
+
+        Ok(())
+    }
+}
+
+struct Gx {
+    id: IntegrationId,
+    event_tx: TxEventChannel,
+    portal_id: Option<String>,
+
+    /// Latest values by path
+    values: BTreeMap<String, f64>,
+
+    /// Power readings change every second, only report changes
+    last_readings: BTreeMap<Reading, f64>,
+}
+
+impl Gx {
+    fn send_keepalive(&self, client: &AsyncClient) -> Result<(), rumqttc::ClientError> {
+        let Some(portal_id) = &self.portal_id else {
+            return Ok(());
+        };
+
+        client.try_publish(
+            format!("R/{}/keepalive", portal_id),
+            QoS::AtMostOnce,
+            false,
+            "",
+        )
+    }
+
+    fn handle_publish(&mut self, topic: &str, payload: &[u8]) -> Result<()> {
+        let Some((portal_id, path)) = parse_topic(topic) else {
+            return Ok(());
+        };
+
+        if self.portal_id.is_none() {
+            info!(integration_id = %self.id, "Found GX device with portal id {}", portal_id);
+            self.portal_id = Some(portal_id.to_string());
+        }
+
+        let payload: ValuePayload = serde_json::from_slice(payload)?;
+
+        match payload.value.as_ref().and_then(|value| value.as_f64()) {
+            Some(value) => self.values.insert(path.to_string(), value),
+            None => self.values.remove(path),
+        };
+
+        self.report();
+
+        Ok(())
+    }
+
+    fn report(&mut self) {
+        for reading in Reading::ALL {
+            let Some(value) = reading.compute(&self.values) else {
+                continue;
+            };
+
+            // Avoid flooding state updates with tiny changes
+            let value = (value * 10.0).round() / 10.0;
+
+            if self.last_readings.insert(reading, value) == Some(value) {
+                continue;
+            }
+
+            let device = Device::new(
+                self.id.clone(),
+                DeviceId::new(reading.id()),
+                reading.name().to_string(),
+                DeviceData::Sensor(SensorDevice::Number {
+                    value: OrderedFloat(value),
+                    unit: Some(reading.unit().to_string()),
+                    raw: None,
+                }),
+            );
+
+            self.event_tx.send(Message::RecvDeviceState { device });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readings() {
+        assert_eq!(
+            parse_topic("N/c0619ab12345/system/0/Ac/Grid/L1/Power"),
+            Some(("c0619ab12345", "Ac/Grid/L1/Power"))
+        );
+        assert_eq!(parse_topic("N/c0619ab12345/battery/512/Soc"), None);
+
+        let values: BTreeMap<String, f64> = [
+            ("Ac/Grid/L1/Power", 120.0),
+            ("Ac/Grid/L2/Power", -40.0),
+            ("Dc/Pv/Power", 1500.0),
+            ("Ac/PvOnGrid/L1/Power", 500.0),
+            ("Dc/Battery/Soc", 87.0),
+        ]
+        .into_iter()
+        .map(|(path, value)| (path.to_string(), value))
+        .collect();
+
+        assert_eq!(Reading::GridPower.compute(&values), Some(80.0));
+        assert_eq!(Reading::PvPower.compute(&values), Some(2000.0));
+        assert_eq!(Reading::BatterySoc.compute(&values), Some(87.0));
+        assert_eq!(Reading::Consumption.compute(&values), None);
+    }
+}
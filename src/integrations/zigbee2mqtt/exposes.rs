@@ -0,0 +1,550 @@
+//! Maps the exposes of Zigbee2MQTT device definitions to homectl devices,
+//! and device state payloads to and from homectl device state.
+
+use crate::types::{
+    color::{Capabilities, ColorMode, DeviceColor},
+    device::{
+        ControllableDevice, ControllableState, DeviceData, DeviceId, ManageKind, SensorDevice,
+    },
+};
+use ordered_float::OrderedFloat;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+/// Expose access flag meaning the property is published in state payloads
+const ACCESS_STATE: u8 = 0b001;
+
+/// Properties that change constantly without being of interest
+const IGNORED_PROPERTIES: &[&str] = &["linkquality"];
+
+/// Entry of the `bridge/devices` list
+#[derive(Clone, Debug, Deserialize)]
+pub struct BridgeDevice {
+    pub friendly_name: String,
+
+    #[serde(rename = "type")]
+    pub device_type: String,
+
+    pub definition: Option<Definition>,
+
+    #[serde(default)]
+    pub disabled: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Definition {
+    #[serde(default)]
+    pub exposes: Vec<Expose>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Expose {
+    #[serde(rename = "type")]
+    pub kind: String,
+
+    pub name: Option<String>,
+    pub property: Option<String>,
+
+    #[serde(default)]
+    pub access: u8,
+
+    pub unit: Option<String>,
+    pub value_on: Option<Value>,
+    pub value_off: Option<Value>,
+    pub value_min: Option<f64>,
+    pub value_max: Option<f64>,
+    pub length_max: Option<usize>,
+
+    #[serde(default)]
+    pub features: Vec<Expose>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LightModel {
+    state_property: String,
+    value_on: Value,
+    value_off: Value,
+
+    /// Brightness is sent as 0 - brightness_max, usually 254
+    brightness_max: Option<f64>,
+
+    capabilities: Capabilities,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum SensorKind {
+    Binary { value_on: Value },
+    Numeric { unit: Option<String> },
+    Text,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct SensorModel {
+    property: String,
+    kind: SensorKind,
+}
+
+/// What homectl makes of a Zigbee2MQTT device: a light or switch, and
+/// sensors for the other properties it publishes
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceModel {
+    pub friendly_name: String,
+    pub light: Option<LightModel>,
+    sensors: Vec<SensorModel>,
+}
+
+fn mireds_to_kelvin(mireds: f64) -> u16 {
+    (1_000_000.0 / mireds.max(1.0)).round() as u16
+}
+
+fn kelvin_to_mireds(kelvin: u64) -> u64 {
+    (1_000_000.0 / (kelvin.max(1) as f64)).round() as u64
+}
+
+/// Parses `#rrggbb` colors as used by gradients
+fn hex_to_color(hex: &str) -> Option<DeviceColor> {
+    let hex = hex.strip_prefix('#')?;
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+
+    Some(DeviceColor::new_from_rgb(
+        channel(0)?,
+        channel(2)?,
+        channel(4)?,
+    ))
+}
+
+fn color_to_hex(color: &DeviceColor) -> Option<String> {
+    match color.to_device_preferred_mode(&Capabilities::singleton(ColorMode::Rgb))? {
+        DeviceColor::Rgb(rgb) => Some(format!("#{:02x}{:02x}{:02x}", rgb.r, rgb.g, rgb.b)),
+        _ => None,
+    }
+}
+
+fn light_model(expose: &Expose) -> Option<LightModel> {
+    let mut light = None;
+    let mut brightness_max = None;
+    let mut capabilities = Capabilities::default();
+
+    for feature in &expose.features {
+        match (feature.kind.as_str(), feature.name.as_deref()) {
+            ("binary", Some("state")) => {
+                light = Some((
+                    feature.property.clone()?,
+                    feature.value_on.clone().unwrap_or(json!("ON")),
+                    feature.value_off.clone().unwrap_or(json!("OFF")),
+                ));
+            }
+            ("numeric", Some("brightness")) => {
+                brightness_max = Some(feature.value_max.unwrap_or(254.0));
+            }
+            ("numeric", Some("color_temp")) => {
+                // Fewer mireds is cooler, i.e. more kelvin
+                let coolest = mireds_to_kelvin(feature.value_min.unwrap_or(153.0));
+                let warmest = mireds_to_kelvin(feature.value_max.unwrap_or(500.0));
+                capabilities.ct = Some(warmest..coolest);
+            }
+            ("composite", Some("color_xy")) => capabilities.xy = true,
+            ("composite", Some("color_hs")) => capabilities.hs = true,
+            _ => {}
+        }
+    }
+
+    let (state_property, value_on, value_off) = light?;
+
+    Some(LightModel {
+        state_property,
+        value_on,
+        value_off,
+        brightness_max,
+        capabilities,
+    })
+}
+
+fn sensor_model(expose: &Expose) -> Option<SensorModel> {
+    let property = expose.property.clone()?;
+
+    if expose.access & ACCESS_STATE == 0 || IGNORED_PROPERTIES.contains(&property.as_str()) {
+        return None;
+    }
+
+    let kind = match expose.kind.as_str() {
+        "binary" => SensorKind::Binary {
+            value_on: expose.value_on.clone().unwrap_or(json!(true)),
+        },
+        "numeric" => SensorKind::Numeric {
+            unit: expose.unit.clone(),
+        },
+        "enum" | "text" => SensorKind::Text,
+        _ => return None,
+    };
+
+    Some(SensorModel { property, kind })
+}
+
+impl DeviceModel {
+    /// Returns None for the coordinator and devices that aren't supported
+    /// by Zigbee2MQTT
+    pub fn new(device: &BridgeDevice) -> Option<DeviceModel> {
+        if device.device_type == "Coordinator" || device.disabled {
+            return None;
+        }
+
+        let exposes = &device.definition.as_ref()?.exposes;
+
+        // Devices with several endpoints expose several lights or switches,
+        // only the first one is controlled
+        let mut light = exposes
+            .iter()
+            .filter(|expose| matches!(expose.kind.as_str(), "light" | "switch"))
+            .find_map(light_model);
+
+        // Gradient lights expose their gradient next to the light itself
+        if let Some(light) = &mut light {
+            light.capabilities.segments = exposes
+                .iter()
+                .find(|expose| expose.kind == "list" && expose.name.as_deref() == Some("gradient"))
+                .map(|expose| expose.length_max.unwrap_or(5));
+        }
+
+        let sensors = exposes.iter().filter_map(sensor_model).collect();
+
+        Some(DeviceModel {
+            friendly_name: device.friendly_name.clone(),
+            light,
+            sensors,
+        })
+    }
+
+    fn sensor_device_id(&self, property: &str) -> DeviceId {
+        DeviceId::new(&format!("{}:{}", self.friendly_name, property))
+    }
+
+    /// Ids of all devices this model results in
+    pub fn device_ids(&self) -> Vec<DeviceId> {
+        let light = self
+            .light
+            .as_ref()
+            .map(|_| DeviceId::new(&self.friendly_name));
+
+        let sensors = self
+            .sensors
+            .iter()
+            .map(|sensor| self.sensor_device_id(&sensor.property));
+
+        light
+            .into_iter()
+            .chain(sensors)
+            .chain(std::iter::once(self.sensor_device_id("update")))
+            .collect()
+    }
+
+    /// Maps a state payload to device ids, names and data
+    pub fn map_state(
+        &self,
+        payload: &Map<String, Value>,
+        managed: &ManageKind,
+    ) -> Vec<(DeviceId, String, DeviceData)> {
+        let mut devices = vec![];
+
+        if let Some(light) = &self.light {
+            if let Some(data) = light.map_state(payload, managed) {
+                devices.push((
+                    DeviceId::new(&self.friendly_name),
+                    self.friendly_name.clone(),
+                    data,
+                ));
+            }
+        }
+
+        for sensor in &self.sensors {
+            let Some(value) = payload.get(&sensor.property).filter(|v| !v.is_null()) else {
+                continue;
+            };
+
+            let data = match &sensor.kind {
+                SensorKind::Binary { value_on } => SensorDevice::Boolean {
+                    value: value == value_on,
+                },
+                SensorKind::Numeric { unit } => {
+                    let Some(value) = value.as_f64() else {
+                        continue;
+                    };
+
+                    SensorDevice::Number {
+                        value: OrderedFloat(value),
+                        unit: unit.clone(),
+                        raw: None,
+                    }
+                }
+                SensorKind::Text => SensorDevice::Text {
+                    value: value
+                        .as_str()
+                        .map_or_else(|| value.to_string(), str::to_string),
+                },
+            };
+
+            devices.push((
+                self.sensor_device_id(&sensor.property),
+                format!(
+                    "{} {}",
+                    self.friendly_name,
+                    sensor.property.replace('_', " ")
+                ),
+                DeviceData::Sensor(data),
+            ));
+        }
+
+        // OTA update state, e.g. "idle", "available" or "updating"
+        if let Some(update) = payload
+            .get("update")
+            .and_then(|update| update.get("state"))
+            .and_then(Value::as_str)
+        {
+            devices.push((
+                self.sensor_device_id("update"),
+                format!("{} update", self.friendly_name),
+                DeviceData::Sensor(SensorDevice::Text {
+                    value: update.to_string(),
+                }),
+            ));
+        }
+
+        devices
+    }
+}
+
+impl LightModel {
+    fn map_state(&self, payload: &Map<String, Value>, managed: &ManageKind) -> Option<DeviceData> {
+        let power = *payload.get(&self.state_property)? == self.value_on;
+
+        let brightness = self.brightness_max.and_then(|max| {
+            let brightness = payload.get("brightness")?.as_f64()?;
+            Some((brightness / max) as f32)
+        });
+
+        let color = payload.get("color");
+        let color_component = |name: &str| color?.get(name)?.as_f64();
+
+        let color = match payload.get("color_mode").and_then(Value::as_str) {
+            Some("color_temp") => payload
+                .get("color_temp")
+                .and_then(Value::as_f64)
+                .map(|mireds| DeviceColor::new_from_ct(mireds_to_kelvin(mireds))),
+            Some("hs") => color_component("hue")
+                .zip(color_component("saturation"))
+                .map(|(hue, saturation)| {
+                    DeviceColor::new_from_hs(hue as u16, (saturation / 100.0) as f32)
+                }),
+            _ => color_component("x")
+                .zip(color_component("y"))
+                .map(|(x, y)| DeviceColor::new_from_xy(x as f32, y as f32)),
+        };
+
+        // Reported in the same color mode as expected segments are converted
+        // to, so that they can be compared
+        let segments = self
+            .capabilities
+            .segments
+            .and_then(|_| payload.get("gradient")?.as_array())
+            .map(|gradient| {
+                gradient
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .filter_map(hex_to_color)
+                    .filter_map(|color| color.to_device_preferred_mode(&self.capabilities))
+                    .collect()
+            });
+
+        let mut controllable = ControllableDevice::new(
+            None,
+            power,
+            brightness,
+            color,
+            None,
+            self.capabilities.clone(),
+            managed.clone(),
+        );
+        controllable.state.segments = segments;
+
+        Some(DeviceData::Controllable(controllable))
+    }
+
+    /// Payload for the `set` topic of the device
+    pub fn set_payload(&self, state: &ControllableState) -> Value {
+        let mut payload = Map::new();
+
+        let value = if state.power {
+            &self.value_on
+        } else {
+            &self.value_off
+        };
+        payload.insert(self.state_property.clone(), value.clone());
+
+        if let (Some(max), Some(brightness)) = (self.brightness_max, state.brightness) {
+            payload.insert(
+                "brightness".to_string(),
+                json!((*brightness as f64 * max).round()),
+            );
+        }
+
+        match &state.color {
+            Some(DeviceColor::Xy(xy)) => {
+                payload.insert("color".to_string(), json!({ "x": *xy.x, "y": *xy.y }));
+            }
+            Some(DeviceColor::Hs(hs)) => {
+                payload.insert(
+                    "color".to_string(),
+                    json!({ "hue": hs.h, "saturation": (*hs.s * 100.0).round() }),
+                );
+            }
+            Some(DeviceColor::Rgb(rgb)) => {
+                payload.insert(
+                    "color".to_string(),
+                    json!({ "r": rgb.r, "g": rgb.g, "b": rgb.b }),
+                );
+            }
+            Some(DeviceColor::Ct(ct)) => {
+                payload.insert("color_temp".to_string(), json!(kelvin_to_mireds(ct.ct)));
+            }
+            // Never exposed by Zigbee2MQTT, so colors are converted away from
+            // these before reaching here
+            Some(DeviceColor::Rgbw(_)) | Some(DeviceColor::Rgbww(_)) | None => {}
+        }
+
+        if let Some(segments) = &state.segments {
+            let gradient: Vec<String> = segments.iter().filter_map(color_to_hex).collect();
+            payload.insert("gradient".to_string(), json!(gradient));
+        }
+
+        if let Some(transition_ms) = state.transition_ms {
+            payload.insert(
+                "transition".to_string(),
+                json!(transition_ms as f64 / 1000.0),
+            );
+        }
+
+        Value::Object(payload)
+    }
+
+    /// Payload for the `get` topic, asking the device to report its state
+    pub fn get_payload(&self) -> Value {
+        json!({ &self.state_property: "" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_model() {
+        let bridge_device: BridgeDevice = serde_json::from_value(json!({
+            "friendly_name": "kitchen_light",
+            "type": "Router",
+            "definition": {
+                "exposes": [
+                    {
+                        "type": "light",
+                        "features": [
+                            { "type": "binary", "name": "state", "property": "state", "access": 7, "value_on": "ON", "value_off": "OFF" },
+                            { "type": "numeric", "name": "brightness", "property": "brightness", "access": 7, "value_min": 0, "value_max": 254 },
+                            { "type": "numeric", "name": "color_temp", "property": "color_temp", "access": 7, "value_min": 153, "value_max": 500 },
+                            { "type": "composite", "name": "color_xy", "property": "color", "access": 7, "features": [] }
+                        ]
+                    },
+                    { "type": "numeric", "name": "power", "property": "power", "access": 5, "unit": "W" },
+                    { "type": "numeric", "name": "linkquality", "property": "linkquality", "access": 1 },
+                    { "type": "enum", "name": "effect", "property": "effect", "access": 2 }
+                ]
+            }
+        }))
+        .unwrap();
+
+        let model = DeviceModel::new(&bridge_device).unwrap();
+        let light = model.light.clone().unwrap();
+        assert_eq!(light.capabilities.ct, Some(2000..6536));
+        assert!(light.capabilities.xy);
+
+        let payload = json!({
+            "state": "ON",
+            "brightness": 127,
+            "color_mode": "color_temp",
+            "color_temp": 370,
+            "power": 7.5,
+            "linkquality": 120,
+            "update": { "state": "available" }
+        });
+
+        let devices = model.map_state(payload.as_object().unwrap(), &ManageKind::Full);
+        let ids: Vec<String> = devices.iter().map(|(id, _, _)| id.to_string()).collect();
+        assert_eq!(
+            ids,
+            [
+                "kitchen_light",
+                "kitchen_light:power",
+                "kitchen_light:update"
+            ]
+        );
+
+        let DeviceData::Controllable(controllable) = &devices[0].2 else {
+            panic!("Expected kitchen_light to be controllable");
+        };
+        assert!(controllable.state.power);
+        assert_eq!(
+            controllable.state.color,
+            Some(DeviceColor::new_from_ct(2703))
+        );
+
+        assert_eq!(
+            light.set_payload(&controllable.state),
+            json!({ "state": "ON", "brightness": 127.0, "color_temp": 370 })
+        );
+    }
+
+    #[test]
+    fn test_gradient() {
+        let bridge_device: BridgeDevice = serde_json::from_value(json!({
+            "friendly_name": "gradient_strip",
+            "type": "Router",
+            "definition": {
+                "exposes": [
+                    {
+                        "type": "light",
+                        "features": [
+                            { "type": "binary", "name": "state", "property": "state", "access": 7, "value_on": "ON", "value_off": "OFF" },
+                            { "type": "composite", "name": "color_xy", "property": "color", "access": 7, "features": [] }
+                        ]
+                    },
+                    { "type": "list", "name": "gradient", "property": "gradient", "access": 7, "length_min": 1, "length_max": 5 }
+                ]
+            }
+        }))
+        .unwrap();
+
+        let model = DeviceModel::new(&bridge_device).unwrap();
+        let light = model.light.clone().unwrap();
+        assert_eq!(light.capabilities.segments, Some(5));
+
+        let payload = json!({ "state": "ON", "gradient": ["#ff0000", "#0000ff"] });
+        let devices = model.map_state(payload.as_object().unwrap(), &ManageKind::Full);
+
+        let DeviceData::Controllable(controllable) = &devices[0].2 else {
+            panic!("Expected gradient_strip to be controllable");
+        };
+        let segments = controllable.state.segments.clone().unwrap();
+        assert_eq!(segments.len(), 2);
+        assert!(matches!(segments[0], DeviceColor::Xy(_)));
+
+        let state = ControllableState {
+            segments: Some(vec![
+                DeviceColor::new_from_rgb(255, 0, 0),
+                DeviceColor::new_from_rgb(0, 0, 255),
+            ]),
+            ..controllable.state.clone()
+        };
+        assert_eq!(
+            light.set_payload(&state)["gradient"],
+            json!(["#ff0000", "#0000ff"])
+        );
+    }
+}
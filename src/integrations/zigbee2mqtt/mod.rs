@@ -0,0 +1,385 @@
+//! Zigbee2MQTT integration, discovering devices from the `bridge/devices`
+//! topic and mapping their exposes to lights, switches and sensors, without
+//! having to configure field mappings as with the generic MQTT integration.
+
+mod exposes;
+
+use crate::core::schema::JsonSchema;
+use crate::types::{
+    device::{Device, DeviceKey, ManageKind},
+    event::{Message, TxEventChannel},
+    integration::{Integration, IntegrationId},
+};
+use async_trait::async_trait;
+use color_eyre::Result;
+use eyre::{eyre, Context};
+use rand::{distributions::Alphanumeric, Rng};
+use rumqttc::{AsyncClient, MqttOptions, Publish, QoS};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::task::{self, JoinHandle};
+
+use exposes::{BridgeDevice, DeviceModel};
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct Zigbee2mqttConfig {
+    /// Address of the MQTT broker Zigbee2MQTT is connected to
+    host: String,
+
+    /// Defaults to 1883
+    port: Option<u16>,
+
+    /// Base topic of Zigbee2MQTT, defaults to "zigbee2mqtt"
+    base_topic: Option<String>,
+
+    /// How lights and switches are managed, defaults to partially managed as
+    /// they're often also switched from Zigbee remotes bound to them
+    managed: Option<ManageKind>,
+}
+
+/// Device models by friendly name
+type Models = Arc<Mutex<BTreeMap<String, DeviceModel>>>;
+
+pub struct Zigbee2mqtt {
+    id: IntegrationId,
+    event_tx: TxEventChannel,
+    config: Zigbee2mqttConfig,
+    models: Models,
+    client: Option<AsyncClient>,
+    eventloop_handle: Option<JoinHandle<()>>,
+}
+
+#[async_trait]
+impl Integration for Zigbee2mqtt {
+    fn new(id: &IntegrationId, config: &config::Value, event_tx: TxEventChannel) -> Result<Self> {
+        let config = config
+            .clone()
+            .try_deserialize()
+            .wrap_err("Failed to deserialize config of Zigbee2mqtt integration")?;
+
+        Ok(Zigbee2mqtt {
+            id: id.clone(),
+            event_tx,
+            config,
+            models: Default::default(),
+            client: None,
+            eventloop_handle: None,
+        })
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        let random_string: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(8)
+            .map(char::from)
+            .collect();
+
+        let mut options = MqttOptions::new(
+            format!("{}-{}", self.id, random_string),
+            self.config.host.clone(),
+            self.config.port.unwrap_or(1883),
+        );
+        options.set_keep_alive(Duration::from_secs(5));
+        // The device list easily exceeds the default limit of 10 kB
+        options.set_max_packet_size(1024 * 1024, 1024 * 1024);
+
+        let (client, mut eventloop) = AsyncClient::new(options, 10);
+        self.client = Some(client.clone());
+
+        let bridge = Bridge {
+            id: self.id.clone(),
+            event_tx: self.event_tx.clone(),
+            base_topic: self.base_topic().to_string(),
+            managed: self.managed(),
+            models: self.models.clone(),
+            client,
+        };
+
+        let eventloop_handle = task::spawn(async move {
+            loop {
+                let notification = eventloop.poll().await;
+
+                // Graceful disconnect was requested in stop()
+                if let Ok(rumqttc::Event::Outgoing(rumqttc::Outgoing::Disconnect)) = notification {
+                    break;
+                }
+
+                let res = match notification {
+                    Ok(rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_))) => bridge
+                        .client
+                        .subscribe(format!("{}/#", bridge.base_topic), QoS::AtMostOnce)
+                        .await
+                        .map_err(Into::into),
+                    Ok(rumqttc::Event::Incoming(rumqttc::Packet::Publish(msg))) => {
+                        bridge.handle_publish(&msg).await
+                    }
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(e.into()),
+                };
+
+                if let Err(e) = res {
+                    error!(integration_id = %bridge.id, "Zigbee2MQTT error: {:?}", e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        });
+
+        self.eventloop_handle = Some(eventloop_handle);
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        let Some(client) = self.client.take() else {
+            return Ok(());
+        };
+
+        client.disconnect().await?;
+
+        if let Some(eventloop_handle) = self.eventloop_handle.take() {
+            tokio::time::timeout(Duration::from_secs(2), eventloop_handle)
+                .await
+                .ok();
+        }
+
+        Ok(())
+    }
+
+    async fn set_integration_device_state(&mut self, device: &Device) -> Result<()> {
+        let Some(state) = device.get_controllable_state() else {
+            return Ok(());
+        };
+
+        let friendly_name = device.id.to_string();
+        let payload = self
+            .models
+            .lock()
+            .unwrap()
+            .get(&friendly_name)
+            .and_then(|model| model.light.as_ref())
+            .map(|light| light.set_payload(state))
+            .ok_or_else(|| eyre!("Zigbee2MQTT light {} not found", friendly_name))?;
+
+        self.client()
+            .publish(
+                format!("{}/{}/set", self.base_topic(), friendly_name),
+                QoS::AtLeastOnce,
+                false,
+                payload.to_string(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn poll_device(&mut self, device: &Device) -> Result<bool> {
+        let friendly_name = device.id.to_string();
+        let payload = self
+            .models
+            .lock()
+            .unwrap()
+            .get(&friendly_name)
+            .and_then(|model| model.light.as_ref())
+            .map(|light| light.get_payload());
+
+        // Sensors only report by themselves
+        let Some(payload) = payload else {
+            return Ok(false);
+        };
+
+        self.client()
+            .publish(
+                format!("{}/{}/get", self.base_topic(), friendly_name),
+                QoS::AtLeastOnce,
+                false,
+                payload.to_string(),
+            )
+            .await?;
+
+        Ok(true)
+    }
+}
+
+impl Zigbee2mqtt {
+    fn base_topic(&self) -> &str {
+        self.config.base_topic.as_deref().unwrap_or("zigbee2mqtt")
+    }
+
+    fn managed(&self) -> ManageKind {
+        self.config.managed.clone().unwrap_or(ManageKind::Partial {
+            prev_change_committed: false,
+        })
+    }
+
+    fn client(&self) -> &AsyncClient {
+        self.client
+            .as_ref()
+            .expect("Expected self.client to be set in start phase")
+    }
+}
+
+/// Topics below the base topic
+#[derive(Debug, PartialEq, Eq)]
+enum Topic<'a> {
+    Devices,
+    State(&'a str),
+    Availability(&'a str),
+    Other,
+}
+
+fn parse_topic<'a>(base_topic: &str, topic: &'a str) -> Topic<'a> {
+    let Some(rest) = topic
+        .strip_prefix(base_topic)
+        .and_then(|rest| rest.strip_prefix('/'))
+    else {
+        return Topic::Other;
+    };
+
+    if rest == "bridge/devices" {
+        Topic::Devices
+    } else if rest.starts_with("bridge/") || rest.ends_with("/set") || rest.ends_with("/get") {
+        Topic::Other
+    } else if let Some(friendly_name) = rest.strip_suffix("/availability") {
+        Topic::Availability(friendly_name)
+    } else {
+        Topic::State(rest)
+    }
+}
+
+struct Bridge {
+    id: IntegrationId,
+    event_tx: TxEventChannel,
+    base_topic: String,
+    managed: ManageKind,
+    models: Models,
+    client: AsyncClient,
+}
+
+impl Bridge {
+    async fn handle_publish(&self, msg: &Publish) -> Result<()> {
+        match parse_topic(&self.base_topic, &msg.topic) {
+            Topic::Devices => self.handle_devices(&msg.payload).await,
+            Topic::State(friendly_name) => self.handle_state(friendly_name, &msg.payload),
+            Topic::Availability(friendly_name) => {
+                self.handle_availability(friendly_name, &msg.payload);
+                Ok(())
+            }
+            Topic::Other => Ok(()),
+        }
+    }
+
+    async fn handle_devices(&self, payload: &[u8]) -> Result<()> {
+        let devices: Vec<BridgeDevice> = serde_json::from_slice(payload)?;
+
+        let models: BTreeMap<String, DeviceModel> = devices
+            .iter()
+            .filter_map(DeviceModel::new)
+            .map(|model| (model.friendly_name.clone(), model))
+            .collect();
+
+        info!(integration_id = %self.id, "Discovered {} Zigbee devices", models.len());
+
+        let lights: Vec<_> = models
+            .values()
+            .filter_map(|model| {
+                Some((
+                    model.friendly_name.clone(),
+                    model.light.as_ref()?.get_payload(),
+                ))
+            })
+            .collect();
+
+        *self.models.lock().unwrap() = models;
+
+        // Lights don't necessarily publish their state until changed
+        for (friendly_name, payload) in lights {
+            self.client
+                .publish(
+                    format!("{}/{}/get", self.base_topic, friendly_name),
+                    QoS::AtLeastOnce,
+                    false,
+                    payload.to_string(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_state(&self, friendly_name: &str, payload: &[u8]) -> Result<()> {
+        let devices = {
+            let models = self.models.lock().unwrap();
+
+            // Not discovered yet, or a group
+            let Some(model) = models.get(friendly_name) else {
+                return Ok(());
+            };
+
+            let payload: serde_json::Map<String, serde_json::Value> =
+                serde_json::from_slice(payload)?;
+
+            model.map_state(&payload, &self.managed)
+        };
+
+        for (device_id, name, data) in devices {
+            let device = Device::new(self.id.clone(), device_id, name, data);
+            self.event_tx.send(Message::RecvDeviceState { device });
+        }
+
+        Ok(())
+    }
+
+    fn handle_availability(&self, friendly_name: &str, payload: &[u8]) {
+        // Either "online" or {"state": "online"}, depending on the
+        // Zigbee2MQTT version
+        let available = match serde_json::from_slice::<serde_json::Value>(payload) {
+            Ok(value) => value.get("state").and_then(|state| state.as_str()) == Some("online"),
+            Err(_) => payload == b"online",
+        };
+
+        let device_ids = match self.models.lock().unwrap().get(friendly_name) {
+            Some(model) => model.device_ids(),
+            None => return,
+        };
+
+        for device_id in device_ids {
+            self.event_tx.send(Message::SetDeviceAvailability {
+                device_key: DeviceKey::new(self.id.clone(), device_id),
+                available,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_topic() {
+        assert_eq!(
+            parse_topic("zigbee2mqtt", "zigbee2mqtt/bridge/devices"),
+            Topic::Devices
+        );
+        assert_eq!(
+            parse_topic("zigbee2mqtt", "zigbee2mqtt/bridge/state"),
+            Topic::Other
+        );
+        assert_eq!(
+            parse_topic("zigbee2mqtt", "zigbee2mqtt/living room/lamp"),
+            Topic::State("living room/lamp")
+        );
+        assert_eq!(
+            parse_topic("zigbee2mqtt", "zigbee2mqtt/lamp/availability"),
+            Topic::Availability("lamp")
+        );
+        assert_eq!(
+            parse_topic("zigbee2mqtt", "zigbee2mqtt/lamp/set"),
+            Topic::Other
+        );
+    }
+}
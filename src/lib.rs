@@ -0,0 +1,18 @@
+#[macro_use]
+extern crate macro_attr;
+
+#[macro_use]
+extern crate newtype_derive;
+
+#[macro_use]
+extern crate tracing;
+
+#[macro_use]
+extern crate eyre;
+
+pub mod api;
+pub mod core;
+pub mod db;
+pub mod integrations;
+pub mod types;
+pub mod utils;
@@ -1,106 +1,344 @@
 #[macro_use]
-extern crate macro_attr;
+extern crate tracing;
 
-#[macro_use]
-extern crate newtype_derive;
-
-#[macro_use]
-extern crate log;
-
-#[macro_use]
-extern crate eyre;
-
-mod api;
-mod core;
-mod db;
-mod integrations;
-mod types;
-mod utils;
-
-use crate::core::expr::Expr;
-// use db::{actions::find_floorplans, establish_connection};
-use crate::core::{
-    devices::Devices, groups::Groups, integrations::Integrations, message::handle_message,
-    rules::Rules, scenes::Scenes, state::AppState,
-};
-use crate::types::event::mk_event_channel;
-use api::init_api;
 use color_eyre::Result;
-use db::init_db;
 use eyre::eyre;
-use std::{error::Error, sync::Arc};
+use homectl_server::api::init_api;
+use homectl_server::core::config::{parse_integration_config, read_config};
+use homectl_server::core::config_reload::watch_config;
+use homectl_server::core::device_config::DeviceConfigs;
+use homectl_server::core::expr::Expr;
+use homectl_server::core::logging::init_logging;
+use homectl_server::core::sentry::init_sentry;
+use homectl_server::core::standby::follow_primary;
+// use db::{actions::find_floorplans, establish_connection};
+use homectl_server::core::{
+    appliances::Appliances,
+    button_presses::ButtonPresses,
+    calendar::Calendar,
+    clock::init_timezone,
+    commands::Commands,
+    conflicts::Conflicts,
+    covers::Covers,
+    device_workers::DeviceWorkers,
+    devices::{expire_unseen_devices, reconcile_devices, Devices},
+    event_bus::EventBus,
+    groups::Groups,
+    ha_discovery::HaDiscovery,
+    ha_import::run_ha_import,
+    heating::{refresh_heating, Heating},
+    integrations::Integrations,
+    message::process_message,
+    modes::Modes,
+    motion_lighting::MotionLighting,
+    notifications::Notifications,
+    open_alerts::OpenAlerts,
+    persons::Persons,
+    polling::{poll_stale_devices, Polling},
+    presence::Presence,
+    quiet_hours::{refresh_quiet_hours, QuietHours},
+    rate_alerts::RateAlerts,
+    rules::Rules,
+    safety::Safety,
+    scene_transitions::SceneTransitions,
+    scenes::Scenes,
+    state::AppState,
+    sun::{refresh_sun, Sun},
+    utility_meters::{refresh_utility_meters, UtilityMeters},
+    websockets::WebSockets,
+};
+use homectl_server::db::{
+    actions::{
+        db_get_device_aliases, db_get_device_metadata, db_get_integrations, db_get_utility_meters,
+    },
+    flush_db_writes, init_db,
+};
+use homectl_server::types::event::{mk_event_channel, Message};
+use std::{error::Error, sync::Arc, time::Duration};
 use tokio::sync::RwLock;
 
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     color_eyre::install()?;
-    pretty_env_logger::init();
 
-    // Attempt connecting to Postgres
+    // Subcommands run without starting the server
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("import-ha") {
+        return Ok(run_ha_import(&args[1..])?);
+    }
+
+    init_logging();
+
+    // Connect to SQLite if DATABASE_URL starts with `sqlite:`, otherwise to
+    // Postgres
     init_db().await;
 
-    let (config, opaque_integrations_configs) = core::config::read_config()?;
+    let (config, opaque_integrations_configs) = read_config()?;
 
     trace!("Using config:\n    {:#?}", config);
 
+    if let Some(timezone) = config
+        .location
+        .as_ref()
+        .and_then(|location| location.timezone.as_ref())
+    {
+        init_timezone(timezone)?;
+    }
+
+    if let Some(sentry_config) = &config.sentry {
+        init_sentry(sentry_config)?;
+    }
+
     let (event_tx, mut event_rx) = mk_event_channel();
 
-    let mut integrations = Integrations::new(event_tx.clone());
+    let device_configs = DeviceConfigs::new(config.devices.unwrap_or_default());
+    let mut integrations = Integrations::new(event_tx.clone(), device_configs.clone());
     let groups = Groups::new(config.groups.unwrap_or_default());
     let mut scenes = Scenes::new(config.scenes.unwrap_or_default());
     scenes.refresh_db_scenes().await;
-    let devices = Devices::new(event_tx.clone());
-    let expr = Expr::new();
-    let rules = Rules::new(config.routines.unwrap_or_default(), event_tx.clone());
+    scenes.load_stats().await;
+    let mut devices = Devices::new(
+        event_tx.clone(),
+        config.overrides.unwrap_or_default(),
+        device_configs,
+    );
+    if let Some(reconcile) = &config.reconcile {
+        tokio::spawn(reconcile_devices(
+            event_tx.clone(),
+            Duration::from_secs(reconcile.interval_secs),
+        ));
+    }
+    if let Some(device_expiry) = &config.device_expiry {
+        tokio::spawn(expire_unseen_devices(
+            event_tx.clone(),
+            device_expiry.expire_after_days,
+        ));
+    }
+    if config.polling.is_some() {
+        tokio::spawn(poll_stale_devices(event_tx.clone()));
+    }
+    let polling = Polling::new(config.polling);
+    let calendar = Calendar::new(config.calendar.unwrap_or_default())?;
+    let expr = Expr::new(calendar);
+    let mut rules = Rules::new(config.get_routines()?, event_tx.clone());
+    let mut persons = Persons::new(config.persons.unwrap_or_default(), event_tx.clone());
+    persons.restore_db_state().await;
+    let presence = Presence::new(config.presence.unwrap_or_default(), event_tx.clone());
+    let notifications = Notifications::new(config.notifications.unwrap_or_default());
+    if config.quiet_hours.is_some() {
+        tokio::spawn(refresh_quiet_hours(event_tx.clone()));
+    }
+    let mut quiet_hours = QuietHours::new(config.quiet_hours, event_tx.clone());
+    quiet_hours.restore_db_state().await;
+    let mut modes = Modes::new(config.modes.unwrap_or_default(), event_tx.clone());
+    modes.restore_db_state().await;
+    modes.report_mode();
+    let safety = Safety::new(config.safety.unwrap_or_default(), event_tx.clone());
+    let appliances = Appliances::new(config.appliances.unwrap_or_default(), event_tx.clone());
+    let rate_alerts = RateAlerts::new(config.rate_alerts.unwrap_or_default(), event_tx.clone());
+    if config.utility_meters.is_some() {
+        tokio::spawn(refresh_utility_meters(event_tx.clone()));
+    }
+    let utility_meters = UtilityMeters::new(
+        config.utility_meters.unwrap_or_default(),
+        event_tx.clone(),
+        db_get_utility_meters().await.unwrap_or_default(),
+    );
+    if config.heating.is_some() {
+        tokio::spawn(refresh_heating(event_tx.clone()));
+    }
+    let heating = Heating::new(config.heating.unwrap_or_default());
+    if config.location.is_some() {
+        tokio::spawn(refresh_sun(event_tx.clone()));
+    }
+    rules.spawn_triggers(config.location.as_ref())?;
+    let sun = Sun::new(config.location, event_tx.clone());
+    let covers = Covers::new(config.covers.unwrap_or_default());
+    let motion_lighting =
+        MotionLighting::new(config.motion_lighting.unwrap_or_default(), event_tx.clone());
+    let open_alerts = OpenAlerts::new(config.open_alerts.unwrap_or_default(), event_tx.clone());
+    let commands = Commands::new(config.commands.unwrap_or_default(), event_tx.clone());
+    let conflicts = Conflicts::new(config.conflicts.unwrap_or_default());
+    let event_bus = EventBus::new(config.event_bus, event_tx.clone());
+    let ha_discovery = HaDiscovery::new(config.ha_discovery, event_tx.clone());
 
+    // A broken integration shouldn't prevent the rest of the server from
+    // starting, it's marked as failed instead
     for (id, integration_config) in &config.integrations.unwrap_or_default() {
         let opaque_integration_config: &config::Value = opaque_integrations_configs
             .get(id)
             .ok_or_else(|| eyre!("Expected to find config for integration with id {}", id))?;
 
-        integrations
-            .load_integration(&integration_config.plugin, id, opaque_integration_config)
-            .await?;
+        if let Err(e) = integrations
+            .load_integration(id, integration_config, opaque_integration_config)
+            .await
+        {
+            error!(
+                "failed to load {} integration {}: {:?}",
+                integration_config.plugin, id, e
+            );
+            integrations.set_failed(id);
+        }
+    }
+
+    // Device metadata edited through the API
+    for (device_key, metadata) in db_get_device_metadata().await.unwrap_or_default() {
+        devices
+            .get_device_configs()
+            .set_device_metadata(&device_key, metadata);
     }
 
-    integrations.run_register_pass().await?;
-    integrations.run_start_pass().await?;
+    // Aliases of re-paired devices
+    for alias in db_get_device_aliases().await.unwrap_or_default() {
+        devices.add_alias(&alias);
+    }
+
+    // Integrations added at runtime through the API
+    let db_integrations = db_get_integrations().await.unwrap_or_default();
+    for (id, json) in &db_integrations {
+        if integrations.is_loaded(id) {
+            warn!(
+                "Integration {} is defined both in config file and DB, ignoring DB config",
+                id
+            );
+            continue;
+        }
+
+        let result = match parse_integration_config(json) {
+            Ok((integration_config, opaque_integration_config)) => {
+                integrations
+                    .load_integration(id, &integration_config, &opaque_integration_config)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = result {
+            error!("failed to load integration {} from DB: {:?}", id, e);
+            integrations.set_failed(id);
+        }
+    }
+
+    // A standby instance only starts its integrations once the primary
+    // instance stops responding
+    let standby = config.standby.is_some();
+    if let Some(standby_config) = config.standby {
+        info!("Starting in standby mode");
+        tokio::spawn(follow_primary(standby_config, event_tx.clone()));
+    } else {
+        integrations.run_register_pass().await?;
+        integrations.run_start_pass().await?;
+        event_tx.send(Message::IntegrationsStarted);
+    }
+
+    tokio::spawn(watch_config(event_tx.clone()));
 
     let state = AppState {
         integrations,
         groups,
         scenes,
+        scene_transitions: SceneTransitions::new(event_tx.clone()),
+        button_presses: ButtonPresses::new(event_tx.clone()),
         devices,
         rules,
+        persons,
+        presence,
+        notifications,
+        quiet_hours,
+        modes,
+        safety,
+        appliances,
+        rate_alerts,
+        utility_meters,
+        heating,
+        sun,
+        covers,
+        motion_lighting,
+        open_alerts,
+        commands,
+        conflicts,
+        polling,
+        event_bus,
+        ha_discovery,
         event_tx,
         expr,
-        ws: Default::default(),
+        ws: WebSockets::new(config.websockets.unwrap_or_default()),
+        standby,
     };
 
     let state = Arc::new(RwLock::new(state));
 
-    init_api(&state)?;
+    init_api(&state, config.api.unwrap_or_default(), config.frontend)?;
+
+    let mut device_workers = DeviceWorkers::new(Arc::clone(&state));
+
+    // Signal futures are created once, so that a signal arriving while a
+    // message is being dispatched isn't missed
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    #[cfg(unix)]
+    let mut sigterm = signal(SignalKind::terminate())?;
+    #[cfg(unix)]
+    let terminate = sigterm.recv();
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<Option<()>>();
+    tokio::pin!(terminate);
 
     loop {
-        let msg = event_rx
-            .recv()
-            .await
-            .expect("Expected sender end of channel to never be dropped");
+        let (correlation_id, msg) = tokio::select! {
+            msg = event_rx.recv() => {
+                msg.expect("Expected sender end of channel to never be dropped")
+            }
+            _ = &mut terminate => break,
+            _ = &mut ctrl_c => break,
+        };
 
-        // trace!("Received message: {:.100}", format!("{:?}", msg));
+        // Device states are queued per device, so that states of a device
+        // are handled in the order they were received
+        let msg = match msg {
+            Message::RecvDeviceState { device } => {
+                if let Err(e) = device_workers.dispatch(correlation_id, device) {
+                    error!("Failed to dispatch device state: {:?}", e);
+                }
+                continue;
+            }
+            Message::DeviceRemoved { device_key } => {
+                device_workers.remove(&device_key);
+                continue;
+            }
+            msg => msg,
+        };
 
         let state = Arc::clone(&state);
-
         tokio::spawn(async move {
             let mut state = state.write().await;
-            let result = handle_message(&mut state, &msg).await;
-
-            if let Err(err) = result {
-                error!(
-                    "Error while handling message:\n    Msg:\n    {:#?}\n\n    Err:\n    {:#?}",
-                    msg, err
-                );
-            }
+            process_message(&mut state, correlation_id, &msg).await;
         });
     }
+
+    shutdown(&state).await;
+
+    Ok(())
+}
+
+async fn shutdown(state: &Arc<RwLock<AppState>>) {
+    info!("Shutting down...");
+
+    let state = state.read().await;
+
+    state.ws.close_all("Server shutting down").await;
+
+    if let Err(e) = state.integrations.run_stop_pass().await {
+        error!("Error while stopping integrations: {:?}", e);
+    }
+
+    if let Err(e) = flush_db_writes(Duration::from_secs(5)).await {
+        error!("Error while flushing DB writes: {:?}", e);
+    }
+
+    info!("Shutdown complete");
 }
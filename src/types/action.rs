@@ -1,15 +1,25 @@
+use crate::core::schema::JsonSchema;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
 use super::{
     device::Device,
-    dim::DimDescriptor,
+    device_expiry::PurgeUnseenDevicesDescriptor,
+    dim::{
+        ColorTemperatureStepDescriptor, DimDescriptor, NudgeColorDescriptor,
+        UpdateDeviceStateDescriptor,
+    },
     integration::CustomActionDescriptor,
+    mode::SetModeDescriptor,
+    notification::NotifyDescriptor,
+    overrides::OverrideDescriptor,
+    power::PowerDescriptor,
+    quiet_hours::DoNotDisturbDescriptor,
     rule::ForceTriggerRoutineDescriptor,
     scene::{CycleScenesDescriptor, SceneDescriptor},
 };
 
-#[derive(TS, Clone, Deserialize, Debug, Serialize)]
+#[derive(TS, Clone, Deserialize, JsonSchema, Debug, Serialize)]
 #[serde(tag = "action")]
 #[ts(export)]
 pub enum Action {
@@ -25,12 +35,61 @@ pub enum Action {
     /// Dims the given groups and devices.
     Dim(DimDescriptor),
 
+    /// Brightens the given groups and devices, opposite of [Action::Dim].
+    Brighten(DimDescriptor),
+
+    /// Makes color temperature of the given groups and devices warmer.
+    Warmer(ColorTemperatureStepDescriptor),
+
+    /// Makes color temperature of the given groups and devices cooler,
+    /// opposite of [Action::Warmer].
+    Cooler(ColorTemperatureStepDescriptor),
+
+    /// Shifts hue and saturation of the given groups and devices relative to
+    /// their current color.
+    NudgeColor(NudgeColorDescriptor),
+
     /// Forcibly triggers a routine, ignoring any possible rules.
     ForceTriggerRoutine(ForceTriggerRoutineDescriptor),
 
     /// Sets device state to given state.
     SetDeviceState(Device),
 
+    /// Updates only the given fields of the state of given devices and
+    /// groups, keeping the rest of their expected state.
+    UpdateDeviceState(UpdateDeviceStateDescriptor),
+
+    /// Pauses scene re-assertion for given devices and groups, so that manual
+    /// adjustments are not reverted.
+    SetOverride(OverrideDescriptor),
+
+    /// Ends override mode for given devices and groups, restoring their
+    /// expected state.
+    ClearOverride(OverrideDescriptor),
+
+    /// Turns off given devices, groups and areas, or all devices if none are
+    /// given. Devices tagged as exempt are skipped.
+    TurnOff(PowerDescriptor),
+
+    /// Turns on given devices, groups and areas, or all devices if none are
+    /// given. Devices tagged as exempt are skipped.
+    TurnOn(PowerDescriptor),
+
+    /// Sends a notification to the channels it's routed to.
+    Notify(NotifyDescriptor),
+
+    /// Switches the house to given mode, running exit and entry actions of
+    /// the modes.
+    SetMode(SetModeDescriptor),
+
+    /// Enables, disables or toggles do not disturb, which has the same effect
+    /// as quiet hours.
+    SetDoNotDisturb(DoNotDisturbDescriptor),
+
+    /// Forgets about devices that haven't reported their state for given
+    /// number of days, deleting them from the DB and from groups and scenes.
+    PurgeUnseenDevices(PurgeUnseenDevicesDescriptor),
+
     /// Evaluates given expression.
     #[serde(untagged, skip_serializing)]
     #[ts(skip)]
@@ -0,0 +1,35 @@
+use crate::core::schema::JsonSchema;
+use serde::Deserialize;
+
+/// What a token allows its holder to do, each scope includes the ones before
+/// it
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiScope {
+    /// Reading state, e.g. for dashboards
+    Read,
+
+    /// Changing device state, activating scenes and triggering actions
+    Control,
+
+    /// Managing integrations, sessions, snapshots and logging
+    Admin,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct ApiToken {
+    /// Sent by clients as `Authorization: Bearer <token>`
+    pub token: String,
+
+    pub scope: ApiScope,
+
+    /// Describes who the token was handed out to
+    pub name: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+pub struct ApiConfig {
+    /// Tokens accepted by the HTTP API. If unset, the API doesn't require
+    /// authentication.
+    pub tokens: Option<Vec<ApiToken>>,
+}
@@ -0,0 +1,41 @@
+use crate::core::schema::JsonSchema;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use super::device::DeviceRef;
+
+macro_attr! {
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Hash, Ord, PartialOrd, NewtypeDisplay!)]
+    pub struct ApplianceId(pub String);
+}
+
+/// Integration id of the virtual "appliance running" sensors
+pub const APPLIANCES_INTEGRATION_ID: &str = "appliances";
+
+/// Derives whether an appliance is running from its power draw
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct ApplianceConfig {
+    pub name: String,
+
+    /// Sensor with a numeric value reporting power draw
+    pub power_sensor: DeviceRef,
+
+    /// Appliance starts running once power exceeds this
+    pub start_above: f64,
+
+    /// Appliance stops running once power drops below this
+    pub stop_below: f64,
+
+    /// How long power needs to stay above `start_above` before the appliance
+    /// is considered running
+    #[serde(default)]
+    pub start_secs: u64,
+
+    /// How long power needs to stay below `stop_below` before the appliance
+    /// is considered finished, useful for appliances that idle between
+    /// cycles like washing machines
+    #[serde(default)]
+    pub stop_secs: u64,
+}
+
+pub type AppliancesConfig = BTreeMap<ApplianceId, ApplianceConfig>;
@@ -0,0 +1,62 @@
+use crate::core::schema::JsonSchema;
+use chrono::Weekday;
+use serde::Deserialize;
+
+/// Countries with built-in public holidays
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HolidayCountry {
+    /// Germany, nationwide holidays only
+    De,
+    Fi,
+    /// England and Wales, without substitute days
+    Gb,
+    Se,
+    /// Federal holidays, without observed days
+    Us,
+}
+
+fn default_weekend() -> Vec<Weekday> {
+    vec![Weekday::Sat, Weekday::Sun]
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct CalendarConfig {
+    /// Public holidays of this country are included
+    pub country: Option<HolidayCountry>,
+
+    /// Additional holidays, either a single date as "YYYY-MM-DD" or a date
+    /// recurring every year as "MM-DD"
+    #[serde(default)]
+    pub holidays: Vec<String>,
+
+    /// Days of the week that aren't workdays, defaults to ["sat", "sun"]
+    #[serde(default = "default_weekend")]
+    pub weekend: Vec<Weekday>,
+}
+
+impl Default for CalendarConfig {
+    fn default() -> Self {
+        CalendarConfig {
+            country: None,
+            holidays: vec![],
+            weekend: default_weekend(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CalendarDay {
+    /// Neither a weekend day nor a holiday
+    Workday,
+    Holiday,
+    /// A weekend day or a holiday
+    DayOff,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct CalendarRule {
+    /// Kind of day today must be according to the holiday calendar
+    pub calendar: CalendarDay,
+}
@@ -1,10 +1,11 @@
+use crate::core::schema::JsonSchema;
 use ordered_float::OrderedFloat;
 use palette::{convert::FromColorUnclamped, FromColor, IntoColor};
 use serde::{Deserialize, Serialize};
 use serde_this_or_that::as_u64;
 use ts_rs::TS;
 
-#[derive(TS, Clone, Debug, Default, PartialEq, Deserialize, Serialize, Hash, Eq)]
+#[derive(TS, Clone, Debug, Default, PartialEq, Deserialize, JsonSchema, Serialize, Hash, Eq)]
 #[ts(export)]
 pub struct Capabilities {
     /// XY color space (0.0 - 1.0)
@@ -19,8 +20,20 @@ pub struct Capabilities {
     #[serde(default)]
     pub rgb: bool,
 
+    /// RGB values with a dedicated white channel (0 - 255)
+    #[serde(default)]
+    pub rgbw: bool,
+
+    /// RGB values with cold and warm white channels (0 - 255)
+    #[serde(default)]
+    pub rgbww: bool,
+
     /// Color temperature (2000 - 6500)
     pub ct: Option<std::ops::Range<u16>>,
+
+    /// Number of individually colored zones, e.g. the gradient points of Hue
+    /// gradient lights or WLED segments
+    pub segments: Option<usize>,
 }
 
 #[derive(TS, Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -29,6 +42,8 @@ pub enum ColorMode {
     Xy,
     Hs,
     Rgb,
+    Rgbw,
+    Rgbww,
     Ct(std::ops::Range<u16>),
 }
 
@@ -37,6 +52,8 @@ impl Capabilities {
         let mut xy = false;
         let mut hs = false;
         let mut rgb = false;
+        let mut rgbw = false;
+        let mut rgbww = false;
         let mut ct = None;
 
         match mode {
@@ -49,12 +66,32 @@ impl Capabilities {
             ColorMode::Rgb => {
                 rgb = true;
             }
+            ColorMode::Rgbw => {
+                rgbw = true;
+            }
+            ColorMode::Rgbww => {
+                rgbww = true;
+            }
             ColorMode::Ct(range) => {
                 ct = Some(range);
             }
         };
 
-        Capabilities { xy, hs, rgb, ct }
+        Capabilities {
+            xy,
+            hs,
+            rgb,
+            rgbw,
+            rgbww,
+            ct,
+            segments: None,
+        }
+    }
+
+    /// Whether no color modes are supported, which is also the case when the
+    /// integration doesn't know them
+    pub fn is_empty(&self) -> bool {
+        *self == Capabilities::default()
     }
 
     pub fn is_supported(&self, color: &DeviceColor) -> bool {
@@ -62,12 +99,14 @@ impl Capabilities {
             DeviceColor::Xy(_) => self.xy,
             DeviceColor::Hs(_) => self.hs,
             DeviceColor::Rgb(_) => self.rgb,
+            DeviceColor::Rgbw(_) => self.rgbw,
+            DeviceColor::Rgbww(_) => self.rgbww,
             DeviceColor::Ct(_) => self.ct.is_some(),
         }
     }
 }
 
-#[derive(TS, Clone, Debug, PartialEq, Deserialize, Serialize, Hash, Eq)]
+#[derive(TS, Clone, Debug, PartialEq, Deserialize, JsonSchema, Serialize, Hash, Eq)]
 #[ts(export)]
 pub struct Xy {
     #[ts(type = "f32")]
@@ -76,7 +115,7 @@ pub struct Xy {
     pub y: OrderedFloat<f32>,
 }
 
-#[derive(TS, Clone, Debug, PartialEq, Deserialize, Serialize, Hash, Eq)]
+#[derive(TS, Clone, Debug, PartialEq, Deserialize, JsonSchema, Serialize, Hash, Eq)]
 #[ts(export)]
 pub struct Hs {
     #[serde(deserialize_with = "as_u64")]
@@ -85,7 +124,7 @@ pub struct Hs {
     pub s: OrderedFloat<f32>,
 }
 
-#[derive(TS, Clone, Debug, PartialEq, Deserialize, Serialize, Hash, Eq)]
+#[derive(TS, Clone, Debug, PartialEq, Deserialize, JsonSchema, Serialize, Hash, Eq)]
 #[ts(export)]
 pub struct Rgb {
     #[serde(deserialize_with = "as_u64")]
@@ -96,23 +135,60 @@ pub struct Rgb {
     pub b: u64,
 }
 
-#[derive(TS, Clone, Debug, PartialEq, Deserialize, Serialize, Hash, Eq)]
+#[derive(TS, Clone, Debug, PartialEq, Deserialize, JsonSchema, Serialize, Hash, Eq)]
+#[ts(export)]
+pub struct Rgbw {
+    #[serde(deserialize_with = "as_u64")]
+    pub r: u64,
+    #[serde(deserialize_with = "as_u64")]
+    pub g: u64,
+    #[serde(deserialize_with = "as_u64")]
+    pub b: u64,
+    #[serde(deserialize_with = "as_u64")]
+    pub w: u64,
+}
+
+#[derive(TS, Clone, Debug, PartialEq, Deserialize, JsonSchema, Serialize, Hash, Eq)]
+#[ts(export)]
+pub struct Rgbww {
+    #[serde(deserialize_with = "as_u64")]
+    pub r: u64,
+    #[serde(deserialize_with = "as_u64")]
+    pub g: u64,
+    #[serde(deserialize_with = "as_u64")]
+    pub b: u64,
+    #[serde(deserialize_with = "as_u64")]
+    pub cw: u64,
+    #[serde(deserialize_with = "as_u64")]
+    pub ww: u64,
+}
+
+#[derive(TS, Clone, Debug, PartialEq, Deserialize, JsonSchema, Serialize, Hash, Eq)]
 #[ts(export)]
 pub struct Ct {
     #[serde(deserialize_with = "as_u64")]
     pub ct: u64,
 }
 
-#[derive(TS, Clone, Debug, PartialEq, Deserialize, Serialize, Hash, Eq)]
+#[derive(TS, Clone, Debug, PartialEq, Deserialize, JsonSchema, Serialize, Hash, Eq)]
 #[serde(untagged)]
 #[ts(export)]
 pub enum DeviceColor {
     Xy(Xy),
     Hs(Hs),
+    // White channel variants must come before Rgb, which would otherwise
+    // match them while ignoring the white channels
+    Rgbww(Rgbww),
+    Rgbw(Rgbw),
     Rgb(Rgb),
     Ct(Ct),
 }
 
+/// Color temperatures assumed for the cold and warm white channels of RGBWW
+/// lights
+const COLD_WHITE_CT: u16 = 6500;
+const WARM_WHITE_CT: u16 = 2700;
+
 impl DeviceColor {
     pub fn new_from_xy(x: f32, y: f32) -> DeviceColor {
         DeviceColor::Xy(Xy {
@@ -136,16 +212,61 @@ impl DeviceColor {
         })
     }
 
+    pub fn new_from_rgbw(r: u8, g: u8, b: u8, w: u8) -> DeviceColor {
+        DeviceColor::Rgbw(Rgbw {
+            r: r as u64,
+            g: g as u64,
+            b: b as u64,
+            w: w as u64,
+        })
+    }
+
+    pub fn new_from_rgbww(r: u8, g: u8, b: u8, cw: u8, ww: u8) -> DeviceColor {
+        DeviceColor::Rgbww(Rgbww {
+            r: r as u64,
+            g: g as u64,
+            b: b as u64,
+            cw: cw as u64,
+            ww: ww as u64,
+        })
+    }
+
     pub fn new_from_ct(ct: u16) -> DeviceColor {
         DeviceColor::Ct(Ct { ct: ct as u64 })
     }
 
+    /// Color `t` (0.0 - 1.0) of the way from this color to `other`. Color
+    /// temperatures are interpolated as such, other colors in the xy color
+    /// space.
+    pub fn interpolate(&self, other: &DeviceColor, t: f32) -> DeviceColor {
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+
+        if let (DeviceColor::Ct(a), DeviceColor::Ct(b)) = (self, other) {
+            return DeviceColor::new_from_ct(lerp(a.ct as f32, b.ct as f32).round() as u16);
+        }
+
+        let a: palette::Yxy = self.into();
+        let b: palette::Yxy = other.into();
+
+        DeviceColor::new_from_xy(lerp(a.x, b.x), lerp(a.y, b.y))
+    }
+
     pub fn to_device_preferred_mode(&self, capabilities: &Capabilities) -> Option<DeviceColor> {
         // Don't perform any conversion if device supports current color mode
         if capabilities.is_supported(self) {
             return Some(self.clone());
         }
 
+        // Color temperatures are best reproduced by white channels
+        if let DeviceColor::Ct(ct) = self {
+            if capabilities.rgbww {
+                return Some(ct_to_rgbww(ct.ct as u16));
+            } else if capabilities.rgbw {
+                let yxy: palette::Yxy = self.into();
+                return Some(DeviceColor::Rgbw(rgb_to_rgbw(yxy.into_color())));
+            }
+        }
+
         // Convert color into supported color mode
         let yxy: palette::Yxy = self.into();
         if capabilities.xy {
@@ -156,6 +277,18 @@ impl DeviceColor {
         } else if capabilities.rgb {
             let rgb: palette::rgb::Rgb = yxy.into_color();
             Some(rgb.into())
+        } else if capabilities.rgbw {
+            Some(DeviceColor::Rgbw(rgb_to_rgbw(yxy.into_color())))
+        } else if capabilities.rgbww {
+            // Split the white component evenly between both white channels
+            let rgbw = rgb_to_rgbw(yxy.into_color());
+            Some(DeviceColor::Rgbww(Rgbww {
+                r: rgbw.r,
+                g: rgbw.g,
+                b: rgbw.b,
+                cw: rgbw.w / 2,
+                ww: rgbw.w - rgbw.w / 2,
+            }))
         } else if let Some(supported_range) = &capabilities.ct {
             // McCamy's approximation
             let x = yxy.x;
@@ -171,6 +304,64 @@ impl DeviceColor {
     }
 }
 
+/// Spreads given colors evenly over `count` segments, interpolating between
+/// neighbouring colors so that a few colors make up a gradient
+pub fn resample_segments(colors: &[DeviceColor], count: usize) -> Vec<DeviceColor> {
+    match colors {
+        [] => vec![],
+        [color] => vec![color.clone(); count],
+        _ if colors.len() == count => colors.to_vec(),
+        _ => (0..count)
+            .map(|i| {
+                let pos = i as f32 / count.saturating_sub(1).max(1) as f32;
+                let pos = pos * (colors.len() - 1) as f32;
+                let index = pos as usize;
+                let t = pos - index as f32;
+
+                match colors.get(index + 1) {
+                    Some(next) if t > 0.0 => colors[index].interpolate(next, t),
+                    _ => colors[index].clone(),
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Moves the white component shared by all RGB channels into the white
+/// channel
+fn rgb_to_rgbw(rgb: palette::rgb::Rgb) -> Rgbw {
+    let [r, g, b] = [rgb.red, rgb.green, rgb.blue].map(|c| (c.clamp(0.0, 1.0) * 255.0) as u64);
+    let w = r.min(g).min(b);
+
+    Rgbw {
+        r: r - w,
+        g: g - w,
+        b: b - w,
+        w,
+    }
+}
+
+/// Mixes the white channels of an RGBWW light to reach given color
+/// temperature, which is interpolated in mireds to match perceived color
+fn ct_to_rgbww(ct: u16) -> DeviceColor {
+    let mireds = |ct: u16| 1_000_000.0 / ct as f32;
+    let ct = ct.clamp(WARM_WHITE_CT, COLD_WHITE_CT);
+    let warm =
+        (mireds(ct) - mireds(COLD_WHITE_CT)) / (mireds(WARM_WHITE_CT) - mireds(COLD_WHITE_CT));
+    let ww = (warm * 255.0).round() as u8;
+
+    DeviceColor::new_from_rgbww(0, 0, 0, 255 - ww, ww)
+}
+
+/// Inverse of [ct_to_rgbww]
+fn rgbww_to_ct(cw: u64, ww: u64) -> u16 {
+    let mireds = |ct: u16| 1_000_000.0 / ct as f32;
+    let warm = ww as f32 / (cw + ww) as f32;
+    let m = mireds(COLD_WHITE_CT) + warm * (mireds(WARM_WHITE_CT) - mireds(COLD_WHITE_CT));
+
+    (1_000_000.0 / m).round() as u16
+}
+
 impl From<&DeviceColor> for palette::Yxy {
     fn from(color: &DeviceColor) -> Self {
         match color {
@@ -183,6 +374,28 @@ impl From<&DeviceColor> for palette::Yxy {
                 let rgb = palette::rgb::Srgb::new(rgb.r, rgb.g, rgb.b);
                 palette::Yxy::from_color(rgb.into_format::<f32>())
             }
+            DeviceColor::Rgbw(rgbw) => {
+                let rgb = [rgbw.r, rgbw.g, rgbw.b].map(|c| (c + rgbw.w).min(255));
+                let rgb = palette::rgb::Srgb::new(rgb[0], rgb[1], rgb[2]);
+                palette::Yxy::from_color(rgb.into_format::<f32>())
+            }
+            DeviceColor::Rgbww(rgbww) => {
+                if rgbww.r == 0 && rgbww.g == 0 && rgbww.b == 0 && rgbww.cw + rgbww.ww > 0 {
+                    let ct = rgbww_to_ct(rgbww.cw, rgbww.ww);
+                    return (&DeviceColor::new_from_ct(ct)).into();
+                }
+
+                // Approximate the white channels as neutral white when mixed
+                // with color
+                let w = (rgbww.cw + rgbww.ww).min(255);
+                let rgbw = DeviceColor::Rgbw(Rgbw {
+                    r: rgbww.r,
+                    g: rgbww.g,
+                    b: rgbww.b,
+                    w,
+                });
+                (&rgbw).into()
+            }
             DeviceColor::Ct(ct) => {
                 // http://www.brucelindbloom.com/index.html?Eqn_T_to_xy.html
                 let t = ct.ct as f32;
@@ -238,3 +451,81 @@ impl From<u16> for DeviceColor {
         DeviceColor::Ct(Ct { ct: ct as u64 })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ct_to_rgbww_uses_white_channels() {
+        let rgbww = Capabilities::singleton(ColorMode::Rgbww);
+
+        let cold = DeviceColor::new_from_ct(6500).to_device_preferred_mode(&rgbww);
+        assert_eq!(cold, Some(DeviceColor::new_from_rgbww(0, 0, 0, 255, 0)));
+
+        let warm = DeviceColor::new_from_ct(2000).to_device_preferred_mode(&rgbww);
+        assert_eq!(warm, Some(DeviceColor::new_from_rgbww(0, 0, 0, 0, 255)));
+
+        let Some(DeviceColor::Rgbww(mixed)) =
+            DeviceColor::new_from_ct(4000).to_device_preferred_mode(&rgbww)
+        else {
+            panic!("expected rgbww color");
+        };
+        assert_eq!((mixed.r, mixed.g, mixed.b), (0, 0, 0));
+        assert!(rgbww_to_ct(mixed.cw, mixed.ww).abs_diff(4000) <= 20);
+    }
+
+    #[test]
+    fn test_ct_to_rgbw_extracts_white() {
+        let rgbw = Capabilities {
+            xy: true,
+            ..Capabilities::singleton(ColorMode::Rgbw)
+        };
+
+        let Some(DeviceColor::Rgbw(color)) =
+            DeviceColor::new_from_ct(4000).to_device_preferred_mode(&rgbw)
+        else {
+            panic!("expected rgbw color");
+        };
+        assert!(color.w > 0);
+        assert_eq!(color.r.min(color.g).min(color.b), 0);
+
+        // Other colors still use the preferred color mode
+        let red = DeviceColor::new_from_rgb(255, 0, 0).to_device_preferred_mode(&rgbw);
+        assert!(matches!(red, Some(DeviceColor::Xy(_))));
+    }
+
+    #[test]
+    fn test_resample_segments() {
+        let red = DeviceColor::new_from_xy(0.7, 0.3);
+        let blue = DeviceColor::new_from_xy(0.15, 0.05);
+
+        assert_eq!(resample_segments(&[red.clone()], 3), vec![red.clone(); 3]);
+        assert_eq!(resample_segments(&[], 3), vec![]);
+
+        let gradient = resample_segments(&[red.clone(), blue.clone()], 3);
+        assert_eq!(gradient[0], red);
+        assert_eq!(gradient[2], blue);
+        let DeviceColor::Xy(middle) = &gradient[1] else {
+            panic!("expected xy color");
+        };
+        assert!((*middle.x - 0.425).abs() < 0.001);
+        assert!((*middle.y - 0.175).abs() < 0.001);
+
+        let fewer = resample_segments(&[red.clone(), blue.clone(), red.clone()], 2);
+        assert_eq!(fewer, vec![red.clone(), red]);
+    }
+
+    #[test]
+    fn test_deserialize_white_channels() {
+        let color: DeviceColor = serde_json::from_str(r#"{"r":1,"g":2,"b":3,"w":4}"#).unwrap();
+        assert_eq!(color, DeviceColor::new_from_rgbw(1, 2, 3, 4));
+
+        let color: DeviceColor =
+            serde_json::from_str(r#"{"r":1,"g":2,"b":3,"cw":4,"ww":5}"#).unwrap();
+        assert_eq!(color, DeviceColor::new_from_rgbww(1, 2, 3, 4, 5));
+
+        let color: DeviceColor = serde_json::from_str(r#"{"r":1,"g":2,"b":3}"#).unwrap();
+        assert_eq!(color, DeviceColor::new_from_rgb(1, 2, 3));
+    }
+}
@@ -0,0 +1,87 @@
+use crate::core::schema::JsonSchema;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::{
+    device::{ControllableState, DeviceKey},
+    scene::SceneId,
+};
+
+/// Integration id of the virtual sensor reporting unresponsive devices
+pub const COMMANDS_INTEGRATION_ID: &str = "commands";
+
+/// Device id of the virtual sensor, which is true while any device has failed
+/// to apply its latest command
+pub const UNRESPONSIVE_DEVICE_ID: &str = "unresponsive";
+
+/// Status of the latest state command sent to a device
+#[derive(TS, Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum CommandStatus {
+    /// Sent, but the integration has not reported the new state yet
+    Pending,
+
+    /// The integration has reported the commanded state
+    Confirmed,
+
+    /// The integration did not report the commanded state in time
+    Failed,
+}
+
+/// Latest command sent to a device, for troubleshooting devices that don't
+/// react
+#[derive(TS, Clone, Debug, Serialize)]
+#[ts(export)]
+pub struct CommandQueueEntry {
+    pub device_key: DeviceKey,
+    pub status: CommandStatus,
+
+    /// State that was sent, while waiting for the device to report it
+    pub pending_state: Option<ControllableState>,
+
+    /// How long ago the pending command was sent
+    pub sent_ms_ago: Option<u64>,
+
+    /// How long until the pending command times out
+    pub timeout_in_ms: Option<u64>,
+
+    /// Commands that re-sent the same state before the device reported it,
+    /// e.g. by reconciliation
+    pub retries: u64,
+
+    /// Commands that replaced a different pending state before the device
+    /// reported it
+    pub coalesced: u64,
+}
+
+/// Progress of applying an activated scene, sent to WebSocket clients
+/// whenever one of its devices is commanded, confirms or fails
+#[derive(TS, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[ts(export)]
+pub struct SceneProgress {
+    pub scene_id: SceneId,
+
+    /// Tells activations apart when a scene is activated again before the
+    /// previous activation has applied. Matches the correlation id in logs.
+    pub activation_id: String,
+
+    /// Devices sent a command so far
+    pub total: usize,
+
+    pub confirmed: usize,
+
+    /// Devices that did not report the commanded state in time
+    pub failed: Vec<DeviceKey>,
+
+    /// Devices yet to report the commanded state. The scene has fully applied
+    /// once this is empty, and no further progress is sent.
+    pub pending: Vec<DeviceKey>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+pub struct CommandsConfig {
+    /// How long to wait for a device to report commanded state, defaults to
+    /// 10 seconds
+    pub timeout_secs: Option<u64>,
+}
@@ -0,0 +1,51 @@
+use crate::core::schema::JsonSchema;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::{device::DeviceKey, rule::RoutineId, scene::SceneId};
+
+/// Automation that caused a device state write
+#[derive(TS, Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum WriteSource {
+    Routine(RoutineId),
+    Scene(SceneId),
+}
+
+impl std::fmt::Display for WriteSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WriteSource::Routine(routine_id) => write!(f, "routine {routine_id}"),
+            WriteSource::Scene(scene_id) => write!(f, "scene {scene_id}"),
+        }
+    }
+}
+
+/// Two automations wrote differing state to the same device within the
+/// configured window
+#[derive(TS, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[ts(export)]
+pub struct Conflict {
+    pub device_key: DeviceKey,
+
+    /// Source of the earlier write
+    pub first: WriteSource,
+
+    /// Source of the write that overrode it
+    pub second: WriteSource,
+
+    #[ts(type = "string")]
+    pub detected_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+pub struct ConflictsConfig {
+    /// Writes from different automations closer together than this are
+    /// considered conflicting, defaults to 5 seconds
+    pub window_secs: Option<u64>,
+
+    /// How many of the most recent conflicts to keep, defaults to 50
+    pub max_conflicts: Option<usize>,
+}
@@ -0,0 +1,53 @@
+use crate::core::schema::JsonSchema;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use super::integration::IntegrationId;
+
+macro_attr! {
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Hash, Ord, PartialOrd, NewtypeDisplay!)]
+    pub struct CoverId(pub String);
+}
+
+fn default_fov() -> f64 {
+    90.0
+}
+
+fn default_open_position() -> u8 {
+    100
+}
+
+/// A cover (blinds, shutters, awning) that is lowered to shade its window
+/// while the sun shines on it
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct CoverConfig {
+    /// Integration controlling the cover
+    pub integration_id: IntegrationId,
+
+    /// Payload of the custom integration action that positions the cover,
+    /// passed as is if it's a string and serialized as JSON otherwise.
+    /// `{position}` is replaced in any strings of the payload.
+    pub payload: serde_json::Value,
+
+    /// Direction the window is facing in degrees clockwise from north
+    pub orientation: f64,
+
+    /// How far the sun may be from `orientation` in degrees and still shine
+    /// on the window, defaults to 90
+    #[serde(default = "default_fov")]
+    pub fov: f64,
+
+    /// Sun elevation in degrees above which to shade, defaults to 0
+    #[serde(default)]
+    pub min_elevation: f64,
+
+    /// Position in percent while shading, defaults to 0 (closed)
+    #[serde(default)]
+    pub shade_position: u8,
+
+    /// Position in percent otherwise, defaults to 100 (open)
+    #[serde(default = "default_open_position")]
+    pub open_position: u8,
+}
+
+pub type CoversConfig = BTreeMap<CoverId, CoverConfig>;
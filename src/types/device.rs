@@ -1,3 +1,4 @@
+use crate::core::schema::{JsonSchema, SchemaGenerator};
 use eyre::Result;
 use ordered_float::OrderedFloat;
 use std::{
@@ -6,7 +7,7 @@ use std::{
 };
 
 use super::{
-    color::{Capabilities, ColorMode, DeviceColor},
+    color::{resample_segments, Capabilities, ColorMode, DeviceColor},
     integration::IntegrationId,
     scene::SceneId,
 };
@@ -17,7 +18,7 @@ use serde::{
 use ts_rs::TS;
 
 macro_attr! {
-    #[derive(TS, Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd, Hash, NewtypeDisplay!, NewtypeFrom!)]
+    #[derive(TS, Clone, Debug, Deserialize, JsonSchema, Serialize, Eq, PartialEq, Ord, PartialOrd, Hash, NewtypeDisplay!, NewtypeFrom!)]
     #[ts(export)]
     /// unique identifier for the Device
     pub struct DeviceId(String);
@@ -37,7 +38,7 @@ impl DeviceId {
     }
 }
 
-#[derive(TS, Clone, Debug, PartialEq, Deserialize, Serialize, Hash, Eq)]
+#[derive(TS, Clone, Debug, PartialEq, Deserialize, JsonSchema, Serialize, Hash, Eq)]
 #[ts(export)]
 pub struct ControllableState {
     pub power: bool,
@@ -49,10 +50,42 @@ pub struct ControllableState {
     /// Current color, if supported
     pub color: Option<DeviceColor>,
 
+    /// Colors of individually colored zones from start to end, if supported
+    pub segments: Option<Vec<DeviceColor>>,
+
     /// Transition time in milliseconds
     pub transition_ms: Option<u64>,
 }
 
+/// Partial device state, fields that are not set keep their previous value
+#[derive(TS, Clone, Debug, Default, PartialEq, Deserialize, JsonSchema, Serialize, Hash, Eq)]
+#[ts(export)]
+pub struct ControllableStateUpdate {
+    pub power: Option<bool>,
+
+    #[ts(type = "number | null")]
+    pub brightness: Option<OrderedFloat<f32>>,
+
+    pub color: Option<DeviceColor>,
+
+    pub segments: Option<Vec<DeviceColor>>,
+
+    pub transition_ms: Option<u64>,
+}
+
+impl ControllableState {
+    /// Applies the fields set in given update on top of this state
+    pub fn merge(&self, update: &ControllableStateUpdate) -> ControllableState {
+        ControllableState {
+            power: update.power.unwrap_or(self.power),
+            brightness: update.brightness.or(self.brightness),
+            color: update.color.clone().or_else(|| self.color.clone()),
+            segments: update.segments.clone().or_else(|| self.segments.clone()),
+            transition_ms: update.transition_ms,
+        }
+    }
+}
+
 impl Display for ControllableState {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let s = {
@@ -64,6 +97,13 @@ impl Display for ControllableState {
                 format!("hs({}, {})", color.h, color.s,)
             } else if let Some(DeviceColor::Rgb(color)) = &self.color {
                 format!("rgb({}, {}, {})", color.r, color.g, color.b)
+            } else if let Some(DeviceColor::Rgbw(color)) = &self.color {
+                format!("rgbw({}, {}, {}, {})", color.r, color.g, color.b, color.w)
+            } else if let Some(DeviceColor::Rgbww(color)) = &self.color {
+                format!(
+                    "rgbww({}, {}, {}, {}, {})",
+                    color.r, color.g, color.b, color.cw, color.ww
+                )
             } else if let Some(DeviceColor::Ct(ct)) = &self.color {
                 format!("ct({})", ct.ct)
             } else {
@@ -89,6 +129,25 @@ impl ControllableState {
             state.color = color.to_device_preferred_mode(capabilities);
         }
 
+        state.segments = match (state.segments.take(), capabilities.segments) {
+            (Some(segments), Some(count)) => Some(
+                resample_segments(&segments, count)
+                    .iter()
+                    .filter_map(|c| c.to_device_preferred_mode(capabilities))
+                    .collect(),
+            ),
+            // Devices without segments show the middle of the gradient
+            (Some(segments), None) => {
+                if state.color.is_none() {
+                    state.color = segments
+                        .get(segments.len() / 2)
+                        .and_then(|c| c.to_device_preferred_mode(capabilities));
+                }
+                None
+            }
+            (None, _) => None,
+        };
+
         state
     }
 
@@ -100,7 +159,7 @@ impl ControllableState {
     }
 }
 
-#[derive(TS, Clone, Debug, PartialEq, Deserialize, Serialize, Default, Hash, Eq)]
+#[derive(TS, Clone, Debug, PartialEq, Deserialize, JsonSchema, Serialize, Default, Hash, Eq)]
 #[ts(export)]
 pub enum ManageKind {
     /// Device is fully managed by homectl.
@@ -132,6 +191,7 @@ pub enum ManageKind {
     Partial {
         /// Whether we have seen the device change state since the previously
         /// issued command.
+        #[serde(default)]
         prev_change_committed: bool,
     },
 
@@ -142,7 +202,7 @@ pub enum ManageKind {
 }
 
 /// lights with adjustable brightness and/or color
-#[derive(TS, Clone, Debug, PartialEq, Deserialize, Serialize, Hash, Eq)]
+#[derive(TS, Clone, Debug, PartialEq, Deserialize, JsonSchema, Serialize, Hash, Eq)]
 #[ts(export)]
 pub struct ControllableDevice {
     pub scene: Option<SceneId>,
@@ -167,6 +227,7 @@ impl ControllableDevice {
                 power,
                 brightness: brightness.map(OrderedFloat),
                 color,
+                segments: None,
                 transition_ms,
             },
             capabilities,
@@ -182,18 +243,79 @@ impl ControllableDevice {
             self.state.brightness = Some(OrderedFloat(brightness));
         }
     }
+
+    /// Steps color temperature by given amount of kelvin, positive steps make
+    /// the color cooler
+    pub fn step_color_temperature(&mut self, step: i64) {
+        if !self.state.power {
+            return;
+        }
+
+        // Devices without color temperature support get converted back into
+        // their own color mode later on
+        let range = self.capabilities.ct.clone().unwrap_or(2000..6500);
+
+        let ct = match self.state.color.as_ref().and_then(|c| {
+            c.to_device_preferred_mode(&Capabilities::singleton(ColorMode::Ct(range.clone())))
+        }) {
+            Some(DeviceColor::Ct(ct)) => ct.ct as i64,
+            _ => return,
+        };
+
+        let ct = (ct + step).clamp(range.start as i64, range.end as i64);
+        self.state.color = Some(DeviceColor::new_from_ct(ct as u16));
+    }
+
+    /// Rotates hue by given amount of degrees and steps saturation by given
+    /// amount
+    pub fn nudge_color(&mut self, hue_step: i64, saturation_step: f32) {
+        if !self.state.power {
+            return;
+        }
+
+        let hs = match self
+            .state
+            .color
+            .as_ref()
+            .and_then(|c| c.to_device_preferred_mode(&Capabilities::singleton(ColorMode::Hs)))
+        {
+            Some(DeviceColor::Hs(hs)) => hs,
+            _ => return,
+        };
+
+        let h = (hs.h as i64 + hue_step).rem_euclid(360);
+        let s = (*hs.s + saturation_step).clamp(0.0, 1.0);
+        self.state.color = Some(DeviceColor::new_from_hs(h as u16, s));
+    }
 }
 
-#[derive(TS, Clone, Debug, PartialEq, Deserialize, Serialize, Hash, Eq)]
+#[derive(TS, Clone, Debug, PartialEq, Deserialize, JsonSchema, Serialize, Hash, Eq)]
 #[ts(export)]
 #[serde(untagged)]
 pub enum SensorDevice {
-    Boolean { value: bool },
-    Text { value: String },
+    Boolean {
+        value: bool,
+    },
+    Text {
+        value: String,
+    },
+    Number {
+        #[ts(type = "number")]
+        value: OrderedFloat<f64>,
+
+        /// Unit of `value` after unit conversion, e.g. "°C"
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        unit: Option<String>,
+
+        /// Value as reported by the sensor, before unit conversion
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        #[ts(type = "number | null")]
+        raw: Option<OrderedFloat<f64>>,
+    },
     Color(ControllableState),
 }
 
-#[derive(TS, Clone, Debug, PartialEq, Deserialize, Serialize, Hash, Eq)]
+#[derive(TS, Clone, Debug, PartialEq, Deserialize, JsonSchema, Serialize, Hash, Eq)]
 #[ts(export)]
 pub enum DeviceData {
     /// This device type can both be read and written to
@@ -214,6 +336,7 @@ impl Display for DeviceData {
     }
 }
 
+#[derive(sqlx::FromRow)]
 pub struct DeviceRow {
     pub device_id: String,
     pub name: String,
@@ -221,7 +344,7 @@ pub struct DeviceRow {
     pub state: sqlx::types::Json<DeviceData>,
 }
 
-#[derive(TS, Clone, Debug, PartialEq, Deserialize, Serialize, Hash, Eq)]
+#[derive(TS, Clone, Debug, PartialEq, Deserialize, JsonSchema, Serialize, Hash, Eq)]
 #[ts(export)]
 pub struct Device {
     pub id: DeviceId,
@@ -280,6 +403,23 @@ impl Device {
         device
     }
 
+    pub fn get_managed(&self) -> Option<&ManageKind> {
+        match self.data {
+            DeviceData::Controllable(ref data) => Some(&data.managed),
+            DeviceData::Sensor(_) => None,
+        }
+    }
+
+    pub fn set_managed(&self, managed: ManageKind) -> Self {
+        let mut device = self.clone();
+
+        if let DeviceData::Controllable(ref mut data) = device.data {
+            data.managed = managed;
+        }
+
+        device
+    }
+
     pub fn is_powered_on(&self) -> Option<bool> {
         match &self.data {
             DeviceData::Controllable(data) => Some(data.state.power),
@@ -295,7 +435,7 @@ impl Device {
         }
     }
 
-    pub fn dim_device(&mut self, amount: f32) -> Self {
+    pub fn dim_device(&self, amount: f32) -> Self {
         let mut device = self.clone();
 
         if let DeviceData::Controllable(ref mut data) = device.data {
@@ -304,6 +444,24 @@ impl Device {
         device
     }
 
+    pub fn step_color_temperature(&self, step: i64) -> Self {
+        let mut device = self.clone();
+
+        if let DeviceData::Controllable(ref mut data) = device.data {
+            data.step_color_temperature(step);
+        }
+        device
+    }
+
+    pub fn nudge_color(&self, hue_step: i64, saturation_step: f32) -> Self {
+        let mut device = self.clone();
+
+        if let DeviceData::Controllable(ref mut data) = device.data {
+            data.nudge_color(hue_step, saturation_step);
+        }
+        device
+    }
+
     pub fn color_to_mode(&self, mode: ColorMode, skip_ct_conversion: bool) -> Device {
         let mut device = self.clone();
 
@@ -312,9 +470,14 @@ impl Device {
                 return device;
             }
 
+            // Keep segments of devices that support them
+            let capabilities = Capabilities {
+                segments: controllable.capabilities.segments,
+                ..Capabilities::singleton(mode)
+            };
             let converted_state = controllable
                 .state
-                .color_to_device_preferred_mode(&Capabilities::singleton(mode));
+                .color_to_device_preferred_mode(&capabilities);
             controllable.state = converted_state;
         }
 
@@ -397,7 +560,9 @@ impl Device {
     }
 }
 
-#[derive(TS, Hash, Clone, Debug, PartialEq, Eq, Deserialize, Serialize, PartialOrd, Ord)]
+#[derive(
+    TS, Hash, Clone, Debug, PartialEq, Eq, Deserialize, JsonSchema, Serialize, PartialOrd, Ord,
+)]
 #[ts(export)]
 pub struct DeviceIdRef {
     pub integration_id: IntegrationId,
@@ -413,7 +578,9 @@ impl DeviceIdRef {
     }
 }
 
-#[derive(TS, Hash, Clone, Debug, PartialEq, Eq, Deserialize, Serialize, PartialOrd, Ord)]
+#[derive(
+    TS, Hash, Clone, Debug, PartialEq, Eq, Deserialize, JsonSchema, Serialize, PartialOrd, Ord,
+)]
 #[ts(export)]
 pub struct DeviceNameRef {
     pub integration_id: IntegrationId,
@@ -421,7 +588,9 @@ pub struct DeviceNameRef {
 }
 
 /// A reference to a device, either by name or by id
-#[derive(TS, Hash, Clone, Debug, PartialEq, Eq, Deserialize, Serialize, PartialOrd, Ord)]
+#[derive(
+    TS, Hash, Clone, Debug, PartialEq, Eq, Deserialize, JsonSchema, Serialize, PartialOrd, Ord,
+)]
 #[serde(untagged)]
 #[ts(export)]
 pub enum DeviceRef {
@@ -472,6 +641,19 @@ impl DeviceKey {
     }
 }
 
+/// Declares that `from` is the same physical device as `to`, e.g. after a
+/// device was re-paired and got a new id.
+#[derive(TS, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[ts(export)]
+pub struct DeviceAlias {
+    pub from: DeviceKey,
+
+    /// Name of the aliased device, so that references by name keep working
+    pub name: Option<String>,
+
+    pub to: DeviceKey,
+}
+
 impl Display for DeviceKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}/{}", self.integration_id, self.device_id)
@@ -511,6 +693,12 @@ impl<'de> Visitor<'de> for DeviceKeyVisitor {
     }
 }
 
+impl JsonSchema for DeviceKey {
+    fn json_schema(_: &mut SchemaGenerator) -> serde_json::Value {
+        serde_json::json!({ "type": "string", "pattern": "^[^/]+/.+$" })
+    }
+}
+
 impl<'de> Deserialize<'de> for DeviceKey {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -0,0 +1,163 @@
+use crate::core::schema::JsonSchema;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use ts_rs::TS;
+
+use super::{color::Capabilities, device::ControllableStateUpdate, integration::IntegrationId};
+
+/// Smooths readings of a numeric sensor before they're stored. Readings pass
+/// through the median, then the moving average, then the deadband.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq)]
+pub struct SensorFilterConfig {
+    /// Median of this many latest readings, removes occasional outliers
+    pub median: Option<usize>,
+
+    /// Average of this many latest readings
+    pub moving_average: Option<usize>,
+
+    /// Readings that differ less than this from the stored value are ignored
+    pub deadband: Option<f64>,
+}
+
+/// Units that readings of numeric sensors can be converted between
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Unit {
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+    W,
+    Kw,
+    Wh,
+    Kwh,
+    Lux,
+}
+
+/// Converts readings of a numeric sensor as they're received. The original
+/// reading is kept as `raw` in the sensor state.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq)]
+pub struct UnitConfig {
+    /// Unit the sensor reports readings in
+    pub from: Option<Unit>,
+
+    /// Unit readings are converted to, must measure the same quantity as
+    /// `from`
+    pub to: Option<Unit>,
+
+    /// Multiplier applied after converting, e.g. to calibrate a sensor
+    pub scale: Option<f64>,
+
+    /// Added after scaling
+    pub offset: Option<f64>,
+}
+
+/// Per-device tweaks, configured either for a single device in the `devices`
+/// section, or for all devices of an integration in its `integrations`
+/// section.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq)]
+pub struct DeviceConfig {
+    /// Exponent of the brightness curve of the device. Brightness sent to the
+    /// device is `brightness ^ gamma`, so values above 1.0 make low
+    /// brightness levels dimmer. Must be positive.
+    #[serde(default, deserialize_with = "positive_gamma")]
+    pub gamma: Option<f32>,
+
+    /// Lower bound for brightness of the device when powered on, e.g. to keep
+    /// the light above its flicker threshold.
+    pub min_brightness: Option<f32>,
+
+    /// Upper bound for brightness of the device.
+    pub max_brightness: Option<f32>,
+
+    /// Transition time used for state changes that don't specify one.
+    pub default_transition_ms: Option<u64>,
+
+    /// Area the device is located in, e.g. "kitchen"
+    pub area: Option<String>,
+
+    /// Free-form tags, e.g. "exempt" to keep a device out of whole-house
+    /// actions
+    pub tags: Option<Vec<String>>,
+
+    /// Filters readings of a numeric sensor, so that jittery sensors don't
+    /// trigger needless updates
+    pub filter: Option<SensorFilterConfig>,
+
+    /// Converts readings of a numeric sensor to another unit
+    pub unit: Option<UnitConfig>,
+
+    /// Color capabilities of the device, used when the integration doesn't
+    /// report any
+    pub capabilities: Option<Capabilities>,
+
+    /// State set once after homectl has started, e.g. to make sure smart
+    /// plugs are on after a restart. Devices discovered later get it when
+    /// they're discovered.
+    pub startup_state: Option<ControllableStateUpdate>,
+}
+
+/// Rejects gammas that would turn brightness into 0, infinity or NaN
+fn positive_gamma<'de, D>(d: D) -> Result<Option<f32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<f32>::deserialize(d)? {
+        Some(gamma) if !gamma.is_finite() || gamma <= 0.0 => Err(de::Error::custom(format!(
+            "gamma must be a positive number, got {gamma}"
+        ))),
+        gamma => Ok(gamma),
+    }
+}
+
+impl DeviceConfig {
+    /// Returns a config where fields that are not set are taken from
+    /// `defaults`
+    pub fn or(&self, defaults: &DeviceConfig) -> DeviceConfig {
+        DeviceConfig {
+            gamma: self.gamma.or(defaults.gamma),
+            min_brightness: self.min_brightness.or(defaults.min_brightness),
+            max_brightness: self.max_brightness.or(defaults.max_brightness),
+            default_transition_ms: self
+                .default_transition_ms
+                .or(defaults.default_transition_ms),
+            area: self.area.clone().or(defaults.area.clone()),
+            tags: self.tags.clone().or(defaults.tags.clone()),
+            filter: self.filter.clone().or(defaults.filter.clone()),
+            unit: self.unit.clone().or(defaults.unit.clone()),
+            capabilities: self.capabilities.clone().or(defaults.capabilities.clone()),
+            startup_state: self
+                .startup_state
+                .clone()
+                .or(defaults.startup_state.clone()),
+        }
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().flatten().any(|t| t == tag)
+    }
+}
+
+/// User editable device metadata, set through the API and persisted in DB.
+/// Takes precedence over the config file.
+#[derive(TS, Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[ts(export)]
+pub struct DeviceMetadata {
+    /// Display name, overrides the name reported by the integration
+    pub name: Option<String>,
+
+    /// Area the device is located in
+    pub area: Option<String>,
+
+    /// Free-form tags
+    pub tags: Option<Vec<String>>,
+
+    /// Icon identifier for UIs
+    pub icon: Option<String>,
+
+    /// Disabled devices are ignored entirely, e.g. retired devices that still
+    /// report state
+    pub disabled: Option<bool>,
+}
+
+/// Device configs by integration id and device name
+pub type DevicesConfig = BTreeMap<IntegrationId, BTreeMap<String, DeviceConfig>>;
@@ -0,0 +1,47 @@
+use crate::core::schema::JsonSchema;
+use chrono::{DateTime, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use ts_rs::TS;
+
+use super::device::DeviceKey;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct DeviceExpiryConfig {
+    /// Devices that haven't reported their state for this many days are
+    /// purged automatically, checked once a day. Must be at least 1.
+    #[serde(deserialize_with = "positive_days")]
+    pub expire_after_days: u32,
+}
+
+#[derive(TS, Clone, Deserialize, JsonSchema, Debug, Serialize)]
+#[ts(export)]
+pub struct PurgeUnseenDevicesDescriptor {
+    /// Purges devices that haven't reported their state for this many days.
+    /// Must be at least 1.
+    #[serde(deserialize_with = "positive_days")]
+    pub not_seen_for_days: u32,
+}
+
+/// Rejects 0 days, which would purge every device that isn't reporting its
+/// state right now
+fn positive_days<'de, D>(d: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match u32::deserialize(d)? {
+        0 => Err(de::Error::custom("days must be at least 1")),
+        days => Ok(days),
+    }
+}
+
+/// A device known to the DB that hasn't reported its state in a while, e.g.
+/// because it was re-paired or renamed
+#[derive(TS, Clone, Debug, Serialize, PartialEq)]
+#[ts(export)]
+pub struct UnseenDevice {
+    pub key: DeviceKey,
+    pub name: String,
+
+    #[ts(type = "string")]
+    pub last_seen_at: DateTime<Utc>,
+}
@@ -2,8 +2,10 @@ use super::color::DeviceColor;
 use super::device::{
     // DeviceRef,
     ControllableState,
+    ControllableStateUpdate,
     DeviceKey,
 };
+use crate::core::schema::JsonSchema;
 
 use super::{device::DeviceId, group::GroupId, integration::IntegrationId};
 use serde::{Deserialize, Serialize};
@@ -20,19 +22,64 @@ pub struct DimDeviceLink {
     pub brightness: Option<f32>, // allow overriding brightness
 }
 
-#[derive(TS, Clone, Deserialize, Serialize, Debug)]
+#[derive(TS, Clone, Deserialize, JsonSchema, Serialize, Debug)]
 #[ts(export)]
 pub struct DimDescriptor {
-    /// Optionally only apply scene to these devices
+    /// Optionally only dim these devices
     pub device_keys: Option<Vec<DeviceKey>>,
 
-    /// Optionally only apply scene to these groups
+    /// Optionally only dim these groups
     pub group_keys: Option<Vec<GroupId>>,
 
-    // The amount to dim
+    /// Optionally only dim devices in these areas
+    pub areas: Option<Vec<String>>,
+
+    /// The amount to dim (or brighten), defaults to 0.1
     pub step: Option<f32>,
 }
 
+#[derive(TS, Clone, Deserialize, JsonSchema, Serialize, Debug)]
+#[ts(export)]
+pub struct ColorTemperatureStepDescriptor {
+    /// Optionally only adjust these devices
+    pub device_keys: Option<Vec<DeviceKey>>,
+
+    /// Optionally only adjust these groups
+    pub group_keys: Option<Vec<GroupId>>,
+
+    /// The amount of kelvin to step by, defaults to 250
+    pub step: Option<u16>,
+}
+
+#[derive(TS, Clone, Deserialize, JsonSchema, Serialize, Debug)]
+#[ts(export)]
+pub struct NudgeColorDescriptor {
+    /// Optionally only adjust these devices
+    pub device_keys: Option<Vec<DeviceKey>>,
+
+    /// Optionally only adjust these groups
+    pub group_keys: Option<Vec<GroupId>>,
+
+    /// Degrees to rotate hue by, may be negative
+    pub hue_step: Option<i64>,
+
+    /// Amount to change saturation by, may be negative
+    pub saturation_step: Option<f32>,
+}
+
+#[derive(TS, Clone, Deserialize, JsonSchema, Serialize, Debug)]
+#[ts(export)]
+pub struct UpdateDeviceStateDescriptor {
+    /// Update these devices
+    pub device_keys: Option<Vec<DeviceKey>>,
+
+    /// Update devices of these groups
+    pub group_keys: Option<Vec<GroupId>>,
+
+    #[serde(flatten)]
+    pub state: ControllableStateUpdate,
+}
+
 #[derive(TS, Clone, Deserialize, Debug, Serialize)]
 #[ts(export)]
 pub struct DimDeviceState {
@@ -1,10 +1,21 @@
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use ts_rs::TS;
 
-use super::scene::{SceneConfig, SceneId};
+use super::scene::{SceneConfig, SceneDescriptor, SceneId};
 
-use super::{action::Action, device::Device, device::DevicesState};
+use super::{
+    action::Action,
+    command::SceneProgress,
+    device::{Device, DeviceKey, DevicesState},
+    device_config::DeviceMetadata,
+    integration::IntegrationId,
+    motion_lighting::MotionLightingId,
+    open_alert::OpenAlertId,
+    rule::RoutineId,
+    snapshot::Snapshot,
+};
 
 #[allow(clippy::large_enum_variant)]
 #[derive(TS, Clone, Debug, Deserialize, Serialize)]
@@ -50,29 +61,254 @@ pub enum Message {
     /// Delete scene from DB.
     DbDeleteScene { scene_id: SceneId },
 
+    /// Marks all devices of an integration as available or unavailable, e.g.
+    /// when the integration keeps failing to apply device state.
+    SetIntegrationAvailability {
+        integration_id: IntegrationId,
+        available: bool,
+    },
+
+    /// Marks a single device as available or unavailable, e.g. when the
+    /// integration learns that the device dropped off its network.
+    SetDeviceAvailability {
+        device_key: DeviceKey,
+        available: bool,
+    },
+
+    /// Re-reads the config section of an integration and starts it.
+    StartIntegration { integration_id: IntegrationId },
+
+    /// Stops a running integration.
+    StopIntegration { integration_id: IntegrationId },
+
+    /// Stops an integration and starts it again with a freshly read config.
+    RestartIntegration { integration_id: IntegrationId },
+
+    /// Adds a new integration at runtime, persisting its config to DB.
+    AddIntegration {
+        integration_id: IntegrationId,
+
+        /// Integration config, same as an `[integrations.<id>]` section in
+        /// the config file
+        #[ts(type = "unknown")]
+        config: serde_json::Value,
+    },
+
+    /// Stops and removes an integration that was added at runtime.
+    RemoveIntegration { integration_id: IntegrationId },
+
+    /// Override timer of a device has run out. The override is cleared unless
+    /// it has been extended since.
+    OverrideExpired { device_key: DeviceKey },
+
+    /// Sets user editable metadata of a device, persisting it to DB.
+    SetDeviceMetadata {
+        device_key: DeviceKey,
+        metadata: DeviceMetadata,
+    },
+
+    /// Declares that device `from` is the same physical device as `to`,
+    /// migrating persisted state of `from` over to `to`.
+    MigrateDevice { from: DeviceKey, to: DeviceKey },
+
+    /// The config file has changed, re-read it and apply changes to groups,
+    /// scenes, routines and integrations.
+    ConfigReloaded,
+
+    /// The primary instance has stopped responding, start integrations and
+    /// take over.
+    PromoteStandby,
+
+    /// Re-evaluate whether quiet hours are in effect
+    RefreshQuietHours,
+
+    /// Re-evaluate appliance running sensors once a minimum duration has
+    /// elapsed
+    RefreshAppliances,
+
+    /// Re-evaluate rate of change alerts once readings leave their window
+    RefreshRateAlerts,
+
+    /// Reset utility meter totals at period boundaries
+    RefreshUtilityMeters,
+
+    /// Apply heating schedules
+    RefreshHeating,
+
+    /// Update sun position and covers following it
+    RefreshSun,
+
+    /// Off delay of a motion lighting has elapsed
+    MotionLightingTimeout {
+        id: MotionLightingId,
+        generation: u64,
+    },
+
+    /// No second press or release of a button has arrived in time
+    ButtonPressTimeout { button: DeviceKey, generation: u64 },
+
+    /// Sources of a presence sensor have been away for its away timeout
+    PresenceTimeout {
+        device_key: DeviceKey,
+        generation: u64,
+    },
+
+    /// A door or window has been open for the configured time, or a reminder
+    /// is due
+    OpenAlertTimeout { id: OpenAlertId, generation: u64 },
+
+    /// Keyframes of a scene have been played, apply the scene's own states
+    SceneTransitionDone {
+        scene_descriptor: SceneDescriptor,
+        generation: u64,
+    },
+
+    /// A device has not reported the state it was commanded to in time
+    CommandTimeout {
+        device_key: DeviceKey,
+        generation: u64,
+    },
+
+    /// Re-send expected state to managed devices that report something else
+    ReconcileDevices,
+
+    /// Ask integrations to report state of devices that have gone quiet
+    PollStaleDevices,
+
+    /// Restore state from a snapshot
+    ImportSnapshot { snapshot: Snapshot },
+
+    /// All integrations have completed their start pass
+    IntegrationsStarted,
+
+    /// A device was removed from devices state, e.g. when it was disabled,
+    /// migrated or purged
+    DeviceRemoved { device_key: DeviceKey },
+
+    /// The trigger of a routine has fired, run it if its rules match
+    RoutineTriggerFired { routine_id: RoutineId },
+
+    /// A routine was triggered, starting a new message chain for its actions
+    RoutineTriggered { routine_id: RoutineId },
+
     /// Broadcast current state to all WS peers
     WsBroadcastState,
 
+    /// Broadcast progress of a scene activation to all WS peers
+    WsBroadcastSceneProgress { progress: SceneProgress },
+
     /// Various actions that can be triggered by rules.
     Action(Action),
 }
 
+impl Message {
+    /// Short name of the message kind, used in tracing spans
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::RecvDeviceState { .. } => "RecvDeviceState",
+            Message::SendDeviceState { .. } => "SendDeviceState",
+            Message::InternalStateUpdate { .. } => "InternalStateUpdate",
+            Message::SetExpectedState { .. } => "SetExpectedState",
+            Message::DbStoreScene { .. } => "DbStoreScene",
+            Message::DbEditScene { .. } => "DbEditScene",
+            Message::DbDeleteScene { .. } => "DbDeleteScene",
+            Message::SetIntegrationAvailability { .. } => "SetIntegrationAvailability",
+            Message::SetDeviceAvailability { .. } => "SetDeviceAvailability",
+            Message::StartIntegration { .. } => "StartIntegration",
+            Message::StopIntegration { .. } => "StopIntegration",
+            Message::RestartIntegration { .. } => "RestartIntegration",
+            Message::AddIntegration { .. } => "AddIntegration",
+            Message::RemoveIntegration { .. } => "RemoveIntegration",
+            Message::OverrideExpired { .. } => "OverrideExpired",
+            Message::SetDeviceMetadata { .. } => "SetDeviceMetadata",
+            Message::MigrateDevice { .. } => "MigrateDevice",
+            Message::ConfigReloaded => "ConfigReloaded",
+            Message::PromoteStandby => "PromoteStandby",
+            Message::RefreshQuietHours => "RefreshQuietHours",
+            Message::RefreshAppliances => "RefreshAppliances",
+            Message::RefreshRateAlerts => "RefreshRateAlerts",
+            Message::RefreshUtilityMeters => "RefreshUtilityMeters",
+            Message::RefreshHeating => "RefreshHeating",
+            Message::RefreshSun => "RefreshSun",
+            Message::MotionLightingTimeout { .. } => "MotionLightingTimeout",
+            Message::ButtonPressTimeout { .. } => "ButtonPressTimeout",
+            Message::PresenceTimeout { .. } => "PresenceTimeout",
+            Message::OpenAlertTimeout { .. } => "OpenAlertTimeout",
+            Message::SceneTransitionDone { .. } => "SceneTransitionDone",
+            Message::CommandTimeout { .. } => "CommandTimeout",
+            Message::ReconcileDevices => "ReconcileDevices",
+            Message::PollStaleDevices => "PollStaleDevices",
+            Message::ImportSnapshot { .. } => "ImportSnapshot",
+            Message::IntegrationsStarted => "IntegrationsStarted",
+            Message::DeviceRemoved { .. } => "DeviceRemoved",
+            Message::RoutineTriggerFired { .. } => "RoutineTriggerFired",
+            Message::RoutineTriggered { .. } => "RoutineTriggered",
+            Message::WsBroadcastState => "WsBroadcastState",
+            Message::WsBroadcastSceneProgress { .. } => "WsBroadcastSceneProgress",
+            Message::Action(_) => "Action",
+        }
+    }
+}
+
+/// Identifies a chain of messages that were caused by the same original
+/// event, e.g. a button press flowing from an integration through devices and
+/// rules back to an integration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CorrelationId(u64);
+
+impl std::fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:x}", self.0)
+    }
+}
+
+static NEXT_CORRELATION_ID: AtomicU64 = AtomicU64::new(1);
+
+tokio::task_local! {
+    /// Correlation id of the message currently being handled
+    pub static CORRELATION_ID: CorrelationId;
+}
+
+impl CorrelationId {
+    pub fn next() -> CorrelationId {
+        CorrelationId(NEXT_CORRELATION_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Returns the correlation id of the message currently being handled, or
+    /// a new one if we're not handling a message (e.g. in integration tasks)
+    pub fn current_or_next() -> CorrelationId {
+        CORRELATION_ID
+            .try_with(|id| *id)
+            .unwrap_or_else(|_| CorrelationId::next())
+    }
+}
+
 #[derive(Clone)]
 pub struct Sender<T> {
-    tx: UnboundedSender<T>,
+    tx: UnboundedSender<(CorrelationId, T)>,
 }
 
 impl<T: std::fmt::Debug> Sender<T> {
+    /// Sends a message, tagging it with the current correlation id
     pub fn send(&self, msg: T) {
-        self.tx.send(msg).expect("Receiver end of channel closed");
+        self.tx
+            .send((CorrelationId::current_or_next(), msg))
+            .expect("Receiver end of channel closed");
+    }
+
+    /// Sends a message as part of the chain identified by `correlation_id`
+    pub fn send_with_correlation_id(&self, correlation_id: CorrelationId, msg: T) {
+        self.tx
+            .send((correlation_id, msg))
+            .expect("Receiver end of channel closed");
     }
 }
 
 pub type TxEventChannel = Sender<Message>;
-pub type RxEventChannel = UnboundedReceiver<Message>;
+pub type RxEventChannel = UnboundedReceiver<(CorrelationId, Message)>;
 
 pub fn mk_event_channel() -> (TxEventChannel, RxEventChannel) {
-    let (tx, rx) = unbounded_channel::<Message>();
+    let (tx, rx) = unbounded_channel::<(CorrelationId, Message)>();
 
     let sender = Sender { tx };
 
@@ -0,0 +1,19 @@
+use crate::core::schema::JsonSchema;
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct EventBusConfig {
+    /// NATS server to connect to, e.g. `nats://localhost:4222`
+    pub url: String,
+
+    /// Prefix of all published and subscribed subjects, defaults to
+    /// `homectl`
+    pub subject_prefix: Option<String>,
+
+    /// Token for servers that use token authentication
+    pub token: Option<String>,
+
+    /// Username and password for servers that use password authentication
+    pub user: Option<String>,
+    pub password: Option<String>,
+}
@@ -0,0 +1,13 @@
+use crate::core::schema::JsonSchema;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct FrontendConfig {
+    /// Directory containing the built frontend, with an `index.html`
+    pub dir: PathBuf,
+
+    /// How long browsers may cache static files other than `index.html`,
+    /// defaults to an hour
+    pub cache_max_age_secs: Option<u64>,
+}
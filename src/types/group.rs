@@ -1,11 +1,12 @@
 use super::device::{DeviceKey, DeviceRef};
+use crate::core::schema::JsonSchema;
 
 use serde::{Deserialize, Serialize};
 use std::{collections::BTreeMap, convert::Infallible};
 use ts_rs::TS;
 
 macro_attr! {
-    #[derive(TS, Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash, Ord, PartialOrd, NewtypeDisplay!)]
+    #[derive(TS, Clone, Debug, Deserialize, JsonSchema, Serialize, Eq, PartialEq, Hash, Ord, PartialOrd, NewtypeDisplay!)]
     #[ts(export)]
     pub struct GroupId(pub String);
 }
@@ -18,21 +19,29 @@ impl std::str::FromStr for GroupId {
     }
 }
 
+/// Integration id of the pseudo-devices representing groups, see
+/// [crate::core::groups::mk_group_device]
+pub const GROUPS_INTEGRATION_ID: &str = "groups";
+
 pub type GroupDevicesConfig = Vec<DeviceRef>;
 
-#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Deserialize, JsonSchema, Serialize, Debug, PartialEq, Eq, Hash)]
 pub struct GroupLink {
     pub group_id: GroupId,
 }
 
 pub type GroupLinksConfig = Vec<GroupLink>;
 
-#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Deserialize, JsonSchema, Serialize, Debug, PartialEq, Eq, Hash)]
 pub struct GroupConfig {
     pub name: String,
     pub devices: Option<GroupDevicesConfig>,
     pub groups: Option<GroupLinksConfig>,
     pub hidden: Option<bool>,
+
+    /// Devices left out of this group even if they are listed directly or
+    /// via a linked group. Also applies to any group linking this one.
+    pub exclude: Option<Vec<DeviceRef>>,
 }
 
 pub type GroupsConfig = BTreeMap<GroupId, GroupConfig>;
@@ -0,0 +1,21 @@
+use crate::core::schema::JsonSchema;
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct HaDiscoveryConfig {
+    /// MQTT broker that Home Assistant is connected to
+    pub host: String,
+
+    /// Defaults to 1883
+    pub port: Option<u16>,
+
+    pub user: Option<String>,
+    pub password: Option<String>,
+
+    /// Prefix Home Assistant watches for discovery messages, defaults to
+    /// `homeassistant`
+    pub discovery_prefix: Option<String>,
+
+    /// Prefix of state and command topics, defaults to `homectl`
+    pub topic_prefix: Option<String>,
+}
@@ -0,0 +1,62 @@
+use crate::core::schema::JsonSchema;
+use chrono::Weekday;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use super::integration::IntegrationId;
+use crate::utils::from_hh_mm;
+
+macro_attr! {
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Hash, Ord, PartialOrd, NewtypeDisplay!)]
+    pub struct HeatingZoneId(pub String);
+}
+
+fn all_days() -> Vec<Weekday> {
+    vec![
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+        Weekday::Sat,
+        Weekday::Sun,
+    ]
+}
+
+/// Setpoint that takes effect at given time on given days, and stays in
+/// effect until the next entry
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct HeatingScheduleEntry {
+    /// Defaults to every day, e.g. ["mon", "tue"]
+    #[serde(default = "all_days")]
+    pub days: Vec<Weekday>,
+
+    #[serde(deserialize_with = "from_hh_mm")]
+    pub at: chrono::NaiveTime,
+
+    pub setpoint: f64,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct HeatingZoneConfig {
+    /// Integration controlling the climate devices of this zone
+    pub integration_id: IntegrationId,
+
+    /// Payload of the custom integration action that sets the setpoint,
+    /// passed as is if it's a string and serialized as JSON otherwise.
+    /// `{setpoint}` is replaced in any strings of the payload.
+    pub payload: serde_json::Value,
+
+    /// Weekly setpoint schedule
+    pub schedule: Vec<HeatingScheduleEntry>,
+
+    /// Setpoint to use instead of the schedule while the house is in away
+    /// mode
+    pub away_setpoint: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+pub struct HeatingConfig {
+    #[serde(default)]
+    pub zones: BTreeMap<HeatingZoneId, HeatingZoneConfig>,
+}
@@ -0,0 +1,76 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::device::DeviceData;
+
+/// State of a device from the moment it was recorded until the next entry
+#[derive(TS, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[ts(export)]
+pub struct HistoryEntry {
+    #[ts(type = "string")]
+    pub at: DateTime<Utc>,
+
+    pub state: DeviceData,
+}
+
+/// Keeps only the latest entry within each `resolution` long interval
+/// starting from `from`, which is enough for charting long ranges of
+/// frequently reporting sensors
+pub fn downsample(
+    entries: Vec<HistoryEntry>,
+    from: DateTime<Utc>,
+    resolution: Duration,
+) -> Vec<HistoryEntry> {
+    let resolution_ms = resolution.num_milliseconds().max(1);
+    let bucket = |entry: &HistoryEntry| (entry.at - from).num_milliseconds() / resolution_ms;
+
+    let mut result: Vec<HistoryEntry> = vec![];
+
+    for entry in entries {
+        match result.last_mut() {
+            Some(last) if bucket(last) == bucket(&entry) => *last = entry,
+            _ => result.push(entry),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::device::SensorDevice;
+    use chrono::TimeZone;
+    use ordered_float::OrderedFloat;
+
+    fn entry(secs: i64, value: f64) -> HistoryEntry {
+        HistoryEntry {
+            at: Utc.timestamp_opt(secs, 0).unwrap(),
+            state: DeviceData::Sensor(SensorDevice::Number {
+                value: OrderedFloat(value),
+                unit: None,
+                raw: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_downsample() {
+        let entries = vec![
+            entry(0, 1.0),
+            entry(20, 2.0),
+            entry(59, 3.0),
+            entry(60, 4.0),
+            entry(200, 5.0),
+        ];
+
+        let from = Utc.timestamp_opt(0, 0).unwrap();
+        let result = downsample(entries, from, Duration::seconds(60));
+
+        assert_eq!(
+            result,
+            vec![entry(59, 3.0), entry(60, 4.0), entry(200, 5.0)]
+        );
+    }
+}
@@ -1,12 +1,19 @@
-use super::{device::Device, event::TxEventChannel};
+use super::{device::Device, device_config::DeviceConfig, event::TxEventChannel};
+use crate::core::schema::JsonSchema;
 use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
 use color_eyre::Result;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, convert::Infallible, str::FromStr};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    str::FromStr,
+};
 use ts_rs::TS;
 
 macro_attr! {
-    #[derive(TS, Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd, Hash, NewtypeDisplay!, NewtypeFrom!)]
+    #[derive(TS, Clone, Debug, Deserialize, JsonSchema, Serialize, Eq, PartialEq, Ord, PartialOrd, Hash, NewtypeDisplay!, NewtypeFrom!)]
     #[ts(export)]
     pub struct IntegrationId(String);
 }
@@ -19,9 +26,21 @@ impl FromStr for IntegrationId {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct IntegrationConfig {
     pub plugin: String,
+
+    /// Integrations that must be registered and started before this one
+    #[serde(default)]
+    pub depends_on: Vec<IntegrationId>,
+
+    /// Whether to start the integration when homectl starts. Integrations
+    /// with autostart disabled can be started later through the API.
+    pub autostart: Option<bool>,
+
+    /// Defaults for per-device configs of all devices of this integration
+    #[serde(flatten)]
+    pub device_defaults: DeviceConfig,
     // NOTE: integration configs may contain other fields as well.
 
     // but since we don't know what fields those might be, they have to be
@@ -31,18 +50,46 @@ pub struct IntegrationConfig {
 pub type IntegrationsConfig = HashMap<IntegrationId, IntegrationConfig>;
 
 macro_attr! {
-    #[derive(TS, Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash, NewtypeDisplay!, NewtypeFrom!)]
+    #[derive(TS, Clone, Debug, Deserialize, JsonSchema, Serialize, Eq, PartialEq, Hash, NewtypeDisplay!, NewtypeFrom!)]
     #[ts(export)]
     pub struct IntegrationActionPayload(String);
 }
 
-#[derive(TS, Clone, Debug, Deserialize, Serialize)]
+#[derive(TS, Clone, Debug, Deserialize, JsonSchema, Serialize)]
 #[ts(export)]
 pub struct CustomActionDescriptor {
     pub integration_id: IntegrationId,
     pub payload: IntegrationActionPayload,
 }
 
+#[derive(TS, Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum TrafficDirection {
+    Inbound,
+    Outbound,
+}
+
+/// Raw message exchanged with an integration's external system, captured for
+/// diagnosing mapping problems
+#[derive(TS, Clone, Debug, Deserialize, Serialize)]
+#[ts(export)]
+pub struct CapturedMessage {
+    pub direction: TrafficDirection,
+
+    #[ts(type = "string")]
+    pub at: DateTime<Utc>,
+
+    pub topic: String,
+    pub payload: String,
+
+    /// Error encountered while converting the payload, if any
+    pub error: Option<String>,
+}
+
+/// Captured messages by device id, oldest first
+pub type CapturedTraffic = BTreeMap<String, Vec<CapturedMessage>>;
+
 #[async_trait]
 pub trait Integration: Send {
     // rustc --explain E0038
@@ -56,10 +103,32 @@ pub trait Integration: Send {
     async fn start(&mut self) -> Result<()> {
         Ok(())
     }
+    /// Called once when homectl is shutting down. Integrations should release
+    /// any external resources here, e.g. unsubscribe and disconnect from
+    /// brokers.
+    async fn stop(&mut self) -> Result<()> {
+        Ok(())
+    }
     async fn set_integration_device_state(&mut self, _device: &Device) -> Result<()> {
         Ok(())
     }
     async fn run_integration_action(&mut self, _payload: &IntegrationActionPayload) -> Result<()> {
         Ok(())
     }
+    /// Asks the integration to report the current state of given device,
+    /// which hasn't been heard from in a while. Returns false if the
+    /// integration has no way of querying device state.
+    async fn poll_device(&mut self, _device: &Device) -> Result<bool> {
+        Ok(false)
+    }
+    /// Returns recently captured raw traffic, or None if the integration
+    /// doesn't capture traffic
+    async fn get_captured_traffic(&mut self) -> Option<CapturedTraffic> {
+        None
+    }
+    /// Returns the latest camera snapshot as a JPEG image, or None if the
+    /// integration has no camera or hasn't taken a snapshot yet
+    async fn get_snapshot(&mut self) -> Option<Bytes> {
+        None
+    }
 }
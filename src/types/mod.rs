@@ -1,10 +1,43 @@
 pub mod action;
+pub mod api;
+pub mod appliance;
+pub mod calendar;
 pub mod color;
+pub mod command;
+pub mod conflict;
+pub mod cover;
 pub mod device;
+pub mod device_config;
+pub mod device_expiry;
 pub mod dim;
 pub mod event;
+pub mod event_bus;
+pub mod frontend;
 pub mod group;
+pub mod ha_discovery;
+pub mod heating;
+pub mod history;
 pub mod integration;
+pub mod mode;
+pub mod motion_lighting;
+pub mod notification;
+pub mod open_alert;
+pub mod overrides;
+pub mod person;
+pub mod polling;
+pub mod power;
+pub mod preferences;
+pub mod presence;
+pub mod quiet_hours;
+pub mod rate_alert;
+pub mod reconcile;
 pub mod rule;
+pub mod safety;
 pub mod scene;
+pub mod sentry;
+pub mod simulation;
+pub mod snapshot;
+pub mod standby;
+pub mod sun;
+pub mod utility_meter;
 pub mod websockets;
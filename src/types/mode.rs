@@ -0,0 +1,43 @@
+use crate::core::schema::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use ts_rs::TS;
+
+use super::action::Actions;
+
+macro_attr! {
+    #[derive(TS, Clone, Debug, Deserialize, JsonSchema, Serialize, Eq, PartialEq, Hash, Ord, PartialOrd, NewtypeDisplay!, NewtypeFrom!)]
+    #[ts(export)]
+    pub struct ModeId(pub String);
+}
+
+/// Modes that are always available, even if not configured
+pub const BUILTIN_MODES: [&str; 4] = ["home", "away", "night", "guest"];
+
+/// Mode the house starts in unless configured otherwise
+pub const DEFAULT_MODE: &str = "home";
+
+/// Integration id of the virtual sensor holding the current mode
+pub const MODES_INTEGRATION_ID: &str = "modes";
+
+/// Device id of the virtual sensor holding the current mode
+pub const MODE_DEVICE_ID: &str = "mode";
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+pub struct ModeConfig {
+    /// Actions to run when entering this mode
+    #[serde(default)]
+    pub on_enter: Actions,
+
+    /// Actions to run when leaving this mode
+    #[serde(default)]
+    pub on_exit: Actions,
+}
+
+pub type ModesConfig = BTreeMap<ModeId, ModeConfig>;
+
+#[derive(TS, Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[ts(export)]
+pub struct SetModeDescriptor {
+    pub mode: ModeId,
+}
@@ -0,0 +1,53 @@
+use crate::core::schema::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use ts_rs::TS;
+
+use super::{
+    device::{DeviceKey, DeviceRef},
+    group::GroupId,
+    scene::SceneId,
+};
+
+macro_attr! {
+    #[derive(TS, Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash, Ord, PartialOrd, NewtypeDisplay!)]
+    #[ts(export)]
+    pub struct MotionLightingId(pub String);
+}
+
+fn default_off_delay_secs() -> u64 {
+    120
+}
+
+/// Only turn lights on when it's dark enough
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct IlluminanceCondition {
+    /// Sensor with a numeric value
+    pub sensor: DeviceRef,
+
+    pub below: f64,
+}
+
+/// Turns lights on when motion is detected and off again once motion has
+/// stopped for a while
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct MotionLightingConfig {
+    /// Motion sensors with a boolean value, motion is detected while any of
+    /// them is true
+    pub sensors: Vec<DeviceRef>,
+
+    /// Scene to activate, otherwise lights are just turned on
+    pub scene_id: Option<SceneId>,
+
+    pub device_keys: Option<Vec<DeviceKey>>,
+    pub group_keys: Option<Vec<GroupId>>,
+
+    /// How long to wait after motion stops before turning lights off,
+    /// defaults to 120
+    #[serde(default = "default_off_delay_secs")]
+    pub off_delay_secs: u64,
+
+    pub illuminance: Option<IlluminanceCondition>,
+}
+
+pub type MotionLightingsConfig = BTreeMap<MotionLightingId, MotionLightingConfig>;
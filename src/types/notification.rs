@@ -0,0 +1,105 @@
+use crate::core::schema::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use ts_rs::TS;
+
+use super::{integration::IntegrationId, person::PersonId, quiet_hours::QuietHoursBehavior};
+use crate::utils::from_hh_mm_opt;
+
+#[derive(
+    TS,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Deserialize,
+    JsonSchema,
+    Serialize,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum Severity {
+    #[default]
+    Info,
+    Warning,
+    Critical,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+#[derive(TS, Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[ts(export)]
+pub struct NotifyDescriptor {
+    pub title: Option<String>,
+    pub message: String,
+
+    /// Defaults to info
+    pub severity: Option<Severity>,
+
+    /// Optionally only notify these persons. Channels that don't belong to a
+    /// person are notified regardless.
+    pub persons: Option<Vec<PersonId>>,
+
+    /// URL of an image to attach, e.g. a doorbell snapshot
+    pub image: Option<String>,
+}
+
+/// A way of delivering notifications, through a custom action of an
+/// integration.
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct NotificationChannelConfig {
+    pub integration_id: IntegrationId,
+
+    /// Payload of the custom action, passed as is if it's a string and
+    /// serialized as JSON otherwise. `{title}`, `{message}`, `{severity}` and
+    /// `{image}` are replaced in any strings of the payload.
+    pub payload: serde_json::Value,
+
+    /// Person this channel belongs to, e.g. for a phone
+    pub person: Option<PersonId>,
+}
+
+/// Sends notifications of at least the given severity to channels, optionally
+/// only during a time of day.
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct NotificationRouteConfig {
+    pub channels: Vec<String>,
+
+    /// Defaults to info
+    #[serde(default)]
+    pub min_severity: Severity,
+
+    /// Start of the time window in which this route applies, e.g. "08:00"
+    #[serde(default, deserialize_with = "from_hh_mm_opt")]
+    pub from: Option<chrono::NaiveTime>,
+
+    /// End of the time window in which this route applies, may wrap past
+    /// midnight
+    #[serde(default, deserialize_with = "from_hh_mm_opt")]
+    pub to: Option<chrono::NaiveTime>,
+
+    /// Whether non-critical notifications are held back during quiet hours
+    #[serde(default)]
+    pub quiet_hours: QuietHoursBehavior,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+pub struct NotificationsConfig {
+    #[serde(default)]
+    pub channels: BTreeMap<String, NotificationChannelConfig>,
+
+    #[serde(default)]
+    pub routes: Vec<NotificationRouteConfig>,
+}
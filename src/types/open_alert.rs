@@ -0,0 +1,47 @@
+use crate::core::schema::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use ts_rs::TS;
+
+use super::{device::DeviceRef, notification::Severity, person::PersonId};
+
+macro_attr! {
+    #[derive(TS, Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash, Ord, PartialOrd, NewtypeDisplay!)]
+    #[ts(export)]
+    pub struct OpenAlertId(pub String);
+}
+
+/// Only alert while a numeric sensor, e.g. outdoor temperature, is within
+/// given bounds
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct ClimateCondition {
+    pub sensor: DeviceRef,
+    pub below: Option<f64>,
+    pub above: Option<f64>,
+}
+
+/// Notifies when a door or window has been left open for too long
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct OpenAlertConfig {
+    /// Contact sensor with a boolean value that is true while open
+    pub sensor: DeviceRef,
+
+    /// How long the sensor needs to stay open before notifying
+    pub after_secs: u64,
+
+    /// Keep reminding at this interval while the sensor stays open
+    pub repeat_secs: Option<u64>,
+
+    /// Defaults to "<sensor name> has been left open"
+    pub message: Option<String>,
+
+    /// Defaults to warning
+    pub severity: Option<Severity>,
+
+    /// Optionally only notify these persons
+    pub persons: Option<Vec<PersonId>>,
+
+    pub climate: Option<ClimateCondition>,
+}
+
+pub type OpenAlertsConfig = BTreeMap<OpenAlertId, OpenAlertConfig>;
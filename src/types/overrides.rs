@@ -0,0 +1,32 @@
+use crate::core::schema::JsonSchema;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::{device::DeviceKey, group::GroupId};
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+pub struct OverridesConfig {
+    /// How long a manual override lasts unless otherwise specified, in
+    /// seconds. Defaults to one hour.
+    pub timeout_secs: Option<u64>,
+
+    /// Whether to treat unexpected state changes reported by managed devices
+    /// as manual overrides, instead of correcting them back to expected state.
+    /// States reported while a device is still transitioning to a state sent
+    /// to it are ignored.
+    pub detect_manual_changes: Option<bool>,
+}
+
+#[derive(TS, Clone, Deserialize, JsonSchema, Serialize, Debug)]
+#[ts(export)]
+pub struct OverrideDescriptor {
+    /// Devices to override
+    pub device_keys: Option<Vec<DeviceKey>>,
+
+    /// Groups to override
+    pub group_keys: Option<Vec<GroupId>>,
+
+    /// How long the override lasts, in seconds. Defaults to the configured
+    /// override timeout. Ignored when clearing overrides.
+    pub duration_secs: Option<u64>,
+}
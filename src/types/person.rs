@@ -0,0 +1,52 @@
+use crate::core::schema::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use ts_rs::TS;
+
+use super::device::DeviceRef;
+
+macro_attr! {
+    #[derive(TS, Clone, Debug, Deserialize, JsonSchema, Serialize, Eq, PartialEq, Hash, Ord, PartialOrd, NewtypeDisplay!)]
+    #[ts(export)]
+    pub struct PersonId(pub String);
+}
+
+/// Integration id of the virtual presence sensors of persons
+pub const PERSONS_INTEGRATION_ID: &str = "persons";
+
+/// Device id of the virtual sensor that is on whenever any person is home
+pub const ANYONE_HOME_DEVICE_ID: &str = "anyone_home";
+
+/// How states of multiple trackers of a person are combined
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackerMerge {
+    /// Person is home if any tracker says so
+    #[default]
+    Any,
+
+    /// The first tracker in the list with a known state decides
+    Priority,
+
+    /// The tracker that changed most recently decides
+    Last,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct PersonConfig {
+    pub name: String,
+
+    /// Devices tracking presence of the person, e.g. ping, BLE or geofence
+    /// sensors. Sensors with a boolean value are home when true, sensors with
+    /// a text value are home when the value is "home".
+    pub trackers: Vec<DeviceRef>,
+
+    #[serde(default)]
+    pub merge: TrackerMerge,
+
+    /// How long the person stays home after all trackers stopped reporting
+    /// them home, defaults to 0
+    pub away_timeout_secs: Option<u64>,
+}
+
+pub type PersonsConfig = BTreeMap<PersonId, PersonConfig>;
@@ -0,0 +1,13 @@
+use crate::core::schema::JsonSchema;
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct PollingConfig {
+    /// Devices that haven't reported their state for this long are asked to
+    /// report it
+    pub stale_after_secs: u64,
+
+    /// Devices are considered unavailable after this many polls go
+    /// unanswered
+    pub unavailable_after_polls: Option<u32>,
+}
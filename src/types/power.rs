@@ -0,0 +1,23 @@
+use crate::core::schema::JsonSchema;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::{device::DeviceKey, group::GroupId};
+
+/// Devices with this tag are left alone by [super::action::Action::TurnOff]
+/// and [super::action::Action::TurnOn], unless explicitly listed in
+/// `device_keys`.
+pub const EXEMPT_TAG: &str = "exempt";
+
+#[derive(TS, Clone, Deserialize, JsonSchema, Serialize, Debug)]
+#[ts(export)]
+pub struct PowerDescriptor {
+    /// Optionally only target these devices
+    pub device_keys: Option<Vec<DeviceKey>>,
+
+    /// Optionally only target these groups
+    pub group_keys: Option<Vec<GroupId>>,
+
+    /// Optionally only target devices in these areas
+    pub areas: Option<Vec<String>>,
+}
@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, convert::Infallible};
+use ts_rs::TS;
+
+use super::{device::DeviceKey, scene::SceneId};
+
+macro_attr! {
+    #[derive(TS, Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash, Ord, PartialOrd, NewtypeDisplay!, NewtypeFrom!)]
+    #[ts(export)]
+    pub struct UserId(pub String);
+}
+
+impl std::str::FromStr for UserId {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(UserId(s.to_string()))
+    }
+}
+
+/// Preferences of a frontend user, stored on their behalf
+#[derive(TS, Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[ts(export)]
+pub struct UserPreferences {
+    #[serde(default)]
+    pub favorite_devices: Vec<DeviceKey>,
+
+    #[serde(default)]
+    pub favorite_scenes: Vec<SceneId>,
+
+    /// Dashboard layouts by name. These are opaque to homectl and stored as
+    /// given.
+    #[serde(default)]
+    #[ts(type = "Record<string, unknown>")]
+    pub dashboards: BTreeMap<String, serde_json::Value>,
+}
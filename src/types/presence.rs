@@ -0,0 +1,34 @@
+use crate::core::schema::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use ts_rs::TS;
+
+use super::device::DeviceRef;
+
+macro_attr! {
+    #[derive(TS, Clone, Debug, Deserialize, JsonSchema, Serialize, Eq, PartialEq, Hash, Ord, PartialOrd, NewtypeDisplay!)]
+    #[ts(export)]
+    pub struct PresenceAreaId(pub String);
+}
+
+/// Integration id of the virtual presence sensors of areas
+pub const PRESENCE_INTEGRATION_ID: &str = "presence";
+
+/// Combines presence sources of an area into a single presence sensor
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct PresenceAreaConfig {
+    pub name: String,
+
+    /// Devices detecting presence in the area, e.g. motion and occupancy
+    /// sensors, ping trackers or presence sensors of persons. Someone is
+    /// present while any of them is. Sensors with a boolean value are present
+    /// when true, sensors with a text value are present when the value is
+    /// "home".
+    pub sources: Vec<DeviceRef>,
+
+    /// How long the area stays occupied after all sources stopped detecting
+    /// presence, defaults to 0
+    pub away_timeout_secs: Option<u64>,
+}
+
+pub type PresenceConfig = BTreeMap<PresenceAreaId, PresenceAreaConfig>;
@@ -0,0 +1,47 @@
+use crate::core::schema::JsonSchema;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::utils::from_hh_mm;
+
+/// Integration id of the virtual quiet hours sensor
+pub const QUIET_HOURS_INTEGRATION_ID: &str = "quiet_hours";
+
+/// Device id of the virtual sensor that is on during quiet hours, or while do
+/// not disturb is enabled
+pub const QUIET_HOURS_DEVICE_ID: &str = "active";
+
+/// Time of day during which routines and notifications that opt in are held
+/// back
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct QuietHoursConfig {
+    /// Start of quiet hours, e.g. "22:00"
+    #[serde(deserialize_with = "from_hh_mm")]
+    pub from: chrono::NaiveTime,
+
+    /// End of quiet hours, may wrap past midnight, e.g. "07:00"
+    #[serde(deserialize_with = "from_hh_mm")]
+    pub to: chrono::NaiveTime,
+}
+
+/// What to do with a routine or notification during quiet hours
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QuietHoursBehavior {
+    /// Run as usual
+    #[default]
+    Ignore,
+
+    /// Drop it
+    Suppress,
+
+    /// Hold it back until quiet hours end
+    Defer,
+}
+
+#[derive(TS, Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[ts(export)]
+pub struct DoNotDisturbDescriptor {
+    /// Toggles do not disturb if omitted
+    pub enabled: Option<bool>,
+}
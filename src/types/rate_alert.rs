@@ -0,0 +1,36 @@
+use crate::core::schema::JsonSchema;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use super::device::DeviceRef;
+
+macro_attr! {
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Hash, Ord, PartialOrd, NewtypeDisplay!)]
+    pub struct RateAlertId(pub String);
+}
+
+/// Integration id of the virtual rate of change alert sensors
+pub const RATE_ALERTS_INTEGRATION_ID: &str = "rate_alerts";
+
+/// Watches a numeric sensor for values changing abnormally fast, e.g. a
+/// temperature drop from a window left open
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct RateAlertConfig {
+    pub name: String,
+
+    /// Sensor with a numeric value
+    pub sensor: DeviceRef,
+
+    /// Period over which changes are measured
+    pub window_secs: u64,
+
+    /// Alert when the value drops at least this much from its highest reading
+    /// within the window
+    pub falls_by: Option<f64>,
+
+    /// Alert when the value rises at least this much from its lowest reading
+    /// within the window
+    pub rises_by: Option<f64>,
+}
+
+pub type RateAlertsConfig = BTreeMap<RateAlertId, RateAlertConfig>;
@@ -0,0 +1,9 @@
+use crate::core::schema::JsonSchema;
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct ReconcileConfig {
+    /// How often to compare reported state of managed devices with their
+    /// expected state
+    pub interval_secs: u64,
+}
@@ -1,18 +1,27 @@
+use super::calendar::CalendarRule;
 use super::device::{DeviceRef, SensorDevice};
 use super::{group::GroupId, scene::SceneId};
+use crate::core::schema::JsonSchema;
 
-use super::action::Actions;
+use super::{action::Action, quiet_hours::QuietHoursBehavior, sun::SunTrigger};
+use chrono::{DateTime, Utc};
+use ordered_float::OrderedFloat;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use ts_rs::TS;
 
 macro_attr! {
-    #[derive(TS, Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash, NewtypeDisplay!, NewtypeFrom!)]
+    #[derive(TS, Clone, Debug, Deserialize, JsonSchema, Serialize, Eq, PartialEq, Hash, NewtypeDisplay!, NewtypeFrom!)]
     #[ts(export)]
     pub struct RoutineId(pub String);
 }
 
-#[derive(Clone, Deserialize, Debug)]
+macro_attr! {
+    #[derive(Clone, Debug, Deserialize, JsonSchema, Eq, PartialEq, Hash, NewtypeDisplay!, NewtypeFrom!)]
+    pub struct RoutineTemplateId(pub String);
+}
+
+#[derive(Clone, Deserialize, JsonSchema, Debug)]
 pub struct SensorRule {
     pub state: SensorDevice,
 
@@ -20,7 +29,7 @@ pub struct SensorRule {
     pub device_ref: DeviceRef,
 }
 
-#[derive(Clone, Deserialize, Debug)]
+#[derive(Clone, Deserialize, JsonSchema, Debug)]
 pub struct DeviceRule {
     pub power: Option<bool>,
     pub scene: Option<SceneId>,
@@ -29,19 +38,46 @@ pub struct DeviceRule {
     pub device_ref: DeviceRef,
 }
 
-#[derive(Clone, Deserialize, Debug)]
+#[derive(Clone, Deserialize, JsonSchema, Debug)]
 pub struct GroupRule {
     pub group_id: GroupId,
     pub power: Option<bool>,
     pub scene: Option<SceneId>,
 }
 
-#[derive(Clone, Deserialize, Debug)]
+/// How readings of several illuminance sensors are combined
+#[derive(Clone, Copy, Deserialize, JsonSchema, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum IlluminanceFusion {
+    Min,
+    Max,
+    #[default]
+    Mean,
+}
+
+#[derive(Clone, Deserialize, JsonSchema, Debug, PartialEq, Eq, Hash)]
+pub struct IlluminanceRule {
+    /// Sensors with a numeric illuminance value, e.g. in lux
+    pub illuminance: Vec<DeviceRef>,
+
+    /// It's dark when illuminance is below this
+    pub below: OrderedFloat<f64>,
+
+    /// Once dark, it stays dark until illuminance rises this much above
+    /// `below`, so that lights turning on don't immediately count as daylight
+    #[serde(default)]
+    pub hysteresis: OrderedFloat<f64>,
+
+    #[serde(default)]
+    pub fusion: IlluminanceFusion,
+}
+
+#[derive(Clone, Deserialize, JsonSchema, Debug)]
 pub struct AnyRule {
     pub any: Rules,
 }
 
-#[derive(Clone, Deserialize, Debug)]
+#[derive(Clone, Deserialize, JsonSchema, Debug)]
 #[serde(untagged)]
 pub enum Rule {
     /// Match fields on individual sensors.
@@ -53,6 +89,13 @@ pub enum Rule {
     /// Match fields on entire device groups.
     Group(GroupRule),
 
+    /// Matches when it's dark according to one or more illuminance sensors.
+    Illuminance(IlluminanceRule),
+
+    /// Matches on workdays, holidays or days off according to the holiday
+    /// calendar.
+    Calendar(CalendarRule),
+
     /// Normally, all rules must match for a routine to be triggered. This
     /// special rule allows you to group multiple rules together, such that only
     /// one of the contained rules need to match.
@@ -64,17 +107,172 @@ pub enum Rule {
 
 pub type Rules = Vec<Rule>;
 
-#[derive(Clone, Deserialize, Debug)]
+/// Moment a routine is triggered at, instead of when its rules start matching
+#[derive(Clone, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Trigger {
+    /// Triggers relative to local sunrise, sunset or twilight. Requires
+    /// `[location]` to be configured.
+    Sun(SunTrigger),
+
+    /// Triggers on a cron schedule in local time.
+    Cron(CronTrigger),
+
+    /// Triggers once after all integrations have completed their start pass.
+    Startup(StartupTrigger),
+}
+
+#[derive(Clone, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+pub struct CronTrigger {
+    /// Cron expression, e.g. "0 7 * * MON-FRI" for 07:00 on weekdays
+    pub cron: String,
+}
+
+#[derive(Clone, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+pub struct StartupTrigger {
+    pub on_startup: bool,
+}
+
+#[derive(Clone, Deserialize, JsonSchema, Debug)]
 pub struct Routine {
     pub name: String,
+
+    /// Rules of routines with a trigger are only checked when the trigger
+    /// fires, and may be left empty
+    #[serde(default)]
     pub rules: Rules,
-    pub actions: Actions,
+
+    pub trigger: Option<Trigger>,
+
+    pub actions: Vec<RoutineAction>,
+
+    /// Whether actions are held back during quiet hours
+    #[serde(default)]
+    pub quiet_hours: QuietHoursBehavior,
+}
+
+/// Runs `then` if all rules in `if` match, otherwise `else`
+#[derive(Clone, Deserialize, JsonSchema, Debug)]
+pub struct RoutineBranch {
+    #[serde(rename = "if")]
+    pub condition: Rules,
+
+    pub then: Vec<RoutineAction>,
+
+    #[serde(default, rename = "else")]
+    pub otherwise: Vec<RoutineAction>,
+}
+
+#[derive(Clone, Deserialize, JsonSchema, Debug)]
+#[serde(untagged)]
+pub enum RoutineAction {
+    /// Chooses between actions when the routine runs, using the same rules as
+    /// routines themselves.
+    Branch(RoutineBranch),
+
+    Action(Action),
 }
 
 pub type RoutinesConfig = HashMap<RoutineId, Routine>;
 
-#[derive(TS, Clone, Deserialize, Debug, Serialize)]
+/// Instance of a routine template. Strings of the template containing
+/// `{{name}}` get the value of variable `name` substituted in, a string that
+/// consists of only the placeholder is replaced by the value as is, so
+/// variables can also hold numbers or lists.
+#[derive(Clone, Deserialize, JsonSchema, Debug)]
+pub struct RoutineTemplateInstance {
+    pub template: RoutineTemplateId,
+
+    #[serde(default)]
+    pub vars: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Clone, Deserialize, JsonSchema, Debug)]
+#[serde(untagged)]
+pub enum RoutineConfig {
+    Template(RoutineTemplateInstance),
+    Routine(Routine),
+}
+
+/// The `[routines]` config section, before templates are instantiated
+pub type RoutineConfigs = HashMap<RoutineId, RoutineConfig>;
+
+/// Routines with placeholders for variables, in the same format as routines
+pub type RoutineTemplatesConfig = HashMap<RoutineTemplateId, serde_json::Value>;
+
+/// Execution statistics of a routine since startup, to spot slow or
+/// hyperactive routines
+#[derive(TS, Clone, Debug, Default, Serialize)]
+#[ts(export)]
+pub struct RoutineStats {
+    /// Times the routine was triggered and its actions run
+    pub trigger_count: u64,
+
+    #[ts(type = "string | null")]
+    pub last_triggered_at: Option<DateTime<Utc>>,
+
+    /// Times the rules of the routine were evaluated
+    pub evaluation_count: u64,
+
+    /// Total time spent evaluating the rules of the routine
+    pub evaluation_total_us: u64,
+
+    /// Longest evaluation of the rules of the routine
+    pub evaluation_max_us: u64,
+}
+
+#[derive(TS, Clone, Deserialize, JsonSchema, Debug, Serialize)]
 #[ts(export)]
 pub struct ForceTriggerRoutineDescriptor {
     pub routine_id: RoutineId,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routine_branch_deserialize() {
+        let actions: Vec<RoutineAction> = serde_json::from_str(
+            r#"[
+                { "action": "ActivateScene", "scene_id": "hallway" },
+                {
+                    "if": [{ "group_id": "downstairs", "power": false }],
+                    "then": [{ "action": "ActivateScene", "scene_id": "night" }],
+                    "else": [{ "action": "ActivateScene", "scene_id": "day" }]
+                },
+                { "if": [], "then": [] }
+            ]"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            actions[0],
+            RoutineAction::Action(Action::ActivateScene(_))
+        ));
+
+        let RoutineAction::Branch(branch) = &actions[1] else {
+            panic!("Expected a branch, got {:?}", actions[1]);
+        };
+        assert!(matches!(branch.condition[..], [Rule::Group(_)]));
+        assert_eq!(branch.then.len(), 1);
+        assert_eq!(branch.otherwise.len(), 1);
+
+        let RoutineAction::Branch(branch) = &actions[2] else {
+            panic!("Expected a branch, got {:?}", actions[2]);
+        };
+        assert!(branch.otherwise.is_empty());
+    }
+
+    #[test]
+    fn test_trigger_deserialize() {
+        let trigger: Trigger = serde_json::from_str(r#"{ "on_startup": true }"#).unwrap();
+        assert_eq!(
+            trigger,
+            Trigger::Startup(StartupTrigger { on_startup: true })
+        );
+
+        let trigger: Trigger = serde_json::from_str(r#"{ "cron": "0 7 * * *" }"#).unwrap();
+        assert!(matches!(trigger, Trigger::Cron(_)));
+    }
+}
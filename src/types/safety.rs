@@ -0,0 +1,49 @@
+use crate::core::schema::JsonSchema;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use super::{action::Actions, device::DeviceRef};
+
+/// What kind of hazard a safety sensor detects
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum SafetyKind {
+    Smoke,
+    CarbonMonoxide,
+    WaterLeak,
+}
+
+impl SafetyKind {
+    pub fn description(&self) -> &'static str {
+        match self {
+            SafetyKind::Smoke => "Smoke detected",
+            SafetyKind::CarbonMonoxide => "Carbon monoxide detected",
+            SafetyKind::WaterLeak => "Water leak detected",
+        }
+    }
+}
+
+/// A sensor with a boolean value that is true while a hazard is detected
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct SafetySensorConfig {
+    pub kind: SafetyKind,
+
+    /// Actions to run when this particular sensor triggers, in addition to
+    /// the actions of its kind
+    #[serde(default)]
+    pub actions: Actions,
+
+    #[serde(flatten)]
+    pub device_ref: DeviceRef,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+pub struct SafetyConfig {
+    #[serde(default)]
+    pub sensors: Vec<SafetySensorConfig>,
+
+    /// Actions to run when any sensor of given kind triggers, e.g. shutting a
+    /// water valve on any water leak
+    #[serde(default)]
+    pub actions: BTreeMap<SafetyKind, Actions>,
+}
@@ -1,5 +1,8 @@
 use super::color::DeviceColor;
-use super::device::{ControllableState, DeviceKey, DeviceRef};
+use super::device::{ControllableState, DeviceKey, DeviceRef, ManageKind};
+use crate::core::schema::JsonSchema;
+use crate::utils::{from_hh_mm, time_in_window, to_hh_mm};
+use chrono::{DateTime, Utc};
 
 use super::{group::GroupId, integration::IntegrationId};
 use ordered_float::OrderedFloat;
@@ -9,7 +12,7 @@ use std::convert::Infallible;
 use ts_rs::TS;
 
 macro_attr! {
-    #[derive(TS, Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash, Ord, PartialOrd, NewtypeDisplay!, NewtypeFrom!)]
+    #[derive(TS, Clone, Debug, Deserialize, JsonSchema, Serialize, Eq, PartialEq, Hash, Ord, PartialOrd, NewtypeDisplay!, NewtypeFrom!)]
     #[ts(export)]
     pub struct SceneId(String);
 }
@@ -28,7 +31,7 @@ impl std::str::FromStr for SceneId {
     }
 }
 
-#[derive(TS, Clone, Deserialize, Debug, Serialize, Eq, PartialEq, Hash)]
+#[derive(TS, Clone, Deserialize, JsonSchema, Debug, Serialize, Eq, PartialEq, Hash)]
 #[ts(export)]
 pub struct SceneDeviceLink {
     #[ts(type = "number | null")]
@@ -39,7 +42,7 @@ pub struct SceneDeviceLink {
     pub device_ref: DeviceRef,
 }
 
-#[derive(TS, Clone, Deserialize, Serialize, Debug, Eq, PartialEq, Hash)]
+#[derive(TS, Clone, Deserialize, JsonSchema, Serialize, Debug, Eq, PartialEq, Hash)]
 #[ts(export)]
 pub struct SceneDescriptor {
     pub scene_id: SceneId,
@@ -49,23 +52,65 @@ pub struct SceneDescriptor {
 
     /// Optionally only apply scene to these groups
     pub group_keys: Option<Vec<GroupId>>,
+
+    /// Optionally override transition time of all devices in the scene
+    pub transition_ms: Option<u64>,
+
+    /// Optionally scale brightness of all devices in the scene, e.g. 0.5 for
+    /// half of the configured brightness
+    #[ts(type = "number | null")]
+    pub brightness: Option<OrderedFloat<f32>>,
+
+    /// Activate the scene even outside of its activation window, e.g. when
+    /// activated manually
+    pub override_window: Option<bool>,
 }
 
-#[derive(TS, Clone, Deserialize, Serialize, Debug, Eq, PartialEq, Hash)]
+#[derive(TS, Clone, Deserialize, JsonSchema, Serialize, Debug, Eq, PartialEq, Hash)]
 #[ts(export)]
 pub struct CycleScenesDescriptor {
     pub scenes: Vec<SceneDescriptor>,
     pub nowrap: Option<bool>,
+
+    /// Button that cycles the scenes, required to tell press types apart
+    #[ts(skip)]
+    pub button: Option<DeviceRef>,
+
+    /// Press types of `button` that turn devices of the cycled scenes off
+    /// instead of cycling
+    #[serde(default)]
+    pub turn_off_on: Vec<PressType>,
 }
 
-#[derive(TS, Clone, Deserialize, Debug, Serialize, Eq, PartialEq, Hash)]
+/// How a button was pressed, either as reported by the integration or told
+/// apart by timing presses and releases
+#[derive(TS, Clone, Copy, Deserialize, JsonSchema, Serialize, Debug, Eq, PartialEq, Hash)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum PressType {
+    Single,
+    Double,
+    Long,
+}
+
+#[derive(TS, Clone, Deserialize, JsonSchema, Debug, Serialize, Eq, PartialEq, Hash)]
 #[ts(export)]
 pub struct SceneDeviceState {
     pub power: Option<bool>,
     pub color: Option<DeviceColor>,
+
+    /// Colors spread over the zones of multi-zone lights from start to end,
+    /// interpolated into a gradient when the light has more zones
+    pub segments: Option<Vec<DeviceColor>>,
+
     #[ts(type = "number | null")]
     pub brightness: Option<OrderedFloat<f32>>,
     pub transition_ms: Option<u64>,
+
+    /// Overrides how closely homectl manages the device while the scene is
+    /// active, e.g. `{ Partial = {} }` to set the state once without
+    /// correcting later manual changes.
+    pub managed: Option<ManageKind>,
 }
 
 impl From<ControllableState> for SceneDeviceState {
@@ -73,13 +118,39 @@ impl From<ControllableState> for SceneDeviceState {
         SceneDeviceState {
             power: Some(state.power),
             color: state.color,
+            segments: state.segments,
             brightness: state.brightness,
             transition_ms: state.transition_ms,
+            managed: None,
         }
     }
 }
 
-#[derive(TS, Clone, Deserialize, Debug, Serialize, PartialEq)]
+#[derive(TS, Clone, Deserialize, JsonSchema, Debug, Serialize, PartialEq)]
+#[ts(export)]
+pub struct SceneDeviceAdjustment {
+    /// Amount to change brightness by, may be negative
+    pub brightness: Option<f32>,
+
+    /// Kelvin to step color temperature by, may be negative
+    pub ct: Option<i64>,
+
+    /// Degrees to rotate hue by, may be negative
+    pub hue: Option<i64>,
+
+    /// Amount to change saturation by, may be negative
+    pub saturation: Option<f32>,
+
+    pub transition_ms: Option<u64>,
+}
+
+#[derive(TS, Clone, Deserialize, JsonSchema, Debug, Serialize, PartialEq)]
+#[ts(export)]
+pub struct SceneDeviceAdjust {
+    pub adjust: SceneDeviceAdjustment,
+}
+
+#[derive(TS, Clone, Deserialize, JsonSchema, Debug, Serialize, PartialEq)]
 #[serde(untagged)]
 #[ts(export)]
 pub enum SceneDeviceConfig {
@@ -91,6 +162,10 @@ pub enum SceneDeviceConfig {
     /// scene
     SceneLink(SceneDescriptor),
 
+    /// Relative adjustment of the device's state at the time the scene is
+    /// activated
+    Adjust(SceneDeviceAdjust),
+
     /// State to be applied to a device
     DeviceState(SceneDeviceState),
 }
@@ -98,18 +173,100 @@ pub enum SceneDeviceConfig {
 pub type SceneDevicesConfig = HashMap<DeviceKey, SceneDeviceConfig>;
 pub type SceneDevicesConfigs = HashMap<SceneId, (SceneConfig, SceneDevicesConfig)>;
 
-#[derive(TS, Clone, Deserialize, Debug, Serialize, PartialEq)]
+/// How colors are picked from a scene palette
+#[derive(
+    TS, Clone, Copy, Deserialize, JsonSchema, Debug, Serialize, Default, PartialEq, Eq, Hash,
+)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum PaletteMode {
+    /// Each device gets a random color from the palette
+    #[default]
+    Random,
+
+    /// Devices get consecutive palette colors, shifted by one on each
+    /// activation
+    Rotate,
+}
+
+#[derive(TS, Clone, Deserialize, JsonSchema, Debug, Serialize, PartialEq)]
+#[ts(export)]
+pub struct ScenePaletteConfig {
+    pub colors: Vec<DeviceColor>,
+    pub mode: Option<PaletteMode>,
+
+    /// Makes random picks reproducible across restarts
+    pub seed: Option<u64>,
+}
+
+/// Easing curve of the transition towards a keyframe
+#[derive(
+    TS, Clone, Copy, Deserialize, JsonSchema, Debug, Serialize, Default, PartialEq, Eq, Hash,
+)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum Easing {
+    #[default]
+    Linear,
+
+    /// Starts slow, e.g. for waking up to a sunrise
+    EaseIn,
+
+    /// Ends slow
+    EaseOut,
+
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps linear progress `t` (0.0 - 1.0) to eased progress
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut if t < 0.5 => 2.0 * t * t,
+            Easing::EaseInOut => -1.0 + (4.0 - 2.0 * t) * t,
+        }
+    }
+}
+
+/// State that all devices of a scene pass through before the scene's own
+/// states are applied
+#[derive(TS, Clone, Deserialize, JsonSchema, Debug, Serialize, PartialEq)]
+#[ts(export)]
+pub struct SceneKeyframe {
+    /// Time taken to reach this keyframe from the previous one, or from the
+    /// current state of the devices for the first keyframe
+    pub duration_ms: u64,
+
+    #[serde(default)]
+    pub easing: Easing,
+
+    /// Devices are powered on at the start of the transition, or off at its
+    /// end
+    pub power: Option<bool>,
+
+    #[ts(type = "number | null")]
+    pub brightness: Option<OrderedFloat<f32>>,
+
+    pub color: Option<DeviceColor>,
+}
+
+#[derive(TS, Clone, Deserialize, JsonSchema, Debug, Serialize, PartialEq)]
 #[ts(export)]
 pub struct SceneGroupsConfig(pub BTreeMap<GroupId, SceneDeviceConfig>);
 
 /// Device "search" config as used directly in the configuration file. We use device names instead of device id as key.
-#[derive(TS, Clone, Deserialize, Debug, Serialize, PartialEq)]
+#[derive(TS, Clone, Deserialize, JsonSchema, Debug, Serialize, PartialEq)]
 #[ts(export)]
 pub struct SceneDevicesSearchConfig(
     pub BTreeMap<IntegrationId, BTreeMap<String, SceneDeviceConfig>>,
 );
 
-#[derive(TS, Clone, Deserialize, Debug, Serialize, PartialEq)]
+#[derive(TS, Clone, Deserialize, JsonSchema, Debug, Serialize, PartialEq)]
 #[ts(export)]
 pub struct SceneConfig {
     pub name: String,
@@ -117,14 +274,63 @@ pub struct SceneConfig {
     pub groups: Option<SceneGroupsConfig>,
     pub hidden: Option<bool>,
 
+    /// Devices left out of this scene, even if they belong to one of the
+    /// scene's groups.
+    pub exclude: Option<Vec<DeviceRef>>,
+
+    /// Colors picked for the scene's devices each time the scene is
+    /// activated, overriding any configured device colors.
+    pub palette: Option<ScenePaletteConfig>,
+
+    /// Timed sequence of states the scene's devices pass through on
+    /// activation, e.g. for a sunrise simulation. The scene's own states are
+    /// applied after the last keyframe.
+    pub keyframes: Option<Vec<SceneKeyframe>>,
+
+    /// Time of day outside of which the scene isn't activated, unless
+    /// activated with `override_window`.
+    pub window: Option<SceneWindow>,
+
     /// Evaluates given expression to compute scene config.
     #[ts(skip)]
     #[serde(skip_serializing)]
     pub expr: Option<evalexpr::Node>,
 }
 
+/// Time of day during which a scene may be activated
+#[derive(TS, Clone, Deserialize, JsonSchema, Debug, Serialize, PartialEq)]
+#[ts(export)]
+pub struct SceneWindow {
+    /// Start of the window, e.g. "22:00"
+    #[serde(deserialize_with = "from_hh_mm", serialize_with = "to_hh_mm")]
+    #[ts(type = "string")]
+    pub from: chrono::NaiveTime,
+
+    /// End of the window, may wrap past midnight, e.g. "07:00"
+    #[serde(deserialize_with = "from_hh_mm", serialize_with = "to_hh_mm")]
+    #[ts(type = "string")]
+    pub to: chrono::NaiveTime,
+}
+
+impl SceneWindow {
+    pub fn contains(&self, time: chrono::NaiveTime) -> bool {
+        time_in_window(Some(self.from), Some(self.to), time)
+    }
+}
+
 pub type ScenesConfig = BTreeMap<SceneId, SceneConfig>;
 
+/// Usage of a scene, to find scenes that are never activated
+#[derive(TS, Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[ts(export)]
+pub struct SceneStats {
+    /// Times the scene was activated
+    pub activation_count: u64,
+
+    #[ts(type = "string | null")]
+    pub last_activated_at: Option<DateTime<Utc>>,
+}
+
 #[derive(TS, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, Hash)]
 #[ts(export)]
 pub struct SceneDeviceStates(pub BTreeMap<DeviceKey, ControllableState>);
@@ -0,0 +1,12 @@
+use crate::core::schema::JsonSchema;
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct SentryConfig {
+    /// Client key of the Sentry project, e.g.
+    /// `https://<key>@o123.ingest.sentry.io/456`
+    pub dsn: String,
+
+    /// Environment reported with events, e.g. `production`
+    pub environment: Option<String>,
+}
@@ -0,0 +1,71 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::{
+    action::Actions,
+    device::{DeviceKey, DeviceRef, SensorDevice},
+    rule::RoutineId,
+};
+
+/// Synthetic sensor reading fed through the rules engine
+#[derive(TS, Clone, Debug, Deserialize, Serialize)]
+#[ts(export)]
+pub struct SimulatedEvent {
+    /// Seconds since the start of the simulation
+    #[serde(default)]
+    pub after_secs: u64,
+
+    pub device: DeviceRef,
+    pub state: SensorDevice,
+}
+
+#[derive(TS, Clone, Debug, Deserialize, Serialize)]
+#[ts(export)]
+pub struct SimulationDescriptor {
+    /// Local time at which the virtual clock starts, defaults to now
+    #[ts(type = "string | null")]
+    pub start: Option<NaiveDateTime>,
+
+    pub events: Vec<SimulatedEvent>,
+}
+
+/// What would happen to the actions of a triggered routine
+#[derive(TS, Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum SimulatedOutcome {
+    Run,
+
+    /// Held back until quiet hours end
+    Deferred,
+
+    /// Dropped due to quiet hours
+    Suppressed,
+}
+
+#[derive(TS, Clone, Debug, Deserialize, Serialize)]
+#[ts(export)]
+pub struct SimulatedRoutine {
+    pub routine_id: RoutineId,
+    pub name: String,
+    pub outcome: SimulatedOutcome,
+    pub actions: Actions,
+}
+
+/// Result of feeding one [SimulatedEvent] through the rules engine
+#[derive(TS, Clone, Debug, Deserialize, Serialize)]
+#[ts(export)]
+pub struct SimulationStep {
+    /// Virtual time of the event
+    #[ts(type = "string")]
+    pub at: NaiveDateTime,
+
+    pub device: DeviceRef,
+
+    /// Key of the device the event was applied to, if it was found
+    pub device_key: Option<DeviceKey>,
+
+    /// Routines that would have been triggered by the event
+    pub routines: Vec<SimulatedRoutine>,
+}
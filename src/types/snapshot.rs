@@ -0,0 +1,42 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use ts_rs::TS;
+
+use super::{
+    device::{DeviceKey, DevicesState},
+    device_config::DeviceMetadata,
+    group::FlattenedGroupsConfig,
+    mode::ModeId,
+    scene::{FlattenedScenesConfig, ScenesConfig},
+};
+
+/// Complete resolved state of homectl, for moving to new hardware or as a
+/// test fixture
+#[derive(TS, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[ts(export)]
+pub struct Snapshot {
+    #[ts(type = "string")]
+    pub created_at: DateTime<Utc>,
+
+    /// Expected state of all devices. Only controllable devices are restored,
+    /// sensors report their own state.
+    pub devices: DevicesState,
+
+    /// Scenes created through the API
+    pub db_scenes: ScenesConfig,
+
+    /// User editable metadata of devices
+    pub device_metadata: BTreeMap<DeviceKey, DeviceMetadata>,
+
+    pub mode: ModeId,
+    pub do_not_disturb: bool,
+
+    /// Resolved scenes, for reference. These follow from config and scenes
+    /// above, and are not restored.
+    pub scenes: FlattenedScenesConfig,
+
+    /// Resolved groups, for reference. These follow from config and are not
+    /// restored.
+    pub groups: FlattenedGroupsConfig,
+}
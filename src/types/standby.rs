@@ -0,0 +1,16 @@
+use crate::core::schema::JsonSchema;
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct StandbyConfig {
+    /// WebSocket endpoint of the primary instance, e.g.
+    /// `ws://primary:45289/ws`
+    pub primary_url: String,
+
+    /// Token for authenticating with the primary, if it requires one
+    pub token: Option<String>,
+
+    /// How long the primary may go without responding before the standby
+    /// instance takes over, in seconds. Defaults to 15 seconds.
+    pub heartbeat_timeout_secs: Option<u64>,
+}
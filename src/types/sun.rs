@@ -0,0 +1,201 @@
+use crate::core::schema::{JsonSchema, SchemaGenerator};
+use serde::{de, Deserialize};
+use std::str::FromStr;
+
+/// Integration id of the virtual sun position sensors
+pub const SUN_INTEGRATION_ID: &str = "sun";
+
+/// Where the house is, used to compute the position of the sun
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct LocationConfig {
+    pub latitude: f64,
+    pub longitude: f64,
+
+    /// IANA time zone used for all schedules and local times, e.g.
+    /// "Europe/Helsinki". Defaults to the time zone of the host.
+    pub timezone: Option<String>,
+}
+
+/// Moments of the day defined by the sun crossing an elevation
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SunEvent {
+    Sunrise,
+    Sunset,
+    CivilDawn,
+    CivilDusk,
+    NauticalDawn,
+    NauticalDusk,
+}
+
+impl SunEvent {
+    /// Elevation of the sun at this event
+    pub fn elevation(&self) -> f64 {
+        match self {
+            SunEvent::Sunrise | SunEvent::Sunset => SUNRISE_ELEVATION,
+            SunEvent::CivilDawn | SunEvent::CivilDusk => CIVIL_TWILIGHT_ELEVATION,
+            SunEvent::NauticalDawn | SunEvent::NauticalDusk => NAUTICAL_TWILIGHT_ELEVATION,
+        }
+    }
+
+    /// Whether the sun is rising rather than setting
+    pub fn is_rising(&self) -> bool {
+        matches!(
+            self,
+            SunEvent::Sunrise | SunEvent::CivilDawn | SunEvent::NauticalDawn
+        )
+    }
+}
+
+/// Signed offset in seconds, written as e.g. "-30m", "1h15m" or "+90s"
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SunOffset(pub i64);
+
+impl FromStr for SunOffset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid offset {}, expected e.g. -30m or 1h15m", s);
+
+        let (sign, rest) = match s.trim().strip_prefix('-') {
+            Some(rest) => (-1, rest),
+            None => (1, s.trim().trim_start_matches('+')),
+        };
+
+        if rest == "0" {
+            return Ok(SunOffset(0));
+        }
+
+        let mut secs = 0;
+        let mut number = String::new();
+
+        for c in rest.chars() {
+            let unit = match c {
+                '0'..='9' => {
+                    number.push(c);
+                    continue;
+                }
+                'h' => 3600,
+                'm' => 60,
+                's' => 1,
+                _ => return Err(invalid()),
+            };
+
+            let value: i64 = number.parse().map_err(|_| invalid())?;
+            secs += value * unit;
+            number.clear();
+        }
+
+        if rest.is_empty() || !number.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(SunOffset(sign * secs))
+    }
+}
+
+impl<'de> Deserialize<'de> for SunOffset {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+impl JsonSchema for SunOffset {
+    fn json_schema(_: &mut SchemaGenerator) -> serde_json::Value {
+        serde_json::json!({ "type": "string", "pattern": "^[+-]?(0|(\\d+h)?(\\d+m)?(\\d+s)?)$" })
+    }
+}
+
+/// Triggers a routine at a sun event, e.g. `{ sun = "sunset", offset = "-30m" }`
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct SunTrigger {
+    pub sun: SunEvent,
+
+    /// Time relative to the event, defaults to 0
+    #[serde(default)]
+    pub offset: SunOffset,
+}
+
+/// Position of the sun in degrees
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SunPosition {
+    /// Clockwise from north
+    pub azimuth: f64,
+
+    /// Above the horizon, negative when the sun has set
+    pub elevation: f64,
+}
+
+/// Phase of the moon
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MoonPhase {
+    /// Fraction of the lunar cycle, 0 at new moon and 0.5 at full moon
+    pub phase: f64,
+
+    /// Illuminated fraction of the moon's disc
+    pub illumination: f64,
+}
+
+impl MoonPhase {
+    pub fn name(&self) -> &'static str {
+        const NAMES: [&str; 8] = [
+            "new_moon",
+            "waxing_crescent",
+            "first_quarter",
+            "waxing_gibbous",
+            "full_moon",
+            "waning_gibbous",
+            "last_quarter",
+            "waning_crescent",
+        ];
+
+        NAMES[(self.phase * 8.0).round() as usize % 8]
+    }
+}
+
+/// Elevation of the sun when it's rising or setting, accounting for
+/// refraction and the size of the sun's disc
+pub const SUNRISE_ELEVATION: f64 = -0.833;
+pub const CIVIL_TWILIGHT_ELEVATION: f64 = -6.0;
+pub const NAUTICAL_TWILIGHT_ELEVATION: f64 = -12.0;
+pub const ASTRONOMICAL_TWILIGHT_ELEVATION: f64 = -18.0;
+
+/// How dark it is outside, by the elevation of the sun
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Twilight {
+    Day,
+    Civil,
+    Nautical,
+    Astronomical,
+    Night,
+}
+
+impl Twilight {
+    pub fn from_elevation(elevation: f64) -> Twilight {
+        if elevation >= SUNRISE_ELEVATION {
+            Twilight::Day
+        } else if elevation >= CIVIL_TWILIGHT_ELEVATION {
+            Twilight::Civil
+        } else if elevation >= NAUTICAL_TWILIGHT_ELEVATION {
+            Twilight::Nautical
+        } else if elevation >= ASTRONOMICAL_TWILIGHT_ELEVATION {
+            Twilight::Astronomical
+        } else {
+            Twilight::Night
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Twilight::Day => "day",
+            Twilight::Civil => "civil",
+            Twilight::Nautical => "nautical",
+            Twilight::Astronomical => "astronomical",
+            Twilight::Night => "night",
+        }
+    }
+}
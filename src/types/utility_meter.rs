@@ -0,0 +1,70 @@
+use crate::core::schema::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::device::DeviceRef;
+
+macro_attr! {
+    #[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash, Ord, PartialOrd, NewtypeDisplay!, NewtypeFrom!)]
+    pub struct UtilityMeterId(pub String);
+}
+
+/// Integration id of the virtual utility meter sensors
+pub const UTILITY_METERS_INTEGRATION_ID: &str = "utility_meters";
+
+#[derive(
+    Clone, Copy, Debug, Deserialize, JsonSchema, Serialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum MeterPeriod {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl MeterPeriod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MeterPeriod::Daily => "daily",
+            MeterPeriod::Weekly => "weekly",
+            MeterPeriod::Monthly => "monthly",
+        }
+    }
+}
+
+fn default_periods() -> Vec<MeterPeriod> {
+    vec![
+        MeterPeriod::Daily,
+        MeterPeriod::Weekly,
+        MeterPeriod::Monthly,
+    ]
+}
+
+/// Accumulates a cumulative source sensor, e.g. total energy or water
+/// consumption, into per-period totals
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+pub struct UtilityMeterConfig {
+    pub name: String,
+
+    /// Sensor with a numeric, ever increasing value. Drops in value are
+    /// treated as the source meter having been reset.
+    pub source: DeviceRef,
+
+    /// Defaults to daily, weekly and monthly totals
+    #[serde(default = "default_periods")]
+    pub periods: Vec<MeterPeriod>,
+}
+
+pub type UtilityMetersConfig = BTreeMap<UtilityMeterId, UtilityMeterConfig>;
+
+/// Persisted state of a utility meter
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct UtilityMeterState {
+    /// Previous reading of the source sensor
+    pub last_reading: Option<f64>,
+
+    pub totals: BTreeMap<MeterPeriod, f64>,
+
+    /// Start date of the period each total belongs to
+    pub period_starts: BTreeMap<MeterPeriod, chrono::NaiveDate>,
+}
@@ -1,27 +1,117 @@
+use crate::core::schema::JsonSchema;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use ts_rs::TS;
 
 use super::{
-    device::DevicesState, event::Message, group::FlattenedGroupsConfig,
+    action::Action,
+    command::{CommandStatus, SceneProgress},
+    device::{DeviceKey, DevicesState},
+    device_config::DeviceMetadata,
+    event::Message,
+    group::FlattenedGroupsConfig,
     scene::FlattenedScenesConfig,
 };
 
 #[derive(TS, Deserialize, Serialize, Debug)]
 #[ts(export)]
 pub enum WebSocketRequest {
+    /// Authenticates the connection. Must be the first message sent when
+    /// tokens are configured.
+    Auth(WebSocketAuth),
+
     Message(Message),
+
+    /// Runs an action, answered with a [WebSocketResponse::CommandResult]
+    Action(WebSocketAction),
+}
+
+#[derive(TS, Deserialize, Serialize, Debug)]
+#[ts(export)]
+pub struct WebSocketAction {
+    /// Chosen by the client, echoed back in the result
+    pub id: String,
+
+    pub action: Action,
+}
+
+/// Acknowledges a [WebSocketRequest::Action]. The action has been accepted
+/// and queued unless `error` is set.
+#[derive(TS, Deserialize, Serialize, Debug)]
+#[ts(export)]
+pub struct CommandResult {
+    /// Not set if the request couldn't be parsed
+    pub id: Option<String>,
+
+    pub error: Option<String>,
+}
+
+#[derive(TS, Deserialize, Serialize, Debug)]
+#[ts(export)]
+pub struct WebSocketAuth {
+    pub token: Option<String>,
+
+    /// Describes the client, e.g. its name and version
+    pub client: Option<String>,
+}
+
+impl WebSocketAuth {
+    /// Auth sent when connecting to another homectl instance
+    pub fn homectl(token: Option<String>) -> Self {
+        WebSocketAuth {
+            token,
+            client: Some(format!("homectl-server {}", env!("CARGO_PKG_VERSION"))),
+        }
+    }
+}
+
+/// A connected and authenticated WebSocket client
+#[derive(TS, Clone, Debug, Deserialize, Serialize)]
+#[ts(export)]
+pub struct WebSocketSession {
+    pub id: usize,
+    pub client: Option<String>,
+    pub remote_addr: Option<String>,
+    pub user_agent: Option<String>,
+
+    #[ts(type = "string")]
+    pub connected_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+pub struct WebSocketsConfig {
+    /// Tokens accepted in the auth handshake. If unset, clients don't need to
+    /// authenticate.
+    pub tokens: Option<Vec<String>>,
+
+    /// How long clients get to authenticate, defaults to 5 seconds
+    pub auth_timeout_secs: Option<u64>,
 }
 
 #[derive(TS, Deserialize, Serialize, Debug)]
 #[ts(export)]
 pub struct StateUpdate {
     pub devices: DevicesState,
+    pub unavailable_devices: BTreeSet<DeviceKey>,
+    pub overridden_devices: BTreeSet<DeviceKey>,
     pub scenes: FlattenedScenesConfig,
     pub groups: FlattenedGroupsConfig,
+
+    /// Aggregate state of groups, as pseudo-devices
+    pub group_devices: DevicesState,
+
+    /// User editable metadata of devices
+    pub device_metadata: BTreeMap<DeviceKey, DeviceMetadata>,
+
+    /// Status of the latest state command sent to each device
+    pub command_statuses: BTreeMap<DeviceKey, CommandStatus>,
 }
 
 #[derive(TS, Deserialize, Serialize, Debug)]
 #[ts(export)]
 pub enum WebSocketResponse {
     State(StateUpdate),
+    SceneProgress(SceneProgress),
+    CommandResult(CommandResult),
 }
@@ -11,6 +11,49 @@ where
     chrono::NaiveTime::parse_from_str(&str, "%H:%M").map_err(serde::de::Error::custom)
 }
 
+pub fn to_hh_mm<S>(time: &chrono::NaiveTime, s: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    s.serialize_str(&time.format("%H:%M").to_string())
+}
+
+pub fn from_hh_mm_opt<'de, D>(d: D) -> Result<Option<chrono::NaiveTime>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    let str = Option::<String>::deserialize(d)?;
+    str.map(|str| chrono::NaiveTime::parse_from_str(&str, "%H:%M"))
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+/// Returns true if `time` is within the window starting at `from` and ending
+/// at `to`. The window may wrap past midnight, and either end may be omitted.
+pub fn time_in_window(
+    from: Option<chrono::NaiveTime>,
+    to: Option<chrono::NaiveTime>,
+    time: chrono::NaiveTime,
+) -> bool {
+    match (from, to) {
+        (Some(from), Some(to)) if from <= to => from <= time && time < to,
+        (Some(from), Some(to)) => time >= from || time < to,
+        (Some(from), None) => time >= from,
+        (None, Some(to)) => time < to,
+        (None, None) => true,
+    }
+}
+
+/// Compares secrets in time independent of where they differ, so that tokens
+/// can't be guessed byte by byte from response times
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
 pub fn keys_match<T: Eq + Hash + Ord, U, V>(map1: &BTreeMap<T, U>, map2: &BTreeMap<T, V>) -> bool {
     map1.len() == map2.len() && map1.keys().all(|k| map2.contains_key(k))
 }